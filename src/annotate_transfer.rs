@@ -0,0 +1,354 @@
+// This file contains the code for the autocycler annotate-transfer subcommand, which lifts
+// annotations over from one of the input assemblies onto the consensus assembly.
+
+// Copyright 2024 Ryan Wick (rrwick@gmail.com)
+// https://github.com/rrwick/Autocycler
+
+// This file is part of Autocycler. Autocycler is free software: you can redistribute it and/or
+// modify it under the terms of the GNU General Public License as published by the Free Software
+// Foundation, either version 3 of the License, or (at your option) any later version. Autocycler
+// is distributed in the hope that it will be useful, but WITHOUT ANY WARRANTY; without even the
+// implied warranty of MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the GNU General
+// Public License for more details. You should have received a copy of the GNU General Public
+// License along with Autocycler. If not, see <http://www.gnu.org/licenses/>.
+
+use std::collections::{BTreeSet, HashMap};
+use std::fs::File;
+use std::io::{BufWriter, Write};
+use std::path::{Path, PathBuf};
+
+use crate::compare::{contigs_to_sequences, joint_graph, unitig_counts};
+use crate::log::{section_header, explanation};
+use crate::misc::{check_if_dir_exists, check_if_file_exists, load_fasta, load_file_lines,
+                  quit_with_error, reverse_complement};
+use crate::sequence::Sequence;
+use crate::unitig_graph::UnitigGraph;
+
+
+pub fn annotate_transfer(autocycler_dir: PathBuf, assembly: String, in_gff: PathBuf,
+                         out_gff: PathBuf) {
+    let input_gfa = autocycler_dir.join("input_assemblies.gfa");
+    let consensus_fasta = autocycler_dir.join("consensus_assembly.fasta");
+    check_settings(&autocycler_dir, &input_gfa, &consensus_fasta, &in_gff);
+    starting_message();
+    print_settings(&autocycler_dir, &assembly, &in_gff, &out_gff);
+    let (input_contigs, k_size) = load_input_contigs(&input_gfa, &assembly);
+    let consensus_contigs = load_fasta(&consensus_fasta);
+
+    section_header("Building joint graph");
+    explanation("The input assembly's contigs and the consensus sequences are now combined into \
+                 one unitig graph. Unitigs which occur once in each are used as anchors for \
+                 moving coordinates between them.");
+    let mut sequences = contigs_to_sequences(input_contigs, &assembly, k_size, 0);
+    let input_count = sequences.len();
+    sequences.extend(contigs_to_sequences(consensus_contigs, "consensus_assembly.fasta", k_size,
+                                          input_count));
+    let (graph, sequences) = joint_graph(sequences, k_size);
+
+    section_header("Transferring features");
+    explanation("Each feature's start and end are now moved onto the consensus. Features which \
+                 overlap a region where the input assembly and the consensus disagree are \
+                 flagged, and features which start or end in such a region can't be \
+                 transferred.");
+    let features = load_gff(&in_gff);
+    let lifter = LiftOver::new(&graph, &sequences, input_count);
+    let mut out_lines = vec!["##gff-version 3".to_string()];
+    let (mut ok_count, mut flagged_count, mut failed_count) = (0, 0, 0);
+    for feature in &features {
+        match lifter.transfer(feature) {
+            Some((line, flagged)) => {
+                if flagged { flagged_count += 1; } else { ok_count += 1; }
+                out_lines.push(line);
+            }
+            None => { failed_count += 1; }
+        }
+    }
+    save_gff(&out_gff, &out_lines);
+    eprintln!("{} feature{} transferred", ok_count, match ok_count { 1 => "", _ => "s" });
+    eprintln!("{} feature{} transferred but flagged as crossing a disagreement", flagged_count,
+              match flagged_count { 1 => "", _ => "s" });
+    eprintln!("{} feature{} not transferred", failed_count,
+              match failed_count { 1 => "", _ => "s" });
+    eprintln!();
+    finished_message(&out_gff);
+}
+
+
+fn check_settings(autocycler_dir: &Path, input_gfa: &Path, consensus_fasta: &Path,
+                  in_gff: &Path) {
+    check_if_dir_exists(autocycler_dir);
+    check_if_file_exists(input_gfa);
+    check_if_file_exists(consensus_fasta);
+    check_if_file_exists(in_gff);
+}
+
+
+fn starting_message() {
+    section_header("Starting autocycler annotate-transfer");
+    explanation("This command moves annotations (in GFF3 format) from one of the input \
+                 assemblies onto the consensus assembly.");
+}
+
+
+fn print_settings(autocycler_dir: &Path, assembly: &str, in_gff: &Path, out_gff: &Path) {
+    eprintln!("Settings:");
+    eprintln!("  --autocycler_dir {}", autocycler_dir.display());
+    eprintln!("  --assembly {}", assembly);
+    eprintln!("  --in_gff {}", in_gff.display());
+    eprintln!("  --out_gff {}", out_gff.display());
+    eprintln!();
+}
+
+
+fn finished_message(out_gff: &Path) {
+    section_header("Finished!");
+    eprintln!("Transferred annotations: {}", out_gff.display());
+    eprintln!();
+}
+
+
+fn load_input_contigs(input_gfa: &Path, assembly: &str) -> (Vec<(String, String, String)>, u32) {
+    // Reconstructs the input assembly's contigs from their paths in the compressed graph, and
+    // returns them along with the graph's k-mer size.
+    section_header("Loading input assembly");
+    explanation("The input assembly's contigs are now rebuilt from their paths in the \
+                 compressed graph.");
+    let (graph, sequences) = UnitigGraph::from_gfa_file(input_gfa);
+    let assembly_seqs: Vec<Sequence> = sequences.iter()
+        .filter(|s| s.filename == assembly).cloned().collect();
+    if assembly_seqs.is_empty() {
+        let filenames: BTreeSet<&str> = sequences.iter().map(|s| s.filename.as_str()).collect();
+        quit_with_error(&format!("no input assembly named {} in {} (available: {})", assembly,
+                                 input_gfa.display(),
+                                 filenames.into_iter().collect::<Vec<_>>().join(", ")));
+    }
+    let paths = graph.get_unitig_paths_for_sequences(&assembly_seqs);
+    let contigs: Vec<(String, String, String)> = paths.iter().zip(&assembly_seqs)
+        .map(|(path, seq)| {
+            (seq.contig_name(), seq.contig_header.clone(),
+             graph.get_repaired_sequence_from_path(seq, path))
+        }).collect();
+    for (name, _, seq) in &contigs {
+        eprintln!("  {} ({} bp)", name, seq.len());
+    }
+    eprintln!();
+    (contigs, graph.k_size)
+}
+
+
+#[derive(Debug, PartialEq)]
+struct Feature {
+    // GFF3 columns, with start and end as 0-based inclusive positions.
+    seqid: String,
+    columns: Vec<String>,
+    start: usize,
+    end: usize,
+    strand: char,
+}
+
+
+fn load_gff(in_gff: &Path) -> Vec<Feature> {
+    parse_gff(&load_file_lines(in_gff))
+}
+
+
+fn parse_gff(lines: &[String]) -> Vec<Feature> {
+    // Reads feature lines up to an embedded FASTA section (if any), skipping comments.
+    if lines.iter().find(|l| !l.is_empty()).is_some_and(|l| l.starts_with("LOCUS")) {
+        quit_with_error("annotations appear to be in GenBank format, which is not supported \
+                         - please convert them to GFF3");
+    }
+    let mut features = Vec::new();
+    for line in lines {
+        if line.starts_with("##FASTA") { break; }
+        if line.is_empty() || line.starts_with('#') { continue; }
+        let columns: Vec<String> = line.split('\t').map(String::from).collect();
+        if columns.len() != 9 {
+            quit_with_error(&format!("GFF line does not have 9 columns: {}", line));
+        }
+        let parse_pos = |s: &str| s.parse::<usize>().ok().filter(|&p| p > 0).unwrap_or_else(|| {
+            quit_with_error(&format!("bad position in GFF line: {}", line));
+        });
+        let (start, end) = (parse_pos(&columns[3]) - 1, parse_pos(&columns[4]) - 1);
+        if end < start { quit_with_error(&format!("GFF feature end before start: {}", line)); }
+        let strand = columns[6].chars().next().unwrap_or('.');
+        features.push(Feature { seqid: columns[0].clone(), columns, start, end, strand });
+    }
+    features
+}
+
+
+fn save_gff(out_gff: &Path, lines: &[String]) {
+    let result = File::create(out_gff).and_then(|file| {
+        let mut writer = BufWriter::new(file);
+        for line in lines {
+            writeln!(writer, "{}", line)?;
+        }
+        writer.flush()
+    });
+    if let Err(e) = result {
+        quit_with_error(&format!("unable to write {}\n{}", out_gff.display(), e));
+    }
+}
+
+
+// An input contig's sequence, unitig path and the start offset of each unitig in the path.
+type InputContig = (Vec<u8>, Vec<i32>, Vec<usize>);
+
+
+struct LiftOver<'a> {
+    graph: &'a UnitigGraph,
+    input_paths: HashMap<String, InputContig>,
+    // Anchor unitig number -> (consensus contig index, offset, strand of the unitig's forward
+    // strand in the consensus).
+    anchors: HashMap<u32, (usize, usize, bool)>,
+    consensus: Vec<(String, Vec<u8>, bool)>,  // name, sequence, circular
+}
+
+impl<'a> LiftOver<'a> {
+    fn new(graph: &'a UnitigGraph, sequences: &[Sequence], input_count: usize) -> Self {
+        let paths = graph.get_unitig_paths_for_sequences_i32(sequences);
+        let unitig_length = |n: i32| graph.unitig(n.unsigned_abs()).length()
+                                         as usize;
+        let offsets = |path: &[i32]| -> Vec<usize> {
+            path.iter().scan(0, |pos, &n| {
+                let start = *pos;
+                *pos += unitig_length(n);
+                Some(start)
+            }).collect()
+        };
+        let input_counts = unitig_counts(&paths[..input_count].concat());
+        let consensus_counts = unitig_counts(&paths[input_count..].concat());
+        let mut anchors = HashMap::new();
+        for (c, path) in paths[input_count..].iter().enumerate() {
+            for (&n, offset) in path.iter().zip(offsets(path)) {
+                let num = n.unsigned_abs();
+                if input_counts.get(&num) == Some(&1) && consensus_counts.get(&num) == Some(&1) {
+                    anchors.insert(num, (c, offset, n > 0));
+                }
+            }
+        }
+        LiftOver {
+            graph,
+            input_paths: sequences[..input_count].iter().zip(&paths)
+                .map(|(s, p)| (s.contig_name(),
+                               (graph.get_sequence_from_path_signed(p), p.clone(), offsets(p))))
+                .collect(),
+            anchors,
+            consensus: sequences[input_count..].iter().zip(&paths[input_count..])
+                .map(|(s, p)| (s.contig_name(), graph.get_sequence_from_path_signed(p),
+                               s.contig_header.split_whitespace().any(|t| t == "circular=true")))
+                .collect(),
+        }
+    }
+
+    fn transfer(&self, feature: &Feature) -> Option<(String, bool)> {
+        // Returns the feature's GFF line on the consensus, and whether its sequence differs
+        // between the input and the consensus. Features crossing the origin of a circular
+        // consensus sequence get an end past the sequence length, as in GFF3.
+        let (input_seq, path, offsets) = self.input_paths.get(&feature.seqid)?;
+        if feature.end >= input_seq.len() { return None; }
+        let (c_1, pos_1, same_1) = self.map_position(path, offsets, feature.start)?;
+        let (c_2, pos_2, same_2) = self.map_position(path, offsets, feature.end)?;
+        if c_1 != c_2 || same_1 != same_2 { return None; }
+        let (name, consensus_seq, circular) = &self.consensus[c_1];
+        let (start, mut end) = if same_1 { (pos_1, pos_2) } else { (pos_2, pos_1) };
+        if end < start {
+            if !circular { return None; }
+            end += consensus_seq.len();
+        }
+        let mut mapped_seq: Vec<u8> = (start..=end).map(|i| consensus_seq[i % consensus_seq.len()])
+                                                   .collect();
+        if !same_1 { mapped_seq = reverse_complement(&mapped_seq); }
+        let flagged = mapped_seq != input_seq[feature.start..=feature.end];
+
+        let strand = match (feature.strand, same_1) {
+            ('+', false) => '-',
+            ('-', false) => '+',
+            (s, _) => s,
+        };
+        let mut columns = feature.columns.clone();
+        columns[0] = name.clone();
+        columns[3] = (start + 1).to_string();
+        columns[4] = (end + 1).to_string();
+        columns[6] = strand.to_string();
+        let flag = format!("autocycler_transfer={}", if flagged { "disagreement" } else { "ok" });
+        columns[8] = if columns[8].is_empty() || columns[8] == "." { flag }
+                     else { format!("{};{}", columns[8].trim_end_matches(';'), flag) };
+        Some((columns.join("\t"), flagged))
+    }
+
+    fn map_position(&self, path: &[i32], offsets: &[usize], pos: usize)
+            -> Option<(usize, usize, bool)> {
+        // Maps an input contig position to (consensus index, position, same strand), which is only
+        // possible if the position is in an anchor unitig.
+        let i = offsets.partition_point(|&o| o <= pos).checked_sub(1)?;
+        let n = path[i];
+        let length = self.graph.unitig(n.unsigned_abs()).length() as usize;
+        let within = pos - offsets[i];
+        if within >= length { return None; }
+        let &(c, c_offset, c_forward) = self.anchors.get(&n.unsigned_abs())?;
+        let same_strand = (n > 0) == c_forward;
+        let c_pos = if same_strand { c_offset + within } else { c_offset + length - 1 - within };
+        Some((c, c_pos, same_strand))
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::tests::random_seq;
+
+    fn gff_lines(text: &str) -> Vec<String> {
+        text.lines().map(String::from).collect()
+    }
+
+    #[test]
+    fn test_parse_gff() {
+        let features = parse_gff(&gff_lines("##gff-version 3\n\
+                                             c1\tx\tCDS\t11\t20\t.\t+\t0\tID=a\n\
+                                             ##FASTA\n>c1\nACGT"));
+        assert_eq!(features.len(), 1);
+        assert_eq!((features[0].start, features[0].end, features[0].strand), (10, 19, '+'));
+    }
+
+    #[test]
+    fn test_parse_genbank() {
+        let lines = gff_lines("LOCUS       c1    4 bp    DNA     circular\nORIGIN\n        1 acgt\n//");
+        assert!(std::panic::catch_unwind(|| { parse_gff(&lines); }).is_err());
+    }
+
+    #[test]
+    fn test_transfer() {
+        // The consensus is the input contig rotated, reverse-complemented and with a SNP at 2000.
+        let input = random_seq(3000, 3);
+        let mut consensus = format!("{}{}", &input[1000..], &input[..1000]).into_bytes();
+        consensus[1000] = if consensus[1000] == b'A' { b'C' } else { b'A' };
+        let consensus = String::from_utf8(reverse_complement(&consensus)).unwrap();
+        let mut sequences = contigs_to_sequences(vec![("c1".to_string(), "c1".to_string(),
+                                                       input)], "a.fasta", 21, 0);
+        sequences.extend(contigs_to_sequences(vec![("1".to_string(),
+                                                     "1 circular=true".to_string(), consensus)],
+                                              "consensus_assembly.fasta", 21, 1));
+        let (graph, sequences) = joint_graph(sequences, 21);
+        let lifter = LiftOver::new(&graph, &sequences, 1);
+        let features = parse_gff(&gff_lines("c1\tx\tgene\t101\t200\t.\t+\t.\tID=a\n\
+                                             c1\tx\tgene\t1901\t2100\t.\t-\t.\tID=b;\n\
+                                             c1\tx\tgene\t951\t1050\t.\t+\t.\t.\n\
+                                             c2\tx\tgene\t1\t10\t.\t+\t.\t."));
+
+        // Input position p is at consensus position 2999 - ((p - 1000) mod 3000), on the other
+        // strand. The second feature contains the SNP and the third crosses the consensus origin.
+        assert_eq!(lifter.transfer(&features[0]),
+                   Some(("1\tx\tgene\t801\t900\t.\t-\t.\tID=a;autocycler_transfer=ok"
+                         .to_string(), false)));
+        assert_eq!(lifter.transfer(&features[1]),
+                   Some(("1\tx\tgene\t1901\t2100\t.\t+\t.\tID=b;autocycler_transfer=disagreement"
+                         .to_string(), true)));
+        assert_eq!(lifter.transfer(&features[2]),
+                   Some(("1\tx\tgene\t2951\t3050\t.\t-\t.\tautocycler_transfer=ok".to_string(),
+                         false)));
+        assert_eq!(lifter.transfer(&features[3]), None);
+    }
+}
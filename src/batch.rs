@@ -0,0 +1,435 @@
+// This file contains the code for running Autocycler steps on multiple samples (batch mode).
+
+// Copyright 2024 Ryan Wick (rrwick@gmail.com)
+// https://github.com/rrwick/Autocycler
+
+// This file is part of Autocycler. Autocycler is free software: you can redistribute it and/or
+// modify it under the terms of the GNU General Public License as published by the Free Software
+// Foundation, either version 3 of the License, or (at your option) any later version. Autocycler
+// is distributed in the hope that it will be useful, but WITHOUT ANY WARRANTY; without even the
+// implied warranty of MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the GNU General
+// Public License for more details. You should have received a copy of the GNU General Public
+// License along with Autocycler. If not, see <http://www.gnu.org/licenses/>.
+
+use std::collections::HashSet;
+use std::ffi::OsString;
+use std::fs::{self, File};
+use std::path::{Path, PathBuf};
+use std::process::{Command, Stdio};
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+
+use crate::log::{section_header, explanation};
+use crate::misc::{check_if_dir_exists, check_if_file_exists, create_dir, load_file_lines,
+                  quit_with_error};
+use crate::table::{get_header_line, get_values_line, parse_fields};
+
+
+// The summary table's columns: the main metrics from each step which can be run in batch mode
+// (compress, cluster, trim and resolve), so the table is filled in as far as the samples have
+// been processed.
+const SUMMARY_FIELDS: &str = "input_assemblies_count, input_assemblies_total_contigs, \
+                              input_assemblies_total_length, compressed_unitig_count, \
+                              compressed_unitig_total_length, pass_cluster_count, \
+                              fail_cluster_count, overall_clustering_score, \
+                              trimmed_cluster_size, trimmed_cluster_median, \
+                              consensus_assembly_bases, consensus_assembly_unitigs, \
+                              consensus_assembly_fully_resolved";
+
+
+#[derive(Debug, PartialEq)]
+pub struct Sample {
+    pub name: String,
+    pub assemblies_dir: Option<PathBuf>,  // only needed for compress
+}
+
+
+pub fn compress_samples(manifest: &Path, batch_dir: &Path, jobs: usize) {
+    // Runs compress for each sample, from its assemblies directory into batch_dir/<name>.
+    let samples = load_samples(manifest, batch_dir, jobs);
+    if let Some(s) = samples.iter().find(|s| s.assemblies_dir.is_none()) {
+        quit_with_error(&format!("sample {} has no assemblies directory in {}", s.name,
+                                 manifest.display()));
+    }
+    for s in &samples {
+        check_if_dir_exists(s.assemblies_dir.as_ref().unwrap());
+    }
+    create_dir(batch_dir);
+    let args = sample_args(&["-i", "--assemblies_dir", "-a", "--autocycler_dir"]);
+    let passed = run_samples(&samples, jobs, |s| {
+        let log = open_log(batch_dir, s, &args);
+        run_step(&args, &[OsString::from("--assemblies_dir"),
+                          s.assemblies_dir.clone().unwrap().into_os_string(),
+                          OsString::from("--autocycler_dir"),
+                          batch_dir.join(&s.name).into_os_string()], log.as_ref())
+    });
+    save_summary(batch_dir, &samples, &passed);
+}
+
+
+pub fn process_samples(manifest: &Path, batch_dir: &Path, jobs: usize) {
+    // Runs a step (e.g. cluster) on each sample's Autocycler directory, batch_dir/<name>.
+    let samples = load_samples(manifest, batch_dir, jobs);
+    check_sample_dirs(batch_dir, &samples);
+    let args = sample_args(&["-a", "--autocycler_dir"]);
+    let passed = run_samples(&samples, jobs, |s| {
+        let log = open_log(batch_dir, s, &args);
+        run_step(&args, &[OsString::from("--autocycler_dir"),
+                          batch_dir.join(&s.name).into_os_string()], log.as_ref())
+    });
+    save_summary(batch_dir, &samples, &passed);
+}
+
+
+pub fn process_clusters(manifest: &Path, batch_dir: &Path, jobs: usize) {
+    // Runs a per-cluster step (e.g. trim or resolve) on each QC-pass cluster of each sample. A
+    // failed cluster fails its sample, but the sample's other clusters are still processed.
+    let samples = load_samples(manifest, batch_dir, jobs);
+    check_sample_dirs(batch_dir, &samples);
+    let args = sample_args(&["-c", "--cluster_dir"]);
+    let passed = run_samples(&samples, jobs, |s| {
+        let log = open_log(batch_dir, s, &args);
+        let cluster_dirs = qc_pass_cluster_dirs(&batch_dir.join(&s.name));
+        if cluster_dirs.is_empty() {
+            eprintln!("Warning: sample {} has no QC-pass clusters", s.name);
+        }
+        let mut passed = true;
+        for cluster_dir in cluster_dirs {
+            passed &= run_step(&args, &[OsString::from("--cluster_dir"),
+                                        cluster_dir.into_os_string()], log.as_ref());
+        }
+        passed
+    });
+    save_summary(batch_dir, &samples, &passed);
+}
+
+
+pub fn check_not_batch_option(used: bool, option: &str) {
+    // Some options only make sense for a single sample (e.g. a read file).
+    if used {
+        quit_with_error(&format!("{} cannot be used with --samples", option));
+    }
+}
+
+
+fn load_samples(manifest: &Path, batch_dir: &Path, jobs: usize) -> Vec<Sample> {
+    check_if_file_exists(manifest);
+    if jobs < 1 { quit_with_error("--jobs cannot be less than 1"); }
+    let samples = parse_manifest(manifest);
+    section_header("Batch mode");
+    explanation(&format!("Each sample in the manifest is now processed in its own subdirectory \
+                          of {}{}. Each sample's output is saved to a log file in the logs \
+                          subdirectory.", batch_dir.display(),
+                         if jobs > 1 { format!(", {} at a time", jobs) } else { String::new() }));
+    eprintln!("{} sample{}: {}", samples.len(), match samples.len() { 1 => "", _ => "s" },
+              samples.iter().map(|s| s.name.as_str()).collect::<Vec<_>>().join(", "));
+    eprintln!();
+    samples
+}
+
+
+fn parse_manifest(manifest: &Path) -> Vec<Sample> {
+    // Each line has a sample name and optionally (separated by a tab) its assemblies directory.
+    // Blank lines and lines starting with # are ignored. Relative directories are relative to
+    // the manifest's directory.
+    let base_dir = manifest.parent().unwrap_or(Path::new(""));
+    let mut samples = Vec::new();
+    let mut names = HashSet::new();
+    for line in load_file_lines(manifest) {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') { continue; }
+        let mut parts = line.split('\t').map(|p| p.trim());
+        let name = parts.next().unwrap().to_string();
+        if name.is_empty() || name.contains('/') || name.contains('\\') || name.starts_with('.') {
+            quit_with_error(&format!("invalid sample name in {}: {}", manifest.display(), name));
+        }
+        if !names.insert(name.clone()) {
+            quit_with_error(&format!("duplicate sample name in {}: {}", manifest.display(), name));
+        }
+        let assemblies_dir = parts.next().filter(|p| !p.is_empty()).map(|p| base_dir.join(p));
+        samples.push(Sample { name, assemblies_dir });
+    }
+    if samples.is_empty() {
+        quit_with_error(&format!("no samples found in {}", manifest.display()));
+    }
+    samples
+}
+
+
+fn check_sample_dirs(batch_dir: &Path, samples: &[Sample]) {
+    check_if_dir_exists(batch_dir);
+    for s in samples {
+        check_if_dir_exists(&batch_dir.join(&s.name));
+    }
+}
+
+
+fn run_samples<F>(samples: &[Sample], jobs: usize, run: F) -> Vec<bool>
+        where F: Fn(&Sample) -> bool + Sync {
+    // With multiple jobs, each worker thread takes the next unstarted sample. Returns whether
+    // each sample succeeded.
+    let next = AtomicUsize::new(0);
+    let passed: Vec<AtomicBool> = samples.iter().map(|_| AtomicBool::new(false)).collect();
+    std::thread::scope(|scope| {
+        for _ in 0..jobs.min(samples.len()) {
+            scope.spawn(|| {
+                loop {
+                    let i = next.fetch_add(1, Ordering::SeqCst);
+                    let Some(s) = samples.get(i) else { break; };
+                    let ok = run(s);
+                    eprintln!("{}: {}", s.name, if ok { "done" } else { "failed" });
+                    passed[i].store(ok, Ordering::SeqCst);
+                }
+            });
+        }
+    });
+    eprintln!();
+    passed.into_iter().map(|p| p.into_inner()).collect()
+}
+
+
+fn sample_args(per_sample_options: &[&str]) -> Vec<OsString> {
+    // The batch command's own arguments (starting with the subcommand), minus the batch options
+    // and the options which are given separately for each sample.
+    let args: Vec<OsString> = std::env::args_os().skip(1).collect();
+    let mut strip = vec!["--samples", "--jobs"];
+    strip.extend_from_slice(per_sample_options);
+    strip_options(&args, &strip)
+}
+
+
+fn strip_options(args: &[OsString], strip: &[&str]) -> Vec<OsString> {
+    // Removes the given options and their values, whether given as separate arguments
+    // (--opt value, -o value) or joined (--opt=value, -ovalue).
+    let mut kept = Vec::new();
+    let mut args = args.iter();
+    while let Some(arg) = args.next() {
+        let a = arg.to_string_lossy();
+        if strip.contains(&a.as_ref()) {
+            args.next();
+        } else if !strip.iter().any(|o| joined_option(&a, o)) {
+            kept.push(arg.clone());
+        }
+    }
+    kept
+}
+
+
+fn joined_option(arg: &str, option: &str) -> bool {
+    if option.starts_with("--") {
+        arg.strip_prefix(option).is_some_and(|v| v.starts_with('='))
+    } else {
+        !arg.starts_with("--") && arg.len() > option.len() && arg.starts_with(option)
+    }
+}
+
+
+fn open_log(batch_dir: &Path, sample: &Sample, args: &[OsString]) -> Option<File> {
+    // Each sample's output for a step goes to its own log file, so parallel samples don't
+    // interleave their output.
+    let log_dir = batch_dir.join("logs");
+    let step = args.first().map(|a| a.to_string_lossy().to_string()).unwrap_or_default();
+    let log = log_dir.join(format!("{}.{}.log", sample.name, step));
+    match fs::create_dir_all(&log_dir).and_then(|_| File::create(&log)) {
+        Ok(file) => Some(file),
+        Err(e) => {
+            eprintln!("Warning: unable to create {}\n{}", log.display(), e);
+            None
+        }
+    }
+}
+
+
+fn run_step(args: &[OsString], sample_args: &[OsString], log: Option<&File>) -> bool {
+    // Runs the step in its own Autocycler process, so a sample which fails (and exits) doesn't
+    // stop the other samples.
+    let Some(log) = log else { return false; };
+    let exe = match std::env::current_exe() {
+        Ok(exe) => exe,
+        Err(e) => quit_with_error(&format!("unable to find the autocycler executable\n{}", e)),
+    };
+    run_command(Command::new(exe).args(args).args(sample_args), log)
+}
+
+
+fn run_command(command: &mut Command, log: &File) -> bool {
+    let (Ok(stdout), Ok(stderr)) = (log.try_clone(), log.try_clone()) else { return false; };
+    command.stdin(Stdio::null()).stdout(stdout).stderr(stderr).status()
+        .is_ok_and(|s| s.success())
+}
+
+
+pub fn qc_pass_cluster_dirs(autocycler_dir: &Path) -> Vec<PathBuf> {
+    let pass_dir = autocycler_dir.join("clustering").join("qc_pass");
+    let mut cluster_dirs: Vec<PathBuf> = fs::read_dir(pass_dir).into_iter().flatten().flatten()
+        .map(|entry| entry.path())
+        .filter(|path| path.is_dir() && path.file_name()
+                                            .is_some_and(|n| n.to_string_lossy()
+                                                              .starts_with("cluster_")))
+        .collect();
+    cluster_dirs.sort();
+    cluster_dirs
+}
+
+
+fn save_summary(batch_dir: &Path, samples: &[Sample], passed: &[bool]) {
+    // Prints a table of all samples' metrics (as made by autocycler table) and saves it to
+    // summary.tsv in the batch directory, replacing the table from any earlier step. The last
+    // column says whether the sample passed this step.
+    let fields = parse_fields(SUMMARY_FIELDS.to_string());
+    let mut table = get_header_line(&fields) + "\tstatus\n";
+    for (s, &p) in samples.iter().zip(passed) {
+        table.push_str(&get_values_line(&batch_dir.join(&s.name), &s.name, &fields, 3));
+        table.push_str(if p { "\tpass\n" } else { "\tfail\n" });
+    }
+    let summary_tsv = batch_dir.join("summary.tsv");
+    if let Err(e) = fs::write(&summary_tsv, &table) {
+        quit_with_error(&format!("unable to write {}\n{}", summary_tsv.display(), e));
+    }
+    section_header("Batch summary");
+    let failures = passed.iter().filter(|&&p| !p).count();
+    if failures > 0 {
+        eprintln!("Warning: {} sample{} failed, see logs in {}", failures,
+                  match failures { 1 => "", _ => "s" }, batch_dir.join("logs").display());
+    }
+    eprintln!("Summary table: {}", summary_tsv.display());
+    eprintln!();
+    print!("{}", table);
+}
+
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+    use std::sync::Mutex;
+
+    use super::*;
+    use crate::compress::{compress, CompressSettings};
+    use crate::tests::{make_test_file, random_seq};
+    use crate::unitig_graph::UnitigGraph;
+
+    #[test]
+    fn test_parse_manifest() {
+        let dir = tempfile::tempdir().unwrap();
+        let manifest = dir.path().join("samples.tsv");
+        make_test_file(&manifest, "# name\tassemblies\nsample_a\tassemblies/a\n\n\
+                                   sample_b\t/data/b\nsample_c\n");
+        assert_eq!(parse_manifest(&manifest),
+                   vec![Sample { name: "sample_a".to_string(),
+                                 assemblies_dir: Some(dir.path().join("assemblies/a")) },
+                        Sample { name: "sample_b".to_string(),
+                                 assemblies_dir: Some(PathBuf::from("/data/b")) },
+                        Sample { name: "sample_c".to_string(), assemblies_dir: None }]);
+    }
+
+    #[test]
+    fn test_parse_manifest_bad() {
+        for contents in ["a\tx\na\ty\n", "../a\tx\n", "# only a comment\n"] {
+            let dir = tempfile::tempdir().unwrap();
+            let manifest = dir.path().join("samples.tsv");
+            make_test_file(&manifest, contents);
+            assert!(std::panic::catch_unwind(|| { parse_manifest(&manifest); }).is_err());
+        }
+    }
+
+    #[test]
+    fn test_run_samples() {
+        // Every sample is run exactly once, however many jobs there are, and a failed sample
+        // doesn't stop the others.
+        let samples: Vec<Sample> = (0..5).map(|i| Sample { name: i.to_string(),
+                                                           assemblies_dir: None }).collect();
+        for jobs in [1, 2, 8] {
+            let done = Mutex::new(Vec::new());
+            let passed = run_samples(&samples, jobs, |s| {
+                done.lock().unwrap().push(s.name.clone());
+                s.name != "2"
+            });
+            let mut done = done.into_inner().unwrap();
+            done.sort();
+            assert_eq!(done, vec!["0", "1", "2", "3", "4"]);
+            assert_eq!(passed, vec![true, true, false, true, true]);
+        }
+    }
+
+    #[test]
+    fn test_strip_options() {
+        let args: Vec<OsString> = ["cluster", "-a", "batch", "--samples", "s.tsv", "--jobs=4",
+                                   "-t", "8", "--autocycler_dir=x", "-abatch", "--max_contigs",
+                                   "25"].iter().map(OsString::from).collect();
+        let strip = ["--samples", "--jobs", "-a", "--autocycler_dir"];
+        assert_eq!(strip_options(&args, &strip),
+                   ["cluster", "-t", "8", "--max_contigs", "25"].iter().map(OsString::from)
+                                                                 .collect::<Vec<_>>());
+    }
+
+    #[test]
+    fn test_run_command() {
+        // The command's stdout and stderr both go to the log.
+        let dir = tempfile::tempdir().unwrap();
+        let log_file = dir.path().join("a.log");
+        let log = File::create(&log_file).unwrap();
+        assert!(run_command(Command::new("sh").args(["-c", "echo out; echo err >&2"]), &log));
+        assert!(!run_command(Command::new("sh").args(["-c", "exit 1"]), &log));
+        assert_eq!(fs::read_to_string(&log_file).unwrap(), "out\nerr\n");
+    }
+
+    #[test]
+    fn test_qc_pass_cluster_dirs() {
+        let dir = tempfile::tempdir().unwrap();
+        assert!(qc_pass_cluster_dirs(dir.path()).is_empty());
+        let pass_dir = dir.path().join("clustering/qc_pass");
+        for name in ["cluster_002", "cluster_001", "other"] {
+            create_dir(&pass_dir.join(name));
+        }
+        assert_eq!(qc_pass_cluster_dirs(dir.path()),
+                   vec![pass_dir.join("cluster_001"), pass_dir.join("cluster_002")]);
+    }
+
+    #[test]
+    fn test_save_summary() {
+        let dir = tempfile::tempdir().unwrap();
+        let samples = vec![Sample { name: "a".to_string(), assemblies_dir: None },
+                           Sample { name: "b".to_string(), assemblies_dir: None }];
+        create_dir(&dir.path().join("a"));
+        make_test_file(&dir.path().join("a/consensus_assembly.yaml"),
+                       "consensus_assembly_bases: 1234\n");
+        save_summary(dir.path(), &samples, &[true, false]);
+        let summary = fs::read_to_string(dir.path().join("summary.tsv")).unwrap();
+        let lines: Vec<&str> = summary.lines().collect();
+        assert_eq!(lines.len(), 3);
+        assert!(lines[0].ends_with("\tstatus"));
+        assert!(lines[1].starts_with("a\t") && lines[1].contains("\t1234\t"));
+        assert!(lines[1].ends_with("\tpass"));
+        assert!(lines[2].starts_with("b\t") && lines[2].ends_with("\tfail"));
+    }
+
+    #[test]
+    fn test_save_summary_after_compress() {
+        // After compress, the summary has the sample's input assembly and unitig metrics.
+        let dir = tempfile::tempdir().unwrap();
+        let assemblies_dir = dir.path().join("assemblies");
+        create_dir(&assemblies_dir);
+        let base = random_seq(2000, 0);
+        let inserted = format!("{}{}{}", &base[..1000], random_seq(100, 1), &base[1000..]);
+        make_test_file(&assemblies_dir.join("x.fasta"), &format!(">x\n{}\n", base));
+        make_test_file(&assemblies_dir.join("y.fasta"), &format!(">y\n{}\n", inserted));
+        let batch_dir = dir.path().join("batch");
+        compress(CompressSettings::new(assemblies_dir, batch_dir.join("a"), 51, 1, 1));
+        let samples = vec![Sample { name: "a".to_string(), assemblies_dir: None }];
+        save_summary(&batch_dir, &samples, &[true]);
+
+        let summary = fs::read_to_string(batch_dir.join("summary.tsv")).unwrap();
+        let lines: Vec<&str> = summary.lines().collect();
+        let row: HashMap<&str, &str> = lines[0].split('\t').zip(lines[1].split('\t')).collect();
+        let (graph, _) = UnitigGraph::from_gfa_file(&batch_dir.join("a/input_assemblies.gfa"));
+        let total_length: u32 = graph.unitigs.iter().map(|u| u.length()).sum();
+        assert!(graph.unitigs.len() > 1);
+        assert_eq!(row["name"], "a");
+        assert_eq!(row["input_assemblies_count"], "2");
+        assert_eq!(row["input_assemblies_total_contigs"], "2");
+        assert_eq!(row["input_assemblies_total_length"], "4100");
+        assert_eq!(row["compressed_unitig_count"], graph.unitigs.len().to_string());
+        assert_eq!(row["compressed_unitig_total_length"], total_length.to_string());
+        assert_eq!(row["pass_cluster_count"], "");
+        assert_eq!(row["status"], "pass");
+    }
+}
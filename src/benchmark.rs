@@ -0,0 +1,195 @@
+// This file contains the code for the autocycler benchmark subcommand.
+
+// Copyright 2024 Ryan Wick (rrwick@gmail.com)
+// https://github.com/rrwick/Autocycler
+
+// This file is part of Autocycler. Autocycler is free software: you can redistribute it and/or
+// modify it under the terms of the GNU General Public License as published by the Free Software
+// Foundation, either version 3 of the License, or (at your option) any later version. Autocycler
+// is distributed in the hope that it will be useful, but WITHOUT ANY WARRANTY; without even the
+// implied warranty of MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the GNU General
+// Public License for more details. You should have received a copy of the GNU General Public
+// License along with Autocycler. If not, see <http://www.gnu.org/licenses/>.
+
+use std::path::{Path, PathBuf};
+
+use crate::compare::{build_joint_graph, compare_assemblies, RepliconComparison};
+use crate::log::{section_header, explanation};
+use crate::metrics::{BenchmarkMetrics, BenchmarkRepliconDetails};
+use crate::misc::{check_if_dir_exists, check_if_file_exists, quit_with_error};
+
+
+pub fn benchmark(autocycler_dir: PathBuf, reference: PathBuf, k_size: u32) {
+    let consensus = autocycler_dir.join("consensus_assembly.fasta");
+    let yaml = autocycler_dir.join("benchmark.yaml");
+    check_settings(&autocycler_dir, &consensus, &reference, k_size);
+    starting_message();
+    print_settings(&autocycler_dir, &reference, k_size);
+
+    // The reference is assembly A and the consensus is assembly B, so each reference replicon
+    // gets a row, paired with its best-matching consensus contig.
+    let (graph, sequences, ref_count) = build_joint_graph(&reference, &consensus, k_size);
+    let (_, replicons) = compare_assemblies(&graph, &sequences, ref_count);
+    let metrics = benchmark_metrics(&replicons);
+    print_results(&metrics);
+    metrics.save_to_yaml(&yaml);
+    finished_message(&yaml);
+}
+
+
+fn check_settings(autocycler_dir: &Path, consensus: &Path, reference: &Path, k_size: u32) {
+    check_if_dir_exists(autocycler_dir);
+    check_if_file_exists(consensus);
+    check_if_file_exists(reference);
+    if k_size < 11 { quit_with_error("--kmer cannot be less than 11"); }
+    if k_size > 501 { quit_with_error("--kmer cannot be greater than 501"); }
+}
+
+
+fn starting_message() {
+    section_header("Starting autocycler benchmark");
+    explanation("This command compares an Autocycler consensus assembly to a trusted reference \
+                 genome. For each reference replicon, it reports how much is present in the \
+                 consensus (completeness), how accurately (identity) and how many structural \
+                 differences there are.");
+}
+
+
+fn print_settings(autocycler_dir: &Path, reference: &Path, k_size: u32) {
+    eprintln!("Settings:");
+    eprintln!("  --autocycler_dir {}", autocycler_dir.display());
+    eprintln!("  --reference {}", reference.display());
+    eprintln!("  --kmer {}", k_size);
+    eprintln!();
+}
+
+
+fn finished_message(yaml: &Path) {
+    section_header("Finished!");
+    eprintln!("Benchmark metrics: {}", yaml.display());
+    eprintln!();
+}
+
+
+fn benchmark_metrics(replicons: &[RepliconComparison]) -> BenchmarkMetrics {
+    // Completeness is the fraction of reference bases in the alignment to the consensus. Identity
+    // counts each SNP and each indel as one difference per aligned base. Consensus contigs which
+    // weren't paired with a reference replicon are counted as extra contigs.
+    let mut metrics = BenchmarkMetrics::default();
+    let mut aligned_bases = 0;
+    for r in replicons {
+        metrics.benchmark_consensus_bases += r.b_length as u64;
+        let Some(reference) = &r.a_name else {
+            metrics.benchmark_extra_contigs += 1;
+            continue;
+        };
+        let details = BenchmarkRepliconDetails {
+            reference: reference.clone(),
+            reference_length: r.a_length as u64,
+            consensus: r.b_name.clone().unwrap_or_default(),
+            consensus_length: r.b_length as u64,
+            aligned_bases: r.aligned_bp,
+            completeness: fraction(r.aligned_bp, r.a_length as u64),
+            identity: identity(r.snps + r.indels, r.aligned_bp),
+            snps: r.snps as u32,
+            indels: r.indels as u32,
+            structural: r.structural as u32,
+        };
+        metrics.benchmark_reference_bases += details.reference_length;
+        metrics.benchmark_snps += details.snps;
+        metrics.benchmark_indels += details.indels;
+        metrics.benchmark_structural += details.structural;
+        aligned_bases += details.aligned_bases;
+        metrics.benchmark_replicons.push(details);
+    }
+    metrics.benchmark_completeness = fraction(aligned_bases, metrics.benchmark_reference_bases);
+    metrics.benchmark_identity = identity((metrics.benchmark_snps + metrics.benchmark_indels)
+                                          as usize, aligned_bases);
+    metrics
+}
+
+
+fn fraction(numerator: u64, denominator: u64) -> f64 {
+    if denominator == 0 { return 0.0; }
+    (numerator as f64 / denominator as f64).min(1.0)
+}
+
+
+fn identity(differences: usize, aligned_bases: u64) -> f64 {
+    if aligned_bases == 0 { return 0.0; }
+    1.0 - fraction(differences as u64, aligned_bases)
+}
+
+
+fn print_results(metrics: &BenchmarkMetrics) {
+    section_header("Benchmark");
+    explanation("Each reference replicon is paired with the consensus contig which shares the \
+                 most sequence with it.");
+    for r in &metrics.benchmark_replicons {
+        let consensus = if r.consensus.is_empty() { "none" } else { &r.consensus };
+        eprintln!("{} ({} bp) -> {} ({} bp): completeness {:.4}%, identity {:.4}%, {} SNP{}, \
+                   {} indel{}, {} structural", r.reference, r.reference_length, consensus,
+                  r.consensus_length, r.completeness * 100.0, r.identity * 100.0, r.snps,
+                  match r.snps { 1 => "", _ => "s" }, r.indels,
+                  match r.indels { 1 => "", _ => "s" }, r.structural);
+    }
+    eprintln!();
+    eprintln!("Overall completeness: {:.4}%", metrics.benchmark_completeness * 100.0);
+    eprintln!("Overall identity:     {:.4}%", metrics.benchmark_identity * 100.0);
+    eprintln!("Extra contigs:        {}", metrics.benchmark_extra_contigs);
+    eprintln!();
+}
+
+
+#[cfg(test)]
+mod tests {
+    use tempfile::tempdir;
+
+    use super::*;
+    use crate::tests::{assert_almost_eq, make_test_file, random_seq};
+
+    #[test]
+    fn test_identity() {
+        assert_almost_eq(identity(0, 100), 1.0, 1e-8);
+        assert_almost_eq(identity(1, 100), 0.99, 1e-8);
+        assert_almost_eq(identity(0, 0), 0.0, 1e-8);
+        assert_almost_eq(fraction(150, 100), 1.0, 1e-8);
+    }
+
+    #[test]
+    fn test_benchmark_metrics() {
+        // The consensus has a SNP in the chromosome, is missing the reference's plasmid and has
+        // an extra contig.
+        let chromosome = random_seq(3000, 0);
+        let mut snp = chromosome.clone().into_bytes();
+        snp[1000] = if snp[1000] == b'A' { b'C' } else { b'A' };
+        let snp = String::from_utf8(snp).unwrap();
+        let plasmid = random_seq(500, 1);
+        let extra = random_seq(400, 2);
+
+        let dir = tempdir().unwrap();
+        let (ref_file, consensus_file) = (dir.path().join("ref.fasta"),
+                                          dir.path().join("consensus.fasta"));
+        make_test_file(&ref_file, &format!(">chr\n{}\n>plasmid\n{}\n", chromosome, plasmid));
+        make_test_file(&consensus_file, &format!(">1\n{}\n>2\n{}\n", snp, extra));
+        let (graph, sequences, ref_count) = build_joint_graph(&ref_file, &consensus_file, 21);
+        let (_, replicons) = compare_assemblies(&graph, &sequences, ref_count);
+        let metrics = benchmark_metrics(&replicons);
+
+        assert_eq!(metrics.benchmark_reference_bases, 3500);
+        assert_eq!(metrics.benchmark_consensus_bases, 3400);
+        assert_eq!((metrics.benchmark_snps, metrics.benchmark_indels), (1, 0));
+        assert_eq!(metrics.benchmark_extra_contigs, 1);
+        assert_eq!(metrics.benchmark_replicons.len(), 2);
+
+        let chr = &metrics.benchmark_replicons[0];
+        assert_eq!(chr.consensus, "1");
+        assert_almost_eq(chr.completeness, 1.0, 1e-8);
+        assert_almost_eq(chr.identity, 1.0 - 1.0 / 3000.0, 1e-8);
+
+        let plasmid = &metrics.benchmark_replicons[1];
+        assert_eq!(plasmid.consensus, "");
+        assert_almost_eq(plasmid.completeness, 0.0, 1e-8);
+        assert_almost_eq(metrics.benchmark_completeness, 3000.0 / 3500.0, 1e-8);
+    }
+}
@@ -0,0 +1,343 @@
+// This file contains the code for the autocycler clean subcommand.
+
+// Copyright 2024 Ryan Wick (rrwick@gmail.com)
+// https://github.com/rrwick/Autocycler
+
+// This file is part of Autocycler. Autocycler is free software: you can redistribute it and/or
+// modify it under the terms of the GNU General Public License as published by the Free Software
+// Foundation, either version 3 of the License, or (at your option) any later version. Autocycler
+// is distributed in the hope that it will be useful, but WITHOUT ANY WARRANTY; without even the
+// implied warranty of MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the GNU General
+// Public License for more details. You should have received a copy of the GNU General Public
+// License along with Autocycler. If not, see <http://www.gnu.org/licenses/>.
+
+use std::collections::HashSet;
+use std::path::{Path, PathBuf};
+
+use crate::log::{section_header, explanation};
+use crate::misc::{check_gfa_version, check_if_file_exists, format_float, quit_with_error,
+                  sign_at_end};
+use crate::sequence::Sequence;
+use crate::unitig_graph::UnitigGraph;
+
+
+pub struct CleanSettings {
+    // The settings for autocycler clean, named as their options.
+    pub in_gfa: PathBuf,
+    pub out_gfa: PathBuf,
+    pub remove: Option<String>,
+    pub min_depth: Option<f64>,
+    pub min_length: Option<u32>,
+    pub delete_links: Option<String>,
+    pub drop_seqs: Option<String>,
+    pub gfa_version: u32,
+}
+
+
+pub fn clean(settings: CleanSettings) {
+    let CleanSettings { in_gfa, out_gfa, remove, min_depth, min_length, delete_links, drop_seqs,
+                        gfa_version } = settings;
+    check_settings(&in_gfa, min_depth, gfa_version);
+    let remove = remove.map(|r| parse_unitig_numbers(&r));
+    let delete_links = delete_links.map(|l| parse_links(&l));
+    let drop_seqs = drop_seqs.map(|d| parse_sequence_ids(&d));
+    let cleanup = Cleanup { remove, min_depth, min_length, delete_links, drop_seqs };
+    starting_message();
+    print_settings(&in_gfa, &out_gfa, &cleanup, gfa_version);
+    let Cleanup { remove, min_depth, min_length, delete_links, drop_seqs } = cleanup;
+    let (mut graph, mut sequences) = load_graph(&in_gfa);
+    section_header("Cleaning graph");
+    explanation("The selected cleanup operations are now applied. Sequence paths which used a \
+                 removed unitig or deleted link are split at that point into truncated \
+                 sequences.");
+    if let Some(drop_seqs) = &drop_seqs {
+        sequences = drop_sequences(&mut graph, &sequences, drop_seqs);
+    }
+    let to_remove = get_unitigs_to_remove(&graph, &remove, min_length);
+    if !to_remove.is_empty() {
+        eprintln!("Removed {} unitig{} by number or length", to_remove.len(),
+                  match to_remove.len() { 1 => "", _ => "s" });
+        sequences = graph.remove_unitigs_and_split_paths(&sequences, to_remove);
+    }
+    if let Some(min_depth) = min_depth {
+        let unitig_count = graph.unitigs.len();
+        sequences = graph.remove_low_depth_unitigs(&sequences, min_depth);
+        let removed = unitig_count - graph.unitigs.len();
+        eprintln!("Removed {} unitig{} by depth", removed, match removed { 1 => "", _ => "s" });
+    }
+    if let Some(delete_links) = &delete_links {
+        sequences = remove_links(&mut graph, &sequences, delete_links);
+    }
+    eprintln!();
+    simplify_graph(&mut graph, &sequences);
+    graph.estimate_multiplicities(&sequences);
+    if let Err(e) = graph.check_links() {
+        quit_with_error(&format!("cleaned graph has inconsistent links: {}", e));
+    }
+    if let Err(e) = graph.save_gfa_with_version(&out_gfa, &sequences, gfa_version) {
+        quit_with_error(&format!("unable to write {}\n{}", out_gfa.display(), e));
+    }
+    finished_message(&out_gfa);
+}
+
+
+fn check_settings(in_gfa: &Path, min_depth: Option<f64>, gfa_version: u32) {
+    check_if_file_exists(in_gfa);
+    check_gfa_version(gfa_version);
+    if min_depth.is_some_and(|d| d < 0.0) { quit_with_error("--min_depth cannot be negative"); }
+}
+
+
+fn starting_message() {
+    section_header("Starting autocycler clean");
+    explanation("This command applies manual cleanup operations to a unitig graph, re-simplifies \
+                 it and saves the result to a new GFA file.");
+}
+
+
+struct Cleanup {
+    // The parsed cleanup operations.
+    remove: Option<Vec<u32>>,
+    min_depth: Option<f64>,
+    min_length: Option<u32>,
+    delete_links: Option<Vec<(i32, i32)>>,
+    drop_seqs: Option<Vec<u16>>,
+}
+
+
+fn print_settings(in_gfa: &Path, out_gfa: &Path, cleanup: &Cleanup, gfa_version: u32) {
+    let Cleanup { ref remove, min_depth, min_length, ref delete_links, ref drop_seqs } = *cleanup;
+    eprintln!("Settings:");
+    eprintln!("  --in_gfa {}", in_gfa.display());
+    eprintln!("  --out_gfa {}", out_gfa.display());
+    if let Some(remove) = remove {
+        let numbers: Vec<String> = remove.iter().map(|n| n.to_string()).collect();
+        eprintln!("  --remove {}", numbers.join(","));
+    }
+    if let Some(min_depth) = min_depth {
+        eprintln!("  --min_depth {}", format_float(min_depth));
+    }
+    if let Some(min_length) = min_length {
+        eprintln!("  --min_length {}", min_length);
+    }
+    if let Some(delete_links) = delete_links {
+        let links: Vec<String> = delete_links.iter()
+            .map(|(a, b)| format!("{}:{}", sign_at_end(*a), sign_at_end(*b))).collect();
+        eprintln!("  --delete_links {}", links.join(","));
+    }
+    if let Some(drop_seqs) = drop_seqs {
+        let ids: Vec<String> = drop_seqs.iter().map(|n| n.to_string()).collect();
+        eprintln!("  --drop_seqs {}", ids.join(","));
+    }
+    eprintln!("  --gfa_version {}", gfa_version);
+    eprintln!();
+}
+
+
+fn finished_message(out_gfa: &Path) {
+    section_header("Finished!");
+    eprintln!("Cleaned graph: {}", out_gfa.display());
+    eprintln!();
+}
+
+
+fn load_graph(gfa: &Path) -> (UnitigGraph, Vec<Sequence>) {
+    section_header("Loading graph");
+    explanation("The unitig graph is now loaded into memory.");
+    let (unitig_graph, sequences) = UnitigGraph::from_gfa_file(gfa);
+    unitig_graph.print_basic_graph_info();
+    (unitig_graph, sequences)
+}
+
+
+fn drop_sequences(graph: &mut UnitigGraph, sequences: &[Sequence], drop_seqs: &[u16])
+        -> Vec<Sequence> {
+    // Removes the given sequences and any unitigs which are left without a sequence.
+    for id in drop_seqs {
+        if !sequences.iter().any(|s| s.id == *id) {
+            quit_with_error(&format!("sequence {} not found in graph", id));
+        }
+        graph.remove_sequence_from_graph(*id);
+    }
+    let unitig_count = graph.unitigs.len();
+    graph.remove_zero_depth_unitigs();
+    eprintln!("Dropped {} sequence{} ({} unitig{} left without sequences)", drop_seqs.len(),
+              match drop_seqs.len() { 1 => "", _ => "s" }, unitig_count - graph.unitigs.len(),
+              match unitig_count - graph.unitigs.len() { 1 => "", _ => "s" });
+    sequences.iter().filter(|s| !drop_seqs.contains(&s.id)).cloned().collect()
+}
+
+
+fn get_unitigs_to_remove(graph: &UnitigGraph, remove: &Option<Vec<u32>>, min_length: Option<u32>)
+        -> HashSet<u32> {
+    let mut to_remove = HashSet::new();
+    if let Some(remove) = remove {
+        for n in remove {
+            if !graph.unitig_index.contains_key(n) {
+                quit_with_error(&format!("unitig {} not found in graph", n));
+            }
+            to_remove.insert(*n);
+        }
+    }
+    for u in &graph.unitigs {
+        if min_length.is_some_and(|l| u.length() < l) {
+            to_remove.insert(u.number);
+        }
+    }
+    to_remove
+}
+
+
+fn remove_links(graph: &mut UnitigGraph, sequences: &[Sequence], links: &[(i32, i32)])
+        -> Vec<Sequence> {
+    // Deletes the given links (and their reverse complements), splitting any sequence paths
+    // which crossed them.
+    let mut to_delete = HashSet::new();
+    for &(a, b) in links {
+        let (a_num, a_strand) = (a.unsigned_abs(), a > 0);
+        let (b_num, b_strand) = (b.unsigned_abs(), b > 0);
+        if !graph.unitig_index.contains_key(&a_num) || !graph.unitig_index.contains_key(&b_num) ||
+                !graph.link_exists(a_num, a_strand, b_num, b_strand) {
+            quit_with_error(&format!("link {} to {} not found in graph",
+                                     sign_at_end(a), sign_at_end(b)));
+        }
+        to_delete.insert((a, b));
+        to_delete.insert((-b, -a));
+    }
+    let sequences = graph.split_sequence_paths(sequences, |path| {
+        let signed: Vec<i32> = path.iter()
+            .map(|&(n, s)| if s { n as i32 } else { -(n as i32) }).collect();
+        let mut pieces = vec![Vec::new()];
+        for i in 0..path.len() {
+            if i > 0 && to_delete.contains(&(signed[i - 1], signed[i])) { pieces.push(Vec::new()); }
+            pieces.last_mut().unwrap().push(path[i]);
+        }
+        pieces
+    });
+    for &(a, b) in links {
+        if graph.link_exists(a.unsigned_abs(), a > 0, b.unsigned_abs(), b > 0) {
+            graph.delete_link(a, b);
+        }
+    }
+    eprintln!("Deleted {} link{}", links.len(), match links.len() { 1 => "", _ => "s" });
+    sequences
+}
+
+
+fn simplify_graph(graph: &mut UnitigGraph, sequences: &[Sequence]) {
+    // Merges linear paths left by the cleanup. Unitig depths come from sequence paths when merging,
+    // so graphs without paths (e.g. resolved consensus graphs) are left as they are.
+    section_header("Simplifying graph");
+    if sequences.is_empty() {
+        explanation("The graph has no sequence paths, so it is not re-simplified.");
+    } else {
+        explanation("Linear paths left by the cleanup are now merged.");
+        graph.merge_linear_paths(sequences);
+    }
+    graph.print_basic_graph_info();
+}
+
+
+fn parse_unitig_numbers(list: &str) -> Vec<u32> {
+    list.split(',').map(|n| n.trim().parse::<u32>().unwrap_or_else(|_| {
+        quit_with_error(&format!("could not parse unitig number: {}", n));
+    })).collect()
+}
+
+
+fn parse_sequence_ids(list: &str) -> Vec<u16> {
+    list.split(',').map(|n| n.trim().parse::<u16>().unwrap_or_else(|_| {
+        quit_with_error(&format!("could not parse sequence ID: {}", n));
+    })).collect()
+}
+
+
+fn parse_links(list: &str) -> Vec<(i32, i32)> {
+    // Links are given as two signed unitigs separated by a colon, e.g. "3+:5-".
+    list.split(',').map(|link| {
+        let (a, b) = link.trim().split_once(':').unwrap_or_else(|| {
+            quit_with_error(&format!("could not parse link: {}", link));
+        });
+        (parse_signed_unitig(a), parse_signed_unitig(b))
+    }).collect()
+}
+
+
+fn parse_signed_unitig(unitig: &str) -> i32 {
+    let sign = match unitig.chars().last() {
+        Some('+') => 1,
+        Some('-') => -1,
+        _ => quit_with_error(&format!("could not parse signed unitig: {}", unitig)),
+    };
+    let num = unitig[..unitig.len() - 1].parse::<i32>().ok().filter(|&n| n > 0)
+        .unwrap_or_else(|| {
+            quit_with_error(&format!("could not parse signed unitig: {}", unitig));
+        });
+    sign * num
+}
+
+
+#[cfg(test)]
+mod tests {
+    use crate::test_gfa::*;
+    use super::*;
+
+    #[test]
+    fn test_parse_links() {
+        assert_eq!(parse_links("3+:5-"), vec![(3, -5)]);
+        assert_eq!(parse_links("1+:2+, 4-:3-"), vec![(1, 2), (-4, -3)]);
+        assert!(std::panic::catch_unwind(|| { parse_links("1+2+"); }).is_err());
+        assert!(std::panic::catch_unwind(|| { parse_links("1:2+"); }).is_err());
+        assert!(std::panic::catch_unwind(|| { parse_links("0+:2+"); }).is_err());
+    }
+
+    #[test]
+    fn test_parse_unitig_numbers() {
+        assert_eq!(parse_unitig_numbers("1,5, 12"), vec![1, 5, 12]);
+        assert_eq!(parse_sequence_ids("3"), vec![3]);
+        assert!(std::panic::catch_unwind(|| { parse_unitig_numbers("1,x"); }).is_err());
+    }
+
+    #[test]
+    fn test_remove_links() {
+        // Deleting the 4->6 link (used by sequences b and c) splits them, and sequence a (which
+        // goes 4->5->6) is unchanged.
+        let (mut graph, sequences) = UnitigGraph::from_gfa_lines(&get_test_gfa_15());
+        let sequences = remove_links(&mut graph, &sequences, &[(4, 6)]);
+        assert!(!graph.link_exists(4, true, 6, true));
+        assert!(!graph.link_exists(6, false, 4, false));
+        assert!(graph.check_links().is_ok());
+        assert_eq!(sequences.len(), 5);
+        assert_eq!(graph.get_unitig_path_for_sequence_i32(&sequences[0]), vec![1, 2, 4, 5, 6]);
+        assert_eq!(graph.get_unitig_path_for_sequence_i32(&sequences[1]), vec![1, 3, 4]);
+        assert_eq!(graph.get_unitig_path_for_sequence_i32(&sequences[2]), vec![6]);
+        assert_eq!(graph.get_unitig_path_for_sequence_i32(&sequences[3]), vec![-6]);
+        assert_eq!(graph.get_unitig_path_for_sequence_i32(&sequences[4]), vec![-4, -2, -1]);
+    }
+
+    #[test]
+    fn test_drop_sequences() {
+        // Unitig 3 is only in sequence b, so it goes when b is dropped, and then unitigs 1, 2 and
+        // 4 can be merged.
+        let (mut graph, sequences) = UnitigGraph::from_gfa_lines(&get_test_gfa_15());
+        let sequences = drop_sequences(&mut graph, &sequences, &[2]);
+        assert_eq!(sequences.iter().map(|s| s.id).collect::<Vec<_>>(), vec![1, 3]);
+        assert_eq!(graph.unitigs.len(), 5);
+        assert!(!graph.unitig_index.contains_key(&3));
+        simplify_graph(&mut graph, &sequences);
+        assert_eq!(graph.unitigs.len(), 3);
+        assert!(graph.check_links().is_ok());
+    }
+
+    #[test]
+    fn test_get_unitigs_to_remove() {
+        let (graph, _) = UnitigGraph::from_gfa_lines(&get_test_gfa_15());
+        let sorted = |set: HashSet<u32>| { let mut v: Vec<_> = set.into_iter().collect();
+                                           v.sort(); v };
+        assert_eq!(sorted(get_unitigs_to_remove(&graph, &Some(vec![2]), None)), vec![2]);
+        assert_eq!(sorted(get_unitigs_to_remove(&graph, &Some(vec![1]), Some(4))), vec![1, 2, 3]);
+        assert!(std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| {
+            get_unitigs_to_remove(&graph, &Some(vec![7]), None);
+        })).is_err());
+    }
+}
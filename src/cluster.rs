@@ -12,67 +12,178 @@
 // License along with Autocycler. If not, see <http://www.gnu.org/licenses/>.
 
 use colored::Colorize;
+use rayon::prelude::*;
+use rayon::ThreadPoolBuilder;
 use std::collections::{HashMap, HashSet};
 use std::fs::File;
 use std::io::Write;
 use std::path::{Path, PathBuf};
+use xxhash_rust::xxh3::Xxh3;
 
 use crate::graph_simplification::merge_linear_paths;
 use crate::log::{section_header, explanation};
-use crate::metrics::{ClusteringMetrics, UntrimmedClusterMetrics};
-use crate::misc::{check_if_dir_exists, check_if_file_exists, format_float, median_usize,
-                  quit_with_error, usize_division_rounded, create_dir, delete_dir_if_exists,
-                  load_file_lines};
+use crate::metrics::{ClusteringMetrics, ClusterQcDetails, UntrimmedClusterMetrics};
+use crate::misc::{check_gfa_version, check_if_dir_exists, check_if_file_exists, format_float,
+                  median_usize, quit_with_error, usize_division_rounded, create_dir,
+                  delete_dir_if_exists, load_fasta, load_file_lines};
 use crate::sequence::Sequence;
+use crate::sketch::Sketch;
 use crate::unitig_graph::UnitigGraph;
 
 
-pub fn cluster(autocycler_dir: PathBuf, cutoff: f64, min_assemblies_option: Option<usize>,
-               max_contigs: u32, manual_clusters: Option<String>) {
+// Settings for the k-mer sketches used by the mash and ani distances.
+const SKETCH_K_SIZE: usize = 21;
+const SKETCH_SCALED: u64 = 100;
+
+// Clusters are only labelled with a reference replicon if their contigs are, on average, within
+// this Mash distance of it.
+const REFERENCE_MAX_DISTANCE: f64 = 0.05;
+
+// Settings for chimeric contig detection. Each side of a chimera must mostly consist of unitigs
+// belonging to one cluster, and it must cover a large part of that cluster's length.
+const CHIMERA_MIN_PURITY: f64 = 0.9;
+const CHIMERA_MIN_CLUSTER_FRACTION: f64 = 0.5;
+
+
+pub struct ClusterSettings {
+    // The settings for autocycler cluster, named as their options.
+    pub autocycler_dir: PathBuf,
+    pub cutoff: f64,
+    pub min_assemblies: Option<usize>,
+    pub max_cluster_distance: Option<f64>,
+    pub max_length_diff: Option<f64>,
+    pub min_cluster_len: Option<usize>,
+    pub max_cluster_len: Option<usize>,
+    pub max_contigs: u32,
+    pub manual: Option<String>,
+    pub distance: String,
+    pub split_chimeras: bool,
+    pub references: Option<PathBuf>,
+    pub threads: usize,
+    pub gfa_version: u32,
+}
+
+impl ClusterSettings {
+    pub fn new(autocycler_dir: PathBuf, threads: usize, gfa_version: u32) -> Self {
+        // Makes settings with the option defaults.
+        ClusterSettings {
+            autocycler_dir, cutoff: 0.2, min_assemblies: None, max_cluster_distance: None,
+            max_length_diff: None, min_cluster_len: None, max_cluster_len: None, max_contigs: 25,
+            manual: None, distance: "graph".to_string(), split_chimeras: false, references: None,
+            threads, gfa_version,
+        }
+    }
+}
+
+
+pub fn cluster(settings: ClusterSettings) {
+    check_settings(&settings);
+    let ClusterSettings { ref autocycler_dir, cutoff, min_assemblies, max_cluster_distance,
+                          max_length_diff, min_cluster_len, max_cluster_len, max_contigs,
+                          ref manual, ref distance, split_chimeras, ref references, threads,
+                          gfa_version, .. } = settings;
     let gfa = autocycler_dir.join("input_assemblies.gfa");
     let clustering_dir = autocycler_dir.join("clustering");
     let pairwise_phylip = clustering_dir.join("pairwise_distances.phylip");
     let clustering_newick = clustering_dir.join("clustering.newick");
     let clustering_tsv = clustering_dir.join("clustering.tsv");
+    let summary_tsv = clustering_dir.join("cluster_summary.tsv");
     let clustering_yaml = clustering_dir.join("clustering.yaml");
-    check_settings(&autocycler_dir, &gfa, cutoff, &min_assemblies_option);
+    let clustering_html = clustering_dir.join("clustering.html");
+    let chimeras_tsv = clustering_dir.join("chimeras.tsv");
+    let distance_cache = autocycler_dir.join("distance_cache.tsv");
+    let split_distance_cache = autocycler_dir.join("distance_cache_split.tsv");
     delete_dir_if_exists(&clustering_dir);
     create_dir(&clustering_dir);
     starting_message();
-    let gfa_lines = load_file_lines(&gfa);
-    let (graph, mut sequences) = UnitigGraph::from_gfa_lines(&gfa_lines);
-    let min_assemblies = set_min_assemblies(min_assemblies_option, &sequences);
-    let manual_clusters = parse_manual_clusters(manual_clusters);
-    print_settings(&autocycler_dir, cutoff, min_assemblies, min_assemblies_option, max_contigs,
-                   &manual_clusters);
-    check_sequence_count(&sequences, max_contigs);
-    let asymmetrical_distances = pairwise_contig_distances(&graph, &sequences, &pairwise_phylip);
-    let symmetrical_distances = make_symmetrical_distances(&asymmetrical_distances, &sequences);
-    let mut tree = upgma(&symmetrical_distances, &mut sequences);
-    normalise_tree(&mut tree);
-    save_tree_to_newick(&tree, &sequences, &clustering_newick);
-    let qc_results = generate_clusters(&tree, &mut sequences, &asymmetrical_distances, cutoff,
-                                       min_assemblies, &manual_clusters);
-    save_clusters(&sequences, &qc_results, &clustering_dir, &gfa_lines);
-    save_data_to_tsv(&sequences, &qc_results, &clustering_tsv);
-    let metrics = clustering_metrics(&sequences, &qc_results);
-    metrics.save_to_yaml(&clustering_yaml);
-
-    // TODO: create a PDF of the tree with clusters? printpdf?
-
-    finished_message(&pairwise_phylip, &clustering_newick, &clustering_tsv);
+    // Parallel steps use a local thread pool, so --threads is respected even when other steps
+    // have already run in this process (e.g. autocycler pipeline).
+    let pool = ThreadPoolBuilder::new().num_threads(threads).build().unwrap();
+    pool.install(|| {
+        let mut gfa_lines = load_file_lines(&gfa);
+        let (mut graph, sequences) = UnitigGraph::from_gfa_lines(&gfa_lines);
+        let thresholds = QcThresholds {
+            min_assemblies: set_min_assemblies(min_assemblies, &sequences),
+            max_cluster_distance, max_length_diff, min_cluster_len, max_cluster_len,
+        };
+        let manual_clusters = parse_manual_clusters(manual.clone());
+        print_settings(&settings, &thresholds, &manual_clusters);
+        check_sequence_count(&sequences, max_contigs);
+        let run = ClusteringRun { distance, cutoff, thresholds: &thresholds,
+                                  manual_clusters: &manual_clusters, distance_cache: &distance_cache,
+                                  pairwise_phylip: &pairwise_phylip };
+        let (mut sequences, mut discarded, mut asymmetrical_distances, mut tree, mut qc_results) =
+            cluster_contigs(&graph, sequences, &gfa_lines, &run);
+        let chimeras = find_chimeras(&graph, &sequences);
+        report_chimeras(&chimeras, &sequences, &chimeras_tsv);
+        if split_chimeras && !chimeras.is_empty() {
+            let all_sequences = sequences.into_iter().chain(discarded).collect::<Vec<_>>();
+            let split_sequences = split_chimeric_contigs(&mut graph, &all_sequences, &chimeras);
+            gfa_lines = graph.gfa_lines(&split_sequences);
+            // The split graph's distances are cached separately, so neither pass overwrites the
+            // other's cache.
+            let split_run = ClusteringRun { distance_cache: &split_distance_cache, ..run };
+            (sequences, discarded, asymmetrical_distances, tree, qc_results) =
+                cluster_contigs(&graph, split_sequences, &gfa_lines, &split_run);
+        }
+        save_discarded(&sequences, &discarded, &clustering_dir, &gfa_lines, gfa_version);
+        save_tree_to_newick(&tree, &sequences, &clustering_newick);
+        let labels = match references {
+            Some(references) => label_clusters(&graph, &sequences, &load_fasta(references)),
+            None => HashMap::new(),
+        };
+        save_clusters(&sequences, &qc_results, &labels, &clustering_dir, &graph, &gfa_lines,
+                      gfa_version);
+        save_data_to_tsv(&sequences, &qc_results, &clustering_tsv);
+        save_cluster_summary(&graph, &sequences, &qc_results, &labels, &summary_tsv);
+        save_html_report(&tree, &sequences, &asymmetrical_distances, &qc_results, cutoff, distance,
+                         &clustering_html);
+        let mut metrics = clustering_metrics(&sequences, &qc_results);
+        metrics.distance_metric = distance.clone();
+        metrics.discard_contig_count = discarded.len() as u32;
+        metrics.chimeric_contig_count = chimeras.len() as u32;
+        metrics.clusters = cluster_qc_details(&sequences, &qc_results, &labels);
+        metrics.save_to_yaml(&clustering_yaml);
+    });
+
+    finished_message(&pairwise_phylip, &clustering_newick, &clustering_tsv, &summary_tsv,
+                     &clustering_html);
 }
 
 
-fn check_settings(autocycler_dir: &Path, gfa: &Path, cutoff: f64, min_assemblies: &Option<usize>) {
+fn check_settings(settings: &ClusterSettings) {
+    let ClusterSettings { ref autocycler_dir, cutoff, min_assemblies, max_cluster_distance,
+                          max_length_diff, min_cluster_len, max_cluster_len, ref distance,
+                          ref references, threads, gfa_version, .. } = *settings;
+    let gfa = autocycler_dir.join("input_assemblies.gfa");
     check_if_dir_exists(autocycler_dir);
-    check_if_file_exists(gfa);
+    check_if_file_exists(&gfa);
+    if let Some(references) = references {
+        check_if_file_exists(references);
+    }
+    check_gfa_version(gfa_version);
     if cutoff <= 0.0 || cutoff >= 1.0 {
         quit_with_error("--cutoff must be between 0 and 1 (exclusive)");
     }
     if min_assemblies.is_some() && min_assemblies.unwrap() < 1 {
         quit_with_error("--min_assemblies must be 1 or greater");
     }
+    if max_cluster_distance.is_some_and(|d| d < 0.0) {
+        quit_with_error("--max_cluster_distance must be 0 or greater");
+    }
+    if max_length_diff.is_some_and(|d| !(0.0..=1.0).contains(&d)) {
+        quit_with_error("--max_length_diff must be between 0 and 1 (inclusive)");
+    }
+    if let (Some(min_len), Some(max_len)) = (min_cluster_len, max_cluster_len) {
+        if min_len > max_len {
+            quit_with_error("--min_cluster_len cannot be greater than --max_cluster_len");
+        }
+    }
+    if !["graph", "jaccard", "mash", "ani"].contains(&distance.as_str()) {
+        quit_with_error("--distance must be graph, jaccard, mash or ani");
+    }
+    if threads < 1   { quit_with_error("--threads cannot be less than 1"); }
+    if threads > 100 { quit_with_error("--threads cannot be greater than 100"); }
 }
 
 
@@ -84,19 +195,25 @@ fn starting_message() {
 }
 
 
-fn finished_message(pairwise_phylip: &Path, clustering_newick: &Path, clustering_tsv: &Path) {
+fn finished_message(pairwise_phylip: &Path, clustering_newick: &Path, clustering_tsv: &Path,
+                    summary_tsv: &Path, clustering_html: &Path) {
     section_header("Finished!");
     explanation("You can now run autocycler trim on each cluster. If you want to manually \
                  inspect the clustering, you can view the following files.");
     eprintln!("Pairwise distances:         {}", pairwise_phylip.display());
     eprintln!("Clustering tree (Newick):   {}", clustering_newick.display());
     eprintln!("Clustering tree (metadata): {}", clustering_tsv.display());
+    eprintln!("Cluster summary:            {}", summary_tsv.display());
+    eprintln!("Clustering report (HTML):   {}", clustering_html.display());
     eprintln!();
 }
 
 
-fn print_settings(autocycler_dir: &Path, cutoff: f64, min_assemblies: usize,
-                  min_assemblies_option: Option<usize>, max_contigs: u32, manual_clusters: &[u16]) {
+fn print_settings(settings: &ClusterSettings, thresholds: &QcThresholds, manual_clusters: &[u16]) {
+    let ClusterSettings { ref autocycler_dir, cutoff, min_assemblies: min_assemblies_option,
+                          max_contigs, ref distance, split_chimeras, ref references, threads,
+                          gfa_version, .. } = *settings;
+    let min_assemblies = thresholds.min_assemblies;
     eprintln!("Settings:");
     eprintln!("  --autocycler_dir {}", autocycler_dir.display());
     eprintln!("  --cutoff {}", format_float(cutoff));
@@ -105,15 +222,68 @@ fn print_settings(autocycler_dir: &Path, cutoff: f64, min_assemblies: usize,
     } else {
         eprintln!("  --min_assemblies {}", min_assemblies);
     }
+    if let Some(d) = thresholds.max_cluster_distance {
+        eprintln!("  --max_cluster_distance {}", format_float(d));
+    }
+    if let Some(d) = thresholds.max_length_diff {
+        eprintln!("  --max_length_diff {}", format_float(d));
+    }
+    if let Some(l) = thresholds.min_cluster_len {
+        eprintln!("  --min_cluster_len {}", l);
+    }
+    if let Some(l) = thresholds.max_cluster_len {
+        eprintln!("  --max_cluster_len {}", l);
+    }
     eprintln!("  --max_contigs {}", max_contigs);
     if !manual_clusters.is_empty() {
         eprintln!("  --manual {}", manual_clusters.iter().map(|c| c.to_string())
                                                   .collect::<Vec<String>>() .join(","));
     }
+    eprintln!("  --distance {}", distance);
+    if split_chimeras {
+        eprintln!("  --split_chimeras");
+    }
+    if let Some(references) = references {
+        eprintln!("  --references {}", references.display());
+    }
+    eprintln!("  --threads {}", threads);
+    eprintln!("  --gfa_version {}", gfa_version);
     eprintln!();
 }
 
 
+struct ClusteringRun<'a> {
+    distance: &'a str,
+    cutoff: f64,
+    thresholds: &'a QcThresholds,
+    manual_clusters: &'a [u16],
+    distance_cache: &'a Path,
+    pairwise_phylip: &'a Path,
+}
+
+
+type ClusteringResult = (Vec<Sequence>, Vec<Sequence>, HashMap<(u16, u16), f64>, TreeNode,
+                         HashMap<u16, ClusterQC>);
+
+
+fn cluster_contigs(graph: &UnitigGraph, sequences: Vec<Sequence>, gfa_lines: &[String],
+                   run: &ClusteringRun) -> ClusteringResult {
+    // Runs everything from pairwise distances to cluster QC. Returns the clustered sequences, the
+    // discarded sequences, the distances between clustered sequences, the tree and QC results.
+    let asymmetrical_distances = pairwise_contig_distances(graph, &sequences, run.distance,
+                                                           gfa_lines, run.distance_cache,
+                                                           run.pairwise_phylip);
+    let (mut sequences, discarded) = screen_contaminants(sequences, &asymmetrical_distances);
+    let asymmetrical_distances = remove_discarded_distances(asymmetrical_distances, &discarded);
+    let symmetrical_distances = make_symmetrical_distances(&asymmetrical_distances, &sequences);
+    let mut tree = upgma(&symmetrical_distances, &mut sequences);
+    normalise_tree(&mut tree);
+    let qc_results = generate_clusters(&tree, &mut sequences, &asymmetrical_distances, run.cutoff,
+                                       run.thresholds, run.manual_clusters);
+    (sequences, discarded, asymmetrical_distances, tree, qc_results)
+}
+
+
 fn check_sequence_count(sequences: &[Sequence], max_contigs: u32) {
     let assembly_count = get_assembly_count(sequences) as f64;
     let sequence_count = sequences.len() as f64;
@@ -130,32 +300,164 @@ fn check_sequence_count(sequences: &[Sequence], max_contigs: u32) {
 }
 
 
-fn pairwise_contig_distances(graph: &UnitigGraph, sequences: &Vec<Sequence>, file_path: &Path)
+fn pairwise_contig_distances(graph: &UnitigGraph, sequences: &Vec<Sequence>, distance: &str,
+                             gfa_lines: &[String], cache_path: &Path, file_path: &Path)
         -> HashMap<(u16, u16), f64> {
     section_header("Pairwise distances");
-    explanation("Every pairwise distance between contigs is calculated based on the similarity of \
-                 their paths through the graph.");
+    let gfa_hash = gfa_lines_hash(gfa_lines);
+    if let Some(distances) = load_distance_cache(cache_path, distance, gfa_hash, sequences) {
+        explanation("Pairwise distances between contigs were already calculated for this graph \
+                     and distance metric, so they are loaded from the cache instead of being \
+                     recalculated.");
+        eprintln!("Loaded cached distances from {}", cache_path.display());
+        eprintln!("{} sequences, {} total pairwise distances", sequences.len(), distances.len());
+        eprintln!();
+        save_distance_matrix(&distances, sequences, file_path);
+        return distances;
+    }
+    let distances = match distance {
+        "jaccard" => {
+            explanation("Every pairwise distance between contigs is calculated using the \
+                         length-weighted Jaccard distance of the unitigs they contain.");
+            jaccard_distances(graph, sequences)
+        }
+        "mash" => {
+            explanation("Every pairwise distance between contigs is calculated using the Mash \
+                         distance between k-mer sketches of their sequences.");
+            sketch_distances(graph, sequences, |a, b| a.mash_distance(b))
+        }
+        "ani" => {
+            explanation("Every pairwise distance between contigs is calculated using the \
+                         containment ANI between k-mer sketches of their sequences.");
+            sketch_distances(graph, sequences, |a, b| a.ani_distance(b))
+        }
+        _ => {
+            explanation("Every pairwise distance between contigs is calculated based on the \
+                         similarity of their paths through the graph.");
+            graph_distances(graph, sequences)
+        }
+    };
+    eprintln!("{} sequences, {} total pairwise distances", sequences.len(), distances.len());
+    eprintln!();
+    save_distance_cache(&distances, sequences, distance, gfa_hash, cache_path);
+    save_distance_matrix(&distances, sequences, file_path);
+    distances
+}
+
+
+fn graph_distances(graph: &UnitigGraph, sequences: &[Sequence]) -> HashMap<(u16, u16), f64> {
     let unitig_lengths: HashMap<u32, u32> = graph.unitigs.iter()
-        .map(|rc| {let u = rc.borrow(); (u.number, u.length())}).collect();
+        .map(|u| (u.number, u.length())).collect();
     let sequence_unitigs: HashMap<u16, HashSet<u32>> = sequences.iter()
-        .map(|s| (s.id, graph.get_unitig_path_for_sequence(s).iter()
-        .map(|(number, _)| *number).collect::<HashSet<u32>>())).collect();
-    let mut distances: HashMap<(u16, u16), f64> = HashMap::new();
-    for seq_a in sequences {
+        .zip(graph.get_unitig_paths_for_sequences(sequences))
+        .map(|(s, path)| (s.id, path.iter().map(|(number, _)| *number).collect::<HashSet<u32>>()))
+        .collect();
+    sequences.par_iter().flat_map_iter(|seq_a| {  // parallel for loop with rayon
         let a = sequence_unitigs.get(&seq_a.id).unwrap();
         let a_len = total_unitig_length(a, &unitig_lengths) as f64;
-        for seq_b in sequences {
+        sequences.iter().map(|seq_b| {
             let b = sequence_unitigs.get(&seq_b.id).unwrap();
             let ab: HashSet<u32> = a.intersection(b).cloned().collect();
             let ab_len = total_unitig_length(&ab, &unitig_lengths) as f64;
-            let distance = 1.0 - (ab_len / a_len);
-            distances.insert((seq_a.id, seq_b.id), distance);
+            ((seq_a.id, seq_b.id), 1.0 - (ab_len / a_len))
+        }).collect::<Vec<_>>()
+    }).collect()
+}
+
+
+fn jaccard_distances(graph: &UnitigGraph, sequences: &[Sequence]) -> HashMap<(u16, u16), f64> {
+    // Uses the sequence positions stored on each unitig to find which contigs contain it, so no
+    // paths need to be traced. Each distance is one minus the shared unitig length divided by the
+    // union of both contigs' unitig lengths, which makes this distance symmetrical.
+    let mut contig_lengths: HashMap<u16, u64> = HashMap::new();
+    let mut shared_lengths: HashMap<(u16, u16), u64> = HashMap::new();
+    for unitig in &graph.unitigs {
+        let length = unitig.length() as u64;
+        let mut seq_ids: Vec<u16> = unitig.forward_positions.iter()
+            .chain(unitig.reverse_positions.iter()).map(|p| p.seq_id()).collect();
+        seq_ids.sort_unstable();
+        seq_ids.dedup();
+        for (i, &a) in seq_ids.iter().enumerate() {
+            *contig_lengths.entry(a).or_insert(0) += length;
+            for &b in &seq_ids[i + 1..] {
+                *shared_lengths.entry((a, b)).or_insert(0) += length;
+            }
+        }
+    }
+    sequences.par_iter().flat_map_iter(|seq_a| {  // parallel for loop with rayon
+        sequences.iter().map(|seq_b| {
+            let (a, b) = (seq_a.id.min(seq_b.id), seq_a.id.max(seq_b.id));
+            let distance = if a == b { 0.0 } else {
+                let a_len = *contig_lengths.get(&a).unwrap_or(&0);
+                let b_len = *contig_lengths.get(&b).unwrap_or(&0);
+                let shared = *shared_lengths.get(&(a, b)).unwrap_or(&0);
+                let union = a_len + b_len - shared;
+                if union == 0 { 1.0 } else { 1.0 - shared as f64 / union as f64 }
+            };
+            ((seq_a.id, seq_b.id), distance)
+        }).collect::<Vec<_>>()
+    }).collect()
+}
+
+
+fn sketch_distances<F>(graph: &UnitigGraph, sequences: &[Sequence], distance: F)
+        -> HashMap<(u16, u16), f64> where F: Fn(&Sketch, &Sketch) -> f64 + Sync {
+    // Sketches each contig's sequence (reconstructed from the graph) and uses the given function
+    // to get the distance between each pair of sketches. The graph can't be shared between
+    // threads, so sequences are reconstructed first and then sketched in parallel.
+    let contig_seqs: Vec<String> = graph.get_unitig_paths_for_sequences(sequences).iter()
+        .zip(sequences).map(|(path, s)| graph.get_repaired_sequence_from_path(s, path)).collect();
+    let sketches: Vec<Sketch> = contig_seqs.par_iter()  // parallel for loop with rayon
+        .map(|seq| Sketch::new(seq.as_bytes(), SKETCH_K_SIZE, SKETCH_SCALED)).collect();
+    sequences.par_iter().zip(sketches.par_iter()).flat_map_iter(|(seq_a, sketch_a)| {
+        sequences.iter().zip(&sketches).map(|(seq_b, sketch_b)| {
+            let d = if seq_a.id == seq_b.id { 0.0 } else { distance(sketch_a, sketch_b) };
+            ((seq_a.id, seq_b.id), d)
+        }).collect::<Vec<_>>()
+    }).collect()
+}
+
+
+fn label_clusters(graph: &UnitigGraph, sequences: &[Sequence],
+                  references: &[(String, String, String)]) -> HashMap<u16, String> {
+    // Labels each cluster with its best-matching reference replicon, using the mean Mash distance
+    // between the cluster's contigs and the reference. The label is the reference's description
+    // (the header after its name), or its name if there is no description.
+    section_header("Labelling clusters");
+    explanation("Clusters are now labelled using the most similar reference replicon.");
+    let contig_seqs: Vec<String> = graph.get_unitig_paths_for_sequences(sequences).iter()
+        .zip(sequences).map(|(path, s)| graph.get_repaired_sequence_from_path(s, path)).collect();
+    let sketches: HashMap<u16, Sketch> = sequences.par_iter().zip(contig_seqs.par_iter())
+        .map(|(s, seq)| (s.id, Sketch::new(seq.as_bytes(), SKETCH_K_SIZE, SKETCH_SCALED)))
+        .collect();
+    let ref_sketches: Vec<Sketch> = references.par_iter()
+        .map(|(_, _, seq)| Sketch::new(seq.as_bytes(), SKETCH_K_SIZE, SKETCH_SCALED)).collect();
+    let mut labels = HashMap::new();
+    for c in 1..=get_max_cluster(sequences) {
+        let ids: Vec<u16> = sequences.iter().filter(|s| s.cluster == c).map(|s| s.id).collect();
+        let best = references.iter().zip(&ref_sketches).map(|((name, header, _), ref_sketch)| {
+            let total: f64 = ids.iter().map(|id| sketches[id].mash_distance(ref_sketch)).sum();
+            (reference_label(name, header), total / ids.len() as f64)
+        }).min_by(|a, b| a.1.partial_cmp(&b.1).unwrap());
+        match best {
+            Some((label, d)) if d <= REFERENCE_MAX_DISTANCE => {
+                eprintln!("Cluster {:03}: {} (distance {})", c, label, format_float(d));
+                labels.insert(c, label);
+            }
+            _ => eprintln!("Cluster {:03}: no matching reference", c),
         }
     }
-    eprintln!("{} sequences, {} total pairwise distances", sequences.len(), distances.len());
     eprintln!();
-    save_distance_matrix(&distances, sequences, file_path);
-    distances
+    labels
+}
+
+
+fn reference_label(name: &str, header: &str) -> String {
+    // A reference is labelled by its name, unless its header has an explicit label key (e.g.
+    // >NC_000913 label=chromosome). Other description text (e.g. length=... circular=true) is
+    // ignored, as it often isn't a useful label.
+    header.split_whitespace().skip(1).find_map(|p| p.strip_prefix("label="))
+        .filter(|label| !label.is_empty()).unwrap_or(name).to_string()
 }
 
 
@@ -181,6 +483,290 @@ fn save_distance_matrix(distances: &HashMap<(u16, u16), f64>, sequences: &Vec<Se
 }
 
 
+fn gfa_lines_hash(gfa_lines: &[String]) -> u64 {
+    let mut hasher = Xxh3::new();
+    for line in gfa_lines {
+        hasher.update(line.as_bytes());
+        hasher.update(b"\n");
+    }
+    hasher.digest()
+}
+
+
+fn distance_cache_header(sequences: &[Sequence], distance: &str, gfa_hash: u64) -> Vec<String> {
+    // The cache header identifies the graph, distance metric and contigs which the distances
+    // belong to. A cache is only used if its header exactly matches the current run.
+    let mut header = vec![format!("distance\t{}", distance),
+                          format!("gfa_hash\t{:016x}", gfa_hash)];
+    for seq in sequences {
+        header.push(format!("sequence\t{}\t{}\t{}", seq.id, seq.filename, seq.contig_header));
+    }
+    header
+}
+
+
+fn save_distance_cache(distances: &HashMap<(u16, u16), f64>, sequences: &[Sequence],
+                       distance: &str, gfa_hash: u64, file_path: &Path) {
+    // Distances are written with full precision so cached values exactly match recalculated ones.
+    // Failure to write the cache isn't fatal, as it only affects the speed of future runs.
+    let mut lines = distance_cache_header(sequences, distance, gfa_hash);
+    for seq_a in sequences {
+        let mut line = format!("row\t{}", seq_a.id);
+        for seq_b in sequences {
+            line.push_str(&format!("\t{}", distances[&(seq_a.id, seq_b.id)]));
+        }
+        lines.push(line);
+    }
+    let mut contents = lines.join("\n");
+    contents.push('\n');
+    if let Err(e) = std::fs::write(file_path, contents) {
+        eprintln!("Warning: unable to write distance cache {}: {}", file_path.display(), e);
+        eprintln!();
+    }
+}
+
+
+fn load_distance_cache(file_path: &Path, distance: &str, gfa_hash: u64, sequences: &[Sequence])
+        -> Option<HashMap<(u16, u16), f64>> {
+    // Returns the cached distances, or None if there is no usable cache (missing, malformed or
+    // made from a different graph, distance metric or set of contigs).
+    let contents = std::fs::read_to_string(file_path).ok()?;
+    let lines: Vec<&str> = contents.lines().collect();
+    let header = distance_cache_header(sequences, distance, gfa_hash);
+    if lines.len() != header.len() + sequences.len() ||
+            header.iter().zip(&lines).any(|(a, b)| a != b) {
+        return None;
+    }
+    let mut distances = HashMap::new();
+    for (seq_a, line) in sequences.iter().zip(&lines[header.len()..]) {
+        let parts: Vec<&str> = line.split('\t').collect();
+        if parts.len() != sequences.len() + 2 || parts[0] != "row" ||
+                parts[1] != seq_a.id.to_string() {
+            return None;
+        }
+        for (seq_b, value) in sequences.iter().zip(&parts[2..]) {
+            distances.insert((seq_a.id, seq_b.id), value.parse::<f64>().ok()?);
+        }
+    }
+    Some(distances)
+}
+
+
+fn screen_contaminants(sequences: Vec<Sequence>, distances: &HashMap<(u16, u16), f64>)
+        -> (Vec<Sequence>, Vec<Sequence>) {
+    // Contigs which share no sequence with any other contig (i.e. they only occur in their own
+    // assembly) are likely contamination or assembler artefacts. Rather than letting each form its
+    // own singleton cluster, they are set aside before clustering. The screen is skipped when
+    // there is only one input assembly or when it would discard every contig.
+    section_header("Contaminant screen");
+    explanation("Contigs which share no sequence with any other contig are likely contamination \
+                 or assembler artefacts, so they are discarded before clustering.");
+    if get_assembly_count(&sequences) < 2 {
+        eprintln!("Only one input assembly, so no contigs were screened");
+        eprintln!();
+        return (sequences, Vec::new());
+    }
+    let isolated: HashSet<u16> = sequences.iter().filter(|s| is_isolated(s, &sequences, distances))
+                                          .map(|s| s.id).collect();
+    if isolated.len() == sequences.len() {
+        eprintln!("No contigs share sequence with any other contig, so none were discarded");
+        eprintln!();
+        return (sequences, Vec::new());
+    }
+    let (discarded, kept): (Vec<Sequence>, Vec<Sequence>) =
+        sequences.into_iter().partition(|s| isolated.contains(&s.id));
+    if discarded.is_empty() {
+        eprintln!("No contigs discarded");
+    } else {
+        eprintln!("Discarded contig{}:", match discarded.len() { 1 => "", _ => "s" });
+        for s in &discarded {
+            eprintln!("  {}", s.to_string().dimmed());
+        }
+    }
+    eprintln!();
+    (kept, discarded)
+}
+
+
+fn is_isolated(seq: &Sequence, sequences: &[Sequence], distances: &HashMap<(u16, u16), f64>)
+        -> bool {
+    // A contig is isolated if it has the maximum distance (no shared sequence) to and from every
+    // other contig.
+    sequences.iter().filter(|other| other.id != seq.id)
+        .all(|other| distances[&(seq.id, other.id)] >= 1.0 && distances[&(other.id, seq.id)] >= 1.0)
+}
+
+
+fn remove_discarded_distances(mut distances: HashMap<(u16, u16), f64>, discarded: &[Sequence])
+        -> HashMap<(u16, u16), f64> {
+    let discarded_ids: HashSet<u16> = discarded.iter().map(|s| s.id).collect();
+    distances.retain(|(a, b), _| !discarded_ids.contains(a) && !discarded_ids.contains(b));
+    distances
+}
+
+
+fn save_discarded(sequences: &[Sequence], discarded: &[Sequence], clustering_dir: &Path,
+                  gfa_lines: &Vec<String>, gfa_version: u32) {
+    // Discarded contigs are saved to their own directory (a graph and a TSV explaining why each
+    // was discarded), so they can be inspected but won't be used by later steps.
+    if discarded.is_empty() {
+        return;
+    }
+    let discard_dir = clustering_dir.join("discard");
+    create_dir(&discard_dir);
+    let mut file = File::create(discard_dir.join("discard.tsv")).unwrap();
+    writeln!(file, "sequence_id\tfile_name\tcontig_name\tlength\treason").unwrap();
+    for seq in discarded {
+        writeln!(file, "{}\t{}\t{}\t{}\tshares no sequence with any other contig", seq.id,
+                 seq.filename, seq.contig_name(), seq.length).unwrap();
+    }
+
+    // The discarded contigs have no cluster (0), so give the kept contigs a placeholder cluster
+    // to separate the two groups when building the discard graph.
+    let mut all_sequences: Vec<Sequence> = sequences.iter().cloned()
+        .map(|mut s| { s.cluster = 1; s }).collect();
+    all_sequences.extend(discarded.iter().cloned());
+    save_cluster_gfa(&all_sequences, 0, gfa_lines, discard_dir.join("discard.gfa"), gfa_version);
+}
+
+
+struct Chimera {
+    seq_id: u16,
+    split_index: usize,  // index in the contig's unitig path where the second part starts
+    split_pos: usize,    // position in the contig where the second part starts
+    first_cluster: u16,
+    second_cluster: u16,
+}
+
+
+fn find_chimeras(graph: &UnitigGraph, sequences: &[Sequence]) -> Vec<Chimera> {
+    // Looks for contigs whose unitig path is split between two clusters, e.g. a chromosome and
+    // plasmid which an assembler wrongly joined. Each unitig is assigned to a cluster based on the
+    // other contigs passing through it, and a contig is chimeric if its path can be split into
+    // two parts that belong to different clusters.
+    let paths = graph.get_unitig_paths_for_sequences(sequences);
+    let mut unitig_seqs: HashMap<u32, HashSet<u16>> = HashMap::new();
+    for (seq, path) in sequences.iter().zip(&paths) {
+        for (unitig, _) in path {
+            unitig_seqs.entry(*unitig).or_default().insert(seq.id);
+        }
+    }
+    let seq_clusters: HashMap<u16, u16> = sequences.iter().map(|s| (s.id, s.cluster)).collect();
+    let cluster_lengths: HashMap<u16, usize> = (1..=get_max_cluster(sequences))
+        .map(|c| (c, cluster_median_length(c, sequences))).collect();
+    let mut chimeras = Vec::new();
+    for (seq, path) in sequences.iter().zip(&paths) {
+        let owners: Vec<(Option<u16>, usize)> = path.iter().map(|(unitig, _)| {
+            let others: HashSet<u16> = unitig_seqs[unitig].iter().filter(|&&id| id != seq.id)
+                                                          .map(|id| seq_clusters[id]).collect();
+            let length = graph.unitig(*unitig).length() as usize;
+            (unitig_owner(&others, seq.cluster), length)
+        }).collect();
+        if let Some(chimera) = best_chimera_split(seq.id, &owners, &cluster_lengths) {
+            chimeras.push(chimera);
+        }
+    }
+    chimeras
+}
+
+
+fn unitig_owner(clusters: &HashSet<u16>, own_cluster: u16) -> Option<u16> {
+    // A unitig belongs to a cluster if the other contigs passing through it are all in that
+    // cluster. If they are in multiple clusters, the contig's own cluster is ignored, as other
+    // copies of the same chimera may have been clustered together.
+    if clusters.len() == 1 {
+        return clusters.iter().next().copied();
+    }
+    let others: Vec<u16> = clusters.iter().copied().filter(|&c| c != own_cluster).collect();
+    if others.len() == 1 { Some(others[0]) } else { None }
+}
+
+
+fn best_chimera_split(seq_id: u16, owners: &[(Option<u16>, usize)],
+                      cluster_lengths: &HashMap<u16, usize>) -> Option<Chimera> {
+    // Tries each split point in the path, keeping the one where the two parts best match two
+    // different clusters. Both parts must pass the purity and cluster-fraction thresholds.
+    let mut totals: HashMap<u16, usize> = HashMap::new();
+    for (owner, length) in owners {
+        if let Some(c) = owner { *totals.entry(*c).or_insert(0) += length; }
+    }
+    if totals.len() < 2 {
+        return None;
+    }
+    let total_length: usize = owners.iter().map(|(_, l)| l).sum();
+    let mut prefix: HashMap<u16, usize> = HashMap::new();
+    let mut prefix_length = 0;
+    let mut best: Option<(usize, Chimera)> = None;
+    for i in 1..owners.len() {
+        let (owner, length) = owners[i - 1];
+        if let Some(c) = owner { *prefix.entry(c).or_insert(0) += length; }
+        prefix_length += length;
+        let Some((&a, &a_len)) = prefix.iter().max_by_key(|(&c, &l)| (l, c)) else { continue; };
+        let Some((b, b_len)) = totals.iter().map(|(&c, &t)| (c, t - prefix.get(&c).unwrap_or(&0)))
+                                     .max_by_key(|&(c, l)| (l, c)) else { continue; };
+        let suffix_length = total_length - prefix_length;
+        if a == b || (a_len as f64) < CHIMERA_MIN_PURITY * prefix_length as f64 ||
+                (b_len as f64) < CHIMERA_MIN_PURITY * suffix_length as f64 ||
+                (a_len as f64) < CHIMERA_MIN_CLUSTER_FRACTION * cluster_lengths[&a] as f64 ||
+                (b_len as f64) < CHIMERA_MIN_CLUSTER_FRACTION * cluster_lengths[&b] as f64 {
+            continue;
+        }
+        let score = a_len + b_len;
+        if best.as_ref().map_or(true, |(best_score, _)| score > *best_score) {
+            best = Some((score, Chimera { seq_id, split_index: i, split_pos: prefix_length,
+                                          first_cluster: a, second_cluster: b }));
+        }
+    }
+    best.map(|(_, chimera)| chimera)
+}
+
+
+fn report_chimeras(chimeras: &[Chimera], sequences: &[Sequence], file_path: &Path) {
+    section_header("Chimeric contigs");
+    explanation("Contigs whose paths are split between two clusters are likely chimeras, e.g. a \
+                 chromosome and plasmid which were incorrectly joined by the assembler.");
+    let index: HashMap<u16, &Sequence> = sequences.iter().map(|s| (s.id, s)).collect();
+    let mut file = File::create(file_path).unwrap();
+    writeln!(file, "sequence_id\tfile_name\tcontig_name\tlength\tsplit_position\t\
+                    first_cluster\tsecond_cluster").unwrap();
+    for chimera in chimeras {
+        let seq = index[&chimera.seq_id];
+        writeln!(file, "{}\t{}\t{}\t{}\t{}\t{}\t{}", seq.id, seq.filename, seq.contig_name(),
+                 seq.length, chimera.split_pos, chimera.first_cluster, chimera.second_cluster)
+            .unwrap();
+        eprintln!("{}: cluster {} up to {} bp, then cluster {}", seq, chimera.first_cluster,
+                  chimera.split_pos, chimera.second_cluster);
+    }
+    if chimeras.is_empty() {
+        eprintln!("No chimeric contigs found");
+    }
+    eprintln!();
+}
+
+
+fn split_chimeric_contigs(graph: &mut UnitigGraph, sequences: &[Sequence], chimeras: &[Chimera])
+        -> Vec<Sequence> {
+    // Splits each chimeric contig's path into two pieces, which then get clustered separately.
+    section_header("Splitting chimeric contigs");
+    explanation("Chimeric contigs are split where their paths move from one cluster to another, \
+                 and the contigs are clustered again.");
+    let paths = graph.get_unitig_paths_for_sequences(sequences);
+    let split_indices: HashMap<u16, usize> = chimeras.iter()
+        .map(|c| (c.seq_id, c.split_index)).collect();
+    let path_splits: HashMap<Vec<(u32, bool)>, usize> = sequences.iter().zip(paths)
+        .filter_map(|(s, path)| split_indices.get(&s.id).map(|&i| (path, i))).collect();
+    let sequences = graph.split_sequence_paths(sequences, |path| {
+        match path_splits.get(path) {
+            Some(&i) => vec![path[..i].to_vec(), path[i..].to_vec()],
+            None => vec![path.to_vec()],
+        }
+    });
+    eprintln!("{} contig{} split", chimeras.len(), match chimeras.len() { 1 => "", _ => "s" });
+    eprintln!();
+    sequences
+}
+
+
 fn make_symmetrical_distances(asymmetrical_distances: &HashMap<(u16, u16), f64>,
                               sequences: &Vec<Sequence>) -> HashMap<(u16, u16), f64> {
     // This function takes in an asymmetrical distance matrix (where A vs B is not necessarily
@@ -406,7 +992,7 @@ fn upgma(distances: &HashMap<(u16, u16), f64>, sequences: &mut Vec<Sequence>) ->
     let mut clusters: HashMap<u16, HashSet<u16>> = HashMap::new();
     let mut cluster_distances: HashMap<(u16, u16), f64> = distances.clone();
     let mut nodes: HashMap<u16, TreeNode> = HashMap::new();
-    let mut internal_node_num: u16 = sequences.iter().map(|s| (s.id)).max().unwrap();
+    let mut internal_node_num: u16 = sequences.iter().map(|s| s.id).max().unwrap();
 
     // Initialise each sequence as its own cluster and create initial nodes.
     for seq in sequences {
@@ -502,22 +1088,23 @@ fn scale_node_distance(node: &mut TreeNode, scaling_factor: f64) {
 
 
 fn generate_clusters(tree: &TreeNode, sequences: &mut Vec<Sequence>,
-                     distances: &HashMap<(u16, u16), f64>, cutoff: f64, min_assemblies: usize,
-                     manual_clusters: &[u16]) -> HashMap<u16, ClusterQC> {
+                     distances: &HashMap<(u16, u16), f64>, cutoff: f64,
+                     thresholds: &QcThresholds, manual_clusters: &[u16])
+        -> HashMap<u16, ClusterQC> {
     let clusters = if manual_clusters.is_empty() {
         let auto_clusters = tree.automatic_clustering(cutoff);
-        refine_auto_clusters(tree, sequences, distances, &auto_clusters, cutoff, min_assemblies)
+        refine_auto_clusters(tree, sequences, distances, &auto_clusters, cutoff, thresholds)
     } else {
         tree.manual_clustering(cutoff, manual_clusters)
     };
     tree.check_complete_coverage(&clusters);
-    qc_clusters(tree, sequences, distances, &clusters, manual_clusters, cutoff, min_assemblies)
+    qc_clusters(tree, sequences, distances, &clusters, manual_clusters, cutoff, thresholds)
 }
 
 
 fn qc_clusters(tree: &TreeNode, sequences: &mut Vec<Sequence>, distances: &HashMap<(u16, u16), f64>,
                cluster_nodes: &Vec<u16>, manual_clusters: &[u16], cutoff: f64,
-               min_assemblies: usize) -> HashMap<u16, ClusterQC> {
+               thresholds: &QcThresholds) -> HashMap<u16, ClusterQC> {
     // Given a set of node numbers for the tree which define clusters, this function returns the
     // QC-results HashMap.
 
@@ -537,7 +1124,7 @@ fn qc_clusters(tree: &TreeNode, sequences: &mut Vec<Sequence>, distances: &HashM
             assign_cluster_to_node(node, sequences, current_cluster);
             let mut qc = ClusterQC::new(tree.max_pairwise_distance(*n));
             if !manual_clusters.is_empty() && !manual_clusters.contains(n) {
-                qc.failure_reasons.push("not included in manual clusters".to_string());
+                qc.add_failure("manual", "not included in manual clusters".to_string());
             }
             qc_results.insert(current_cluster, qc);
         } else {
@@ -555,24 +1142,65 @@ fn qc_clusters(tree: &TreeNode, sequences: &mut Vec<Sequence>, distances: &HashM
     }
     qc_results = reordered_qc_results;
 
-    // If using automatic clustering, clusters are now failed for being contained in other clusters
-    // or appearing in too few input assemblies.
+    // If using automatic clustering, clusters are now failed for appearing in too few input
+    // assemblies.
+    let max_cluster = get_max_cluster(sequences);
     if manual_clusters.is_empty() {
-        let max_cluster = get_max_cluster(sequences);
         for c in 1..=max_cluster {
             let assemblies: HashSet<_> = sequences.iter().filter(|s| s.cluster == c)
-                                                  .map(|s| s.filename.clone()).collect();
-            if assemblies.len() < min_assemblies {
+                                                  .flat_map(|s| s.filenames()).collect();
+            if assemblies.len() < thresholds.min_assemblies {
                 let fail_reason = "present in too few assemblies".to_string();
-                qc_results.get_mut(&c).unwrap().failure_reasons.push(fail_reason);
+                qc_results.get_mut(&c).unwrap().add_failure("min_assemblies", fail_reason);
+            }
+        }
+    }
+
+    // The optional distance, length and size thresholds apply to both automatic and manual
+    // clusters.
+    for c in 1..=max_cluster {
+        let qc = qc_results.get_mut(&c).unwrap();
+        if let Some(max_dist) = thresholds.max_cluster_distance {
+            if qc.cluster_dist > max_dist {
+                let fail_reason = format!("cluster distance ({}) above maximum ({})",
+                                          format_float(qc.cluster_dist), format_float(max_dist));
+                qc.add_failure("max_cluster_distance", fail_reason);
+            }
+        }
+        if let Some(max_diff) = thresholds.max_length_diff {
+            let length_diff = cluster_length_diff(c, sequences);
+            if length_diff > max_diff {
+                let fail_reason = format!("contig length difference ({}) above maximum ({})",
+                                          format_float(length_diff), format_float(max_diff));
+                qc.add_failure("max_length_diff", fail_reason);
+            }
+        }
+        let cluster_len = cluster_median_length(c, sequences);
+        if let Some(min_len) = thresholds.min_cluster_len {
+            if cluster_len < min_len {
+                let fail_reason = format!("length ({} bp) below minimum ({} bp)", cluster_len,
+                                          min_len);
+                qc.add_failure("min_cluster_len", fail_reason);
             }
         }
+        if let Some(max_len) = thresholds.max_cluster_len {
+            if cluster_len > max_len {
+                let fail_reason = format!("length ({} bp) above maximum ({} bp)", cluster_len,
+                                          max_len);
+                qc.add_failure("max_cluster_len", fail_reason);
+            }
+        }
+    }
+
+    // If using automatic clustering, clusters are lastly failed for being contained in other
+    // clusters which have passed QC.
+    if manual_clusters.is_empty() {
         for c in 1..=max_cluster {
             let container = cluster_is_contained_in_another(c, sequences, distances, cutoff,
                                                             &qc_results);
             if container > 0 {
                 let fail_reason = format!("contained within cluster {}", container);
-                qc_results.get_mut(&c).unwrap().failure_reasons.push(fail_reason);
+                qc_results.get_mut(&c).unwrap().add_failure("contained", fail_reason);
             }
         }
     }
@@ -582,11 +1210,10 @@ fn qc_clusters(tree: &TreeNode, sequences: &mut Vec<Sequence>, distances: &HashM
 
 fn score_clustering(tree: &TreeNode, sequences: &mut Vec<Sequence>,
                     distances: &HashMap<(u16, u16), f64>, clusters: &Vec<u16>, cutoff: f64,
-                    min_assemblies: usize) -> f64 {
+                    thresholds: &QcThresholds) -> f64 {
     // Given a set of node numbers for the tree which define clusters, this function returns the
     // overall score for that clustering (higher is better).
-    let qc_results = qc_clusters(tree, sequences, distances, clusters, &[], cutoff,
-                                 min_assemblies);
+    let qc_results = qc_clusters(tree, sequences, distances, clusters, &[], cutoff, thresholds);
     let metrics = clustering_metrics(sequences, &qc_results);
     metrics.overall_clustering_score
 }
@@ -594,19 +1221,19 @@ fn score_clustering(tree: &TreeNode, sequences: &mut Vec<Sequence>,
 
 fn refine_auto_clusters(tree: &TreeNode, sequences: &mut Vec<Sequence>,
                         distances: &HashMap<(u16, u16), f64>, clusters: &[u16], cutoff: f64,
-                        min_assemblies: usize) -> Vec<u16> {
+                        thresholds: &QcThresholds) -> Vec<u16> {
     // Given a set of node numbers for the tree which define clusters, this function tries to
     // improve the clustering by splitting each cluster and checking if the score gets better,
     // repeating until no improvements can be made.
     let mut best_clusters = clusters.to_vec();
     let mut best_score = score_clustering(tree, sequences, distances, &best_clusters, cutoff,
-                                          min_assemblies);
+                                          thresholds);
     let mut improved = true;
     while improved {
         improved = false;
         for alt_clusters in tree.split_clusters(&best_clusters) {
             let alt_score = score_clustering(tree, sequences, distances, &alt_clusters, cutoff,
-                                             min_assemblies);
+                                             thresholds);
             if alt_score > best_score {
                 best_clusters = alt_clusters;
                 best_score = alt_score;
@@ -664,6 +1291,7 @@ fn parse_manual_clusters(manual_clusters: Option<String>) -> Vec<u16> {
 #[derive(Default)]
 struct ClusterQC {
     pub failure_reasons: Vec<String>,
+    pub failed_rules: Vec<String>,  // parallel to failure_reasons, names the rule for each
     pub cluster_dist: f64,
 }
 
@@ -671,14 +1299,46 @@ impl ClusterQC {
     pub fn new(cluster_dist: f64) -> Self {
         ClusterQC {
             failure_reasons: Vec::new(),
+            failed_rules: Vec::new(),
             cluster_dist,
         }
     }
+    pub fn add_failure(&mut self, rule: &str, reason: String) {
+        self.failed_rules.push(rule.to_string());
+        self.failure_reasons.push(reason);
+    }
     pub fn pass(&self) -> bool { self.failure_reasons.is_empty() }
     pub fn fail(&self) -> bool { !self.failure_reasons.is_empty() }
 }
 
 
+struct QcThresholds {
+    min_assemblies: usize,
+    max_cluster_distance: Option<f64>,
+    max_length_diff: Option<f64>,
+    min_cluster_len: Option<usize>,
+    max_cluster_len: Option<usize>,
+}
+
+
+fn cluster_length_diff(cluster_num: u16, sequences: &[Sequence]) -> f64 {
+    // Returns the difference between the longest and shortest contigs in the cluster, as a
+    // fraction of the longest.
+    let lengths: Vec<usize> = sequences.iter().filter(|s| s.cluster == cluster_num)
+                                       .map(|s| s.length).collect();
+    let max_length = lengths.iter().max().copied().unwrap_or(0);
+    let min_length = lengths.iter().min().copied().unwrap_or(0);
+    if max_length == 0 { 0.0 } else { (max_length - min_length) as f64 / max_length as f64 }
+}
+
+
+fn cluster_median_length(cluster_num: u16, sequences: &[Sequence]) -> usize {
+    let lengths: Vec<usize> = sequences.iter().filter(|s| s.cluster == cluster_num)
+                                       .map(|s| s.length).collect();
+    median_usize(&lengths)
+}
+
+
 fn cluster_is_contained_in_another(cluster_num: u16, sequences: &[Sequence],
                                    distances: &HashMap<(u16, u16), f64>, cutoff: f64,
                                    qc_results: &HashMap<u16, ClusterQC>) -> u16 {
@@ -714,16 +1374,20 @@ fn cluster_is_contained_in_another(cluster_num: u16, sequences: &[Sequence],
 
 
 fn save_clusters(sequences: &[Sequence], qc_results: &HashMap<u16, ClusterQC>,
-                 clustering_dir: &Path, gfa_lines: &Vec<String>) {
+                 labels: &HashMap<u16, String>, clustering_dir: &Path, graph: &UnitigGraph,
+                 gfa_lines: &Vec<String>, gfa_version: u32) {
     let pass_dir = clustering_dir.join("qc_pass");
     let fail_dir = clustering_dir.join("qc_fail");
-    save_qc_pass_clusters(sequences, qc_results, gfa_lines, &pass_dir);
-    save_qc_fail_clusters(sequences, qc_results, gfa_lines, &fail_dir);
+    save_qc_pass_clusters(sequences, qc_results, labels, graph, gfa_lines, &pass_dir,
+                          gfa_version);
+    save_qc_fail_clusters(sequences, qc_results, labels, graph, gfa_lines, &fail_dir,
+                          gfa_version);
 }
 
 
 fn save_qc_pass_clusters(sequences: &[Sequence], qc_results: &HashMap<u16, ClusterQC>,
-                         gfa_lines: &Vec<String>, pass_dir: &Path) {
+                         labels: &HashMap<u16, String>, graph: &UnitigGraph,
+                         gfa_lines: &Vec<String>, pass_dir: &Path, gfa_version: u32) {
     for c in 1..=get_max_cluster(sequences) {
         let qc = qc_results.get(&c).unwrap();
         if qc.pass() {
@@ -739,9 +1403,13 @@ fn save_qc_pass_clusters(sequences: &[Sequence], qc_results: &HashMap<u16, Clust
                 eprintln!("  cluster distance: {}", format_float(qc.cluster_dist));
             }
             eprintln!("{}", "  passed QC".green());
-            let cluster_dir = pass_dir.join(format!("cluster_{:03}", c));
+            let cluster_dir = pass_dir.join(cluster_dir_name(c, labels));
             create_dir(&cluster_dir);
-            save_cluster_gfa(sequences, c, gfa_lines, cluster_dir.join("1_untrimmed.gfa"));
+            save_cluster_gfa(sequences, c, gfa_lines, cluster_dir.join("1_untrimmed.gfa"),
+                             gfa_version);
+            save_cluster_subgraph(graph, sequences, c, cluster_dir.join("subgraph.gfa"),
+                                  gfa_version);
+            add_label_to_gfas(&cluster_dir, labels.get(&c));
             save_untrimmed_cluster_metrics(seq_lengths, qc.cluster_dist,
                                            cluster_dir.join("1_untrimmed.yaml"));
             eprintln!();
@@ -751,7 +1419,8 @@ fn save_qc_pass_clusters(sequences: &[Sequence], qc_results: &HashMap<u16, Clust
 
 
 fn save_qc_fail_clusters(sequences: &[Sequence], qc_results: &HashMap<u16, ClusterQC>,
-                         gfa_lines: &Vec<String>, fail_dir: &Path) {
+                         labels: &HashMap<u16, String>, graph: &UnitigGraph,
+                         gfa_lines: &Vec<String>, fail_dir: &Path, gfa_version: u32) {
     for c in 1..=get_max_cluster(sequences) {
         let qc = qc_results.get(&c).unwrap();
         if qc.fail() {
@@ -770,9 +1439,13 @@ fn save_qc_fail_clusters(sequences: &[Sequence], qc_results: &HashMap<u16, Clust
             for f in &qc.failure_reasons {
                 eprintln!("  {}", format!("failed QC: {}", f).red());
             }
-            let cluster_dir = fail_dir.join(format!("cluster_{:03}", c));
+            let cluster_dir = fail_dir.join(cluster_dir_name(c, labels));
             create_dir(&cluster_dir);
-            save_cluster_gfa(sequences, c, gfa_lines, cluster_dir.join("1_untrimmed.gfa"));
+            save_cluster_gfa(sequences, c, gfa_lines, cluster_dir.join("1_untrimmed.gfa"),
+                             gfa_version);
+            save_cluster_subgraph(graph, sequences, c, cluster_dir.join("subgraph.gfa"),
+                                  gfa_version);
+            add_label_to_gfas(&cluster_dir, labels.get(&c));
             save_untrimmed_cluster_metrics(seq_lengths, qc.cluster_dist,
                                            cluster_dir.join("1_untrimmed.yaml"));
             eprintln!();
@@ -781,8 +1454,38 @@ fn save_qc_fail_clusters(sequences: &[Sequence], qc_results: &HashMap<u16, Clust
 }
 
 
+fn cluster_dir_name(cluster_num: u16, labels: &HashMap<u16, String>) -> String {
+    // Labelled clusters get their label in the directory name (made filename-safe), e.g.
+    // cluster_001_chromosome. The name still starts with cluster_NNN, so globs like cluster_*
+    // continue to work.
+    match labels.get(&cluster_num) {
+        Some(label) => {
+            let safe: String = label.chars().map(|c| if c.is_ascii_alphanumeric() ||
+                                                        "-._".contains(c) { c } else { '_' })
+                                    .collect();
+            format!("cluster_{:03}_{}", cluster_num, safe)
+        }
+        None => format!("cluster_{:03}", cluster_num),
+    }
+}
+
+
+fn add_label_to_gfas(cluster_dir: &Path, label: Option<&String>) {
+    // Adds the cluster's label as an LB tag on the header line of its GFA files.
+    let Some(label) = label else { return; };
+    for gfa in [cluster_dir.join("1_untrimmed.gfa"), cluster_dir.join("subgraph.gfa")] {
+        let mut lines = load_file_lines(&gfa);
+        lines[0] = format!("{}\tLB:Z:{}", lines[0], label);
+        let mut file = File::create(&gfa).unwrap();
+        for line in lines {
+            writeln!(file, "{}", line).unwrap();
+        }
+    }
+}
+
+
 fn save_cluster_gfa(sequences: &[Sequence], cluster_num: u16, gfa_lines: &Vec<String>,
-                    out_gfa: PathBuf) {
+                    out_gfa: PathBuf, gfa_version: u32) {
     let cluster_seqs: Vec<Sequence> = sequences.iter().filter(|s| s.cluster == cluster_num)
                                                .cloned().collect();
     let (mut cluster_graph, _) = UnitigGraph::from_gfa_lines(gfa_lines);
@@ -793,7 +1496,22 @@ fn save_cluster_gfa(sequences: &[Sequence], cluster_num: u16, gfa_lines: &Vec<St
     }
     cluster_graph.remove_zero_depth_unitigs();
     merge_linear_paths(&mut cluster_graph, &cluster_seqs, None);
-    cluster_graph.save_gfa(&out_gfa, &cluster_seqs).unwrap();
+    cluster_graph.estimate_multiplicities(&cluster_seqs);
+    cluster_graph.save_gfa_with_version(&out_gfa, &cluster_seqs, gfa_version).unwrap();
+}
+
+
+fn save_cluster_subgraph(graph: &UnitigGraph, sequences: &[Sequence], cluster_num: u16,
+                         out_gfa: PathBuf, gfa_version: u32) {
+    // Unlike the untrimmed cluster graph, this is an unmodified piece of the input assemblies
+    // graph (same unitig numbers, no merging), so it can be compared directly with the full graph.
+    let cluster_seqs: Vec<Sequence> = sequences.iter().filter(|s| s.cluster == cluster_num)
+                                               .cloned().collect();
+    let unitig_numbers: Vec<u32> = graph.get_unitig_paths_for_sequences(&cluster_seqs).iter()
+        .flat_map(|path| path.iter().map(|(number, _)| *number))
+        .collect::<HashSet<u32>>().into_iter().collect();
+    let (subgraph, sub_seqs) = graph.extract_subgraph(&unitig_numbers, &cluster_seqs);
+    subgraph.save_gfa_with_version(&out_gfa, &sub_seqs, gfa_version).unwrap();
 }
 
 
@@ -825,6 +1543,218 @@ fn save_data_to_tsv(sequences: &Vec<Sequence>, qc_results: &HashMap<u16, Cluster
 }
 
 
+fn save_cluster_summary(graph: &UnitigGraph, sequences: &[Sequence],
+                        qc_results: &HashMap<u16, ClusterQC>, labels: &HashMap<u16, String>,
+                        file_path: &Path) {
+    // Saves one row per cluster, for easy aggregation across many samples. A cluster is called
+    // circular if most of its contigs look circular in the graph.
+    let circular: HashMap<u16, bool> = sequences.iter()
+        .zip(graph.get_unitig_paths_for_sequences_i32(sequences))
+        .map(|(s, path)| (s.id, contig_is_circular(graph, &path))).collect();
+    let mut file = File::create(file_path).unwrap();
+    writeln!(file, "cluster\tcontigs\tassemblies\tmedian_length\tcircular\tqc\t\
+                    failed_rules\tlabel").unwrap();
+    for c in 1..=get_max_cluster(sequences) {
+        let qc = &qc_results[&c];
+        let contigs: Vec<&Sequence> = sequences.iter().filter(|s| s.cluster == c).collect();
+        let assemblies: HashSet<&str> = contigs.iter().flat_map(|s| s.filenames()).collect();
+        let circular_count = contigs.iter().filter(|s| circular[&s.id]).count();
+        let failed_rules = if qc.failed_rules.is_empty() { "none".to_string() }
+                                                    else { qc.failed_rules.join(",") };
+        writeln!(file, "{}\t{}\t{}\t{}\t{}\t{}\t{}\t{}", c, contigs.len(), assemblies.len(),
+                 cluster_median_length(c, sequences),
+                 if circular_count * 2 > contigs.len() { "yes" } else { "no" },
+                 if qc.pass() { "pass" } else { "fail" }, failed_rules,
+                 labels.get(&c).map_or("none", |l| l.as_str())).unwrap();
+    }
+}
+
+
+fn contig_is_circular(graph: &UnitigGraph, path: &[i32]) -> bool {
+    // Contig ends fall on unitig boundaries, so a circular contig's last unitig links back to its
+    // first (when other contigs span the start-end junction). Alternatively, a contig with a
+    // start-end overlap passes through its first unitig strand again.
+    let (Some(&first), Some(&last)) = (path.first(), path.last()) else { return false; };
+    graph.outgoing_strands(last).contains(&first) || path[1..].contains(&first)
+}
+
+
+fn save_html_report(tree: &TreeNode, sequences: &[Sequence],
+                    distances: &HashMap<(u16, u16), f64>, qc_results: &HashMap<u16, ClusterQC>,
+                    cutoff: f64, distance_metric: &str, file_path: &Path) {
+    let html = html_report(tree, sequences, distances, qc_results, cutoff, distance_metric);
+    if let Err(e) = std::fs::write(file_path, html) {
+        quit_with_error(&format!("unable to write {}\n{}", file_path.display(), e));
+    }
+}
+
+
+fn html_report(tree: &TreeNode, sequences: &[Sequence], distances: &HashMap<(u16, u16), f64>,
+               qc_results: &HashMap<u16, ClusterQC>, cutoff: f64, distance_metric: &str)
+        -> String {
+    // Builds a self-contained HTML page (no external scripts or styles) with the clustering tree,
+    // a heatmap of the pairwise distances and a table of cluster QC results. The tree and heatmap
+    // share the same contig order, so rows line up.
+    let mut tip_order = Vec::new();
+    tree.collect_tips(&mut tip_order);
+    let index: HashMap<u16, &Sequence> = sequences.iter().map(|s| (s.id, s)).collect();
+    let mut html = String::new();
+    html.push_str("<!DOCTYPE html>\n<html>\n<head>\n<meta charset=\"utf-8\">\n\
+                   <title>Autocycler clustering</title>\n<style>\n\
+                   body { font-family: sans-serif; margin: 2em; }\n\
+                   table { border-collapse: collapse; }\n\
+                   th, td { border: 1px solid #ccc; padding: 4px 8px; text-align: left; }\n\
+                   .pass { color: #1a7f37; } .fail { color: #cf222e; }\n\
+                   </style>\n</head>\n<body>\n<h1>Autocycler clustering</h1>\n");
+    html.push_str(&format!("<p>{} contigs, distance metric: {}, cutoff: {}</p>\n",
+                           sequences.len(), distance_metric, format_float(cutoff)));
+    html.push_str("<h2>Clustering tree</h2>\n<p>Internal node numbers can be used with \
+                   <code>--manual</code>. The dashed line shows the cutoff.</p>\n");
+    html.push_str(&dendrogram_svg(tree, &tip_order, &index, qc_results, cutoff));
+    html.push_str("<h2>Pairwise distances</h2>\n<p>Each row shows the distance from that \
+                   contig to the contig in each column. Darker is closer, and distances at or \
+                   above the cutoff are white.</p>\n");
+    html.push_str(&heatmap_svg(&tip_order, &index, distances, cutoff));
+    html.push_str("<h2>Cluster QC</h2>\n");
+    html.push_str(&qc_table_html(sequences, qc_results));
+    html.push_str("</body>\n</html>\n");
+    html
+}
+
+
+const REPORT_ROW_HEIGHT: f64 = 16.0;
+const REPORT_TREE_WIDTH: f64 = 400.0;
+const REPORT_LABEL_WIDTH: f64 = 400.0;
+
+
+fn dendrogram_svg(tree: &TreeNode, tip_order: &[u16], index: &HashMap<u16, &Sequence>,
+                  qc_results: &HashMap<u16, ClusterQC>, cutoff: f64) -> String {
+    let margin = 10.0;
+    let root_height = if tree.distance > 0.0 { tree.distance } else { 1.0 };
+    let x_pos = |height: f64| margin + (1.0 - height / root_height) * REPORT_TREE_WIDTH;
+    let tip_y: HashMap<u16, f64> = tip_order.iter().enumerate()
+        .map(|(i, &id)| (id, margin + (i as f64 + 0.5) * REPORT_ROW_HEIGHT)).collect();
+    let width = 2.0 * margin + REPORT_TREE_WIDTH + REPORT_LABEL_WIDTH;
+    let height = 2.0 * margin + tip_order.len() as f64 * REPORT_ROW_HEIGHT;
+    let mut svg = format!("<svg xmlns=\"http://www.w3.org/2000/svg\" width=\"{:.0}\" \
+                           height=\"{:.0}\" font-size=\"11\">\n", width, height);
+    if cutoff / 2.0 <= root_height {
+        let x = x_pos(cutoff / 2.0);
+        svg.push_str(&format!("<line x1=\"{:.1}\" y1=\"{:.1}\" x2=\"{:.1}\" y2=\"{:.1}\" \
+                               stroke=\"#cf222e\" stroke-dasharray=\"4,3\"/>\n",
+                              x, margin, x, height - margin));
+    }
+    draw_tree_node(tree, &x_pos, &tip_y, &mut svg);
+    for &id in tip_order {
+        let seq = index[&id];
+        let colour = cluster_colour(seq.cluster, qc_results);
+        svg.push_str(&format!("<text x=\"{:.1}\" y=\"{:.1}\" dy=\"0.35em\" fill=\"{}\">{} {} \
+                               ({} bp, cluster {})</text>\n",
+                              x_pos(0.0) + 5.0, tip_y[&id], colour, html_escape(&seq.filename),
+                              html_escape(&seq.contig_name()), seq.length, seq.cluster));
+    }
+    svg.push_str("</svg>\n");
+    svg
+}
+
+
+fn draw_tree_node<F: Fn(f64) -> f64>(node: &TreeNode, x_pos: &F, tip_y: &HashMap<u16, f64>,
+                                     svg: &mut String) -> f64 {
+    // Draws the node's clade and returns the node's y position.
+    if node.is_tip() {
+        return tip_y[&node.id];
+    }
+    let x = x_pos(node.distance);
+    let mut child_ys = Vec::new();
+    for child in [node.left.as_ref().unwrap(), node.right.as_ref().unwrap()] {
+        let child_y = draw_tree_node(child, x_pos, tip_y, svg);
+        svg.push_str(&format!("<line x1=\"{:.1}\" y1=\"{:.1}\" x2=\"{:.1}\" y2=\"{:.1}\" \
+                               stroke=\"black\"/>\n", x, child_y, x_pos(child.distance), child_y));
+        child_ys.push(child_y);
+    }
+    svg.push_str(&format!("<line x1=\"{:.1}\" y1=\"{:.1}\" x2=\"{:.1}\" y2=\"{:.1}\" \
+                           stroke=\"black\"/>\n", x, child_ys[0], x, child_ys[1]));
+    let y = (child_ys[0] + child_ys[1]) / 2.0;
+    svg.push_str(&format!("<circle cx=\"{:.1}\" cy=\"{:.1}\" r=\"2.5\"><title>node {} \
+                           (distance {:.6})</title></circle>\n",
+                          x, y, node.id, node.distance * 2.0));
+    svg.push_str(&format!("<text x=\"{:.1}\" y=\"{:.1}\" font-size=\"9\" fill=\"#666\">{}</text>\n",
+                          x + 3.0, y - 3.0, node.id));
+    y
+}
+
+
+fn heatmap_svg(tip_order: &[u16], index: &HashMap<u16, &Sequence>,
+               distances: &HashMap<(u16, u16), f64>, cutoff: f64) -> String {
+    let cell = (600.0 / tip_order.len().max(1) as f64).clamp(3.0, 20.0);
+    let size = cell * tip_order.len() as f64;
+    let mut svg = format!("<svg xmlns=\"http://www.w3.org/2000/svg\" width=\"{:.0}\" \
+                           height=\"{:.0}\">\n", size, size);
+    for (i, a) in tip_order.iter().enumerate() {
+        for (j, b) in tip_order.iter().enumerate() {
+            let d = distances[&(*a, *b)];
+            svg.push_str(&format!("<rect x=\"{:.1}\" y=\"{:.1}\" width=\"{:.1}\" \
+                                   height=\"{:.1}\" fill=\"{}\"><title>{} vs {}: {:.6}</title>\
+                                   </rect>\n",
+                                  j as f64 * cell, i as f64 * cell, cell, cell,
+                                  heatmap_colour(d, cutoff), html_escape(&index[a].to_string()),
+                                  html_escape(&index[b].to_string()), d));
+        }
+    }
+    svg.push_str("</svg>\n");
+    svg
+}
+
+
+fn heatmap_colour(distance: f64, cutoff: f64) -> String {
+    // Interpolates from dark blue (distance of zero) to white (distance at or above the cutoff).
+    let t = 1.0 - (distance / cutoff).clamp(0.0, 1.0);
+    let channel = |dark: f64| (255.0 - t * (255.0 - dark)).round() as u8;
+    format!("#{:02x}{:02x}{:02x}", channel(8.0), channel(48.0), channel(107.0))
+}
+
+
+fn cluster_colour(cluster: u16, qc_results: &HashMap<u16, ClusterQC>) -> &'static str {
+    // Passing clusters cycle through a palette, while failing clusters are grey.
+    const PALETTE: [&str; 8] = ["#1f77b4", "#ff7f0e", "#2ca02c", "#d62728", "#9467bd",
+                                "#8c564b", "#e377c2", "#17becf"];
+    match qc_results.get(&cluster) {
+        Some(qc) if qc.pass() => PALETTE[(cluster as usize - 1) % PALETTE.len()],
+        _ => "#999999",
+    }
+}
+
+
+fn qc_table_html(sequences: &[Sequence], qc_results: &HashMap<u16, ClusterQC>) -> String {
+    let mut html = "<table>\n<tr><th>Cluster</th><th>QC</th><th>Contigs</th>\
+                    <th>Assemblies</th><th>Median length</th><th>Max distance</th>\
+                    <th>Failure reasons</th></tr>\n"
+        .to_string();
+    let mut clusters: Vec<&u16> = qc_results.keys().collect();
+    clusters.sort();
+    for c in clusters {
+        let qc = &qc_results[c];
+        let contigs: Vec<&Sequence> = sequences.iter().filter(|s| s.cluster == *c).collect();
+        let assemblies: HashSet<&str> = contigs.iter().flat_map(|s| s.filenames()).collect();
+        let (class, status) = if qc.pass() { ("pass", "pass") } else { ("fail", "fail") };
+        html.push_str(&format!("<tr><td style=\"color: {}\">{}</td><td class=\"{}\">{}</td>\
+                                <td>{}</td><td>{}</td><td>{} bp</td><td>{:.6}</td><td>{}</td>\
+                                </tr>\n",
+                               cluster_colour(*c, qc_results), c, class, status, contigs.len(),
+                               assemblies.len(), cluster_median_length(*c, sequences),
+                               qc.cluster_dist,
+                               html_escape(&qc.failure_reasons.join("; "))));
+    }
+    html.push_str("</table>\n");
+    html
+}
+
+
+fn html_escape(text: &str) -> String {
+    text.replace('&', "&amp;").replace('<', "&lt;").replace('>', "&gt;").replace('"', "&quot;")
+}
+
+
 fn clustering_metrics(sequences: &Vec<Sequence>, qc_results: &HashMap<u16, ClusterQC>)
         -> ClusteringMetrics {
     let mut metrics = ClusteringMetrics::default();
@@ -841,7 +1771,8 @@ fn clustering_metrics(sequences: &Vec<Sequence>, qc_results: &HashMap<u16, Clust
     let mut cluster_filenames = HashMap::new();
     for seq in sequences {
         let qc = qc_results.get(&seq.cluster).unwrap();
-        cluster_filenames.entry(seq.cluster).or_insert_with(Vec::new).push(seq.filename.clone());
+        cluster_filenames.entry(seq.cluster).or_insert_with(Vec::new)
+                         .extend(seq.filenames().map(String::from));
         if qc.pass() {
             metrics.pass_contig_count += 1;
         } else {
@@ -854,6 +1785,28 @@ fn clustering_metrics(sequences: &Vec<Sequence>, qc_results: &HashMap<u16, Clust
 }
 
 
+fn cluster_qc_details(sequences: &[Sequence], qc_results: &HashMap<u16, ClusterQC>,
+                      labels: &HashMap<u16, String>) -> Vec<ClusterQcDetails> {
+    (1..=get_max_cluster(sequences)).map(|c| {
+        let qc = &qc_results[&c];
+        let contigs: Vec<&Sequence> = sequences.iter().filter(|s| s.cluster == c).collect();
+        let assemblies: HashSet<&str> = contigs.iter().flat_map(|s| s.filenames()).collect();
+        ClusterQcDetails {
+            cluster: c,
+            pass: qc.pass(),
+            contig_count: contigs.len() as u32,
+            assembly_count: assemblies.len() as u32,
+            median_length: cluster_median_length(c, sequences) as u64,
+            cluster_distance: qc.cluster_dist,
+            length_diff: cluster_length_diff(c, sequences),
+            failed_rules: qc.failed_rules.clone(),
+            failure_reasons: qc.failure_reasons.clone(),
+            label: labels.get(&c).cloned(),
+        }
+    }).collect()
+}
+
+
 fn reorder_clusters(sequences: &mut Vec<Sequence>) -> HashMap<u16, u16>{
     // Reorder clusters based on their median sequence length (large to small). Returns the mapping
     // of old cluster numbers to new cluster numbers.
@@ -879,7 +1832,7 @@ fn reorder_clusters(sequences: &mut Vec<Sequence>) -> HashMap<u16, u16>{
 
 
 fn get_assembly_count(sequences: &[Sequence]) -> usize {
-    sequences.iter().map(|s| &s.filename).collect::<HashSet<_>>().len()
+    sequences.iter().flat_map(|s| s.filenames()).collect::<HashSet<_>>().len()
 }
 
 
@@ -892,6 +1845,7 @@ fn get_max_cluster(sequences: &[Sequence]) -> u16 {
 mod tests {
     use super::*;
     use std::panic;
+    use crate::test_gfa::*;
     use crate::tests::assert_almost_eq;
 
     #[test]
@@ -966,6 +1920,295 @@ mod tests {
         assert_eq!(newick_string, "((1__a__a__1_bp:0.05,2__b__b__1_bp:0.05)5:0.2,(3__c__c__1_bp:0.1,4__d__d__1_bp:0.1)6:0.15)7");
     }
 
+    #[test]
+    fn test_jaccard_distances() {
+        let (graph, sequences) = UnitigGraph::from_gfa_lines(&get_test_gfa_15());
+        let distances = jaccard_distances(&graph, &sequences);
+        assert_eq!(distances.len(), 9);
+        let (a, b, c) = (sequences[0].id, sequences[1].id, sequences[2].id);
+        assert_almost_eq(distances[&(a, a)], 0.0, 1e-8);
+        assert_almost_eq(distances[&(a, b)], 9.0 / 39.0, 1e-8);
+        assert_almost_eq(distances[&(b, a)], 9.0 / 39.0, 1e-8);
+        assert_almost_eq(distances[&(a, c)], 4.0 / 36.0, 1e-8);
+        assert_almost_eq(distances[&(b, c)], 5.0 / 35.0, 1e-8);
+    }
+
+    #[test]
+    fn test_save_cluster_subgraph() {
+        let (graph, mut sequences) = UnitigGraph::from_gfa_lines(&get_test_gfa_15());
+        sequences[0].cluster = 1; sequences[1].cluster = 1; sequences[2].cluster = 2;
+        let dir = tempfile::tempdir().unwrap();
+        let out_gfa = dir.path().join("subgraph.gfa");
+        save_cluster_subgraph(&graph, &sequences, 2, out_gfa.clone(), 1);
+        let (subgraph, sub_seqs) = UnitigGraph::from_gfa_file(&out_gfa);
+        let mut unitigs: Vec<u32> = subgraph.unitigs.iter().map(|u| u.number).collect();
+        unitigs.sort();
+        assert_eq!(unitigs, vec![1, 2, 4, 6]);
+        assert_eq!(sub_seqs.len(), 1);
+        assert_eq!(sub_seqs[0].filename, sequences[2].filename);
+    }
+
+    #[test]
+    fn test_contig_is_circular() {
+        let mut gfa = get_test_gfa_4();
+        gfa.push("P\t1\t2-,3+,1+\t*\tLN:i:35\tFN:Z:a.fasta\tHD:Z:a".to_string());
+        gfa.push("P\t2\t2-,3+\t*\tLN:i:20\tFN:Z:b.fasta\tHD:Z:b".to_string());
+        gfa.push("P\t3\t1+,2-,3+,1+\t*\tLN:i:50\tFN:Z:c.fasta\tHD:Z:c".to_string());
+        let (graph, sequences) = UnitigGraph::from_gfa_lines(&gfa);
+        let paths = graph.get_unitig_paths_for_sequences_i32(&sequences);
+        assert!(contig_is_circular(&graph, &paths[0]));
+        assert!(!contig_is_circular(&graph, &paths[1]));
+        assert!(contig_is_circular(&graph, &paths[2]));
+        assert!(!contig_is_circular(&graph, &[]));
+
+        let (graph, sequences) = UnitigGraph::from_gfa_lines(&get_test_gfa_15());
+        let paths = graph.get_unitig_paths_for_sequences_i32(&sequences);
+        assert!(paths.iter().all(|p| !contig_is_circular(&graph, p)));
+    }
+
+    #[test]
+    fn test_save_cluster_summary() {
+        let (graph, mut sequences) = UnitigGraph::from_gfa_lines(&get_test_gfa_15());
+        sequences[0].cluster = 1; sequences[1].cluster = 1; sequences[2].cluster = 2;
+        let mut qc_results = HashMap::new();
+        qc_results.insert(1, ClusterQC::new(0.1));
+        qc_results.insert(2, ClusterQC::new(0.0));
+        qc_results.get_mut(&2).unwrap().add_failure("min_assemblies", "present in too few assemblies".to_string());
+        let dir = tempfile::tempdir().unwrap();
+        let summary_tsv = dir.path().join("cluster_summary.tsv");
+        let labels = HashMap::from([(1, "chromosome".to_string())]);
+        save_cluster_summary(&graph, &sequences, &qc_results, &labels, &summary_tsv);
+        assert_eq!(std::fs::read_to_string(summary_tsv).unwrap(),
+                   "cluster\tcontigs\tassemblies\tmedian_length\tcircular\tqc\tfailed_rules\tlabel\n\
+                    1\t2\t2\t34\tno\tpass\tnone\tchromosome\n\
+                    2\t1\t1\t32\tno\tfail\tmin_assemblies\tnone\n");
+    }
+
+    #[test]
+    fn test_label_clusters() {
+        // A simple LCG makes two unrelated pseudo-random sequences, long enough to sketch.
+        let mut state = 12345_u64;
+        let mut random_seq = |n: usize| -> String { (0..n).map(|_| {
+            state = state.wrapping_mul(6364136223846793005).wrapping_add(1442695040888963407);
+            b"ACGT"[(state >> 62) as usize] as char
+        }).collect() };
+        let (seq_1, seq_2) = (random_seq(5000), random_seq(5000));
+        let gfa: Vec<String> = vec!["H\tVN:Z:1.0\tKM:i:3".to_string(),
+            format!("S\t1\t{}\tDP:f:2.00", seq_1),
+            format!("S\t2\t{}\tDP:f:1.00", seq_2),
+            "P\t1\t1+\t*\tLN:i:5000\tFN:Z:a.fasta\tHD:Z:a1".to_string(),
+            "P\t2\t1+\t*\tLN:i:5000\tFN:Z:b.fasta\tHD:Z:b1".to_string(),
+            "P\t3\t2+\t*\tLN:i:5000\tFN:Z:a.fasta\tHD:Z:a2".to_string()];
+        let (graph, mut sequences) = UnitigGraph::from_gfa_lines(&gfa);
+        for (s, c) in sequences.iter_mut().zip([1, 1, 2]) { s.cluster = c; }
+        let references = vec![("chr".to_string(), "chr length=5000 circular=true".to_string(),
+                               seq_1),
+                              ("other".to_string(), "other".to_string(), random_seq(5000))];
+        let labels = label_clusters(&graph, &sequences, &references);
+        assert_eq!(labels, HashMap::from([(1, "chr".to_string())]));
+    }
+
+    #[test]
+    fn test_cluster_labels_in_names() {
+        assert_eq!(reference_label("chr", "chr chromosome"), "chr");
+        assert_eq!(reference_label("chr", "chr length=5123456 circular=true"), "chr");
+        assert_eq!(reference_label("NC_1", "NC_1 length=5123456 label=chromosome"), "chromosome");
+        assert_eq!(reference_label("p1", "p1  label=pKPC-like_plasmid "), "pKPC-like_plasmid");
+        assert_eq!(reference_label("p2", "p2 label="), "p2");
+        assert_eq!(reference_label("p3", "p3"), "p3");
+        let labels = HashMap::from([(2, "pKPC-like plasmid".to_string())]);
+        assert_eq!(cluster_dir_name(1, &labels), "cluster_001");
+        assert_eq!(cluster_dir_name(2, &labels), "cluster_002_pKPC-like_plasmid");
+    }
+
+    #[test]
+    fn test_find_chimeras() {
+        // Unitig 1 is one cluster and unitig 2 is another, but the last contig has both.
+        let gfa: Vec<String> = vec!["H\tVN:Z:1.0\tKM:i:3",
+            "S\t1\tACGACTACGA\tDP:f:3.00",
+            "S\t2\tGATCGATCGA\tDP:f:3.00",
+            "L\t1\t+\t2\t+\t0M",
+            "L\t2\t-\t1\t-\t0M",
+            "P\t1\t1+\t*\tLN:i:10\tFN:Z:a.fasta\tHD:Z:a1",
+            "P\t2\t2+\t*\tLN:i:10\tFN:Z:a.fasta\tHD:Z:a2",
+            "P\t3\t1+\t*\tLN:i:10\tFN:Z:b.fasta\tHD:Z:b1",
+            "P\t4\t2+\t*\tLN:i:10\tFN:Z:b.fasta\tHD:Z:b2",
+            "P\t5\t1+,2+\t*\tLN:i:20\tFN:Z:c.fasta\tHD:Z:c1"]
+            .into_iter().map(String::from).collect();
+        let (mut graph, mut sequences) = UnitigGraph::from_gfa_lines(&gfa);
+        for (s, c) in sequences.iter_mut().zip([1, 2, 1, 2, 1]) { s.cluster = c; }
+        let chimeras = find_chimeras(&graph, &sequences);
+        assert_eq!(chimeras.len(), 1);
+        assert_eq!(chimeras[0].seq_id, 5);
+        assert_eq!(chimeras[0].split_index, 1);
+        assert_eq!(chimeras[0].split_pos, 10);
+        assert_eq!((chimeras[0].first_cluster, chimeras[0].second_cluster), (1, 2));
+        assert!(find_chimeras(&graph, &sequences[..4]).is_empty());
+
+        let sequences = split_chimeric_contigs(&mut graph, &sequences, &chimeras);
+        assert_eq!(sequences.len(), 6);
+        let paths = graph.get_unitig_paths_for_sequences_i32(&sequences);
+        assert_eq!(paths.iter().filter(|p| *p == &vec![1]).count(), 3);
+        assert_eq!(paths.iter().filter(|p| *p == &vec![2]).count(), 3);
+    }
+
+    #[test]
+    fn test_distance_cache() {
+        let sequences = vec![Sequence::new_with_seq(1, "A".to_string(), "a.fasta".to_string(), "a 1".to_string(), 1, 1),
+                             Sequence::new_with_seq(2, "A".to_string(), "b.fasta".to_string(), "b 1".to_string(), 1, 1)];
+        let distances = HashMap::from_iter(vec![((1, 1), 0.0), ((1, 2), 0.123456789123),
+                                                ((2, 1), 1.0 / 3.0), ((2, 2), 0.0)]);
+        let dir = tempfile::tempdir().unwrap();
+        let cache = dir.path().join("distance_cache.tsv");
+        assert!(load_distance_cache(&cache, "graph", 123, &sequences).is_none());
+        save_distance_cache(&distances, &sequences, "graph", 123, &cache);
+        assert_eq!(load_distance_cache(&cache, "graph", 123, &sequences), Some(distances));
+        assert!(load_distance_cache(&cache, "mash", 123, &sequences).is_none());
+        assert!(load_distance_cache(&cache, "graph", 456, &sequences).is_none());
+        assert!(load_distance_cache(&cache, "graph", 123, &sequences[..1]).is_none());
+        std::fs::write(&cache, "distance\tgraph\n").unwrap();
+        assert!(load_distance_cache(&cache, "graph", 123, &sequences).is_none());
+    }
+
+    #[test]
+    fn test_gfa_lines_hash() {
+        let a = vec!["H\tVN:Z:1.0".to_string(), "S\t1\tACGT".to_string()];
+        let b = vec!["H\tVN:Z:1.0".to_string(), "S\t1\tACGA".to_string()];
+        let c = vec!["H\tVN:Z:1.0S\t1\tACGT".to_string()];
+        assert_eq!(gfa_lines_hash(&a), gfa_lines_hash(&a.clone()));
+        assert_ne!(gfa_lines_hash(&a), gfa_lines_hash(&b));
+        assert_ne!(gfa_lines_hash(&a), gfa_lines_hash(&c));
+    }
+
+    #[test]
+    fn test_screen_contaminants() {
+        let sequences = vec![Sequence::new_with_seq(1, "A".to_string(), "a.fasta".to_string(), "1".to_string(), 1, 1),
+                             Sequence::new_with_seq(2, "A".to_string(), "b.fasta".to_string(), "1".to_string(), 1, 1),
+                             Sequence::new_with_seq(3, "A".to_string(), "b.fasta".to_string(), "2".to_string(), 1, 1),
+                             Sequence::new_with_seq(4, "A".to_string(), "c.fasta".to_string(), "1".to_string(), 1, 1)];
+        let distances = HashMap::from_iter(vec![((1, 1), 0.0), ((1, 2), 0.1), ((1, 3), 1.0), ((1, 4), 0.2),
+                                                ((2, 1), 0.1), ((2, 2), 0.0), ((2, 3), 1.0), ((2, 4), 0.2),
+                                                ((3, 1), 1.0), ((3, 2), 1.0), ((3, 3), 0.0), ((3, 4), 1.0),
+                                                ((4, 1), 0.9), ((4, 2), 0.9), ((4, 3), 1.0), ((4, 4), 0.0)]);
+        assert!(!is_isolated(&sequences[0], &sequences, &distances));
+        assert!(is_isolated(&sequences[2], &sequences, &distances));
+        assert!(!is_isolated(&sequences[3], &sequences, &distances));
+
+        let (kept, discarded) = screen_contaminants(sequences.clone(), &distances);
+        assert_eq!(kept.iter().map(|s| s.id).collect::<Vec<_>>(), vec![1, 2, 4]);
+        assert_eq!(discarded.iter().map(|s| s.id).collect::<Vec<_>>(), vec![3]);
+        let remaining = remove_discarded_distances(distances.clone(), &discarded);
+        assert_eq!(remaining.len(), 9);
+        assert!(!remaining.contains_key(&(1, 3)));
+
+        // Nothing is discarded from a single assembly, or if everything would be discarded.
+        let (kept, discarded) = screen_contaminants(sequences[1..3].to_vec(), &distances);
+        assert_eq!((kept.len(), discarded.len()), (2, 0));
+        let (kept, discarded) = screen_contaminants(sequences[2..].to_vec(), &distances);
+        assert_eq!((kept.len(), discarded.len()), (2, 0));
+    }
+
+    #[test]
+    fn test_qc_thresholds() {
+        let mut sequences = vec![Sequence::new_with_seq(1, "A".to_string(), "a".to_string(), "a".to_string(), 100, 1),
+                                 Sequence::new_with_seq(2, "A".to_string(), "b".to_string(), "b".to_string(), 80, 1),
+                                 Sequence::new_with_seq(3, "A".to_string(), "a".to_string(), "c".to_string(), 50, 1),
+                                 Sequence::new_with_seq(4, "A".to_string(), "b".to_string(), "d".to_string(), 50, 1)];
+        let distances = HashMap::from_iter(vec![((1, 1), 0.0), ((1, 2), 0.1), ((1, 3), 0.5), ((1, 4), 0.5),
+                                                ((2, 1), 0.1), ((2, 2), 0.0), ((2, 3), 0.5), ((2, 4), 0.5),
+                                                ((3, 1), 0.5), ((3, 2), 0.5), ((3, 3), 0.0), ((3, 4), 0.2),
+                                                ((4, 1), 0.5), ((4, 2), 0.5), ((4, 3), 0.2), ((4, 4), 0.0)]);
+        let root = upgma(&distances, &mut sequences);
+        let thresholds = QcThresholds { min_assemblies: 2, max_cluster_distance: None,
+                                        max_length_diff: None, min_cluster_len: None,
+                                        max_cluster_len: None };
+        let qc_results = qc_clusters(&root, &mut sequences, &distances, &vec![5, 6], &[], 0.3,
+                                     &thresholds);
+        assert!(qc_results.values().all(|qc| qc.pass()));
+
+        let thresholds = QcThresholds { min_assemblies: 2, max_cluster_distance: Some(0.15),
+                                        max_length_diff: Some(0.1), min_cluster_len: None,
+                                        max_cluster_len: None };
+        let qc_results = qc_clusters(&root, &mut sequences, &distances, &vec![5, 6], &[], 0.3,
+                                     &thresholds);
+        let qc_1 = &qc_results[&sequences[0].cluster];
+        let qc_2 = &qc_results[&sequences[2].cluster];
+        assert_eq!(qc_1.failed_rules, vec!["max_length_diff"]);
+        assert_eq!(qc_2.failed_rules, vec!["max_cluster_distance"]);
+        assert_eq!(qc_1.failure_reasons, vec!["contig length difference (0.2) above maximum (0.1)"]);
+
+        let details = cluster_qc_details(&sequences, &qc_results, &HashMap::new());
+        assert_eq!(details.len(), 2);
+        assert!(details.iter().all(|d| d.label.is_none()));
+        assert!(details.iter().all(|d| !d.pass && d.contig_count == 2 && d.assembly_count == 2));
+
+        let thresholds = QcThresholds { min_assemblies: 2, max_cluster_distance: None,
+                                        max_length_diff: None, min_cluster_len: Some(60),
+                                        max_cluster_len: Some(85) };
+        let qc_results = qc_clusters(&root, &mut sequences, &distances, &vec![5, 6], &[], 0.3,
+                                     &thresholds);
+        let qc_1 = &qc_results[&sequences[0].cluster];
+        let qc_2 = &qc_results[&sequences[2].cluster];
+        assert_eq!(qc_1.failed_rules, vec!["max_cluster_len"]);
+        assert_eq!(qc_1.failure_reasons, vec!["length (90 bp) above maximum (85 bp)"]);
+        assert_eq!(qc_2.failed_rules, vec!["min_cluster_len"]);
+        assert_eq!(cluster_median_length(sequences[2].cluster, &sequences), 50);
+    }
+
+    #[test]
+    fn test_cluster_length_diff() {
+        let mut sequences = vec![Sequence::new_with_seq(1, "A".to_string(), "a".to_string(), "a".to_string(), 100, 1),
+                                 Sequence::new_with_seq(2, "A".to_string(), "b".to_string(), "b".to_string(), 75, 1),
+                                 Sequence::new_with_seq(3, "A".to_string(), "c".to_string(), "c".to_string(), 10, 1)];
+        sequences[0].cluster = 1; sequences[1].cluster = 1; sequences[2].cluster = 2;
+        assert_almost_eq(cluster_length_diff(1, &sequences), 0.25, 1e-8);
+        assert_almost_eq(cluster_length_diff(2, &sequences), 0.0, 1e-8);
+        assert_almost_eq(cluster_length_diff(3, &sequences), 0.0, 1e-8);
+    }
+
+    #[test]
+    fn test_html_report() {
+        let mut sequences = vec![Sequence::new_with_seq(1, "A".to_string(), "a".to_string(), "a".to_string(), 1, 1),
+                                 Sequence::new_with_seq(2, "A".to_string(), "b".to_string(), "b".to_string(), 1, 1),
+                                 Sequence::new_with_seq(3, "A".to_string(), "c".to_string(), "c<1>".to_string(), 1, 1),
+                                 Sequence::new_with_seq(4, "A".to_string(), "d".to_string(), "d".to_string(), 1, 1)];
+        let distances = HashMap::from_iter(vec![((1, 1), 0.0), ((1, 2), 0.1), ((1, 3), 0.5), ((1, 4), 0.5),
+                                                ((2, 1), 0.1), ((2, 2), 0.0), ((2, 3), 0.5), ((2, 4), 0.5),
+                                                ((3, 1), 0.5), ((3, 2), 0.5), ((3, 3), 0.0), ((3, 4), 0.2),
+                                                ((4, 1), 0.5), ((4, 2), 0.5), ((4, 3), 0.2), ((4, 4), 0.0)]);
+        let mut root = upgma(&distances, &mut sequences);
+        normalise_tree(&mut root);
+        sequences[0].cluster = 1; sequences[1].cluster = 1;
+        sequences[2].cluster = 2; sequences[3].cluster = 2;
+        let mut qc_results = HashMap::new();
+        qc_results.insert(1, ClusterQC::new(0.1));
+        qc_results.insert(2, ClusterQC::new(0.2));
+        qc_results.get_mut(&2).unwrap().add_failure("min_assemblies", "present in too few assemblies".to_string());
+        let html = html_report(&root, &sequences, &distances, &qc_results, 0.3, "graph");
+        assert!(html.starts_with("<!DOCTYPE html>"));
+        assert_eq!(html.matches("<svg").count(), 2);
+        assert_eq!(html.matches("<rect").count(), 16);
+        assert!(html.contains("c&lt;1&gt;"));
+        assert!(!html.contains("c<1>"));
+        assert!(html.contains("<td class=\"pass\">pass</td>"));
+        assert!(html.contains("<td class=\"fail\">fail</td>"));
+        assert!(html.contains("present in too few assemblies"));
+        assert!(html.contains(">7</text>"));
+    }
+
+    #[test]
+    fn test_html_escape() {
+        assert_eq!(html_escape("contig_1"), "contig_1");
+        assert_eq!(html_escape("a<b>&\"c\""), "a&lt;b&gt;&amp;&quot;c&quot;");
+    }
+
+    #[test]
+    fn test_heatmap_colour() {
+        assert_eq!(heatmap_colour(0.0, 0.2), "#08306b");
+        assert_eq!(heatmap_colour(0.2, 0.2), "#ffffff");
+        assert_eq!(heatmap_colour(0.5, 0.2), "#ffffff");
+    }
+
     #[test]
     fn test_reorder_clusters() {
         let mut sequences = vec![Sequence::new_with_seq(1, "CGCGA".to_string(), "assembly_1.fasta".to_string(), "contig_2".to_string(), 5, 1),
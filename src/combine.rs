@@ -17,22 +17,30 @@ use std::io::Write;
 use std::path::{Path, PathBuf};
 
 use crate::log::{section_header, explanation};
-use crate::metrics::{CombineMetrics, ResolvedClusterDetails};
-use crate::misc::{check_if_file_exists, create_dir};
+use crate::metrics::{CombineMetrics, ResolvedClusterDetails, ResolvedSequenceDetails};
+use crate::misc::{check_gfa_version, check_if_file_exists, create_dir, write_fasta_seq};
+use crate::unitig::Unitig;
 use crate::unitig_graph::UnitigGraph;
 
 
-pub fn combine(autocycler_dir: PathBuf, in_gfas: Vec<PathBuf>) {
+// Thresholds used to classify clusters as chromosomes or plasmids.
+const CHROMOSOME_MIN_LENGTH: u64 = 1_000_000;
+const PLASMID_MIN_RELATIVE_DEPTH: f64 = 0.5;
+
+
+pub fn combine(autocycler_dir: PathBuf, in_gfas: Vec<PathBuf>, gfa_version: u32) {
     let combined_gfa = autocycler_dir.join("consensus_assembly.gfa");
     let combined_fasta = autocycler_dir.join("consensus_assembly.fasta");
     let combined_yaml = autocycler_dir.join("consensus_assembly.yaml");
+    let combined_bed = autocycler_dir.join("consensus_assembly.bed");
+    let combined_bedgraph = autocycler_dir.join("consensus_assembly_support.bedgraph");
 
-    check_settings(&in_gfas);
+    check_settings(&in_gfas, gfa_version);
     if let Some(parent) = combined_gfa.parent() {
         create_dir(parent);
     }
     starting_message();
-    print_settings(&autocycler_dir, &in_gfas);
+    print_settings(&autocycler_dir, &in_gfas, gfa_version);
 
     // TODO: add an optional argument for reads, which will add depth values to the combined
     //       assembly. Find unique k-mers in the combined assembly and then count the occurrences
@@ -43,16 +51,18 @@ pub fn combine(autocycler_dir: PathBuf, in_gfas: Vec<PathBuf>) {
     //       duplication).
 
     let mut metrics = CombineMetrics::default();
-    combine_clusters(&in_gfas, &combined_gfa, &combined_fasta, &mut metrics);
+    combine_clusters(&in_gfas, &combined_gfa, &combined_fasta, &combined_bed, &combined_bedgraph,
+                     gfa_version, &mut metrics);
     metrics.save_to_yaml(&combined_yaml);
-    finished_message(&combined_gfa, &combined_fasta, &metrics);
+    finished_message(&combined_gfa, &combined_fasta, &combined_bed, &combined_bedgraph, &metrics);
 }
 
 
-fn check_settings(in_gfas: &Vec<PathBuf>) {
+fn check_settings(in_gfas: &Vec<PathBuf>, gfa_version: u32) {
     for gfa in in_gfas {
         check_if_file_exists(gfa);
     }
+    check_gfa_version(gfa_version);
 }
 
 
@@ -62,21 +72,25 @@ fn starting_message() {
 }
 
 
-fn print_settings(autocycler_dir: &Path, in_gfas: &[PathBuf]) {
+fn print_settings(autocycler_dir: &Path, in_gfas: &[PathBuf], gfa_version: u32) {
     eprintln!("Settings:");
     eprintln!("  --autocycler_dir {}", autocycler_dir.display());
     eprintln!("  --in_gfas {}", in_gfas[0].display());
     for gfa in &in_gfas[1..] {
         eprintln!("            {}", gfa.display());
     }
+    eprintln!("  --gfa_version {}", gfa_version);
     eprintln!();
 }
 
 
-fn finished_message(combined_gfa: &Path, combined_fasta: &Path, metrics: &CombineMetrics) {
+fn finished_message(combined_gfa: &Path, combined_fasta: &Path, combined_bed: &Path,
+                    combined_bedgraph: &Path, metrics: &CombineMetrics) {
     section_header("Finished!");
     eprintln!("Combined graph: {}", combined_gfa.display());
     eprintln!("Combined fasta: {}", combined_fasta.display());
+    eprintln!("Confidence BED: {}", combined_bed.display());
+    eprintln!("Support track:  {}", combined_bedgraph.display());
     eprintln!();
     if metrics.consensus_assembly_fully_resolved {
         eprintln!("{}", "Consensus assembly is fully resolved 😄".green().bold());
@@ -87,35 +101,64 @@ fn finished_message(combined_gfa: &Path, combined_fasta: &Path, metrics: &Combin
 }
 
 
-fn combine_clusters(in_gfas: &Vec<PathBuf>, combined_gfa: &Path, combined_fasta: &Path,
+fn combine_clusters(in_gfas: &[PathBuf], combined_gfa: &Path, combined_fasta: &Path,
+                    combined_bed: &Path, combined_bedgraph: &Path, gfa_version: u32,
                     metrics: &mut CombineMetrics) {
     section_header("Combining clusters");
     explanation("This command combines different clusters into a single assembly file.");
     let mut gfa_file = File::create(combined_gfa).unwrap();
     let mut fasta_file = File::create(combined_fasta).unwrap();
-    writeln!(gfa_file, "H\tVN:Z:1.0").unwrap();
+    let mut bed_file = File::create(combined_bed).unwrap();
+    let mut bedgraph_file = File::create(combined_bedgraph).unwrap();
+    writeln!(bedgraph_file, "track type=bedGraph name=assembly_support \
+                             description=\"Input assemblies agreeing with each base\"").unwrap();
+    writeln!(gfa_file, "H\tVN:Z:{}.0", gfa_version).unwrap();
     metrics.consensus_assembly_fully_resolved = true;
+    let graphs: Vec<UnitigGraph> = in_gfas.iter().map(|gfa| UnitigGraph::from_gfa_file(gfa).0)
+                                          .collect();
+    let reference_depth = reference_depth(&graphs);
     let mut offset = 0;
-    for gfa in in_gfas {
+    for (gfa, graph) in in_gfas.iter().zip(&graphs) {
         eprintln!("{}", gfa.display());
-        let (graph, _) = UnitigGraph::from_gfa_file(gfa);
+        let classification = classify_cluster(graph, reference_depth);
+        eprintln!("classification: {}", classification);
         graph.print_basic_graph_info();
-        for unitig in &graph.unitigs {
-            let unitig = unitig.borrow();
+        for (handle, unitig) in graph.unitigs.iter().enumerate() {
             let unitig_num = unitig.number + offset;
             let unitig_seq = String::from_utf8_lossy(&unitig.forward_seq);
-            let circ = if unitig.is_isolated_and_circular() { " circular=true".to_string() }
-                                                       else { "".to_string() };
+            let circular = verify_circularity(unitig, handle, unitig_num);
             let mut colour_tag = unitig.colour_tag();
             if colour_tag.is_empty() {
                 colour_tag = "\tCL:z:orangered".to_string();
             }
-            writeln!(gfa_file, "S\t{}\t{}{}", unitig_num, unitig_seq, colour_tag).unwrap();
-            writeln!(fasta_file, ">{} length={}{}", unitig_num, unitig.length(), circ).unwrap();
-            writeln!(fasta_file, "{}", unitig_seq).unwrap();
+            if gfa_version == 2 {
+                writeln!(gfa_file, "S\t{}\t{}\t{}{}\tRT:Z:{}", unitig_num, unitig.length(),
+                         unitig_seq, colour_tag, classification).unwrap();
+            } else {
+                writeln!(gfa_file, "S\t{}\t{}{}\tRT:Z:{}", unitig_num, unitig_seq, colour_tag,
+                         classification).unwrap();
+            }
+            writeln!(fasta_file, ">{} length={} circular={}", unitig_num, unitig.length(),
+                     circular).unwrap();
+            metrics.consensus_assembly_sequences.push(
+                ResolvedSequenceDetails { name: unitig_num.to_string(),
+                                          length: unitig.length() as u64, circular });
+            write_fasta_seq(&mut fasta_file, &unitig_seq, 0).unwrap();
         }
-        for (a, a_strand, b, b_strand) in &graph.get_links_for_gfa(offset) {
-            writeln!(gfa_file, "L\t{}\t{}\t{}\t{}\t0M", a, a_strand, b, b_strand).unwrap();
+        if gfa_version == 2 {
+            for edge_line in &graph.get_gfa2_edge_lines(offset) {
+                writeln!(gfa_file, "{}", edge_line).unwrap();
+            }
+        } else {
+            for (a, a_strand, b, b_strand) in &graph.get_links_for_gfa(offset) {
+                writeln!(gfa_file, "L\t{}\t{}\t{}\t{}\t0M", a, a_strand, b, b_strand).unwrap();
+            }
+        }
+        for bed_line in &graph.get_confidence_bed_lines(offset) {
+            writeln!(bed_file, "{}", bed_line).unwrap();
+        }
+        for bedgraph_line in &graph.get_support_bedgraph_lines(offset) {
+            writeln!(bedgraph_file, "{}", bedgraph_line).unwrap();
         }
         offset += graph.max_unitig_number();
         let component_length = graph.total_length();
@@ -124,8 +167,119 @@ fn combine_clusters(in_gfas: &Vec<PathBuf>, combined_gfa: &Path, combined_fasta:
         metrics.consensus_assembly_unitigs += unitig_count;
         let cluster_metrics = ResolvedClusterDetails { length: component_length,
                                                        unitigs: unitig_count,
-                                                       topology: graph.topology() };
+                                                       topology: graph.topology(),
+                                                       classification: classification.to_string() };
         metrics.consensus_assembly_clusters.push(cluster_metrics);
         if unitig_count > 1 { metrics.consensus_assembly_fully_resolved = false; }
     }
 }
+
+
+fn verify_circularity(unitig: &Unitig, handle: usize, unitig_num: u32) -> bool {
+    // Returns whether the unitig is a circular sequence with a clean end-join. A unitig which
+    // looks circular on its forward strand but fails the stricter check gets a warning, since that
+    // points to an inconsistent graph.
+    let circular = unitig.has_clean_circular_join(handle);
+    if !circular && unitig.is_isolated_and_circular(handle) {
+        eprintln!("Warning: unitig {} has an inconsistent circularising link, so it is not \
+                   marked as circular", unitig_num);
+    }
+    circular
+}
+
+
+fn classify_cluster(graph: &UnitigGraph, reference_depth: f64) -> &'static str {
+    // Classifies a cluster as a chromosome, plasmid or uncertain. Only clusters which resolved to
+    // a single circular sequence are classified: large ones are chromosomes, and small ones are
+    // plasmids if their depth (relative to the largest cluster) isn't too low.
+    let components = graph.connected_components();
+    let circular = components.len() == 1 && graph.component_is_circular_loop(&components[0]);
+    if !circular {
+        return "uncertain";
+    }
+    let relative_depth = if reference_depth > 0.0 { mean_depth(graph) / reference_depth }
+                                              else { 1.0 };
+    if graph.total_length() >= CHROMOSOME_MIN_LENGTH {
+        "chromosome"
+    } else if relative_depth >= PLASMID_MIN_RELATIVE_DEPTH {
+        "plasmid"
+    } else {
+        "uncertain"
+    }
+}
+
+
+fn reference_depth(graphs: &[UnitigGraph]) -> f64 {
+    // Returns the depth of the largest cluster, which other clusters' depths are compared to.
+    graphs.iter().max_by_key(|g| g.total_length()).map_or(0.0, mean_depth)
+}
+
+
+fn mean_depth(graph: &UnitigGraph) -> f64 {
+    // Returns the length-weighted mean depth of the graph's unitigs.
+    let total_length = graph.total_length();
+    if total_length == 0 {
+        return 0.0;
+    }
+    let weighted_sum: f64 = graph.unitigs.iter()
+        .map(|u| u.depth * u.length() as f64).sum();
+    weighted_sum / total_length as f64
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn graph(lines: &[&str]) -> UnitigGraph {
+        let lines: Vec<String> = lines.iter().map(|l| l.to_string()).collect();
+        UnitigGraph::from_gfa_lines(&lines).0
+    }
+
+    #[test]
+    fn test_classify_cluster() {
+        let circular = graph(&["S\t1\tACGATCAGCTACGACTACG\tDP:f:4",
+                               "L\t1\t+\t1\t+\t0M", "L\t1\t-\t1\t-\t0M"]);
+        let low_depth = graph(&["S\t1\tACGATCAGCTACGACTACGACTAG\tDP:f:1",
+                                "L\t1\t+\t1\t+\t0M", "L\t1\t-\t1\t-\t0M"]);
+        let linear = graph(&["S\t1\tACGATCAGCTACGACTACG\tDP:f:4"]);
+        let fragmented = graph(&["S\t1\tACGATCAGCT\tDP:f:4", "S\t2\tACGACTACG\tDP:f:4",
+                                 "L\t1\t+\t2\t+\t0M", "L\t2\t-\t1\t-\t0M",
+                                 "L\t2\t+\t1\t+\t0M", "L\t1\t-\t2\t-\t0M",
+                                 "L\t1\t+\t1\t+\t0M", "L\t1\t-\t1\t-\t0M"]);
+        assert_eq!(mean_depth(&circular), 4.0);
+        assert_eq!(classify_cluster(&circular, 4.0), "plasmid");
+        assert_eq!(classify_cluster(&low_depth, 4.0), "uncertain");
+        assert_eq!(classify_cluster(&linear, 4.0), "uncertain");
+        assert_eq!(classify_cluster(&fragmented, 4.0), "uncertain");
+        assert_eq!(reference_depth(&[circular, low_depth]), 1.0);
+        assert_eq!(reference_depth(&[]), 0.0);
+    }
+
+    #[test]
+    fn test_verify_circularity() {
+        let circular = graph(&["S\t1\tACGATCAGCTACGACTACG\tDP:f:4",
+                               "L\t1\t+\t1\t+\t0M", "L\t1\t-\t1\t-\t0M"]);
+        let linear = graph(&["S\t1\tACGATCAGCTACGACTACG\tDP:f:4"]);
+        let hairpin = graph(&["S\t1\tACGATCAGCTACGACTACG\tDP:f:4", "L\t1\t+\t1\t+\t0M",
+                              "L\t1\t-\t1\t-\t0M", "L\t1\t+\t1\t-\t0M"]);
+        assert!(verify_circularity(&circular.unitigs[0], 0, 1));
+        assert!(!verify_circularity(&linear.unitigs[0], 0, 1));
+        assert!(!verify_circularity(&hairpin.unitigs[0], 0, 1));
+    }
+
+    #[test]
+    fn test_support_bedgraph() {
+        // Four assemblies: bases 0-10 have full support, 10-12 were only in three of them, and
+        // 12-24 (two adjacent regions with full support) are joined. The counts come from the
+        // header's assembly count, not the depth of the unresolved repeat (unitig 2).
+        let segments = ["S\t1\tACGATCAGCTACGACTACGATCAG\tDP:f:4\t\
+                         CF:Z:0-10:1.00:1.00,10-12:0.75:1.00,12-20:1.00:0.90,20-24:1.00:1.00",
+                        "S\t2\tACGATCAGCT\tDP:f:8"];
+        let g = graph(&[&["H\tVN:Z:1.0\tKM:i:3\tAC:i:4"], &segments[..]].concat());
+        assert_eq!(g.get_support_bedgraph_lines(10), vec!["11\t0\t10\t4", "11\t10\t12\t3",
+                                                          "11\t12\t24\t4"]);
+        let g = graph(&[&["H\tVN:Z:1.0\tKM:i:3"], &segments[..]].concat());
+        assert!(g.get_support_bedgraph_lines(10).is_empty());
+    }
+}
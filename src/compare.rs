@@ -0,0 +1,396 @@
+// This file contains the code for the autocycler compare subcommand.
+
+// Copyright 2024 Ryan Wick (rrwick@gmail.com)
+// https://github.com/rrwick/Autocycler
+
+// This file is part of Autocycler. Autocycler is free software: you can redistribute it and/or
+// modify it under the terms of the GNU General Public License as published by the Free Software
+// Foundation, either version 3 of the License, or (at your option) any later version. Autocycler
+// is distributed in the hope that it will be useful, but WITHOUT ANY WARRANTY; without even the
+// implied warranty of MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the GNU General
+// Public License for more details. You should have received a copy of the GNU General Public
+// License along with Autocycler. If not, see <http://www.gnu.org/licenses/>.
+
+use std::collections::{HashMap, HashSet};
+use std::path::{Path, PathBuf};
+
+use crate::compress::sequence_end_repair;
+use crate::graph_simplification::simplify_structure;
+use crate::kmer_graph::KmerGraph;
+use crate::log::{section_header, explanation};
+use crate::metrics::SimplificationMetrics;
+use crate::misc::{check_if_file_exists, edit_distance, load_assembly, quit_with_error,
+                  reverse_path};
+use crate::sequence::Sequence;
+use crate::unitig_graph::UnitigGraph;
+
+
+// Differences between two replicons which are no longer than this (in both assemblies) are
+// counted as SNPs and indels. Longer differences are counted as structural.
+const MAX_SMALL_DIFF_LEN: usize = 1000;
+
+
+pub fn compare(assembly_a: PathBuf, assembly_b: PathBuf, k_size: u32, out_gfa: Option<PathBuf>) {
+    check_settings(&assembly_a, &assembly_b, k_size);
+    starting_message();
+    print_settings(&assembly_a, &assembly_b, k_size, &out_gfa);
+    let (graph, sequences, a_count) = build_joint_graph(&assembly_a, &assembly_b, k_size);
+    if let Some(out_gfa) = &out_gfa {
+        if let Err(e) = graph.save_gfa(out_gfa, &sequences) {
+            quit_with_error(&format!("unable to write {}\n{}", out_gfa.display(), e));
+        }
+    }
+    let (summary, replicons) = compare_assemblies(&graph, &sequences, a_count);
+    print_summary(&summary);
+    print_replicons(&replicons);
+}
+
+
+fn check_settings(assembly_a: &Path, assembly_b: &Path, k_size: u32) {
+    check_if_file_exists(assembly_a);
+    check_if_file_exists(assembly_b);
+    if k_size < 11 { quit_with_error("--kmer cannot be less than 11"); }
+    if k_size > 501 { quit_with_error("--kmer cannot be greater than 501"); }
+}
+
+
+fn starting_message() {
+    section_header("Starting autocycler compare");
+    explanation("This command compares two assemblies (e.g. consensus assemblies from different \
+                 tools or replicate runs) by building a joint unitig graph of both. It reports \
+                 how much sequence is shared and, for each replicon, the SNPs, indels and \
+                 structural differences between the assemblies.");
+}
+
+
+fn print_settings(assembly_a: &Path, assembly_b: &Path, k_size: u32, out_gfa: &Option<PathBuf>) {
+    eprintln!("Settings:");
+    eprintln!("  --assembly_a {}", assembly_a.display());
+    eprintln!("  --assembly_b {}", assembly_b.display());
+    eprintln!("  --kmer {}", k_size);
+    if let Some(out_gfa) = out_gfa {
+        eprintln!("  --out_gfa {}", out_gfa.display());
+    }
+    eprintln!();
+}
+
+
+pub fn build_joint_graph(assembly_a: &Path, assembly_b: &Path, k_size: u32)
+        -> (UnitigGraph, Vec<Sequence>, usize) {
+    // Returns the graph, its sequences (assembly A's first) and the number of A sequences.
+    section_header("Building joint graph");
+    explanation("Both assemblies are now loaded and combined into one unitig graph, where \
+                 sequence shared by the assemblies is in shared unitigs.");
+    let mut sequences = load_sequences(assembly_a, k_size, 0);
+    let a_count = sequences.len();
+    sequences.extend(load_sequences(assembly_b, k_size, a_count));
+    if a_count == 0 || sequences.len() == a_count {
+        quit_with_error("both assemblies must contain at least one contig");
+    }
+    let (graph, sequences) = joint_graph(sequences, k_size);
+    (graph, sequences, a_count)
+}
+
+
+pub fn joint_graph(mut sequences: Vec<Sequence>, k_size: u32) -> (UnitigGraph, Vec<Sequence>) {
+    // Builds a simplified unitig graph from sequences of more than one assembly. Sequence ends are
+    // repaired first (as in compress), so where one assembly's ends are joined isn't counted as
+    // sequence unique to the other.
+    sequence_end_repair(&mut sequences, k_size);
+    let mut kmer_graph = KmerGraph::new(k_size, rayon::current_num_threads());
+    kmer_graph.add_sequences(&sequences, 2);
+    let mut graph = UnitigGraph::from_kmer_graph(&kmer_graph);
+    simplify_structure(&mut graph, &mut sequences, 0, 0.0, &mut SimplificationMetrics::default());
+    graph.print_basic_graph_info();
+    (graph, sequences)
+}
+
+
+fn load_sequences(filename: &Path, k_size: u32, first_id: usize) -> Vec<Sequence> {
+    let (contigs, _) = load_assembly(filename, false, true);
+    let file_name = filename.file_name().unwrap().to_string_lossy().into_owned();
+    contigs_to_sequences(contigs, &file_name, k_size, first_id)
+}
+
+
+pub fn contigs_to_sequences(contigs: Vec<(String, String, String)>, file_name: &str, k_size: u32,
+                            first_id: usize) -> Vec<Sequence> {
+    // Makes Sequence objects (with IDs following first_id) for building a joint graph. Contigs
+    // shorter than k are skipped because they have no k-mers.
+    let mut sequences = Vec::new();
+    for (name, header, seq) in contigs {
+        if seq.len() < k_size as usize {
+            eprintln!("  {} {} ({} bp) skipped: shorter than k", file_name, name, seq.len());
+            continue;
+        }
+        let id = first_id + sequences.len() + 1;
+        let contig_header = header.split_whitespace().collect::<Vec<&str>>().join(" ");
+        let length = seq.len();
+        sequences.push(Sequence::new_with_seq(id, seq.to_ascii_uppercase(), file_name.to_string(),
+                                              contig_header, length, k_size / 2));
+    }
+    sequences
+}
+
+
+#[derive(Debug, Default, PartialEq)]
+pub struct ComparisonSummary {
+    pub shared_bp: u64,
+    pub a_only_bp: u64,
+    pub b_only_bp: u64,
+}
+
+
+#[derive(Debug, Default, PartialEq)]
+pub struct RepliconComparison {
+    // One row per replicon in assembly A, paired with the replicon in assembly B which shares
+    // the most sequence with it, plus a row for each B replicon which was not paired. aligned_bp
+    // counts the A bases in anchors and in the small differences between them.
+    pub a_name: Option<String>,
+    pub a_length: usize,
+    pub b_name: Option<String>,
+    pub b_length: usize,
+    pub shared_bp: u64,
+    pub aligned_bp: u64,
+    pub snps: usize,
+    pub indels: usize,
+    pub structural: usize,
+}
+
+
+pub fn compare_assemblies(graph: &UnitigGraph, sequences: &[Sequence], a_count: usize)
+        -> (ComparisonSummary, Vec<RepliconComparison>) {
+    let paths = graph.get_unitig_paths_for_sequences_i32(sequences);
+    let unitig_length = |n: u32| graph.unitig(n).length() as u64;
+    let unitig_set = |paths: &[Vec<i32>]| -> HashSet<u32> {
+        paths.iter().flatten().map(|n| n.unsigned_abs()).collect()
+    };
+    let a_unitigs = unitig_set(&paths[..a_count]);
+    let b_unitigs = unitig_set(&paths[a_count..]);
+    let summary = ComparisonSummary {
+        shared_bp: a_unitigs.intersection(&b_unitigs).map(|&n| unitig_length(n)).sum(),
+        a_only_bp: a_unitigs.difference(&b_unitigs).map(|&n| unitig_length(n)).sum(),
+        b_only_bp: b_unitigs.difference(&a_unitigs).map(|&n| unitig_length(n)).sum(),
+    };
+
+    let mut replicons = Vec::new();
+    let mut paired_b = HashSet::new();
+    for a in 0..a_count {
+        let a_set = unitig_set(&paths[a..a + 1]);
+        let best_b = (a_count..sequences.len()).map(|b| {
+            let b_set = unitig_set(&paths[b..b + 1]);
+            (b, a_set.intersection(&b_set).map(|&n| unitig_length(n)).sum::<u64>())
+        }).filter(|&(_, shared)| shared > 0).max_by_key(|&(b, shared)| (shared, usize::MAX - b));
+        let mut replicon = RepliconComparison { a_name: Some(sequences[a].contig_name()),
+                                                a_length: sequences[a].length,
+                                                ..Default::default() };
+        if let Some((b, shared_bp)) = best_b {
+            paired_b.insert(b);
+            replicon.b_name = Some(sequences[b].contig_name());
+            replicon.b_length = sequences[b].length;
+            replicon.shared_bp = shared_bp;
+            let circular = is_circular(&sequences[a]) && is_circular(&sequences[b]);
+            compare_paths(graph, &paths[a], &paths[b], circular, &mut replicon);
+        }
+        replicons.push(replicon);
+    }
+    for b in (a_count..sequences.len()).filter(|b| !paired_b.contains(b)) {
+        replicons.push(RepliconComparison { b_name: Some(sequences[b].contig_name()),
+                                            b_length: sequences[b].length,
+                                            ..Default::default() });
+    }
+    (summary, replicons)
+}
+
+
+fn is_circular(seq: &Sequence) -> bool {
+    seq.contig_header.split_whitespace().any(|t| t == "circular=true")
+}
+
+
+fn compare_paths(graph: &UnitigGraph, a_path: &[i32], b_path: &[i32], circular: bool,
+                 replicon: &mut RepliconComparison) {
+    // Anchors are unitigs which occur exactly once in both paths. B's path is flipped if most
+    // anchors are on the opposite strand. Then, for each pair of consecutive anchors in A, the
+    // same two anchors should also be consecutive in B, and the sequences between them are
+    // compared. If they aren't consecutive in B, that's a structural difference. For circular
+    // replicons, the anchors wrap around, so a different starting position isn't a difference.
+    let a_counts = unitig_counts(a_path);
+    let b_counts = unitig_counts(b_path);
+    let is_anchor = |n: i32| a_counts.get(&n.unsigned_abs()) == Some(&1) &&
+                             b_counts.get(&n.unsigned_abs()) == Some(&1);
+    let same_strand = |n: i32| b_path.contains(&n);
+    let a_anchors: Vec<i32> = a_path.iter().copied().filter(|&n| is_anchor(n)).collect();
+    let flipped = a_anchors.iter().filter(|&&n| !same_strand(n)).count() * 2 > a_anchors.len();
+    let b_path = if flipped { reverse_path(b_path) } else { b_path.to_vec() };
+
+    let anchor_indices = |path: &[i32]| -> Vec<usize> {
+        (0..path.len()).filter(|&i| is_anchor(path[i]) && a_path.contains(&path[i]) &&
+                                    b_path.contains(&path[i])).collect()
+    };
+    let a_indices = anchor_indices(a_path);
+    let b_indices = anchor_indices(&b_path);
+    if a_indices.is_empty() { return; }
+    let b_next: HashMap<i32, usize> = consecutive_pairs(&b_indices, circular).into_iter()
+        .map(|(i, j)| (b_path[i], j)).collect();
+    let b_index: HashMap<i32, usize> = b_indices.iter().map(|&i| (b_path[i], i)).collect();
+
+    replicon.aligned_bp = a_indices.iter()
+        .map(|&i| graph.unitig(a_path[i].unsigned_abs()).length() as u64).sum();
+    for (i, j) in consecutive_pairs(&a_indices, circular) {
+        let (x, y) = (a_path[i], a_path[j]);
+        if b_next.get(&x).map(|&k| b_path[k]) != Some(y) {
+            replicon.structural += 1;
+            continue;
+        }
+        let a_seq = graph.get_sequence_from_path_signed(&between(a_path, i, j));
+        let b_seq = graph.get_sequence_from_path_signed(&between(&b_path, b_index[&x],
+                                                                 b_index[&y]));
+        if count_differences(&a_seq, &b_seq, replicon) {
+            replicon.aligned_bp += a_seq.len() as u64;
+        }
+    }
+}
+
+
+pub fn unitig_counts(path: &[i32]) -> HashMap<u32, usize> {
+    let mut counts = HashMap::new();
+    for n in path {
+        *counts.entry(n.unsigned_abs()).or_insert(0) += 1;
+    }
+    counts
+}
+
+
+fn consecutive_pairs(indices: &[usize], circular: bool) -> Vec<(usize, usize)> {
+    let mut pairs: Vec<(usize, usize)> = indices.windows(2).map(|w| (w[0], w[1])).collect();
+    if circular && !indices.is_empty() {
+        pairs.push((indices[indices.len() - 1], indices[0]));
+    }
+    pairs
+}
+
+
+fn between(path: &[i32], i: usize, j: usize) -> Vec<i32> {
+    // Returns the part of the path strictly between indices i and j, wrapping around the end of
+    // the path if j is not after i.
+    if j > i { path[i + 1..j].to_vec() }
+    else { path[i + 1..].iter().chain(&path[..j]).copied().collect() }
+}
+
+
+fn count_differences(a: &[u8], b: &[u8], replicon: &mut RepliconComparison) -> bool {
+    // Equal-length differences are counted as substitutions. Otherwise there is one indel, and
+    // any edits beyond the length difference are counted as substitutions. Returns false if the
+    // sequences were too different to align (a structural difference).
+    if a == b { return true; }
+    if a.len().max(b.len()) > MAX_SMALL_DIFF_LEN {
+        replicon.structural += 1;
+        return false;
+    } else if a.len() == b.len() {
+        replicon.snps += a.iter().zip(b).filter(|(x, y)| x != y).count();
+    } else {
+        let length_diff = a.len().abs_diff(b.len());
+        replicon.indels += 1;
+        replicon.snps += edit_distance(a, b) - length_diff;
+    }
+    true
+}
+
+
+fn print_summary(summary: &ComparisonSummary) {
+    section_header("Comparison");
+    explanation("Shared sequence is in unitigs used by both assemblies. Each replicon in \
+                 assembly A is paired with the replicon in assembly B which shares the most \
+                 sequence with it.");
+    eprintln!("Shared sequence:      {} bp", summary.shared_bp);
+    eprintln!("Only in assembly A:   {} bp", summary.a_only_bp);
+    eprintln!("Only in assembly B:   {} bp", summary.b_only_bp);
+    eprintln!();
+}
+
+
+fn print_replicons(replicons: &[RepliconComparison]) {
+    println!("a_contig\ta_length\tb_contig\tb_length\tshared_bp\tsnps\tindels\tstructural");
+    for r in replicons {
+        println!("{}\t{}\t{}\t{}\t{}\t{}\t{}\t{}", r.a_name.as_deref().unwrap_or("-"), r.a_length,
+                 r.b_name.as_deref().unwrap_or("-"), r.b_length, r.shared_bp, r.snps, r.indels,
+                 r.structural);
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use tempfile::tempdir;
+
+    use super::*;
+    use crate::misc::reverse_complement;
+    use crate::tests::{make_test_file, random_seq};
+
+    fn compare_seqs(a: &str, b: &str) -> (ComparisonSummary, Vec<RepliconComparison>) {
+        let dir = tempdir().unwrap();
+        let (a_file, b_file) = (dir.path().join("a.fasta"), dir.path().join("b.fasta"));
+        make_test_file(&a_file, a);
+        make_test_file(&b_file, b);
+        let (graph, sequences, a_count) = build_joint_graph(&a_file, &b_file, 21);
+        compare_assemblies(&graph, &sequences, a_count)
+    }
+
+    #[test]
+    fn test_count_differences() {
+        let mut r = RepliconComparison::default();
+        assert!(count_differences(b"ACGT", b"ACGT", &mut r));
+        assert!(count_differences(b"ACGT", b"AGGA", &mut r));
+        assert!(count_differences(b"ACGTT", b"ACT", &mut r));
+        assert_eq!((r.snps, r.indels, r.structural), (2, 1, 0));
+        assert!(!count_differences(&[b'A'; 1001], b"A", &mut r));
+        assert_eq!(r.structural, 1);
+    }
+
+    #[test]
+    fn test_between() {
+        let path = vec![1, 2, 3, 4, 5];
+        assert_eq!(between(&path, 1, 3), vec![3]);
+        assert_eq!(between(&path, 3, 1), vec![5, 1]);
+        assert_eq!(between(&path, 2, 2), vec![4, 5, 1, 2]);
+    }
+
+    #[test]
+    fn test_compare_assemblies() {
+        // B has a SNP and a 3-bp deletion relative to A, and also an extra plasmid.
+        let seq = random_seq(3000, 0);
+        let mut snp = seq.clone().into_bytes();
+        snp[1000] = if snp[1000] == b'A' { b'C' } else { b'A' };
+        let snp = String::from_utf8(snp).unwrap();
+        let b_seq = format!("{}{}", &snp[..2000], &snp[2003..]);
+        let plasmid = random_seq(500, 1);
+        let (summary, replicons) = compare_seqs(&format!(">a\n{}\n", seq),
+                                                &format!(">b\n{}\n>p\n{}\n", b_seq, plasmid));
+        assert!(summary.shared_bp > 2900);
+        assert!(summary.b_only_bp > 500 && summary.b_only_bp < 510);
+        assert_eq!(replicons.len(), 2);
+        assert_eq!(replicons[0].b_name.as_deref(), Some("b"));
+        assert_eq!((replicons[0].snps, replicons[0].indels, replicons[0].structural), (1, 1, 0));
+        assert_eq!(replicons[0].aligned_bp, 3000);
+        assert_eq!(replicons[1].a_name, None);
+        assert_eq!(replicons[1].b_name.as_deref(), Some("p"));
+    }
+
+    #[test]
+    fn test_compare_assemblies_rotated() {
+        // A rotated and reverse-complemented circular replicon has no differences, but the same
+        // sequences without circular=true have a structural difference where A's start is. End
+        // repair means neither assembly has unique sequence where the other's ends are joined.
+        let seq = random_seq(3000, 2);
+        let rotated = format!("{}{}", &seq[1200..], &seq[..1200]);
+        let rotated = String::from_utf8(reverse_complement(rotated.as_bytes())).unwrap();
+        let (summary, replicons) = compare_seqs(&format!(">a circular=true\n{}\n", seq),
+                                                &format!(">b circular=true\n{}\n", rotated));
+        assert_eq!((summary.a_only_bp, summary.b_only_bp), (0, 0));
+        assert_eq!((replicons[0].snps, replicons[0].indels, replicons[0].structural), (0, 0, 0));
+
+        let (_, replicons) = compare_seqs(&format!(">a\n{}\n", seq), &format!(">b\n{}\n", rotated));
+        assert_eq!(replicons[0].structural, 1);
+    }
+}
@@ -11,52 +11,231 @@
 // Public License for more details. You should have received a copy of the GNU General Public
 // License along with Autocycler. If not, see <http://www.gnu.org/licenses/>.
 
+use fxhash::FxHashMap;
 use rayon::prelude::*;
 use rayon::ThreadPoolBuilder;
 use regex::bytes::Regex;
-use std::collections::HashMap;
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, HashMap};
+use std::fs;
+use std::fs::File;
+use std::io::{self, BufWriter, Write};
 use std::path::{Path, PathBuf};
 use std::str;
 use std::time::Instant;
+use xxhash_rust::xxh3::xxh3_64;
 
 use crate::log::{section_header, explanation};
-use crate::graph_simplification::simplify_structure;
+use crate::graph_simplification::{collapse_near_identical_unitigs, pop_bubbles,
+                                  simplify_structure, unroll_loops, LoopCandidate};
 use crate::kmer_graph::KmerGraph;
-use crate::misc::{check_if_dir_exists, check_if_dir_is_not_dir, create_dir, find_all_assemblies,
-                  load_fasta, format_duration, spinner, quit_with_error, reverse_complement};
-use crate::metrics::{InputAssemblyMetrics, InputAssemblyDetails, InputContigDetails};
-use crate::sequence::Sequence;
+use crate::misc::{check_gfa_version, check_if_dir_exists, check_if_dir_is_not_dir, create_dir,
+                  fasta_line_widths, find_all_assemblies, load_assembly, load_file_lines,
+                  format_duration, format_float, spinner, quit_with_error, reverse_complement,
+                  write_fasta_seq};
+use crate::minimizer_graph::MinimizerGraph;
+use crate::metrics::{InputAssemblyMetrics, InputAssemblyDetails, InputContigDetails,
+                     SimplificationMetrics};
+use crate::sequence::{Duplicate, Sequence};
 use crate::unitig_graph::UnitigGraph;
 
 
 
-pub fn compress(assemblies_dir: PathBuf, autocycler_dir: PathBuf, k_size: u32, threads: usize) {
+pub struct CompressSettings {
+    // The settings for autocycler compress, named as their options.
+    pub assemblies_dir: PathBuf,
+    pub autocycler_dir: PathBuf,
+    pub kmer: u32,
+    pub threads: usize,
+    pub gfa_version: u32,
+    pub canonical_kmers: bool,
+    pub minimizer_window: Option<usize>,
+    pub min_kmer_count: usize,
+    pub min_shared_kmers: f64,
+    pub kmer_histogram: Option<PathBuf>,
+    pub gfa_paths: bool,
+    pub min_contig_len: usize,
+    pub exclude_contigs: Option<String>,
+    pub dedup_contigs: bool,
+    pub rename_duplicates: bool,
+    pub weights: Option<PathBuf>,
+    pub max_unitig_diff: usize,
+    pub max_bubble_len: u32,
+    pub max_bubble_div: f64,
+    pub bubble_poa: bool,
+    pub max_loop_len: u32,
+    pub max_tip_len: u32,
+    pub min_tip_depth: f64,
+    pub resume: bool,
+}
+
+impl CompressSettings {
+    pub fn new(assemblies_dir: PathBuf, autocycler_dir: PathBuf, kmer: u32, threads: usize,
+               gfa_version: u32) -> Self {
+        // Makes settings with the optional steps turned off, i.e. the option defaults.
+        CompressSettings {
+            assemblies_dir, autocycler_dir, kmer, threads, gfa_version,
+            canonical_kmers: false, minimizer_window: None, min_kmer_count: 1,
+            min_shared_kmers: 0.0, kmer_histogram: None, gfa_paths: false, min_contig_len: 0,
+            exclude_contigs: None, dedup_contigs: false, rename_duplicates: false, weights: None,
+            max_unitig_diff: 0, max_bubble_len: 0, max_bubble_div: 0.05, bubble_poa: false,
+            max_loop_len: 0, max_tip_len: 0, min_tip_depth: 2.0, resume: false,
+        }
+    }
+}
+
+
+#[derive(Default)]
+pub struct LoadSettings {
+    // The settings which control how input assemblies are loaded.
+    pub k_size: u32,
+    pub gfa_paths: bool,
+    pub min_contig_len: usize,
+    pub exclude_contigs: Option<Regex>,
+    pub dedup_contigs: bool,
+    pub rename_duplicates: bool,
+}
+
+
+pub fn compress(settings: CompressSettings) {
     let start_time = Instant::now();
-    check_settings(&assemblies_dir, &autocycler_dir, k_size, threads);
+    let exclude_contigs = check_settings(&settings);
+    let assembly_weights = settings.weights.as_ref().map(|w| load_weights(w));
     starting_message();
-    print_settings(&assemblies_dir, &autocycler_dir, k_size, threads);
+    print_settings(&settings);
+    let CompressSettings { assemblies_dir, autocycler_dir, kmer: k_size, threads, gfa_version,
+                           canonical_kmers, minimizer_window, min_kmer_count, min_shared_kmers,
+                           kmer_histogram, gfa_paths, min_contig_len, dedup_contigs,
+                           rename_duplicates,
+                           max_unitig_diff, max_bubble_len, max_bubble_div, bubble_poa,
+                           max_loop_len, max_tip_len, min_tip_depth, resume,
+                           .. } = settings;
     create_dir(&autocycler_dir);
-    let mut metrics = InputAssemblyMetrics::default();
-    let (sequences, assembly_count) = load_sequences(&assemblies_dir, k_size, &mut metrics);
-    let kmer_graph = build_kmer_graph(k_size, assembly_count, &sequences);
-    let mut unitig_graph = build_unitig_graph(kmer_graph);
-    simplify_unitig_graph(&mut unitig_graph, &sequences);
+    let checkpoint_settings = CheckpointSettings {
+        kmer: k_size, canonical_kmers, minimizer_window, min_kmer_count, min_shared_kmers,
+        gfa_paths, min_contig_len,
+        exclude_contigs: exclude_contigs.as_ref().map(|r| r.as_str().to_string()),
+        dedup_contigs, rename_duplicates };
+    let checkpoint_inputs = CheckpointInputs::new(&assemblies_dir);
+    let checkpoint = if resume {
+        load_checkpoint(&autocycler_dir, &checkpoint_settings, &checkpoint_inputs)
+    } else {
+        None
+    };
+    let (mut unitig_graph, mut sequences, mut metrics) = if let Some(checkpoint) = checkpoint {
+        checkpoint
+    } else {
+        delete_checkpoint(&autocycler_dir);
+        let mut metrics = InputAssemblyMetrics::default();
+
+        // Parallel steps use a local thread pool, which (unlike the global pool) can be made with
+        // a different number of threads each time compress is called.
+        let pool = ThreadPoolBuilder::new().num_threads(threads).build().unwrap();
+        let load_settings = LoadSettings { k_size, gfa_paths, min_contig_len, exclude_contigs,
+                                           dedup_contigs, rename_duplicates };
+        let (mut sequences, assembly_count) = pool.install(|| {
+            load_sequences(&assemblies_dir, &load_settings, &mut metrics)
+        });
+        let unitig_graph = if let Some(window) = minimizer_window {
+            let minimizer_graph = build_minimizer_graph(k_size, window, &sequences);
+            build_unitig_graph_from_minimizers(minimizer_graph)
+        } else {
+            let mut kmer_graph = pool.install(|| {
+                build_kmer_graph(k_size, threads, assembly_count, &sequences, canonical_kmers)
+            });
+            if min_shared_kmers > 0.0 {
+                sequences = pool.install(|| {
+                    exclude_unshared_assemblies(&mut kmer_graph, sequences, min_shared_kmers,
+                                                &mut metrics)
+                });
+            }
+            filter_kmer_graph(&mut kmer_graph, min_kmer_count, &mut metrics);
+            build_unitig_graph(kmer_graph, &sequences, min_kmer_count)
+        };
+        let checkpoint = Checkpoint { settings: checkpoint_settings, inputs: checkpoint_inputs,
+                                      metrics };
+        save_checkpoint(&autocycler_dir, &unitig_graph, &sequences, &checkpoint);
+        (unitig_graph, sequences, checkpoint.metrics)
+    };
+    let assembly_count = metrics.input_assembly_details.len();
+    if let Some(assembly_weights) = &assembly_weights {
+        apply_weights(&mut unitig_graph, &mut sequences, assembly_weights);
+    } else if sequences.iter().any(|s| !s.duplicates.is_empty()) {
+        unitig_graph.set_sequence_weights(&sequences);
+    }
+    if max_unitig_diff > 0 {
+        sequences = collapse_unitig_graph_near_identical(&mut unitig_graph, &sequences,
+                                                         max_unitig_diff,
+                                                         &mut metrics.simplification);
+    }
+    if max_bubble_len > 0 {
+        sequences = pop_unitig_graph_bubbles(&mut unitig_graph, &sequences, max_bubble_len,
+                                             max_bubble_div, bubble_poa,
+                                             &mut metrics.simplification);
+    }
+    if max_loop_len > 0 {
+        sequences = unroll_unitig_graph_loops(&mut unitig_graph, &sequences, max_loop_len,
+                                              &autocycler_dir.join("unrolled_loops.fasta"),
+                                              &mut metrics.simplification);
+    }
+    simplify_unitig_graph(&mut unitig_graph, &mut sequences, max_tip_len, min_tip_depth,
+                          &mut metrics.simplification);
+    unitig_graph.estimate_multiplicities(&sequences);
     let out_gfa = autocycler_dir.join("input_assemblies.gfa");
     let out_yaml = autocycler_dir.join("input_assemblies.yaml");
-    unitig_graph.save_gfa(&out_gfa, &sequences).unwrap();
+    unitig_graph.save_gfa_with_version(&out_gfa, &sequences, gfa_version).unwrap();
     save_metrics(&mut metrics, assembly_count, &sequences, &unitig_graph, &out_yaml);
+    if let Some(kmer_histogram) = &kmer_histogram {
+        if let Err(e) = save_kmer_histogram(&metrics.kmer_count_histogram, kmer_histogram) {
+            quit_with_error(&format!("unable to write {}\n{}", kmer_histogram.display(), e));
+        }
+    }
+    delete_checkpoint(&autocycler_dir);
     finished_message(start_time, out_gfa, out_yaml);
 }
 
 
-fn check_settings(assemblies_dir: &Path, autocycler_dir: &Path, k_size: u32, threads: usize) {
+fn check_settings(settings: &CompressSettings) -> Option<Regex> {
+    let CompressSettings { ref assemblies_dir, ref autocycler_dir, kmer: k_size, threads,
+                           gfa_version, canonical_kmers, minimizer_window, min_kmer_count,
+                           min_shared_kmers, ref exclude_contigs, dedup_contigs, max_bubble_div,
+                           min_tip_depth,
+                           .. } = *settings;
     check_if_dir_exists(assemblies_dir);
     check_if_dir_is_not_dir(autocycler_dir);
+    check_gfa_version(gfa_version);
     if k_size < 11   { quit_with_error("--kmer cannot be less than 11"); }
     if k_size > 501  { quit_with_error("--kmer cannot be greater than 501"); }
     if threads < 1   { quit_with_error("--threads cannot be less than 1"); }
     if threads > 100 { quit_with_error("--threads cannot be greater than 100"); }
-    ThreadPoolBuilder::new().num_threads(threads).build_global().unwrap();
+    if min_kmer_count < 1 { quit_with_error("--min_kmer_count cannot be less than 1"); }
+    if min_tip_depth < 0.0 { quit_with_error("--min_tip_depth cannot be negative"); }
+    if !(0.0..=1.0).contains(&min_shared_kmers) {
+        quit_with_error("--min_shared_kmers must be between 0 and 1");
+    }
+    if min_shared_kmers > 0.0 && dedup_contigs {
+        quit_with_error("--min_shared_kmers and --dedup_contigs cannot be used together");
+    }
+    if !(0.0..=1.0).contains(&max_bubble_div) {
+        quit_with_error("--max_bubble_div must be between 0 and 1");
+    }
+    if let Some(window) = minimizer_window {
+        if window < 1 { quit_with_error("--minimizer_window cannot be less than 1"); }
+        if canonical_kmers {
+            quit_with_error("--canonical_kmers and --minimizer_window cannot be used together");
+        }
+        if min_kmer_count > 1 {
+            quit_with_error("--min_kmer_count and --minimizer_window cannot be used together");
+        }
+        if min_shared_kmers > 0.0 {
+            quit_with_error("--min_shared_kmers and --minimizer_window cannot be used together");
+        }
+    }
+    exclude_contigs.as_ref().map(|pattern| {
+        Regex::new(pattern).unwrap_or_else(|e| {
+            quit_with_error(&format!("--exclude_contigs is not a valid regex\n{}", e));
+        })
+    })
 }
 
 
@@ -69,43 +248,164 @@ fn starting_message() {
 }
 
 
-fn print_settings(assemblies_dir: &Path, autocycler_dir: &Path, k_size: u32, threads: usize) {
+fn print_settings(settings: &CompressSettings) {
+    let CompressSettings { ref assemblies_dir, ref autocycler_dir, kmer: k_size, threads,
+                           gfa_version, canonical_kmers, minimizer_window, min_kmer_count,
+                           min_shared_kmers, ref kmer_histogram, gfa_paths, min_contig_len, ref exclude_contigs,
+                           dedup_contigs, rename_duplicates, ref weights, max_unitig_diff,
+                           max_bubble_len, max_bubble_div, bubble_poa, max_loop_len, max_tip_len,
+                           min_tip_depth, resume } = *settings;
     eprintln!("Settings:");
     eprintln!("  --assemblies_dir {}", assemblies_dir.display());
     eprintln!("  --autocycler_dir {}", autocycler_dir.display());
     eprintln!("  --kmer {}", k_size);
     eprintln!("  --threads {}", threads);
+    eprintln!("  --gfa_version {}", gfa_version);
+    if canonical_kmers {
+        eprintln!("  --canonical_kmers");
+    }
+    if let Some(window) = minimizer_window {
+        eprintln!("  --minimizer_window {}", window);
+    }
+    if min_kmer_count > 1 {
+        eprintln!("  --min_kmer_count {}", min_kmer_count);
+    }
+    if min_shared_kmers > 0.0 {
+        eprintln!("  --min_shared_kmers {}", format_float(min_shared_kmers));
+    }
+    if let Some(kmer_histogram) = kmer_histogram {
+        eprintln!("  --kmer_histogram {}", kmer_histogram.display());
+    }
+    if gfa_paths {
+        eprintln!("  --gfa_paths");
+    }
+    if min_contig_len > 0 {
+        eprintln!("  --min_contig_len {}", min_contig_len);
+    }
+    if let Some(exclude_contigs) = exclude_contigs {
+        eprintln!("  --exclude_contigs {}", exclude_contigs);
+    }
+    if dedup_contigs {
+        eprintln!("  --dedup_contigs");
+    }
+    if rename_duplicates {
+        eprintln!("  --rename_duplicates");
+    }
+    if let Some(weights) = weights {
+        eprintln!("  --weights {}", weights.display());
+    }
+    if max_unitig_diff > 0 {
+        eprintln!("  --max_unitig_diff {}", max_unitig_diff);
+    }
+    if max_bubble_len > 0 {
+        eprintln!("  --max_bubble_len {}", max_bubble_len);
+        eprintln!("  --max_bubble_div {}", format_float(max_bubble_div));
+        if bubble_poa {
+            eprintln!("  --bubble_poa");
+        }
+    }
+    if max_loop_len > 0 {
+        eprintln!("  --max_loop_len {}", max_loop_len);
+    }
+    if max_tip_len > 0 {
+        eprintln!("  --max_tip_len {}", max_tip_len);
+        eprintln!("  --min_tip_depth {}", format_float(min_tip_depth));
+    }
+    if resume {
+        eprintln!("  --resume");
+    }
     eprintln!();
 }
 
 
-pub fn load_sequences(assemblies_dir: &Path, k_size: u32, metrics: &mut InputAssemblyMetrics)
-        -> (Vec<Sequence>, usize) {
+pub fn load_sequences(assemblies_dir: &Path, settings: &LoadSettings,
+                      metrics: &mut InputAssemblyMetrics) -> (Vec<Sequence>, usize) {
+    let LoadSettings { k_size, gfa_paths, min_contig_len, ref exclude_contigs, dedup_contigs,
+                       rename_duplicates } = *settings;
     section_header("Loading input assemblies");
     explanation("Input assemblies are now loaded and each contig is given a unique ID.");
     let assemblies = find_all_assemblies(assemblies_dir);
     let half_k = k_size / 2;
     let mut seq_id: usize = 0;
     let mut sequences = Vec::new();
-    for assembly in &assemblies {
+
+    // To find duplicate contigs, sequences are indexed by a hash of their canonical strand, and
+    // each sequence's location in the metrics (assembly index, contig index) is stored.
+    let mut seq_index: FxHashMap<u64, Vec<usize>> = FxHashMap::default();
+    let mut seq_locations: Vec<(usize, usize)> = Vec::new();
+
+    for (assembly_i, assembly) in assemblies.iter().enumerate() {
         let mut assembly_details = InputAssemblyDetails::new(assembly);
-        for (name, header, seq) in load_fasta(assembly) {
+        let (contigs, renamed) = load_assembly(assembly, gfa_paths, rename_duplicates);
+        let line_widths = fasta_line_widths(assembly);
+        for (original_name, new_name) in &renamed {
+            eprintln!("   -: {} {} renamed to {}", assembly.display(), original_name, new_name);
+            assembly_details.renamed_contigs.insert(new_name.clone(), original_name.clone());
+        }
+        let original_names: HashMap<String, String> = renamed.into_iter()
+            .map(|(original_name, new_name)| (new_name, original_name)).collect();
+        for (contig_i, (name, header, seq)) in contigs.into_iter().enumerate() {
             let seq_len = seq.len();
-            if seq_len < k_size as usize { continue; }
-            seq_id += 1;
-            eprintln!(" {:>3}: {} {} ({} bp)", seq_id, assembly.display(), name, seq_len);
-            if seq_id > 32767 {
+            if let Some(reason) = skip_contig_reason(&header, seq_len, k_size, min_contig_len,
+                                                     exclude_contigs.as_ref()) {
+                eprintln!("   -: {} {} ({} bp) skipped: {}", assembly.display(), name, seq_len,
+                          reason);
+                assembly_details.skipped_contigs.push(InputContigDetails::from_header(&header,
+                                                                                     seq_len));
+                metrics.input_assemblies_skipped_contigs += 1;
+                continue;
+            }
+            if seq_id >= 32767 {
                 quit_with_error("no more than 32767 input sequences are allowed");
             }
             let contig_header = header.split_whitespace().collect::<Vec<&str>>().join(" ");
             let filename = assembly.file_name().unwrap().to_string_lossy().into_owned();
-            let seq = Sequence::new_with_seq(seq_id, seq, filename, contig_header, seq_len, half_k);
+            let mut seq = Sequence::new_with_seq(seq_id + 1, seq, filename, contig_header,
+                                                 seq_len, half_k);
+            seq.line_width = line_widths.get(contig_i).copied().unwrap_or(0);
+            seq.original_name = original_names.get(&name).cloned();
+            let seq_hash = canonical_hash(&seq);
+            let duplicate = find_duplicate(&seq, &sequences, seq_index.get(&seq_hash))
+                .filter(|&i| seq_locations[i].0 != assembly_i);
+            let duplicate_note = if let Some(i) = duplicate {
+                metrics.input_assemblies_duplicate_contigs += 1;
+                let original = &sequences[i];
+                let note = format!("duplicate of {}: {} {}", original.id, original.filename,
+                                   original.contig_name());
+                if dedup_contigs {
+                    // The duplicate is collapsed into the original, which then counts for both
+                    // in depths and is written out for both by decompress.
+                    eprintln!("   -: {} {} ({} bp) collapsed: {}", assembly.display(), name,
+                              seq_len, note);
+                    let (a, c) = seq_locations[i];
+                    metrics.input_assembly_details[a].contigs[c].multiplicity += 1;
+                    assembly_details.skipped_contigs.push(InputContigDetails::new(&seq));
+                    let reverse = seq.forward_seq != sequences[i].forward_seq;
+                    sequences[i].duplicates.push(Duplicate { filename: seq.filename.clone(),
+                                                             contig_header: seq.original_header(),
+                                                             reverse });
+                    sequences[i].weight += 1.0;
+                    continue;
+                }
+                format!(" {}", note)
+            } else {
+                String::new()
+            };
+            seq_id += 1;
+            eprintln!(" {:>3}: {} {} ({} bp){}", seq_id, assembly.display(), name, seq_len,
+                      duplicate_note);
+            seq_index.entry(seq_hash).or_default().push(sequences.len());
+            seq_locations.push((assembly_i, assembly_details.contigs.len()));
             assembly_details.contigs.push(InputContigDetails::new(&seq));
             sequences.push(seq);
         }
         metrics.input_assembly_details.push(assembly_details);
     }
     eprintln!();
+    print_duplicate_info(metrics.input_assemblies_duplicate_contigs, dedup_contigs);
+    if sequences.is_empty() {
+        quit_with_error("no input contigs remain after filtering");
+    }
     let pb = spinner("repairing sequence ends...");
     sequence_end_repair(&mut sequences, k_size);
     pb.finish_and_clear();
@@ -114,6 +414,104 @@ pub fn load_sequences(assemblies_dir: &Path, k_size: u32, metrics: &mut InputAss
 }
 
 
+fn load_weights(weights_file: &Path) -> BTreeMap<String, f64> {
+    // Loads a weights file (assembly filename and weight, tab-delimited) into a map of filename
+    // to weight. Only the filename part of any path is used, to match the sequences' filenames.
+    let lines = load_file_lines(weights_file);
+    let mut weights = BTreeMap::new();
+    for line in lines.iter().map(|l| l.trim()).filter(|l| !l.is_empty()) {
+        let parts: Vec<&str> = line.split('\t').collect();
+        if parts.len() != 2 {
+            quit_with_error(&format!("weights file lines must have two tab-delimited columns: {}",
+                                     line));
+        }
+        let filename = Path::new(parts[0]).file_name().map(|f| f.to_string_lossy().into_owned())
+            .unwrap_or_else(|| quit_with_error(&format!("invalid assembly filename: {}", parts[0])));
+        let weight = parts[1].parse::<f64>().ok().filter(|w| w.is_finite() && *w > 0.0)
+            .unwrap_or_else(|| quit_with_error(&format!("invalid weight for {}: {}", parts[0],
+                                                        parts[1])));
+        if weights.insert(filename, weight).is_some() {
+            quit_with_error(&format!("{} is listed more than once in the weights file", parts[0]));
+        }
+    }
+    weights
+}
+
+
+fn apply_weights(unitig_graph: &mut UnitigGraph, sequences: &mut [Sequence],
+                 weights: &BTreeMap<String, f64>) {
+    // Gives each sequence the weight of its assembly (1 for unlisted assemblies), plus those of
+    // its duplicates' assemblies, and recalculates unitig depths as the summed weight of the
+    // sequences passing through them.
+    section_header("Applying assembly weights");
+    explanation("Unitig depths are now weighted by the input assembly each sequence came from.");
+    for filename in weights.keys() {
+        if !sequences.iter().any(|s| &s.filename == filename) {
+            quit_with_error(&format!("{} in the weights file is not an input assembly",
+                                     filename));
+        }
+    }
+    for seq in sequences.iter_mut() {
+        seq.weight = seq.filenames().map(|f| weights.get(f).copied().unwrap_or(1.0)).sum();
+    }
+    for (filename, weight) in weights {
+        eprintln!("{}: {}", filename, weight);
+    }
+    eprintln!();
+    unitig_graph.set_sequence_weights(sequences);
+}
+
+
+fn canonical_hash(seq: &Sequence) -> u64 {
+    // Hashes the lesser strand of a sequence, so a sequence and its reverse complement have the
+    // same hash.
+    fxhash::hash64(seq.forward_seq.as_slice().min(seq.reverse_seq.as_slice()))
+}
+
+
+fn find_duplicate(seq: &Sequence, sequences: &[Sequence], candidates: Option<&Vec<usize>>)
+        -> Option<usize> {
+    // Returns the index of an earlier sequence which is identical to the given sequence on either
+    // strand. This must be run before end repair, when both ends of each sequence are just dot
+    // padding.
+    candidates?.iter().copied().find(|&i| {
+        let other = &sequences[i];
+        seq.forward_seq == other.forward_seq || seq.forward_seq == other.reverse_seq
+    })
+}
+
+
+fn print_duplicate_info(duplicate_count: u32, dedup_contigs: bool) {
+    if duplicate_count == 0 { return; }
+    let plural = match duplicate_count { 1 => "", _ => "s" };
+    if dedup_contigs {
+        eprintln!("{} duplicate contig{} collapsed", duplicate_count, plural);
+    } else {
+        eprintln!("{} contig{} duplicate{} contigs in other assemblies", duplicate_count, plural,
+                  match duplicate_count { 1 => "s", _ => "" });
+        eprintln!("(use --dedup_contigs to collapse each into one path with a multiplicity)");
+    }
+    eprintln!();
+}
+
+
+fn skip_contig_reason(header: &str, seq_len: usize, k_size: u32, min_contig_len: usize,
+                      exclude_contigs: Option<&Regex>) -> Option<String> {
+    // Returns why a contig should be excluded from the graph, or None if it should be included.
+    // Contigs shorter than the k-mer size are always excluded, since they contain no k-mers.
+    if seq_len < k_size as usize {
+        return Some("shorter than k-mer size".to_string());
+    }
+    if seq_len < min_contig_len {
+        return Some("shorter than --min_contig_len".to_string());
+    }
+    if exclude_contigs.is_some_and(|re| re.is_match(header.as_bytes())) {
+        return Some("matches --exclude_contigs".to_string());
+    }
+    None
+}
+
+
 fn print_sequence_info(sequence_count: usize, assembly_count: usize) {
     eprintln!("{} sequence{} loaded from {} assembl{}",
               sequence_count, match sequence_count { 1 => "", _ => "s" },
@@ -122,37 +520,382 @@ fn print_sequence_info(sequence_count: usize, assembly_count: usize) {
 }
 
 
-fn build_kmer_graph(k_size: u32, assembly_count: usize, sequences: &Vec<Sequence>) -> KmerGraph {
+fn build_kmer_graph(k_size: u32, threads: usize, assembly_count: usize, sequences: &[Sequence],
+                    canonical_kmers: bool) -> KmerGraph {
     section_header("Building k-mer De Bruijn graph");
     explanation("K-mers in the input sequences are now hashed to make a De Bruijn graph.");
-    let mut kmer_graph = KmerGraph::new(k_size);
+    let mut kmer_graph = if canonical_kmers { KmerGraph::new_canonical(k_size, threads) }
+                                       else { KmerGraph::new(k_size, threads) };
     let pb = spinner("adding k-mers to graph...");
     kmer_graph.add_sequences(sequences, assembly_count);
     pb.finish_and_clear();
-    eprintln!("Graph contains {} k-mers", kmer_graph.kmers.len());
-    eprintln!();
     kmer_graph
 }
 
 
-fn build_unitig_graph(kmer_graph: KmerGraph) -> UnitigGraph {
+fn exclude_unshared_assemblies(kmer_graph: &mut KmerGraph, sequences: Vec<Sequence>,
+                               min_shared_kmers: f64, metrics: &mut InputAssemblyMetrics)
+        -> Vec<Sequence> {
+    // Removes the sequences of any assembly with too few of its k-mers in another assembly from
+    // the k-mer graph, so the graph doesn't need to be rebuilt without them. Returns the
+    // remaining sequences.
+    section_header("Excluding unshared assemblies");
+    explanation("Input assemblies which share too few of their k-mers with the other assemblies \
+                 (e.g. one from the wrong sample) are now removed from the graph.");
+    let mut filenames: Vec<&str> = Vec::new();
+    for seq in &sequences {
+        if !filenames.contains(&seq.filename.as_str()) {
+            filenames.push(&seq.filename);
+        }
+    }
+    let groups: HashMap<u16, usize> = sequences.iter()
+        .map(|s| (s.id, filenames.iter().position(|&f| f == s.filename).unwrap())).collect();
+    let counts = kmer_graph.shared_kmer_counts(&groups);
+    let mut excluded = Vec::new();
+    for (filename, (shared, total)) in filenames.iter().zip(counts) {
+        let fraction = if total == 0 { 0.0 } else { shared as f64 / total as f64 };
+        let status = if fraction < min_shared_kmers { "excluded" } else { "kept" };
+        eprintln!("{}: {:.1}% of k-mers shared ({})", filename, 100.0 * fraction, status);
+        if fraction < min_shared_kmers {
+            excluded.push(filename.to_string());
+        }
+    }
+    eprintln!();
+    if excluded.len() == filenames.len() {
+        quit_with_error("all input assemblies were excluded by --min_shared_kmers");
+    }
+    let (removed, kept): (Vec<Sequence>, Vec<Sequence>) =
+        sequences.into_iter().partition(|s| excluded.contains(&s.filename));
+    for seq in &removed {
+        kmer_graph.remove_sequence(seq.id);
+    }
+    metrics.input_assembly_details.retain(|d| {
+        let filename = Path::new(&d.filename).file_name().map(|f| f.to_string_lossy());
+        !filename.is_some_and(|f| excluded.iter().any(|e| *e == f))
+    });
+    kept
+}
+
+
+fn filter_kmer_graph(kmer_graph: &mut KmerGraph, min_kmer_count: usize,
+                     metrics: &mut InputAssemblyMetrics) {
+    metrics.kmer_count_histogram = kmer_graph.count_histogram();
+    if min_kmer_count > 1 {
+        let removed = kmer_graph.remove_rare_kmers(min_kmer_count);
+        eprintln!("Removed {} k-mer{} occurring fewer than {} times", removed,
+                  match removed { 1 => "", _ => "s" }, min_kmer_count);
+    }
+    eprintln!("Graph contains {} k-mers", kmer_graph.kmer_count());
+    eprintln!();
+}
+
+
+fn build_unitig_graph(kmer_graph: KmerGraph, sequences: &[Sequence],
+                      min_kmer_count: usize) -> UnitigGraph {
     section_header("Building compacted unitig graph");
     explanation("All non-branching paths are now collapsed to form a compacted De Bruijn graph, \
                  a.k.a. a unitig graph.");
     let pb = spinner("building graph...");
-    let unitig_graph = UnitigGraph::from_kmer_graph(&kmer_graph);
+    let unitig_graph = if min_kmer_count > 1 {
+        UnitigGraph::from_filtered_kmer_graph(&kmer_graph, sequences)
+    } else {
+        UnitigGraph::from_kmer_graph(&kmer_graph)
+    };
     pb.finish_and_clear();
     unitig_graph.print_basic_graph_info();
     unitig_graph
 }
 
 
-fn simplify_unitig_graph(unitig_graph: &mut UnitigGraph, sequences: &Vec<Sequence>) {
+fn build_minimizer_graph(k_size: u32, window: usize, sequences: &[Sequence]) -> MinimizerGraph<'_> {
+    section_header("Building minimizer graph");
+    explanation("Input sequences are now split into segments at their minimizers, and the \
+                 segments are hashed to make a sparse graph.");
+    let mut minimizer_graph = MinimizerGraph::new(k_size, window);
+    let pb = spinner("adding segments to graph...");
+    minimizer_graph.add_sequences(sequences);
+    pb.finish_and_clear();
+    eprintln!("Graph contains {} segments", minimizer_graph.segment_count());
+    eprintln!();
+    minimizer_graph
+}
+
+
+fn build_unitig_graph_from_minimizers(minimizer_graph: MinimizerGraph) -> UnitigGraph {
+    section_header("Building compacted unitig graph");
+    explanation("All non-branching paths of segments are now collapsed and expanded back to full \
+                 sequence to form a unitig graph.");
+    let pb = spinner("building graph...");
+    let unitig_graph = UnitigGraph::from_minimizer_graph(&minimizer_graph);
+    pb.finish_and_clear();
+    unitig_graph.print_basic_graph_info();
+    unitig_graph
+}
+
+
+fn checkpoint_files(autocycler_dir: &Path) -> (PathBuf, PathBuf) {
+    (autocycler_dir.join("compress_checkpoint.gfa"),
+     autocycler_dir.join("compress_checkpoint.yaml"))
+}
+
+
+#[derive(Serialize, Deserialize, Debug, PartialEq)]
+struct CheckpointSettings {
+    // The settings which affect the checkpoint's unitig graph, named as their options. Settings
+    // for later steps (e.g. simplification) can differ when resuming.
+    kmer: u32,
+    canonical_kmers: bool,
+    minimizer_window: Option<usize>,
+    min_kmer_count: usize,
+    min_shared_kmers: f64,
+    gfa_paths: bool,
+    min_contig_len: usize,
+    exclude_contigs: Option<String>,
+    dedup_contigs: bool,
+    rename_duplicates: bool,
+}
+
+
+#[derive(Serialize, Deserialize, Debug, PartialEq)]
+struct CheckpointInputs {
+    // The input assemblies the checkpoint was made from. Each file's size and hash are stored, so
+    // a checkpoint isn't used after an assembly is added, removed or changed.
+    assemblies_dir: PathBuf,
+    assemblies: Vec<CheckpointInputFile>,
+}
+
+
+#[derive(Serialize, Deserialize, Debug, PartialEq)]
+struct CheckpointInputFile {
+    filename: String,
+    size: u64,
+    hash: u64,
+}
+
+
+impl CheckpointInputs {
+    fn new(assemblies_dir: &Path) -> Self {
+        let assemblies = find_all_assemblies(assemblies_dir).into_iter().map(|path| {
+            let contents = fs::read(&path).unwrap_or_else(|e| {
+                quit_with_error(&format!("unable to read {}\n{}", path.display(), e))
+            });
+            let filename = path.file_name().unwrap().to_string_lossy().into_owned();
+            CheckpointInputFile { filename, size: contents.len() as u64, hash: xxh3_64(&contents) }
+        }).collect();
+        let assemblies_dir = fs::canonicalize(assemblies_dir)
+            .unwrap_or_else(|_| assemblies_dir.to_path_buf());
+        CheckpointInputs { assemblies_dir, assemblies }
+    }
+
+    fn changes(&self, new: &CheckpointInputs) -> Vec<String> {
+        // Describes how the new inputs differ from these, e.g. "b.fasta changed".
+        let mut changes = Vec::new();
+        if self.assemblies_dir != new.assemblies_dir {
+            changes.push("--assemblies_dir".to_string());
+        }
+        let old_files: HashMap<&str, &CheckpointInputFile> =
+            self.assemblies.iter().map(|a| (a.filename.as_str(), a)).collect();
+        let new_files: HashMap<&str, &CheckpointInputFile> =
+            new.assemblies.iter().map(|a| (a.filename.as_str(), a)).collect();
+        for a in &new.assemblies {
+            match old_files.get(a.filename.as_str()) {
+                None => changes.push(format!("{} added", a.filename)),
+                Some(old) if *old != a => changes.push(format!("{} changed", a.filename)),
+                Some(_) => {}
+            }
+        }
+        for a in &self.assemblies {
+            if !new_files.contains_key(a.filename.as_str()) {
+                changes.push(format!("{} removed", a.filename));
+            }
+        }
+        changes
+    }
+}
+
+
+#[derive(Serialize, Deserialize)]
+struct Checkpoint {
+    settings: CheckpointSettings,
+    inputs: CheckpointInputs,
+    metrics: InputAssemblyMetrics,
+}
+
+
+fn save_checkpoint(autocycler_dir: &Path, unitig_graph: &UnitigGraph, sequences: &[Sequence],
+                   checkpoint: &Checkpoint) {
+    // Saves the unsimplified unitig graph (which contains the input sequences as paths), the
+    // metrics gathered so far and the settings used, so an interrupted run can be resumed without
+    // rebuilding the graph. The YAML file is written last, so its presence indicates a complete
+    // checkpoint. There is only this one checkpoint, so a run interrupted before the unitig
+    // graph is built (e.g. during the k-mer graph build) restarts from the beginning.
+    let (checkpoint_gfa, checkpoint_yaml) = checkpoint_files(autocycler_dir);
+    let pb = spinner("saving checkpoint...");
+    if let Err(e) = unitig_graph.save_gfa(&checkpoint_gfa, sequences) {
+        quit_with_error(&format!("unable to write {}\n{}", checkpoint_gfa.display(), e));
+    }
+    if let Err(e) = fs::write(&checkpoint_yaml, serde_yaml::to_string(checkpoint).unwrap()) {
+        quit_with_error(&format!("unable to write {}\n{}", checkpoint_yaml.display(), e));
+    }
+    pb.finish_and_clear();
+}
+
+
+fn load_checkpoint(autocycler_dir: &Path, settings: &CheckpointSettings,
+                   inputs: &CheckpointInputs)
+        -> Option<(UnitigGraph, Vec<Sequence>, InputAssemblyMetrics)> {
+    // Loads the checkpoint, unless there isn't one or it was made with different settings or
+    // input assemblies, in which case compress starts from the beginning.
+    let (checkpoint_gfa, checkpoint_yaml) = checkpoint_files(autocycler_dir);
+    if !checkpoint_gfa.is_file() || !checkpoint_yaml.is_file() {
+        eprintln!("No checkpoint found in {}, starting from the beginning", autocycler_dir.display());
+        eprintln!();
+        return None;
+    }
+    let checkpoint: Option<Checkpoint> = fs::read_to_string(&checkpoint_yaml).ok()
+        .and_then(|yaml| serde_yaml::from_str(&yaml).ok());
+    let Some(checkpoint) = checkpoint else {
+        eprintln!("Unable to read checkpoint in {}, starting from the beginning",
+                  autocycler_dir.display());
+        eprintln!();
+        return None;
+    };
+    let changed = changed_settings(&checkpoint.settings, settings);
+    if !changed.is_empty() {
+        eprintln!("Checkpoint in {} was made with different settings ({}), starting from the \
+                   beginning", autocycler_dir.display(), changed.join(", "));
+        eprintln!();
+        return None;
+    }
+    let changed = checkpoint.inputs.changes(inputs);
+    if !changed.is_empty() {
+        eprintln!("Checkpoint in {} was made from different input assemblies ({}), starting from \
+                   the beginning", autocycler_dir.display(), changed.join(", "));
+        eprintln!();
+        return None;
+    }
+    section_header("Resuming from checkpoint");
+    explanation("A checkpoint from a previous run was found, so the input assemblies are loaded \
+                 from its unitig graph instead of being rebuilt.");
+    let (unitig_graph, sequences) = UnitigGraph::from_gfa_file(&checkpoint_gfa);
+    let metrics = checkpoint.metrics;
+    print_sequence_info(sequences.len(), metrics.input_assembly_details.len());
+    unitig_graph.print_basic_graph_info();
+    Some((unitig_graph, sequences, metrics))
+}
+
+
+fn changed_settings(old: &CheckpointSettings, new: &CheckpointSettings) -> Vec<String> {
+    // Returns the options (e.g. --kmer) whose values differ between the two settings.
+    let old = serde_json::to_value(old).unwrap();
+    let new = serde_json::to_value(new).unwrap();
+    new.as_object().unwrap().iter().filter(|(name, value)| old.get(name.as_str()) != Some(value))
+        .map(|(name, _)| format!("--{}", name)).collect()
+}
+
+
+fn delete_checkpoint(autocycler_dir: &Path) {
+    let (checkpoint_gfa, checkpoint_yaml) = checkpoint_files(autocycler_dir);
+    for file in [checkpoint_yaml, checkpoint_gfa] {
+        if file.exists() {
+            if let Err(e) = fs::remove_file(&file) {
+                quit_with_error(&format!("unable to delete {}\n{}", file.display(), e));
+            }
+        }
+    }
+}
+
+
+fn collapse_unitig_graph_near_identical(unitig_graph: &mut UnitigGraph, sequences: &[Sequence],
+                                        max_unitig_diff: usize,
+                                        metrics: &mut SimplificationMetrics) -> Vec<Sequence> {
+    section_header("Collapsing near-identical unitigs");
+    explanation("Pairs of unitigs with the same neighbours and only a few differing bases are now \
+                 collapsed into the better-supported one, and the difference is recorded as an \
+                 ambiguity (AM tag) on the kept unitig.");
+    let pb = spinner("collapsing unitigs...");
+    let sequences = collapse_near_identical_unitigs(unitig_graph, sequences, max_unitig_diff,
+                                                    metrics);
+    pb.finish_and_clear();
+    let collapsed = metrics.near_identical_unitigs_collapsed;
+    eprintln!("{} unitig{} collapsed", collapsed, match collapsed { 1 => "", _ => "s" });
+    eprintln!();
+    sequences
+}
+
+
+fn pop_unitig_graph_bubbles(unitig_graph: &mut UnitigGraph, sequences: &[Sequence],
+                            max_bubble_len: u32, max_bubble_div: f64, poa: bool,
+                            metrics: &mut SimplificationMetrics) -> Vec<Sequence> {
+    section_header("Popping bubbles");
+    explanation("Small bubbles in the graph are now collapsed into their best-supported arm, and \
+                 input sequences which used a collapsed arm are rewritten to use that arm.");
+    let pb = spinner("popping bubbles...");
+    let sequences = pop_bubbles(unitig_graph, sequences, max_bubble_len, max_bubble_div, poa,
+                                metrics);
+    pb.finish_and_clear();
+    let collapsed_arms = metrics.bubble_arms_collapsed;
+    eprintln!("{} bubble arm{} collapsed", collapsed_arms,
+              match collapsed_arms { 1 => "", _ => "s" });
+    if poa {
+        let polished_arms = metrics.bubble_arms_polished;
+        eprintln!("{} kept arm{} replaced by POA consensus", polished_arms,
+                  match polished_arms { 1 => "", _ => "s" });
+    }
+    eprintln!();
+    sequences
+}
+
+
+fn unroll_unitig_graph_loops(unitig_graph: &mut UnitigGraph, sequences: &[Sequence],
+                             max_loop_len: u32, candidates_fasta: &Path,
+                             metrics: &mut SimplificationMetrics) -> Vec<Sequence> {
+    section_header("Unrolling loops");
+    explanation("Short tandem repeat loops in the graph are now unrolled into linear copies, \
+                 going around each loop the number of times best supported by the input \
+                 sequences. Input sequences which went around a different number of times are \
+                 rewritten to match. Each loop's linearised candidates are saved to a FASTA file \
+                 so the alternatives can be checked.");
+    let pb = spinner("unrolling loops...");
+    let (sequences, candidates) = unroll_loops(unitig_graph, sequences, max_loop_len, metrics);
+    pb.finish_and_clear();
+    let unrolled = metrics.loops_unrolled;
+    eprintln!("{} loop{} unrolled", unrolled, match unrolled { 1 => "", _ => "s" });
+    if !candidates.is_empty() {
+        if let Err(e) = save_loop_candidates(&candidates, candidates_fasta) {
+            quit_with_error(&format!("unable to write {}\n{}", candidates_fasta.display(), e));
+        }
+        eprintln!("Loop candidates: {}", candidates_fasta.display());
+    }
+    eprintln!();
+    sequences
+}
+
+
+fn save_loop_candidates(candidates: &[LoopCandidate], out_file: &Path) -> io::Result<()> {
+    let mut file = BufWriter::new(File::create(out_file)?);
+    for c in candidates {
+        writeln!(file, ">loop_{}_{} passes={} support={:.2} chosen={}", c.junction, c.passes,
+                 c.passes, c.support, c.chosen)?;
+        write_fasta_seq(&mut file, str::from_utf8(&c.seq).unwrap(), 0)?;
+    }
+    file.flush()
+}
+
+
+fn simplify_unitig_graph(unitig_graph: &mut UnitigGraph, sequences: &mut Vec<Sequence>,
+                         max_tip_len: u32, min_tip_depth: f64,
+                         metrics: &mut SimplificationMetrics) {
     section_header("Simplifying unitig graph");
-    explanation("The graph structure is now simplified by moving sequence into repeat unitigs \
-                 when possible.");
+    if max_tip_len > 0 {
+        explanation("The graph structure is now simplified by clipping low-depth tips and moving \
+                     sequence into repeat unitigs when possible.");
+    } else {
+        explanation("The graph structure is now simplified by moving sequence into repeat \
+                     unitigs when possible.");
+    }
     let pb = spinner("simplifying graph...");
-    simplify_structure(unitig_graph, sequences);
+    simplify_structure(unitig_graph, sequences, max_tip_len, min_tip_depth, metrics);
     pb.finish_and_clear();
     unitig_graph.print_basic_graph_info();
 }
@@ -169,6 +912,16 @@ fn save_metrics(metrics: &mut InputAssemblyMetrics, assembly_count: usize,
 }
 
 
+fn save_kmer_histogram(histogram: &BTreeMap<usize, usize>, out_file: &Path) -> io::Result<()> {
+    let mut file = BufWriter::new(File::create(out_file)?);
+    writeln!(file, "count\tkmers")?;
+    for (count, kmers) in histogram {
+        writeln!(file, "{}\t{}", count, kmers)?;
+    }
+    file.flush()
+}
+
+
 fn finished_message(start_time: Instant, out_gfa: PathBuf, out_yaml: PathBuf) {
     section_header("Finished!");
     explanation("You can now run autocycler cluster to group contigs based on their similarity.");
@@ -179,40 +932,95 @@ fn finished_message(start_time: Instant, out_gfa: PathBuf, out_yaml: PathBuf) {
 }
 
 
-fn sequence_end_repair(sequences: &mut Vec<Sequence>, k_size: u32) {
+pub fn sequence_end_repair(sequences: &mut [Sequence], k_size: u32) {
     // Since each sequence ends with a half-k string of dots, these will create a dead-end tip for
     // the sequence's start and end in the graph. To prevent this, this function looks for matching
     // sequences to replace the dots in other sequences, and if found, replaces the dots. Since the
     // half-k ends will be trimmed off during overlap trimming, it doesn't matter if the replacing
     // sequences are 'wrong'.
     let overlap_size = (k_size - 1) as usize;
-    let all_seqs: Vec<_> = sequences.iter().flat_map(|s| vec![s.forward_seq.clone(), s.reverse_seq.clone()]).collect();
-    sequences.par_iter_mut().for_each(|seq| {  // parallel for loop with rayon
-        let start = &seq.forward_seq[..overlap_size];
-        let start_re = Regex::new(str::from_utf8(start).unwrap()).unwrap();
-        let end = &seq.forward_seq[seq.forward_seq.len() - overlap_size..];
-        let end_re = Regex::new(str::from_utf8(end).unwrap()).unwrap();
-
-        let mut all_matches = Vec::new();
-        for s in &all_seqs {
-            for m in start_re.find_iter(s) {
-                all_matches.push(m.as_bytes().to_vec());
-            }
-        }
-        let best_match = find_best_match(all_matches);
+    let ends: Vec<&[u8]> = sequences.iter().flat_map(|s| {
+        [&s.forward_seq[..overlap_size], &s.forward_seq[s.forward_seq.len() - overlap_size..]]
+    }).collect();
+    let strands: Vec<&[u8]> = sequences.iter()
+        .flat_map(|s| [s.forward_seq.as_slice(), s.reverse_seq.as_slice()]).collect();
+    let mut all_matches = find_end_matches(&ends, &strands);
+    for (i, seq) in sequences.iter_mut().enumerate() {
+        let best_match = find_best_match(std::mem::take(&mut all_matches[i * 2]));
         seq.forward_seq.splice(..overlap_size, best_match.iter().cloned());
+        let best_match = find_best_match(std::mem::take(&mut all_matches[i * 2 + 1]));
+        seq.forward_seq.splice(seq.forward_seq.len() - overlap_size.., best_match.iter().cloned());
+        seq.reverse_seq = reverse_complement(&seq.forward_seq);
+    }
+}
 
-        let mut all_matches = Vec::new();
-        for s in &all_seqs {
-            for m in end_re.find_iter(s) {
-                all_matches.push(m.as_bytes().to_vec());
+
+fn find_end_matches(ends: &[&[u8]], strands: &[&[u8]]) -> Vec<Vec<Vec<u8>>> {
+    // Finds all matches of each sequence end in the strands, where dots in an end match any base.
+    // Matches in a strand don't overlap (like a regex's find_iter), so each is taken as far left
+    // as possible after the previous one.
+    // Rather than scanning the strands once per end, each end is indexed by a seed (its longest
+    // run of bases), and the strands are scanned once for all seeds. Seed hits are then checked
+    // against the whole end.
+    let seeds: Vec<(usize, usize)> = ends.iter().map(|e| longest_base_run(e)).collect();
+    let mut seed_index: BTreeMap<usize, FxHashMap<&[u8], Vec<usize>>> = BTreeMap::new();
+    for (i, (end, &(offset, len))) in ends.iter().zip(&seeds).enumerate() {
+        seed_index.entry(len).or_default().entry(&end[offset..offset + len]).or_default().push(i);
+    }
+    let strand_matches: Vec<Vec<(usize, usize)>> = strands.par_iter().map(|strand| {
+        let mut hits = Vec::new();  // (end index, start of match in strand)
+        for (&len, index) in &seed_index {
+            if len > strand.len() { continue; }
+            for p in 0..=strand.len() - len {
+                let Some(end_indices) = index.get(&strand[p..p + len]) else { continue; };
+                for &i in end_indices {
+                    let offset = seeds[i].0;
+                    if p < offset || p - offset + ends[i].len() > strand.len() { continue; }
+                    let start = p - offset;
+                    if end_matches(ends[i], &strand[start..start + ends[i].len()]) {
+                        hits.push((i, start));
+                    }
+                }
             }
         }
-        let best_match = find_best_match(all_matches);
-        seq.forward_seq.splice(seq.forward_seq.len() - overlap_size.., best_match.iter().cloned());
+        hits.sort_unstable();
+        let mut matches = Vec::new();
+        let mut next_allowed = (usize::MAX, 0);
+        for (i, start) in hits {
+            if i == next_allowed.0 && start < next_allowed.1 { continue; }
+            next_allowed = (i, start + ends[i].len());
+            matches.push((i, start));
+        }
+        matches
+    }).collect();
+    let mut all_matches = vec![Vec::new(); ends.len()];
+    for (strand, matches) in strands.iter().zip(strand_matches) {
+        for (i, start) in matches {
+            all_matches[i].push(strand[start..start + ends[i].len()].to_vec());
+        }
+    }
+    all_matches
+}
 
-        seq.reverse_seq = reverse_complement(&seq.forward_seq);
-    });
+
+fn longest_base_run(seq: &[u8]) -> (usize, usize) {
+    // Returns the start and length of the first longest stretch of non-dot bases in the sequence.
+    let mut best = (0, 0);
+    let mut run_start = 0;
+    for (i, &b) in seq.iter().chain(std::iter::once(&b'.')).enumerate() {
+        if b != b'.' { continue; }
+        if i - run_start > best.1 {
+            best = (run_start, i - run_start);
+        }
+        run_start = i + 1;
+    }
+    best
+}
+
+
+fn end_matches(end: &[u8], seq: &[u8]) -> bool {
+    // Dots in the end act as wildcards.
+    end.iter().zip(seq).all(|(&a, &b)| a == b'.' || a == b)
 }
 
 
@@ -256,7 +1064,7 @@ mod tests {
     use std::panic;
     use tempfile::tempdir;
 
-    use crate::tests::make_test_file;
+    use crate::tests::{make_test_file, random_seq};
 
     #[test]
     fn test_find_best_match_1() {
@@ -294,6 +1102,47 @@ mod tests {
         assert_eq!(find_best_match(all_matches), b".CGACGT");
     }
 
+    #[test]
+    fn test_find_end_matches() {
+        // Results should be the same as scanning each strand with a regex made from each end
+        // (dots are regex wildcards), including for repetitive and very short sequences.
+        let a = random_seq(200, 0);
+        let b = format!("{}{}{}", &a[150..], random_seq(100, 1), &a[..30]);
+        let seqs = [a, b, "A".repeat(60), "AC".repeat(20), "GT".to_string(), "ACGTT".to_string()];
+        for k_size in [11, 12, 21] {
+            let half_k = k_size / 2;
+            let sequences: Vec<Sequence> = seqs.iter().enumerate().map(|(i, s)| {
+                Sequence::new_with_seq(i + 1, s.clone(), "a.fasta".to_string(),
+                                       format!("{}", i + 1), s.len(), half_k)
+            }).collect();
+            let overlap_size = (k_size - 1) as usize;
+            let ends: Vec<&[u8]> = sequences.iter().flat_map(|s| {
+                [&s.forward_seq[..overlap_size], &s.forward_seq[s.forward_seq.len() - overlap_size..]]
+            }).collect();
+            let strands: Vec<&[u8]> = sequences.iter()
+                .flat_map(|s| [s.forward_seq.as_slice(), s.reverse_seq.as_slice()]).collect();
+            let all_matches = find_end_matches(&ends, &strands);
+            for (end, mut matches) in ends.iter().zip(all_matches) {
+                let re = Regex::new(str::from_utf8(end).unwrap()).unwrap();
+                let mut expected: Vec<Vec<u8>> = strands.iter()
+                    .flat_map(|s| re.find_iter(s).map(|m| m.as_bytes().to_vec())).collect();
+                assert!(!matches.is_empty());
+                matches.sort();
+                expected.sort();
+                assert_eq!(matches, expected);
+            }
+        }
+    }
+
+    #[test]
+    fn test_longest_base_run() {
+        assert_eq!(longest_base_run(b"....."), (0, 0));
+        assert_eq!(longest_base_run(b"...ACGT"), (3, 4));
+        assert_eq!(longest_base_run(b"ACG..."), (0, 3));
+        assert_eq!(longest_base_run(b"..AC.GTA."), (5, 3));
+        assert_eq!(longest_base_run(b"AC.GT"), (0, 2));
+    }
+
     #[test]
     fn test_find_best_match_2() {
         let all_matches = vec![b"ACGT...".to_vec()];
@@ -330,7 +1179,9 @@ mod tests {
         make_test_file(&assembly_dir.path().join("b.fasta"), ">b1\nACGT\n>b2\nACGT\n");
         make_test_file(&assembly_dir.path().join("c.fasta"), ">c1\nACGT\n>c2\nACGT\n>c3\nACGT\n");
         let mut metrics = InputAssemblyMetrics::default();
-        let (sequences, count) = load_sequences(&assembly_dir.into_path(), 3, &mut metrics);
+        let (sequences, count) = load_sequences(&assembly_dir.into_path(),
+                                                &LoadSettings { k_size: 3, ..Default::default() },
+                                                &mut metrics);
         assert_eq!(sequences.len(), 6);
         assert_eq!(count, 3);
     }
@@ -344,7 +1195,231 @@ mod tests {
         make_test_file(&assembly_dir.path().join("c.fasta"), ">c1\nACGT\n>c1\nACGT\n>c3\nACGT\n");
         assert!(panic::catch_unwind(|| {
             let mut metrics = InputAssemblyMetrics::default();
-            load_sequences(&assembly_dir.into_path(), 3, &mut metrics);
+            load_sequences(&assembly_dir.into_path(),
+                           &LoadSettings { k_size: 3, ..Default::default() }, &mut metrics);
         }).is_err());
     }
+
+    #[test]
+    fn test_load_sequences_rename() {
+        // Like the previous test, but c.fasta's duplicate sequence name is renamed.
+        let assembly_dir = tempdir().unwrap();
+        make_test_file(&assembly_dir.path().join("a.fasta"), ">a1\nACGT\n");
+        make_test_file(&assembly_dir.path().join("c.fasta"), ">c1\nACGT\n>c1 x\nACGT\n>c3\nACGT\n");
+        let mut metrics = InputAssemblyMetrics::default();
+        let load_settings = LoadSettings { k_size: 3, rename_duplicates: true,
+                                           ..Default::default() };
+        let (sequences, _) = load_sequences(assembly_dir.path(), &load_settings, &mut metrics);
+        let headers: Vec<_> = sequences.iter().map(|s| s.contig_header.as_str()).collect();
+        assert_eq!(headers, vec!["a1", "c1", "c1_2 x", "c3"]);
+        assert_eq!(metrics.input_assembly_details[1].renamed_contigs["c1_2"], "c1");
+        let originals: Vec<_> = sequences.iter().map(|s| s.original_header()).collect();
+        assert_eq!(originals, vec!["a1", "c1", "c1 x", "c3"]);
+        assert_eq!(sequences[2].original_name, Some("c1".to_string()));
+    }
+
+    #[test]
+    fn test_load_sequences_3() {
+        // GFA assemblies can be mixed with FASTA assemblies, using either segments or paths.
+        let assembly_dir = tempdir().unwrap();
+        make_test_file(&assembly_dir.path().join("a.fasta"), ">a1\nACGT\n");
+        make_test_file(&assembly_dir.path().join("b.gfa"),
+                       "S\t1\tACGT\nS\t2\tCGTA\nL\t1\t+\t2\t+\t0M\nP\tb1\t1+,2+\t*\n");
+        let mut metrics = InputAssemblyMetrics::default();
+        let load_settings = LoadSettings { k_size: 3, ..Default::default() };
+        let (sequences, count) = load_sequences(assembly_dir.path(), &load_settings, &mut metrics);
+        assert_eq!(sequences.len(), 3);
+        assert_eq!(count, 2);
+        let mut metrics = InputAssemblyMetrics::default();
+        let load_settings = LoadSettings { k_size: 3, gfa_paths: true, ..Default::default() };
+        let (sequences, count) = load_sequences(assembly_dir.path(), &load_settings, &mut metrics);
+        assert_eq!(sequences.len(), 2);
+        assert_eq!(count, 2);
+        assert_eq!(sequences[1].contig_name(), "b1");
+        assert_eq!(sequences[1].length, 8);
+    }
+
+    #[test]
+    fn test_load_sequences_4() {
+        // Contigs can be excluded by length or by a regex on their header.
+        let assembly_dir = tempdir().unwrap();
+        make_test_file(&assembly_dir.path().join("a.fasta"),
+                       ">a1\nACGTACGT\n>a2 plasmid\nACGTACGT\n>a3\nACGTA\n>a4\nAC\n");
+        let mut metrics = InputAssemblyMetrics::default();
+        let load_settings = LoadSettings { k_size: 3, min_contig_len: 6,
+                                          exclude_contigs: Some(Regex::new("plasmid").unwrap()),
+                                          ..Default::default() };
+        let (sequences, _) = load_sequences(assembly_dir.path(), &load_settings, &mut metrics);
+        assert_eq!(sequences.len(), 1);
+        assert_eq!(sequences[0].contig_name(), "a1");
+        assert_eq!(metrics.input_assemblies_skipped_contigs, 3);
+        let skipped: Vec<_> = metrics.input_assembly_details[0].skipped_contigs.iter()
+            .map(|c| c.name.as_str()).collect();
+        assert_eq!(skipped, vec!["a2", "a3", "a4"]);
+        assert_eq!(metrics.input_assembly_details[0].skipped_contigs[0].description, "plasmid");
+    }
+
+    #[test]
+    fn test_load_sequences_5() {
+        // b1 is identical to a1 and c1 is the reverse complement of b2, but a2 only duplicates a
+        // contig in its own assembly so it isn't counted.
+        let assembly_dir = tempdir().unwrap();
+        make_test_file(&assembly_dir.path().join("a.fasta"), ">a1\nAACGTT\n>a2\nAACGTT\n");
+        make_test_file(&assembly_dir.path().join("b.fasta"), ">b1\nAACGTT\n>b2\nACGTAC\n");
+        make_test_file(&assembly_dir.path().join("c.fasta"), ">c1\nGTACGT\n");
+
+        let mut metrics = InputAssemblyMetrics::default();
+        let load_settings = LoadSettings { k_size: 3, ..Default::default() };
+        let (sequences, _) = load_sequences(assembly_dir.path(), &load_settings, &mut metrics);
+        assert_eq!(sequences.len(), 5);
+        assert_eq!(metrics.input_assemblies_duplicate_contigs, 2);
+
+        let mut metrics = InputAssemblyMetrics::default();
+        let load_settings = LoadSettings { k_size: 3, dedup_contigs: true, ..Default::default() };
+        let (sequences, _) = load_sequences(assembly_dir.path(), &load_settings, &mut metrics);
+        let names: Vec<_> = sequences.iter().map(|s| s.contig_name()).collect();
+        assert_eq!(names, vec!["a1", "a2", "b2"]);
+        assert_eq!(sequences.iter().map(|s| s.id).collect::<Vec<_>>(), vec![1, 2, 3]);
+        assert_eq!(metrics.input_assemblies_duplicate_contigs, 2);
+        assert_eq!(metrics.input_assembly_details[0].contigs[0].multiplicity, 2);
+        assert_eq!(metrics.input_assembly_details[0].contigs[1].multiplicity, 1);
+        assert_eq!(metrics.input_assembly_details[1].contigs[0].multiplicity, 2);
+        assert_eq!(metrics.input_assembly_details[1].skipped_contigs[0].name, "b1");
+        assert_eq!(metrics.input_assembly_details[2].skipped_contigs[0].name, "c1");
+
+        // The kept contigs carry their duplicates, which count towards their weight.
+        assert_eq!(sequences[0].duplicates, vec![Duplicate { filename: "b.fasta".to_string(),
+                                                             contig_header: "b1".to_string(),
+                                                             reverse: false }]);
+        assert_eq!(sequences[2].duplicates, vec![Duplicate { filename: "c.fasta".to_string(),
+                                                             contig_header: "c1".to_string(),
+                                                             reverse: true }]);
+        assert_eq!(sequences.iter().map(|s| s.weight).collect::<Vec<_>>(), vec![2.0, 1.0, 2.0]);
+    }
+
+    #[test]
+    fn test_checkpoint() {
+        let assembly_dir = tempdir().unwrap();
+        let autocycler_dir = tempdir().unwrap();
+        make_test_file(&assembly_dir.path().join("a.fasta"),
+                       ">a\nTTCGCTGCGCTCGCTTCGCTTTTGCCGTCGTCGCTGTGCAGCTCGGCTCGATCT\n");
+        make_test_file(&assembly_dir.path().join("b.fasta"),
+                       ">b\nTTCGCTGCGCTCGCTTCGCTTTTGCCTGAATCGCCTAGCTCGGCTCGATCT\n");
+        let mut metrics = InputAssemblyMetrics::default();
+        let load_settings = LoadSettings { k_size: 11, ..Default::default() };
+        let (mut sequences, assembly_count) = load_sequences(assembly_dir.path(), &load_settings,
+                                                             &mut metrics);
+        let mut kmer_graph = KmerGraph::new(11, 1);
+        kmer_graph.add_sequences(&sequences, assembly_count);
+        let mut unitig_graph = UnitigGraph::from_kmer_graph(&kmer_graph);
+
+        // Resuming from the checkpoint gives the same final graph as continuing without it.
+        let settings = || CheckpointSettings { kmer: 11, canonical_kmers: false,
+                                               minimizer_window: None, min_kmer_count: 1,
+                                               min_shared_kmers: 0.0, gfa_paths: false,
+                                               min_contig_len: 0, exclude_contigs: None,
+                                               dedup_contigs: false, rename_duplicates: false };
+        let inputs = || CheckpointInputs::new(assembly_dir.path());
+        assert!(load_checkpoint(autocycler_dir.path(), &settings(), &inputs()).is_none());
+        save_checkpoint(autocycler_dir.path(), &unitig_graph, &sequences,
+                        &Checkpoint { settings: settings(), inputs: inputs(), metrics });
+        let (mut resumed_graph, mut resumed_sequences, resumed_metrics) =
+            load_checkpoint(autocycler_dir.path(), &settings(), &inputs()).unwrap();
+        assert_eq!(resumed_metrics.input_assembly_details.len(), 2);
+        simplify_structure(&mut unitig_graph, &mut sequences, 0, 0.0,
+                           &mut SimplificationMetrics::default());
+        simplify_structure(&mut resumed_graph, &mut resumed_sequences, 0, 0.0,
+                           &mut SimplificationMetrics::default());
+        let gfa = autocycler_dir.path().join("graph.gfa");
+        let resumed_gfa = autocycler_dir.path().join("resumed_graph.gfa");
+        unitig_graph.save_gfa(&gfa, &sequences).unwrap();
+        resumed_graph.save_gfa(&resumed_gfa, &resumed_sequences).unwrap();
+        assert_eq!(fs::read_to_string(gfa).unwrap(), fs::read_to_string(resumed_gfa).unwrap());
+
+        // A checkpoint made with different graph-building settings isn't used.
+        let different = CheckpointSettings { kmer: 13, dedup_contigs: true, ..settings() };
+        assert_eq!(changed_settings(&settings(), &different), vec!["--dedup_contigs", "--kmer"]);
+        assert!(load_checkpoint(autocycler_dir.path(), &different, &inputs()).is_none());
+        let different = CheckpointSettings { exclude_contigs: Some("plasmid".to_string()),
+                                             ..settings() };
+        assert!(load_checkpoint(autocycler_dir.path(), &different, &inputs()).is_none());
+        assert!(load_checkpoint(autocycler_dir.path(), &settings(), &inputs()).is_some());
+
+        // Nor is a checkpoint made from different input assemblies.
+        let original_inputs = inputs();
+        make_test_file(&assembly_dir.path().join("b.fasta"),
+                       ">b\nTTCGCTGCGCTCGCTTCGCTTTTGCCTGAATCGCCTAGCTCGGCTCGATCA\n");
+        make_test_file(&assembly_dir.path().join("c.fasta"),
+                       ">c\nTTCGCTGCGCTCGCTTCGCTTTTGCCTGAATCGCCTAGCTCGGCTCGATCT\n");
+        fs::remove_file(assembly_dir.path().join("a.fasta")).unwrap();
+        assert_eq!(original_inputs.changes(&inputs()),
+                   vec!["b.fasta changed", "c.fasta added", "a.fasta removed"]);
+        assert!(load_checkpoint(autocycler_dir.path(), &settings(), &inputs()).is_none());
+        let other_dir = tempdir().unwrap();
+        make_test_file(&other_dir.path().join("b.fasta"),
+                       ">b\nTTCGCTGCGCTCGCTTCGCTTTTGCCTGAATCGCCTAGCTCGGCTCGATCA\n");
+        let other_inputs = CheckpointInputs::new(other_dir.path());
+        assert_eq!(other_inputs.changes(&CheckpointInputs::new(other_dir.path())),
+                   Vec::<String>::new());
+        assert_eq!(original_inputs.changes(&other_inputs)[0], "--assemblies_dir");
+
+        delete_checkpoint(autocycler_dir.path());
+        assert!(load_checkpoint(autocycler_dir.path(), &settings(), &original_inputs).is_none());
+    }
+
+    #[test]
+    fn test_exclude_unshared_assemblies() {
+        let assembly_dir = tempdir().unwrap();
+        let shared = random_seq(500, 0);
+        make_test_file(&assembly_dir.path().join("a.fasta"), &format!(">a\n{}\n", shared));
+        make_test_file(&assembly_dir.path().join("b.fasta"), &format!(">b\n{}\n", shared));
+        make_test_file(&assembly_dir.path().join("c.fasta"),
+                       &format!(">c1\n{}\n>c2\n{}\n", random_seq(500, 1), &shared[..100]));
+        let mut metrics = InputAssemblyMetrics::default();
+        let load_settings = LoadSettings { k_size: 11, ..Default::default() };
+        let (sequences, assembly_count) = load_sequences(assembly_dir.path(), &load_settings,
+                                                         &mut metrics);
+        let mut kmer_graph = KmerGraph::new(11, 1);
+        kmer_graph.add_sequences(&sequences, assembly_count);
+
+        // About a sixth of c's k-mers are shared, so it is only excluded by the higher threshold.
+        let sequences = exclude_unshared_assemblies(&mut kmer_graph, sequences, 0.1,
+                                                    &mut metrics);
+        assert_eq!(sequences.len(), 4);
+        let sequences = exclude_unshared_assemblies(&mut kmer_graph, sequences, 0.5,
+                                                    &mut metrics);
+        assert_eq!(sequences.iter().map(|s| s.filename.as_str()).collect::<Vec<_>>(),
+                   vec!["a.fasta", "b.fasta"]);
+        assert_eq!(metrics.input_assembly_details.len(), 2);
+        let mut expected_graph = KmerGraph::new(11, 1);
+        expected_graph.add_sequences(&sequences, assembly_count);
+        assert_eq!(kmer_graph.kmer_count(), expected_graph.kmer_count());
+    }
+
+    #[test]
+    fn test_load_weights() {
+        let temp_dir = tempdir().unwrap();
+        let weights_file = temp_dir.path().join("weights.tsv");
+        make_test_file(&weights_file, "a.fasta\t2\n\ndir/b.fasta\t0.5\n");
+        let weights = load_weights(&weights_file);
+        assert_eq!(weights.len(), 2);
+        assert_eq!(weights["a.fasta"], 2.0);
+        assert_eq!(weights["b.fasta"], 0.5);
+
+        for bad in ["a.fasta\t0\n", "a.fasta\t-1\n", "a.fasta\tx\n", "a.fasta 2\n",
+                    "a.fasta\t1\na.fasta\t2\n"] {
+            make_test_file(&weights_file, bad);
+            assert!(panic::catch_unwind(|| load_weights(&weights_file)).is_err());
+        }
+    }
+
+    #[test]
+    fn test_skip_contig_reason() {
+        let exclude = Regex::new("^chr").unwrap();
+        assert!(skip_contig_reason("a", 100, 51, 0, None).is_none());
+        assert!(skip_contig_reason("a", 50, 51, 0, None).is_some());
+        assert!(skip_contig_reason("a", 100, 51, 101, None).is_some());
+        assert!(skip_contig_reason("chr1 x", 100, 51, 0, Some(&exclude)).is_some());
+        assert!(skip_contig_reason("a chr1", 100, 51, 0, Some(&exclude)).is_none());
+    }
 }
@@ -11,42 +11,82 @@
 // Public License for more details. You should have received a copy of the GNU General Public
 // License along with Autocycler. If not, see <http://www.gnu.org/licenses/>.
 
-use std::io::{BufWriter, Write};
+use std::collections::{HashMap, HashSet};
+use std::io::{self, BufWriter, Write};
 use std::fs::File;
 use std::path::{Path, PathBuf};
-use flate2::write::GzEncoder;
-use flate2::Compression;
 
 use crate::log::{section_header, explanation};
-use crate::misc::{check_if_dir_is_not_dir, check_if_file_exists, create_dir, quit_with_error,
-                  up_to_first_space};
-use crate::sequence::Sequence;
+use crate::misc::{check_if_dir_is_not_dir, check_if_file_exists, create_dir, is_fastq_file,
+                  output_writer_for_extension, output_writer_with_level, quit_with_error,
+                  up_to_first_space, write_fasta_seq, COMPRESSION_EXTENSIONS};
+use crate::sequence::{Sequence, sequence_checksum};
 use crate::unitig_graph::UnitigGraph;
 
 
-pub fn decompress(in_gfa: PathBuf, out_dir: Option<PathBuf>, out_file: Option<PathBuf>) {
-    check_settings(&in_gfa, &out_dir, &out_file);
+pub struct DecompressSettings {
+    // The settings for autocycler decompress, named as their options.
+    pub in_gfa: PathBuf,
+    pub out_dir: Option<PathBuf>,
+    pub out_file: Option<PathBuf>,
+    pub cluster: Option<u16>,
+    pub seqs: Option<String>,
+    pub wrap: String,
+    pub verify: bool,
+    pub bed: Option<PathBuf>,
+    pub report: Option<PathBuf>,
+    pub compress_level: u32,
+    pub uncompressed: bool,
+}
+
+
+pub fn decompress(settings: DecompressSettings) {
+    let compress_level = check_settings(&settings);
     starting_message();
-    print_settings(&in_gfa, &out_dir, &out_file);
-    let (unitig_graph, sequences) = load_graph(&in_gfa);
+    print_settings(&settings, compress_level);
+    let DecompressSettings { in_gfa, out_dir, out_file, cluster, seqs, wrap, verify, bed, report,
+                             .. } = settings;
+    let (unitig_graph, all_sequences) = load_graph(&in_gfa);
+    let sequences = select_sequences(all_sequences.clone(), cluster, &seqs);
+    let wrap = Wrap::new(&wrap, &sequences);
+    if verify {
+        verify_checksums(&unitig_graph, &sequences);
+    }
     if let Some(out_dir) = out_dir {
         create_dir(&out_dir);
-        save_original_seqs_to_dir(&out_dir, &unitig_graph, &sequences);
+        save_original_seqs_to_dir(&out_dir, &unitig_graph, &sequences, &wrap, compress_level);
     }
     if let Some(out_file) = out_file {
-        save_original_seqs_to_file(&out_file, &unitig_graph, &sequences);
+        save_original_seqs_to_file(&out_file, &unitig_graph, &sequences, &wrap);
+    }
+    if let Some(bed) = bed {
+        save_unitig_bed(&bed, &unitig_graph, &sequences);
+    }
+    if let Some(report) = report {
+        save_report(&report, &unitig_graph, &sequences, &all_sequences);
     }
 }
 
 
-fn check_settings(in_gfa: &Path, out_dir: &Option<PathBuf>, out_file: &Option<PathBuf>) {
+fn check_settings(settings: &DecompressSettings) -> Option<u32> {
+    // Returns the compression level for compressed files in the output directory (None if they
+    // should be written uncompressed).
+    let DecompressSettings { ref in_gfa, ref out_dir, ref out_file, ref wrap, verify, ref bed,
+                             ref report, compress_level, uncompressed, .. } = *settings;
     check_if_file_exists(in_gfa);
-    if out_dir.is_none() && out_file.is_none() {
-        quit_with_error("either --out_dir or --out_file is required")
+    if out_dir.is_none() && out_file.is_none() && !verify && bed.is_none() && report.is_none() {
+        quit_with_error("one of --out_dir, --out_file, --verify, --bed or --report is required")
     }
     if let Some(out_dir) = out_dir {
         check_if_dir_is_not_dir(out_dir);
     }
+    if wrap != "original" && wrap.parse::<usize>().is_err() {
+        quit_with_error("--wrap must be a number of bases or original");
+    }
+    if !(1..=9).contains(&compress_level) {
+        quit_with_error("--compress_level must be between 1 and 9");
+    }
+    if uncompressed { None } else { Some(compress_level) }
 }
 
 
@@ -58,7 +98,9 @@ fn starting_message() {
 }
 
 
-fn print_settings(in_gfa: &Path, out_dir: &Option<PathBuf>, out_file: &Option<PathBuf>) {
+fn print_settings(settings: &DecompressSettings, compress_level: Option<u32>) {
+    let DecompressSettings { ref in_gfa, ref out_dir, ref out_file, cluster, ref seqs, ref wrap,
+                             verify, ref bed, ref report, .. } = *settings;
     eprintln!("Settings:");
     eprintln!("  --in_gfa {}", in_gfa.display());
     if let Some(out_dir) = out_dir {
@@ -67,62 +109,264 @@ fn print_settings(in_gfa: &Path, out_dir: &Option<PathBuf>, out_file: &Option<Pa
     if let Some(out_file) = out_file {
         eprintln!("  --out_file {}", out_file.display());
     }
+    if let Some(cluster) = cluster {
+        eprintln!("  --cluster {}", cluster);
+    }
+    if let Some(seqs) = seqs {
+        eprintln!("  --seqs {}", seqs);
+    }
+    eprintln!("  --wrap {}", wrap);
+    if verify {
+        eprintln!("  --verify");
+    }
+    if let Some(bed) = bed {
+        eprintln!("  --bed {}", bed.display());
+    }
+    if let Some(report) = report {
+        eprintln!("  --report {}", report.display());
+    }
+    if out_dir.is_some() {
+        match compress_level {
+            Some(level) => eprintln!("  --compress_level {}", level),
+            None        => eprintln!("  --uncompressed"),
+        }
+    }
     eprintln!();
 }
 
 
 fn load_graph(gfa: &Path) -> (UnitigGraph, Vec<Sequence>) {
+    // Loads the graph, skipping any sequence paths which can't be reconstructed. This lets
+    // decompress work on GFAs from any stage of the pipeline (or ones which have been edited),
+    // recovering whatever sequences are still intact.
     section_header("Loading graph");
     explanation("The unitig graph is now loaded into memory.");
-    let (unitig_graph, sequences) = UnitigGraph::from_gfa_file(gfa);
+    let (unitig_graph, sequences, problems) = UnitigGraph::from_gfa_file_skipping_bad_paths(gfa);
     unitig_graph.print_basic_graph_info();
+    if !problems.is_empty() {
+        eprintln!("{} sequence{} cannot be reconstructed:", problems.len(),
+                  match problems.len() { 1 => "", _ => "s" });
+        for problem in &problems {
+            eprintln!("  {}", problem);
+        }
+        eprintln!();
+    }
+    if sequences.is_empty() {
+        if problems.is_empty() {
+            quit_with_error(&format!("{} contains no sequence paths, so there is nothing to \
+                                      decompress (graphs made by autocycler resolve do not \
+                                      contain paths)", gfa.display()));
+        }
+        quit_with_error("none of the sequences in the graph can be reconstructed");
+    }
     (unitig_graph, sequences)
 }
 
 
+fn select_sequences(sequences: Vec<Sequence>, cluster: Option<u16>, seqs: &Option<String>)
+        -> Vec<Sequence> {
+    // Keeps only the sequences in the given cluster and/or matching the given comma-delimited
+    // list, where each item is a sequence ID or a contig name.
+    if cluster.is_none() && seqs.is_none() {
+        return sequences;
+    }
+    let items: Option<Vec<&str>> = seqs.as_ref().map(|s| s.split(',').map(|i| i.trim()).collect());
+    let selected: Vec<Sequence> = sequences.into_iter().filter(|s| {
+        cluster.map_or(true, |c| s.cluster == c) &&
+        items.as_ref().map_or(true, |items| items.iter().any(|&i| {
+            i == s.id.to_string() || i == up_to_first_space(&s.contig_header)
+        }))
+    }).collect();
+    if selected.is_empty() {
+        quit_with_error("no sequences in the graph match the given --cluster/--seqs");
+    }
+    eprintln!("{} sequence{} selected", selected.len(), match selected.len() { 1 => "", _ => "s" });
+    eprintln!();
+    selected
+}
+
+
+fn verify_checksums(unitig_graph: &UnitigGraph, sequences: &[Sequence]) {
+    section_header("Verifying reconstructed sequences");
+    explanation("Each contig is reconstructed from the unitig graph and its checksum is compared \
+                 to the one stored when the graph was built.");
+    let (matched, mismatched, missing) = check_checksums(unitig_graph, sequences);
+    eprintln!("{} sequence{} matched", matched, match matched { 1 => "", _ => "s" });
+    if missing > 0 {
+        eprintln!("{} sequence{} had no stored checksum", missing,
+                  match missing { 1 => "", _ => "s" });
+    }
+    eprintln!();
+    if !mismatched.is_empty() {
+        quit_with_error(&format!("checksum mismatch for {} sequence{}: {}", mismatched.len(),
+                                 match mismatched.len() { 1 => "", _ => "s" },
+                                 mismatched.join(", ")));
+    }
+}
+
+
+fn check_checksums(unitig_graph: &UnitigGraph, sequences: &[Sequence])
+        -> (usize, Vec<String>, usize) {
+    // Returns the number of sequences whose reconstruction matches their stored checksum, the
+    // names of those which don't match and the number of sequences without a stored checksum
+    // (e.g. those which were trimmed).
+    let (mut matched, mut mismatched, mut missing) = (0, Vec::new(), 0);
+    for (seq, path) in sequences.iter().zip(unitig_graph.get_unitig_paths_for_sequences(sequences)) {
+        let Some(checksum) = seq.checksum else {
+            missing += 1;
+            continue;
+        };
+        let reconstructed = unitig_graph.get_repaired_sequence_from_path(seq, &path);
+        if sequence_checksum(reconstructed.as_bytes()) == checksum {
+            matched += 1;
+        } else {
+            mismatched.push(format!("{} {}", seq.filename, seq.contig_name()));
+        }
+    }
+    (matched, mismatched, missing)
+}
+
+
+pub enum Wrap {
+    // How reconstructed FASTA sequences are wrapped: a fixed number of bases per line (0 for no
+    // wrapping), or the line width of each contig's input file, looked up by filename and header.
+    Fixed(usize),
+    Original(HashMap<(String, String), usize>),
+}
+
+impl Wrap {
+    pub fn new(wrap: &str, sequences: &[Sequence]) -> Wrap {
+        match wrap.parse::<usize>() {
+            Ok(width) => Wrap::Fixed(width),
+            Err(_) => Wrap::Original(sequences.iter()
+                .map(|s| ((s.filename.clone(), s.original_header()), s.line_width))
+                .collect()),
+        }
+    }
+
+    fn width(&self, filename: &str, header: &str) -> usize {
+        match self {
+            Wrap::Fixed(width) => *width,
+            Wrap::Original(widths) => widths.get(&(filename.to_string(), header.to_string()))
+                                            .copied().unwrap_or(0),
+        }
+    }
+}
+
+
+// The quality given to every base of a reconstructed FASTQ assembly.
+const PLACEHOLDER_QUALITY: char = '!';
+
+
 pub fn save_original_seqs_to_dir(out_dir: &Path, unitig_graph: &UnitigGraph,
-                                 sequences: &Vec<Sequence>) {
+                                 sequences: &[Sequence], wrap: &Wrap,
+                                 compress_level: Option<u32>) {
+    // Each assembly is saved using its original filename. Compressed files (e.g. ending in .gz or
+    // .zst) are compressed in the same format at the given level, or saved without the
+    // compression extension if no level is given. GFA assemblies are saved as FASTA (see
+    // output_filename). FASTQ assemblies are saved as FASTQ, with
+    // placeholder qualities since compress doesn't keep the originals.
     section_header("Reconstructing assemblies from unitig graph");
     explanation("Each contig is reconstructed by tracing its path through the unitig graph, with \
                  the results saved to a directory.");
     let original_seqs = unitig_graph.reconstruct_original_sequences(sequences);
     let mut filenames: Vec<&String> = original_seqs.keys().collect();
     filenames.sort();
+    let mut output_filenames = HashSet::new();
+    for filename in &filenames {
+        let output = output_filename(filename, compress_level.is_none());
+        if !output_filenames.insert(output.clone()) {
+            quit_with_error(&format!("more than one assembly would be saved as {}", output));
+        }
+    }
     for filename in filenames {
         let headers_seqs = &original_seqs[filename];
-        let file_path = out_dir.join(filename.clone());
-        let file = File::create(&file_path).unwrap();
+        let file_path = out_dir.join(output_filename(filename, compress_level.is_none()));
         eprintln!("{}:", file_path.display());
-        if file_path.extension().and_then(|s| s.to_str()) == Some("gz") {
-            let buf_writer = BufWriter::new(GzEncoder::new(file, Compression::default()));
-            write_sequences(buf_writer, headers_seqs);
-        } else {
-            let buf_writer = BufWriter::new(file);
-            write_sequences(buf_writer, headers_seqs);
+        let writer = match compress_level {
+            Some(level) => output_writer_for_extension(&file_path, level),
+            None        => output_writer_with_level(&file_path, None),
+        };
+        let mut writer = writer.unwrap_or_else(|e| {
+            quit_with_error(&format!("unable to create {}\n{}", file_path.display(), e));
+        });
+        let fastq = is_fastq_file(Path::new(filename));
+        write_sequences(&mut writer, filename, headers_seqs, wrap, fastq);
+        if let Err(e) = writer.finish() {
+            quit_with_error(&format!("unable to write {}\n{}", file_path.display(), e));
         }
         eprintln!();
     }
 }
 
 
-fn write_sequences<W: Write>(mut writer: BufWriter<W>, headers_seqs: &Vec<(String, String)>) {
+fn output_filename(filename: &str, uncompressed: bool) -> String {
+    // GFA assemblies are reconstructed as FASTA (their contigs, not their graph), so they get a
+    // .fasta extension in place of .gfa.
+    let (base, compression) = COMPRESSION_EXTENSIONS.iter()
+        .find_map(|ext| filename.strip_suffix(ext).and_then(|f| f.strip_suffix('.'))
+                                .map(|f| (f, Some(*ext))))
+        .unwrap_or((filename, None));
+    let base = match base.strip_suffix(".gfa") {
+        Some(stem) => format!("{}.fasta", stem),
+        None       => base.to_string(),
+    };
+    match compression {
+        Some(ext) if !uncompressed => format!("{}.{}", base, ext),
+        _                          => base,
+    }
+}
+
+
+fn write_sequences<W: Write>(writer: &mut W, filename: &str,
+                             headers_seqs: &Vec<(String, String)>, wrap: &Wrap, fastq: bool) {
     for (header, seq) in headers_seqs {
         eprintln!("  {} ({} bp)", up_to_first_space(header), seq.len());
-        writeln!(writer, ">{}", header).unwrap();
-        writeln!(writer, "{}", seq).unwrap();
+        if fastq {
+            writeln!(writer, "@{}\n{}\n+\n{}", header, seq,
+                     PLACEHOLDER_QUALITY.to_string().repeat(seq.len())).unwrap();
+        } else {
+            writeln!(writer, ">{}", header).unwrap();
+            write_fasta_seq(writer, seq, wrap.width(filename, header)).unwrap();
+        }
     }
 }
 
 
 fn save_original_seqs_to_file(out_file: &Path, unitig_graph: &UnitigGraph,
-                              sequences: &Vec<Sequence>) {
+                              sequences: &[Sequence], wrap: &Wrap) {
+    // All contigs go into one FASTA, with headers prefixed by their original filename. An
+    // out_file of "-" writes to stdout.
     section_header("Reconstructing assemblies from unitig graph");
     explanation("Each contig is reconstructed by tracing its path through the unitig graph, with \
                  the results saved to a file.");
-    eprintln!("{}:", out_file.display());
+    let to_stdout = out_file == Path::new("-");
+    if to_stdout { eprintln!("stdout:"); } else { eprintln!("{}:", out_file.display()); }
     let original_seqs = unitig_graph.reconstruct_original_sequences(sequences);
-    let file = File::create(out_file).unwrap();
-    let mut buf_writer = BufWriter::new(file);
+    let writer: Box<dyn Write> = if to_stdout {
+        Box::new(io::stdout().lock())
+    } else {
+        Box::new(File::create(out_file).unwrap_or_else(|e| {
+            quit_with_error(&format!("unable to create {}\n{}", out_file.display(), e));
+        }))
+    };
+    match write_combined_sequences(BufWriter::new(writer), &original_seqs, wrap) {
+        Ok(()) => {}
+        // A closed stdout (e.g. piping into head) just means no more output is wanted.
+        Err(e) if e.kind() == io::ErrorKind::BrokenPipe => std::process::exit(0),
+        Err(e) => {
+            let target = if to_stdout { "stdout".to_string() }
+                         else { out_file.display().to_string() };
+            quit_with_error(&format!("unable to write {}\n{}", target, e));
+        }
+    }
+    eprintln!();
+}
+
+
+fn write_combined_sequences<W: Write>(mut writer: BufWriter<W>,
+                                      original_seqs: &HashMap<String, Vec<(String, String)>>,
+                                      wrap: &Wrap) -> io::Result<()> {
     let mut filenames: Vec<&String> = original_seqs.keys().collect();
     filenames.sort();
     for filename in filenames {
@@ -130,9 +374,221 @@ fn save_original_seqs_to_file(out_file: &Path, unitig_graph: &UnitigGraph,
         let clean_filename = filename.replace(" ", "_");
         for (header, seq) in headers_seqs {
             eprintln!("  {}__{} ({} bp)", filename, up_to_first_space(header), seq.len());
-            writeln!(buf_writer, ">{}__{}", clean_filename, header).unwrap();
-            writeln!(buf_writer, "{}", seq).unwrap();
+            writeln!(writer, ">{}__{}", clean_filename, header)?;
+            write_fasta_seq(&mut writer, seq, wrap.width(filename, header))?;
+        }
+    }
+    writer.flush()
+}
+
+
+fn save_unitig_bed(bed: &Path, unitig_graph: &UnitigGraph, sequences: &[Sequence]) {
+    section_header("Saving unitig coordinates");
+    explanation("The position of each unitig in each contig is now saved to a BED file. Contig \
+                 names are prefixed by their filename, as in the --out_file FASTA.");
+    let file = File::create(bed).unwrap_or_else(|e| {
+        quit_with_error(&format!("unable to create {}\n{}", bed.display(), e));
+    });
+    write_unitig_bed(BufWriter::new(file), unitig_graph, sequences);
+    eprintln!("{}", bed.display());
+    eprintln!();
+}
+
+
+fn write_unitig_bed<W: Write>(mut writer: BufWriter<W>, unitig_graph: &UnitigGraph,
+                              sequences: &[Sequence]) {
+    // Writes one BED line per unitig in each contig's path: contig, start, end, unitig number,
+    // score (unused) and the unitig's strand in the contig.
+    for (seq, path) in sequences.iter().zip(unitig_graph.get_unitig_paths_for_sequences(sequences)) {
+        let contig = format!("{}__{}", seq.filename.replace(" ", "_"), seq.contig_name());
+        let positions = unitig_graph.get_path_positions(seq, &path);
+        for ((unitig_num, strand), start) in path.into_iter().zip(positions) {
+            let end = start + unitig_graph.unitig(unitig_num).length();
+            writeln!(writer, "{}\t{}\t{}\t{}\t0\t{}", contig, start, end, unitig_num,
+                     if strand { "+" } else { "-" }).unwrap();
         }
     }
+    writer.flush().unwrap();
+}
+
+
+fn save_report(report: &Path, unitig_graph: &UnitigGraph, sequences: &[Sequence],
+               all_sequences: &[Sequence]) {
+    section_header("Saving reconstruction report");
+    explanation("A summary of each contig's path through the unitig graph is now saved to a TSV \
+                 file. The shared fraction is the proportion of the contig's length in unitigs \
+                 which are also used by other assemblies.");
+    let file = File::create(report).unwrap_or_else(|e| {
+        quit_with_error(&format!("unable to create {}\n{}", report.display(), e));
+    });
+    write_report(BufWriter::new(file), unitig_graph, sequences, all_sequences);
+    eprintln!("{}", report.display());
     eprintln!();
 }
+
+
+fn write_report<W: Write>(mut writer: BufWriter<W>, unitig_graph: &UnitigGraph,
+                          sequences: &[Sequence], all_sequences: &[Sequence]) {
+    // Sharing is judged using all sequences in the graph, not just the selected ones.
+    let seq_filenames: HashMap<u16, &str> = all_sequences.iter()
+        .map(|s| (s.id, s.filename.as_str())).collect();
+    writeln!(writer, "sequence_id\tfile_name\tcontig_name\tlength\tcluster\tunitigs\t\
+                      shared_fraction").unwrap();
+    for (seq, path) in sequences.iter().zip(unitig_graph.get_unitig_paths_for_sequences(sequences)) {
+        let mut shared_length = 0;
+        for (unitig_num, _) in &path {
+            let unitig = unitig_graph.unitig(*unitig_num);
+            if unitig.forward_positions.iter().any(|p| {
+                seq_filenames.get(&p.seq_id()).is_some_and(|&f| f != seq.filename)
+            }) {
+                shared_length += unitig.length() as usize;
+            }
+        }
+        let shared_fraction = if seq.length == 0 { 0.0 }
+                              else { shared_length as f64 / seq.length as f64 };
+        let cluster = if seq.cluster > 0 { seq.cluster.to_string() } else { "none".to_string() };
+        writeln!(writer, "{}\t{}\t{}\t{}\t{}\t{}\t{:.4}", seq.id, seq.filename,
+                 seq.contig_name(), seq.length, cluster, path.len(), shared_fraction).unwrap();
+    }
+    writer.flush().unwrap();
+}
+
+
+#[cfg(test)]
+mod tests {
+    use crate::test_gfa::*;
+    use super::*;
+
+    #[test]
+    fn test_select_sequences() {
+        let (_, mut sequences) = UnitigGraph::from_gfa_lines(&get_test_gfa_15());
+        sequences[0].cluster = 1;
+        sequences[1].cluster = 2;
+        sequences[2].cluster = 2;
+        let ids = |seqs: Vec<Sequence>| seqs.iter().map(|s| s.id).collect::<Vec<_>>();
+        assert_eq!(ids(select_sequences(sequences.clone(), None, &None)), vec![1, 2, 3]);
+        assert_eq!(ids(select_sequences(sequences.clone(), Some(2), &None)), vec![2, 3]);
+        assert_eq!(ids(select_sequences(sequences.clone(), None, &Some("3, a".to_string()))),
+                   vec![1, 3]);
+        assert_eq!(ids(select_sequences(sequences.clone(), Some(2), &Some("1,b".to_string()))),
+                   vec![2]);
+        assert!(std::panic::catch_unwind(|| {
+            select_sequences(sequences.clone(), Some(9), &None);
+        }).is_err());
+    }
+
+    #[test]
+    fn test_check_checksums() {
+        let (graph, mut sequences) = UnitigGraph::from_gfa_lines(&get_test_gfa_15());
+        sequences[0].checksum = Some(sequence_checksum(b"ACGACTACGATTGATCGATCGAGGGGTACGTACGTA"));
+        sequences[1].checksum = Some(sequence_checksum(b"ACGACTACGACCCGATCGATCGATACGTACGTAA"));
+        let (matched, mismatched, missing) = check_checksums(&graph, &sequences);
+        assert_eq!(matched, 1);
+        assert_eq!(mismatched, vec!["b.fasta b".to_string()]);
+        assert_eq!(missing, 1);
+    }
+
+    #[test]
+    fn test_output_filename() {
+        assert_eq!(output_filename("a.fasta.gz", false), "a.fasta.gz");
+        assert_eq!(output_filename("a.fasta.gz", true), "a.fasta");
+        assert_eq!(output_filename("a.fasta", true), "a.fasta");
+        assert_eq!(output_filename("a.gz.fasta", true), "a.gz.fasta");
+        assert_eq!(output_filename("a.fasta.zst", true), "a.fasta");
+        assert_eq!(output_filename("a.fasta.xz", true), "a.fasta");
+        assert_eq!(output_filename("a.fasta.bz2", true), "a.fasta");
+        assert_eq!(output_filename("a.fasta.bz2", false), "a.fasta.bz2");
+        assert_eq!(output_filename("a.fastaxz", true), "a.fastaxz");
+        assert_eq!(output_filename("flye.gfa", false), "flye.fasta");
+        assert_eq!(output_filename("flye.gfa", true), "flye.fasta");
+        assert_eq!(output_filename("flye.gfa.gz", false), "flye.fasta.gz");
+        assert_eq!(output_filename("flye.gfa.gz", true), "flye.fasta");
+        assert_eq!(output_filename("gfa.fasta", true), "gfa.fasta");
+    }
+
+    #[test]
+    fn test_write_sequences() {
+        let headers_seqs = vec![("a circular=true".to_string(), "ACGTACGT".to_string()),
+                                ("b".to_string(), "ACG".to_string())];
+        let mut buffer = Vec::new();
+        write_sequences(&mut buffer, "x.fasta", &headers_seqs, &Wrap::Fixed(5), false);
+        assert_eq!(String::from_utf8(buffer).unwrap(),
+                   ">a circular=true\nACGTA\nCGT\n>b\nACG\n");
+        let mut buffer = Vec::new();
+        write_sequences(&mut buffer, "x.fastq", &headers_seqs, &Wrap::Fixed(5), true);
+        assert_eq!(String::from_utf8(buffer).unwrap(),
+                   "@a circular=true\nACGTACGT\n+\n!!!!!!!!\n@b\nACG\n+\n!!!\n");
+    }
+
+    #[test]
+    fn test_write_unitig_bed() {
+        let (graph, sequences) = UnitigGraph::from_gfa_lines(&get_test_gfa_15());
+        let mut buffer = Vec::new();
+        write_unitig_bed(BufWriter::new(&mut buffer), &graph, &sequences[1..]);
+        assert_eq!(String::from_utf8(buffer).unwrap(),
+                   "b.fasta__b\t0\t10\t1\t0\t+\n\
+                    b.fasta__b\t10\t13\t3\t0\t+\n\
+                    b.fasta__b\t13\t23\t4\t0\t+\n\
+                    b.fasta__b\t23\t33\t6\t0\t+\n\
+                    c.fasta__c\t0\t10\t6\t0\t-\n\
+                    c.fasta__c\t10\t20\t4\t0\t-\n\
+                    c.fasta__c\t20\t22\t2\t0\t-\n\
+                    c.fasta__c\t22\t32\t1\t0\t-\n");
+    }
+
+    #[test]
+    fn test_write_report() {
+        let (graph, mut sequences) = UnitigGraph::from_gfa_lines(&get_test_gfa_15());
+        sequences[2].filename = "a.fasta".to_string();
+        sequences[2].cluster = 3;
+        let mut buffer = Vec::new();
+        write_report(BufWriter::new(&mut buffer), &graph, &sequences, &sequences);
+        assert_eq!(String::from_utf8(buffer).unwrap(),
+                   "sequence_id\tfile_name\tcontig_name\tlength\tcluster\tunitigs\t\
+                    shared_fraction\n\
+                    1\ta.fasta\ta\t36\tnone\t5\t0.8333\n\
+                    2\tb.fasta\tb\t33\tnone\t4\t0.9091\n\
+                    3\ta.fasta\tc\t32\t3\t4\t0.9375\n");
+    }
+
+    #[test]
+    fn test_write_combined_sequences() {
+        let (graph, sequences) = UnitigGraph::from_gfa_lines(&get_test_gfa_15());
+        let mut original_seqs = graph.reconstruct_original_sequences(&sequences[..2]);
+        original_seqs.insert("my assembly.fasta".to_string(),
+                             vec![("x circular=true".to_string(), "ACGT".to_string())]);
+        let mut buffer = Vec::new();
+        write_combined_sequences(BufWriter::new(&mut buffer), &original_seqs, &Wrap::Fixed(0))
+            .unwrap();
+        assert_eq!(String::from_utf8(buffer).unwrap(),
+                   ">a.fasta__a\nACGACTACGATTGATCGATCGAGGGGTACGTACGTA\n\
+                    >b.fasta__b\nACGACTACGACCCGATCGATCGATACGTACGTA\n\
+                    >my_assembly.fasta__x circular=true\nACGT\n");
+        let mut buffer = Vec::new();
+        write_combined_sequences(BufWriter::new(&mut buffer), &original_seqs,
+                                 &Wrap::Fixed(16)).unwrap();
+        assert_eq!(String::from_utf8(buffer).unwrap(),
+                   ">a.fasta__a\nACGACTACGATTGATC\nGATCGAGGGGTACGTA\nCGTA\n\
+                    >b.fasta__b\nACGACTACGACCCGAT\nCGATCGATACGTACGT\nA\n\
+                    >my_assembly.fasta__x circular=true\nACGT\n");
+    }
+
+    struct ClosedPipe;
+
+    impl Write for ClosedPipe {
+        fn write(&mut self, _buf: &[u8]) -> io::Result<usize> {
+            Err(io::Error::from(io::ErrorKind::BrokenPipe))
+        }
+        fn flush(&mut self) -> io::Result<()> { Ok(()) }
+    }
+
+    #[test]
+    fn test_write_combined_sequences_closed_pipe() {
+        // Write errors are returned rather than panicking, so a closed stdout can be handled.
+        let (graph, sequences) = UnitigGraph::from_gfa_lines(&get_test_gfa_15());
+        let original_seqs = graph.reconstruct_original_sequences(&sequences);
+        let result = write_combined_sequences(BufWriter::new(ClosedPipe), &original_seqs,
+                                              &Wrap::Fixed(0));
+        assert_eq!(result.unwrap_err().kind(), io::ErrorKind::BrokenPipe);
+    }
+}
@@ -0,0 +1,259 @@
+// This file contains the code for the autocycler diff subcommand.
+
+// Copyright 2024 Ryan Wick (rrwick@gmail.com)
+// https://github.com/rrwick/Autocycler
+
+// This file is part of Autocycler. Autocycler is free software: you can redistribute it and/or
+// modify it under the terms of the GNU General Public License as published by the Free Software
+// Foundation, either version 3 of the License, or (at your option) any later version. Autocycler
+// is distributed in the hope that it will be useful, but WITHOUT ANY WARRANTY; without even the
+// implied warranty of MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the GNU General
+// Public License for more details. You should have received a copy of the GNU General Public
+// License along with Autocycler. If not, see <http://www.gnu.org/licenses/>.
+
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+
+use crate::log::{section_header, explanation};
+use crate::misc::check_if_file_exists;
+use crate::sequence::Sequence;
+use crate::unitig_graph::UnitigGraph;
+
+
+pub fn diff(old_gfa: PathBuf, new_gfa: PathBuf) {
+    check_if_file_exists(&old_gfa);
+    check_if_file_exists(&new_gfa);
+    starting_message();
+    print_settings(&old_gfa, &new_gfa);
+    let (old_graph, old_seqs) = load_graph(&old_gfa, "old");
+    let (new_graph, new_seqs) = load_graph(&new_gfa, "new");
+    let graph_diff = diff_graphs(&old_graph, &old_seqs, &new_graph, &new_seqs);
+    print_diff(&graph_diff);
+}
+
+
+fn starting_message() {
+    section_header("Starting autocycler diff");
+    explanation("This command compares two unitig graphs and reports the differences between \
+                 them. Unitigs are matched by their sequence (not their number), so graphs which \
+                 have been renumbered can still be compared.");
+}
+
+
+fn print_settings(old_gfa: &Path, new_gfa: &Path) {
+    eprintln!("Settings:");
+    eprintln!("  --old_gfa {}", old_gfa.display());
+    eprintln!("  --new_gfa {}", new_gfa.display());
+    eprintln!();
+}
+
+
+fn load_graph(gfa: &Path, label: &str) -> (UnitigGraph, Vec<Sequence>) {
+    section_header(&format!("Loading {} graph", label));
+    explanation(&format!("The {} unitig graph is now loaded into memory.", label));
+    let (unitig_graph, sequences) = UnitigGraph::from_gfa_file(gfa);
+    unitig_graph.print_basic_graph_info();
+    (unitig_graph, sequences)
+}
+
+
+#[derive(Debug, Default, PartialEq)]
+pub struct GraphDiff {
+    pub removed_unitigs: Vec<String>,
+    pub added_unitigs: Vec<String>,
+    pub removed_links: Vec<String>,
+    pub added_links: Vec<String>,
+    pub removed_paths: Vec<String>,
+    pub added_paths: Vec<String>,
+    pub changed_paths: Vec<String>,
+}
+
+impl GraphDiff {
+    fn is_empty(&self) -> bool {
+        self.removed_unitigs.is_empty() && self.added_unitigs.is_empty() &&
+            self.removed_links.is_empty() && self.added_links.is_empty() &&
+            self.removed_paths.is_empty() && self.added_paths.is_empty() &&
+            self.changed_paths.is_empty()
+    }
+}
+
+
+fn print_diff(graph_diff: &GraphDiff) {
+    // The differences are printed to stdout (with old-graph numbers for removed things and
+    // new-graph numbers for added things), so they can be easily redirected to a file.
+    section_header("Differences");
+    if graph_diff.is_empty() {
+        explanation("The graphs are equivalent.");
+        return;
+    }
+    explanation("Lines starting with - are only in the old graph, lines starting with + are only \
+                 in the new graph and lines starting with ~ are paths which differ between the \
+                 graphs.");
+    for u in &graph_diff.removed_unitigs { println!("- unitig {}", u); }
+    for u in &graph_diff.added_unitigs   { println!("+ unitig {}", u); }
+    for l in &graph_diff.removed_links   { println!("- link {}", l); }
+    for l in &graph_diff.added_links     { println!("+ link {}", l); }
+    for p in &graph_diff.removed_paths   { println!("- path {}", p); }
+    for p in &graph_diff.added_paths     { println!("+ path {}", p); }
+    for p in &graph_diff.changed_paths   { println!("~ path {}", p); }
+}
+
+
+// Unitigs are identified across graphs by their canonical sequence (the lesser of their forward
+// and reverse-complement sequences) and a unitig strand by that plus whether it is on the same
+// strand as the canonical sequence.
+type CanonicalStrand = (Vec<u8>, bool);
+type CanonicalLink = (CanonicalStrand, CanonicalStrand);
+
+
+struct CanonicalGraph {
+    unitigs: HashMap<Vec<u8>, String>,
+    links: HashMap<CanonicalLink, String>,
+    paths: HashMap<(String, String), (Vec<CanonicalStrand>, String)>,
+}
+
+impl CanonicalGraph {
+    fn new(graph: &UnitigGraph, sequences: &[Sequence]) -> Self {
+        let mut canonical: HashMap<u32, (Vec<u8>, bool)> = HashMap::new();
+        let mut unitigs = HashMap::new();
+        for u in &graph.unitigs {
+            let flipped = u.reverse_seq < u.forward_seq;
+            let seq = if flipped { u.reverse_seq.clone() } else { u.forward_seq.clone() };
+            unitigs.insert(seq.clone(), format!("{} ({} bp)", u.number, u.length()));
+            canonical.insert(u.number, (seq, flipped));
+        }
+        let to_canonical = |num: u32, strand: bool| -> CanonicalStrand {
+            let (seq, flipped) = &canonical[&num];
+            (seq.clone(), strand != *flipped)
+        };
+        let mut links = HashMap::new();
+        for (a, a_strand, b, b_strand) in graph.get_links_for_gfa(0) {
+            let a_num = a.parse::<u32>().unwrap();
+            let b_num = b.parse::<u32>().unwrap();
+            let a_canon = to_canonical(a_num, a_strand == "+");
+            let b_canon = to_canonical(b_num, b_strand == "+");
+            let forward = (a_canon.clone(), b_canon.clone());
+            let reverse = ((b_canon.0, !b_canon.1), (a_canon.0, !a_canon.1));
+            links.entry(forward.min(reverse))
+                 .or_insert(format!("{}{} -> {}{}", a, a_strand, b, b_strand));
+        }
+        let mut paths = HashMap::new();
+        for (seq, path) in sequences.iter().zip(graph.get_unitig_paths_for_sequences(sequences)) {
+            let canonical_path = path.iter().map(|&(n, s)| to_canonical(n, s)).collect();
+            let path_str = path.iter().map(|&(n, s)| format!("{}{}", n, if s { "+" } else { "-" }))
+                               .collect::<Vec<_>>().join(",");
+            paths.insert((seq.filename.clone(), seq.contig_header.clone()),
+                         (canonical_path, path_str));
+        }
+        CanonicalGraph { unitigs, links, paths }
+    }
+}
+
+
+pub fn diff_graphs(old_graph: &UnitigGraph, old_seqs: &[Sequence],
+                   new_graph: &UnitigGraph, new_seqs: &[Sequence]) -> GraphDiff {
+    let old = CanonicalGraph::new(old_graph, old_seqs);
+    let new = CanonicalGraph::new(new_graph, new_seqs);
+    let mut graph_diff = GraphDiff {
+        removed_unitigs: only_in(&old.unitigs, &new.unitigs),
+        added_unitigs: only_in(&new.unitigs, &old.unitigs),
+        removed_links: only_in(&old.links, &new.links),
+        added_links: only_in(&new.links, &old.links),
+        ..Default::default()
+    };
+    let path_name = |(filename, header): &(String, String)| format!("{} {}", filename, header);
+    for (name, (old_path, old_str)) in &old.paths {
+        match new.paths.get(name) {
+            None => graph_diff.removed_paths.push(format!("{}: {}", path_name(name), old_str)),
+            Some((new_path, new_str)) => {
+                if old_path != new_path {
+                    graph_diff.changed_paths.push(format!("{}: {} -> {}", path_name(name),
+                                                          old_str, new_str));
+                }
+            }
+        }
+    }
+    for (name, (_, new_str)) in &new.paths {
+        if !old.paths.contains_key(name) {
+            graph_diff.added_paths.push(format!("{}: {}", path_name(name), new_str));
+        }
+    }
+    graph_diff.removed_paths.sort();
+    graph_diff.added_paths.sort();
+    graph_diff.changed_paths.sort();
+    graph_diff
+}
+
+
+fn only_in<K: std::hash::Hash + Eq>(a: &HashMap<K, String>, b: &HashMap<K, String>) -> Vec<String> {
+    // Returns the (sorted) descriptions of keys which are in a but not in b.
+    let mut descriptions: Vec<String> = a.iter().filter(|(k, _)| !b.contains_key(*k))
+                                         .map(|(_, v)| v.clone()).collect();
+    descriptions.sort();
+    descriptions
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_gfa::*;
+
+    fn renumber(gfa: &[String], mapping: &HashMap<&str, &str>) -> Vec<String> {
+        // Renumbers the segments in a GFA (without paths) to check that diff matches unitigs by
+        // sequence.
+        gfa.iter().map(|line| {
+            let mut parts: Vec<String> = line.split('\t').map(String::from).collect();
+            match parts[0].as_str() {
+                "S" => { parts[1] = mapping[parts[1].as_str()].to_string(); }
+                "L" => { parts[1] = mapping[parts[1].as_str()].to_string();
+                         parts[3] = mapping[parts[3].as_str()].to_string(); }
+                _ => {}
+            }
+            parts.join("\t")
+        }).collect()
+    }
+
+    #[test]
+    fn test_identical_graphs() {
+        let (graph_a, seqs_a) = UnitigGraph::from_gfa_lines(&get_test_gfa_14());
+        let (graph_b, seqs_b) = UnitigGraph::from_gfa_lines(&get_test_gfa_14());
+        assert!(diff_graphs(&graph_a, &seqs_a, &graph_b, &seqs_b).is_empty());
+    }
+
+    #[test]
+    fn test_renumbered_graph() {
+        let gfa = get_test_gfa_2();
+        let mapping = HashMap::from([("1", "3"), ("2", "1"), ("3", "2")]);
+        let (graph_a, seqs_a) = UnitigGraph::from_gfa_lines(&gfa);
+        let (graph_b, seqs_b) = UnitigGraph::from_gfa_lines(&renumber(&gfa, &mapping));
+        assert!(diff_graphs(&graph_a, &seqs_a, &graph_b, &seqs_b).is_empty());
+    }
+
+    #[test]
+    fn test_changed_graph() {
+        let (graph_a, seqs_a) = UnitigGraph::from_gfa_lines(&get_test_gfa_2());
+        let (mut graph_b, seqs_b) = UnitigGraph::from_gfa_lines(&get_test_gfa_2());
+        graph_b.delete_link(1, 2);
+        graph_b.remove_unitigs_by_number([3].into_iter().collect());
+        let graph_diff = diff_graphs(&graph_a, &seqs_a, &graph_b, &seqs_b);
+        assert_eq!(graph_diff.removed_unitigs, vec!["3 (4 bp)"]);
+        assert!(graph_diff.added_unitigs.is_empty());
+        assert_eq!(graph_diff.removed_links.len(), 3);
+        assert!(graph_diff.added_links.is_empty());
+    }
+
+    #[test]
+    fn test_changed_paths() {
+        let (graph_a, seqs_a) = UnitigGraph::from_gfa_lines(&get_test_gfa_14());
+        let (mut graph_b, mut seqs_b) = UnitigGraph::from_gfa_lines(&get_test_gfa_14());
+        graph_b.remove_sequence_from_graph(seqs_b[0].id);
+        seqs_b.remove(0);
+        let graph_diff = diff_graphs(&graph_a, &seqs_a, &graph_b, &seqs_b);
+        assert!(graph_diff.removed_unitigs.is_empty());
+        assert_eq!(graph_diff.removed_paths.len(), 1);
+        assert!(graph_diff.removed_paths[0].starts_with("a.fasta a_2: 8+,22+,17-"));
+        assert!(graph_diff.added_paths.is_empty());
+        assert!(graph_diff.changed_paths.is_empty());
+    }
+}
@@ -20,7 +20,7 @@ use std::path::{Path, PathBuf};
 
 use crate::log::{section_header, explanation};
 use crate::misc::{first_char_in_file, quit_with_error, reverse_complement, spinner,
-                  find_all_assemblies, load_fasta};
+                  find_all_assemblies, load_assembly, load_fasta};
 use crate::sequence::Sequence;
 use crate::unitig_graph::UnitigGraph;
 
@@ -160,12 +160,12 @@ fn load_from_fasta(filename: &Path) -> Vec<(FileSeqName, Vec<u8>)> {
 
 fn load_from_directory(dir: &Path) -> Vec<(FileSeqName, Vec<u8>)> {
     section_header("Loading sequences");
-    explanation("Sequences are now loaded from FASTA files in the provided directory.");
+    explanation("Sequences are now loaded from assembly files in the provided directory.");
     let mut seqs = Vec::new();
     let assemblies = find_all_assemblies(dir);
     for assembly in &assemblies {
         let filename = assembly.file_name().and_then(|name| name.to_str()).map(|s| s.to_string()).unwrap();
-        for (name, _, seq) in load_fasta(assembly) {
+        for (name, _, seq) in load_assembly(assembly, false, false).0 {
             eprintln!("{} {} ({} bp)", filename, name, seq.len());
             seqs.push((FileSeqName { filename: filename.clone(), seqname: name },
                        seq.as_bytes().to_owned()));
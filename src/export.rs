@@ -0,0 +1,115 @@
+// This file contains the code for the autocycler export subcommand.
+
+// Copyright 2024 Ryan Wick (rrwick@gmail.com)
+// https://github.com/rrwick/Autocycler
+
+// This file is part of Autocycler. Autocycler is free software: you can redistribute it and/or
+// modify it under the terms of the GNU General Public License as published by the Free Software
+// Foundation, either version 3 of the License, or (at your option) any later version. Autocycler
+// is distributed in the hope that it will be useful, but WITHOUT ANY WARRANTY; without even the
+// implied warranty of MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the GNU General
+// Public License for more details. You should have received a copy of the GNU General Public
+// License along with Autocycler. If not, see <http://www.gnu.org/licenses/>.
+
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+
+use crate::log::{section_header, explanation};
+use crate::misc::{check_if_file_exists, output_writer, quit_with_error, sign_at_end,
+                  sign_at_end_vec};
+use crate::sequence::Sequence;
+use crate::unitig_graph::UnitigGraph;
+
+
+pub fn export(in_gfa: PathBuf, out_file: PathBuf, format: String) {
+    check_settings(&in_gfa, &format);
+    starting_message();
+    print_settings(&in_gfa, &out_file, &format);
+    let (graph, sequences) = load_graph(&in_gfa);
+    save_graph(&graph, &sequences, &out_file, &format);
+    finished_message(&out_file);
+}
+
+
+fn check_settings(in_gfa: &Path, format: &str) {
+    check_if_file_exists(in_gfa);
+    if format != "dot" && format != "graphml" && format != "fasta" && format != "bubbles" {
+        quit_with_error("--format must be dot, graphml, fasta or bubbles");
+    }
+}
+
+
+fn starting_message() {
+    section_header("Starting autocycler export");
+    explanation("This command will take a unitig graph and save it in a different format, either \
+                 for viewing in other graph visualisation tools or as unitig sequences in FASTA \
+                 format.");
+}
+
+
+fn print_settings(in_gfa: &Path, out_file: &Path, format: &str) {
+    eprintln!("Settings:");
+    eprintln!("  --in_gfa {}", in_gfa.display());
+    eprintln!("  --out_file {}", out_file.display());
+    eprintln!("  --format {}", format);
+    eprintln!();
+}
+
+
+fn finished_message(out_file: &Path) {
+    section_header("Finished!");
+    eprintln!("Exported graph: {}", out_file.display());
+    eprintln!();
+}
+
+
+fn load_graph(gfa: &Path) -> (UnitigGraph, Vec<Sequence>) {
+    section_header("Loading graph");
+    explanation("The unitig graph is now loaded into memory.");
+    let (unitig_graph, sequences) = UnitigGraph::from_gfa_file(gfa);
+    unitig_graph.print_basic_graph_info();
+    (unitig_graph, sequences)
+}
+
+
+fn save_graph(graph: &UnitigGraph, sequences: &[Sequence], out_file: &Path, format: &str) {
+    section_header("Saving graph");
+    if format == "fasta" {
+        explanation("The unitig sequences are now saved, with headers containing each unitig's \
+                     number, length, depth and cluster.");
+    } else if format == "bubbles" {
+        explanation("Bubbles in the unitig graph are now saved to a TSV file, with one line per \
+                     bubble arm showing the input assemblies which support that arm.");
+    } else {
+        explanation("The unitig graph is now saved, with each strand of each unitig as a \
+                     separate node.");
+    }
+    let result = match format {
+        "dot"     => graph.save_dot(out_file),
+        "graphml" => graph.save_graphml(out_file),
+        "fasta"   => graph.save_unitig_fasta(out_file, sequences),
+        "bubbles" => save_bubbles_tsv(graph, sequences, out_file),
+        _         => unreachable!(),
+    };
+    if let Err(e) = result {
+        quit_with_error(&format!("unable to write {}\n{}", out_file.display(), e));
+    }
+}
+
+
+fn save_bubbles_tsv(graph: &UnitigGraph, sequences: &[Sequence], out_file: &Path)
+        -> io::Result<()> {
+    let bubbles = graph.find_bubbles(sequences);
+    let mut file = output_writer(out_file)?;
+    writeln!(file, "bubble\tstart\tend\tarm_path\tarm_length\tassemblies")?;
+    for (i, bubble) in bubbles.iter().enumerate() {
+        for arm in &bubble.arms {
+            writeln!(file, "{}\t{}\t{}\t{}\t{}\t{}", i + 1, sign_at_end(bubble.start),
+                     sign_at_end(bubble.end), sign_at_end_vec(&arm.path), arm.seq.len(),
+                     arm.assemblies.join(","))?;
+        }
+    }
+    eprintln!("{} bubble{} found", bubbles.len(), match bubbles.len() { 1 => "", _ => "s" });
+    eprintln!();
+    file.finish()
+}
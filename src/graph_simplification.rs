@@ -12,18 +12,38 @@
 // Public License for more details. You should have received a copy of the GNU General Public
 // License along with Autocycler. If not, see <http://www.gnu.org/licenses/>.
 
-use std::cell::RefCell;
 use std::collections::HashSet;
-use std::rc::Rc;
-
-use crate::misc::{reverse_complement, strand};
-use crate::sequence::Sequence;
-use crate::unitig::{Unitig, UnitigStrand};
-use crate::unitig_graph::UnitigGraph;
 
+use crate::metrics::SimplificationMetrics;
+use crate::misc::{edit_distance, reverse_complement, reverse_path, strand};
+use crate::poa::poa_consensus;
+use crate::sequence::{Sequence, sequence_checksum};
+use crate::unitig::{Ambiguity, Confidence, Unitig, UnitigStrand};
+use crate::unitig_graph::{Bubble, UnitigGraph};
+
+
+pub fn simplify_structure(graph: &mut UnitigGraph, seqs: &mut Vec<Sequence>, max_tip_len: u32,
+                          min_tip_depth: f64, metrics: &mut SimplificationMetrics) {
+    // Tips no longer than max_tip_len with a depth below min_tip_depth are clipped (a max_tip_len
+    // of 0 turns this off). These are usually dead ends made by ragged contig ends, and any
+    // sequences which start or end in them are trimmed to match.
+    if max_tip_len > 0 {
+        metrics.passes.push("clip_tips".to_string());
+        let unitig_count = graph.unitigs.len();
+        *seqs = graph.trim_tips(seqs, max_tip_len, min_tip_depth);
+        metrics.tips_clipped += unitig_count - graph.unitigs.len();
+        if graph.unitigs.len() < unitig_count {
+            metrics.unitigs_merged += merge_linear_paths(graph, seqs, None);
+        }
+    }
 
-pub fn simplify_structure(graph: &mut UnitigGraph, seqs: &Vec<Sequence>) {
-    while expand_repeats(graph, seqs) > 0 {}
+    metrics.passes.push("expand_repeats".to_string());
+    loop {
+        metrics.repeat_expansion_rounds += 1;
+        let shifted_bases = expand_repeats(graph, seqs);
+        if shifted_bases == 0 { break; }
+        metrics.repeat_expansion_shifted_bases += shifted_bases;
+    }
 
     // TODO: sometimes the simplified graph ends up with a little redundant dead-end contig. This
     //       occurs because graph simplification won't allow contigs to be shortened to 0-bp. So
@@ -39,7 +59,528 @@ pub fn simplify_structure(graph: &mut UnitigGraph, seqs: &Vec<Sequence>) {
 }
 
 
-fn expand_repeats(graph: &mut UnitigGraph, seqs: &Vec<Sequence>) -> usize {
+pub fn pop_bubbles(graph: &mut UnitigGraph, seqs: &[Sequence], max_len: u32, max_divergence: f64,
+                   poa: bool, metrics: &mut SimplificationMetrics) -> Vec<Sequence> {
+    // This function collapses simple bubbles (see UnitigGraph::find_bubbles) where every arm is
+    // no longer than max_len. Each arm which differs from the best-supported arm by no more than
+    // max_divergence (edit distance relative to the longer arm) is collapsed into it.
+    //
+    // For example, it will turn this (where the upper arm is supported by more sequences):
+    //                 TCGA
+    //                /    \
+    //    ACTACTCAACT      GCTACGACTAC
+    //                \    /
+    //                 TCCA
+    //
+    // Into this:
+    //    ACTACTCAACT - TCGA - GCTACGACTAC
+    //
+    // Sequence paths through a collapsed arm are rewritten to use the best arm instead (which
+    // changes their sequence), so they can still be reconstructed from the graph. The rewritten
+    // sequences are returned.
+    //
+    // If poa is true and the best arm is a single unitig, that unitig's sequence is replaced by a
+    // partial-order alignment consensus of all arms collapsed into it (weighted by support), so
+    // the result needn't match any one arm verbatim.
+    metrics.passes.push("pop_bubbles".to_string());
+    let bubbles = graph.find_bubbles(seqs);
+    let mut paths = graph.get_gapped_unitig_paths_i32(seqs);
+    let mut changed = vec![false; seqs.len()];
+    let mut collapsed_arms = 0;
+    for bubble in bubbles {
+        if bubble.arms.iter().any(|arm| arm.seq.len() > max_len as usize) { continue; }
+        let arm_paths: Vec<Vec<i32>> = bubble.arms.iter().map(|arm| {
+            [&[bubble.start], arm.path.as_slice(), &[bubble.end]].concat()
+        }).collect();
+        let support: Vec<f64> = arm_paths.iter()
+            .map(|arm_path| arm_support(&paths, seqs, arm_path)).collect();
+        let mut best = 0;
+        for i in 1..support.len() {
+            if support[i] > support[best] { best = i; }
+        }
+        if support[best] == 0.0 { continue; }
+        let mut collapsed = vec![best];
+        for i in 0..bubble.arms.len() {
+            if i == best { continue; }
+            if arm_divergence(&bubble.arms[i].seq, &bubble.arms[best].seq) > max_divergence {
+                continue;
+            }
+            collapsed.push(i);
+            for (path, changed) in paths.iter_mut().zip(changed.iter_mut()) {
+                let forward = replace_subpath(path, &arm_paths[i], &arm_paths[best]);
+                let reverse = replace_subpath(path, &reverse_path(&arm_paths[i]),
+                                              &reverse_path(&arm_paths[best]));
+                *changed |= forward || reverse;
+            }
+            collapsed_arms += 1;
+        }
+        if poa && polish_bubble_arm(graph, &bubble, &collapsed, &support) {
+            for (path, changed) in paths.iter_mut().zip(changed.iter_mut()) {
+                *changed |= passes_through(path, &arm_paths[best]);
+            }
+            metrics.bubble_arms_polished += 1;
+        }
+    }
+    if collapsed_arms == 0 {
+        return seqs.to_vec();
+    }
+    metrics.bubble_arms_collapsed += collapsed_arms;
+    apply_rewritten_paths(graph, seqs, &paths, &changed, metrics)
+}
+
+
+pub fn collapse_near_identical_unitigs(graph: &mut UnitigGraph, seqs: &[Sequence], max_diff: usize,
+                                       metrics: &mut SimplificationMetrics) -> Vec<Sequence> {
+    // This function looks for pairs of unitigs which share both their flanking unitigs and differ
+    // by no more than max_diff bases (edit distance), e.g. homopolymer-length differences between
+    // assemblers. The less-supported unitig is collapsed into the other, and the difference is
+    // recorded as an ambiguity on the kept unitig.
+    //
+    // For example, it will turn this (where the upper unitig is supported by more sequences):
+    //                 TCAAAAG
+    //                /       \
+    //    ACTACTCAACT         GCTACGACTAC
+    //                \       /
+    //                 TCAAAG
+    //
+    // Into this (with an ambiguity recording that bases 5..6 of the middle unitig can be absent):
+    //    ACTACTCAACT - TCAAAAG - GCTACGACTAC
+    //
+    // Sequence paths through a collapsed unitig are rewritten in the same way as for pop_bubbles.
+    metrics.passes.push("collapse_near_identical".to_string());
+    let bubbles = graph.find_bubbles(seqs);
+    let mut paths = graph.get_gapped_unitig_paths_i32(seqs);
+    let mut changed = vec![false; seqs.len()];
+    let mut collapsed = 0;
+    for bubble in bubbles {
+        if bubble.arms.len() != 2 || bubble.arms.iter().any(|arm| arm.path.len() != 1) { continue; }
+        if edit_distance(&bubble.arms[0].seq, &bubble.arms[1].seq) > max_diff { continue; }
+        let arm_paths: Vec<Vec<i32>> = bubble.arms.iter().map(|arm| {
+            vec![bubble.start, arm.path[0], bubble.end]
+        }).collect();
+        let support: Vec<f64> = arm_paths.iter()
+            .map(|arm_path| arm_support(&paths, seqs, arm_path)).collect();
+        let (keep, other) = if support[1] > support[0] { (1, 0) } else { (0, 1) };
+        if support[keep] == 0.0 { continue; }
+        for (path, changed) in paths.iter_mut().zip(changed.iter_mut()) {
+            let forward = replace_subpath(path, &arm_paths[other], &arm_paths[keep]);
+            let reverse = replace_subpath(path, &reverse_path(&arm_paths[other]),
+                                          &reverse_path(&arm_paths[keep]));
+            *changed |= forward || reverse;
+        }
+        let kept_number = bubble.arms[keep].path[0];
+        let kept_unitig = graph.unitig_mut(kept_number.unsigned_abs());
+        let ambiguity = get_ambiguity(&bubble.arms[keep].seq, &bubble.arms[other].seq,
+                                      support[other]);
+        let ambiguity = if kept_number > 0 { ambiguity }
+                        else { ambiguity.flipped(kept_unitig.length()) };
+        kept_unitig.ambiguities.push(ambiguity);
+        collapsed += 1;
+    }
+    if collapsed == 0 {
+        return seqs.to_vec();
+    }
+    metrics.near_identical_unitigs_collapsed += collapsed;
+    apply_rewritten_paths(graph, seqs, &paths, &changed, metrics)
+}
+
+
+fn get_ambiguity(seq: &[u8], alt: &[u8], depth: f64) -> Ambiguity {
+    // Trims any shared start/end from the two sequences, so the ambiguity only covers the part
+    // which differs.
+    let prefix = seq.iter().zip(alt).take_while(|(a, b)| a == b).count();
+    let max_suffix = seq.len().min(alt.len()) - prefix;
+    let suffix = seq.iter().rev().zip(alt.iter().rev()).take(max_suffix)
+        .take_while(|(a, b)| a == b).count();
+    Ambiguity {
+        start: prefix as u32,
+        end: (seq.len() - suffix) as u32,
+        alt: alt[prefix..alt.len() - suffix].to_vec(),
+        depth,
+    }
+}
+
+
+pub fn unroll_loops(graph: &mut UnitigGraph, seqs: &[Sequence], max_loop_len: u32,
+                    metrics: &mut SimplificationMetrics) -> (Vec<Sequence>, Vec<LoopCandidate>) {
+    // This function unrolls tandem repeat loops (see find_tandem_loop) no longer than
+    // max_loop_len. Each loop is replaced by a linear chain of copies with the number of passes
+    // through the loop that is best supported by the input sequences.
+    //
+    // For example, if most sequences go around this loop twice (1,2,3,2,3,2,4):
+    //                   GAT
+    //                  /   \
+    //    ACTACTCAACT - TCA - GCTACGACTAC
+    //
+    // It will become this (which is then merged into a single unitig):
+    //    ACTACTCAACT - TCA - GAT - TCA - GAT - TCA - GCTACGACTAC
+    //
+    // Sequence paths through an unrolled loop are rewritten to use the chain, which changes the
+    // sequence of any that went around a different number of times. Loops which a sequence
+    // starts or ends in are left alone. The paths are updated as each loop is unrolled, and the
+    // sequences are rebuilt on them once all loops are done. The rewritten sequences are
+    // returned, along with the linearised candidates for each loop (one per pass count found in
+    // the input sequences).
+    metrics.passes.push("unroll_loops".to_string());
+    let mut paths = graph.get_gapped_unitig_paths_i32(seqs);
+    let mut changed = vec![false; seqs.len()];
+    let mut candidates = Vec::new();
+    let mut checked = HashSet::new();
+    while let Some(tandem) = find_tandem_loop(graph, max_loop_len, &checked) {
+        checked.insert(tandem.cycle[0].unsigned_abs());
+        let Some(support) = loop_support(&tandem, &paths, seqs) else { continue; };
+        let passes = best_pass_count(&support);
+        candidates.extend(loop_candidates(graph, &tandem, &support, passes));
+        unroll_loop(graph, &mut paths, &mut changed, &tandem, passes);
+        metrics.loops_unrolled += 1;
+    }
+    if !changed.contains(&true) {
+        return (seqs.to_vec(), candidates);
+    }
+    (apply_rewritten_paths(graph, seqs, &paths, &changed, metrics), candidates)
+}
+
+
+pub struct LoopCandidate {
+    // One way of linearising a tandem repeat loop: from its entry unitig, around the loop the
+    // given number of times, to its exit unitig.
+    pub junction: u32,
+    pub passes: usize,
+    pub support: f64,  // summed weight of the input sequences which went around this many times
+    pub chosen: bool,
+    pub seq: Vec<u8>,
+}
+
+
+struct TandemLoop {
+    // The cycle starts with the junction unitig, which is entered from entry and exited to exit.
+    // Sequences pass through it like this: entry, cycle[0], (cycle[1..], cycle[0])*n, exit.
+    entry: i32,
+    exit: i32,
+    cycle: Vec<i32>,
+}
+
+
+fn find_tandem_loop(graph: &UnitigGraph, max_loop_len: u32, checked: &HashSet<u32>)
+        -> Option<TandemLoop> {
+    // Looks for a junction unitig with two inputs and two outputs, where one output leads back
+    // to one input via a chain of unitigs with single links (or directly, for a self-loop).
+    // Junctions in the checked set are skipped.
+    for u in &graph.unitigs {
+        let junction = u.number as i32;
+        if checked.contains(&junction.unsigned_abs()) { continue; }
+        let outputs = graph.outgoing_strands(junction);
+        let inputs = graph.incoming_strands(junction);
+        if outputs.len() != 2 || inputs.len() != 2 { continue; }
+        let cycles: Vec<_> = outputs.iter()
+            .map(|&o| follow_loop(graph, junction, o, max_loop_len)).collect();
+        let (cycle, exit) = match (&cycles[0], &cycles[1]) {
+            (Some(c), None) => (c.clone(), outputs[1]),
+            (None, Some(c)) => (c.clone(), outputs[0]),
+            _ => continue,
+        };
+        let last = *cycle.last().unwrap();
+        let entry = match inputs.iter().position(|&i| i == last) {
+            Some(0) => inputs[1],
+            Some(_) => inputs[0],
+            None => continue,
+        };
+        let in_cycle = |n: i32| cycle.iter().any(|c| c.unsigned_abs() == n.unsigned_abs());
+        if in_cycle(entry) || in_cycle(exit) || entry.unsigned_abs() == exit.unsigned_abs() {
+            continue;
+        }
+        return Some(TandemLoop { entry, exit, cycle });
+    }
+    None
+}
+
+
+fn follow_loop(graph: &UnitigGraph, junction: i32, first: i32, max_loop_len: u32)
+        -> Option<Vec<i32>> {
+    // Follows single-link unitigs from the junction's output, returning the cycle (starting
+    // with the junction) if they lead back to the junction within max_loop_len.
+    let mut cycle = vec![junction];
+    let mut length = graph.unitig(junction.unsigned_abs()).length();
+    let mut current = first;
+    while current != junction {
+        if cycle.iter().any(|c| c.unsigned_abs() == current.unsigned_abs()) { return None; }
+        if graph.incoming_strands(current).len() != 1 { return None; }
+        let outputs = graph.outgoing_strands(current);
+        if outputs.len() != 1 { return None; }
+        length += graph.unitig(current.unsigned_abs()).length();
+        if length > max_loop_len { return None; }
+        cycle.push(current);
+        current = outputs[0];
+    }
+    if length > max_loop_len { return None; }
+    Some(cycle)
+}
+
+
+fn find_loop_runs(path: &[i32], tandem: &TandemLoop) -> Option<Vec<(usize, usize, usize)>> {
+    // Returns each pass of the path through the loop as (index of entry, index of exit, number of
+    // times around the loop). Returns None if the path has any loop unitigs which are not part
+    // of a complete pass from entry to exit.
+    let junction = tandem.cycle[0];
+    let rest = &tandem.cycle[1..];
+    let mut runs = Vec::new();
+    let mut covered = 0;
+    let mut i = 0;
+    while i < path.len() {
+        if path[i] != junction {
+            i += 1;
+            continue;
+        }
+        if i == 0 || path[i - 1] != tandem.entry { return None; }
+        let mut j = i + 1;
+        let mut count = 0;
+        while j + rest.len() < path.len() && path[j..j + rest.len()] == *rest &&
+                path[j + rest.len()] == junction {
+            count += 1;
+            j += rest.len() + 1;
+        }
+        if j >= path.len() || path[j] != tandem.exit { return None; }
+        runs.push((i - 1, j, count));
+        covered += 1 + count * tandem.cycle.len();
+        i = j;
+    }
+    let loop_unitigs = path.iter()
+        .filter(|n| tandem.cycle.iter().any(|c| c.unsigned_abs() == n.unsigned_abs())).count();
+    if loop_unitigs != covered { return None; }
+    Some(runs)
+}
+
+
+fn oriented_path(path: &[i32], tandem: &TandemLoop) -> Option<(Vec<i32>, bool)> {
+    // Returns the path in the same orientation as the loop, and whether it was reversed. Returns
+    // None if the path does not use the loop.
+    let junction = tandem.cycle[0];
+    if path.contains(&junction) {
+        Some((path.to_vec(), false))
+    } else if path.contains(&-junction) {
+        Some((reverse_path(path), true))
+    } else {
+        None
+    }
+}
+
+
+fn loop_support(tandem: &TandemLoop, paths: &[Vec<i32>], seqs: &[Sequence]) -> Option<Vec<f64>> {
+    // Returns the support (summed sequence weights) for each number of times around the loop, or
+    // None if the loop can't be unrolled.
+    let mut support: Vec<f64> = Vec::new();
+    for (path, seq) in paths.iter().zip(seqs) {
+        let Some((path, _)) = oriented_path(path, tandem) else { continue; };
+        if path.contains(&-tandem.cycle[0]) { return None; }
+        for (_, _, count) in find_loop_runs(&path, tandem)? {
+            if support.len() <= count { support.resize(count + 1, 0.0); }
+            support[count] += seq.weight;
+        }
+    }
+    if support.iter().all(|&s| s <= 0.0) { return None; }
+    Some(support)
+}
+
+
+fn best_pass_count(support: &[f64]) -> usize {
+    // Returns the number of times around the loop with the most support, ties going to the lower
+    // count.
+    let mut best = 0;
+    for (count, &s) in support.iter().enumerate() {
+        if s > support[best] { best = count; }
+    }
+    best
+}
+
+
+fn loop_chain(tandem: &TandemLoop, passes: usize) -> Vec<i32> {
+    // The loop's unitigs for the given number of passes, from the junction to the junction.
+    let mut chain = vec![tandem.cycle[0]];
+    for _ in 0..passes {
+        chain.extend_from_slice(&tandem.cycle[1..]);
+        chain.push(tandem.cycle[0]);
+    }
+    chain
+}
+
+
+fn loop_candidates(graph: &UnitigGraph, tandem: &TandemLoop, support: &[f64], passes: usize)
+        -> Vec<LoopCandidate> {
+    support.iter().enumerate().filter(|(_, &s)| s > 0.0).map(|(count, &s)| {
+        let mut path = vec![tandem.entry];
+        path.extend(loop_chain(tandem, count));
+        path.push(tandem.exit);
+        LoopCandidate { junction: tandem.cycle[0].unsigned_abs(), passes: count, support: s,
+                        chosen: count == passes, seq: graph.get_sequence_from_path_signed(&path) }
+    }).collect()
+}
+
+
+fn unroll_loop(graph: &mut UnitigGraph, paths: &mut [Vec<i32>], changed: &mut [bool],
+               tandem: &TandemLoop, passes: usize) {
+    // Replaces the loop with a chain which goes around it the given number of times, using the
+    // original unitigs for the first pass and copies for the rest, and updates the paths through
+    // it (marking them as changed).
+    let cycle = &tandem.cycle;
+    let mut chain = vec![cycle[0]];
+    let mut next_number = graph.max_unitig_number() + 1;
+    for (i, &c) in loop_chain(tandem, passes).iter().enumerate().skip(1) {
+        if i < cycle.len() {
+            chain.push(c);
+            continue;
+        }
+        let u = graph.unitig(c.unsigned_abs());
+        let copy = Unitig {
+            number: next_number,
+            forward_seq: u.forward_seq.clone(),
+            reverse_seq: u.reverse_seq.clone(),
+            ambiguities: u.ambiguities.clone(),
+            ..Default::default()
+        };
+        graph.add_unitig(copy);
+        chain.push(if c > 0 { next_number as i32 } else { -(next_number as i32) });
+        next_number += 1;
+    }
+    for i in 0..cycle.len() {
+        graph.delete_link(cycle[i], cycle[(i + 1) % cycle.len()]);
+    }
+    graph.delete_link(cycle[0], tandem.exit);
+    for pair in chain.windows(2) {
+        graph.create_link(pair[0], pair[1]);
+    }
+    graph.create_link(*chain.last().unwrap(), tandem.exit);
+
+    for (path, changed) in paths.iter_mut().zip(changed.iter_mut()) {
+        let Some((oriented, reversed)) = oriented_path(path, tandem) else { continue; };
+        let runs = find_loop_runs(&oriented, tandem).unwrap();
+        let mut new_path = Vec::with_capacity(oriented.len());
+        let mut i = 0;
+        for (start, end, _) in runs {
+            new_path.extend_from_slice(&oriented[i..=start]);
+            new_path.extend_from_slice(&chain);
+            i = end;
+        }
+        new_path.extend_from_slice(&oriented[i..]);
+        *path = if reversed { reverse_path(&new_path) } else { new_path };
+        *changed = true;
+    }
+}
+
+
+fn apply_rewritten_paths(graph: &mut UnitigGraph, seqs: &[Sequence], paths: &[Vec<i32>],
+                         changed: &[bool], metrics: &mut SimplificationMetrics) -> Vec<Sequence> {
+    // Rebuilds the changed sequences on their new paths, then cleans up the unitigs which no
+    // longer have any sequences and the linear paths left behind.
+    let new_seqs = rewrite_sequence_paths(graph, seqs, paths, changed);
+    let unitig_count = graph.unitigs.len();
+    graph.remove_zero_depth_unitigs();
+    metrics.unitigs_removed += unitig_count - graph.unitigs.len();
+    metrics.unitigs_merged += merge_linear_paths(graph, &new_seqs, None);
+    new_seqs
+}
+
+
+fn passes_through(path: &[i32], arm_path: &[i32]) -> bool {
+    // Returns whether the path passes through the given bubble arm on either strand.
+    let reverse_arm_path = reverse_path(arm_path);
+    path.windows(arm_path.len()).any(|w| w == arm_path || w == reverse_arm_path.as_slice())
+}
+
+
+fn arm_support(paths: &[Vec<i32>], seqs: &[Sequence], arm_path: &[i32]) -> f64 {
+    // Returns the total weight of sequences whose paths pass through the given bubble arm (which
+    // includes the bubble's start and end), on either strand.
+    let reverse_arm_path = reverse_path(arm_path);
+    paths.iter().zip(seqs).map(|(path, seq)| {
+        let count = path.windows(arm_path.len())
+            .filter(|w| *w == arm_path || *w == reverse_arm_path.as_slice()).count();
+        count as f64 * seq.weight
+    }).fold(0.0, |a, b| a + b)
+}
+
+
+fn polish_bubble_arm(graph: &mut UnitigGraph, bubble: &Bubble, collapsed: &[usize],
+                     support: &[f64]) -> bool {
+    // Replaces the sequence of the bubble's best arm (the first of collapsed) with a POA consensus
+    // of the collapsed arms, returning whether it changed. This is only done when the best arm is
+    // a single unitig and there are no empty arms, which can't take part in an alignment.
+    let best = &bubble.arms[collapsed[0]];
+    if collapsed.len() < 2 || best.path.len() != 1 ||
+       collapsed.iter().any(|&i| bubble.arms[i].seq.is_empty()) {
+        return false;
+    }
+    let mut order = collapsed[1..].to_vec();
+    order.sort_by(|&a, &b| support[b].total_cmp(&support[a]));
+    order.insert(0, collapsed[0]);
+    let arm_seqs: Vec<(&[u8], f64)> = order.iter()
+        .map(|&i| (bubble.arms[i].seq.as_slice(), support[i])).collect();
+    let consensus = poa_consensus(&arm_seqs);
+    if consensus == best.seq {
+        return false;
+    }
+    let unitig = graph.unitig_mut(best.path[0].unsigned_abs());
+    unitig.forward_seq = if best.path[0] > 0 { consensus } else { reverse_complement(&consensus) };
+    unitig.reverse_seq = reverse_complement(&unitig.forward_seq);
+    true
+}
+
+
+fn arm_divergence(a: &[u8], b: &[u8]) -> f64 {
+    let max_len = a.len().max(b.len());
+    if max_len == 0 { return 0.0; }
+    edit_distance(a, b) as f64 / max_len as f64
+}
+
+
+fn replace_subpath(path: &mut Vec<i32>, old: &[i32], new: &[i32]) -> bool {
+    // Replaces each occurrence of old in the path with new, returning whether any were replaced.
+    // Both start and end with the same unitigs, so the end of one replacement can begin another.
+    let mut replaced = false;
+    let mut i = 0;
+    while i + old.len() <= path.len() {
+        if path[i..i + old.len()] == *old {
+            path.splice(i..i + old.len(), new.iter().copied());
+            i += new.len() - 1;
+            replaced = true;
+        } else {
+            i += 1;
+        }
+    }
+    replaced
+}
+
+
+fn rewrite_sequence_paths(graph: &mut UnitigGraph, seqs: &[Sequence], paths: &[Vec<i32>],
+                          changed: &[bool]) -> Vec<Sequence> {
+    // Replaces the Positions of each changed sequence with ones for its new path. The paths have
+    // a 0 for each repair (see get_gapped_unitig_paths_i32), which stays in place between the
+    // unitigs around it. Unchanged sequences are returned as they are, and changed ones keep
+    // their original name (if renamed) and line width. A changed sequence only keeps its checksum
+    // if its new path still spells the same bases (e.g. a loop unrolled to its own pass count).
+    let mut new_seqs = Vec::with_capacity(seqs.len());
+    for ((seq, path), &changed) in seqs.iter().zip(paths).zip(changed) {
+        if !changed {
+            new_seqs.push(seq.clone());
+            continue;
+        }
+        graph.remove_sequence_from_graph(seq.id);
+        let (path, length) = graph.set_repair_positions(seq.id, path);
+        let mut new_seq = graph.create_sequence_and_positions(
+            seq.id, length, seq.filename.clone(), seq.contig_header.clone(), seq.cluster,
+            path.clone());
+        new_seq.original_name = seq.original_name.clone();
+        new_seq.line_width = seq.line_width;
+        new_seq.checksum = seq.checksum.filter(|&checksum| length as usize == seq.length &&
+            sequence_checksum(graph.get_repaired_sequence_from_path(&new_seq, &path).as_bytes())
+                == checksum);
+        new_seqs.push(new_seq);
+    }
+    graph.recalculate_depths();
+    new_seqs
+}
+
+
+fn expand_repeats(graph: &mut UnitigGraph, seqs: &[Sequence]) -> usize {
     // This function simplifies the graph structure by expanding repeats.
     //
     // For example, it will turn this:
@@ -60,24 +601,24 @@ fn expand_repeats(graph: &mut UnitigGraph, seqs: &Vec<Sequence>) -> usize {
     // start/ends of such paths. The return value is the total amount of sequence shifted.
     let (fixed_starts, fixed_ends) = get_fixed_unitig_starts_and_ends(graph, seqs);
     let mut total_shifted_seq = 0;
-    for unitig_rc in &graph.unitigs {
-        let unitig_number = unitig_rc.borrow().number;
-        let inputs = get_exclusive_inputs(unitig_rc);
+    for handle in 0..graph.unitigs.len() {
+        let unitig_number = graph.unitigs[handle].number;
+        let inputs = get_exclusive_inputs(graph, handle);
         if inputs.len() >= 2 && !fixed_starts.contains(&unitig_number) {
             let can_shift = inputs.iter().all(|input| {
-                !(input.strand && fixed_ends.contains(&input.number()) ||
-                !input.strand && fixed_starts.contains(&input.number()))});
+                !(input.strand && fixed_ends.contains(&graph.number(input)) ||
+                !input.strand && fixed_starts.contains(&graph.number(input)))});
             if can_shift {
-                total_shifted_seq += shift_sequence_1(&inputs, unitig_rc);
+                total_shifted_seq += shift_sequence_1(graph, &inputs, handle);
             }
         }
-        let outputs = get_exclusive_outputs(unitig_rc);
+        let outputs = get_exclusive_outputs(graph, handle);
         if outputs.len() >= 2 && !fixed_ends.contains(&unitig_number) {
             let can_shift = outputs.iter().all(|output| {
-                !(output.strand && fixed_starts.contains(&output.number()) ||
-                !output.strand && fixed_ends.contains(&output.number()))});
+                !(output.strand && fixed_starts.contains(&graph.number(output)) ||
+                !output.strand && fixed_ends.contains(&graph.number(output)))});
             if can_shift {
-                total_shifted_seq += shift_sequence_2(unitig_rc, &outputs);
+                total_shifted_seq += shift_sequence_2(graph, handle, &outputs);
             }
         }
     }
@@ -85,7 +626,8 @@ fn expand_repeats(graph: &mut UnitigGraph, seqs: &Vec<Sequence>) -> usize {
 }
 
 
-fn shift_sequence_1(sources: &Vec<UnitigStrand>, destination_rc: &Rc<RefCell<Unitig>>) -> usize {
+fn shift_sequence_1(graph: &mut UnitigGraph, sources: &[UnitigStrand], destination: usize)
+        -> usize {
     // This function:
     // * removes any common sequence from the ends of the source unitigs
     // * adds that common sequence to the start of the destination unitig
@@ -99,49 +641,51 @@ fn shift_sequence_1(sources: &Vec<UnitigStrand>, destination_rc: &Rc<RefCell<Uni
     //   start of a path.
     //
     // The return value is the amount of sequence shifted.
-    let mut common_seq = get_common_end_seq(sources);
-    avoid_zero_len_unitigs(&mut common_seq, sources, true);
-    avoid_start_of_path(&mut common_seq, destination_rc, true);
+    let mut common_seq = get_common_end_seq(graph, sources);
+    avoid_zero_len_unitigs(graph, &mut common_seq, sources, true);
+    avoid_start_of_path(&mut common_seq, &graph.unitigs[destination], true);
     let shifted_amount = common_seq.len();
     if shifted_amount == 0 {
         return 0;
     }
     for source in sources {
         if source.strand {
-            source.unitig.borrow_mut().remove_seq_from_end(shifted_amount);
+            graph.unitigs[source.unitig].remove_seq_from_end(shifted_amount);
         } else {
-            source.unitig.borrow_mut().remove_seq_from_start(shifted_amount);
+            graph.unitigs[source.unitig].remove_seq_from_start(shifted_amount);
         }
     }
-    destination_rc.borrow_mut().add_seq_to_start(common_seq);
+    graph.unitigs[destination].add_seq_to_start(common_seq);
     shifted_amount
 }
 
 
-fn shift_sequence_2(destination_rc: &Rc<RefCell<Unitig>>, sources: &[UnitigStrand]) -> usize {
+fn shift_sequence_2(graph: &mut UnitigGraph, destination: usize, sources: &[UnitigStrand])
+        -> usize {
     // This function does the same thing as shift_sequence_1, but for the other side of a unitig:
     // * removes any common sequence from the starts of the source unitigs
     // * adds that common sequence to the end of the destination unitig
-    let mut common_seq = get_common_start_seq(sources);
-    avoid_zero_len_unitigs(&mut common_seq, sources, false);
-    avoid_start_of_path(&mut common_seq, destination_rc, false);
+    let mut common_seq = get_common_start_seq(graph, sources);
+    avoid_zero_len_unitigs(graph, &mut common_seq, sources, false);
+    avoid_start_of_path(&mut common_seq, &graph.unitigs[destination], false);
     let shifted_amount = common_seq.len();
     if shifted_amount == 0 {
         return 0;
     }
     for source in sources {
         if source.strand {
-            source.unitig.borrow_mut().remove_seq_from_start(shifted_amount);
+            graph.unitigs[source.unitig].remove_seq_from_start(shifted_amount);
         } else {
-            source.unitig.borrow_mut().remove_seq_from_end(shifted_amount);
+            graph.unitigs[source.unitig].remove_seq_from_end(shifted_amount);
         }
     }
-    destination_rc.borrow_mut().add_seq_to_end(common_seq);
+    graph.unitigs[destination].add_seq_to_end(common_seq);
     shifted_amount
 }
 
 
-fn avoid_zero_len_unitigs(common_seq: &mut Vec<u8>, sources: &[UnitigStrand], trim_from_start: bool) {
+fn avoid_zero_len_unitigs(graph: &UnitigGraph, common_seq: &mut Vec<u8>, sources: &[UnitigStrand],
+                          trim_from_start: bool) {
     // This function takes some common sequence (sequence that will be shifted from some unitigs
     // onto another) and trims it down to ensure that none of the source unitigs will end up with
     // a length of zero.
@@ -149,7 +693,8 @@ fn avoid_zero_len_unitigs(common_seq: &mut Vec<u8>, sources: &[UnitigStrand], tr
         return;
     }
     let dup = if check_for_duplicates(sources) { 2 } else { 1 };
-    let min_source_len = sources.iter().map(|source| source.length()).min().unwrap();
+    let min_source_len = sources.iter().map(|source| graph.unitigs[source.unitig].length())
+                                .min().unwrap();
     while min_source_len <= (common_seq.len() as u32) * dup {
         if trim_from_start {
             common_seq.remove(0);
@@ -160,8 +705,7 @@ fn avoid_zero_len_unitigs(common_seq: &mut Vec<u8>, sources: &[UnitigStrand], tr
 }
 
 
-fn avoid_start_of_path(common_seq: &mut Vec<u8>, dest_rc: &Rc<RefCell<Unitig>>,
-                       trim_from_start: bool) {
+fn avoid_start_of_path(common_seq: &mut Vec<u8>, dest: &Unitig, trim_from_start: bool) {
     // This function takes some common sequence (sequence that will be shifted from some unitigs
     // onto another) and trims it down to ensure that the destination unitig's positions will not
     // end up at the start of a path, as this can cause problems.
@@ -169,11 +713,11 @@ fn avoid_start_of_path(common_seq: &mut Vec<u8>, dest_rc: &Rc<RefCell<Unitig>>,
         return;
     }
     if trim_from_start {
-        while dest_rc.borrow().forward_positions.iter().any(|p| p.pos <= common_seq.len() as u32) {
+        while dest.forward_positions.iter().any(|p| p.pos <= common_seq.len() as u32) {
             common_seq.remove(0);
         }
     } else {
-        while dest_rc.borrow().reverse_positions.iter().any(|p| p.pos <= common_seq.len() as u32) {
+        while dest.reverse_positions.iter().any(|p| p.pos <= common_seq.len() as u32) {
             common_seq.pop();
         }
     }
@@ -181,47 +725,48 @@ fn avoid_start_of_path(common_seq: &mut Vec<u8>, dest_rc: &Rc<RefCell<Unitig>>,
 
 
 fn check_for_duplicates(unitigs: &[UnitigStrand]) -> bool {
-    // Returns true if any two unitigs in the vector have the same number.
-    unitigs.iter().map(|u| u.number()).collect::<HashSet<_>>().len() != unitigs.len()
+    // Returns true if any two unitig strands in the vector are on the same unitig.
+    unitigs.iter().map(|u| u.unitig).collect::<HashSet<_>>().len() != unitigs.len()
 }
 
 
 fn get_fixed_unitig_starts_and_ends(graph: &UnitigGraph,
-                                    sequences: &Vec<Sequence>) -> (HashSet<u32>, HashSet<u32>) {
+                                    sequences: &[Sequence]) -> (HashSet<u32>, HashSet<u32>) {
     // Returns two sets of unitig IDs: all unitigs where the start can't be changed and all
     // unitigs where the end can't be changed. All results are in terms of the unitig's forward
     // strand.
     let mut fixed_starts = HashSet::new();
     let mut fixed_ends = HashSet::new();
 
-    // The starts/end of sequence paths are fixed.
-    for seq in sequences {
-        let unitig_path = graph.get_unitig_path_for_sequence(seq);
-        if unitig_path.is_empty() { continue; }
-        let (first_unitig, first_strand) = unitig_path[0];
-        if first_strand { fixed_starts.insert(first_unitig); }
-                   else { fixed_ends.insert(first_unitig); }
-        let (last_unitig, last_strand) = unitig_path.last().unwrap();
-        if *last_strand { fixed_ends.insert(*last_unitig); }
-                   else { fixed_starts.insert(*last_unitig); }
+    // The starts/end of sequence paths are fixed, as are those next to a repair (a 0 in the
+    // path), since the repair's bases must stay where they are.
+    for gapped_path in graph.get_gapped_unitig_paths_i32(sequences) {
+        for unitig_path in gapped_path.split(|&n| n == 0).filter(|p| !p.is_empty()) {
+            let first = unitig_path[0];
+            if first > 0 { fixed_starts.insert(first.unsigned_abs()); }
+                     else { fixed_ends.insert(first.unsigned_abs()); }
+            let last = *unitig_path.last().unwrap();
+            if last > 0 { fixed_ends.insert(last.unsigned_abs()); }
+                    else { fixed_starts.insert(last.unsigned_abs()); }
+        }
     }
 
     let fixed_starts_copy = fixed_starts.clone();
     let fixed_ends_copy = fixed_ends.clone();
 
     // Any unitig which is upstream of a fixed start has a fixed end.
-    for u in &fixed_starts_copy {
-        for upstream in &graph.unitig_index.get(u).unwrap().borrow().forward_prev {
-            if upstream.strand { fixed_ends.insert(upstream.number()); }
-                          else { fixed_starts.insert(upstream.number()); }
+    for &u in &fixed_starts_copy {
+        for upstream in &graph.unitig(u).forward_prev {
+            if upstream.strand { fixed_ends.insert(graph.number(upstream)); }
+                          else { fixed_starts.insert(graph.number(upstream)); }
         }
     }
 
     // Any unitig which is downstream of a fixed end has a fixed start.
-    for u in &fixed_ends_copy {
-        for downstream in &graph.unitig_index.get(u).unwrap().borrow().forward_next {
-            if downstream.strand { fixed_starts.insert(downstream.number()); }
-                            else { fixed_ends.insert(downstream.number()); }
+    for &u in &fixed_ends_copy {
+        for downstream in &graph.unitig(u).forward_next {
+            if downstream.strand { fixed_starts.insert(graph.number(downstream)); }
+                            else { fixed_ends.insert(graph.number(downstream)); }
         }
     }
 
@@ -229,61 +774,57 @@ fn get_fixed_unitig_starts_and_ends(graph: &UnitigGraph,
 }
 
 
-fn get_exclusive_inputs(unitig_rc: &Rc<RefCell<Unitig>>) -> Vec<UnitigStrand> {
+fn get_exclusive_inputs(graph: &UnitigGraph, handle: usize) -> Vec<UnitigStrand> {
     // This function returns a vector of unitigs which exclusively input to the given unitig.
     // Exclusive input means the unitig leads only to the given unitig. If any of the given
     // unitig's inputs are not exclusive inputs, then this function returns an empty vector.
     let mut inputs = Vec::new();
-    let unitig = unitig_rc.borrow();
-    for prev in &unitig.forward_prev {
-        let prev_unitig = &prev.unitig.borrow();
+    for &prev in &graph.unitigs[handle].forward_prev {
+        let prev_unitig = &graph.unitigs[prev.unitig];
         let prev_next_unitigs = if prev.strand { &prev_unitig.forward_next } else { &prev_unitig.reverse_next };
         if prev_next_unitigs.len() != 1 {
             return Vec::new();
         }
-        let prev_next = &prev_next_unitigs[0];
-        if prev_next.strand && prev_next.number() == unitig.number {
-            inputs.push(UnitigStrand::new(&prev.unitig, prev.strand));
+        if prev_next_unitigs[0] == UnitigStrand::new(handle, strand::FORWARD) {
+            inputs.push(prev);
         } else {
             return Vec::new();
         }
     }
-    if inputs.iter().any(|input| { input.number() == unitig.number }) {
+    if inputs.iter().any(|input| { input.unitig == handle }) {
         return Vec::new();
     }
     inputs
 }
 
 
-fn get_exclusive_outputs(unitig_rc: &Rc<RefCell<Unitig>>) -> Vec<UnitigStrand> {
+fn get_exclusive_outputs(graph: &UnitigGraph, handle: usize) -> Vec<UnitigStrand> {
     // This function returns a vector of unitigs which exclusively output from the given unitig.
     // Exclusive output means the given unitig leads only to the unitig. If any of the given
     // unitig's outputs are not exclusive outputs, then this function returns an empty vector.
     let mut outputs = Vec::new();
-    let unitig = unitig_rc.borrow();
-    for next in &unitig.forward_next {
-        let next_unitig = &next.unitig.borrow();
+    for &next in &graph.unitigs[handle].forward_next {
+        let next_unitig = &graph.unitigs[next.unitig];
         let next_prev_unitigs = if next.strand { &next_unitig.forward_prev } else { &next_unitig.reverse_prev };
         if next_prev_unitigs.len() != 1 {
             return Vec::new();
         }
-        let next_prev = &next_prev_unitigs[0];
-        if next_prev.strand && next_prev.number() == unitig.number {
-            outputs.push(UnitigStrand::new(&next.unitig, next.strand));
+        if next_prev_unitigs[0] == UnitigStrand::new(handle, strand::FORWARD) {
+            outputs.push(next);
         } else {
             return Vec::new();
         }
     }
-    if outputs.iter().any(|output| { output.number() == unitig.number }) {
+    if outputs.iter().any(|output| { output.unitig == handle }) {
         return Vec::new();
     }
     outputs
 }
 
 
-fn get_common_start_seq(unitigs: &[UnitigStrand]) -> Vec<u8> {
+fn get_common_start_seq(graph: &UnitigGraph, unitigs: &[UnitigStrand]) -> Vec<u8> {
     // This function returns the common sequence at the start of all given unitigs.
-    let seqs: Vec<_> = unitigs.iter().map(|u| u.get_seq()).collect();
+    let seqs: Vec<_> = unitigs.iter().map(|u| graph.unitigs[u.unitig].get_seq(u.strand)).collect();
     if seqs.is_empty() { return Vec::new(); }
     let mut prefix = seqs[0].clone();
     for seq in seqs.iter() {
@@ -296,9 +837,9 @@ fn get_common_start_seq(unitigs: &[UnitigStrand]) -> Vec<u8> {
 }
 
 
-fn get_common_end_seq(unitigs: &[UnitigStrand]) -> Vec<u8> {
+fn get_common_end_seq(graph: &UnitigGraph, unitigs: &[UnitigStrand]) -> Vec<u8> {
     // This function returns the common sequence at the end of all given unitigs.
-    let seqs: Vec<Vec<u8>> = unitigs.iter().map(|u| u.get_seq())
+    let seqs: Vec<Vec<u8>> = unitigs.iter().map(|u| graph.unitigs[u.unitig].get_seq(u.strand))
         .map(|mut seq| { seq.reverse(); seq }).collect();
     if seqs.is_empty() { return Vec::new(); }
     let mut suffix = seqs[0].clone();
@@ -313,7 +854,8 @@ fn get_common_end_seq(unitigs: &[UnitigStrand]) -> Vec<u8> {
 }
 
 
-pub fn merge_linear_paths(graph: &mut UnitigGraph, seqs: &Vec<Sequence>, depth: Option<f64>) {
+pub fn merge_linear_paths(graph: &mut UnitigGraph, seqs: &[Sequence], depth: Option<f64>)
+        -> usize {
     // This function looks for linear paths in the graph (where one Unitig leads only to another
     // and vice versa) and merges them together when possible.
     //
@@ -325,33 +867,33 @@ pub fn merge_linear_paths(graph: &mut UnitigGraph, seqs: &Vec<Sequence>, depth:
     //
     // To avoid messing with input sequence paths, this function will not merge sequences at the
     // start/ends of such paths. If no sequences are provided, then all possible linear paths will
-    // be merged.
+    // be merged. The return value is the reduction in unitig count.
     let (mut fixed_starts, fixed_ends) = get_fixed_unitig_starts_and_ends(graph, seqs);
     fix_circular_loops(graph, &mut fixed_starts);
     let mut already_used = HashSet::new();
     let mut merge_paths = Vec::new();
-    for unitig_rc in &graph.unitigs {
-        let unitig_number = unitig_rc.borrow().number;
+    for handle in 0..graph.unitigs.len() {
+        let unitig_number = graph.unitigs[handle].number;
         for unitig_strand in [strand::FORWARD, strand::REVERSE] {
 
             // Find unitigs which can potentially start a mergeable path.
             if already_used.contains(&unitig_number) { continue; }
-            if has_single_exclusive_input(unitig_rc, unitig_strand) && can_merge_start(unitig_number, unitig_strand, &fixed_starts, &fixed_ends) { continue; }
-            let mut current_path = vec![UnitigStrand::new(unitig_rc, unitig_strand)];
+            if has_single_exclusive_input(graph, handle, unitig_strand) && can_merge_start(unitig_number, unitig_strand, &fixed_starts, &fixed_ends) { continue; }
+            let mut current_path = vec![UnitigStrand::new(handle, unitig_strand)];
             already_used.insert(unitig_number);
 
             // Extend the path as far as possible.
             loop {
-                let unitig = current_path.last().unwrap();
-                if cannot_merge_end(unitig.number(), unitig.strand, &fixed_starts, &fixed_ends) { break; }
-                let mut outputs = if unitig.strand { get_exclusive_outputs(&unitig.unitig) } else { get_exclusive_inputs(&unitig.unitig) };
+                let unitig = *current_path.last().unwrap();
+                if cannot_merge_end(graph.number(&unitig), unitig.strand, &fixed_starts, &fixed_ends) { break; }
+                let mut outputs = if unitig.strand { get_exclusive_outputs(graph, unitig.unitig) } else { get_exclusive_inputs(graph, unitig.unitig) };
                 if outputs.len() != 1 { break; }
                 let output = &mut outputs[0];
                 if !unitig.strand { output.strand = !output.strand; }
-                let output_number = output.number();
+                let output_number = graph.number(output);
                 if already_used.contains(&output_number) { break; }
                 if cannot_merge_start(output_number, output.strand, &fixed_starts, &fixed_ends) { break; }
-                current_path.push(UnitigStrand::new(&output.unitig, output.strand));
+                current_path.push(*output);
                 already_used.insert(output_number);
             }
 
@@ -361,14 +903,20 @@ pub fn merge_linear_paths(graph: &mut UnitigGraph, seqs: &Vec<Sequence>, depth:
         }
     }
 
+    // The merged unitigs are removed after all paths are merged, as removing unitigs changes
+    // the handles which the remaining paths refer to.
     let mut new_unitig_number: u32 = graph.max_unitig_number();
+    let mut merged_count = 0;
+    let mut merged_numbers = HashSet::new();
     for path in merge_paths {
         new_unitig_number += 1;
+        merged_count += path.len() - 1;
         merge_path(graph, &path, new_unitig_number, depth);
+        merged_numbers.extend(path.iter().map(|u| graph.number(u)));
     }
-    graph.delete_dangling_links();
-    graph.build_unitig_index();
-    graph.check_links();
+    graph.retain_unitigs(|u| !merged_numbers.contains(&u.number));
+    graph.check_links().unwrap();
+    merged_count
 }
 
 
@@ -402,273 +950,319 @@ fn cannot_merge_end(unitig_number: u32, unitig_strand: bool, fixed_starts: &Hash
 }
 
 
-fn has_single_exclusive_input(unitig_rc: &Rc<RefCell<Unitig>>, unitig_strand: bool) -> bool {
-    let inputs = if unitig_strand {get_exclusive_inputs(unitig_rc)} else {get_exclusive_outputs(unitig_rc)};
+fn has_single_exclusive_input(graph: &UnitigGraph, handle: usize, unitig_strand: bool) -> bool {
+    let inputs = if unitig_strand {get_exclusive_inputs(graph, handle)} else {get_exclusive_outputs(graph, handle)};
     inputs.len() == 1
 }
 
 
-fn merge_path(graph: &mut UnitigGraph, path: &Vec<UnitigStrand>, new_unitig_number: u32, depth: Option<f64>) {
-    let merged_seq = merge_unitig_seqs(path);
-    let first = &path[0];
-    let last = path.last().unwrap();
-    let forward_positions = if first.strand {first.unitig.borrow().forward_positions.clone()} else {first.unitig.borrow().reverse_positions.clone()};
-    let reverse_positions = if last.strand {last.unitig.borrow().reverse_positions.clone()} else {last.unitig.borrow().forward_positions.clone()};
+fn merge_path(graph: &mut UnitigGraph, path: &[UnitigStrand], new_unitig_number: u32, depth: Option<f64>) {
+    // The new unitig is added to the end of the graph, but the path's unitigs are left for the
+    // caller to remove.
+    let merged_seq = merge_unitig_seqs(graph, path);
+    let first = path[0];
+    let last = *path.last().unwrap();
+    let first_unitig = &graph.unitigs[first.unitig];
+    let last_unitig = &graph.unitigs[last.unitig];
+    let forward_positions = if first.strand {first_unitig.forward_positions.clone()} else {first_unitig.reverse_positions.clone()};
+    let reverse_positions = if last.strand {last_unitig.reverse_positions.clone()} else {last_unitig.forward_positions.clone()};
 
     // Check to see if the path has any self links, so we can make those after the merge if needed.
-    let end_to_start_link = graph.link_exists(last.number(), last.strand, first.number(), first.strand);
-    let start_flip_link = graph.link_exists(first.number(), !first.strand, first.number(), first.strand);
-    let end_flip_link = graph.link_exists(last.number(), last.strand, last.number(), !last.strand);
+    let (first_number, last_number) = (first_unitig.number, last_unitig.number);
+    let end_to_start_link = graph.link_exists(last_number, last.strand, first_number, first.strand);
+    let start_flip_link = graph.link_exists(first_number, !first.strand, first_number, first.strand);
+    let end_flip_link = graph.link_exists(last_number, last.strand, last_number, !last.strand);
 
     // For the new unitig, we take links (forward_prev, reverse_next, forward_next, reverse_prev)
     // from the first/last unitigs in the path.
-    let forward_prev = if first.strand {first.unitig.borrow().forward_prev.clone()} else {first.unitig.borrow().reverse_prev.clone()};
-    let reverse_next = if first.strand {first.unitig.borrow().reverse_next.clone()} else {first.unitig.borrow().forward_next.clone()};
-    let forward_next = if last.strand {last.unitig.borrow().forward_next.clone()} else {last.unitig.borrow().reverse_next.clone()};
-    let reverse_prev = if last.strand {last.unitig.borrow().reverse_prev.clone()} else {last.unitig.borrow().forward_prev.clone()};
+    let forward_prev = if first.strand {first_unitig.forward_prev.clone()} else {first_unitig.reverse_prev.clone()};
+    let reverse_next = if first.strand {first_unitig.reverse_next.clone()} else {first_unitig.forward_next.clone()};
+    let forward_next = if last.strand {last_unitig.forward_next.clone()} else {last_unitig.reverse_next.clone()};
+    let reverse_prev = if last.strand {last_unitig.reverse_prev.clone()} else {last_unitig.forward_prev.clone()};
 
     let mut unitig = Unitig {
         number: new_unitig_number,
         reverse_seq: reverse_complement(&merged_seq),
         forward_seq: merged_seq,
-        depth: if let Some(d) = depth { d } else { forward_positions.len() as f64 },
+        ambiguities: merge_unitig_ambiguities(graph, path),
+        confidence: merge_unitig_confidence(graph, path),
+        multiplicity: path.iter().map(|u| graph.unitigs[u.unitig].multiplicity)
+                          .collect::<Option<Vec<_>>>().and_then(|m| m.into_iter().min()),
         forward_positions, reverse_positions,
         forward_next, forward_prev, reverse_next, reverse_prev,
         ..Default::default()
     };
+    if let Some(d) = depth {
+        unitig.depth = d;
+    } else {
+        unitig.recalculate_depth(&graph.seq_weights);
+    }
 
-    if path.iter().any(|p| p.anchor()) {
+    if path.iter().any(|u| graph.unitigs[u.unitig].anchor) {
         unitig.set_as_consentig();
     }
 
-    let unitig_rc = Rc::new(RefCell::new(unitig));
-    graph.unitigs.push(unitig_rc.clone());
-
     // Create links to the new unitig from its neighbours.
-    for u in &unitig_rc.borrow().forward_next {
-        if u.strand {u.unitig.borrow_mut().forward_prev.push(UnitigStrand::new(&unitig_rc, strand::FORWARD));}
-              else {u.unitig.borrow_mut().reverse_prev.push(UnitigStrand::new(&unitig_rc, strand::FORWARD));}
+    let handle = graph.unitigs.len();
+    for u in &unitig.forward_next {
+        let n = &mut graph.unitigs[u.unitig];
+        if u.strand {n.forward_prev.push(UnitigStrand::new(handle, strand::FORWARD));}
+              else {n.reverse_prev.push(UnitigStrand::new(handle, strand::FORWARD));}
     }
-    for u in &unitig_rc.borrow().forward_prev {
-        if u.strand {u.unitig.borrow_mut().forward_next.push(UnitigStrand::new(&unitig_rc, strand::FORWARD));}
-              else {u.unitig.borrow_mut().reverse_next.push(UnitigStrand::new(&unitig_rc, strand::FORWARD));}
+    for u in &unitig.forward_prev {
+        let n = &mut graph.unitigs[u.unitig];
+        if u.strand {n.forward_next.push(UnitigStrand::new(handle, strand::FORWARD));}
+              else {n.reverse_next.push(UnitigStrand::new(handle, strand::FORWARD));}
     }
-    for u in &unitig_rc.borrow().reverse_next {
-        if u.strand {u.unitig.borrow_mut().forward_prev.push(UnitigStrand::new(&unitig_rc, strand::REVERSE));}
-              else {u.unitig.borrow_mut().reverse_prev.push(UnitigStrand::new(&unitig_rc, strand::REVERSE));}
+    for u in &unitig.reverse_next {
+        let n = &mut graph.unitigs[u.unitig];
+        if u.strand {n.forward_prev.push(UnitigStrand::new(handle, strand::REVERSE));}
+              else {n.reverse_prev.push(UnitigStrand::new(handle, strand::REVERSE));}
     }
-    for u in &unitig_rc.borrow().reverse_prev {
-        if u.strand {u.unitig.borrow_mut().forward_next.push(UnitigStrand::new(&unitig_rc, strand::REVERSE));}
-              else {u.unitig.borrow_mut().reverse_next.push(UnitigStrand::new(&unitig_rc, strand::REVERSE));}
+    for u in &unitig.reverse_prev {
+        let n = &mut graph.unitigs[u.unitig];
+        if u.strand {n.forward_next.push(UnitigStrand::new(handle, strand::REVERSE));}
+              else {n.reverse_next.push(UnitigStrand::new(handle, strand::REVERSE));}
     }
 
     // Create any needed links from the new unitig to itself.
     if end_to_start_link {
-        let mut u = unitig_rc.borrow_mut();
-        u.forward_next.push(UnitigStrand::new(&unitig_rc, strand::FORWARD));
-        u.forward_prev.push(UnitigStrand::new(&unitig_rc, strand::FORWARD));
-        u.reverse_next.push(UnitigStrand::new(&unitig_rc, strand::REVERSE));
-        u.reverse_prev.push(UnitigStrand::new(&unitig_rc, strand::REVERSE));
+        unitig.forward_next.push(UnitigStrand::new(handle, strand::FORWARD));
+        unitig.forward_prev.push(UnitigStrand::new(handle, strand::FORWARD));
+        unitig.reverse_next.push(UnitigStrand::new(handle, strand::REVERSE));
+        unitig.reverse_prev.push(UnitigStrand::new(handle, strand::REVERSE));
     }
     if start_flip_link {
-        let mut u = unitig_rc.borrow_mut();
-        u.reverse_next.push(UnitigStrand::new(&unitig_rc, strand::FORWARD));
-        u.forward_prev.push(UnitigStrand::new(&unitig_rc, strand::REVERSE));
+        unitig.reverse_next.push(UnitigStrand::new(handle, strand::FORWARD));
+        unitig.forward_prev.push(UnitigStrand::new(handle, strand::REVERSE));
     }
     if end_flip_link {
-        let mut u = unitig_rc.borrow_mut();
-        u.forward_next.push(UnitigStrand::new(&unitig_rc, strand::REVERSE));
-        u.reverse_prev.push(UnitigStrand::new(&unitig_rc, strand::FORWARD));
+        unitig.forward_next.push(UnitigStrand::new(handle, strand::REVERSE));
+        unitig.reverse_prev.push(UnitigStrand::new(handle, strand::FORWARD));
     }
 
-    let path_numbers: HashSet<_> = path.iter().map(|u| u.number()).collect();
-    graph.unitigs.retain(|u| !path_numbers.contains(&u.borrow().number));
+    graph.add_unitig(unitig);
 }
 
 
-fn merge_unitig_seqs(path: &Vec<UnitigStrand>) -> Vec<u8> {
+fn merge_unitig_seqs(graph: &UnitigGraph, path: &[UnitigStrand]) -> Vec<u8> {
     // Given a path of unitigs (with their strand), this function returns their merged sequence. It
     // assumes no overlap and it does not check that the given unitigs are actually linked to each
     // other.
-    let total_length: usize = path.iter().map(|u| u.length()).sum::<u32>().try_into().unwrap();
+    let total_length: usize = path.iter().map(|u| graph.unitigs[u.unitig].length())
+                                  .sum::<u32>().try_into().unwrap();
     let mut merged_seq = Vec::with_capacity(total_length);
     for u in path {
-        merged_seq.extend(u.get_seq());
+        merged_seq.extend(graph.unitigs[u.unitig].get_seq(u.strand));
     }
     merged_seq
 }
 
 
+fn merge_unitig_ambiguities(graph: &UnitigGraph, path: &[UnitigStrand]) -> Vec<Ambiguity> {
+    // Given a path of unitigs (with their strand), this function returns their ambiguities with
+    // coordinates on the merged sequence (see merge_unitig_seqs).
+    let mut ambiguities = Vec::new();
+    let mut offset = 0;
+    for u in path {
+        let unitig = &graph.unitigs[u.unitig];
+        for a in &unitig.ambiguities {
+            let a = if u.strand { a.clone() } else { a.flipped(unitig.length()) };
+            ambiguities.push(a.shifted(offset));
+        }
+        offset += unitig.length();
+    }
+    ambiguities
+}
+
+
+fn merge_unitig_confidence(graph: &UnitigGraph, path: &[UnitigStrand]) -> Vec<Confidence> {
+    // Same as merge_unitig_ambiguities, but for the unitigs' confidence regions. These are sorted
+    // by start, so a reverse-strand unitig's regions are reversed after flipping.
+    let mut confidence = Vec::new();
+    let mut offset = 0;
+    for u in path {
+        let unitig = &graph.unitigs[u.unitig];
+        let mut regions: Vec<Confidence> = unitig.confidence.iter()
+            .map(|c| if u.strand { c.clone() } else { c.flipped(unitig.length()) }).collect();
+        if !u.strand { regions.reverse(); }
+        confidence.extend(regions.into_iter().map(|c| c.shifted(offset)));
+        offset += unitig.length();
+    }
+    confidence
+}
+
+
 #[cfg(test)]
 mod tests {
     use crate::test_gfa::*;
     use super::*;
 
-    fn unitig_vec_to_str(mut unitigs: Vec<UnitigStrand>) -> String {
+    fn unitig_vec_to_str(graph: &UnitigGraph, mut unitigs: Vec<UnitigStrand>) -> String {
         // Converts a vector of Unitigs to a string (makes my tests easier to write).
-        unitigs.sort_by(|a, b| {a.number().cmp(&b.number()).then_with(|| a.strand.cmp(&b.strand))});
-        unitigs.iter().map(|u| {format!("{}{}", u.number(), if u.strand {'+'} else {'-'})}).collect::<Vec<String>>().join(",")
+        unitigs.sort_by(|a, b| {graph.number(a).cmp(&graph.number(b)).then_with(|| a.strand.cmp(&b.strand))});
+        unitigs.iter().map(|u| {format!("{}{}", graph.number(u), if u.strand {'+'} else {'-'})}).collect::<Vec<String>>().join(",")
+    }
+
+    fn three_unitig_graph() -> UnitigGraph {
+        // Unlinked unitigs 1, 2 and 3, at handles 0, 1 and 2.
+        let mut graph = UnitigGraph::default();
+        for line in ["S\t1\tACGATCAGC\tDP:f:1", "S\t2\tACTATCAGC\tDP:f:1", "S\t3\tACTACGACT\tDP:f:1"] {
+            graph.add_unitig(Unitig::from_segment_line(line).unwrap());
+        }
+        graph
     }
 
     #[test]
     fn test_get_common_start_seq() {
-        let a = Rc::new(RefCell::new(Unitig::from_segment_line("S\t1\tACGATCAGC\tDP:f:1")));
-        let b = Rc::new(RefCell::new(Unitig::from_segment_line("S\t2\tACTATCAGC\tDP:f:1")));
-        let c = Rc::new(RefCell::new(Unitig::from_segment_line("S\t3\tACTACGACT\tDP:f:1")));
-        let unitigs = vec![UnitigStrand::new(&a, strand::FORWARD), UnitigStrand::new(&b, strand::FORWARD), UnitigStrand::new(&c, strand::FORWARD)];
-        assert_eq!(std::str::from_utf8(&get_common_start_seq(&unitigs)).unwrap(), "AC");
+        let graph = three_unitig_graph();
+        let unitigs = vec![UnitigStrand::new(0, strand::FORWARD), UnitigStrand::new(1, strand::FORWARD), UnitigStrand::new(2, strand::FORWARD)];
+        assert_eq!(std::str::from_utf8(&get_common_start_seq(&graph, &unitigs)).unwrap(), "AC");
 
-        let a = Rc::new(RefCell::new(Unitig::from_segment_line("S\t1\tACGATCAGC\tDP:f:1")));
-        let b = Rc::new(RefCell::new(Unitig::from_segment_line("S\t2\tACTATCAGC\tDP:f:1")));
-        let c = Rc::new(RefCell::new(Unitig::from_segment_line("S\t3\tACTACGACT\tDP:f:1")));
-        let unitigs = vec![UnitigStrand::new(&a, strand::FORWARD), UnitigStrand::new(&b, strand::FORWARD), UnitigStrand::new(&c, strand::REVERSE)];
-        assert_eq!(std::str::from_utf8(&get_common_start_seq(&unitigs)).unwrap(), "A");
+        let unitigs = vec![UnitigStrand::new(0, strand::FORWARD), UnitigStrand::new(1, strand::FORWARD), UnitigStrand::new(2, strand::REVERSE)];
+        assert_eq!(std::str::from_utf8(&get_common_start_seq(&graph, &unitigs)).unwrap(), "A");
 
-        let a = Rc::new(RefCell::new(Unitig::from_segment_line("S\t1\tACGATCAGC\tDP:f:1")));
-        let b = Rc::new(RefCell::new(Unitig::from_segment_line("S\t2\tACTATCAGC\tDP:f:1")));
-        let c = Rc::new(RefCell::new(Unitig::from_segment_line("S\t3\tACTACGACT\tDP:f:1")));
-        let unitigs = vec![UnitigStrand::new(&a, strand::FORWARD), UnitigStrand::new(&b, strand::REVERSE), UnitigStrand::new(&c, strand::REVERSE)];
-        assert_eq!(std::str::from_utf8(&get_common_start_seq(&unitigs)).unwrap(), "");
+        let unitigs = vec![UnitigStrand::new(0, strand::FORWARD), UnitigStrand::new(1, strand::REVERSE), UnitigStrand::new(2, strand::REVERSE)];
+        assert_eq!(std::str::from_utf8(&get_common_start_seq(&graph, &unitigs)).unwrap(), "");
     }
 
     #[test]
     fn test_get_common_end_seq() {
-        let a = Rc::new(RefCell::new(Unitig::from_segment_line("S\t1\tACGATCAGC\tDP:f:1")));
-        let b = Rc::new(RefCell::new(Unitig::from_segment_line("S\t2\tACTATCAGC\tDP:f:1")));
-        let c = Rc::new(RefCell::new(Unitig::from_segment_line("S\t3\tACTACGACT\tDP:f:1")));
-        let unitigs = vec![UnitigStrand::new(&a, strand::FORWARD), UnitigStrand::new(&b, strand::FORWARD), UnitigStrand::new(&c, strand::FORWARD)];
-        assert_eq!(std::str::from_utf8(&get_common_end_seq(&unitigs)).unwrap(), "");
+        let graph = three_unitig_graph();
+        let unitigs = vec![UnitigStrand::new(0, strand::FORWARD), UnitigStrand::new(1, strand::FORWARD), UnitigStrand::new(2, strand::FORWARD)];
+        assert_eq!(std::str::from_utf8(&get_common_end_seq(&graph, &unitigs)).unwrap(), "");
 
-        let a = Rc::new(RefCell::new(Unitig::from_segment_line("S\t1\tACGATCAGC\tDP:f:1")));
-        let b = Rc::new(RefCell::new(Unitig::from_segment_line("S\t2\tACTATCAGC\tDP:f:1")));
-        let c = Rc::new(RefCell::new(Unitig::from_segment_line("S\t3\tACTACGACT\tDP:f:1")));
-        let unitigs = vec![UnitigStrand::new(&a, strand::REVERSE), UnitigStrand::new(&b, strand::REVERSE), UnitigStrand::new(&c, strand::FORWARD)];
-        assert_eq!(std::str::from_utf8(&get_common_end_seq(&unitigs)).unwrap(), "T");
+        let unitigs = vec![UnitigStrand::new(0, strand::REVERSE), UnitigStrand::new(1, strand::REVERSE), UnitigStrand::new(2, strand::FORWARD)];
+        assert_eq!(std::str::from_utf8(&get_common_end_seq(&graph, &unitigs)).unwrap(), "T");
 
-        let a = Rc::new(RefCell::new(Unitig::from_segment_line("S\t1\tACGATCAGC\tDP:f:1")));
-        let b = Rc::new(RefCell::new(Unitig::from_segment_line("S\t2\tACTATCAGC\tDP:f:1")));
-        let c = Rc::new(RefCell::new(Unitig::from_segment_line("S\t3\tACTACGACT\tDP:f:1")));
-        let unitigs = vec![UnitigStrand::new(&a, strand::REVERSE), UnitigStrand::new(&b, strand::REVERSE), UnitigStrand::new(&c, strand::REVERSE)];
-        assert_eq!(std::str::from_utf8(&get_common_end_seq(&unitigs)).unwrap(), "GT");
+        let unitigs = vec![UnitigStrand::new(0, strand::REVERSE), UnitigStrand::new(1, strand::REVERSE), UnitigStrand::new(2, strand::REVERSE)];
+        assert_eq!(std::str::from_utf8(&get_common_end_seq(&graph, &unitigs)).unwrap(), "GT");
     }
 
     #[test]
     fn test_get_exclusive_inputs_and_outputs() {
         let (graph, _) = UnitigGraph::from_gfa_lines(&get_test_gfa_1());
 
-        let unitig_1 = &graph.unitigs[0];
-        assert_eq!(unitig_vec_to_str(get_exclusive_inputs(unitig_1)), "2+,3-");
-        assert_eq!(unitig_vec_to_str(get_exclusive_outputs(unitig_1)), "");
+        assert_eq!(unitig_vec_to_str(&graph, get_exclusive_inputs(&graph, 0)), "2+,3-");
+        assert_eq!(unitig_vec_to_str(&graph, get_exclusive_outputs(&graph, 0)), "");
 
-        let unitig_2 = &graph.unitigs[1];
-        assert_eq!(unitig_vec_to_str(get_exclusive_inputs(unitig_2)), "");
-        assert_eq!(unitig_vec_to_str(get_exclusive_outputs(unitig_2)), "");
+        assert_eq!(unitig_vec_to_str(&graph, get_exclusive_inputs(&graph, 1)), "");
+        assert_eq!(unitig_vec_to_str(&graph, get_exclusive_outputs(&graph, 1)), "");
 
-        let unitig_3 = &graph.unitigs[2];
-        assert_eq!(unitig_vec_to_str(get_exclusive_inputs(unitig_3)), "");
-        assert_eq!(unitig_vec_to_str(get_exclusive_outputs(unitig_3)), "");
+        assert_eq!(unitig_vec_to_str(&graph, get_exclusive_inputs(&graph, 2)), "");
+        assert_eq!(unitig_vec_to_str(&graph, get_exclusive_outputs(&graph, 2)), "");
 
-        let unitig_4 = &graph.unitigs[3];
-        assert_eq!(unitig_vec_to_str(get_exclusive_inputs(unitig_4)), "");
-        assert_eq!(unitig_vec_to_str(get_exclusive_outputs(unitig_4)), "7-,8+");
+        assert_eq!(unitig_vec_to_str(&graph, get_exclusive_inputs(&graph, 3)), "");
+        assert_eq!(unitig_vec_to_str(&graph, get_exclusive_outputs(&graph, 3)), "7-,8+");
 
-        let unitig_5 = &graph.unitigs[4];
-        assert_eq!(unitig_vec_to_str(get_exclusive_inputs(unitig_5)), "");
-        assert_eq!(unitig_vec_to_str(get_exclusive_outputs(unitig_5)), "");
+        assert_eq!(unitig_vec_to_str(&graph, get_exclusive_inputs(&graph, 4)), "");
+        assert_eq!(unitig_vec_to_str(&graph, get_exclusive_outputs(&graph, 4)), "");
 
-        let unitig_6 = &graph.unitigs[5];
-        assert_eq!(unitig_vec_to_str(get_exclusive_inputs(unitig_6)), "");
-        assert_eq!(unitig_vec_to_str(get_exclusive_outputs(unitig_6)), "");
+        assert_eq!(unitig_vec_to_str(&graph, get_exclusive_inputs(&graph, 5)), "");
+        assert_eq!(unitig_vec_to_str(&graph, get_exclusive_outputs(&graph, 5)), "");
 
-        let unitig_7 = &graph.unitigs[6];
-        assert_eq!(unitig_vec_to_str(get_exclusive_inputs(unitig_7)), "9-,9+");
-        assert_eq!(unitig_vec_to_str(get_exclusive_outputs(unitig_7)), "");
+        assert_eq!(unitig_vec_to_str(&graph, get_exclusive_inputs(&graph, 6)), "9-,9+");
+        assert_eq!(unitig_vec_to_str(&graph, get_exclusive_outputs(&graph, 6)), "");
 
-        let unitig_8 = &graph.unitigs[7];
-        assert_eq!(unitig_vec_to_str(get_exclusive_inputs(unitig_8)), "");
-        assert_eq!(unitig_vec_to_str(get_exclusive_outputs(unitig_8)), "10-");
+        assert_eq!(unitig_vec_to_str(&graph, get_exclusive_inputs(&graph, 7)), "");
+        assert_eq!(unitig_vec_to_str(&graph, get_exclusive_outputs(&graph, 7)), "10-");
 
-        let unitig_9 = &graph.unitigs[8];
-        assert_eq!(unitig_vec_to_str(get_exclusive_inputs(unitig_9)), "");
-        assert_eq!(unitig_vec_to_str(get_exclusive_outputs(unitig_9)), "");
+        assert_eq!(unitig_vec_to_str(&graph, get_exclusive_inputs(&graph, 8)), "");
+        assert_eq!(unitig_vec_to_str(&graph, get_exclusive_outputs(&graph, 8)), "");
 
-        let unitig_10 = &graph.unitigs[9];
-        assert_eq!(unitig_vec_to_str(get_exclusive_inputs(unitig_10)), "");
-        assert_eq!(unitig_vec_to_str(get_exclusive_outputs(unitig_10)), "8-");
+        assert_eq!(unitig_vec_to_str(&graph, get_exclusive_inputs(&graph, 9)), "");
+        assert_eq!(unitig_vec_to_str(&graph, get_exclusive_outputs(&graph, 9)), "8-");
     }
 
     #[test]
     fn test_simplify_structure_1() {
         let (mut graph, _) = UnitigGraph::from_gfa_lines(&get_test_gfa_1());
-        let sequences: Vec<Sequence> = vec![];
-
-        assert_eq!(std::str::from_utf8(&graph.unitigs[0].borrow().forward_seq).unwrap(), "TTCGCTGCGCTCGCTTCGCTTT");
-        assert_eq!(std::str::from_utf8(&graph.unitigs[1].borrow().forward_seq).unwrap(), "TGCCGTCGTCGCTGTGCA");
-        assert_eq!(std::str::from_utf8(&graph.unitigs[2].borrow().forward_seq).unwrap(), "TGCCTGAATCGCCTA");
-        assert_eq!(std::str::from_utf8(&graph.unitigs[3].borrow().forward_seq).unwrap(), "GCTCGGCTCG");
-        assert_eq!(std::str::from_utf8(&graph.unitigs[4].borrow().forward_seq).unwrap(), "CGAACCAT");
-        assert_eq!(std::str::from_utf8(&graph.unitigs[5].borrow().forward_seq).unwrap(), "TACTTGT");
-        assert_eq!(std::str::from_utf8(&graph.unitigs[6].borrow().forward_seq).unwrap(), "GCCTT");
-        assert_eq!(std::str::from_utf8(&graph.unitigs[7].borrow().forward_seq).unwrap(), "ATCT");
-        assert_eq!(std::str::from_utf8(&graph.unitigs[8].borrow().forward_seq).unwrap(), "GC");
-        assert_eq!(std::str::from_utf8(&graph.unitigs[9].borrow().forward_seq).unwrap(), "T");
-
-        simplify_structure(&mut graph, &sequences);
-
-        assert_eq!(std::str::from_utf8(&graph.unitigs[0].borrow().forward_seq).unwrap(), "GCATTCGCTGCGCTCGCTTCGCTTT");
-        assert_eq!(std::str::from_utf8(&graph.unitigs[1].borrow().forward_seq).unwrap(), "TGCCGTCGTCGCTGT");
-        assert_eq!(std::str::from_utf8(&graph.unitigs[2].borrow().forward_seq).unwrap(), "CTGAATCGCCTA");
-        assert_eq!(std::str::from_utf8(&graph.unitigs[3].borrow().forward_seq).unwrap(), "GCTCGGCTCGA");
-        assert_eq!(std::str::from_utf8(&graph.unitigs[4].borrow().forward_seq).unwrap(), "CGAACCAT");
-        assert_eq!(std::str::from_utf8(&graph.unitigs[5].borrow().forward_seq).unwrap(), "TACTTGT");
-        assert_eq!(std::str::from_utf8(&graph.unitigs[6].borrow().forward_seq).unwrap(), "GCCT");
-        assert_eq!(std::str::from_utf8(&graph.unitigs[7].borrow().forward_seq).unwrap(), "TCT");
-        assert_eq!(std::str::from_utf8(&graph.unitigs[8].borrow().forward_seq).unwrap(), "GC");
-        assert_eq!(std::str::from_utf8(&graph.unitigs[9].borrow().forward_seq).unwrap(), "T");
+        let mut sequences: Vec<Sequence> = vec![];
+
+        assert_eq!(std::str::from_utf8(&graph.unitigs[0].forward_seq).unwrap(), "TTCGCTGCGCTCGCTTCGCTTT");
+        assert_eq!(std::str::from_utf8(&graph.unitigs[1].forward_seq).unwrap(), "TGCCGTCGTCGCTGTGCA");
+        assert_eq!(std::str::from_utf8(&graph.unitigs[2].forward_seq).unwrap(), "TGCCTGAATCGCCTA");
+        assert_eq!(std::str::from_utf8(&graph.unitigs[3].forward_seq).unwrap(), "GCTCGGCTCG");
+        assert_eq!(std::str::from_utf8(&graph.unitigs[4].forward_seq).unwrap(), "CGAACCAT");
+        assert_eq!(std::str::from_utf8(&graph.unitigs[5].forward_seq).unwrap(), "TACTTGT");
+        assert_eq!(std::str::from_utf8(&graph.unitigs[6].forward_seq).unwrap(), "GCCTT");
+        assert_eq!(std::str::from_utf8(&graph.unitigs[7].forward_seq).unwrap(), "ATCT");
+        assert_eq!(std::str::from_utf8(&graph.unitigs[8].forward_seq).unwrap(), "GC");
+        assert_eq!(std::str::from_utf8(&graph.unitigs[9].forward_seq).unwrap(), "T");
+
+        simplify_structure(&mut graph, &mut sequences, 0, 0.0,
+                           &mut SimplificationMetrics::default());
+
+        assert_eq!(std::str::from_utf8(&graph.unitigs[0].forward_seq).unwrap(), "GCATTCGCTGCGCTCGCTTCGCTTT");
+        assert_eq!(std::str::from_utf8(&graph.unitigs[1].forward_seq).unwrap(), "TGCCGTCGTCGCTGT");
+        assert_eq!(std::str::from_utf8(&graph.unitigs[2].forward_seq).unwrap(), "CTGAATCGCCTA");
+        assert_eq!(std::str::from_utf8(&graph.unitigs[3].forward_seq).unwrap(), "GCTCGGCTCGA");
+        assert_eq!(std::str::from_utf8(&graph.unitigs[4].forward_seq).unwrap(), "CGAACCAT");
+        assert_eq!(std::str::from_utf8(&graph.unitigs[5].forward_seq).unwrap(), "TACTTGT");
+        assert_eq!(std::str::from_utf8(&graph.unitigs[6].forward_seq).unwrap(), "GCCT");
+        assert_eq!(std::str::from_utf8(&graph.unitigs[7].forward_seq).unwrap(), "TCT");
+        assert_eq!(std::str::from_utf8(&graph.unitigs[8].forward_seq).unwrap(), "GC");
+        assert_eq!(std::str::from_utf8(&graph.unitigs[9].forward_seq).unwrap(), "T");
     }
 
     #[test]
     fn test_simplify_structure_2() {
         let (mut graph, _) = UnitigGraph::from_gfa_lines(&get_test_gfa_2());
-        let sequences: Vec<Sequence> = vec![];
+        let mut sequences: Vec<Sequence> = vec![];
 
-        assert_eq!(std::str::from_utf8(&graph.unitigs[0].borrow().forward_seq).unwrap(), "ACCGCTGCGCTCGCTTCGCTCT");
-        assert_eq!(std::str::from_utf8(&graph.unitigs[1].borrow().forward_seq).unwrap(), "ATGAT");
-        assert_eq!(std::str::from_utf8(&graph.unitigs[2].borrow().forward_seq).unwrap(), "GCGC");
+        assert_eq!(std::str::from_utf8(&graph.unitigs[0].forward_seq).unwrap(), "ACCGCTGCGCTCGCTTCGCTCT");
+        assert_eq!(std::str::from_utf8(&graph.unitigs[1].forward_seq).unwrap(), "ATGAT");
+        assert_eq!(std::str::from_utf8(&graph.unitigs[2].forward_seq).unwrap(), "GCGC");
 
-        simplify_structure(&mut graph, &sequences);
+        simplify_structure(&mut graph, &mut sequences, 0, 0.0,
+                           &mut SimplificationMetrics::default());
 
-        assert_eq!(std::str::from_utf8(&graph.unitigs[0].borrow().forward_seq).unwrap(), "CACCGCTGCGCTCGCTTCGCTCTAT");
-        assert_eq!(std::str::from_utf8(&graph.unitigs[1].borrow().forward_seq).unwrap(), "CG"); // formerly unitig 3
-        assert_eq!(std::str::from_utf8(&graph.unitigs[2].borrow().forward_seq).unwrap(), "G");  // formerly unitig 2
+        assert_eq!(std::str::from_utf8(&graph.unitigs[0].forward_seq).unwrap(), "CACCGCTGCGCTCGCTTCGCTCTAT");
+        assert_eq!(std::str::from_utf8(&graph.unitigs[1].forward_seq).unwrap(), "CG"); // formerly unitig 3
+        assert_eq!(std::str::from_utf8(&graph.unitigs[2].forward_seq).unwrap(), "G");  // formerly unitig 2
     }
 
     #[test]
-    fn test_check_for_duplicates() {
-        let a = Rc::new(RefCell::new(Unitig::from_segment_line("S\t1\tACGATCAGC\tDP:f:1")));
-        let b = Rc::new(RefCell::new(Unitig::from_segment_line("S\t2\tACTATCAGC\tDP:f:1")));
-        let c = Rc::new(RefCell::new(Unitig::from_segment_line("S\t3\tACTACGACT\tDP:f:1")));
+    fn test_simplify_structure_tips() {
+        // With tip clipping off, the sequences are unchanged.
+        let (mut graph, mut sequences) = UnitigGraph::from_gfa_lines(&get_test_gfa_15());
+        let original_seqs = graph.reconstruct_original_sequences(&sequences);
+        simplify_structure(&mut graph, &mut sequences, 0, 4.0,
+                           &mut SimplificationMetrics::default());
+        assert_eq!(graph.reconstruct_original_sequences(&sequences), original_seqs);
+
+        // Unitigs 1 and 6 are low-depth tips, so they are clipped from the graph and sequences.
+        let (mut graph, mut sequences) = UnitigGraph::from_gfa_lines(&get_test_gfa_15());
+        let mut metrics = SimplificationMetrics::default();
+        simplify_structure(&mut graph, &mut sequences, 100, 4.0, &mut metrics);
+        assert_eq!(metrics.passes, vec!["clip_tips", "expand_repeats"]);
+        assert_eq!(metrics.tips_clipped, 2);
+        assert!(metrics.repeat_expansion_rounds >= 1);
+        assert_eq!(graph.total_length(), 19);
+        assert_eq!(sequences.iter().map(|s| s.length).collect::<Vec<_>>(), vec![16, 13, 12]);
+        let reconstructed = graph.reconstruct_original_sequences(&sequences);
+        assert_eq!(reconstructed["a.fasta"][0].1, "TTGATCGATCGAGGGG");
+        assert_eq!(reconstructed["b.fasta"][0].1, "CCCGATCGATCGA");
+        assert_eq!(reconstructed["c.fasta"][0].1, "TCGATCGATCAA");
+    }
 
-        let unitigs_1 = vec![UnitigStrand::new(&a, strand::FORWARD), UnitigStrand::new(&b, strand::FORWARD), UnitigStrand::new(&c, strand::FORWARD)];
+    #[test]
+    fn test_check_for_duplicates() {
+        let unitigs_1 = vec![UnitigStrand::new(0, strand::FORWARD), UnitigStrand::new(1, strand::FORWARD), UnitigStrand::new(2, strand::FORWARD)];
         assert!(!check_for_duplicates(&unitigs_1));
         
-        let unitigs_2 = vec![UnitigStrand::new(&a, strand::FORWARD), UnitigStrand::new(&b, strand::FORWARD), UnitigStrand::new(&a, strand::REVERSE)];
+        let unitigs_2 = vec![UnitigStrand::new(0, strand::FORWARD), UnitigStrand::new(1, strand::FORWARD), UnitigStrand::new(0, strand::REVERSE)];
         assert!(check_for_duplicates(&unitigs_2));
     }
 
     #[test]
     fn test_merge_unitig_seqs() {
-        let a = Rc::new(RefCell::new(Unitig::from_segment_line("S\t1\tACGATCAGC\tDP:f:1")));
-        let b = Rc::new(RefCell::new(Unitig::from_segment_line("S\t2\tACTATCAGC\tDP:f:1")));
-        let c = Rc::new(RefCell::new(Unitig::from_segment_line("S\t3\tACTACGACT\tDP:f:1")));
-        let path = vec![UnitigStrand::new(&a, strand::FORWARD), UnitigStrand::new(&b, strand::FORWARD), UnitigStrand::new(&c, strand::FORWARD)];
-        assert_eq!(std::str::from_utf8(&merge_unitig_seqs(&path)).unwrap(), "ACGATCAGCACTATCAGCACTACGACT");
+        let graph = three_unitig_graph();
+        let path = vec![UnitigStrand::new(0, strand::FORWARD), UnitigStrand::new(1, strand::FORWARD), UnitigStrand::new(2, strand::FORWARD)];
+        assert_eq!(std::str::from_utf8(&merge_unitig_seqs(&graph, &path)).unwrap(), "ACGATCAGCACTATCAGCACTACGACT");
 
-        let a = Rc::new(RefCell::new(Unitig::from_segment_line("S\t1\tACGATCAGC\tDP:f:1")));
-        let b = Rc::new(RefCell::new(Unitig::from_segment_line("S\t2\tACTATCAGC\tDP:f:1")));
-        let c = Rc::new(RefCell::new(Unitig::from_segment_line("S\t3\tACTACGACT\tDP:f:1")));
-        let path = vec![UnitigStrand::new(&a, strand::FORWARD), UnitigStrand::new(&b, strand::REVERSE), UnitigStrand::new(&c, strand::FORWARD)];
-        assert_eq!(std::str::from_utf8(&merge_unitig_seqs(&path)).unwrap(), "ACGATCAGCGCTGATAGTACTACGACT");
+        let path = vec![UnitigStrand::new(0, strand::FORWARD), UnitigStrand::new(1, strand::REVERSE), UnitigStrand::new(2, strand::FORWARD)];
+        assert_eq!(std::str::from_utf8(&merge_unitig_seqs(&graph, &path)).unwrap(), "ACGATCAGCGCTGATAGTACTACGACT");
     }
 
     #[test]
@@ -718,11 +1312,11 @@ mod tests {
         assert_eq!(graph.unitigs.len(), 7);
         merge_linear_paths(&mut graph, &seqs, None);
         assert_eq!(graph.unitigs.len(), 3);
-        assert_eq!(std::str::from_utf8(&graph.unitig_index.get(&8).unwrap().borrow().forward_seq).unwrap(),
+        assert_eq!(std::str::from_utf8(&graph.unitig(8).forward_seq).unwrap(),
                    "TTCGCTGCGCTCGCTTCGCTTTTGCACAGCGACGACGGCATGCCTGAATCGCCTA");
-        assert_eq!(std::str::from_utf8(&graph.unitig_index.get(&9).unwrap().borrow().forward_seq).unwrap(),
+        assert_eq!(std::str::from_utf8(&graph.unitig(9).forward_seq).unwrap(),
                     "GCTCGGCTCGATGGTTCG");
-        assert_eq!(std::str::from_utf8(&graph.unitig_index.get(&10).unwrap().borrow().forward_seq).unwrap(),
+        assert_eq!(std::str::from_utf8(&graph.unitig(10).forward_seq).unwrap(),
                     "TACTTGTAAGGC");
         let mut links = graph.get_links_for_gfa(0);
         let mut expected_links = vec![("8".to_string(), "+".to_string(), "9".to_string(), "+".to_string()),
@@ -742,9 +1336,9 @@ mod tests {
         assert_eq!(graph.unitigs.len(), 5);
         merge_linear_paths(&mut graph, &seqs, None);
         assert_eq!(graph.unitigs.len(), 2);
-        assert_eq!(std::str::from_utf8(&graph.unitig_index.get(&6).unwrap().borrow().forward_seq).unwrap(),
+        assert_eq!(std::str::from_utf8(&graph.unitig(6).forward_seq).unwrap(),
                    "ACGACTACGAGCACGAGTCGTCGTCGTAACTGACT");
-        assert_eq!(std::str::from_utf8(&graph.unitig_index.get(&7).unwrap().borrow().forward_seq).unwrap(),
+        assert_eq!(std::str::from_utf8(&graph.unitig(7).forward_seq).unwrap(),
                    "GCTCGGTG");
         let mut links = graph.get_links_for_gfa(0);
         let mut expected_links = vec![("6".to_string(), "+".to_string(), "6".to_string(), "+".to_string()),
@@ -761,7 +1355,7 @@ mod tests {
         assert_eq!(graph.unitigs.len(), 6);
         merge_linear_paths(&mut graph, &seqs, None);
         assert_eq!(graph.unitigs.len(), 5);
-        assert_eq!(std::str::from_utf8(&graph.unitig_index.get(&7).unwrap().borrow().forward_seq).unwrap(),
+        assert_eq!(std::str::from_utf8(&graph.unitig(7).forward_seq).unwrap(),
                    "AAATGCGACTGTG");
     }
 
@@ -772,4 +1366,199 @@ mod tests {
         merge_linear_paths(&mut graph, &seqs, None);
         assert_eq!(graph.unitigs.len(), 11);
     }
+
+    #[test]
+    fn test_pop_bubbles() {
+        // Unitig 2 (in sequences a and c) is better supported than unitig 3 (in sequence b), and
+        // going directly from 4 to 6 (in b and c) is better supported than unitig 5 (in a).
+        let (mut graph, seqs) = UnitigGraph::from_gfa_lines(&get_test_gfa_15());
+        let original_seqs = graph.reconstruct_original_sequences(&seqs);
+
+        // Nothing is collapsed if the arms are too divergent or too long.
+        let mut metrics = SimplificationMetrics::default();
+        let new_seqs = pop_bubbles(&mut graph, &seqs, 100, 0.5, false, &mut metrics);
+        assert_eq!(graph.unitigs.len(), 6);
+        assert_eq!(graph.reconstruct_original_sequences(&new_seqs), original_seqs);
+        pop_bubbles(&mut graph, &seqs, 2, 1.0, false, &mut metrics);
+        assert_eq!(metrics.bubble_arms_collapsed, 0);
+
+        // Only the first bubble's arms are short enough.
+        let new_seqs = pop_bubbles(&mut graph, &seqs, 3, 1.0, false, &mut metrics);
+        assert_eq!(metrics.bubble_arms_collapsed, 1);
+        assert_eq!(metrics.unitigs_removed, 1);
+        assert_eq!(new_seqs.iter().map(|s| s.length).collect::<Vec<_>>(), vec![36, 32, 32]);
+        let reconstructed = graph.reconstruct_original_sequences(&new_seqs);
+        assert_eq!(reconstructed["a.fasta"], original_seqs["a.fasta"]);
+        assert_eq!(reconstructed["b.fasta"][0].1, "ACGACTACGATTGATCGATCGATACGTACGTA");
+        assert_eq!(reconstructed["c.fasta"], original_seqs["c.fasta"]);
+        assert_eq!(graph.find_bubbles(&new_seqs).len(), 1);
+
+        // Popping the second bubble leaves all three sequences the same.
+        let new_seqs = pop_bubbles(&mut graph, &new_seqs, 4, 1.0, false, &mut metrics);
+        assert_eq!(metrics.bubble_arms_collapsed, 2);
+        assert_eq!(metrics.unitigs_removed, 2);
+        assert!(graph.find_bubbles(&new_seqs).is_empty());
+        assert_eq!(graph.unitigs.len(), 1);
+        assert_eq!(metrics.passes, vec!["pop_bubbles"; 4]);
+        let reconstructed = graph.reconstruct_original_sequences(&new_seqs);
+        assert_eq!(reconstructed["a.fasta"][0].1, "ACGACTACGATTGATCGATCGATACGTACGTA");
+        assert_eq!(reconstructed["b.fasta"][0].1, "ACGACTACGATTGATCGATCGATACGTACGTA");
+        for u in &graph.unitigs {
+            assert_eq!(u.depth, u.forward_positions.len() as f64);
+        }
+    }
+
+    #[test]
+    fn test_pop_bubbles_poa() {
+        // Three arms (unitigs 2, 3 and 4) each have a different error, so with POA the kept arm
+        // (unitig 2) becomes a consensus which matches none of them.
+        let gfa: Vec<String> = vec!["H\tVN:Z:1.0\tKM:i:3",
+                                    "S\t1\tGGGGG\tDP:f:3.00",
+                                    "S\t2\tACGTTCGTAC\tDP:f:1.00",
+                                    "S\t3\tACCTACGTAC\tDP:f:1.00",
+                                    "S\t4\tACGTACGTTC\tDP:f:1.00",
+                                    "S\t5\tCCCCC\tDP:f:3.00",
+                                    "L\t1\t+\t2\t+\t0M", "L\t2\t-\t1\t-\t0M",
+                                    "L\t1\t+\t3\t+\t0M", "L\t3\t-\t1\t-\t0M",
+                                    "L\t1\t+\t4\t+\t0M", "L\t4\t-\t1\t-\t0M",
+                                    "L\t2\t+\t5\t+\t0M", "L\t5\t-\t2\t-\t0M",
+                                    "L\t3\t+\t5\t+\t0M", "L\t5\t-\t3\t-\t0M",
+                                    "L\t4\t+\t5\t+\t0M", "L\t5\t-\t4\t-\t0M",
+                                    "P\t1\t1+,2+,5+\t*\tLN:i:20\tFN:Z:a.fasta\tHD:Z:a",
+                                    "P\t2\t1+,3+,5+\t*\tLN:i:20\tFN:Z:b.fasta\tHD:Z:b",
+                                    "P\t3\t5-,4-,1-\t*\tLN:i:20\tFN:Z:c.fasta\tHD:Z:c"]
+                                   .into_iter().map(String::from).collect();
+        let (mut graph, seqs) = UnitigGraph::from_gfa_lines(&gfa);
+        let mut metrics = SimplificationMetrics::default();
+        let new_seqs = pop_bubbles(&mut graph, &seqs, 100, 0.2, true, &mut metrics);
+        assert_eq!(metrics.bubble_arms_collapsed, 2);
+        assert_eq!(metrics.bubble_arms_polished, 1);
+        assert_eq!(graph.unitigs.len(), 1);
+        let reconstructed = graph.reconstruct_original_sequences(&new_seqs);
+        assert_eq!(reconstructed["a.fasta"][0].1, "GGGGGACGTACGTACCCCCC");
+        assert_eq!(reconstructed["b.fasta"][0].1, "GGGGGACGTACGTACCCCCC");
+        assert_eq!(reconstructed["c.fasta"][0].1, "GGGGGGTACGTACGTCCCCC");  // reverse strand
+    }
+
+    #[test]
+    fn test_collapse_near_identical_unitigs() {
+        // Unitigs 2 (TT) and 3 (CCC) differ by three bases, and 2 is better supported. The other
+        // bubble has an empty arm, so it is never collapsed.
+        let (mut graph, seqs) = UnitigGraph::from_gfa_lines(&get_test_gfa_15());
+        let original_seqs = graph.reconstruct_original_sequences(&seqs);
+        let mut metrics = SimplificationMetrics::default();
+        let new_seqs = collapse_near_identical_unitigs(&mut graph, &seqs, 2, &mut metrics);
+        assert_eq!(metrics.near_identical_unitigs_collapsed, 0);
+        assert_eq!(graph.unitigs.len(), 6);
+        assert_eq!(graph.reconstruct_original_sequences(&new_seqs), original_seqs);
+
+        let new_seqs = collapse_near_identical_unitigs(&mut graph, &seqs, 3, &mut metrics);
+        assert_eq!(metrics.near_identical_unitigs_collapsed, 1);
+        assert_eq!(metrics.unitigs_removed, 1);
+        assert_eq!(metrics.passes, vec!["collapse_near_identical"; 2]);
+        let reconstructed = graph.reconstruct_original_sequences(&new_seqs);
+        assert_eq!(reconstructed["a.fasta"], original_seqs["a.fasta"]);
+        assert_eq!(reconstructed["b.fasta"][0].1, "ACGACTACGATTGATCGATCGATACGTACGTA");
+        assert_eq!(graph.find_bubbles(&new_seqs).len(), 1);
+
+        // Unitigs 1, 2 and 4 are merged, and the ambiguity moves with unitig 2's sequence.
+        let ambiguous: Vec<_> = graph.unitigs.iter()
+            .filter(|u| !u.ambiguities.is_empty()).map(|u| u.gfa_segment_line()).collect();
+        assert_eq!(ambiguous, vec!["S\t7\tACGACTACGATTGATCGATCGA\tDP:f:3.00\tAM:Z:10-12:CCC:1.00"]);
+    }
+
+    #[test]
+    fn test_unroll_loops() {
+        // The loop (unitigs 2 and 3) is 6 bp, so it's left alone with a lower length limit.
+        let (mut graph, mut seqs) = UnitigGraph::from_gfa_lines(&get_test_gfa_16());
+        let original_seqs = graph.reconstruct_original_sequences(&seqs);
+        for seq in &mut seqs {
+            seq.checksum = Some(sequence_checksum(original_seqs[&seq.filename][0].1.as_bytes()));
+        }
+        let mut metrics = SimplificationMetrics::default();
+        let (new_seqs, candidates) = unroll_loops(&mut graph, &seqs, 5, &mut metrics);
+        assert_eq!(metrics.loops_unrolled, 0);
+        assert!(candidates.is_empty());
+        assert_eq!(graph.unitigs.len(), 4);
+        assert_eq!(graph.reconstruct_original_sequences(&new_seqs), original_seqs);
+
+        // Going around once is best supported (a and c), so b and d are rewritten to match and
+        // the whole graph becomes one unitig.
+        let (new_seqs, candidates) = unroll_loops(&mut graph, &seqs, 6, &mut metrics);
+        assert_eq!(metrics.loops_unrolled, 1);
+        assert_eq!(graph.unitigs.len(), 1);
+        assert!(graph.check_links().is_ok());
+        assert_eq!(new_seqs.iter().map(|s| s.length).collect::<Vec<_>>(), vec![31; 4]);
+        let reconstructed = graph.reconstruct_original_sequences(&new_seqs);
+        assert_eq!(reconstructed["a.fasta"], original_seqs["a.fasta"]);
+        assert_eq!(reconstructed["b.fasta"], original_seqs["a.fasta"].iter()
+            .map(|(_, s)| ("b".to_string(), s.clone())).collect::<Vec<_>>());
+        assert_eq!(reconstructed["c.fasta"], original_seqs["c.fasta"]);
+        assert_eq!(reconstructed["d.fasta"][0].1, "ACTACTCAACTTCAGATTCAGCTACGACTAC");
+        assert_eq!(graph.unitigs[0].depth, 4.0);
+
+        // Only the rewritten sequences whose bases changed (b and d) lose their checksums.
+        assert_eq!(new_seqs.iter().map(|s| s.checksum.is_some()).collect::<Vec<_>>(),
+                   vec![true, false, true, false]);
+
+        // Each pass count seen in the input sequences gives a candidate, and the chosen one
+        // matches the unrolled graph.
+        let passes: Vec<_> = candidates.iter().map(|c| (c.passes, c.chosen)).collect();
+        assert!(passes.contains(&(1, true)));
+        assert_eq!(passes.iter().filter(|(_, chosen)| *chosen).count(), 1);
+        let chosen = candidates.iter().find(|c| c.chosen).unwrap();
+        assert_eq!(chosen.support, 2.0);
+        assert!(chosen.seq.len() < candidates.iter().map(|c| c.seq.len()).max().unwrap());
+    }
+
+    #[test]
+    fn test_find_loop_runs() {
+        let tandem = TandemLoop { entry: 1, exit: 4, cycle: vec![2, 3] };
+        assert_eq!(find_loop_runs(&[1, 2, 4], &tandem), Some(vec![(0, 2, 0)]));
+        assert_eq!(find_loop_runs(&[5, 1, 2, 3, 2, 3, 2, 4], &tandem), Some(vec![(1, 7, 2)]));
+        assert_eq!(find_loop_runs(&[5, 6], &tandem), Some(vec![]));
+        assert_eq!(find_loop_runs(&[1, 2, 3, 2, 3], &tandem), None);
+        assert_eq!(find_loop_runs(&[3, 2, 4], &tandem), None);
+        assert_eq!(find_loop_runs(&[1, 2, 3, -3, 2, 4], &tandem), None);
+
+        let self_loop = TandemLoop { entry: 1, exit: 4, cycle: vec![2] };
+        assert_eq!(find_loop_runs(&[1, 2, 2, 2, 4, 1, 2, 4], &self_loop),
+                   Some(vec![(0, 4, 2), (5, 7, 0)]));
+    }
+
+    #[test]
+    fn test_get_ambiguity() {
+        assert_eq!(get_ambiguity(b"TCAAAAG", b"TCAAAG", 1.0),
+                   Ambiguity { start: 5, end: 6, alt: vec![], depth: 1.0 });
+        assert_eq!(get_ambiguity(b"ACGTA", b"ACTTA", 2.0),
+                   Ambiguity { start: 2, end: 3, alt: b"T".to_vec(), depth: 2.0 });
+        assert_eq!(get_ambiguity(b"ACG", b"ACGT", 1.0),
+                   Ambiguity { start: 3, end: 3, alt: b"T".to_vec(), depth: 1.0 });
+        assert_eq!(get_ambiguity(b"TT", b"CCC", 1.0),
+                   Ambiguity { start: 0, end: 2, alt: b"CCC".to_vec(), depth: 1.0 });
+    }
+
+    #[test]
+    fn test_replace_subpath() {
+        let mut path = vec![1, 2, 4, 5, 6];
+        assert!(replace_subpath(&mut path, &[1, 2, 4], &[1, 3, 4]));
+        assert_eq!(path, vec![1, 3, 4, 5, 6]);
+        assert!(replace_subpath(&mut path, &[4, 5, 6], &[4, 6]));
+        assert_eq!(path, vec![1, 3, 4, 6]);
+        assert!(!replace_subpath(&mut path, &[4, 5, 6], &[4, 6]));
+        assert_eq!(path, vec![1, 3, 4, 6]);
+
+        // Back-to-back occurrences which share a unitig are both replaced.
+        let mut path = vec![1, 2, 1, 2, 1];
+        assert!(replace_subpath(&mut path, &[1, 2, 1], &[1, 3, 3, 1]));
+        assert_eq!(path, vec![1, 3, 3, 1, 3, 3, 1]);
+    }
+
+    #[test]
+    fn test_arm_divergence() {
+        assert_eq!(arm_divergence(b"", b""), 0.0);
+        assert_eq!(arm_divergence(b"", b"GGGG"), 1.0);
+        assert_eq!(arm_divergence(b"ACGTACGTAC", b"ACGTTCGTAC"), 0.1);
+        assert_eq!(arm_divergence(b"AAAAT", b"AAAT"), 0.2);
+    }
 }
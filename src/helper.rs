@@ -0,0 +1,377 @@
+// This file contains the code for the autocycler helper subcommand.
+
+// Copyright 2024 Ryan Wick (rrwick@gmail.com)
+// https://github.com/rrwick/Autocycler
+
+// This file is part of Autocycler. Autocycler is free software: you can redistribute it and/or
+// modify it under the terms of the GNU General Public License as published by the Free Software
+// Foundation, either version 3 of the License, or (at your option) any later version. Autocycler
+// is distributed in the hope that it will be useful, but WITHOUT ANY WARRANTY; without even the
+// implied warranty of MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the GNU General
+// Public License for more details. You should have received a copy of the GNU General Public
+// License along with Autocycler. If not, see <http://www.gnu.org/licenses/>.
+
+use regex::Regex;
+use std::fs::{self, File};
+use std::sync::OnceLock;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::process::{Command, Stdio};
+use std::time::Instant;
+
+use crate::log::{section_header, explanation};
+use crate::misc::{check_if_file_exists, create_dir, format_duration, load_fasta,
+                  load_file_lines, output_writer, quit_with_error, write_fasta_seq};
+
+
+pub const ASSEMBLERS: [&str; 4] = ["flye", "raven", "miniasm", "canu"];
+pub const READ_TYPES: [&str; 4] = ["ont_r9", "ont_r10", "pacbio_clr", "pacbio_hifi"];
+
+
+pub fn helper(task: String, reads: PathBuf, out_prefix: PathBuf, genome_size: Option<String>,
+              read_type: String, threads: usize) {
+    let start_time = Instant::now();
+    check_settings(&task, &reads, &genome_size, &read_type, threads);
+    starting_message(&task);
+    print_settings(&task, &reads, &out_prefix, &genome_size, &read_type, threads);
+    check_requirements(&task);
+    if let Some(parent) = out_prefix.parent() {
+        if !parent.as_os_str().is_empty() { create_dir(parent); }
+    }
+    let temp_dir = match tempfile::tempdir() {
+        Ok(dir) => dir,
+        Err(e) => quit_with_error(&format!("unable to create temporary directory\n{}", e)),
+    };
+    let out_fasta = with_extension(&out_prefix, "fasta");
+    let out_gfa = with_extension(&out_prefix, "gfa");
+    match task.as_str() {
+        "flye"    => run_flye(&reads, temp_dir.path(), &read_type, threads, &out_fasta, &out_gfa),
+        "raven"   => run_raven(&reads, temp_dir.path(), threads, &out_fasta, &out_gfa),
+        "miniasm" => run_miniasm(&reads, temp_dir.path(), &read_type, threads, &out_fasta,
+                                 &out_gfa),
+        "canu"    => run_canu(&reads, temp_dir.path(), genome_size.as_ref().unwrap(), &read_type,
+                              threads, &out_fasta),
+        _ => unreachable!(),
+    }
+    check_output(&task, &out_fasta);
+    finished_message(start_time, &out_fasta, &out_gfa);
+}
+
+
+fn check_settings(task: &str, reads: &Path, genome_size: &Option<String>, read_type: &str,
+                  threads: usize) {
+    if !ASSEMBLERS.contains(&task) {
+        quit_with_error(&format!("task must be one of: {}", ASSEMBLERS.join(", ")));
+    }
+    check_if_file_exists(reads);
+    if !READ_TYPES.contains(&read_type) {
+        quit_with_error(&format!("--read_type must be one of: {}", READ_TYPES.join(", ")));
+    }
+    if task == "canu" && genome_size.is_none() {
+        quit_with_error("--genome_size is required for canu");
+    }
+    if threads == 0 {
+        quit_with_error("--threads must be at least 1");
+    }
+}
+
+
+fn starting_message(task: &str) {
+    section_header("Starting autocycler helper");
+    explanation(&format!("This command runs {} on a read set and saves the assembly where \
+                          autocycler compress expects it.", task));
+}
+
+
+fn print_settings(task: &str, reads: &Path, out_prefix: &Path, genome_size: &Option<String>,
+                  read_type: &str, threads: usize) {
+    eprintln!("Settings:");
+    eprintln!("  task: {}", task);
+    eprintln!("  --reads {}", reads.display());
+    eprintln!("  --out_prefix {}", out_prefix.display());
+    if let Some(genome_size) = genome_size {
+        eprintln!("  --genome_size {}", genome_size);
+    }
+    eprintln!("  --read_type {}", read_type);
+    eprintln!("  --threads {}", threads);
+    eprintln!();
+}
+
+
+fn finished_message(start_time: Instant, out_fasta: &Path, out_gfa: &Path) {
+    section_header("Finished!");
+    eprintln!("Assembly FASTA: {}", out_fasta.display());
+    if out_gfa.exists() {
+        eprintln!("Assembly graph: {}", out_gfa.display());
+    }
+    eprintln!("Time to run: {}", format_duration(start_time.elapsed()));
+    eprintln!();
+}
+
+
+fn required_programs(task: &str) -> Vec<&'static str> {
+    match task {
+        "flye"    => vec!["flye"],
+        "raven"   => vec!["raven"],
+        "miniasm" => vec!["minimap2", "miniasm", "minipolish", "racon"],
+        "canu"    => vec!["canu"],
+        _ => Vec::new(),
+    }
+}
+
+
+fn check_requirements(task: &str) {
+    let path = std::env::var_os("PATH").unwrap_or_default();
+    for program in required_programs(task) {
+        if !std::env::split_paths(&path).any(|dir| dir.join(program).is_file()) {
+            quit_with_error(&format!("{} not found in PATH", program));
+        }
+    }
+}
+
+
+fn with_extension(prefix: &Path, extension: &str) -> PathBuf {
+    let mut path = prefix.as_os_str().to_owned();
+    path.push(".");
+    path.push(extension);
+    PathBuf::from(path)
+}
+
+
+fn run_command(program: &str, args: &[String], stdout_file: Option<&Path>) {
+    // Runs an external program, quitting with an error if it fails. Its stderr is passed through,
+    // and its stdout can be saved to a file.
+    eprintln!("{} {}", program, args.join(" "));
+    let stdout = match stdout_file {
+        Some(path) => match File::create(path) {
+            Ok(file) => Stdio::from(file),
+            Err(e) => quit_with_error(&format!("unable to write {}\n{}", path.display(), e)),
+        },
+        None => Stdio::null(),
+    };
+    let status = Command::new(program).args(args).stdout(stdout).status();
+    match status {
+        Ok(s) if s.success() => {},
+        Ok(s) => quit_with_error(&format!("{} failed ({})", program, s)),
+        Err(e) => quit_with_error(&format!("unable to run {}\n{}", program, e)),
+    }
+    eprintln!();
+}
+
+
+fn copy_file(from: &Path, to: &Path) {
+    if let Err(e) = fs::copy(from, to) {
+        quit_with_error(&format!("unable to copy {} to {}\n{}", from.display(), to.display(), e));
+    }
+}
+
+
+fn run_flye(reads: &Path, temp_dir: &Path, read_type: &str, threads: usize, out_fasta: &Path,
+            out_gfa: &Path) {
+    run_command("flye", &flye_args(reads, temp_dir, read_type, threads), None);
+    check_output("flye", &temp_dir.join("assembly.fasta"));
+    copy_file(&temp_dir.join("assembly.fasta"), out_fasta);
+    copy_file(&temp_dir.join("assembly_graph.gfa"), out_gfa);
+}
+
+
+fn flye_args(reads: &Path, temp_dir: &Path, read_type: &str, threads: usize) -> Vec<String> {
+    let read_flag = match read_type {
+        "ont_r9"      => "--nano-raw",
+        "pacbio_clr"  => "--pacbio-raw",
+        "pacbio_hifi" => "--pacbio-hifi",
+        _             => "--nano-hq",
+    };
+    vec![read_flag.to_string(), reads.display().to_string(),
+         "--threads".to_string(), threads.to_string(),
+         "--out-dir".to_string(), temp_dir.display().to_string()]
+}
+
+
+fn run_raven(reads: &Path, temp_dir: &Path, threads: usize, out_fasta: &Path, out_gfa: &Path) {
+    let args = vec!["--threads".to_string(), threads.to_string(),
+                    "--disable-checkpoints".to_string(),
+                    "--graphical-fragment-assembly".to_string(), out_gfa.display().to_string(),
+                    reads.display().to_string()];
+    let temp_fasta = temp_dir.join("assembly.fasta");
+    run_command("raven", &args, Some(&temp_fasta));
+    check_output("raven", &temp_fasta);
+    copy_file(&temp_fasta, out_fasta);
+}
+
+
+fn run_miniasm(reads: &Path, temp_dir: &Path, read_type: &str, threads: usize, out_fasta: &Path,
+               out_gfa: &Path) {
+    let preset = if read_type.starts_with("pacbio") { "ava-pb" } else { "ava-ont" };
+    let overlaps = temp_dir.join("overlap.paf");
+    let unpolished = temp_dir.join("unpolished.gfa");
+    let reads = reads.display().to_string();
+    run_command("minimap2", &["-x".to_string(), preset.to_string(), "-t".to_string(),
+                              threads.to_string(), reads.clone(), reads.clone()],
+                Some(&overlaps));
+    run_command("miniasm", &["-f".to_string(), reads.clone(), overlaps.display().to_string()],
+                Some(&unpolished));
+    check_output("miniasm", &unpolished);
+    run_command("minipolish", &["--threads".to_string(), threads.to_string(), reads,
+                                unpolished.display().to_string()], Some(out_gfa));
+    check_output("minipolish", out_gfa);
+    write_fasta(&gfa_to_fasta(&load_file_lines(out_gfa)), out_fasta);
+}
+
+
+fn run_canu(reads: &Path, temp_dir: &Path, genome_size: &str, read_type: &str, threads: usize,
+            out_fasta: &Path) {
+    let read_flag = match read_type {
+        "pacbio_clr"  => "-pacbio",
+        "pacbio_hifi" => "-pacbio-hifi",
+        _             => "-nanopore",
+    };
+    let args = vec!["-p".to_string(), "canu".to_string(),
+                    "-d".to_string(), temp_dir.display().to_string(), "-fast".to_string(),
+                    format!("genomeSize={}", genome_size), "useGrid=false".to_string(),
+                    format!("maxThreads={}", threads), read_flag.to_string(),
+                    reads.display().to_string()];
+    run_command("canu", &args, None);
+    let contigs = temp_dir.join("canu.contigs.fasta");
+    check_output("canu", &contigs);
+    let trimmed: Vec<(String, String)> = load_fasta(&contigs).into_iter()
+        .filter_map(|(_, header, seq)| trim_canu_contig(&header, &seq)).collect();
+    write_fasta(&trimmed, out_fasta);
+}
+
+
+fn trim_canu_contig(header: &str, seq: &str) -> Option<(String, String)> {
+    // Excludes Canu contigs flagged as repeats or bubbles, and trims circular contigs (using the
+    // trim range in their header) so their ends don't overlap.
+    if header.contains("suggestRepeat=yes") || header.contains("suggestBubble=yes") {
+        return None;
+    }
+    if !header.contains("suggestCircular=yes") {
+        return Some((header.to_string(), seq.to_string()));
+    }
+    static TRIM_RE: OnceLock<Regex> = OnceLock::new();
+    static LEN_RE: OnceLock<Regex> = OnceLock::new();
+    let trim_re = TRIM_RE.get_or_init(|| Regex::new(r"trim=(\d+)-(\d+)").unwrap());
+    let len_re = LEN_RE.get_or_init(|| Regex::new(r"len=\d+").unwrap());
+    let Some(caps) = trim_re.captures(header) else {
+        return Some((header.to_string(), seq.to_string()));
+    };
+    let parse = |s: &str| s.parse::<usize>().unwrap_or_else(|_| {
+        quit_with_error(&format!("unable to parse Canu trim range: {}", &caps[0]));
+    });
+    let start = parse(&caps[1]);
+    let end = parse(&caps[2]).min(seq.len());
+    let trimmed = seq[start.min(end)..end].to_string();
+    let header = header.replace(&caps[0], &format!("trim=0-{}", trimmed.len()));
+    let header = len_re.replace(&header, format!("len={}", trimmed.len()).as_str()).to_string();
+    Some((header, trimmed))
+}
+
+
+fn gfa_to_fasta(gfa_lines: &[String]) -> Vec<(String, String)> {
+    // Converts a Minipolish GFA to FASTA, keeping the circularity and depth tags of each segment
+    // in its header.
+    let mut seqs = Vec::new();
+    for line in gfa_lines {
+        let parts: Vec<&str> = line.split('\t').collect();
+        if parts.len() < 3 || parts[0] != "S" { continue; }
+        let mut header = parts[1].to_string();
+        for tag in &parts[3..] {
+            if let Some(value) = tag.strip_prefix("LN:i:") {
+                header.push_str(&format!(" length={}", value));
+            } else if let Some(value) = tag.strip_prefix("dp:f:") {
+                header.push_str(&format!(" depth={}", value));
+            }
+        }
+        let circular = gfa_lines.iter().any(|l| {
+            let p: Vec<&str> = l.split('\t').collect();
+            p.len() > 3 && p[0] == "L" && p[1] == parts[1] && p[3] == parts[1]
+        });
+        if circular { header.push_str(" circular=true"); }
+        seqs.push((header, parts[2].to_string()));
+    }
+    seqs
+}
+
+
+fn write_fasta(seqs: &[(String, String)], path: &Path) {
+    let mut writer = output_writer(path).unwrap_or_else(|e| {
+        quit_with_error(&format!("unable to create {}\n{}", path.display(), e));
+    });
+    let result = seqs.iter().try_for_each(|(header, seq)| {
+        writeln!(writer, ">{}", header)?;
+        write_fasta_seq(&mut writer, seq, 0)
+    }).and_then(|_| writer.finish());
+    if let Err(e) = result {
+        quit_with_error(&format!("unable to write {}\n{}", path.display(), e));
+    }
+}
+
+
+fn check_output(program: &str, path: &Path) {
+    let empty = fs::metadata(path).map(|m| m.len() == 0).unwrap_or(true);
+    if empty {
+        quit_with_error(&format!("{} assembly failed, {} is missing or empty", program,
+                                 path.display()));
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_trim_canu_contig() {
+        let seq = "AGTAGCCAAACTATTTAATGCTAGAGATGCTGCATATCAAAAAATAATCAAACAATTATC";
+        let header = "tig00000001 len=60 reads=50 class=contig suggestRepeat=no \
+                      suggestBubble=no suggestCircular=no trim=0-60";
+        assert_eq!(trim_canu_contig(header, seq), Some((header.to_string(), seq.to_string())));
+        assert!(trim_canu_contig(&header.replace("suggestRepeat=no", "suggestRepeat=yes"),
+                                 seq).is_none());
+        assert!(trim_canu_contig(&header.replace("suggestBubble=no", "suggestBubble=yes"),
+                                 seq).is_none());
+
+        let header = "tig00000001 len=60 reads=50 class=contig suggestRepeat=no \
+                      suggestBubble=no suggestCircular=yes trim=10-50";
+        assert_eq!(trim_canu_contig(header, seq),
+                   Some(("tig00000001 len=40 reads=50 class=contig suggestRepeat=no \
+                          suggestBubble=no suggestCircular=yes trim=0-40".to_string(),
+                         "CTATTTAATGCTAGAGATGCTGCATATCAAAAAATAATCA".to_string())));
+
+        let header = "tig00000001 len=60 suggestCircular=yes trim=0-99999999999999999999999";
+        assert!(std::panic::catch_unwind(|| { trim_canu_contig(header, seq); }).is_err());
+    }
+
+    #[test]
+    fn test_write_fasta() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("a.fasta");
+        write_fasta(&[("a circular=true".to_string(), "ACGT".to_string())], &path);
+        assert_eq!(fs::read_to_string(&path).unwrap(), ">a circular=true\nACGT\n");
+        let missing = dir.path().join("missing").join("a.fasta");
+        assert!(std::panic::catch_unwind(|| { write_fasta(&[], &missing); }).is_err());
+    }
+
+    #[test]
+    fn test_gfa_to_fasta() {
+        let gfa: Vec<String> = ["S\tutg1c\tACGT\tLN:i:4\tdp:f:12.5", "S\tutg2l\tGGCC\tLN:i:4",
+                                "L\tutg1c\t+\tutg1c\t+\t0M"].iter().map(|s| s.to_string())
+                                                               .collect();
+        assert_eq!(gfa_to_fasta(&gfa),
+                   vec![("utg1c length=4 depth=12.5 circular=true".to_string(),
+                         "ACGT".to_string()),
+                        ("utg2l length=4".to_string(), "GGCC".to_string())]);
+    }
+
+    #[test]
+    fn test_helper_settings() {
+        assert_eq!(with_extension(Path::new("assemblies/flye_01"), "fasta"),
+                   PathBuf::from("assemblies/flye_01.fasta"));
+        assert_eq!(flye_args(Path::new("reads.fastq"), Path::new("tmp"), "ont_r10", 4),
+                   vec!["--nano-hq", "reads.fastq", "--threads", "4", "--out-dir", "tmp"]);
+        assert_eq!(flye_args(Path::new("reads.fastq"), Path::new("tmp"), "pacbio_hifi", 4)[0],
+                   "--pacbio-hifi");
+        assert_eq!(required_programs("miniasm").len(), 4);
+    }
+}
@@ -12,39 +12,38 @@
 // License along with Autocycler. If not, see <http://www.gnu.org/licenses/>.
 
 use fxhash::FxHashMap;  // a bit faster than Rust's built-in HashMap
-use std::collections::hash_map::Entry;
+use hashbrown::HashTable;
+use rayon::prelude::*;
+use std::borrow::Cow;
+use std::collections::{BTreeMap, HashMap};
 use std::fmt;
-use std::slice::from_raw_parts;
+use std::ops::Range;
 
-use crate::misc::{reverse_complement, strand};
+use crate::misc::{complement_base, reverse_complement, strand};
 use crate::position::Position;
 use crate::sequence::Sequence;
 
 pub static ALPHABET: [u8; 5] = [b'.', b'A', b'C', b'G', b'T'];
 
+// Sequences are cut into chunks of this many k-mers when adding them in parallel, which limits
+// how many hashed k-mers are held in buckets at once.
+const CHUNK_SIZE: usize = 1_000_000;
 
+
+#[derive(Default)]
 pub struct Kmer {
-    // Kmer objects store a raw pointer to sequence. This is faster and uses less memory than
-    // storing a copy, and it avoids a bunch of tricky lifetimes which would be needed to store a
-    // slice. However, it requires unsafe code to access the k-mer sequence.
-    pointer: *const u8,
-    length: usize,
+    // The k-mer's sequence is the key it is stored under in the KmerGraph, so Kmer objects only
+    // need to hold positions.
     pub positions: Vec<Position>,
 }
 
 impl Kmer {
-    pub fn new(pointer: *const u8, length: usize, assembly_count: usize) -> Kmer {
+    pub fn new(assembly_count: usize) -> Kmer {
         Kmer {
-            pointer,
-            length,
             positions: Vec::with_capacity(assembly_count), // most k-mers occur once per assembly
         }
     }
 
-    pub fn seq(&self) -> &[u8] {
-        unsafe{ from_raw_parts(self.pointer, self.length) }
-    }
-
     pub fn add_position(&mut self, seq_id: u16, strand: bool, pos: usize) {
         self.positions.push(Position::new(seq_id, strand, pos));
     }
@@ -60,124 +59,535 @@ impl Kmer {
     }
 }
 
-impl fmt::Display for Kmer {
-    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
-        let seq = std::str::from_utf8(self.seq()).unwrap();
-        let positions = self.positions.iter().map(|p| p.to_string())
-                                      .collect::<Vec<String>>().join(",");
-        write!(f, "{}:{}", seq, positions)
-    }
-}
-
 
-pub struct KmerGraph<'a> {
+pub struct KmerGraph {
     pub k_size: u32,
-    pub kmers: FxHashMap<&'a [u8], Kmer>,
+
+    // K-mers are split between shards (by their hash) so they can be added in parallel, one
+    // thread per shard at a time. Each shard owns its k-mer sequences, so the graph doesn't borrow
+    // from (or depend on the memory location of) the input sequences. Stored k-mers carry a
+    // rolling hash of their sequence, so neighbouring k-mers can be looked up without re-hashing
+    // k bases.
+    kmers: Vec<KmerShard>,
+
+    // In canonical mode, only the lesser of each k-mer and its reverse complement is stored, and
+    // the other strand is recovered on lookup. This uses about half the memory, but lookups are
+    // slower. Sequence lengths (including padding) are needed to recover reverse-strand positions.
+    pub canonical: bool,
+    seq_lengths: FxHashMap<u16, usize>,
 }
 
-impl<'a> KmerGraph<'a> {
-    pub fn new(k_size: u32) -> KmerGraph<'a> {
+impl KmerGraph {
+    pub fn new(k_size: u32, shard_count: usize) -> KmerGraph {
+        // The shard count sets how many threads can add k-mers at once (see add_sequences).
         KmerGraph {
             k_size,
-            kmers: FxHashMap::default(),
+            kmers: (0..shard_count.max(1)).map(|_| KmerShard::new(k_size as usize)).collect(),
+            canonical: false,
+            seq_lengths: FxHashMap::default(),
+        }
+    }
+
+    pub fn new_canonical(k_size: u32, shard_count: usize) -> KmerGraph {
+        KmerGraph {
+            canonical: true,
+            ..KmerGraph::new(k_size, shard_count)
         }
     }
 
-    pub fn add_sequences(&mut self, seqs: &'a Vec<Sequence>, assembly_count: usize) {
+    pub fn add_sequences(&mut self, seqs: &[Sequence], assembly_count: usize) {
+        self.add_sequences_in_chunks(seqs, assembly_count, CHUNK_SIZE);
+    }
+
+    fn add_sequences_in_chunks(&mut self, seqs: &[Sequence], assembly_count: usize,
+                               chunk_size: usize) {
+        // Adds many sequences to the KmerGraph in parallel. Sequences are cut into chunks, and for
+        // one batch of chunks at a time, each chunk's k-mers are hashed and sorted into per-shard
+        // buckets (in parallel over chunks), and then each shard adds its buckets' k-mers (in
+        // parallel over shards). Shards get their k-mers in sequence order, so the results are the
+        // same as adding sequences one at a time.
+        let shard_count = self.kmers.len();
+        if shard_count == 1 {
+            for seq in seqs {
+                self.add_sequence(seq, assembly_count);
+            }
+            return;
+        }
         for seq in seqs {
-            self.add_sequence(seq, assembly_count)
+            self.seq_lengths.insert(seq.id, seq.forward_seq.len());
+        }
+        let (k_size, canonical) = (self.k_size as usize, self.canonical);
+        let chunks: Vec<(&Sequence, Range<usize>)> = seqs.iter().flat_map(|seq| {
+            (0..seq.length).step_by(chunk_size)
+                .map(move |start| (seq, start..(start + chunk_size).min(seq.length)))
+        }).collect();
+        for batch in chunks.chunks(shard_count) {
+            let buckets: Vec<Vec<Vec<(u64, bool, usize)>>> = batch.par_iter()
+                .map(|(seq, range)| {
+                    let mut buckets = vec![Vec::new(); shard_count];
+                    for_each_kmer(seq, k_size, canonical, range.clone(), |_, hash, strand, pos| {
+                        buckets[shard_index(hash, shard_count)].push((hash, strand, pos));
+                    });
+                    buckets
+                }).collect();
+            self.kmers.par_iter_mut().enumerate().for_each(|(i, shard)| {
+                for ((seq, _), chunk_buckets) in batch.iter().zip(&buckets) {
+                    for &(hash, strand, pos) in &chunk_buckets[i] {
+                        let strand_seq = if strand { &seq.forward_seq } else { &seq.reverse_seq };
+                        let kmer_seq = &strand_seq[pos..pos + k_size];
+                        shard.add_position(kmer_seq, hash, seq.id, strand, pos, assembly_count);
+                    }
+                }
+            });
         }
     }
 
-    pub fn add_sequence(&mut self, seq: &'a Sequence, assembly_count: usize) {
+    pub fn add_sequence(&mut self, seq: &Sequence, assembly_count: usize) {
         // Adds a sequence to the KmerGraph. For each k-mer in the sequence, a Kmer object and its
         // reverse complement are created (if necessary), and then the position of that k-mer in
-        // the sequence is added to the Kmer object.
-        let k_size = self.k_size as usize;
-        let half_k = (self.k_size / 2) as usize;
-        let two_half_k = half_k + half_k;
-
-        let forward_raw = seq.forward_seq.as_ptr();
-        let reverse_raw = seq.reverse_seq.as_ptr();
-
-        for forward_start in 0..seq.length {
-            let forward_end = forward_start + k_size;
-            let reverse_start = seq.length + two_half_k - forward_end;
-            let reverse_end = reverse_start + k_size;
-            let forward_k = &seq.forward_seq[forward_start..forward_end];
-            let reverse_k = &seq.reverse_seq[reverse_start..reverse_end];
-
-            match self.kmers.entry(forward_k) {
-                Entry::Occupied(mut entry) => {
-                    entry.get_mut().add_position(seq.id, strand::FORWARD, forward_start);
-                },
-                Entry::Vacant(entry) => {
-                    let mut kmer = unsafe { Kmer::new(forward_raw.add(forward_start), k_size,
-                                                      assembly_count) };
-                    kmer.add_position(seq.id, strand::FORWARD, forward_start);
-                    entry.insert(kmer);
-                }
+        // the sequence is added to the Kmer object. In canonical mode, only the lesser of the two
+        // is created/updated.
+        self.seq_lengths.insert(seq.id, seq.forward_seq.len());
+        let shard_count = self.kmers.len();
+        let kmers = &mut self.kmers;
+        for_each_kmer(seq, self.k_size as usize, self.canonical, 0..seq.length,
+                      |kmer_seq, hash, strand, pos| {
+            let shard = &mut kmers[shard_index(hash, shard_count)];
+            shard.add_position(kmer_seq, hash, seq.id, strand, pos, assembly_count);
+        });
+    }
+
+    pub fn remove_sequence(&mut self, seq_id: u16) {
+        // Removes all positions for the given sequence, dropping any k-mers which are left with no
+        // positions. This lets a sequence be excluded before building unitigs without rebuilding
+        // the whole graph.
+        self.seq_lengths.remove(&seq_id);
+        self.kmers.par_iter_mut().for_each(|shard| {  // parallel for loop with rayon
+            shard.table.retain(|stored| {
+                stored.kmer.positions.retain(|p| p.seq_id() != seq_id);
+                !stored.kmer.positions.is_empty()
+            });
+        });
+    }
+
+    pub fn shared_kmer_counts(&self, groups: &HashMap<u16, usize>) -> Vec<(usize, usize)> {
+        // Sequences are put into groups (e.g. their input assemblies) by the given map of sequence
+        // ID to group index. For each group, this counts its sequences' k-mer positions and how
+        // many of those are in a k-mer which another group also has: (shared, total).
+        let group_count = groups.values().max().map_or(0, |&g| g + 1);
+        let mut counts = vec![(0, 0); group_count];
+        for (_, kmer) in self.kmers.iter().flat_map(|shard| shard.iter()) {
+            let first = groups[&kmer.positions[0].seq_id()];
+            let shared = kmer.positions.iter().any(|p| groups[&p.seq_id()] != first);
+            for p in &kmer.positions {
+                let (shared_count, total_count) = &mut counts[groups[&p.seq_id()]];
+                *shared_count += shared as usize;
+                *total_count += 1;
             }
+        }
+        counts
+    }
 
-            match self.kmers.entry(reverse_k) {
-                Entry::Occupied(mut entry) => {
-                    entry.get_mut().add_position(seq.id, strand::REVERSE, reverse_start);
-                },
-                Entry::Vacant(entry) => {
-                    let mut kmer = unsafe { Kmer::new(reverse_raw.add(reverse_start), k_size,
-                                                      assembly_count) };
-                    kmer.add_position(seq.id, strand::REVERSE, reverse_start);
-                    entry.insert(kmer);
-                }
+    pub fn count_histogram(&self) -> BTreeMap<usize, usize> {
+        // Returns the k-mer spectrum: how many k-mers occur each number of times in the input
+        // sequences. Each k-mer and its reverse complement are counted once together.
+        let mut histogram = BTreeMap::new();
+        for (key, kmer) in self.kmers.iter().flat_map(|shard| shard.iter()) {
+            if !self.canonical && *key > *reverse_complement(key) {
+                continue;
             }
+            *histogram.entry(kmer.depth()).or_insert(0) += 1;
         }
+        histogram
+    }
+
+    pub fn remove_rare_kmers(&mut self, min_count: usize) -> usize {
+        // Removes k-mers which occur fewer than the given number of times in the input sequences
+        // and returns how many Kmer objects were removed. Both strands of a k-mer always have the
+        // same count, so they are removed together.
+        let before = self.stored_kmer_count();
+        for shard in &mut self.kmers {
+            shard.table.retain(|stored| stored.kmer.depth() >= min_count);
+        }
+        before - self.stored_kmer_count()
+    }
+
+    fn stored(&self, query: &KmerQuery) -> Option<(&[u8], &Kmer)> {
+        self.kmers[shard_index(query.hash, self.kmers.len())].get(query)
+    }
+
+    pub fn stored_kmer_count(&self) -> usize {
+        // Returns the number of Kmer objects in the graph. In canonical mode, this is about half
+        // the number of distinct k-mers.
+        self.kmers.iter().map(|shard| shard.table.len()).sum()
+    }
+
+    #[cfg(test)]
+    pub fn get(&self, kmer: &[u8]) -> Option<KmerStrand<'_>> {
+        // Looks up a k-mer by its sequence. In canonical mode, k-mers which aren't stored directly
+        // are looked up by their reverse complement.
+        let (hash, rc_hash) = nthash(kmer);
+        self.find(KmerQuery { hash, source: kmer, source_reversed: false, edit: Edit::None,
+                              reversed: false }, rc_hash)
+    }
+
+    fn find(&self, query: KmerQuery, rc_hash: u64) -> Option<KmerStrand<'_>> {
+        // Looks up a query k-mer (whose hash and reverse-complement hash are given), also trying
+        // its reverse complement in canonical mode.
+        if let Some((key, k)) = self.stored(&query) {
+            return Some(self.kmer_strand(key, k, false, query.hash, rc_hash));
+        }
+        if self.canonical {
+            let reverse_query = KmerQuery { hash: rc_hash, reversed: true, ..query };
+            if let Some((key, k)) = self.stored(&reverse_query) {
+                return Some(self.kmer_strand(key, k, true, query.hash, rc_hash));
+            }
+        }
+        None
+    }
+
+    fn kmer_strand<'a>(&'a self, key: &'a [u8], kmer: &'a Kmer, reversed: bool, hash: u64,
+                       rc_hash: u64) -> KmerStrand<'a> {
+        KmerStrand { key, kmer, reversed, hash, rc_hash, k_size: self.k_size as usize,
+                     seq_lengths: &self.seq_lengths }
     }
 
-    pub fn next_kmers(&self, kmer: &[u8]) -> Vec<&Kmer> {
-        // Given an input k-mer, this function returns all k-mers in the graph which overlap by k-1
-        // bases on the right side. For example, ACGACT -> CGACTA, CGACTG.
-        let mut next_kmers = Vec::new();
-        let mut next_kmer = kmer[1..].to_vec();
-        next_kmer.push(b'N');
+    pub fn kmer_count(&self) -> usize {
+        // Returns the number of distinct k-mers in the graph (counting both strands, even in
+        // canonical mode).
+        if !self.canonical {
+            return self.stored_kmer_count();
+        }
+        self.kmers.iter().flat_map(|shard| shard.iter())
+            .map(|(k, _)| if *k == *reverse_complement(k) { 1 } else { 2 }).sum()
+    }
+
+    pub fn next_kmers<'a>(&'a self, kmer: &KmerStrand<'a>, next_kmers: &mut Vec<KmerStrand<'a>>) {
+        // Given an input k-mer, this function fills the buffer with all k-mers in the graph which
+        // overlap by k-1 bases on the right side. For example, ACGACT -> CGACTA, CGACTG. Candidate
+        // hashes are rolled from the input k-mer's hash.
+        next_kmers.clear();
+        let k = self.k_size;
+        let first = kmer.base(0);
+        let hash_base = kmer.hash.rotate_left(1) ^ seed(first).rotate_left(k);
+        let rc_hash_base = (kmer.rc_hash ^ rc_seed(first)).rotate_right(1);
         for &base in &ALPHABET {
-            *next_kmer.last_mut().unwrap() = base;
-            if let Some(k) = self.kmers.get(next_kmer.as_slice()) {
+            let hash = hash_base ^ seed(base);
+            let rc_hash = rc_hash_base ^ rc_seed(base).rotate_left(k - 1);
+            let query = KmerQuery { hash, source: kmer.key, source_reversed: kmer.reversed,
+                                    edit: Edit::Next(base), reversed: false };
+            if let Some(k) = self.find(query, rc_hash) {
                 next_kmers.push(k);
             }
         }
         debug_assert!(next_kmers.len() <= 4);
-        next_kmers
     }
 
-    pub fn prev_kmers(&self, kmer: &[u8]) -> Vec<&Kmer> {
-        // Given an input k-mer, this function returns all k-mers in the graph which overlap by k-1
-        // bases on the left side. For example, ACGACT -> AACGAC, GACGAC.
-        let mut prev_kmers = Vec::new();
-        let mut prev_kmer = vec![b'N'];
-        prev_kmer.extend_from_slice(&kmer[..kmer.len() - 1]);
+    pub fn prev_kmers<'a>(&'a self, kmer: &KmerStrand<'a>, prev_kmers: &mut Vec<KmerStrand<'a>>) {
+        // Given an input k-mer, this function fills the buffer with all k-mers in the graph which
+        // overlap by k-1 bases on the left side. For example, ACGACT -> AACGAC, GACGAC. Candidate
+        // hashes are rolled from the input k-mer's hash.
+        prev_kmers.clear();
+        let k = self.k_size;
+        let last = kmer.base(k as usize - 1);
+        let hash_base = (kmer.hash ^ seed(last)).rotate_right(1);
+        let rc_hash_base = (kmer.rc_hash ^ rc_seed(last).rotate_left(k - 1)).rotate_left(1);
         for &base in &ALPHABET {
-            *prev_kmer.first_mut().unwrap() = base;
-            if let Some(k) = self.kmers.get(prev_kmer.as_slice()) {
+            let hash = hash_base ^ seed(base).rotate_left(k - 1);
+            let rc_hash = rc_hash_base ^ rc_seed(base);
+            let query = KmerQuery { hash, source: kmer.key, source_reversed: kmer.reversed,
+                                    edit: Edit::Prev(base), reversed: false };
+            if let Some(k) = self.find(query, rc_hash) {
                 prev_kmers.push(k);
             }
         }
         debug_assert!(prev_kmers.len() <= 4);
-        prev_kmers
     }
 
-    pub fn iterate_kmers(&self) -> impl Iterator<Item = &Kmer> {
-        // Iterates through the Kmer objects in alphabetical order.
-        let mut sorted_keys: Vec<&&[u8]> = self.kmers.keys().collect();
-        sorted_keys.sort_unstable();
-        sorted_keys.into_iter().map(move |&k| self.kmers.get(k).unwrap())
+    pub fn iterate_kmers(&self) -> impl Iterator<Item = KmerStrand<'_>> {
+        // Iterates through the stored Kmer objects in alphabetical order. In canonical mode, this
+        // only includes the canonical strand of each k-mer.
+        let mut sorted_kmers: Vec<(&[u8], &Kmer)> = self.kmers.iter()
+            .flat_map(|shard| shard.iter()).collect();
+        sorted_kmers.sort_unstable_by_key(|(k, _)| *k);
+        sorted_kmers.into_iter().map(move |(key, kmer)| {
+            let (hash, rc_hash) = nthash(key);
+            self.kmer_strand(key, kmer, false, hash, rc_hash)
+        })
+    }
+
+    pub fn reverse<'a>(&'a self, kmer: &KmerStrand<'a>) -> KmerStrand<'a> {
+        // Given a k-mer, this function returns its reverse complement. Since all k-mers are added
+        // on both strands, it can be assumed that the reverse-complement k-mer exists.
+        if self.canonical {
+            return KmerStrand { reversed: !kmer.reversed, hash: kmer.rc_hash, rc_hash: kmer.hash,
+                                ..*kmer };
+        }
+        let query = KmerQuery { hash: kmer.rc_hash, source: kmer.key, source_reversed: false,
+                                edit: Edit::None, reversed: true };
+        let (key, k) = self.stored(&query).unwrap();
+        self.kmer_strand(key, k, false, kmer.rc_hash, kmer.hash)
+    }
+}
+
+
+fn for_each_kmer<F>(seq: &Sequence, k_size: usize, canonical: bool, starts: Range<usize>,
+                    mut f: F)
+        where F: FnMut(&[u8], u64, bool, usize) {
+    // Calls the given function with each of the sequence's k-mers (on both strands) which start
+    // in the given range of forward positions, along with its hash, strand and position. In
+    // canonical mode, only the lesser of each k-mer and its reverse complement is included.
+    // Hashes are rolled along the sequence.
+    let half_k = k_size / 2;
+    let two_half_k = half_k + half_k;
+    let k = k_size as u32;
+    let (mut forward_hash, mut reverse_hash) =
+        nthash(&seq.forward_seq[starts.start..starts.start + k_size]);
+    for forward_start in starts {
+        let forward_end = forward_start + k_size;
+        let reverse_start = seq.length + two_half_k - forward_end;
+        let reverse_end = reverse_start + k_size;
+        let forward_k = &seq.forward_seq[forward_start..forward_end];
+        let reverse_k = &seq.reverse_seq[reverse_start..reverse_end];
+        if !canonical || forward_k <= reverse_k {
+            f(forward_k, forward_hash, strand::FORWARD, forward_start);
+        }
+        if !canonical || reverse_k <= forward_k {
+            f(reverse_k, reverse_hash, strand::REVERSE, reverse_start);
+        }
+        if forward_end < seq.forward_seq.len() {
+            let (old, new) = (seq.forward_seq[forward_start], seq.forward_seq[forward_end]);
+            forward_hash = forward_hash.rotate_left(1) ^ seed(old).rotate_left(k) ^ seed(new);
+            reverse_hash = (reverse_hash ^ rc_seed(old)).rotate_right(1)
+                           ^ rc_seed(new).rotate_left(k - 1);
+        }
+    }
+}
+
+
+struct KmerShard {
+    // A shard's k-mer sequences are stored end to end in one arena, and the table holds each
+    // k-mer's offset into the arena. This is faster and uses less memory than storing a separate
+    // copy of each k-mer.
+    k_size: usize,
+    table: HashTable<StoredKmer>,
+    arena: Vec<u8>,
+}
+
+struct StoredKmer {
+    hash: u64,
+    offset: usize,
+    kmer: Kmer,
+}
+
+impl KmerShard {
+    fn new(k_size: usize) -> KmerShard {
+        KmerShard { k_size, table: HashTable::new(), arena: Vec::new() }
+    }
+
+    fn get(&self, query: &KmerQuery) -> Option<(&[u8], &Kmer)> {
+        let k_size = self.k_size;
+        self.table.find(query.hash, |s| query.matches(&self.arena[s.offset..s.offset + k_size]))
+            .map(|s| (&self.arena[s.offset..s.offset + k_size], &s.kmer))
+    }
+
+    fn iter(&self) -> impl Iterator<Item = (&[u8], &Kmer)> {
+        self.table.iter().map(|s| (&self.arena[s.offset..s.offset + self.k_size], &s.kmer))
+    }
+
+    fn add_position(&mut self, kmer_seq: &[u8], hash: u64, seq_id: u16, strand: bool, pos: usize,
+                    assembly_count: usize) {
+        let query = KmerQuery { hash, source: kmer_seq, source_reversed: false, edit: Edit::None,
+                                reversed: false };
+        let KmerShard { k_size, table, arena } = self;
+        let found = table.find_mut(hash, |s| query.matches(&arena[s.offset..s.offset + *k_size]));
+        if let Some(stored) = found {
+            stored.kmer.add_position(seq_id, strand, pos);
+        } else {
+            let mut kmer = Kmer::new(assembly_count);
+            kmer.add_position(seq_id, strand, pos);
+            let offset = arena.len();
+            arena.extend_from_slice(kmer_seq);
+            table.insert_unique(hash, StoredKmer { hash, offset, kmer }, |s| s.hash);
+        }
+    }
+}
+
+
+fn shard_index(hash: u64, shard_count: usize) -> usize {
+    // Chooses a shard for a k-mer using the high bits of its mixed hash, leaving the low bits
+    // (used for hash map buckets) evenly spread within each shard.
+    if shard_count == 1 { return 0; }
+    (hash.wrapping_mul(0x9e3779b97f4a7c15) >> 32) as usize % shard_count
+}
+
+
+const SEEDS: [u64; 256] = seed_table(false);
+const RC_SEEDS: [u64; 256] = seed_table(true);
+
+
+const fn seed_table(complement: bool) -> [u64; 256] {
+    // Per-base seeds for ntHash-style rolling hashes (the ACGT values are from ntHash). Lookups
+    // go through a table so hashing random sequence doesn't suffer from branch mispredictions.
+    let (a, c, g, t) = (0x3c8bfbb395c60474, 0x3193c18562a02b4c, 0x20323ed082572324,
+                        0x295549f54be24456);
+    let mut table = [0_u64; 256];
+    table[b'A' as usize] = if complement { t } else { a };
+    table[b'C' as usize] = if complement { g } else { c };
+    table[b'G' as usize] = if complement { c } else { g };
+    table[b'T' as usize] = if complement { a } else { t };
+    table[b'.' as usize] = 0x8a7d6c5e4f3b2a19;
+    table
+}
+
+
+fn seed(base: u8) -> u64 {
+    SEEDS[base as usize]
+}
+
+
+fn rc_seed(base: u8) -> u64 {
+    // The seed of the base's complement.
+    RC_SEEDS[base as usize]
+}
+
+
+fn nthash(kmer: &[u8]) -> (u64, u64) {
+    // Returns the rolling hashes of a k-mer and its reverse complement, computed from scratch.
+    let forward = kmer.iter().fold(0_u64, |h, &b| h.rotate_left(1) ^ seed(b));
+    let reverse = kmer.iter().enumerate()
+        .fold(0_u64, |h, (i, &b)| h ^ rc_seed(b).rotate_left(i as u32));
+    (forward, reverse)
+}
+
+
+#[derive(Clone, Copy)]
+enum Edit {
+    None,
+    Next(u8),
+    Prev(u8),
+}
+
+#[derive(Clone, Copy)]
+struct KmerQuery<'q> {
+    // A k-mer to look up, described relative to an existing sequence so neighbouring k-mers and
+    // reverse complements can be looked up without building them: the source (reverse
+    // complemented if source_reversed) is shifted by one base for Next/Prev, and then the result
+    // is reverse complemented if reversed.
+    hash: u64,
+    source: &'q [u8],
+    source_reversed: bool,
+    edit: Edit,
+    reversed: bool,
+}
+
+impl KmerQuery<'_> {
+    fn parts(&self) -> (Option<u8>, &[u8], bool, Option<u8>) {
+        // Describes the query as an optional first base, a slice of the source (reverse
+        // complemented if the bool is true) and an optional last base.
+        let k = self.source.len();
+        let sr = self.source_reversed;
+        let (front, middle, middle_rc, back) = match self.edit {
+            Edit::None => (None, self.source, sr, None),
+            Edit::Next(base) => (None, if sr { &self.source[..k - 1] } else { &self.source[1..] }, sr, Some(base)),
+            Edit::Prev(base) => (Some(base), if sr { &self.source[1..] } else { &self.source[..k - 1] }, sr, None),
+        };
+        if self.reversed {
+            (back.map(complement_base), middle, !middle_rc, front.map(complement_base))
+        } else {
+            (front, middle, middle_rc, back)
+        }
+    }
+
+    fn matches(&self, seq: &[u8]) -> bool {
+        // Returns true if the query k-mer is the same as the given stored k-mer sequence.
+        if seq.len() != self.source.len() {
+            return false;
+        }
+        let (front, middle, middle_rc, back) = self.parts();
+        let start = front.is_some() as usize;
+        let end = seq.len() - back.is_some() as usize;
+        if front.is_some_and(|b| b != seq[0]) || back.is_some_and(|b| b != seq[end]) {
+            return false;
+        }
+        if middle_rc {
+            seq[start..end].iter().zip(middle.iter().rev()).all(|(&a, &b)| a == complement_base(b))
+        } else {
+            seq[start..end] == *middle
+        }
     }
+}
+
 
-    pub fn reverse(&self, kmer: &Kmer) -> &Kmer {
-        // Given a Kmer object, this function returns the reverse-complement Kmer object. Since all
-        // k-mers are added on both strands, it can be assumed that the reverse-complement Kmer
-        // object exists.
-        let reverse_seq: &[u8] = &reverse_complement(kmer.seq());
-        self.kmers.get(reverse_seq).unwrap()
+#[derive(Clone, Copy)]
+pub struct KmerStrand<'a> {
+    // A k-mer as returned from KmerGraph lookups. This is usually just a stored Kmer, but in
+    // canonical mode it can also be the reverse complement of a stored Kmer. The hashes are for
+    // this strand's sequence and its reverse complement.
+    key: &'a [u8],
+    kmer: &'a Kmer,
+    reversed: bool,
+    hash: u64,
+    rc_hash: u64,
+    k_size: usize,
+    seq_lengths: &'a FxHashMap<u16, usize>,
+}
+
+impl<'a> KmerStrand<'a> {
+    #[cfg(test)]
+    pub fn key(&self) -> &'a [u8] {
+        // Returns the sequence of the underlying stored Kmer, which is the same for both strands
+        // in canonical mode.
+        self.key
+    }
+
+    pub fn id(&self) -> usize {
+        // Returns a number which identifies the underlying stored Kmer (its key's address), for
+        // cheaply tracking which k-mers have been seen.
+        self.key.as_ptr() as usize
+    }
+
+    pub fn seq(&self) -> Cow<'a, [u8]> {
+        if self.reversed { Cow::Owned(reverse_complement(self.key)) }
+                    else { Cow::Borrowed(self.key) }
+    }
+
+    fn base(&self, i: usize) -> u8 {
+        if self.reversed { complement_base(self.key[self.key.len() - 1 - i]) }
+                    else { self.key[i] }
+    }
+
+    pub fn depth(&self) -> usize {
+        self.kmer.depth()
+    }
+
+    pub fn positions(&self) -> Vec<Position> {
+        if !self.reversed {
+            return self.kmer.positions.clone();
+        }
+        self.kmer.positions.iter().map(|p| {
+            let seq_length = self.seq_lengths[&p.seq_id()];
+            Position::new(p.seq_id(), !p.strand(), seq_length - p.pos as usize - self.k_size)
+        }).collect()
+    }
+
+    pub fn first_position(&self) -> bool {
+        // Returns true if any of this k-mer's positions are at the start of an input sequence.
+        if !self.reversed {
+            return self.kmer.first_position();
+        }
+        self.kmer.positions.iter()
+            .any(|p| p.pos as usize + self.k_size == self.seq_lengths[&p.seq_id()])
+    }
+}
+
+
+impl fmt::Display for KmerStrand<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let seq = String::from_utf8_lossy(&self.seq()).into_owned();
+        let positions = self.positions().iter().map(|p| p.to_string())
+                                         .collect::<Vec<String>>().join(",");
+        write!(f, "{}:{}", seq, positions)
     }
 }
 
@@ -188,18 +598,19 @@ mod tests {
 
     #[test]
     fn test_kmer() {
-        let seq = String::from("ACGACTGACATCAGCACTGA").into_bytes();
-        let raw = seq.as_ptr();
-        let mut k = Kmer::new(raw, 4, 2);
+        let mut k = Kmer::new(2);
         k.add_position(1, strand::FORWARD, 123);
         k.add_position(2, strand::REVERSE, 456);
-        assert_eq!(format!("{}", k), "ACGA:1+123,2-456");
+        assert_eq!(k.depth(), 2);
+        assert!(!k.first_position());
+        k.add_position(3, strand::FORWARD, 0);
+        assert!(k.first_position());
     }
 
     #[test]
     fn test_kmer_graph() {
         let k_size = 5; let half_k = k_size / 2;
-        let mut kmer_graph = KmerGraph::new(k_size);
+        let mut kmer_graph = KmerGraph::new(k_size, 1);
         let seq = Sequence::new_with_seq(1, "ACGACTGACATCAGCACTGC".to_string(),
                                          "assembly.fasta".to_string(), "contig_1".to_string(), 20, half_k);
         kmer_graph.add_sequence(&seq, 1);
@@ -208,65 +619,254 @@ mod tests {
         // AGTGC ATCAG ATGTC CACTG CAGCA CAGTC CAGTG CATCA CGACT CGT..
         // CTGAC CTGAT CTGC. GACAT GACTG GATGT GCACT GCAGT GCTGA GTCAG
         // GTCGT GTGCT TCAGC TCAGT TCGT. TGACA TGATG TGC.. TGCTG TGTCA
-        assert_eq!(kmer_graph.kmers.len(), 40);
+        assert_eq!(kmer_graph.stored_kmer_count(), 40);
+    }
+
+    #[test]
+    fn test_remove_rare_kmers() {
+        let k_size = 5; let half_k = k_size / 2;
+        let seqs = vec![Sequence::new_with_seq(1, "ACGACTGACATCAGCACTGC".to_string(),
+                                               "assembly.fasta".to_string(), "contig_1".to_string(), 20, half_k),
+                        Sequence::new_with_seq(2, "ACGACTGACATTAGCACTGC".to_string(),
+                                               "assembly.fasta".to_string(), "contig_2".to_string(), 20, half_k)];
+        let mut kmer_graph = KmerGraph::new(k_size, 4);
+        kmer_graph.add_sequences(&seqs, 2);
+        assert_eq!(kmer_graph.remove_rare_kmers(1), 0);
+
+        // The five k-mers overlapping the C/T difference (on both strands) only occur once.
+        let total = kmer_graph.stored_kmer_count();
+        assert_eq!(kmer_graph.remove_rare_kmers(2), 20);
+        assert_eq!(kmer_graph.stored_kmer_count(), total - 20);
+        assert!(kmer_graph.iterate_kmers().all(|k| k.depth() == 2));
+        assert!(kmer_graph.get(b"ATCAG").is_none());
+        assert!(kmer_graph.get(b"ACGAC").is_some());
+    }
+
+    #[test]
+    fn test_remove_sequence() {
+        let k_size = 5; let half_k = k_size / 2;
+        let seqs = vec![Sequence::new_with_seq(1, "ACGACTGACATCAGCACTGC".to_string(),
+                                               "assembly.fasta".to_string(), "contig_1".to_string(), 20, half_k),
+                        Sequence::new_with_seq(2, "ACGACTGACATTAGCACTGC".to_string(),
+                                               "assembly.fasta".to_string(), "contig_2".to_string(), 20, half_k)];
+        let mut kmer_graph = KmerGraph::new(k_size, 1);
+        kmer_graph.add_sequences(&seqs, 2);
+        kmer_graph.remove_sequence(2);
+
+        // The graph should now be the same as one built from only the first sequence.
+        let mut expected_graph = KmerGraph::new(k_size, 1);
+        expected_graph.add_sequences(&seqs[..1], 2);
+        assert_eq!(kmer_graph.stored_kmer_count(), 40);
+        let kmers: Vec<String> = kmer_graph.iterate_kmers().map(|k| k.to_string()).collect();
+        let expected: Vec<String> = expected_graph.iterate_kmers().map(|k| k.to_string()).collect();
+        assert_eq!(kmers, expected);
+        assert!(kmer_graph.get(b"CATTA").is_none());
+
+        kmer_graph.remove_sequence(1);
+        assert_eq!(kmer_graph.stored_kmer_count(), 0);
+    }
+
+    #[test]
+    fn test_shared_kmer_counts() {
+        let k_size = 5; let half_k = k_size / 2;
+        let seqs = vec![Sequence::new_with_seq(1, "ACGACTGACATCAGCACTGC".to_string(),
+                                               "a.fasta".to_string(), "contig_1".to_string(), 20, half_k),
+                        Sequence::new_with_seq(2, "ACGACTGACATTAGCACTGC".to_string(),
+                                               "b.fasta".to_string(), "contig_2".to_string(), 20, half_k)];
+        let mut kmer_graph = KmerGraph::new(k_size, 1);
+        kmer_graph.add_sequences(&seqs, 2);
+
+        // Each sequence has 40 k-mer positions (20 per strand), and the 5 k-mers overlapping the
+        // C/T difference (on both strands) aren't in the other sequence.
+        let groups = HashMap::from([(1, 0), (2, 1)]);
+        assert_eq!(kmer_graph.shared_kmer_counts(&groups), vec![(30, 40), (30, 40)]);
+        let groups = HashMap::from([(1, 0), (2, 0)]);
+        assert_eq!(kmer_graph.shared_kmer_counts(&groups), vec![(0, 80)]);
+    }
+
+    #[test]
+    fn test_count_histogram() {
+        let k_size = 5; let half_k = k_size / 2;
+        let seqs = vec![Sequence::new_with_seq(1, "ACGACTGACATCAGCACTGC".to_string(),
+                                               "assembly.fasta".to_string(), "contig_1".to_string(), 20, half_k),
+                        Sequence::new_with_seq(2, "ACGACTGACATTAGCACTGC".to_string(),
+                                               "assembly.fasta".to_string(), "contig_2".to_string(), 20, half_k)];
+        let mut kmer_graph = KmerGraph::new(k_size, 1);
+        kmer_graph.add_sequences(&seqs, 2);
+        let histogram = kmer_graph.count_histogram();
+        assert_eq!(histogram, BTreeMap::from([(1, 10), (2, 15)]));
+
+        let mut canonical_kmer_graph = KmerGraph::new_canonical(k_size, 1);
+        canonical_kmer_graph.add_sequences(&seqs, 2);
+        assert_eq!(canonical_kmer_graph.count_histogram(), histogram);
+    }
+
+    #[test]
+    fn test_canonical_kmer_graph() {
+        let k_size = 5; let half_k = k_size / 2;
+        let mut kmer_graph = KmerGraph::new_canonical(k_size, 1);
+        let seq = Sequence::new_with_seq(1, "ACGACTGACATCAGCACTGC".to_string(),
+                                         "assembly.fasta".to_string(), "contig_1".to_string(), 20, half_k);
+        kmer_graph.add_sequence(&seq, 1);
+        assert_eq!(kmer_graph.stored_kmer_count(), 20);
+        assert_eq!(kmer_graph.kmer_count(), 40);
+
+        // TGTCA is stored as its reverse complement (TGACA) but can still be looked up.
+        assert_eq!(kmer_graph.get(b"TGACA").unwrap().key(), b"TGACA".as_slice());
+        assert_eq!(kmer_graph.get(b"TGTCA").unwrap().key(), b"TGACA".as_slice());
+        let k = kmer_graph.get(b"TGTCA").unwrap();
+        assert_eq!(k.seq().as_ref(), b"TGTCA".as_slice());
+        assert_eq!(k.positions(), vec![Position::new(1, strand::REVERSE, 12)]);
+        let r = kmer_graph.reverse(&k);
+        assert_eq!(r.seq().as_ref(), b"TGACA".as_slice());
+        assert_eq!(r.positions(), vec![Position::new(1, strand::FORWARD, 7)]);
+
+        // The first and last k-mers are at the start of the sequence on each strand.
+        assert!(kmer_graph.get(b"..ACG").unwrap().first_position());
+        assert!(kmer_graph.get(b"..GCA").unwrap().first_position());
+        assert!(!kmer_graph.get(b"TGC..").unwrap().first_position());
+        assert!(kmer_graph.get(b"AAAAA").is_none());
+
+        let next = next_kmers(&kmer_graph, b"CACTG");
+        assert_eq!(next.len(), 2);
+        assert_eq!(next[0].seq().as_ref(), b"ACTGA".as_slice());
+        assert_eq!(next[1].seq().as_ref(), b"ACTGC".as_slice());
+    }
+
+    #[test]
+    fn test_parallel_add_sequences() {
+        // Adding sequences with different numbers of shards and chunk sizes should give the same
+        // k-mers with the same positions in the same order.
+        let k_size = 5; let half_k = k_size / 2;
+        let seqs: Vec<Sequence> = ["ACGACTGACATCAGCACTGC", "TCAGCACTGCAAACGACTGA", "GGGACTGACATCAGCA"]
+            .iter().enumerate()
+            .map(|(i, s)| Sequence::new_with_seq(i + 1, s.to_string(), "assembly.fasta".to_string(),
+                                                 format!("contig_{}", i + 1), s.len(), half_k))
+            .collect();
+        let describe = |graph: &KmerGraph| -> Vec<String> {
+            graph.iterate_kmers().map(|k| format!("{}", k)).collect()
+        };
+        let mut results = Vec::new();
+        for (shards, chunk_size) in [(1, CHUNK_SIZE), (3, CHUNK_SIZE), (8, 7), (2, 1)] {
+            let mut graph = KmerGraph::new(k_size, shards);
+            graph.add_sequences_in_chunks(&seqs, 3, chunk_size);
+            assert_eq!(graph.kmers.len(), shards);
+            results.push(describe(&graph));
+        }
+        assert!(results.iter().all(|r| *r == results[0]));
+    }
+
+    fn next_kmers<'a>(kmer_graph: &'a KmerGraph, kmer: &[u8]) -> Vec<KmerStrand<'a>> {
+        let mut next = Vec::new();
+        if let Some(k) = kmer_graph.get(kmer) {
+            kmer_graph.next_kmers(&k, &mut next);
+        }
+        next
+    }
+
+    fn prev_kmers<'a>(kmer_graph: &'a KmerGraph, kmer: &[u8]) -> Vec<KmerStrand<'a>> {
+        let mut prev = Vec::new();
+        if let Some(k) = kmer_graph.get(kmer) {
+            kmer_graph.prev_kmers(&k, &mut prev);
+        }
+        prev
+    }
+
+    #[test]
+    fn test_rolling_hash() {
+        let k_size = 7; let half_k = k_size / 2;
+        let seq = Sequence::new_with_seq(1, "ACGACTGACATCAGCACTGCAGTCAGTCGATCGACTAGC".to_string(),
+                                         "assembly.fasta".to_string(), "contig_1".to_string(), 39, half_k);
+
+        // Hashes rolled along the sequence match hashes computed from scratch, and the reverse
+        // hash of a k-mer is the forward hash of its reverse complement.
+        for_each_kmer(&seq, k_size as usize, false, 0..39, |kmer_seq, hash, _, _| {
+            assert_eq!(hash, nthash(kmer_seq).0);
+            assert_eq!(nthash(kmer_seq).1, nthash(&reverse_complement(kmer_seq)).0);
+        });
+
+        // The same is true for neighbouring k-mers found by rolling, on both strands.
+        for canonical in [false, true] {
+            let mut kmer_graph = if canonical { KmerGraph::new_canonical(k_size, 1) }
+                                         else { KmerGraph::new(k_size, 1) };
+            kmer_graph.add_sequence(&seq, 1);
+            let mut next = Vec::new();
+            let mut prev = Vec::new();
+            for kmer in kmer_graph.iterate_kmers() {
+                for k in [kmer, kmer_graph.reverse(&kmer)] {
+                    assert_eq!((k.hash, k.rc_hash), nthash(&k.seq()));
+                    kmer_graph.next_kmers(&k, &mut next);
+                    kmer_graph.prev_kmers(&k, &mut prev);
+                    for n in next.iter().chain(prev.iter()) {
+                        assert_eq!((n.hash, n.rc_hash), nthash(&n.seq()));
+                    }
+                    let expected = ALPHABET.iter().filter(|&&b| {
+                        let mut candidate = k.seq()[1..].to_vec();
+                        candidate.push(b);
+                        kmer_graph.get(&candidate).is_some()
+                    }).count();
+                    assert_eq!(next.len(), expected);
+                }
+            }
+        }
     }
 
     #[test]
     fn test_next_kmers() {
         let k_size = 5; let half_k = k_size / 2;
-        let mut kmer_graph = KmerGraph::new(k_size);
+        let mut kmer_graph = KmerGraph::new(k_size, 1);
         let seq = Sequence::new_with_seq(1, "ACGACTGACATCAGCACTGC".to_string(),
                                          "assembly.fasta".to_string(), "contig_1".to_string(), 20, half_k);
         kmer_graph.add_sequence(&seq, 1);
 
-        let next = kmer_graph.next_kmers(b"ACATC");
+        let next = next_kmers(&kmer_graph, b"ACATC");
         assert_eq!(next.len(), 1);
-        assert_eq!(next[0].seq(), b"CATCA".as_slice());
+        assert_eq!(next[0].seq().as_ref(), b"CATCA".as_slice());
 
-        let next = kmer_graph.next_kmers(b"CACTG");
+        let next = next_kmers(&kmer_graph, b"CACTG");
         assert_eq!(next.len(), 2);
-        assert_eq!(next[0].seq(), b"ACTGA".as_slice());
-        assert_eq!(next[1].seq(), b"ACTGC".as_slice());
+        assert_eq!(next[0].seq().as_ref(), b"ACTGA".as_slice());
+        assert_eq!(next[1].seq().as_ref(), b"ACTGC".as_slice());
 
-        let next = kmer_graph.next_kmers(b"ACTGA");
+        let next = next_kmers(&kmer_graph, b"ACTGA");
         assert_eq!(next.len(), 2);
-        assert_eq!(next[0].seq(), b"CTGAC".as_slice());
-        assert_eq!(next[1].seq(), b"CTGAT".as_slice());
+        assert_eq!(next[0].seq().as_ref(), b"CTGAC".as_slice());
+        assert_eq!(next[1].seq().as_ref(), b"CTGAT".as_slice());
 
-        let next = kmer_graph.next_kmers(b"AAAAA");
+        let next = next_kmers(&kmer_graph, b"AAAAA");
         assert_eq!(next.len(), 0);
     }
 
     #[test]
     fn test_prev_kmers() {
         let k_size = 5; let half_k = k_size / 2;
-        let mut kmer_graph = KmerGraph::new(k_size);
+        let mut kmer_graph = KmerGraph::new(k_size, 1);
         let seq = Sequence::new_with_seq(1, "ACGACTGACATCAGCACTGC".to_string(),
                                          "assembly.fasta".to_string(), "contig_1".to_string(), 20, half_k);
         kmer_graph.add_sequence(&seq, 1);
 
-        let prev = kmer_graph.prev_kmers(b"CATCA");
+        let prev = prev_kmers(&kmer_graph, b"CATCA");
         assert_eq!(prev.len(), 1);
-        assert_eq!(prev[0].seq(), b"ACATC".as_slice());
+        assert_eq!(prev[0].seq().as_ref(), b"ACATC".as_slice());
 
-        let prev = kmer_graph.prev_kmers(b"CTGAC");
+        let prev = prev_kmers(&kmer_graph, b"CTGAC");
         assert_eq!(prev.len(), 2);
-        assert_eq!(prev[0].seq(), b"ACTGA".as_slice());
-        assert_eq!(prev[1].seq(), b"GCTGA".as_slice());
+        assert_eq!(prev[0].seq().as_ref(), b"ACTGA".as_slice());
+        assert_eq!(prev[1].seq().as_ref(), b"GCTGA".as_slice());
 
-        let prev = kmer_graph.prev_kmers(b"ACTGC");
+        let prev = prev_kmers(&kmer_graph, b"ACTGC");
         assert_eq!(prev.len(), 2);
-        assert_eq!(prev[0].seq(), b"CACTG".as_slice());
-        assert_eq!(prev[1].seq(), b"GACTG".as_slice());
+        assert_eq!(prev[0].seq().as_ref(), b"CACTG".as_slice());
+        assert_eq!(prev[1].seq().as_ref(), b"GACTG".as_slice());
 
-        let prev = kmer_graph.prev_kmers(b"AAAAA");
+        let prev = prev_kmers(&kmer_graph, b"AAAAA");
         assert_eq!(prev.len(), 0);
     }
 
     #[test]
     fn test_iterate_kmers() {
         let k_size = 5; let half_k = k_size / 2;
-        let mut kmer_graph = KmerGraph::new(k_size);
+        let mut kmer_graph = KmerGraph::new(k_size, 1);
         let seq = Sequence::new_with_seq(1, "ACGACTGACATCAGCACTGC".to_string(),
                                          "assembly.fasta".to_string(), "contig_1".to_string(), 20, half_k);
         kmer_graph.add_sequence(&seq, 1);
@@ -277,7 +877,7 @@ mod tests {
             "GTCGT", "GTGCT", "TCAGC", "TCAGT", "TCGT.", "TGACA", "TGATG", "TGC..", "TGCTG", "TGTCA"
         ];
         let expected_kmers: Vec<&[u8]> = expected_kmers.iter().map(|s| s.as_bytes()).collect();
-        let actual_kmers: Vec<&[u8]> = kmer_graph.iterate_kmers().map(|kmer| kmer.seq()).collect();
+        let actual_kmers: Vec<&[u8]> = kmer_graph.iterate_kmers().map(|kmer| kmer.key()).collect();
         assert_eq!(expected_kmers, actual_kmers);
     }
 }
@@ -15,25 +15,47 @@
 use std::path::PathBuf;
 use clap::{Parser, Subcommand, crate_version};
 
+mod annotate_transfer;
+mod batch;
+mod benchmark;
+mod clean;
 mod cluster;
 mod combine;
+mod compare;
 mod compress;
 mod decompress;
+mod diff;
 mod dotplot;
+mod export;
 mod graph_simplification;
+mod helper;
 mod kmer_graph;
 mod log;
+mod map;
+mod merge;
 mod metrics;
+mod minimizer_graph;
 mod misc;
+mod pipeline;
+mod poa;
 mod position;
+mod purge;
+mod read_threading;
 mod resolve;
+mod rotate;
 mod sequence;
+mod serve;
+mod simulate;
+mod sketch;
+mod stats;
 mod subsample;
 mod table;
 mod test_gfa;
 mod trim;
 mod unitig;
 mod unitig_graph;
+mod validate;
+mod view;
 
 #[cfg(test)]
 mod tests;
@@ -63,6 +85,76 @@ struct Cli {
 #[derive(Subcommand)]
 enum Commands {
 
+    /// transfer GFF3 annotations from an input assembly onto the consensus
+    AnnotateTransfer {
+        /// Autocycler directory containing input_assemblies.gfa and consensus_assembly.fasta
+        /// (required)
+        #[clap(short = 'a', long = "autocycler_dir", required = true)]
+        autocycler_dir: PathBuf,
+
+        /// Input assembly filename the annotations are for, e.g. flye_01.fasta (required)
+        #[clap(long = "assembly", required = true)]
+        assembly: String,
+
+        /// GFF3 annotations of the input assembly, GenBank is not supported (required)
+        #[clap(short = 'i', long = "in_gff", required = true)]
+        in_gff: PathBuf,
+
+        /// Output GFF3 annotations of the consensus assembly (required)
+        #[clap(short = 'o', long = "out_gff", required = true)]
+        out_gff: PathBuf,
+    },
+
+    /// compare a consensus assembly to a trusted reference
+    Benchmark {
+        /// Autocycler directory containing consensus_assembly.fasta (required)
+        #[clap(short = 'a', long = "autocycler_dir", required = true)]
+        autocycler_dir: PathBuf,
+
+        /// Reference genome in FASTA format (required)
+        #[clap(short = 'r', long = "reference", required = true)]
+        reference: PathBuf,
+
+        /// K-mer size for joint De Bruijn graph
+        #[clap(long = "kmer", default_value = "51")]
+        kmer: u32,
+    },
+
+    /// apply manual cleanup operations to a unitig graph
+    Clean {
+        /// Autocycler GFA file (required)
+        #[clap(short = 'i', long = "in_gfa", required = true)]
+        in_gfa: PathBuf,
+
+        /// File path where cleaned graph will be saved (required)
+        #[clap(short = 'o', long = "out_gfa", required = true)]
+        out_gfa: PathBuf,
+
+        /// Comma-delimited unitig numbers to remove
+        #[clap(long = "remove")]
+        remove: Option<String>,
+
+        /// Remove unitigs with a depth below this
+        #[clap(long = "min_depth")]
+        min_depth: Option<f64>,
+
+        /// Remove unitigs shorter than this (bp)
+        #[clap(long = "min_length")]
+        min_length: Option<u32>,
+
+        /// Comma-delimited links to delete, each as two signed unitigs (e.g. 3+:5-)
+        #[clap(long = "delete_links")]
+        delete_links: Option<String>,
+
+        /// Comma-delimited sequence IDs (GFA path names) to drop
+        #[clap(long = "drop_seqs")]
+        drop_seqs: Option<String>,
+
+        /// GFA version for output graph (1 or 2)
+        #[clap(long = "gfa_version", default_value = "1")]
+        gfa_version: u32,
+    },
+
     /// cluster contigs in the unitig graph based on similarity
     Cluster {
         /// Autocycler directory containing input_assemblies.gfa file (required)
@@ -78,6 +170,22 @@ enum Commands {
                help = "exclude clusters with fewer than this many assemblies [default: automatic]")]
         min_assemblies: Option<usize>,
 
+        /// fail clusters with a pairwise contig distance above this
+        #[clap(long = "max_cluster_distance")]
+        max_cluster_distance: Option<f64>,
+
+        /// fail clusters whose contig lengths differ by more than this fraction
+        #[clap(long = "max_length_diff")]
+        max_length_diff: Option<f64>,
+
+        /// exclude clusters with a median contig length below this (bp)
+        #[clap(long = "min_cluster_len")]
+        min_cluster_len: Option<usize>,
+
+        /// exclude clusters with a median contig length above this (bp)
+        #[clap(long = "max_cluster_len")]
+        max_cluster_len: Option<usize>,
+
         /// refuse to run if mean contigs per assembly exceeds this value
         #[clap(long = "max_contigs", default_value = "25")]
         max_contigs: u32,
@@ -86,6 +194,34 @@ enum Commands {
         #[clap(long = "manual", hide_default_value = true,
                help = "manually define clusters using tree node numbers [default: automatic]")]
         manual: Option<String>,
+
+        /// contig distance metric: graph or jaccard (shared unitigs), mash or ani (k-mer sketches)
+        #[clap(long = "distance", default_value = "graph")]
+        distance: String,
+
+        /// Split contigs which span two clusters (chimeras) and cluster the pieces separately
+        #[clap(long = "split_chimeras")]
+        split_chimeras: bool,
+
+        /// FASTA of reference replicons used to label clusters, by name or label=... (optional)
+        #[clap(long = "references")]
+        references: Option<PathBuf>,
+
+        /// Number of CPU threads
+        #[clap(short = 't', long = "threads", default_value = "8")]
+        threads: usize,
+
+        /// GFA version for output graphs (1 or 2)
+        #[clap(long = "gfa_version", default_value = "1")]
+        gfa_version: u32,
+
+        /// Samples manifest for batch mode, with --autocycler_dir containing a directory per sample
+        #[clap(long = "samples")]
+        samples: Option<PathBuf>,
+
+        /// Number of samples to process at once (with --samples)
+        #[clap(long = "jobs", default_value = "1")]
+        jobs: usize,
     },
 
     /// combine Autocycler GFAs into one assembly
@@ -97,15 +233,40 @@ enum Commands {
         /// Autocycler cluster GFA files (one or more required)
         #[clap(short = 'i', long = "in_gfas", required = true, num_args = 1..)]
         in_gfas: Vec<PathBuf>,
+
+        /// GFA version for output graphs (1 or 2)
+        #[clap(long = "gfa_version", default_value = "1")]
+        gfa_version: u32,
+    },
+
+    /// compare two assemblies using a joint unitig graph
+    Compare {
+        /// First assembly, e.g. an Autocycler consensus_assembly.fasta (required)
+        #[clap(short = 'a', long = "assembly_a", required = true)]
+        assembly_a: PathBuf,
+
+        /// Second assembly (required)
+        #[clap(short = 'b', long = "assembly_b", required = true)]
+        assembly_b: PathBuf,
+
+        /// K-mer size for joint De Bruijn graph
+        #[clap(long = "kmer", default_value = "51")]
+        kmer: u32,
+
+        /// Also save the joint unitig graph to this GFA file
+        #[clap(long = "out_gfa")]
+        out_gfa: Option<PathBuf>,
     },
 
     /// compress input contigs into a unitig graph
     Compress {
-        /// Directory containing input assemblies (required)
-        #[clap(short = 'i', long = "assemblies_dir", required = true)]
-        assemblies_dir: PathBuf,
+        /// Directory containing input assemblies (required unless --samples is used)
+        #[clap(short = 'i', long = "assemblies_dir", required_unless_present = "samples",
+               conflicts_with = "samples")]
+        assemblies_dir: Option<PathBuf>,
 
-        /// Autocycler directory to be created (required)
+        /// Autocycler directory to be created, or with --samples the directory for all
+        /// samples' Autocycler directories (required)
         #[clap(short = 'a', long = "autocycler_dir", required = true)]
         autocycler_dir: PathBuf,
 
@@ -116,6 +277,106 @@ enum Commands {
         /// Number of CPU threads
         #[clap(short = 't', long = "threads", default_value = "8")]
         threads: usize,
+
+        /// GFA version for output graphs (1 or 2)
+        #[clap(long = "gfa_version", default_value = "1")]
+        gfa_version: u32,
+
+        /// Only store canonical k-mers (uses less memory but is slower)
+        #[clap(long = "canonical_kmers")]
+        canonical_kmers: bool,
+
+        /// Build the graph from (w,k-1)-minimizers with this window size instead of all k-mers
+        /// (uses much less memory but gives a less compact graph)
+        #[clap(long = "minimizer_window")]
+        minimizer_window: Option<usize>,
+
+        /// Exclude k-mers which occur fewer than this many times from the graph (the excluded
+        /// bases are stored with each sequence's path to keep it reconstructable)
+        #[clap(long = "min_kmer_count", default_value = "1")]
+        min_kmer_count: usize,
+
+        /// Exclude input assemblies with less than this fraction of their k-mers found in any
+        /// other assembly, e.g. one from the wrong sample (0 = keep all assemblies)
+        #[clap(long = "min_shared_kmers", default_value = "0.0")]
+        min_shared_kmers: f64,
+
+        /// Optional TSV file for the k-mer count histogram
+        #[clap(long = "kmer_histogram")]
+        kmer_histogram: Option<PathBuf>,
+
+        /// For GFA input assemblies, use paths (P lines) as contigs instead of segments
+        #[clap(long = "gfa_paths")]
+        gfa_paths: bool,
+
+        /// Exclude input contigs shorter than this length (bp)
+        #[clap(long = "min_contig_len", default_value = "0")]
+        min_contig_len: usize,
+
+        /// Exclude input contigs whose header matches this regex
+        #[clap(long = "exclude_contigs")]
+        exclude_contigs: Option<String>,
+
+        /// Collapse contigs which are identical (on either strand) to a contig in another assembly
+        #[clap(long = "dedup_contigs")]
+        dedup_contigs: bool,
+
+        /// Rename contigs with duplicate names in an assembly (instead of quitting with an error)
+        #[clap(long = "rename_duplicates")]
+        rename_duplicates: bool,
+
+        /// Optional TSV file of assembly weights (assembly filename and weight on each line) to
+        /// scale each assembly's contribution to unitig depths
+        #[clap(long = "weights")]
+        weights: Option<PathBuf>,
+
+        /// Collapse pairs of unitigs with the same neighbours which differ by up to this many
+        /// bases, recording the difference as an ambiguity (0 = no collapsing)
+        #[clap(long = "max_unitig_diff", default_value = "0")]
+        max_unitig_diff: usize,
+
+        /// Collapse bubbles with all arms up to this length (bp) into their best-supported arm,
+        /// rewriting the affected input sequences (0 = no bubble popping)
+        #[clap(long = "max_bubble_len", default_value = "0")]
+        max_bubble_len: u32,
+
+        /// Only collapse bubble arms which differ from the best-supported arm by up to this
+        /// fraction (edit distance relative to the longer arm)
+        #[clap(long = "max_bubble_div", default_value = "0.05")]
+        max_bubble_div: f64,
+
+        /// Replace the kept arm of each popped bubble with a partial-order alignment consensus of
+        /// the collapsed arms, instead of using the best-supported arm verbatim
+        #[clap(long = "bubble_poa")]
+        bubble_poa: bool,
+
+        /// Unroll tandem repeat loops up to this length (bp) to the number of passes best supported
+        /// by the input sequences, rewriting the others (0 = no loop unrolling)
+        #[clap(long = "max_loop_len", default_value = "0")]
+        max_loop_len: u32,
+
+        /// Clip dead-end unitigs up to this length (bp), trimming the input sequences which end
+        /// in them (0 = no tip clipping)
+        #[clap(long = "max_tip_len", default_value = "0")]
+        max_tip_len: u32,
+
+        /// Only clip tips with a depth below this
+        #[clap(long = "min_tip_depth", default_value = "2.0")]
+        min_tip_depth: f64,
+
+        /// Resume from the checkpoint saved by a previous unfinished run, if there is one and it
+        /// used the same graph-building settings (saved once the unitig graph is built, so earlier
+        /// interruptions restart from the beginning)
+        #[clap(long = "resume")]
+        resume: bool,
+
+        /// Samples manifest (one name and assemblies directory per line, tab-separated) for batch mode
+        #[clap(long = "samples")]
+        samples: Option<PathBuf>,
+
+        /// Number of samples to process at once (with --samples)
+        #[clap(long = "jobs", default_value = "1")]
+        jobs: usize,
     },
 
     /// decompress contigs from a unitig graph
@@ -124,13 +385,58 @@ enum Commands {
         #[clap(short = 'i', long = "in_gfa", required = true)]
         in_gfa: PathBuf,
 
-        /// Directory where decompressed sequences will be saved (either -o or -f is required)
+        /// Directory where decompressed sequences will be saved
         #[clap(short = 'o', long = "out_dir")]
         out_dir: Option<PathBuf>,
 
-        /// FASTA file where decompressed sequences will be saved (either -o or -f is required)
+        /// FASTA file where all decompressed sequences will be saved, with headers prefixed by
+        /// filename (- for stdout)
         #[clap(short = 'f', long = "out_file")]
         out_file: Option<PathBuf>,
+
+        /// Only decompress sequences in this cluster
+        #[clap(long = "cluster")]
+        cluster: Option<u16>,
+
+        /// Only decompress these sequences (comma-delimited sequence IDs or contig names)
+        #[clap(long = "seqs")]
+        seqs: Option<String>,
+
+        /// Wrap FASTA sequences to this many bases per line (0 = no wrapping, original = same
+        /// wrapping as the input assemblies)
+        #[clap(long = "wrap", default_value = "0")]
+        wrap: String,
+
+        /// Check reconstructed sequences against the checksums stored by compress
+        #[clap(long = "verify")]
+        verify: bool,
+
+        /// BED file of the unitig coordinates in each decompressed sequence
+        #[clap(long = "bed")]
+        bed: Option<PathBuf>,
+
+        /// TSV file summarising each decompressed sequence's path through the graph
+        #[clap(long = "report")]
+        report: Option<PathBuf>,
+
+        /// Compression level (1-9) for compressed files (.gz, .zst, .xz or .bz2) in --out_dir
+        #[clap(long = "compress_level", default_value = "6")]
+        compress_level: u32,
+
+        /// Save compressed inputs (.gz, .zst, .xz or .bz2) uncompressed in --out_dir
+        #[clap(long = "uncompressed")]
+        uncompressed: bool,
+    },
+
+    /// report differences between two unitig graphs
+    Diff {
+        /// Old Autocycler GFA file (required)
+        #[clap(long = "old_gfa", required = true)]
+        old_gfa: PathBuf,
+
+        /// New Autocycler GFA file (required)
+        #[clap(long = "new_gfa", required = true)]
+        new_gfa: PathBuf,
     },
 
     /// generate an all-vs-all dotplot from a unitig graph
@@ -152,22 +458,293 @@ enum Commands {
         kmer: u32,
     },
 
+    /// export a unitig graph to another format
+    Export {
+        /// Autocycler GFA file (required)
+        #[clap(short = 'i', long = "in_gfa", required = true)]
+        in_gfa: PathBuf,
+
+        /// File path where exported graph will be saved (required)
+        #[clap(short = 'o', long = "out_file", required = true)]
+        out_file: PathBuf,
+
+        /// Output format: dot, graphml, fasta (unitig sequences) or bubbles (TSV)
+        #[clap(long = "format", default_value = "dot")]
+        format: String,
+    },
+
+    /// run an assembler on a read set, saving the assembly for autocycler compress
+    Helper {
+        /// Assembler to run: flye, raven, miniasm or canu
+        #[clap(value_name = "TASK")]
+        task: String,
+
+        /// Input long reads in FASTQ format, e.g. a subsampled read set (required)
+        #[clap(short = 'r', long = "reads", required = true)]
+        reads: PathBuf,
+
+        /// Output prefix, e.g. assemblies/flye_01 (.fasta and .gfa will be added) (required)
+        #[clap(short = 'o', long = "out_prefix", required = true)]
+        out_prefix: PathBuf,
+
+        /// Estimated genome size (required for canu)
+        #[clap(short = 'g', long = "genome_size")]
+        genome_size: Option<String>,
+
+        /// Read type: ont_r9, ont_r10, pacbio_clr or pacbio_hifi
+        #[clap(long = "read_type", default_value = "ont_r10")]
+        read_type: String,
+
+        /// Number of threads to use
+        #[clap(short = 't', long = "threads", default_value = "8")]
+        threads: usize,
+    },
+
+    /// align long reads to a unitig graph
+    Map {
+        /// Autocycler GFA file (required)
+        #[clap(short = 'i', long = "in_gfa", required = true)]
+        in_gfa: PathBuf,
+
+        /// Long reads in FASTQ format (required)
+        #[clap(short = 'r', long = "reads", required = true)]
+        reads: PathBuf,
+
+        /// Output GAF file of read-to-graph alignments (required)
+        #[clap(short = 'o', long = "out_gaf", required = true)]
+        out_gaf: PathBuf,
+
+        /// Also save the graph with unitig depths set from the read alignments
+        #[clap(long = "out_gfa")]
+        out_gfa: Option<PathBuf>,
+
+        /// GFA version for output graph (1 or 2)
+        #[clap(long = "gfa_version", default_value = "1")]
+        gfa_version: u32,
+    },
+
+    /// merge compressed unitig graphs into one graph
+    Merge {
+        /// Autocycler compressed GFA files with the same k-mer size (two or more required)
+        #[clap(short = 'i', long = "in_gfas", required = true, num_args = 2..)]
+        in_gfas: Vec<PathBuf>,
+
+        /// Output merged GFA file (required)
+        #[clap(short = 'o', long = "out_gfa", required = true)]
+        out_gfa: PathBuf,
+
+        /// GFA version for output graph (1 or 2)
+        #[clap(long = "gfa_version", default_value = "1")]
+        gfa_version: u32,
+    },
+
+    /// run the whole pipeline, from reads or input assemblies to a consensus assembly
+    Pipeline {
+        /// Output directory (required)
+        #[clap(short = 'o', long = "out_dir", required = true)]
+        out_dir: PathBuf,
+
+        /// Input long reads in FASTQ format, to subsample and assemble
+        #[clap(short = 'r', long = "reads")]
+        reads: Option<PathBuf>,
+
+        /// Directory containing existing input assemblies (instead of --reads)
+        #[clap(short = 'i', long = "assemblies_dir")]
+        assemblies_dir: Option<PathBuf>,
+
+        /// Estimated genome size (required with --reads)
+        #[clap(short = 'g', long = "genome_size")]
+        genome_size: Option<String>,
+
+        /// Comma-delimited assemblers to run on each read subset (with --reads)
+        #[clap(long = "assemblers", default_value = "flye,raven,miniasm")]
+        assemblers: String,
+
+        /// Number of subsampled read sets (with --reads)
+        #[clap(short = 'c', long = "count", default_value = "4")]
+        count: usize,
+
+        /// Read type: ont_r9, ont_r10, pacbio_clr or pacbio_hifi (with --reads)
+        #[clap(long = "read_type", default_value = "ont_r10")]
+        read_type: String,
+
+        /// Number of threads to use
+        #[clap(short = 't', long = "threads", default_value = "8")]
+        threads: usize,
+
+        /// K-mer size for De Bruijn graph
+        #[clap(long = "kmer", default_value = "51")]
+        kmer: u32,
+
+        /// GFA version for output graphs (1 or 2)
+        #[clap(long = "gfa_version", default_value = "1")]
+        gfa_version: u32,
+    },
+
+    /// remove input assemblies from an existing compressed graph
+    Purge {
+        /// Autocycler directory containing input_assemblies.gfa (required)
+        #[clap(short = 'a', long = "autocycler_dir", required = true)]
+        autocycler_dir: PathBuf,
+
+        /// Comma-delimited input assembly filenames to remove, e.g. flye_01.fasta (required)
+        #[clap(long = "assemblies", required = true)]
+        assemblies: String,
+
+        /// GFA version for output graph (1 or 2)
+        #[clap(long = "gfa_version", default_value = "1")]
+        gfa_version: u32,
+    },
+
     /// resolve repeats in the the unitig graph
     Resolve {
         /// Autocycler directory (required)
         #[clap(short = 'c', long = "cluster_dir", required = true)]
         cluster_dir: PathBuf,
 
+        /// Long reads (FASTQ) or read alignments to 2_trimmed.gfa (GAF) for extra bridging evidence
+        #[clap(long = "reads")]
+        reads: Option<PathBuf>,
+
+        /// How to break ties between equally common choices: depth, length or trusted
+        #[clap(long = "tie_break", default_value = "depth")]
+        tie_break: String,
+
+        /// Assembly filename (e.g. flye.fasta) favoured by --tie_break trusted
+        #[clap(long = "trusted")]
+        trusted: Option<String>,
+
+        /// How to count paths when choosing bridge paths: assembly (each sequence counts once) or depth (by sequence weights from compress --weights)
+        #[clap(long = "weighting", default_value = "assembly")]
+        weighting: String,
+
+        /// Keep all paths as bubbles for bridges whose best path has less than this fraction of assembly support
+        #[clap(long = "bubble_support")]
+        bubble_support: Option<f64>,
+
+        /// Treat the cluster as a linear replicon and preserve its ends (auto-detected if all sequences share dead ends)
+        #[clap(long = "linear")]
+        linear: bool,
+
+        /// Cull conflicting bridges in rounds, from strict to relaxed depth ratios
+        #[clap(long = "iterative")]
+        iterative: bool,
+
+        /// Depth ratio for the last --iterative round, conflicts not resolved by it are left in the graph [default: 1]
+        #[clap(long = "min_ratio")]
+        min_ratio: Option<f64>,
+
+        /// Print the planned path choices, link deletions, culls and merges without writing any files
+        #[clap(long = "dry_run")]
+        dry_run: bool,
+
         /// Enable verbose output
         #[clap(long = "verbose")]
         verbose: bool,
+
+        /// GFA version for output graphs (1 or 2)
+        #[clap(long = "gfa_version", default_value = "1")]
+        gfa_version: u32,
+
+        /// Samples manifest for batch mode, with --cluster_dir containing a directory per sample
+        #[clap(long = "samples")]
+        samples: Option<PathBuf>,
+
+        /// Number of samples to process at once (with --samples)
+        #[clap(long = "jobs", default_value = "1")]
+        jobs: usize,
+    },
+
+    /// rotate circular sequences to begin with a start gene
+    Rotate {
+        /// Input FASTA, e.g. consensus_assembly.fasta from autocycler combine (required)
+        #[clap(short = 'i', long = "in_fasta", required = true)]
+        in_fasta: PathBuf,
+
+        /// Output FASTA of rotated sequences (required)
+        #[clap(short = 'o', long = "out_fasta", required = true)]
+        out_fasta: PathBuf,
+
+        /// FASTA of start gene sequences, e.g. dnaA and repA (required)
+        #[clap(short = 'g', long = "start_genes", required = true)]
+        start_genes: PathBuf,
+    },
+
+    /// view an Autocycler directory in a web browser
+    Serve {
+        /// Autocycler directory (required)
+        #[clap(value_name = "AUTOCYCLER_DIR")]
+        autocycler_dir: PathBuf,
+
+        /// Unitig graph to show, relative to the Autocycler directory
+        #[clap(long = "gfa", default_value = "input_assemblies.gfa")]
+        gfa: String,
+
+        /// Port for the local web server
+        #[clap(short = 'p', long = "port", default_value = "8080")]
+        port: u16,
+    },
+
+    /// simulate a genome and imperfect input assemblies of it
+    Simulate {
+        /// Output directory (required)
+        #[clap(short = 'o', long = "out_dir", required = true)]
+        out_dir: PathBuf,
+
+        /// Chromosome size
+        #[clap(short = 'g', long = "genome_size", default_value = "500k")]
+        genome_size: String,
+
+        /// Comma-delimited plasmid sizes
+        #[clap(long = "plasmids", default_value = "20k,4k")]
+        plasmids: String,
+
+        /// Number of repeat copies to insert into the genome
+        #[clap(long = "repeats", default_value = "3")]
+        repeats: usize,
+
+        /// Length of the repeat sequence (bp)
+        #[clap(long = "repeat_length", default_value = "1500")]
+        repeat_length: usize,
+
+        /// Number of simulated assemblies to output
+        #[clap(short = 'c', long = "count", default_value = "6")]
+        count: usize,
+
+        /// Number of SNPs in each assembly
+        #[clap(long = "snps", default_value = "5")]
+        snps: usize,
+
+        /// Number of small indels in each assembly
+        #[clap(long = "indels", default_value = "5")]
+        indels: usize,
+
+        /// Number of assemblies with a mis-join
+        #[clap(long = "misjoins", default_value = "1")]
+        misjoins: usize,
+
+        /// Seed for random number generator
+        #[clap(short = 's', long = "seed", default_value = "0")]
+        seed: u64,
+    },
+
+    /// print assembly statistics for a FASTA or Autocycler GFA
+    Stats {
+        /// Input FASTA or Autocycler GFA file (required)
+        #[clap(short = 'i', long = "input", required = true)]
+        input: PathBuf,
+
+        /// Output format: text, tsv or json
+        #[clap(long = "format", default_value = "text")]
+        format: String,
     },
 
     /// subsample a long-read set
     Subsample {
-        /// Input long reads in FASTQ format (required)
-        #[clap(short = 'r', long = "reads", required = true)]
-        reads: PathBuf,
+        /// Input long reads in FASTQ format, as one or more files which are pooled together
+        /// (a .txt or .fofn file lists read files one per line) (required)
+        #[clap(short = 'r', long = "reads", required = true, num_args = 1..)]
+        reads: Vec<PathBuf>,
 
         /// Output directory (required)
         #[clap(short = 'o', long = "out_dir")]
@@ -185,6 +762,50 @@ enum Commands {
         #[clap(short = 'd', long = "min_read_depth", default_value = "25.0")]
         min_read_depth: f64,
 
+        /// Exclude reads shorter than this length (bp) before subsetting
+        #[clap(long = "min_length", default_value = "0")]
+        min_length: usize,
+
+        /// Exclude reads with a mean quality (from mean error probability) below this before
+        /// subsetting
+        #[clap(long = "min_mean_q", default_value = "0.0")]
+        min_mean_q: f64,
+
+        /// Exclude reads with the same name or sequence as an earlier read before subsetting
+        #[clap(long = "dedup_reads")]
+        dedup_reads: bool,
+
+        /// Fraction of reads to hold out from all subsets and save to qc_reads.fastq
+        #[clap(long = "holdout", default_value = "0.0")]
+        holdout: f64,
+
+        /// Compression for subset files (none or gz)
+        #[clap(long = "compress", default_value = "none")]
+        compress: String,
+
+        /// Gzip compression level (1-9) for subset files
+        #[clap(long = "compress_level", default_value = "6")]
+        compress_level: u32,
+
+        /// Subset reads in a single pass using a hash of each read's name (uses little memory and
+        /// allows reading from stdin with -, but needs --input_bases)
+        #[clap(long = "stream")]
+        stream: bool,
+
+        /// Estimated total bases in the input reads, used to size subsets in --stream mode
+        #[clap(long = "input_bases")]
+        input_bases: Option<String>,
+
+        /// Choose reads with probability proportional to their length, and size subsets by bases
+        /// instead of read count
+        #[clap(long = "length_weighted")]
+        length_weighted: bool,
+
+        /// Order reads so that every subset has the same read-length distribution (and N50) as
+        /// the full read set
+        #[clap(long = "stratified")]
+        stratified: bool,
+
         /// Seed for random number generator
         #[clap(short = 's', long = "seed", default_value = "0")]
         seed: u64,
@@ -192,28 +813,28 @@ enum Commands {
 
     /// create TSV line from YAML files
     Table {
-        /// Autocycler directory (if absent, a header line will be output)
-        #[clap(short = 'a', long = "autocycler_dir")]
-        autocycler_dir: Option<PathBuf>,
+        /// Autocycler directory (if absent, a header line will be output). If multiple are given,
+        /// a header line is output followed by one line per directory, named by directory.
+        #[clap(short = 'a', long = "autocycler_dir", num_args = 1..)]
+        autocycler_dir: Vec<PathBuf>,
 
-        /// Sample name
+        /// Sample name (only for a single autocycler directory)
         #[clap(short = 'n', long = "name", default_value = "", hide_default_value = true,
                help = "Sample name [default: blank]")]
         name: String,
 
         /// Comma-delimited list of YAML fields to include
         #[clap(short = 'f', long = "fields",
-               default_value = "input_read_count, input_read_bases, input_read_n50, \
-                                pass_cluster_count, fail_cluster_count, overall_clustering_score, \
-                                untrimmed_cluster_size, untrimmed_cluster_distance, \
-                                trimmed_cluster_size, trimmed_cluster_median, trimmed_cluster_mad, \
-                                consensus_assembly_bases, consensus_assembly_unitigs, \
-                                consensus_assembly_fully_resolved")]
+               default_value = table::DEFAULT_FIELDS)]
         fields: String,
 
         /// Significant figures to use for floating point numbers
         #[clap(short = 's', long = "sigfigs", default_value = "3")]
         sigfigs: usize,
+
+        /// Output a header line even for a single autocycler directory
+        #[clap(long = "header")]
+        header: bool,
     },
 
     /// trim contigs in a cluster
@@ -234,9 +855,39 @@ enum Commands {
         #[clap(long = "mad", default_value = "5.0")]
         mad: f64,
 
+        /// Treat the cluster as a linear replicon and preserve its ends (auto-detected if all sequences share dead ends)
+        #[clap(long = "linear")]
+        linear: bool,
+
         /// Number of CPU threads
         #[clap(short = 't', long = "threads", default_value = "8")]
         threads: usize,
+
+        /// GFA version for output graphs (1 or 2)
+        #[clap(long = "gfa_version", default_value = "1")]
+        gfa_version: u32,
+
+        /// Samples manifest for batch mode, with --cluster_dir containing a directory per sample
+        #[clap(long = "samples")]
+        samples: Option<PathBuf>,
+
+        /// Number of samples to process at once (with --samples)
+        #[clap(long = "jobs", default_value = "1")]
+        jobs: usize,
+    },
+
+    /// check a unitig graph for problems
+    Validate {
+        /// Autocycler GFA file (required)
+        #[clap(short = 'i', long = "in_gfa", required = true)]
+        in_gfa: PathBuf,
+    },
+
+    /// interactively browse the unitigs of a graph in the terminal
+    View {
+        /// Autocycler GFA file (required)
+        #[clap(short = 'i', long = "in_gfa", required = true)]
+        in_gfa: PathBuf,
     },
 }
 
@@ -245,32 +896,144 @@ fn main() {
     let cli = Cli::parse();
 
     match cli.command {
-        Some(Commands::Cluster { autocycler_dir, cutoff, min_assemblies, max_contigs, manual }) => {
-            cluster::cluster(autocycler_dir, cutoff, min_assemblies, max_contigs, manual);
+        Some(Commands::AnnotateTransfer { autocycler_dir, assembly, in_gff, out_gff }) => {
+            annotate_transfer::annotate_transfer(autocycler_dir, assembly, in_gff, out_gff);
+        },
+        Some(Commands::Benchmark { autocycler_dir, reference, kmer }) => {
+            benchmark::benchmark(autocycler_dir, reference, kmer);
+        },
+        Some(Commands::Clean { in_gfa, out_gfa, remove, min_depth, min_length, delete_links,
+                               drop_seqs, gfa_version }) => {
+            clean::clean(clean::CleanSettings {
+                in_gfa, out_gfa, remove, min_depth, min_length, delete_links, drop_seqs,
+                gfa_version });
+        },
+        Some(Commands::Cluster { autocycler_dir, cutoff, min_assemblies, max_cluster_distance,
+                                 max_length_diff, min_cluster_len, max_cluster_len, max_contigs,
+                                 manual, distance, split_chimeras, references, threads,
+                                 gfa_version, samples, jobs }) => {
+            match samples {
+                Some(samples) => {
+                    batch::check_not_batch_option(manual.is_some(), "--manual");
+                    batch::process_samples(&samples, &autocycler_dir, jobs);
+                },
+                None => cluster::cluster(cluster::ClusterSettings {
+                    autocycler_dir, cutoff, min_assemblies, max_cluster_distance, max_length_diff,
+                    min_cluster_len, max_cluster_len, max_contigs, manual, distance,
+                    split_chimeras, references, threads, gfa_version }),
+            }
         },
-        Some(Commands::Combine { autocycler_dir, in_gfas }) => {
-            combine::combine(autocycler_dir, in_gfas);
+        Some(Commands::Combine { autocycler_dir, in_gfas, gfa_version }) => {
+            combine::combine(autocycler_dir, in_gfas, gfa_version);
         },
-        Some(Commands::Compress { assemblies_dir, autocycler_dir, kmer, threads }) => {
-            compress::compress(assemblies_dir, autocycler_dir, kmer, threads);
+        Some(Commands::Compare { assembly_a, assembly_b, kmer, out_gfa }) => {
+            compare::compare(assembly_a, assembly_b, kmer, out_gfa);
         },
-        Some(Commands::Decompress { in_gfa, out_dir, out_file }) => {
-            decompress::decompress(in_gfa, out_dir, out_file);
+        Some(Commands::Compress { assemblies_dir, autocycler_dir, kmer, threads, gfa_version,
+                                  canonical_kmers, minimizer_window, min_kmer_count,
+                                  min_shared_kmers, kmer_histogram, gfa_paths, min_contig_len,
+                                  exclude_contigs, dedup_contigs, rename_duplicates, weights,
+                                  max_unitig_diff, max_bubble_len, max_bubble_div, bubble_poa,
+                                  max_loop_len, max_tip_len, min_tip_depth, resume, samples,
+                                  jobs }) => {
+            match samples {
+                Some(samples) => {
+                    batch::check_not_batch_option(kmer_histogram.is_some(), "--kmer_histogram");
+                    batch::compress_samples(&samples, &autocycler_dir, jobs);
+                },
+                None => compress::compress(compress::CompressSettings {
+                    assemblies_dir: assemblies_dir.unwrap(), autocycler_dir, kmer,
+                    threads, gfa_version, canonical_kmers, minimizer_window, min_kmer_count,
+                    min_shared_kmers, kmer_histogram, gfa_paths, min_contig_len, exclude_contigs, dedup_contigs,
+                    rename_duplicates, weights, max_unitig_diff, max_bubble_len, max_bubble_div,
+                    bubble_poa, max_loop_len, max_tip_len, min_tip_depth, resume }),
+            }
+        },
+        Some(Commands::Decompress { in_gfa, out_dir, out_file, cluster, seqs, wrap, verify,
+                                    bed, report, compress_level, uncompressed }) => {
+            decompress::decompress(decompress::DecompressSettings {
+                in_gfa, out_dir, out_file, cluster, seqs, wrap, verify, bed, report,
+                compress_level, uncompressed });
+        },
+        Some(Commands::Diff { old_gfa, new_gfa }) => {
+            diff::diff(old_gfa, new_gfa);
         },
         Some(Commands::Dotplot { input, out_png, res, kmer }) => {
             dotplot::dotplot(input, out_png, res, kmer);
         },
-        Some(Commands::Resolve { cluster_dir, verbose }) => {
-            resolve::resolve(cluster_dir, verbose);
+        Some(Commands::Export { in_gfa, out_file, format }) => {
+            export::export(in_gfa, out_file, format);
+        },
+        Some(Commands::Helper { task, reads, out_prefix, genome_size, read_type, threads }) => {
+            helper::helper(task, reads, out_prefix, genome_size, read_type, threads);
+        },
+        Some(Commands::Map { in_gfa, reads, out_gaf, out_gfa, gfa_version }) => {
+            map::map(in_gfa, reads, out_gaf, out_gfa, gfa_version);
+        },
+        Some(Commands::Merge { in_gfas, out_gfa, gfa_version }) => {
+            merge::merge(in_gfas, out_gfa, gfa_version);
+        },
+        Some(Commands::Pipeline { out_dir, reads, assemblies_dir, genome_size, assemblers, count,
+                                  read_type, threads, kmer, gfa_version }) => {
+            pipeline::pipeline(pipeline::PipelineSettings {
+                out_dir, reads, assemblies_dir, genome_size, assemblers, count, read_type,
+                threads, kmer, gfa_version });
+        },
+        Some(Commands::Purge { autocycler_dir, assemblies, gfa_version }) => {
+            purge::purge(autocycler_dir, assemblies, gfa_version);
+        },
+        Some(Commands::Resolve { cluster_dir, reads, tie_break, trusted, weighting,
+                                 bubble_support, linear, iterative, min_ratio, dry_run, verbose,
+                                 gfa_version, samples, jobs }) => {
+            match samples {
+                Some(samples) => {
+                    batch::check_not_batch_option(reads.is_some(), "--reads");
+                    batch::process_clusters(&samples, &cluster_dir, jobs);
+                },
+                None => resolve::resolve(resolve::ResolveSettings {
+                    cluster_dir, reads, tie_break, trusted, weighting, bubble_support, linear,
+                    iterative, min_ratio, dry_run, verbose, gfa_version }),
+            }
+        },
+        Some(Commands::Rotate { in_fasta, out_fasta, start_genes }) => {
+            rotate::rotate(in_fasta, out_fasta, start_genes);
+        },
+        Some(Commands::Serve { autocycler_dir, gfa, port }) => {
+            serve::serve(autocycler_dir, gfa, port);
+        },
+        Some(Commands::Simulate { out_dir, genome_size, plasmids, repeats, repeat_length, count,
+                                  snps, indels, misjoins, seed }) => {
+            simulate::simulate(simulate::SimulateSettings {
+                out_dir, genome_size, plasmids, repeats, repeat_length, count, snps, indels,
+                misjoins, seed });
+        },
+        Some(Commands::Stats { input, format }) => {
+            stats::stats(input, format);
+        },
+        Some(Commands::Subsample { reads, out_dir, genome_size, count, min_read_depth, min_length,
+                                   min_mean_q, compress, compress_level, stream, input_bases,
+                                   length_weighted, stratified, dedup_reads, holdout, seed }) => {
+            subsample::subsample(subsample::SubsampleSettings {
+                reads, out_dir, genome_size, count, min_read_depth, min_length, min_mean_q,
+                dedup_reads, holdout, compress, compress_level, stream, input_bases,
+                length_weighted, stratified, seed });
+        },
+        Some(Commands::Table { autocycler_dir, name, fields, sigfigs, header }) => {
+            table::table(autocycler_dir, name, fields, sigfigs, header);
         },
-        Some(Commands::Subsample { reads, out_dir, genome_size, count, min_read_depth, seed }) => {
-            subsample::subsample(reads, out_dir, genome_size, count, min_read_depth, seed);
+        Some(Commands::Trim { cluster_dir, min_identity, max_unitigs, mad, linear, threads,
+                              gfa_version, samples, jobs }) => {
+            match samples {
+                Some(samples) => batch::process_clusters(&samples, &cluster_dir, jobs),
+                None => trim::trim(trim::TrimSettings {
+                    cluster_dir, min_identity, max_unitigs, mad, linear, threads, gfa_version }),
+            }
         },
-        Some(Commands::Table { autocycler_dir, name, fields, sigfigs }) => {
-            table::table(autocycler_dir, name, fields, sigfigs);
+        Some(Commands::Validate { in_gfa }) => {
+            validate::validate(in_gfa);
         },
-        Some(Commands::Trim { cluster_dir, min_identity, max_unitigs, mad, threads }) => {
-            trim::trim(cluster_dir, min_identity, max_unitigs, mad, threads);
+        Some(Commands::View { in_gfa }) => {
+            view::view(in_gfa);
         },
         None => {}
     }
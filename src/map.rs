@@ -0,0 +1,405 @@
+// This file contains the code for the autocycler map subcommand, which aligns long reads to a
+// unitig graph and saves the alignments in GAF format.
+
+// Copyright 2024 Ryan Wick (rrwick@gmail.com)
+// https://github.com/rrwick/Autocycler
+
+// This file is part of Autocycler. Autocycler is free software: you can redistribute it and/or
+// modify it under the terms of the GNU General Public License as published by the Free Software
+// Foundation, either version 3 of the License, or (at your option) any later version. Autocycler
+// is distributed in the hope that it will be useful, but WITHOUT ANY WARRANTY; without even the
+// implied warranty of MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the GNU General
+// Public License for more details. You should have received a copy of the GNU General Public
+// License along with Autocycler. If not, see <http://www.gnu.org/licenses/>.
+
+use seq_io::fastq::Record;
+use std::fs::File;
+use std::io::{BufWriter, Write};
+use std::path::{Path, PathBuf};
+
+use crate::log::{section_header, explanation};
+use crate::misc::{check_gfa_version, check_if_file_exists, fastq_reader, format_float,
+                  quit_with_error};
+use crate::read_threading::KmerIndex;
+use crate::unitig_graph::UnitigGraph;
+
+
+// Reads are seeded with k-mers of this size which occur in only one place in the graph. A run of
+// seeds on one unitig needs at least MAP_MIN_HITS seeds to be used, which filters out spurious
+// hits caused by read errors.
+const MAP_KMER_SIZE: usize = 21;
+const MAP_MIN_HITS: usize = 2;
+
+
+pub fn map(in_gfa: PathBuf, reads: PathBuf, out_gaf: PathBuf, out_gfa: Option<PathBuf>,
+           gfa_version: u32) {
+    check_settings(&in_gfa, &reads, gfa_version);
+    starting_message();
+    print_settings(&in_gfa, &reads, &out_gaf, &out_gfa, gfa_version);
+    let (mut graph, sequences) = UnitigGraph::from_gfa_file(&in_gfa);
+    graph.print_basic_graph_info();
+
+    section_header("Aligning reads");
+    explanation("Reads are seeded with k-mers that occur once in the graph. Runs of seeds on a \
+                 unitig are chained together along the graph's links and then extended, giving \
+                 each read one or more paths through the graph.");
+    let index = KmerIndex::new(&graph, MAP_KMER_SIZE);
+    let mut aligned_bases = vec![0u64; graph.max_unitig_number() as usize + 1];
+    let (read_count, aligned_count, alignment_count) =
+        align_reads(&graph, &index, &reads, &out_gaf, &mut aligned_bases);
+    eprintln!("{} read{}, {} aligned ({} alignment{})", read_count,
+              match read_count { 1 => "", _ => "s" }, aligned_count, alignment_count,
+              match alignment_count { 1 => "", _ => "s" });
+    eprintln!();
+
+    if let Some(out_gfa) = &out_gfa {
+        set_read_depths(&mut graph, &aligned_bases);
+        if let Err(e) = graph.save_gfa_with_version(out_gfa, &sequences, gfa_version) {
+            quit_with_error(&format!("unable to write {}\n{}", out_gfa.display(), e));
+        }
+    }
+    finished_message(&out_gaf, &out_gfa);
+}
+
+
+fn check_settings(in_gfa: &Path, reads: &Path, gfa_version: u32) {
+    check_if_file_exists(in_gfa);
+    check_if_file_exists(reads);
+    check_gfa_version(gfa_version);
+}
+
+
+fn starting_message() {
+    section_header("Starting autocycler map");
+    explanation("This command aligns long reads to a unitig graph and saves the read-to-graph \
+                 alignments in GAF format. It can also save a copy of the graph with unitig \
+                 depths set from the read alignments.");
+}
+
+
+fn print_settings(in_gfa: &Path, reads: &Path, out_gaf: &Path, out_gfa: &Option<PathBuf>,
+                  gfa_version: u32) {
+    eprintln!("Settings:");
+    eprintln!("  --in_gfa {}", in_gfa.display());
+    eprintln!("  --reads {}", reads.display());
+    eprintln!("  --out_gaf {}", out_gaf.display());
+    if let Some(out_gfa) = out_gfa {
+        eprintln!("  --out_gfa {}", out_gfa.display());
+        eprintln!("  --gfa_version {}", gfa_version);
+    }
+    eprintln!();
+}
+
+
+fn finished_message(out_gaf: &Path, out_gfa: &Option<PathBuf>) {
+    section_header("Finished!");
+    eprintln!("Read alignments: {}", out_gaf.display());
+    if let Some(out_gfa) = out_gfa {
+        eprintln!("Graph with read depths: {}", out_gfa.display());
+    }
+    eprintln!();
+}
+
+
+fn align_reads(graph: &UnitigGraph, index: &KmerIndex, reads: &Path,
+               out_gaf: &Path, aligned_bases: &mut [u64]) -> (usize, usize, usize) {
+    let file = File::create(out_gaf).unwrap_or_else(|e| {
+        quit_with_error(&format!("unable to create {}\n{}", out_gaf.display(), e));
+    });
+    let mut gaf = BufWriter::new(file);
+    let (mut read_count, mut aligned_count, mut alignment_count) = (0, 0, 0);
+    let mut reader = fastq_reader(reads);
+    while let Some(record) = reader.next() {
+        let record = record.unwrap_or_else(|e| {
+            quit_with_error(&format!("unable to load {}\n{}", reads.display(), e));
+        });
+        let name = record.id().unwrap_or_default();
+        let seq = record.seq().to_ascii_uppercase();
+        let alignments = align_read(graph, index, &seq);
+        read_count += 1;
+        if !alignments.is_empty() { aligned_count += 1; }
+        alignment_count += alignments.len();
+        for a in alignments {
+            a.add_aligned_bases(graph, aligned_bases);
+            if let Err(e) = writeln!(gaf, "{}", a.gaf_line(name, seq.len())) {
+                quit_with_error(&format!("unable to write {}\n{}", out_gaf.display(), e));
+            }
+        }
+    }
+    (read_count, aligned_count, alignment_count)
+}
+
+
+#[derive(Clone, Debug, PartialEq)]
+struct Run {
+    // A colinear run of seeds on one unitig strand. Ends are exclusive.
+    unitig: i32,
+    q_start: usize,
+    q_end: usize,
+    u_start: usize,
+    u_end: usize,
+    matches: usize,
+    hits: usize,
+}
+
+impl Run {
+    fn new(unitig: i32, q: usize, u: usize) -> Self {
+        Run { unitig, q_start: q, q_end: q + MAP_KMER_SIZE, u_start: u, u_end: u + MAP_KMER_SIZE,
+              matches: MAP_KMER_SIZE, hits: 1 }
+    }
+
+    fn is_colinear(&self, unitig: i32, q: usize, u: usize) -> bool {
+        // Checks whether a seed continues this run: same unitig strand, later position and a
+        // similar distance travelled in the read and in the unitig.
+        if unitig != self.unitig || u + MAP_KMER_SIZE <= self.u_end { return false; }
+        let q_dist = (q + MAP_KMER_SIZE - self.q_end) as i64;
+        let u_dist = (u + MAP_KMER_SIZE - self.u_end) as i64;
+        (q_dist - u_dist).abs() <= 20.max(q_dist / 5)
+    }
+
+    fn extend(&mut self, q: usize, u: usize) {
+        self.matches += MAP_KMER_SIZE.min(q + MAP_KMER_SIZE - self.q_end);
+        self.q_end = q + MAP_KMER_SIZE;
+        self.u_end = u + MAP_KMER_SIZE;
+        self.hits += 1;
+    }
+}
+
+
+#[derive(Debug, PartialEq)]
+struct Alignment {
+    // A read's path through the graph. Path coordinates are on the concatenated path sequence.
+    path: Vec<i32>,
+    q_start: usize,
+    q_end: usize,
+    path_length: usize,
+    path_start: usize,
+    path_end: usize,
+    matches: usize,
+}
+
+impl Alignment {
+    fn gaf_line(&self, read_name: &str, read_length: usize) -> String {
+        // The path is given in the read's orientation, so the strand column is always +. Mapping
+        // quality is not estimated (255).
+        let block_length = (self.q_end - self.q_start).max(self.path_end - self.path_start);
+        format!("{}\t{}\t{}\t{}\t+\t{}\t{}\t{}\t{}\t{}\t{}\t255", read_name, read_length,
+                self.q_start, self.q_end, gaf_path(&self.path), self.path_length,
+                self.path_start, self.path_end, self.matches, block_length)
+    }
+
+    fn add_aligned_bases(&self, graph: &UnitigGraph, aligned_bases: &mut [u64]) {
+        let mut offset = 0;
+        for &n in &self.path {
+            let length = unitig_length(graph, n);
+            let start = self.path_start.max(offset);
+            let end = self.path_end.min(offset + length);
+            if end > start { aligned_bases[n.unsigned_abs() as usize] += (end - start) as u64; }
+            offset += length;
+        }
+    }
+}
+
+
+fn align_read(graph: &UnitigGraph, index: &KmerIndex, seq: &[u8])
+        -> Vec<Alignment> {
+    let runs = find_runs(seq, index);
+    chain_runs(graph, &runs).into_iter().map(|chain| extend_chain(graph, seq, chain)).collect()
+}
+
+
+fn find_runs(seq: &[u8], index: &KmerIndex) -> Vec<Run> {
+    // Groups the read's seeds into colinear runs, in read order, dropping runs with too few seeds.
+    // Runs which were only split by a dropped run are joined back together.
+    let mut runs: Vec<Run> = Vec::new();
+    for (q, kmer) in seq.windows(MAP_KMER_SIZE).enumerate() {
+        let Some((n, u)) = index.get(kmer) else { continue; };
+        match runs.last_mut() {
+            Some(run) if run.is_colinear(n, q, u) => run.extend(q, u),
+            _ => runs.push(Run::new(n, q, u)),
+        }
+    }
+    let mut kept: Vec<Run> = Vec::new();
+    for run in runs.into_iter().filter(|r| r.hits >= MAP_MIN_HITS) {
+        match kept.last_mut() {
+            Some(last) if last.is_colinear(run.unitig, run.q_start, run.u_start) => {
+                last.matches += run.matches.min(run.q_end - last.q_end);
+                last.q_end = run.q_end;
+                last.u_end = run.u_end;
+                last.hits += run.hits;
+            }
+            _ => kept.push(run),
+        }
+    }
+    kept
+}
+
+
+fn chain_runs(graph: &UnitigGraph, runs: &[Run]) -> Vec<Vec<(Vec<i32>, Run)>> {
+    // Chains consecutive runs through the graph. Each chain element is a run along with the
+    // unitigs which lead to it from the previous run (empty for a direct link). A run which can't
+    // be reached from the previous run starts a new chain.
+    let mut chains: Vec<Vec<(Vec<i32>, Run)>> = Vec::new();
+    for run in runs {
+        let between = chains.last().and_then(|c| c.last())
+                            .and_then(|(_, prev)| connecting_path(graph, prev, run));
+        match (between, chains.last_mut()) {
+            (Some(between), Some(chain)) => chain.push((between, run.clone())),
+            _ => chains.push(vec![(Vec::new(), run.clone())]),
+        }
+    }
+    chains
+}
+
+
+fn connecting_path(graph: &UnitigGraph, a: &Run, b: &Run) -> Option<Vec<i32>> {
+    // Finds the unitigs between two runs, using a direct link if there is one or else the
+    // shortest path through the graph. The path's length must roughly match the read sequence
+    // between the runs.
+    let a_length = unitig_length(graph, a.unitig);
+    let b_length = unitig_length(graph, b.unitig);
+    let read_gap = b.q_start as i64 - a.q_end as i64;
+    let between = if graph.outgoing_strands(a.unitig).contains(&b.unitig) {
+        Vec::new()
+    } else {
+        if a.unitig == b.unitig { return None; }
+        let max_len = a_length + b_length + (read_gap.max(0) as usize * 6 / 5) + 100;
+        let path = graph.shortest_path(a.unitig, b.unitig, Some(max_len as u32))?;
+        path[1..path.len() - 1].to_vec()
+    };
+    let path_gap = (a_length - a.u_end) as i64 + b.u_start as i64
+        + between.iter().map(|&n| unitig_length(graph, n) as i64).sum::<i64>();
+    let allowed = 50 + read_gap.abs().max(path_gap.abs()) / 5;
+    if (read_gap - path_gap).abs() <= allowed { Some(between) } else { None }
+}
+
+
+fn extend_chain(graph: &UnitigGraph, seq: &[u8], chain: Vec<(Vec<i32>, Run)>) -> Alignment {
+    // Turns a chain into an alignment. Read sequence between runs counts towards the matches if
+    // it is the same length as the path sequence between them, and the alignment's ends are
+    // extended (without gaps) for as long as the read matches the path.
+    let mut path = Vec::new();
+    let mut run_offsets = Vec::new();
+    for (between, run) in &chain {
+        path.extend(between);
+        run_offsets.push(path.iter().map(|&n| unitig_length(graph, n)).sum::<usize>());
+        path.push(run.unitig);
+    }
+    let path_seq = graph.get_sequence_from_path_signed(&path);
+    let mut matches: usize = chain.iter().map(|(_, run)| run.matches).sum();
+    for (i, w) in chain.windows(2).enumerate() {
+        let (a, b) = (&w[0].1, &w[1].1);
+        let (a_end, b_start) = (run_offsets[i] + a.u_end, run_offsets[i + 1] + b.u_start);
+        if b.q_start >= a.q_end && b.q_start - a.q_end == b_start.saturating_sub(a_end) {
+            matches += seq[a.q_end..b.q_start].iter().zip(&path_seq[a_end..b_start])
+                                              .filter(|(x, y)| x == y).count();
+        }
+    }
+
+    let first = &chain[0].1;
+    let (mut q_start, mut path_start) = (first.q_start, first.u_start);
+    while q_start > 0 && path_start > 0 && seq[q_start - 1] == path_seq[path_start - 1] {
+        q_start -= 1;
+        path_start -= 1;
+        matches += 1;
+    }
+    let last = &chain[chain.len() - 1].1;
+    let (mut q_end, mut path_end) = (last.q_end, run_offsets[chain.len() - 1] + last.u_end);
+    while q_end < seq.len() && path_end < path_seq.len() && seq[q_end] == path_seq[path_end] {
+        q_end += 1;
+        path_end += 1;
+        matches += 1;
+    }
+    Alignment { path, q_start, q_end, path_length: path_seq.len(), path_start, path_end,
+                matches }
+}
+
+
+fn set_read_depths(graph: &mut UnitigGraph, aligned_bases: &[u64]) {
+    for unitig in &mut graph.unitigs {
+        let length = unitig.length().max(1) as f64;
+        unitig.depth = aligned_bases[unitig.number as usize] as f64 / length;
+    }
+    let total_bases: u64 = aligned_bases.iter().sum();
+    let total_length = graph.total_length().max(1);
+    eprintln!("Mean read depth: {}x", format_float(total_bases as f64 / total_length as f64));
+    eprintln!();
+}
+
+
+fn unitig_length(graph: &UnitigGraph, signed_num: i32) -> usize {
+    graph.unitig(signed_num.unsigned_abs()).length() as usize
+}
+
+
+fn gaf_path(path: &[i32]) -> String {
+    path.iter().map(|&n| format!("{}{}", if n > 0 { '>' } else { '<' }, n.unsigned_abs()))
+        .collect()
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::misc::reverse_complement;
+
+    fn test_graph() -> UnitigGraph {
+        let gfa: Vec<String> = vec!["H\tVN:Z:1.0\tKM:i:3",
+                                    "S\t1\tGCTAAAGACAATTACATAACATACACGTCAGCACGAAACT\tDP:f:1.00",
+                                    "S\t2\tTGTTGGCCCA\tDP:f:1.00",
+                                    "S\t3\tGTGTGAATCGCTTAAGGGTTAAGTAAGTGTGATGCATACG\tDP:f:1.00",
+                                    "L\t1\t+\t2\t+\t0M",
+                                    "L\t2\t+\t3\t+\t0M",
+                                    "L\t2\t-\t1\t-\t0M",
+                                    "L\t3\t-\t2\t-\t0M"]
+            .into_iter().map(String::from).collect();
+        UnitigGraph::from_gfa_lines(&gfa).0
+    }
+
+    #[test]
+    fn test_gaf_path() {
+        assert_eq!(gaf_path(&[12, -5, 7]), ">12<5>7");
+        assert_eq!(gaf_path(&[-3]), "<3");
+    }
+
+    #[test]
+    fn test_align_read() {
+        // The read covers the end of unitig 1, all of unitig 2 (too short for seeds) and the start
+        // of unitig 3.
+        let graph = test_graph();
+        let index = KmerIndex::new(&graph, MAP_KMER_SIZE);
+        let read = b"TTACATAACATACACGTCAGCACGAAACTTGTTGGCCCAGTGTGAATCGCTTAAGGGTTAAGTAAG";
+        let alignments = align_read(&graph, &index, read);
+        assert_eq!(alignments, vec![Alignment { path: vec![1, 2, 3], q_start: 0, q_end: 66,
+                                                path_length: 90, path_start: 11, path_end: 77,
+                                                matches: 66 }]);
+        assert_eq!(alignments[0].gaf_line("read", 66),
+                   "read\t66\t0\t66\t+\t>1>2>3\t90\t11\t77\t66\t66\t255");
+
+        let mut aligned_bases = vec![0; 4];
+        alignments[0].add_aligned_bases(&graph, &mut aligned_bases);
+        assert_eq!(aligned_bases, vec![0, 29, 10, 27]);
+
+        let alignments = align_read(&graph, &index, &reverse_complement(read));
+        assert_eq!(alignments.len(), 1);
+        assert_eq!(alignments[0].path, vec![-3, -2, -1]);
+        assert_eq!((alignments[0].path_start, alignments[0].path_end), (13, 79));
+
+        assert!(align_read(&graph, &index, b"ACGTACGTACGTACGTACGTACGTACGT").is_empty());
+    }
+
+    #[test]
+    fn test_align_read_unlinked() {
+        // The read jumps from unitig 3 back to unitig 1, which isn't a link in the graph, so it
+        // gives two separate alignments.
+        let graph = test_graph();
+        let index = KmerIndex::new(&graph, MAP_KMER_SIZE);
+        let read = b"GTGTGAATCGCTTAAGGGTTAAGTAAGTGTGATGCATACGGCTAAAGACAATTACATAACATACACG";
+        let alignments = align_read(&graph, &index, read);
+        assert_eq!(alignments.len(), 2);
+        assert_eq!(alignments[0].path, vec![3]);
+        assert_eq!((alignments[0].q_start, alignments[0].q_end), (0, 40));
+        assert_eq!(alignments[1].path, vec![1]);
+        assert_eq!((alignments[1].q_start, alignments[1].q_end), (40, 67));
+    }
+}
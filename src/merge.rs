@@ -0,0 +1,199 @@
+// This file contains the code for the autocycler merge subcommand.
+
+// Copyright 2024 Ryan Wick (rrwick@gmail.com)
+// https://github.com/rrwick/Autocycler
+
+// This file is part of Autocycler. Autocycler is free software: you can redistribute it and/or
+// modify it under the terms of the GNU General Public License as published by the Free Software
+// Foundation, either version 3 of the License, or (at your option) any later version. Autocycler
+// is distributed in the hope that it will be useful, but WITHOUT ANY WARRANTY; without even the
+// implied warranty of MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the GNU General
+// Public License for more details. You should have received a copy of the GNU General Public
+// License along with Autocycler. If not, see <http://www.gnu.org/licenses/>.
+
+use std::collections::HashSet;
+use std::path::{Path, PathBuf};
+
+use crate::compress::sequence_end_repair;
+use crate::graph_simplification::simplify_structure;
+use crate::kmer_graph::KmerGraph;
+use crate::log::{section_header, explanation};
+use crate::metrics::SimplificationMetrics;
+use crate::misc::{check_gfa_version, check_if_file_exists, quit_with_error};
+use crate::sequence::Sequence;
+use crate::unitig_graph::UnitigGraph;
+
+
+pub fn merge(in_gfas: Vec<PathBuf>, out_gfa: PathBuf, gfa_version: u32) {
+    check_settings(&in_gfas, gfa_version);
+    starting_message();
+    print_settings(&in_gfas, &out_gfa, gfa_version);
+    let graphs: Vec<(UnitigGraph, Vec<Sequence>)> = in_gfas.iter().map(|gfa| {
+        let (graph, sequences) = UnitigGraph::from_gfa_file(gfa);
+        eprintln!("{}: {} sequence{}, k={}", gfa.display(), sequences.len(),
+                  match sequences.len() { 1 => "", _ => "s" }, graph.k_size);
+        (graph, sequences)
+    }).collect();
+    eprintln!();
+
+    section_header("Merging graphs");
+    explanation("Each graph's input sequences are now rebuilt from their paths and compressed \
+                 into a single unitig graph, so sequence shared between the graphs ends up in \
+                 common unitigs. Sequences are renumbered in the order of the input graphs.");
+    let (graph, sequences) = merge_graphs(&graphs);
+    graph.print_basic_graph_info();
+    if let Err(e) = graph.save_gfa_with_version(&out_gfa, &sequences, gfa_version) {
+        quit_with_error(&format!("unable to write {}\n{}", out_gfa.display(), e));
+    }
+    finished_message(&out_gfa);
+}
+
+
+fn check_settings(in_gfas: &[PathBuf], gfa_version: u32) {
+    if in_gfas.len() < 2 { quit_with_error("at least two input graphs are required"); }
+    for gfa in in_gfas {
+        check_if_file_exists(gfa);
+    }
+    check_gfa_version(gfa_version);
+}
+
+
+fn starting_message() {
+    section_header("Starting autocycler merge");
+    explanation("This command merges compressed unitig graphs (input_assemblies.gfa files made \
+                 by autocycler compress with the same k-mer size) into one graph, e.g. to add \
+                 new assemblies to an existing run.");
+}
+
+
+fn print_settings(in_gfas: &[PathBuf], out_gfa: &Path, gfa_version: u32) {
+    eprintln!("Settings:");
+    eprintln!("  --in_gfas {}", in_gfas.iter().map(|p| p.display().to_string())
+                                         .collect::<Vec<_>>().join(" "));
+    eprintln!("  --out_gfa {}", out_gfa.display());
+    eprintln!("  --gfa_version {}", gfa_version);
+    eprintln!();
+}
+
+
+fn finished_message(out_gfa: &Path) {
+    section_header("Finished!");
+    explanation("You can now run autocycler cluster on the merged graph (saved as \
+                 input_assemblies.gfa in an Autocycler directory).");
+    eprintln!("Merged unitig graph: {}", out_gfa.display());
+    eprintln!();
+}
+
+
+fn merge_graphs(graphs: &[(UnitigGraph, Vec<Sequence>)]) -> (UnitigGraph, Vec<Sequence>) {
+    let k_size = graphs[0].0.k_size;
+    if graphs.iter().any(|(g, _)| g.k_size != k_size) {
+        quit_with_error("all input graphs must have the same k-mer size");
+    }
+    let mut filenames = HashSet::new();
+    for (_, sequences) in graphs {
+        let graph_filenames: HashSet<&str> = sequences.iter().map(|s| s.filename.as_str())
+                                                      .collect();
+        for filename in graph_filenames {
+            if !filenames.insert(filename) {
+                quit_with_error(&format!("assembly {} is in more than one input graph",
+                                         filename));
+            }
+        }
+    }
+    let mut sequences = rebuild_sequences(graphs, k_size);
+    if sequences.len() > 32767 {
+        quit_with_error("no more than 32767 input sequences are allowed");
+    }
+    sequence_end_repair(&mut sequences, k_size);
+    let mut kmer_graph = KmerGraph::new(k_size, rayon::current_num_threads());
+    kmer_graph.add_sequences(&sequences, filenames.len());
+    let mut graph = UnitigGraph::from_kmer_graph(&kmer_graph);
+    simplify_structure(&mut graph, &mut sequences, 0, 0.0, &mut SimplificationMetrics::default());
+    graph.set_sequence_weights(&sequences);
+    graph.estimate_multiplicities(&sequences);
+    (graph, sequences)
+}
+
+
+fn rebuild_sequences(graphs: &[(UnitigGraph, Vec<Sequence>)], k_size: u32) -> Vec<Sequence> {
+    // Makes new sequences (with sequential IDs) from each graph's sequence paths. Weights, line
+    // widths, original names and duplicates carry over, and checksums are only kept for
+    // sequences which had one (i.e. still trace their original contig).
+    let mut rebuilt = Vec::new();
+    for (graph, sequences) in graphs {
+        for (seq, path) in sequences.iter().zip(graph.get_unitig_paths_for_sequences(sequences)) {
+            let mut new_seq = Sequence::new_with_seq(rebuilt.len() + 1,
+                                                     graph.get_repaired_sequence_from_path(seq,
+                                                                                           &path),
+                                                     seq.filename.clone(),
+                                                     seq.contig_header.clone(), seq.length,
+                                                     k_size / 2);
+            new_seq.weight = seq.weight;
+            new_seq.line_width = seq.line_width;
+            new_seq.original_name = seq.original_name.clone();
+            new_seq.duplicates = seq.duplicates.clone();
+            if seq.checksum.is_none() { new_seq.checksum = None; }
+            rebuilt.push(new_seq);
+        }
+    }
+    rebuilt
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::compare::contigs_to_sequences;
+    use crate::tests::random_seq;
+
+    fn compressed_graph(contigs: Vec<(&str, String)>, k_size: u32)
+            -> (UnitigGraph, Vec<Sequence>) {
+        let mut sequences = Vec::new();
+        for (filename, seq) in contigs {
+            let contig = vec![("c".to_string(), "c".to_string(), seq)];
+            sequences.extend(contigs_to_sequences(contig, filename, k_size, sequences.len()));
+        }
+        let mut kmer_graph = KmerGraph::new(k_size, 1);
+        kmer_graph.add_sequences(&sequences, 2);
+        let mut graph = UnitigGraph::from_kmer_graph(&kmer_graph);
+        simplify_structure(&mut graph, &mut sequences, 0, 0.0,
+                           &mut SimplificationMetrics::default());
+        (graph, sequences)
+    }
+
+    #[test]
+    fn test_merge_graphs() {
+        // Three assemblies of the same sequence, each with a different SNP, split across two
+        // graphs. After merging, all sequences are unchanged and the shared sequence is only in
+        // the graph once.
+        let seq = random_seq(2000, 4);
+        let with_snp = |i: usize| {
+            let mut s = seq.clone().into_bytes();
+            s[i] = if s[i] == b'A' { b'C' } else { b'A' };
+            String::from_utf8(s).unwrap()
+        };
+        let graph_1 = compressed_graph(vec![("a.fasta", with_snp(500)),
+                                            ("b.fasta", with_snp(1000))], 21);
+        let graph_2 = compressed_graph(vec![("c.fasta", with_snp(1500))], 21);
+        let mut before = graph_1.0.reconstruct_original_sequences_u8(&graph_1.1);
+        before.extend(graph_2.0.reconstruct_original_sequences_u8(&graph_2.1));
+        before.sort();
+
+        let (graph, sequences) = merge_graphs(&[graph_1, graph_2]);
+        assert_eq!(sequences.iter().map(|s| s.id).collect::<Vec<_>>(), vec![1, 2, 3]);
+        assert_eq!(graph.reconstruct_original_sequences_u8(&sequences), before);
+        assert!(graph.total_length() < 2100);
+        assert!(sequences.iter().all(|s| s.checksum.is_some()));
+    }
+
+    #[test]
+    fn test_merge_graphs_same_assembly() {
+        assert!(std::panic::catch_unwind(|| {
+            let seq = random_seq(500, 5);
+            let graph_1 = compressed_graph(vec![("a.fasta", seq.clone())], 21);
+            let graph_2 = compressed_graph(vec![("a.fasta", seq)], 21);
+            merge_graphs(&[graph_1, graph_2]);
+        }).is_err());
+    }
+}
@@ -12,12 +12,13 @@
 // License along with Autocycler. If not, see <http://www.gnu.org/licenses/>.
 
 use serde::{Deserialize, Serialize};
-use std::collections::{HashMap, HashSet};
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::fs;
 use std::fs::File;
 use std::io::Write;
 use std::path::Path;
 
-use crate::misc::{median_usize, mad_usize};
+use crate::misc::{after_first_space, median_usize, mad_usize, quit_with_error, up_to_first_space};
 use crate::sequence::Sequence;
 
 
@@ -29,6 +30,20 @@ pub struct SubsampleMetrics {
 
     // TODO: add input_read_min_length andinput_read_max_length
 
+    // Reads removed by --min_length, --min_mean_q and --dedup_reads, and the remaining reads used
+    // for subsetting.
+    pub filtered_short_read_count: usize,
+    pub filtered_low_qual_read_count: usize,
+    pub filtered_duplicate_read_count: usize,
+    pub pass_read_count: usize,
+    pub pass_read_bases: u64,
+    pub pass_read_n50: u64,
+
+    // Passing reads held out from all subsets by --holdout (saved to qc_reads.fastq).
+    pub holdout_read_count: usize,
+    pub holdout_read_bases: u64,
+    pub holdout_read_n50: u64,
+
     pub output_reads: Vec<ReadSetDetails>,
 }
 
@@ -59,6 +74,24 @@ impl ReadSetDetails {
             n50,
         }
     }
+
+    pub fn from_length_counts(length_counts: &BTreeMap<u64, usize>) -> Self {
+        // Like new, but takes a count of each read length, which uses less memory than storing
+        // every read length when there are many reads.
+        let count = length_counts.values().sum();
+        let bases: u64 = length_counts.iter().map(|(&l, &c)| l * c as u64).sum();
+        let n50_target_bases = bases / 2;
+        let mut running_total = 0;
+        let mut n50 = 0;
+        for (&read_length, &read_count) in length_counts {
+            running_total += read_length * read_count as u64;
+            if running_total >= n50_target_bases {
+                n50 = read_length;
+                break;
+            }
+        }
+        ReadSetDetails { count, bases, n50 }
+    }
 }
 
 
@@ -67,16 +100,42 @@ pub struct InputAssemblyMetrics {
     pub input_assemblies_count: u32,
     pub input_assemblies_total_contigs: u32,
     pub input_assemblies_total_length: u64,
+    pub input_assemblies_skipped_contigs: u32,
+    pub input_assemblies_duplicate_contigs: u32,
     pub compressed_unitig_count: u32,
     pub compressed_unitig_total_length: u64,
+    pub kmer_count_histogram: BTreeMap<usize, usize>,
     pub input_assembly_details: Vec<InputAssemblyDetails>,
+    pub simplification: SimplificationMetrics,
+}
+
+
+#[derive(Serialize, Deserialize, Debug, Default)]
+pub struct SimplificationMetrics {
+    // Names of the simplification passes which were run, in order.
+    pub passes: Vec<String>,
+
+    pub near_identical_unitigs_collapsed: usize,
+    pub bubble_arms_collapsed: usize,
+    pub bubble_arms_polished: usize,
+    pub loops_unrolled: usize,
+    pub tips_clipped: usize,
+    pub repeat_expansion_rounds: usize,
+    pub repeat_expansion_shifted_bases: usize,
+
+    // Unitigs deleted by simplification (not counting clipped tips), and the reduction in unitig
+    // count from merging linear paths.
+    pub unitigs_removed: usize,
+    pub unitigs_merged: usize,
 }
 
 
 #[derive(Serialize, Deserialize, Debug, Default)]
 pub struct InputAssemblyDetails {
     pub filename: String,
-    pub contigs: Vec<InputContigDetails>
+    pub contigs: Vec<InputContigDetails>,
+    pub skipped_contigs: Vec<InputContigDetails>,
+    pub renamed_contigs: BTreeMap<String, String>,
 }
 
 impl InputAssemblyDetails {
@@ -84,6 +143,8 @@ impl InputAssemblyDetails {
         InputAssemblyDetails {
             filename: filename.to_string_lossy().to_string(),
             contigs: Vec::new(),
+            skipped_contigs: Vec::new(),
+            renamed_contigs: BTreeMap::new(),
         }
     }
 }
@@ -94,6 +155,7 @@ pub struct InputContigDetails {
     pub name: String,
     pub description: String,
     pub length: u64,
+    pub multiplicity: u32,
 }
 
 impl InputContigDetails {
@@ -102,6 +164,17 @@ impl InputContigDetails {
             name: seq.contig_name(),
             description: seq.contig_description(),
             length: seq.length as u64,
+            multiplicity: 1,
+        }
+    }
+
+    pub fn from_header(header: &str, length: usize) -> Self {
+        // For contigs which were skipped and so never became a Sequence.
+        InputContigDetails {
+            name: up_to_first_space(header),
+            description: after_first_space(header),
+            length: length as u64,
+            multiplicity: 1,
         }
     }
 }
@@ -109,15 +182,36 @@ impl InputContigDetails {
 
 #[derive(Serialize, Deserialize, Debug, Default)]
 pub struct ClusteringMetrics {
+    pub distance_metric: String,
     pub pass_cluster_count: u32,
     pub fail_cluster_count: u32,
     pub pass_contig_count: u32,
     pub fail_contig_count: u32,
+    pub discard_contig_count: u32,
+    pub chimeric_contig_count: u32,
     pub pass_contig_fraction: f64,
     pub fail_contig_fraction: f64,
     pub cluster_balance_score: f64,
     pub cluster_tightness_score: f64,
     pub overall_clustering_score: f64,
+    pub clusters: Vec<ClusterQcDetails>,
+}
+
+
+#[derive(Serialize, Deserialize, Debug, Default)]
+pub struct ClusterQcDetails {
+    // QC outcome for one cluster. Each failed rule is named by the option which sets it (e.g.
+    // min_assemblies), and failure_reasons gives the matching human-readable explanations.
+    pub cluster: u16,
+    pub pass: bool,
+    pub contig_count: u32,
+    pub assembly_count: u32,
+    pub median_length: u64,
+    pub cluster_distance: f64,
+    pub length_diff: f64,
+    pub failed_rules: Vec<String>,
+    pub failure_reasons: Vec<String>,
+    pub label: Option<String>,  // from the best-matching reference replicon, if any
 }
 
 impl ClusteringMetrics {
@@ -231,6 +325,7 @@ pub struct CombineMetrics {
     pub consensus_assembly_unitigs: u32,
     pub consensus_assembly_fully_resolved: bool,
     pub consensus_assembly_clusters: Vec<ResolvedClusterDetails>,
+    pub consensus_assembly_sequences: Vec<ResolvedSequenceDetails>,
 }
 
 
@@ -239,6 +334,85 @@ pub struct ResolvedClusterDetails {
     pub length: u64,
     pub unitigs: u32,
     pub topology: String,
+    pub classification: String,
+}
+
+
+#[derive(Serialize, Deserialize, Debug, Default)]
+pub struct ResolvedSequenceDetails {
+    pub name: String,
+    pub length: u64,
+    pub circular: bool,
+}
+
+
+#[derive(Serialize, Deserialize, Debug, Default)]
+pub struct BenchmarkMetrics {
+    pub benchmark_reference_bases: u64,
+    pub benchmark_consensus_bases: u64,
+    pub benchmark_identity: f64,
+    pub benchmark_completeness: f64,
+    pub benchmark_snps: u32,
+    pub benchmark_indels: u32,
+    pub benchmark_structural: u32,
+    pub benchmark_extra_contigs: u32,
+    pub benchmark_replicons: Vec<BenchmarkRepliconDetails>,
+}
+
+
+#[derive(Serialize, Deserialize, Debug, Default)]
+pub struct BenchmarkRepliconDetails {
+    pub reference: String,
+    pub reference_length: u64,
+    pub consensus: String,  // empty if no consensus contig matched the reference replicon
+    pub consensus_length: u64,
+    pub aligned_bases: u64,
+    pub completeness: f64,
+    pub identity: f64,
+    pub snps: u32,
+    pub indels: u32,
+    pub structural: u32,
+}
+
+
+#[derive(Serialize, Deserialize, Debug, Default)]
+pub struct AmbiguityReport {
+    pub ambiguity_count: u32,
+    pub ambiguities: Vec<AmbiguityDetails>,
+    pub inverted_repeat_count: u32,
+    pub inverted_repeats: Vec<InvertedRepeatDetails>,
+}
+
+
+#[derive(Serialize, Deserialize, Debug, Default)]
+pub struct AmbiguityDetails {
+    // One place where the input assemblies disagreed during resolve: either a bridge with multiple
+    // paths (bridge_paths) or a set of bridges competing for the same anchors (conflicting_bridges).
+    pub id: u32,
+    pub kind: String,
+    pub decided_by: String,
+    pub alternatives: Vec<AlternativeDetails>,
+}
+
+
+#[derive(Serialize, Deserialize, Debug, Default)]
+pub struct InvertedRepeatDetails {
+    // An inverted repeat in the final graph: either within a unitig (internal) or a link from one
+    // of a unitig's ends back onto its own other strand (hairpin_start or hairpin_end).
+    pub unitig: u32,
+    pub kind: String,
+    pub position: String,
+    pub length: u64,
+}
+
+
+#[derive(Serialize, Deserialize, Debug, Default)]
+pub struct AlternativeDetails {
+    pub name: String,  // matches the record in the ambiguity FASTA file
+    pub path: String,
+    pub assemblies: Vec<String>,  // empty for a path through the graph which no assembly took
+    pub length: u64,   // bases between the anchors
+    pub chosen: bool,
 }
 
 
@@ -272,6 +446,30 @@ impl_metrics_helpers!(ClusteringMetrics);
 impl_metrics_helpers!(UntrimmedClusterMetrics);
 impl_metrics_helpers!(TrimmedClusterMetrics);
 impl_metrics_helpers!(CombineMetrics);
+impl_metrics_helpers!(BenchmarkMetrics);
+
+
+impl AmbiguityReport {
+    // Not one of the metrics structs used by Autocycler table, so it only needs saving.
+    pub fn save_to_yaml(&self, filename: &Path) {
+        let yaml_string = serde_yaml::to_string(&self).unwrap();
+        let mut file = File::create(filename).unwrap();
+        file.write_all(yaml_string.as_bytes()).unwrap();
+    }
+}
+
+
+impl InputAssemblyMetrics {
+    pub fn load_from_yaml(filename: &Path) -> Self {
+        // Used to restore metrics from a compress checkpoint.
+        let yaml_string = fs::read_to_string(filename).unwrap_or_else(|e| {
+            quit_with_error(&format!("unable to read {}\n{}", filename.display(), e));
+        });
+        serde_yaml::from_str(&yaml_string).unwrap_or_else(|e| {
+            quit_with_error(&format!("unable to parse {}\n{}", filename.display(), e));
+        })
+    }
+}
 
 
 #[cfg(test)]
@@ -329,18 +527,40 @@ mod tests {
         assert!(metrics_6.cluster_balance_score < metrics_5.cluster_balance_score);
     }
 
+    #[test]
+    fn test_read_set_details_from_length_counts() {
+        let lengths = vec![100, 100, 200, 500, 500, 500, 1000];
+        let mut length_counts = BTreeMap::new();
+        for l in &lengths {
+            *length_counts.entry(*l).or_insert(0) += 1;
+        }
+        let a = ReadSetDetails::new(&lengths);
+        let b = ReadSetDetails::from_length_counts(&length_counts);
+        assert_eq!((a.count, a.bases, a.n50), (b.count, b.bases, b.n50));
+        assert_eq!((b.count, b.bases, b.n50), (7, 2900, 500));
+        let empty = ReadSetDetails::from_length_counts(&BTreeMap::new());
+        assert_eq!((empty.count, empty.bases, empty.n50), (0, 0, 0));
+    }
+
     #[test]
     fn test_get_field_names() {
         assert_eq!(SubsampleMetrics::get_field_names(),
-                   vec!["input_read_bases", "input_read_count", "input_read_n50", "output_reads"]);
+                   vec!["filtered_duplicate_read_count", "filtered_low_qual_read_count",
+                        "filtered_short_read_count", "holdout_read_bases", "holdout_read_count",
+                        "holdout_read_n50", "input_read_bases", "input_read_count", "input_read_n50",
+                        "output_reads", "pass_read_bases", "pass_read_count", "pass_read_n50"]);
 
         assert_eq!(InputAssemblyMetrics::get_field_names(),
                    vec!["compressed_unitig_count", "compressed_unitig_total_length",
-                        "input_assemblies_count", "input_assemblies_total_contigs",
-                        "input_assemblies_total_length", "input_assembly_details"]);
+                        "input_assemblies_count", "input_assemblies_duplicate_contigs",
+                        "input_assemblies_skipped_contigs",
+                        "input_assemblies_total_contigs", "input_assemblies_total_length",
+                        "input_assembly_details",
+                        "kmer_count_histogram", "simplification"]);
 
         assert_eq!(ClusteringMetrics::get_field_names(),
-                   vec!["cluster_balance_score", "cluster_tightness_score", "fail_cluster_count",
+                   vec!["chimeric_contig_count", "cluster_balance_score",
+                        "cluster_tightness_score", "clusters", "discard_contig_count", "distance_metric", "fail_cluster_count",
                         "fail_contig_count", "fail_contig_fraction", "overall_clustering_score",
                         "pass_cluster_count", "pass_contig_count", "pass_contig_fraction"]);
 
@@ -355,6 +575,13 @@ mod tests {
 
         assert_eq!(CombineMetrics::get_field_names(),
                    vec!["consensus_assembly_bases", "consensus_assembly_clusters",
-                        "consensus_assembly_fully_resolved", "consensus_assembly_unitigs"]);
+                        "consensus_assembly_fully_resolved", "consensus_assembly_sequences",
+                        "consensus_assembly_unitigs"]);
+
+        assert_eq!(BenchmarkMetrics::get_field_names(),
+                   vec!["benchmark_completeness", "benchmark_consensus_bases",
+                        "benchmark_extra_contigs", "benchmark_identity", "benchmark_indels",
+                        "benchmark_reference_bases", "benchmark_replicons", "benchmark_snps",
+                        "benchmark_structural"]);
     }
 }
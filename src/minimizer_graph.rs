@@ -0,0 +1,269 @@
+// This file defines structs for building a sparse minimizer graph from the input assemblies.
+
+// Copyright 2024 Ryan Wick (rrwick@gmail.com)
+// https://github.com/rrwick/Autocycler
+
+// This file is part of Autocycler. Autocycler is free software: you can redistribute it and/or
+// modify it under the terms of the GNU General Public License as published by the Free Software
+// Foundation, either version 3 of the License, or (at your option) any later version. Autocycler
+// is distributed in the hope that it will be useful, but WITHOUT ANY WARRANTY; without even the
+// implied warranty of MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the GNU General
+// Public License for more details. You should have received a copy of the GNU General Public
+// License along with Autocycler. If not, see <http://www.gnu.org/licenses/>.
+
+use fxhash::{FxHashMap, FxHashSet};
+use std::collections::VecDeque;
+
+use crate::sequence::Sequence;
+
+
+pub struct MinimizerGraph<'a> {
+    pub k_size: u32,
+    pub window: usize,
+
+    // Instead of storing every k-mer, this graph stores segments: stretches of sequence running
+    // from one (w,k-1)-minimizer to the next (both inclusive). Consecutive segments in a sequence
+    // therefore overlap by k-1 bases, just like k-mers in a De Bruijn graph, so the same linking
+    // and overlap trimming can be used when building a unitig graph. Both strands of each segment
+    // are stored as slices of the sequences' forward and reverse buffers.
+    pub segments: Vec<&'a [u8]>,
+    pub reverse: Vec<usize>,
+    pub starts_sequence: Vec<bool>,
+    index: FxHashMap<&'a [u8], usize>,
+    by_start: FxHashMap<&'a [u8], Vec<usize>>,
+    by_end: FxHashMap<&'a [u8], Vec<usize>>,
+
+    // The forward-strand path of segments through each sequence: (seq ID, length, segments).
+    pub paths: Vec<(u16, u32, Vec<usize>)>,
+}
+
+impl<'a> MinimizerGraph<'a> {
+    pub fn new(k_size: u32, window: usize) -> MinimizerGraph<'a> {
+        MinimizerGraph {
+            k_size,
+            window,
+            segments: Vec::new(),
+            reverse: Vec::new(),
+            starts_sequence: Vec::new(),
+            index: FxHashMap::default(),
+            by_start: FxHashMap::default(),
+            by_end: FxHashMap::default(),
+            paths: Vec::new(),
+        }
+    }
+
+    pub fn add_sequences(&mut self, seqs: &'a [Sequence]) {
+        // Each sequence needs an anchor at its first and last m-mer, but circular contigs start
+        // at arbitrary positions, so these would split segments differently in different inputs
+        // and put the same sequence in more than one segment. So anchors are chosen globally: any
+        // m-mer which is a minimizer in one sequence, or starts or ends one (ignoring the dot
+        // padding), is an anchor everywhere it occurs.
+        let m = self.k_size as usize - 1;
+        let mut anchor_hashes = FxHashSet::default();
+        for seq in seqs {
+            let hashes = mmer_hashes(seq, m);
+            anchor_hashes.extend(find_minimizers(&hashes, self.window).iter().map(|&p| hashes[p]));
+            anchor_hashes.extend(end_mmers(seq, m).iter().map(|&p| hashes[p]));
+        }
+        for seq in seqs {
+            let hashes = mmer_hashes(seq, m);
+            let anchors: Vec<usize> = (0..hashes.len())
+                .filter(|&p| anchor_hashes.contains(&hashes[p])).collect();
+            self.add_sequence(seq, &anchors);
+        }
+    }
+
+    fn add_sequence(&mut self, seq: &'a Sequence, anchors: &[usize]) {
+        // Splits a sequence into segments at its anchors and adds both strands of each segment
+        // to the graph. The segment path through the sequence is saved so the sequence can later
+        // be threaded through the unitig graph.
+        let m = self.k_size as usize - 1;
+        let seq_len = seq.forward_seq.len();
+        let mut path = Vec::with_capacity(anchors.len() - 1);
+        for (i, pair) in anchors.windows(2).enumerate() {
+            let (start, end) = (pair[0], pair[1] + m);
+            let forward = &seq.forward_seq[start..end];
+            let reverse = &seq.reverse_seq[seq_len - end..seq_len - start];
+            let (forward_i, reverse_i) = self.add_segment(forward, reverse);
+            if i == 0 {
+                self.starts_sequence[forward_i] = true;
+            }
+            if i == anchors.len() - 2 {
+                self.starts_sequence[reverse_i] = true;
+            }
+            path.push(forward_i);
+        }
+        self.paths.push((seq.id, seq.length as u32, path));
+    }
+
+    fn add_segment(&mut self, forward: &'a [u8], reverse: &'a [u8]) -> (usize, usize) {
+        // Returns the indices of the forward and reverse segments, adding them if necessary.
+        if let Some(&forward_i) = self.index.get(forward) {
+            return (forward_i, self.reverse[forward_i]);
+        }
+        let forward_i = self.insert_segment(forward);
+        let reverse_i = if forward == reverse { forward_i } else { self.insert_segment(reverse) };
+        self.reverse[forward_i] = reverse_i;
+        self.reverse[reverse_i] = forward_i;
+        (forward_i, reverse_i)
+    }
+
+    fn insert_segment(&mut self, segment: &'a [u8]) -> usize {
+        let m = self.k_size as usize - 1;
+        let i = self.segments.len();
+        self.segments.push(segment);
+        self.reverse.push(i);
+        self.starts_sequence.push(false);
+        self.index.insert(segment, i);
+        self.by_start.entry(&segment[..m]).or_default().push(i);
+        self.by_end.entry(&segment[segment.len() - m..]).or_default().push(i);
+        i
+    }
+
+    pub fn next_segments(&self, i: usize) -> &[usize] {
+        let m = self.k_size as usize - 1;
+        let segment = self.segments[i];
+        self.by_start.get(&segment[segment.len() - m..]).map_or(&[], |v| v.as_slice())
+    }
+
+    pub fn prev_segments(&self, i: usize) -> &[usize] {
+        let m = self.k_size as usize - 1;
+        self.by_end.get(&self.segments[i][..m]).map_or(&[], |v| v.as_slice())
+    }
+
+    pub fn segment_count(&self) -> usize {
+        self.segments.len()
+    }
+}
+
+
+fn mmer_hashes(seq: &Sequence, m: usize) -> Vec<u64> {
+    // Returns the hash of each m-mer's canonical sequence in the padded forward sequence, so both
+    // strands of a sequence give the same hashes.
+    let seq_len = seq.forward_seq.len();
+    (0..seq_len - m + 1).map(|p| {
+        let forward = &seq.forward_seq[p..p + m];
+        let reverse = &seq.reverse_seq[seq_len - m - p..seq_len - p];
+        fxhash::hash64(forward.min(reverse))
+    }).collect()
+}
+
+
+fn end_mmers(seq: &Sequence, m: usize) -> Vec<usize> {
+    // Returns the positions of the first and last m-mers, along with the first and last m-mers
+    // without any dot padding (the same unless end repair replaced all of the dots).
+    let seq_len = seq.forward_seq.len();
+    let last = seq_len - m;
+    let mut ends = vec![0, last];
+    let leading = seq.forward_seq.iter().take_while(|&&c| c == b'.').count();
+    let trailing = seq.forward_seq.iter().rev().take_while(|&&c| c == b'.').count();
+    if leading + trailing + m <= seq_len {
+        ends.extend([leading, last - trailing]);
+    }
+    ends
+}
+
+
+fn find_minimizers(hashes: &[u64], window: usize) -> Vec<usize> {
+    // Returns the sorted positions of (w,m)-minimizers, given the m-mer hashes. All tied m-mers in
+    // a window are included, so both strands of a sequence choose mirrored minimizers.
+    let mmer_count = hashes.len();
+    let mut is_minimizer = vec![false; mmer_count];
+    let window = window.min(mmer_count);
+    let mut candidates: VecDeque<usize> = VecDeque::new();
+    for p in 0..mmer_count {
+        while candidates.back().is_some_and(|&c| hashes[c] > hashes[p]) {
+            candidates.pop_back();
+        }
+        candidates.push_back(p);
+        while candidates[0] + window <= p {
+            candidates.pop_front();
+        }
+        if p + 1 >= window {
+            let min_hash = hashes[candidates[0]];
+            for &c in candidates.iter().take_while(|&&c| hashes[c] == min_hash) {
+                is_minimizer[c] = true;
+            }
+        }
+    }
+    (0..mmer_count).filter(|&p| is_minimizer[p]).collect()
+}
+
+
+#[cfg(test)]
+mod tests {
+    use crate::misc::reverse_complement;
+    use super::*;
+
+    #[test]
+    fn test_find_minimizers() {
+        let seq = Sequence::new_with_seq(1, "ACGACTGACATCAGCACTGACGACGACGACAGCAGCAGCAGCATCAGC".to_string(),
+                                         "assembly.fasta".to_string(), "contig_1".to_string(), 48, 2);
+        let hashes = mmer_hashes(&seq, 4);
+        assert_eq!(hashes.len(), 49);
+        for window in [1, 3, 10] {
+            let anchors = find_minimizers(&hashes, window);
+            assert!(*anchors.first().unwrap() < window);
+            assert!(*anchors.last().unwrap() > 48 - window);
+            assert!(anchors.windows(2).all(|p| p[0] < p[1]));
+            assert!(anchors.windows(2).all(|p| p[1] - p[0] <= window));
+        }
+        assert_eq!(find_minimizers(&hashes, 1), (0..=48).collect::<Vec<_>>());
+        assert_eq!(end_mmers(&seq, 4), vec![0, 48, 2, 46]);
+
+        // Minimizers on the reverse strand mirror those on the forward strand.
+        let rev_seq = String::from_utf8(reverse_complement(&seq.forward_seq[2..50])).unwrap();
+        let rev_seq = Sequence::new_with_seq(2, rev_seq, "assembly.fasta".to_string(),
+                                             "contig_2".to_string(), 48, 2);
+        let anchors = find_minimizers(&hashes, 5);
+        let mut rev_anchors: Vec<usize> = find_minimizers(&mmer_hashes(&rev_seq, 4), 5).iter().map(|p| 48 - p).collect();
+        rev_anchors.sort();
+        assert_eq!(anchors, rev_anchors);
+    }
+
+    #[test]
+    fn test_minimizer_graph() {
+        let seq = Sequence::new_with_seq(1, "ACGACTGACATCAGCACTGACGACGACGACAGCAGCAGCAGCATCAGC".to_string(),
+                                         "assembly.fasta".to_string(), "contig_1".to_string(), 48, 2);
+        let seqs = vec![seq];
+        let mut m_graph = MinimizerGraph::new(5, 4);
+        m_graph.add_sequences(&seqs);
+        let (_, length, path) = &m_graph.paths[0];
+        assert_eq!(*length, 48);
+
+        // The path's segments overlap by k-1 and spell out the padded sequence.
+        let mut spelled = m_graph.segments[path[0]].to_vec();
+        for w in path.windows(2) {
+            assert!(m_graph.next_segments(w[0]).contains(&w[1]));
+            assert!(m_graph.prev_segments(w[1]).contains(&w[0]));
+            spelled.extend_from_slice(&m_graph.segments[w[1]][4..]);
+        }
+        assert_eq!(spelled, seqs[0].forward_seq);
+        assert!(m_graph.starts_sequence[path[0]]);
+        assert!(m_graph.starts_sequence[m_graph.reverse[*path.last().unwrap()]]);
+        for i in 0..m_graph.segment_count() {
+            assert_eq!(m_graph.reverse[m_graph.reverse[i]], i);
+        }
+    }
+
+    #[test]
+    fn test_rotated_sequences() {
+        // The second sequence is a rotation of the first, so its ends fall in the middle of the
+        // first's segments. The anchors at its ends are used in both sequences, so each k-mer is
+        // in only one segment.
+        let seq = crate::tests::random_seq(400, 0);
+        let rotated = format!("{}{}", &seq[150..], &seq[..150]);
+        let seqs = vec![Sequence::new_with_seq(1, seq, "a.fasta".to_string(), "a".to_string(),
+                                               400, 10),
+                        Sequence::new_with_seq(2, rotated, "b.fasta".to_string(), "b".to_string(),
+                                               400, 10)];
+        let mut m_graph = MinimizerGraph::new(21, 20);
+        m_graph.add_sequences(&seqs);
+        let mut kmer_segments: FxHashMap<&[u8], usize> = FxHashMap::default();
+        for (i, segment) in m_graph.segments.iter().enumerate() {
+            for kmer in segment.windows(21).filter(|kmer| !kmer.contains(&b'.')) {
+                assert_eq!(*kmer_segments.entry(kmer).or_insert(i), i);
+            }
+        }
+    }
+}
@@ -12,15 +12,20 @@
 // License along with Autocycler. If not, see <http://www.gnu.org/licenses/>.
 
 use indicatif::{ProgressBar, ProgressStyle};
+use bzip2::read::MultiBzDecoder;
+use bzip2::write::BzEncoder;
 use flate2::read::MultiGzDecoder;
-use seq_io::fastq::Reader;
-use std::collections::HashSet;
+use flate2::write::GzEncoder;
+use seq_io::fastq::{Reader, Record};
+use std::collections::{HashMap, HashSet};
 use std::fs;
 use std::fs::{File, read_dir, create_dir_all, remove_dir_all};
 use std::io;
-use std::io::{prelude::*, BufReader, Read};
+use std::io::{prelude::*, BufReader, BufWriter, Read};
 use std::path::{Path, PathBuf};
 use std::time::Duration;
+use xz2::read::XzDecoder;
+use xz2::write::XzEncoder;
 
 
 pub mod strand {
@@ -49,10 +54,11 @@ pub fn delete_dir_if_exists(dir_path: &Path) {
 
 
 pub fn load_file_lines(filename: &Path) -> Vec<String> {
-    let file = File::open(filename).unwrap_or_else(|e| {
+    // Loads all lines of a text file, which can be either uncompressed or compressed.
+    let reader = decompressed_reader(filename).unwrap_or_else(|e| {
         quit_with_error(&format!("failed to open file {}\n{}", filename.display(), e));
     });
-    let reader = BufReader::new(file);
+    let reader = BufReader::new(reader);
     reader.lines().map(|line_result| {
         line_result.unwrap_or_else(|e| {
             quit_with_error(&format!("failed to read line\n{}", e));
@@ -84,14 +90,43 @@ pub fn find_all_assemblies(in_dir: &Path) -> Vec<PathBuf> {
 
 
 fn is_assembly_file(path: &Path) -> bool {
-    path.is_file() && 
-        (path.extension().unwrap_or_default() == "fasta" ||
-         path.extension().unwrap_or_default() == "fna" ||
-         path.extension().unwrap_or_default() == "fa" ||
-         (path.extension().unwrap_or_default() == "gz" &&
-          path.file_stem().unwrap_or_default().to_str().unwrap_or_default().ends_with(".fasta") ||
-          path.file_stem().unwrap_or_default().to_str().unwrap_or_default().ends_with(".fna") ||
-          path.file_stem().unwrap_or_default().to_str().unwrap_or_default().ends_with(".fa")))
+    path.is_file() && (is_fasta_file(path) || is_gfa_file(path) || is_fastq_file(path))
+}
+
+
+fn is_fasta_file(path: &Path) -> bool {
+    has_extension(path, &["fasta", "fna", "fa"])
+}
+
+
+fn is_gfa_file(path: &Path) -> bool {
+    has_extension(path, &["gfa"])
+}
+
+
+pub fn is_fastq_file(path: &Path) -> bool {
+    has_extension(path, &["fastq", "fq"])
+}
+
+
+pub fn is_gaf_file(path: &Path) -> bool {
+    has_extension(path, &["gaf"])
+}
+
+
+// File extensions of the compression formats Autocycler can read and write.
+pub const COMPRESSION_EXTENSIONS: [&str; 4] = ["gz", "zst", "xz", "bz2"];
+
+
+fn has_extension(path: &Path, extensions: &[&str]) -> bool {
+    // Checks a file's extension, looking past any compression extension (e.g. "x.fasta.gz" has
+    // the "fasta" extension).
+    let mut extension = path.extension().unwrap_or_default().to_str().unwrap_or_default();
+    if COMPRESSION_EXTENSIONS.contains(&extension) {
+        let stem = Path::new(path.file_stem().unwrap_or_default());
+        extension = stem.extension().unwrap_or_default().to_str().unwrap_or_default();
+    }
+    extensions.contains(&extension)
 }
 
 
@@ -127,6 +162,14 @@ pub fn check_if_dir_is_not_dir(dir: &Path) {
 }
 
 
+pub fn check_gfa_version(gfa_version: u32) {
+    // Quits with an error if the given GFA version is not one that Autocycler can write.
+    if gfa_version != 1 && gfa_version != 2 {
+        quit_with_error("--gfa_version must be 1 or 2");
+    }
+}
+
+
 #[cfg(not(test))]
 pub fn quit_with_error(text: &str) -> ! {
     // For friendly error messages, this function normally just prints the error and quits.
@@ -141,17 +184,172 @@ pub fn quit_with_error(text: &str) -> ! {
 }
 
 
+// A loaded contig: (name, header, sequence).
+type Contig = (String, String, String);
+
+
+pub fn load_assembly(filename: &Path, gfa_paths: bool, rename_duplicates: bool)
+        -> (Vec<Contig>, Vec<(String, String)>) {
+    // Loads an assembly's contigs from a FASTA, FASTQ or GFA file, returning name+header+sequence
+    // tuples. Contig names must be unique, so duplicate names either cause an error or are renamed,
+    // in which case the (original name, new name) pairs are also returned.
+    let mut seqs = if is_gfa_file(filename) {
+        read_gfa(filename, gfa_paths)
+    } else if is_fastq_file(filename) {
+        read_fastq(filename)
+    } else {
+        read_fasta(filename)
+    };
+    if rename_duplicates {
+        let renamed = rename_duplicate_names(&mut seqs);
+        (seqs, renamed)
+    } else {
+        check_unique_names(&seqs, filename);
+        (seqs, Vec::new())
+    }
+}
+
+
+fn read_gfa(filename: &Path, use_paths: bool) -> Vec<(String, String, String)> {
+    // Loads contigs from an assembly graph (e.g. from Flye, miniasm or Unicycler). Each segment
+    // becomes a contig, or if use_paths is true, each path is spelled out to make a contig.
+    if is_file_empty(filename) {
+        quit_with_error(&format!("{} is an empty file", filename.display()));
+    }
+    let gfa_lines = load_file_lines(filename);
+    let gfa_seqs = load_gfa_lines(&gfa_lines, use_paths).unwrap_or_else(|e| {
+        quit_with_error(&format!("unable to load {}\n{}", filename.display(), e));
+    });
+    check_load_fasta(&gfa_seqs, filename);
+    gfa_seqs
+}
+
+
+fn load_gfa_lines(gfa_lines: &[String], use_paths: bool)
+        -> Result<Vec<(String, String, String)>, String> {
+    let mut segments = Vec::new();
+    let mut links = HashMap::new();
+    let mut paths = Vec::new();
+    for line in gfa_lines {
+        let parts: Vec<&str> = line.split('\t').collect();
+        match parts[0] {
+            "S" => {
+                if parts.len() < 3 {
+                    return Err(format!("S line has too few fields: {}", line));
+                }
+                if parts[2] == "*" {
+                    return Err(format!("segment {} has no sequence", parts[1]));
+                }
+                // Any tags (e.g. depth) are kept in the header, like a FASTA description.
+                let header = parts[1..].iter().enumerate().filter(|(i, _)| *i != 1)
+                    .map(|(_, p)| *p).collect::<Vec<_>>().join(" ");
+                segments.push((parts[1].to_string(), header, parts[2].to_ascii_uppercase()));
+            }
+            "L" => {
+                if parts.len() < 6 {
+                    return Err(format!("L line has too few fields: {}", line));
+                }
+                let from = format!("{}{}", parts[1], parts[2]);
+                let to = format!("{}{}", parts[3], parts[4]);
+                let overlap = cigar_overlap(parts[5])?;
+                links.insert((flip_gfa_segment(&to), flip_gfa_segment(&from)), overlap);
+                links.insert((from, to), overlap);
+            }
+            "P" => {
+                if parts.len() < 3 {
+                    return Err(format!("P line has too few fields: {}", line));
+                }
+                let overlaps = parts.get(3).copied().unwrap_or("*");
+                paths.push((parts[1].to_string(), parts[2].to_string(), overlaps.to_string()));
+            }
+            _ => {}
+        }
+    }
+    if !use_paths {
+        return Ok(segments);
+    }
+    let segment_seqs: HashMap<&str, &str> = segments.iter()
+        .map(|(name, _, seq)| (name.as_str(), seq.as_str())).collect();
+    let mut path_seqs = Vec::new();
+    for (name, segment_list, overlaps) in paths {
+        let segment_list: Vec<&str> = segment_list.split(',').collect();
+        let overlaps: Vec<usize> = if overlaps == "*" {
+            segment_list.windows(2)
+                .map(|w| *links.get(&(w[0].to_string(), w[1].to_string())).unwrap_or(&0)).collect()
+        } else {
+            overlaps.split(',').map(cigar_overlap).collect::<Result<_, _>>()?
+        };
+        let mut sequence = String::new();
+        for (i, segment) in segment_list.iter().enumerate() {
+            if segment.len() < 2 || !(segment.ends_with('+') || segment.ends_with('-')) {
+                return Err(format!("path {} has an invalid segment: {}", name, segment));
+            }
+            let segment_name = &segment[..segment.len() - 1];
+            let seq = segment_seqs.get(segment_name).ok_or_else(|| {
+                format!("path {} uses a segment not in the graph: {}", name, segment_name)
+            })?;
+            let seq = if segment.ends_with('+') { seq.to_string() }
+                      else { String::from_utf8(reverse_complement(seq.as_bytes())).unwrap() };
+            let overlap = if i == 0 { 0 } else { *overlaps.get(i - 1).unwrap_or(&0) };
+            if overlap > seq.len() {
+                return Err(format!("path {} has an overlap longer than segment {}", name, segment_name));
+            }
+            sequence.push_str(&seq[overlap..]);
+        }
+        path_seqs.push((name.clone(), name, sequence));
+    }
+    if path_seqs.is_empty() {
+        return Err("no paths found".to_string());
+    }
+    Ok(path_seqs)
+}
+
+
+fn flip_gfa_segment(segment: &str) -> String {
+    // Changes the strand of an oriented segment name, e.g. "5+" to "5-".
+    let (name, orientation) = segment.split_at(segment.len() - 1);
+    format!("{}{}", name, if orientation == "+" { "-" } else { "+" })
+}
+
+
+fn cigar_overlap(cigar: &str) -> Result<usize, String> {
+    // Returns how many bases of the next segment an overlap CIGAR (e.g. "10M") covers.
+    if cigar == "*" { return Ok(0); }
+    let mut overlap = 0;
+    let mut count = String::new();
+    for c in cigar.chars() {
+        if c.is_ascii_digit() {
+            count.push(c);
+            continue;
+        }
+        let n: usize = count.parse().map_err(|_| format!("invalid overlap: {}", cigar))?;
+        if matches!(c, 'M' | 'I' | '=' | 'X') {
+            overlap += n;
+        }
+        count.clear();
+    }
+    if !count.is_empty() {
+        return Err(format!("invalid overlap: {}", cigar));
+    }
+    Ok(overlap)
+}
+
+
 pub fn load_fasta(filename: &Path) -> Vec<(String, String, String)> {
     // This function loads a FASTA file and runs a few checks on the result. If everything looks
     // good, it returns a vector of name+sequence tuples.
+    let fasta_seqs = read_fasta(filename);
+    check_unique_names(&fasta_seqs, filename);
+    fasta_seqs
+}
+
+
+fn read_fasta(filename: &Path) -> Vec<(String, String, String)> {
     if is_file_empty(filename) {
         quit_with_error(&format!("{} is an empty file", filename.display()));
     }
-    let load_result = if is_file_gzipped(filename) {
-        load_fasta_gzipped(filename)
-    } else {
-        load_fasta_not_gzipped(filename)
-    };
+    let load_result = decompressed_reader(filename)
+        .and_then(|reader| load_fasta_from_reader(BufReader::new(reader), filename));
     match load_result {
         Ok(_)  => (),
         Err(e) => quit_with_error(&format!("unable to load {}\n{}", filename.display(), e)),
@@ -162,6 +360,37 @@ pub fn load_fasta(filename: &Path) -> Vec<(String, String, String)> {
 }
 
 
+pub fn fasta_line_widths(filename: &Path) -> Vec<usize> {
+    // Returns the bases per line for each record of a FASTA file (in file order), taken from its
+    // first sequence line. Records on a single line get 0 (no wrapping), as do all records of
+    // non-FASTA assemblies.
+    if is_gfa_file(filename) || is_fastq_file(filename) {
+        return Vec::new();
+    }
+    let reader = match decompressed_reader(filename) {
+        Ok(reader) => BufReader::new(reader),
+        Err(e) => quit_with_error(&format!("unable to load {}\n{}", filename.display(), e)),
+    };
+    let mut widths = Vec::new();
+    let (mut in_record, mut first_line, mut line_count) = (false, 0, 0);
+    for line in reader.lines() {
+        let text = line.unwrap_or_else(|e| {
+            quit_with_error(&format!("unable to load {}\n{}", filename.display(), e));
+        });
+        if text.is_empty() { continue; }
+        if text.starts_with('>') {
+            if in_record { widths.push(if line_count > 1 { first_line } else { 0 }); }
+            (in_record, first_line, line_count) = (true, 0, 0);
+        } else {
+            if line_count == 0 { first_line = text.len(); }
+            line_count += 1;
+        }
+    }
+    if in_record { widths.push(if line_count > 1 { first_line } else { 0 }); }
+    widths
+}
+
+
 fn check_load_fasta(fasta_seqs: &Vec<(String, String, String)>, filename: &Path) {
     // This function looks at the result of the load_fasta function and does some checks to make
     // sure everything looks okay. If any problems are found, it will quit with an error message.
@@ -176,8 +405,12 @@ fn check_load_fasta(fasta_seqs: &Vec<(String, String, String)>, filename: &Path)
             quit_with_error(&format!("{} has an empty sequence", filename.display()));
         }
     }
+}
+
+
+fn check_unique_names(seqs: &[(String, String, String)], filename: &Path) {
     let mut set = HashSet::new();
-    for (name, _, _) in fasta_seqs {
+    for (name, _, _) in seqs {
         if !set.insert(name) {
             quit_with_error(&format!("{} has a duplicate name: {}", filename.display(), name));
         }
@@ -185,16 +418,59 @@ fn check_load_fasta(fasta_seqs: &Vec<(String, String, String)>, filename: &Path)
 }
 
 
+fn rename_duplicate_names(seqs: &mut [(String, String, String)]) -> Vec<(String, String)> {
+    // Gives a numbered suffix to each repeated sequence name (e.g. the second "ctg" becomes
+    // "ctg_2"), keeping any description in the header. Returns (original name, new name) pairs.
+    let mut used: HashSet<String> = seqs.iter().map(|(name, _, _)| name.clone()).collect();
+    let mut seen = HashSet::new();
+    let mut renamed = Vec::new();
+    for (name, header, _) in seqs.iter_mut() {
+        if seen.insert(name.clone()) {
+            continue;
+        }
+        let new_name = (2..).map(|i| format!("{}_{}", name, i))
+            .find(|n| !used.contains(n)).unwrap();
+        let description = after_first_space(header);
+        *header = if description.is_empty() { new_name.clone() }
+                  else { format!("{} {}", new_name, description) };
+        used.insert(new_name.clone());
+        seen.insert(new_name.clone());
+        renamed.push((std::mem::replace(name, new_name.clone()), new_name));
+    }
+    renamed
+}
+
+
+fn read_fastq(filename: &Path) -> Vec<(String, String, String)> {
+    // Loads contigs from a FASTQ file (e.g. from a polishing tool), ignoring their qualities. The
+    // same checks are run as for FASTA files.
+    if is_file_empty(filename) {
+        quit_with_error(&format!("{} is an empty file", filename.display()));
+    }
+    let mut fastq_seqs = Vec::new();
+    let mut reader = fastq_reader(filename);
+    while let Some(record) = reader.next() {
+        let record = record.unwrap_or_else(|e| {
+            quit_with_error(&format!("unable to load {}\n{}", filename.display(), e));
+        });
+        let header = String::from_utf8_lossy(record.head()).to_string();
+        let name = up_to_first_space(&header);
+        let mut sequence = String::from_utf8_lossy(record.seq()).to_string();
+        sequence.make_ascii_uppercase();
+        fastq_seqs.push((name, header, sequence));
+    }
+    check_load_fasta(&fastq_seqs, filename);
+    fastq_seqs
+}
+
+
 pub fn fastq_reader(fastq_file: &Path)
         -> seq_io::fastq::Reader<BufReader<Box<dyn std::io::Read>>> {
-    // Returns a reader for a FASTQ file that works on both unzipped and gzipped files.
-    let file = File::open(fastq_file).expect("Error opening file");
-    let reader: Box<dyn Read> = if is_file_gzipped(fastq_file) {
-        Box::new(MultiGzDecoder::new(file))
-    } else {
-        Box::new(file)
-    };
-    Reader::new(BufReader::new(reader))
+    // Returns a reader for a FASTQ file that works on both uncompressed and compressed files.
+    match decompressed_reader(fastq_file) {
+        Ok(reader) => Reader::new(BufReader::new(reader)),
+        Err(e) => quit_with_error(&format!("unable to open {}\n{}", fastq_file.display(), e)),
+    }
 }
 
 
@@ -206,31 +482,82 @@ fn is_file_empty(filename: &Path) -> bool {
 }
 
 
-fn is_file_gzipped(filename: &Path) -> bool {
-    // This function returns true if the file appears to be gzipped (based on the first two bytes)
-    // and false if not. If it can't open the file or read the first two bytes, it will quit with
-    // an error message.
+#[derive(Debug, PartialEq)]
+enum Compression {
+    None,
+    Gzip,
+    Zstd,
+    Xz,  // common for archived read sets, so supported alongside zstd and bzip2
+    Bzip2,
+}
+
+
+fn file_compression(filename: &Path) -> Compression {
+    // This function determines a file's compression type from its first few bytes. If it can't
+    // open the file, it will quit with an error message.
     let open_result = File::open(filename);
     match open_result {
         Ok(_)  => (),
         Err(e) => quit_with_error(&format!("unable to open {}\n{}", filename.display(), e)),
     }
-    let file = open_result.unwrap();
-    let mut reader = BufReader::new(file);
-    let mut buf = vec![0u8; 2];
-    let read_result = reader.read_exact(&mut buf);
+    let mut buf = Vec::with_capacity(6);
+    let read_result = open_result.unwrap().take(6).read_to_end(&mut buf);
     match read_result {
         Ok(_)  => (),
-        Err(e) => quit_with_error(&format!("{} is too small\n{}", filename.display(), e)),
+        Err(e) => quit_with_error(&format!("unable to read {}\n{}", filename.display(), e)),
     }
-    buf[0] == 31 && buf[1] == 139
+    compression_from_magic_bytes(&buf)
 }
 
 
-fn load_fasta_not_gzipped(filename: &Path) -> io::Result<Vec<(String, String, String)>> {
+fn compression_from_magic_bytes(buf: &[u8]) -> Compression {
+    if buf.starts_with(&[0x1f, 0x8b]) {
+        Compression::Gzip
+    } else if buf.starts_with(&[0x28, 0xb5, 0x2f, 0xfd]) {
+        Compression::Zstd
+    } else if buf.starts_with(&[0xfd, b'7', b'z', b'X', b'Z', 0x00]) {
+        Compression::Xz
+    } else if buf.starts_with(b"BZh") {
+        Compression::Bzip2
+    } else {
+        Compression::None
+    }
+}
+
+
+fn decompressed_reader(filename: &Path) -> io::Result<Box<dyn Read>> {
+    // Opens a file for reading, transparently decompressing it if it's gzip, zstd, xz or bzip2
+    // compressed. A filename of "-" reads from stdin, where the compression is found by peeking at
+    // the first bytes.
+    if is_stdin(filename) {
+        let mut stdin = BufReader::new(io::stdin());
+        let compression = compression_from_magic_bytes(stdin.fill_buf()?);
+        return decompress(stdin, compression);
+    }
+    let compression = file_compression(filename);
+    decompress(File::open(filename)?, compression)
+}
+
+
+fn decompress<R: Read + 'static>(reader: R, compression: Compression) -> io::Result<Box<dyn Read>> {
+    Ok(match compression {
+        Compression::None  => Box::new(reader),
+        Compression::Gzip  => Box::new(MultiGzDecoder::new(reader)),
+        Compression::Zstd  => Box::new(zstd::Decoder::new(reader)?),
+        Compression::Xz    => Box::new(XzDecoder::new_multi_decoder(reader)),
+        Compression::Bzip2 => Box::new(MultiBzDecoder::new(reader)),
+    })
+}
+
+
+pub fn is_stdin(filename: &Path) -> bool {
+    filename == Path::new("-")
+}
+
+
+fn load_fasta_from_reader<R: BufRead>(reader: R, filename: &Path)
+        -> io::Result<Vec<(String, String, String)>> {
     let mut fasta_seqs = Vec::new();
-    let file = File::open(filename)?;
-    let reader = BufReader::new(file);
     let mut name = String::new();
     let mut header = String::new();
     let mut sequence = String::new();
@@ -265,45 +592,108 @@ fn load_fasta_not_gzipped(filename: &Path) -> io::Result<Vec<(String, String, St
 }
 
 
-fn load_fasta_gzipped(filename: &Path) -> io::Result<Vec<(String, String, String)>> {
-    let mut fasta_seqs = Vec::new();
-    let file = File::open(filename)?;
-    let reader = BufReader::new(MultiGzDecoder::new(file));
-    let mut name = String::new();
-    let mut header = String::new();
-    let mut sequence = String::new();
-    for line in reader.lines() {
-        let text = line?;
-        if text.is_empty() {continue;}
-        if let Some(text) = text.strip_prefix('>') {
-            if !name.is_empty() {
-                sequence.make_ascii_uppercase();
-                fasta_seqs.push((name, header, sequence));
-                sequence = String::new();
-            }
-            header = text.to_string();
-            let first_piece = text.split_whitespace().next();
-            match first_piece {
-                Some(_) => (),
-                None    => quit_with_error(&format!("{} is not correctly formatted", filename.display())),
-            }
-            name = first_piece.unwrap().to_string();
-        } else {
-            if name.is_empty() {
-                quit_with_error(&format!("{} is not correctly formatted", filename.display()));
-            }
-            sequence.push_str(&text);
+pub enum OutputWriter {
+    // A buffered writer for an output file, which may be compressed. Call finish when done
+    // writing, so errors from the final flush (including any compression trailer) aren't lost in
+    // Drop.
+    Plain(BufWriter<File>),
+    Gzip(BufWriter<GzEncoder<File>>),
+    Zstd(BufWriter<zstd::Encoder<'static, File>>),
+    Xz(BufWriter<XzEncoder<File>>),
+    Bzip2(BufWriter<BzEncoder<File>>),
+}
+
+impl Write for OutputWriter {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        match self {
+            OutputWriter::Plain(w) => w.write(buf),
+            OutputWriter::Gzip(w) => w.write(buf),
+            OutputWriter::Zstd(w) => w.write(buf),
+            OutputWriter::Xz(w) => w.write(buf),
+            OutputWriter::Bzip2(w) => w.write(buf),
         }
     }
-    if !name.is_empty() {
-        sequence.make_ascii_uppercase();
-        fasta_seqs.push((name, header, sequence));
+
+    fn flush(&mut self) -> io::Result<()> {
+        match self {
+            OutputWriter::Plain(w) => w.flush(),
+            OutputWriter::Gzip(w) => w.flush(),
+            OutputWriter::Zstd(w) => w.flush(),
+            OutputWriter::Xz(w) => w.flush(),
+            OutputWriter::Bzip2(w) => w.flush(),
+        }
     }
-    Ok(fasta_seqs)
+}
+
+impl OutputWriter {
+    pub fn finish(self) -> io::Result<()> {
+        match self {
+            OutputWriter::Plain(mut w) => w.flush()?,
+            OutputWriter::Gzip(w) => { w.into_inner().map_err(|e| e.into_error())?.finish()?; }
+            OutputWriter::Zstd(w) => { w.into_inner().map_err(|e| e.into_error())?.finish()?; }
+            OutputWriter::Xz(w) => { w.into_inner().map_err(|e| e.into_error())?.finish()?; }
+            OutputWriter::Bzip2(w) => { w.into_inner().map_err(|e| e.into_error())?.finish()?; }
+        }
+        Ok(())
+    }
+}
+
+
+pub fn output_writer(filename: &Path) -> io::Result<OutputWriter> {
+    // Returns a writer for an output file (GFA, TSV, etc.). If the filename ends in .gz, the
+    // output will be gzip-compressed.
+    let gzip_level = if filename.extension().and_then(|s| s.to_str()) == Some("gz") {
+        Some(flate2::Compression::default().level())
+    } else {
+        None
+    };
+    output_writer_with_level(filename, gzip_level)
+}
+
+
+pub fn output_writer_with_level(filename: &Path, gzip_level: Option<u32>)
+        -> io::Result<OutputWriter> {
+    // Returns a writer for an output file, gzip-compressed at the given level (if any).
+    let file = File::create(filename)?;
+    Ok(match gzip_level {
+        Some(level) => OutputWriter::Gzip(BufWriter::new(
+            GzEncoder::new(file, flate2::Compression::new(level)))),
+        None => OutputWriter::Plain(BufWriter::new(file)),
+    })
+}
+
+
+pub fn output_writer_for_extension(filename: &Path, level: u32) -> io::Result<OutputWriter> {
+    // Returns a writer for an output file, compressed at the given level (1-9) to match its
+    // extension (.gz, .zst, .xz or .bz2). Files with other extensions are written uncompressed.
+    let file = File::create(filename)?;
+    Ok(match filename.extension().and_then(|s| s.to_str()) {
+        Some("gz")  => OutputWriter::Gzip(BufWriter::new(
+            GzEncoder::new(file, flate2::Compression::new(level)))),
+        Some("zst") => OutputWriter::Zstd(BufWriter::new(zstd::Encoder::new(file, level as i32)?)),
+        Some("xz")  => OutputWriter::Xz(BufWriter::new(XzEncoder::new(file, level))),
+        Some("bz2") => OutputWriter::Bzip2(BufWriter::new(
+            BzEncoder::new(file, bzip2::Compression::new(level)))),
+        _ => OutputWriter::Plain(BufWriter::new(file)),
+    })
+}
+
+
+pub fn write_fasta_seq<W: Write>(writer: &mut W, seq: &str, wrap: usize) -> io::Result<()> {
+    // Writes a FASTA sequence with lines of at most wrap bases. A wrap of 0 puts the whole
+    // sequence on one line.
+    if wrap == 0 || seq.len() <= wrap {
+        return writeln!(writer, "{}", seq);
+    }
+    for line in seq.as_bytes().chunks(wrap) {
+        writer.write_all(line)?;
+        writer.write_all(b"\n")?;
+    }
+    Ok(())
 }
 
 
-fn complement_base(base: u8) -> u8 {
+pub fn complement_base(base: u8) -> u8 {
     match base {
         b'A' => b'T',
         b'T' => b'A',
@@ -422,6 +812,22 @@ pub fn spinner(message: &str) -> ProgressBar {
 }
 
 
+pub fn edit_distance(a: &[u8], b: &[u8]) -> usize {
+    // Levenshtein distance between two sequences, using two rows of the DP matrix.
+    let mut prev: Vec<usize> = (0..=b.len()).collect();
+    let mut curr = vec![0; b.len() + 1];
+    for (i, &a_base) in a.iter().enumerate() {
+        curr[0] = i + 1;
+        for (j, &b_base) in b.iter().enumerate() {
+            let substitution = prev[j] + usize::from(a_base != b_base);
+            curr[j + 1] = substitution.min(prev[j + 1] + 1).min(curr[j] + 1);
+        }
+        std::mem::swap(&mut prev, &mut curr);
+    }
+    prev[b.len()]
+}
+
+
 pub fn reverse_path(path: &[i32]) -> Vec<i32> {
     path.iter().rev().map(|&num| -num).collect()
 }
@@ -454,25 +860,7 @@ pub fn after_first_space(string: &str) -> String {
 
 
 pub fn first_char_in_file(filename: &Path) -> io::Result<char> {
-    if is_file_gzipped(filename) {
-        first_char_in_file_gzipped(filename)
-    } else {
-        first_char_in_file_not_gzipped(filename)
-    }
-}
-
-
-fn first_char_in_file_not_gzipped(filename: &Path) -> io::Result<char> {
-    let file = File::open(filename)?;
-    let reader = BufReader::new(file);
-    first_non_empty_char(reader)
-}
-
-
-fn first_char_in_file_gzipped(filename: &Path) -> io::Result<char> {
-    let file = File::open(filename)?;
-    let reader = BufReader::new(MultiGzDecoder::new(file));
-    first_non_empty_char(reader)
+    first_non_empty_char(BufReader::new(decompressed_reader(filename)?))
 }
 
 
@@ -511,6 +899,20 @@ mod tests {
         assert_eq!(reverse_complement(b"XYZ"), b"NNN");
     }
 
+    #[test]
+    fn test_write_fasta_seq() {
+        let wrapped = |seq: &str, wrap: usize| {
+            let mut buffer = Vec::new();
+            write_fasta_seq(&mut buffer, seq, wrap).unwrap();
+            String::from_utf8(buffer).unwrap()
+        };
+        assert_eq!(wrapped("ACGTACGTAC", 0), "ACGTACGTAC\n");
+        assert_eq!(wrapped("ACGTACGTAC", 4), "ACGT\nACGT\nAC\n");
+        assert_eq!(wrapped("ACGTACGT", 4), "ACGT\nACGT\n");
+        assert_eq!(wrapped("ACGTACGTAC", 10), "ACGTACGTAC\n");
+        assert_eq!(wrapped("", 4), "\n");
+    }
+
     #[test]
     fn test_usize_division_rounded() {
         assert_eq!(usize_division_rounded(0, 3), 0);
@@ -639,6 +1041,19 @@ mod tests {
         assert_eq!(mad_isize(&[4, 1, 9, 6, 1, 2, 2]), 1);
     }
 
+    #[test]
+    fn test_edit_distance() {
+        assert_eq!(edit_distance(b"", b""), 0);
+        assert_eq!(edit_distance(b"ACGT", b""), 4);
+        assert_eq!(edit_distance(b"", b"AC"), 2);
+        assert_eq!(edit_distance(b"ACGT", b"ACGT"), 0);
+        assert_eq!(edit_distance(b"ACGT", b"ACCT"), 1);
+        assert_eq!(edit_distance(b"ACGT", b"AGT"), 1);
+        assert_eq!(edit_distance(b"AAAAT", b"AAAT"), 1);
+        assert_eq!(edit_distance(b"kitten", b"sitting"), 3);
+        assert_eq!(edit_distance(b"sitting", b"kitten"), 3);
+    }
+
     #[test]
     fn test_reverse_path() {
         assert_eq!(reverse_path(&[1, -2]), vec![2, -1]);
@@ -702,16 +1117,196 @@ mod tests {
         let filename = dir.path().join("temp.fasta");
 
         make_test_file(&filename, ">a\nACGT\n>b xyz\nACGT\nACGT\n");
-        let fasta = load_fasta_not_gzipped(&filename).unwrap();
+        let fasta = load_fasta(&filename);
         assert_eq!(fasta, vec![("a".to_string(), "a".to_string(), "ACGT".to_string()),
                                ("b".to_string(), "b xyz".to_string(), "ACGTACGT".to_string())]);
 
         make_gzipped_test_file(&filename, ">a\nACGT\n>b xyz\nACGT\nACGT\n");
-        let fasta = load_fasta_gzipped(&filename).unwrap();
+        let fasta = load_fasta(&filename);
         assert_eq!(fasta, vec![("a".to_string(), "a".to_string(), "ACGT".to_string()),
                                ("b".to_string(), "b xyz".to_string(), "ACGTACGT".to_string())]);
     }
 
+    fn make_compressed_test_file(filename: &Path, contents: &str, compression: Compression) {
+        let contents = contents.as_bytes();
+        let compressed = match compression {
+            Compression::Zstd => zstd::encode_all(contents, 3).unwrap(),
+            Compression::Xz => {
+                let mut encoder = xz2::write::XzEncoder::new(Vec::new(), 6);
+                encoder.write_all(contents).unwrap();
+                encoder.finish().unwrap()
+            }
+            Compression::Bzip2 => {
+                let mut encoder = bzip2::write::BzEncoder::new(Vec::new(),
+                                                               bzip2::Compression::default());
+                encoder.write_all(contents).unwrap();
+                encoder.finish().unwrap()
+            }
+            _ => unreachable!(),
+        };
+        fs::write(filename, compressed).unwrap();
+    }
+
+    #[test]
+    fn test_load_compressed_files() {
+        let dir = tempdir().unwrap();
+        for (name, compression) in [("temp.fasta.zst", Compression::Zstd),
+                                    ("temp.fasta.xz", Compression::Xz),
+                                    ("temp.fasta.bz2", Compression::Bzip2)] {
+            let filename = dir.path().join(name);
+            make_compressed_test_file(&filename, ">a\nACGT\n>b xyz\nACGT\nACGT\n", compression);
+            assert!(is_assembly_file(&filename));
+            assert_eq!(load_fasta(&filename),
+                       vec![("a".to_string(), "a".to_string(), "ACGT".to_string()),
+                            ("b".to_string(), "b xyz".to_string(), "ACGTACGT".to_string())]);
+            assert_eq!(first_char_in_file(&filename).unwrap(), '>');
+        }
+
+        let filename = dir.path().join("temp.fastq.zst");
+        make_compressed_test_file(&filename, "@a\nACGT\n+\n!!!!\n", Compression::Zstd);
+        assert_eq!(read_fastq(&filename),
+                   vec![("a".to_string(), "a".to_string(), "ACGT".to_string())]);
+    }
+
+    #[test]
+    fn test_fasta_line_widths() {
+        let dir = tempdir().unwrap();
+        let filename = dir.path().join("temp.fasta");
+        make_test_file(&filename, ">a\nACGT\nACGT\nAC\n>b\nACGTACGT\n\n>c\nACG\nA\n");
+        assert_eq!(fasta_line_widths(&filename), vec![4, 0, 3]);
+        let filename = dir.path().join("temp.fastq");
+        make_test_file(&filename, "@a\nACGT\n+\n!!!!\n");
+        assert!(fasta_line_widths(&filename).is_empty());
+    }
+
+    #[test]
+    fn test_output_writer() {
+        let dir = tempdir().unwrap();
+        for name in ["out.fasta", "out.fasta.gz"] {
+            let filename = dir.path().join(name);
+            let mut writer = output_writer(&filename).unwrap();
+            writeln!(writer, ">a\nACGT").unwrap();
+            writer.finish().unwrap();
+            assert_eq!(file_compression(&filename) == Compression::Gzip, name.ends_with(".gz"));
+            assert_eq!(load_fasta(&filename),
+                       vec![("a".to_string(), "a".to_string(), "ACGT".to_string())]);
+        }
+    }
+
+    #[test]
+    fn test_output_writer_for_extension() {
+        let dir = tempdir().unwrap();
+        for (name, compression) in [("out.fasta", Compression::None),
+                                    ("out.fasta.gz", Compression::Gzip),
+                                    ("out.fasta.zst", Compression::Zstd),
+                                    ("out.fasta.xz", Compression::Xz),
+                                    ("out.fasta.bz2", Compression::Bzip2)] {
+            let filename = dir.path().join(name);
+            let mut writer = output_writer_for_extension(&filename, 6).unwrap();
+            writeln!(writer, ">a\nACGT").unwrap();
+            writer.finish().unwrap();
+            assert_eq!(file_compression(&filename), compression);
+            assert_eq!(load_fasta(&filename),
+                       vec![("a".to_string(), "a".to_string(), "ACGT".to_string())]);
+        }
+    }
+
+    #[test]
+    fn test_fastq_reader_missing_file() {
+        let dir = tempdir().unwrap();
+        let filename = dir.path().join("missing.fastq");
+        assert!(std::panic::catch_unwind(|| { fastq_reader(&filename); }).is_err());
+    }
+
+    #[test]
+    fn test_file_compression() {
+        let dir = tempdir().unwrap();
+        let filename = dir.path().join("temp.fasta");
+        make_test_file(&filename, ">a\nACGT\n");
+        assert_eq!(file_compression(&filename), Compression::None);
+        make_test_file(&filename, "");
+        assert_eq!(file_compression(&filename), Compression::None);
+        make_gzipped_test_file(&filename, ">a\nACGT\n");
+        assert_eq!(file_compression(&filename), Compression::Gzip);
+        make_compressed_test_file(&filename, ">a\nACGT\n", Compression::Bzip2);
+        assert_eq!(file_compression(&filename), Compression::Bzip2);
+    }
+
+    #[test]
+    fn test_load_gfa() {
+        let dir = tempdir().unwrap();
+        let filename = dir.path().join("temp.gfa");
+
+        make_test_file(&filename, "H\tVN:Z:1.0\nS\t1\tacgtac\tdp:i:10\nS\t2\tACCGT\n\
+                                   L\t1\t+\t2\t-\t2M\nP\tctg\t1+,2-\t*\n");
+        assert_eq!(read_gfa(&filename, false),
+                   vec![("1".to_string(), "1 dp:i:10".to_string(), "ACGTAC".to_string()),
+                        ("2".to_string(), "2".to_string(), "ACCGT".to_string())]);
+        assert_eq!(read_gfa(&filename, true),
+                   vec![("ctg".to_string(), "ctg".to_string(), "ACGTACGGT".to_string())]);
+
+        make_gzipped_test_file(&filename, "S\t1\tCCGT\nS\t2\tGGCC\nP\tp1\t2-,1+\t1M\n");
+        assert_eq!(read_gfa(&filename, true),
+                   vec![("p1".to_string(), "p1".to_string(), "GGCCCGT".to_string())]);
+
+        assert!(load_gfa_lines(&["S\t1\t*".to_string()], false).is_err());
+        assert!(load_gfa_lines(&["S\t1\tACGT".to_string(), "P\tp\t2+\t*".to_string()], true).is_err());
+    }
+
+    #[test]
+    fn test_rename_duplicate_names() {
+        let mut seqs = vec![("a".to_string(), "a".to_string(), "ACGT".to_string()),
+                            ("a".to_string(), "a xyz".to_string(), "ACGT".to_string()),
+                            ("a_2".to_string(), "a_2".to_string(), "ACGT".to_string()),
+                            ("a".to_string(), "a".to_string(), "ACGT".to_string())];
+        let renamed = rename_duplicate_names(&mut seqs);
+        assert_eq!(renamed, vec![("a".to_string(), "a_3".to_string()),
+                                 ("a".to_string(), "a_4".to_string())]);
+        let names: Vec<_> = seqs.iter().map(|(n, h, _)| (n.as_str(), h.as_str())).collect();
+        assert_eq!(names, vec![("a", "a"), ("a_3", "a_3 xyz"), ("a_2", "a_2"), ("a_4", "a_4")]);
+    }
+
+    #[test]
+    fn test_load_fastq() {
+        let dir = tempdir().unwrap();
+        let filename = dir.path().join("temp.fastq");
+
+        make_test_file(&filename, "@a\nACGT\n+\n!!!!\n@b xyz\nacgtac\n+\n######\n");
+        assert_eq!(read_fastq(&filename),
+                   vec![("a".to_string(), "a".to_string(), "ACGT".to_string()),
+                        ("b".to_string(), "b xyz".to_string(), "ACGTAC".to_string())]);
+
+        make_gzipped_test_file(&filename, "@a\nACGT\n+\n!!!!\n");
+        assert_eq!(read_fastq(&filename),
+                   vec![("a".to_string(), "a".to_string(), "ACGT".to_string())]);
+    }
+
+    #[test]
+    fn test_is_assembly_file() {
+        let dir = tempdir().unwrap();
+        for name in ["a.fasta", "a.fa.gz", "a.gfa", "a.gfa.gz", "a.fastq", "a.fq.gz", "a.fna.zst",
+                     "a.fastq.bz2", "a.gfa.xz"] {
+            let filename = dir.path().join(name);
+            make_test_file(&filename, "x");
+            assert!(is_assembly_file(&filename));
+        }
+        for name in ["a.txt", "a.gz", "a.fastq.rar"] {
+            let filename = dir.path().join(name);
+            make_test_file(&filename, "x");
+            assert!(!is_assembly_file(&filename));
+        }
+    }
+
+    #[test]
+    fn test_cigar_overlap() {
+        assert_eq!(cigar_overlap("*").unwrap(), 0);
+        assert_eq!(cigar_overlap("0M").unwrap(), 0);
+        assert_eq!(cigar_overlap("12M").unwrap(), 12);
+        assert_eq!(cigar_overlap("5M1I2M1D").unwrap(), 8);
+        assert!(cigar_overlap("M").is_err());
+        assert!(cigar_overlap("12").is_err());
+    }
+
     #[test]
     fn test_is_file_empty() {
         let dir = tempdir().unwrap();
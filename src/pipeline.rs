@@ -0,0 +1,296 @@
+// This file contains the code for the autocycler pipeline subcommand.
+
+// Copyright 2024 Ryan Wick (rrwick@gmail.com)
+// https://github.com/rrwick/Autocycler
+
+// This file is part of Autocycler. Autocycler is free software: you can redistribute it and/or
+// modify it under the terms of the GNU General Public License as published by the Free Software
+// Foundation, either version 3 of the License, or (at your option) any later version. Autocycler
+// is distributed in the hope that it will be useful, but WITHOUT ANY WARRANTY; without even the
+// implied warranty of MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the GNU General
+// Public License for more details. You should have received a copy of the GNU General Public
+// License along with Autocycler. If not, see <http://www.gnu.org/licenses/>.
+
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+use std::time::Instant;
+
+use crate::batch::qc_pass_cluster_dirs;
+use crate::cluster::{cluster, ClusterSettings};
+use crate::combine::combine;
+use crate::compress::{compress, CompressSettings};
+use crate::helper::{ASSEMBLERS, READ_TYPES};
+use crate::log::{section_header, explanation};
+use crate::misc::{check_gfa_version, check_if_dir_exists, check_if_dir_is_not_dir,
+                  check_if_file_exists, create_dir, find_all_assemblies, format_duration,
+                  quit_with_error};
+use crate::resolve::{resolve, ResolveSettings};
+use crate::subsample::{subsample, SubsampleSettings};
+use crate::table::{DEFAULT_FIELDS, get_header_line, get_values_line, parse_fields};
+use crate::trim::{trim, TrimSettings};
+
+
+pub struct PipelineSettings {
+    // The settings for autocycler pipeline, named as their options.
+    pub out_dir: PathBuf,
+    pub reads: Option<PathBuf>,
+    pub assemblies_dir: Option<PathBuf>,
+    pub genome_size: Option<String>,
+    pub assemblers: String,
+    pub count: usize,
+    pub read_type: String,
+    pub threads: usize,
+    pub kmer: u32,
+    pub gfa_version: u32,
+}
+
+
+pub fn pipeline(settings: PipelineSettings) {
+    let start_time = Instant::now();
+    let assemblers = check_settings(&settings);
+    starting_message();
+    print_settings(&settings, &assemblers);
+    let PipelineSettings { out_dir, reads, assemblies_dir, genome_size, count, read_type, threads,
+                           kmer: k_size, gfa_version, .. } = settings;
+    create_dir(&out_dir);
+    let autocycler_dir = out_dir.join("autocycler_out");
+    let metrics_tsv = out_dir.join("metrics.tsv");
+
+    // With reads, the pipeline makes its own input assemblies. Otherwise it starts from the
+    // user's assemblies.
+    let assemblies_dir = match (reads, assemblies_dir) {
+        (Some(reads), _) => {
+            let reads_dir = out_dir.join("subsampled_reads");
+            let assemblies_dir = out_dir.join("assemblies");
+            subsample(SubsampleSettings::new(vec![reads], reads_dir.clone(),
+                                             genome_size.clone().unwrap(), count));
+            run_assemblers(&reads_dir, &assemblies_dir, &assemblers, count,
+                           genome_size.as_ref().unwrap(), &read_type, threads);
+            assemblies_dir
+        }
+        (None, Some(assemblies_dir)) => assemblies_dir,
+        (None, None) => unreachable!(),
+    };
+
+    compress(CompressSettings::new(assemblies_dir, autocycler_dir.clone(), k_size, threads,
+                                   gfa_version));
+    cluster(ClusterSettings::new(autocycler_dir.clone(), threads, gfa_version));
+    let cluster_dirs = qc_pass_cluster_dirs(&autocycler_dir);
+    if cluster_dirs.is_empty() {
+        quit_with_error("no clusters passed QC");
+    }
+    for cluster_dir in &cluster_dirs {
+        trim(TrimSettings::new(cluster_dir.clone(), threads, gfa_version));
+        resolve(ResolveSettings::new(cluster_dir.clone(), gfa_version));
+    }
+    let final_gfas = cluster_dirs.iter().map(|d| d.join("5_final.gfa")).collect();
+    combine(autocycler_dir.clone(), final_gfas, gfa_version);
+
+    save_metrics(&out_dir, &metrics_tsv);
+    finished_message(start_time, &autocycler_dir, &metrics_tsv);
+}
+
+
+fn check_settings(settings: &PipelineSettings) -> Vec<String> {
+    // Returns the assemblers to run, parsed from --assemblers.
+    let PipelineSettings { ref out_dir, ref reads, ref assemblies_dir, ref genome_size,
+                           ref assemblers, count, ref read_type, threads, gfa_version,
+                           .. } = *settings;
+    let assemblers = parse_assemblers(assemblers);
+    check_if_dir_is_not_dir(out_dir);
+    match (reads, assemblies_dir) {
+        (Some(_), Some(_)) => quit_with_error("--reads and --assemblies_dir cannot both be used"),
+        (None, None) => quit_with_error("either --reads or --assemblies_dir is required"),
+        (Some(reads), None) => {
+            check_if_file_exists(reads);
+            if genome_size.is_none() {
+                quit_with_error("--genome_size is required with --reads");
+            }
+            if assemblers.is_empty() {
+                quit_with_error("--assemblers must name at least one assembler");
+            }
+            if let Some(a) = assemblers.iter().find(|a| !ASSEMBLERS.contains(&a.as_str())) {
+                quit_with_error(&format!("{} is not a valid assembler (must be one of: {})", a,
+                                         ASSEMBLERS.join(", ")));
+            }
+            if !READ_TYPES.contains(&read_type.as_str()) {
+                quit_with_error(&format!("--read_type must be one of: {}",
+                                         READ_TYPES.join(", ")));
+            }
+            if count < 1 { quit_with_error("--count must be at least 1"); }
+        }
+        (None, Some(assemblies_dir)) => check_if_dir_exists(assemblies_dir),
+    }
+    if threads == 0 { quit_with_error("--threads must be at least 1"); }
+    check_gfa_version(gfa_version);
+    assemblers
+}
+
+
+fn starting_message() {
+    section_header("Starting autocycler pipeline");
+    explanation("This command runs the whole Autocycler pipeline with default settings: \
+                 subsample and assemble the reads (or use existing input assemblies), then \
+                 compress, cluster, trim, resolve and combine. For more control, run each \
+                 step individually.");
+}
+
+
+fn print_settings(settings: &PipelineSettings, assemblers: &[String]) {
+    let PipelineSettings { ref out_dir, ref reads, ref assemblies_dir, ref genome_size, count,
+                           ref read_type, threads, kmer: k_size, gfa_version, .. } = *settings;
+    eprintln!("Settings:");
+    eprintln!("  --out_dir {}", out_dir.display());
+    if let Some(reads) = reads {
+        eprintln!("  --reads {}", reads.display());
+        eprintln!("  --genome_size {}", genome_size.as_deref().unwrap_or(""));
+        eprintln!("  --assemblers {}", assemblers.join(","));
+        eprintln!("  --count {}", count);
+        eprintln!("  --read_type {}", read_type);
+    }
+    if let Some(assemblies_dir) = assemblies_dir {
+        eprintln!("  --assemblies_dir {}", assemblies_dir.display());
+    }
+    eprintln!("  --threads {}", threads);
+    eprintln!("  --kmer {}", k_size);
+    eprintln!("  --gfa_version {}", gfa_version);
+    eprintln!();
+}
+
+
+fn finished_message(start_time: Instant, autocycler_dir: &Path, metrics_tsv: &Path) {
+    section_header("Finished pipeline!");
+    eprintln!("Consensus assembly: {}", autocycler_dir.join("consensus_assembly.fasta").display());
+    eprintln!("Pipeline metrics:   {}", metrics_tsv.display());
+    eprintln!("Time to run: {}", format_duration(start_time.elapsed()));
+    eprintln!();
+}
+
+
+fn parse_assemblers(assemblers: &str) -> Vec<String> {
+    assemblers.split(',').map(|a| a.trim().to_lowercase()).filter(|a| !a.is_empty()).collect()
+}
+
+
+fn run_assemblers(reads_dir: &Path, assemblies_dir: &Path, assemblers: &[String], count: usize,
+                  genome_size: &str, read_type: &str, threads: usize) {
+    // Each assembler is run on each read subset using autocycler helper, in its own process so
+    // an assembler failure doesn't stop the pipeline. The failed assembly is just left out.
+    section_header("Assembling read subsets");
+    explanation("Each subsampled read set is now assembled with each assembler.");
+    create_dir(assemblies_dir);
+    let exe = match std::env::current_exe() {
+        Ok(exe) => exe,
+        Err(e) => quit_with_error(&format!("unable to find the autocycler executable\n{}", e)),
+    };
+    let mut failures = 0;
+    for assembler in assemblers {
+        for i in 1..=count {
+            let reads = reads_dir.join(format!("sample_{:02}.fastq", i));
+            let out_prefix = assemblies_dir.join(format!("{}_{:02}", assembler, i));
+            let status = Command::new(&exe).arg("helper").arg(assembler)
+                .arg("--reads").arg(&reads).arg("--out_prefix").arg(&out_prefix)
+                .arg("--genome_size").arg(genome_size).arg("--read_type").arg(read_type)
+                .arg("--threads").arg(threads.to_string()).status();
+            if !status.is_ok_and(|s| s.success()) {
+                eprintln!("Warning: {} failed on {}", assembler, reads.display());
+                failures += 1;
+            }
+        }
+    }
+    eprintln!();
+    if find_all_assemblies(assemblies_dir).is_empty() {
+        quit_with_error("all assemblies failed");
+    }
+    if failures > 0 {
+        eprintln!("{} assembl{} failed and will not be used", failures,
+                  match failures { 1 => "y", _ => "ies" });
+        eprintln!();
+    }
+}
+
+
+fn save_metrics(out_dir: &Path, metrics_tsv: &Path) {
+    // Gathers the metrics from all steps (the subsample and Autocycler directories are both in
+    // out_dir) into a one-sample table, as made by autocycler table.
+    let fields = parse_fields(DEFAULT_FIELDS.to_string());
+    let name = out_dir.canonicalize().unwrap_or(out_dir.to_path_buf()).file_name()
+        .map(|n| n.to_string_lossy().to_string()).unwrap_or_default();
+    let table = format!("{}\n{}\n", get_header_line(&fields),
+                        get_values_line(out_dir, &name, &fields, 3));
+    if let Err(e) = fs::write(metrics_tsv, table) {
+        quit_with_error(&format!("unable to write {}\n{}", metrics_tsv.display(), e));
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::misc::load_fasta;
+    use crate::simulate::{simulate, SimulateSettings};
+
+    #[test]
+    fn test_parse_assemblers() {
+        assert_eq!(parse_assemblers("flye, Raven,,miniasm"), vec!["flye", "raven", "miniasm"]);
+        assert!(parse_assemblers("").is_empty());
+    }
+
+    fn test_settings(out_dir: &Path, reads: &Option<PathBuf>, assemblies_dir: &Option<PathBuf>,
+                     genome_size: Option<&str>, assemblers: &str) -> PipelineSettings {
+        PipelineSettings { out_dir: out_dir.to_path_buf(), reads: reads.clone(),
+                           assemblies_dir: assemblies_dir.clone(),
+                           genome_size: genome_size.map(|g| g.to_string()),
+                           assemblers: assemblers.to_string(), count: 4,
+                           read_type: "ont_r10".to_string(), threads: 8, kmer: 51, gfa_version: 1 }
+    }
+
+    #[test]
+    fn test_check_settings() {
+        let dir = tempfile::tempdir().unwrap();
+        let out_dir = dir.path().join("out");
+        let reads = Some(dir.path().join("reads.fastq"));
+        let assemblies = Some(dir.path().to_path_buf());
+        fs::write(reads.as_ref().unwrap(), "").unwrap();
+        check_settings(&test_settings(&out_dir, &None, &assemblies, None, ""));
+        check_settings(&test_settings(&out_dir, &reads, &None, Some("5m"), "flye"));
+        for (reads, assemblies, genome_size, assemblers) in
+                [(&reads, &assemblies, Some("5m"), "flye"),
+                 (&None, &None, None, "flye"),
+                 (&reads, &None, None, "flye"),
+                 (&reads, &None, Some("5m"), "spades")] {
+            assert!(std::panic::catch_unwind(|| {
+                check_settings(&test_settings(&out_dir, reads, assemblies, genome_size,
+                                              assemblers));
+            }).is_err());
+        }
+    }
+
+    #[test]
+    fn test_pipeline_from_assemblies() {
+        // Simulated assemblies of a chromosome and a plasmid, without mis-joins, should give a
+        // consensus with both replicons.
+        let dir = tempfile::tempdir().unwrap();
+        let sim_dir = dir.path().join("sim");
+        simulate(SimulateSettings { out_dir: sim_dir.clone(), genome_size: "20k".to_string(),
+                                    plasmids: "3k".to_string(), repeats: 0, repeat_length: 0,
+                                    count: 4, snps: 2, indels: 2, misjoins: 0, seed: 0 });
+        let out_dir = dir.path().join("out");
+        let assemblies_dir = Some(sim_dir.join("assemblies"));
+        pipeline(PipelineSettings { threads: 1,
+                                    ..test_settings(&out_dir, &None, &assemblies_dir, None, "") });
+
+        let consensus = load_fasta(&out_dir.join("autocycler_out/consensus_assembly.fasta"));
+        let mut lengths: Vec<usize> = consensus.iter().map(|(_, _, seq)| seq.len()).collect();
+        lengths.sort();
+        assert_eq!(lengths.len(), 2);
+        assert!(lengths[0].abs_diff(3000) < 50 && lengths[1].abs_diff(20000) < 50);
+
+        let metrics = fs::read_to_string(out_dir.join("metrics.tsv")).unwrap();
+        let lines: Vec<&str> = metrics.lines().collect();
+        assert_eq!(lines.len(), 2);
+        assert!(lines[0].starts_with("name\tinput_read_count"));
+        assert!(lines[1].starts_with("out\t"));
+    }
+}
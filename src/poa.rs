@@ -0,0 +1,199 @@
+// This file contains a simple partial-order alignment (POA), used to build a consensus from
+// several alternative versions of a short sequence.
+
+// Copyright 2024 Ryan Wick (rrwick@gmail.com)
+// https://github.com/rrwick/Autocycler
+
+// This file is part of Autocycler. Autocycler is free software: you can redistribute it and/or
+// modify it under the terms of the GNU General Public License as published by the Free Software
+// Foundation, either version 3 of the License, or (at your option) any later version. Autocycler
+// is distributed in the hope that it will be useful, but WITHOUT ANY WARRANTY; without even the
+// implied warranty of MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the GNU General
+// Public License for more details. You should have received a copy of the GNU General Public
+// License along with Autocycler. If not, see <http://www.gnu.org/licenses/>.
+
+const MATCH: i32 = 1;
+const MISMATCH: i32 = -2;
+const GAP: i32 = -2;
+
+
+pub fn poa_consensus(seqs: &[(&[u8], f64)]) -> Vec<u8> {
+    // Aligns the sequences (each with a weight) into a partial-order graph, in the order given,
+    // and returns the heaviest path through it. Putting the best-supported sequence first makes
+    // it the backbone of the alignment.
+    let mut graph = PoaGraph::default();
+    for (seq, weight) in seqs {
+        graph.add_sequence(seq, *weight);
+    }
+    graph.consensus()
+}
+
+
+#[derive(Default)]
+struct PoaGraph {
+    bases: Vec<u8>,
+    next: Vec<Vec<(usize, f64)>>,  // outgoing edges and their weights
+    prev: Vec<Vec<usize>>,
+}
+
+impl PoaGraph {
+    fn add_node(&mut self, base: u8) -> usize {
+        self.bases.push(base);
+        self.next.push(Vec::new());
+        self.prev.push(Vec::new());
+        self.bases.len() - 1
+    }
+
+    fn add_edge(&mut self, a: usize, b: usize, weight: f64) {
+        if let Some(edge) = self.next[a].iter_mut().find(|(n, _)| *n == b) {
+            edge.1 += weight;
+        } else {
+            self.next[a].push((b, weight));
+            self.prev[b].push(a);
+        }
+    }
+
+    fn topological_order(&self) -> Vec<usize> {
+        let mut in_degree: Vec<usize> = self.prev.iter().map(|p| p.len()).collect();
+        let mut stack: Vec<usize> = (0..self.bases.len()).rev().filter(|&i| in_degree[i] == 0)
+                                                                 .collect();
+        let mut order = Vec::with_capacity(self.bases.len());
+        while let Some(i) = stack.pop() {
+            order.push(i);
+            for &(n, _) in self.next[i].iter().rev() {
+                in_degree[n] -= 1;
+                if in_degree[n] == 0 { stack.push(n); }
+            }
+        }
+        order
+    }
+
+    fn add_sequence(&mut self, seq: &[u8], weight: f64) {
+        if seq.is_empty() { return; }
+        let path = if self.bases.is_empty() {
+            seq.iter().map(|&b| self.add_node(b)).collect()
+        } else {
+            self.align(seq)
+        };
+        for w in path.windows(2) {
+            self.add_edge(w[0], w[1], weight);
+        }
+    }
+
+    fn align(&mut self, seq: &[u8]) -> Vec<usize> {
+        // Globally aligns the sequence to the graph and returns the node for each of its bases,
+        // reusing nodes where the bases match and adding new nodes elsewhere.
+        let order = self.topological_order();
+        let mut row = vec![0; self.bases.len()];
+        for (r, &i) in order.iter().enumerate() { row[i] = r + 1; }
+        let n = seq.len();
+
+        // Row 0 is a virtual start node which precedes every node without a predecessor.
+        let mut scores = vec![vec![i32::MIN / 2; n + 1]; order.len() + 1];
+        for (j, score) in scores[0].iter_mut().enumerate() { *score = j as i32 * GAP; }
+        let preds = |i: usize| -> Vec<usize> {
+            if self.prev[i].is_empty() { vec![0] }
+            else { self.prev[i].iter().map(|&p| row[p]).collect() }
+        };
+        for &i in &order {
+            let r = row[i];
+            for j in 0..=n {
+                let mut best = i32::MIN / 2;
+                for p in preds(i) {
+                    best = best.max(scores[p][j] + GAP);
+                    if j > 0 {
+                        let s = if seq[j - 1] == self.bases[i] { MATCH } else { MISMATCH };
+                        best = best.max(scores[p][j - 1] + s);
+                    }
+                }
+                if j > 0 { best = best.max(scores[r][j - 1] + GAP); }
+                scores[r][j] = best;
+            }
+        }
+
+        // The alignment ends at whichever sink node scores best for the whole sequence.
+        let mut r = order.iter().filter(|&&i| self.next[i].is_empty()).map(|&i| row[i])
+                         .max_by_key(|&r| (scores[r][n], std::cmp::Reverse(r))).unwrap();
+        let mut j = n;
+        let mut nodes: Vec<Option<usize>> = vec![None; n];
+        while j > 0 && r > 0 {
+            let i = order[r - 1];
+            let score = scores[r][j];
+            let s = if seq[j - 1] == self.bases[i] { MATCH } else { MISMATCH };
+            if let Some(p) = preds(i).into_iter().find(|&p| scores[p][j - 1] + s == score) {
+                if s == MATCH { nodes[j - 1] = Some(i); }
+                r = p;
+                j -= 1;
+            } else if let Some(p) = preds(i).into_iter().find(|&p| scores[p][j] + GAP == score) {
+                r = p;
+            } else {
+                j -= 1;
+            }
+        }
+
+        // Unmatched bases get new nodes.
+        nodes.into_iter().zip(seq).map(|(node, &b)| node.unwrap_or_else(|| self.add_node(b)))
+             .collect()
+    }
+
+    fn consensus(&self) -> Vec<u8> {
+        // Finds the heaviest path, as in SPOA: each node follows its heaviest incoming edge (with
+        // ties going to the higher-scoring predecessor), so longer paths through rare insertions
+        // don't win just by having more edges.
+        let order = self.topological_order();
+        let mut scores = vec![0.0; self.bases.len()];
+        let mut best_prev: Vec<Option<usize>> = vec![None; self.bases.len()];
+        for &i in &order {
+            let mut best_weight = 0.0;
+            for &p in &self.prev[i] {
+                let weight = self.next[p].iter().find(|(n, _)| *n == i).unwrap().1;
+                if best_prev[i].is_none() || weight > best_weight ||
+                   (weight == best_weight && scores[p] > scores[best_prev[i].unwrap()]) {
+                    best_weight = weight;
+                    best_prev[i] = Some(p);
+                }
+            }
+            if let Some(p) = best_prev[i] { scores[i] = scores[p] + best_weight; }
+        }
+        let Some(mut i) = order.iter().copied().filter(|&i| self.next[i].is_empty())
+                               .reduce(|a, b| if scores[b] > scores[a] { b } else { a })
+        else { return Vec::new(); };
+        let mut consensus = vec![self.bases[i]];
+        while let Some(p) = best_prev[i] {
+            consensus.push(self.bases[p]);
+            i = p;
+        }
+        consensus.reverse();
+        consensus
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn consensus(seqs: &[(&str, f64)]) -> String {
+        let seqs: Vec<(&[u8], f64)> = seqs.iter().map(|(s, w)| (s.as_bytes(), *w)).collect();
+        String::from_utf8(poa_consensus(&seqs)).unwrap()
+    }
+
+    #[test]
+    fn test_poa_consensus() {
+        assert_eq!(consensus(&[]), "");
+        assert_eq!(consensus(&[("ACGTACGT", 1.0)]), "ACGTACGT");
+        assert_eq!(consensus(&[("ACGTACGT", 1.0), ("ACGTACGT", 1.0)]), "ACGTACGT");
+
+        // Each sequence has a different error, so the consensus matches none of them.
+        assert_eq!(consensus(&[("ACGTTCGTAC", 1.0), ("ACCTACGTAC", 1.0), ("ACGTACGTTC", 1.0)]),
+                   "ACGTACGTAC");
+
+        // Insertions and deletions.
+        assert_eq!(consensus(&[("ACGTAACGT", 1.0), ("ACGTACGT", 1.0), ("ACGACGT", 1.0),
+                               ("ACGTACGT", 1.0)]), "ACGTACGT");
+
+        // Weights can outvote a majority.
+        assert_eq!(consensus(&[("ACGTACGT", 1.0), ("ACGTTCGT", 3.0), ("ACGTACGT", 1.0)]),
+                   "ACGTTCGT");
+    }
+}
@@ -0,0 +1,203 @@
+// This file contains the code for the autocycler purge subcommand.
+
+// Copyright 2024 Ryan Wick (rrwick@gmail.com)
+// https://github.com/rrwick/Autocycler
+
+// This file is part of Autocycler. Autocycler is free software: you can redistribute it and/or
+// modify it under the terms of the GNU General Public License as published by the Free Software
+// Foundation, either version 3 of the License, or (at your option) any later version. Autocycler
+// is distributed in the hope that it will be useful, but WITHOUT ANY WARRANTY; without even the
+// implied warranty of MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the GNU General
+// Public License for more details. You should have received a copy of the GNU General Public
+// License along with Autocycler. If not, see <http://www.gnu.org/licenses/>.
+
+use std::collections::BTreeSet;
+use std::path::{Path, PathBuf};
+
+use crate::graph_simplification::simplify_structure;
+use crate::log::{section_header, explanation};
+use crate::metrics::{InputAssemblyMetrics, SimplificationMetrics};
+use crate::misc::{check_gfa_version, check_if_dir_exists, check_if_file_exists, quit_with_error};
+use crate::sequence::Sequence;
+use crate::unitig_graph::UnitigGraph;
+
+
+pub fn purge(autocycler_dir: PathBuf, assemblies: String, gfa_version: u32) {
+    let gfa = autocycler_dir.join("input_assemblies.gfa");
+    let yaml = autocycler_dir.join("input_assemblies.yaml");
+    check_settings(&autocycler_dir, &gfa, gfa_version);
+    let assemblies = parse_assembly_names(&assemblies);
+    starting_message();
+    print_settings(&autocycler_dir, &assemblies, gfa_version);
+    let (mut graph, sequences) = load_graph(&gfa);
+
+    section_header("Purging assemblies");
+    explanation("Sequences from the purged assemblies are now removed from the graph, along with \
+                 any unitigs which only they used.");
+    let mut sequences = purge_assemblies(&mut graph, &sequences, &assemblies);
+    eprintln!();
+
+    section_header("Simplifying graph");
+    explanation("The graph is now re-simplified: linear paths left by the removed unitigs are \
+                 merged and sequence is moved into repeat unitigs when possible.");
+    graph.merge_linear_paths(&sequences);
+    simplify_structure(&mut graph, &mut sequences, 0, 0.0, &mut SimplificationMetrics::default());
+    graph.estimate_multiplicities(&sequences);
+    graph.print_basic_graph_info();
+
+    if let Err(e) = graph.save_gfa_with_version(&gfa, &sequences, gfa_version) {
+        quit_with_error(&format!("unable to write {}\n{}", gfa.display(), e));
+    }
+    if yaml.is_file() {
+        update_metrics(&yaml, &assemblies, &sequences, &graph);
+    }
+    finished_message(&gfa, &yaml);
+}
+
+
+fn check_settings(autocycler_dir: &Path, gfa: &Path, gfa_version: u32) {
+    check_if_dir_exists(autocycler_dir);
+    check_if_file_exists(gfa);
+    check_gfa_version(gfa_version);
+}
+
+
+fn starting_message() {
+    section_header("Starting autocycler purge");
+    explanation("This command removes one or more input assemblies from an existing compressed \
+                 graph (input_assemblies.gfa), so they can be excluded without rerunning \
+                 autocycler compress.");
+}
+
+
+fn print_settings(autocycler_dir: &Path, assemblies: &[String], gfa_version: u32) {
+    eprintln!("Settings:");
+    eprintln!("  --autocycler_dir {}", autocycler_dir.display());
+    eprintln!("  --assemblies {}", assemblies.join(","));
+    eprintln!("  --gfa_version {}", gfa_version);
+    eprintln!();
+}
+
+
+fn finished_message(gfa: &Path, yaml: &Path) {
+    section_header("Finished!");
+    explanation("Any existing clustering is now out of date, so you should rerun autocycler \
+                 cluster and the steps after it.");
+    eprintln!("Compressed unitig graph: {}", gfa.display());
+    if yaml.is_file() {
+        eprintln!("Input assembly stats:    {}", yaml.display());
+    }
+    eprintln!();
+}
+
+
+fn load_graph(gfa: &Path) -> (UnitigGraph, Vec<Sequence>) {
+    section_header("Loading graph");
+    explanation("The compressed unitig graph is now loaded into memory.");
+    let (graph, sequences) = UnitigGraph::from_gfa_file(gfa);
+    graph.print_basic_graph_info();
+    (graph, sequences)
+}
+
+
+fn parse_assembly_names(assemblies: &str) -> Vec<String> {
+    let names: Vec<String> = assemblies.split(',').map(|a| a.trim().to_string())
+                                       .filter(|a| !a.is_empty()).collect();
+    if names.is_empty() { quit_with_error("--assemblies must name at least one assembly"); }
+    names
+}
+
+
+fn purge_assemblies(graph: &mut UnitigGraph, sequences: &[Sequence], assemblies: &[String])
+        -> Vec<Sequence> {
+    // Removes the named assemblies' sequences from the graph and deletes unitigs which are left
+    // with zero depth. Returns the remaining sequences.
+    let filenames: BTreeSet<&str> = sequences.iter().map(|s| s.filename.as_str()).collect();
+    for assembly in assemblies {
+        if !filenames.contains(assembly.as_str()) {
+            quit_with_error(&format!("no input assembly named {} (available: {})", assembly,
+                                     filenames.iter().copied().collect::<Vec<_>>().join(", ")));
+        }
+    }
+    if assemblies.len() == filenames.len() {
+        quit_with_error("cannot purge all input assemblies");
+    }
+    let (purged, kept): (Vec<&Sequence>, Vec<&Sequence>) =
+        sequences.iter().partition(|s| assemblies.contains(&s.filename));
+    for seq in &purged {
+        graph.remove_sequence_from_graph(seq.id);
+    }
+    graph.recalculate_depths();
+    let unitig_count = graph.unitigs.len();
+    graph.remove_zero_depth_unitigs();
+    let removed = unitig_count - graph.unitigs.len();
+    eprintln!("Removed {} sequence{} from {} assembl{}", purged.len(),
+              match purged.len() { 1 => "", _ => "s" }, assemblies.len(),
+              match assemblies.len() { 1 => "y", _ => "ies" });
+    eprintln!("Removed {} unitig{} left without sequences", removed,
+              match removed { 1 => "", _ => "s" });
+    kept.into_iter().cloned().collect()
+}
+
+
+fn update_metrics(yaml: &Path, assemblies: &[String], sequences: &[Sequence],
+                  graph: &UnitigGraph) {
+    // Drops the purged assemblies from the compress metrics and updates the totals.
+    let mut metrics = InputAssemblyMetrics::load_from_yaml(yaml);
+    metrics.input_assembly_details.retain(|d| {
+        let filename = Path::new(&d.filename).file_name().map(|f| f.to_string_lossy());
+        !filename.is_some_and(|f| assemblies.iter().any(|a| *a == f))
+    });
+    metrics.input_assemblies_count = metrics.input_assembly_details.len() as u32;
+    metrics.input_assemblies_total_contigs = sequences.len() as u32;
+    metrics.input_assemblies_total_length = sequences.iter().map(|s| s.length as u64).sum();
+    metrics.compressed_unitig_count = graph.unitigs.len() as u32;
+    metrics.compressed_unitig_total_length = graph.total_length();
+    metrics.save_to_yaml(yaml);
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_gfa::*;
+
+    #[test]
+    fn test_parse_assembly_names() {
+        assert_eq!(parse_assembly_names("a.fasta"), vec!["a.fasta"]);
+        assert_eq!(parse_assembly_names("a.fasta, b.fasta,"), vec!["a.fasta", "b.fasta"]);
+        assert!(std::panic::catch_unwind(|| { parse_assembly_names(" , "); }).is_err());
+    }
+
+    #[test]
+    fn test_purge_assemblies() {
+        // Unitig 3 is only in b.fasta, so it goes when b.fasta is purged, and the remaining
+        // sequences are unchanged.
+        let (mut graph, sequences) = UnitigGraph::from_gfa_lines(&get_test_gfa_15());
+        let before = graph.reconstruct_original_sequences_u8(&sequences);
+        let mut sequences = purge_assemblies(&mut graph, &sequences, &["b.fasta".to_string()]);
+        assert_eq!(sequences.iter().map(|s| s.id).collect::<Vec<_>>(), vec![1, 3]);
+        assert!(!graph.unitig_index.contains_key(&3));
+        assert_eq!(graph.unitig(1).depth, 2.0);
+
+        graph.merge_linear_paths(&sequences);
+        simplify_structure(&mut graph, &mut sequences, 0, 0.0,
+                           &mut SimplificationMetrics::default());
+        assert_eq!(graph.unitigs.len(), 3);
+        assert!(graph.check_links().is_ok());
+        let after = graph.reconstruct_original_sequences_u8(&sequences);
+        assert_eq!(after, before.into_iter().filter(|((f, _), _)| f != "b.fasta")
+                                .collect::<Vec<_>>());
+    }
+
+    #[test]
+    fn test_purge_assemblies_bad_names() {
+        for names in [vec!["x.fasta".to_string()],
+                      vec!["a.fasta".to_string(), "b.fasta".to_string(), "c.fasta".to_string()]] {
+            assert!(std::panic::catch_unwind(|| {
+                let (mut graph, sequences) = UnitigGraph::from_gfa_lines(&get_test_gfa_15());
+                purge_assemblies(&mut graph, &sequences, &names);
+            }).is_err());
+        }
+    }
+}
@@ -0,0 +1,254 @@
+// This file contains code for threading long reads through a unitig graph. Each read (or
+// read-to-graph alignment) becomes a path of signed unitig numbers, which can then be used to
+// count read support for the different paths between two unitigs.
+
+// Copyright 2024 Ryan Wick (rrwick@gmail.com)
+// https://github.com/rrwick/Autocycler
+
+// This file is part of Autocycler. Autocycler is free software: you can redistribute it and/or
+// modify it under the terms of the GNU General Public License as published by the Free Software
+// Foundation, either version 3 of the License, or (at your option) any later version. Autocycler
+// is distributed in the hope that it will be useful, but WITHOUT ANY WARRANTY; without even the
+// implied warranty of MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the GNU General
+// Public License for more details. You should have received a copy of the GNU General Public
+// License along with Autocycler. If not, see <http://www.gnu.org/licenses/>.
+
+use seq_io::fastq::Record;
+use std::collections::HashMap;
+use std::path::Path;
+use xxhash_rust::xxh3::xxh3_64;
+
+use crate::misc::{fastq_reader, is_gaf_file, load_file_lines, quit_with_error, reverse_path};
+use crate::unitig_graph::UnitigGraph;
+
+
+// Reads are placed on unitigs using k-mers of this size which occur in only one place in the
+// graph. A unitig is only included in a read's path if at least READ_MIN_HITS of the read's
+// k-mers land on it, which filters out spurious hits caused by read errors.
+const READ_KMER_SIZE: usize = 21;
+const READ_MIN_HITS: usize = 2;
+
+
+pub fn load_read_paths(graph: &UnitigGraph, filename: &Path) -> Vec<Vec<i32>> {
+    // Loads read paths from either a GAF file of read-to-graph alignments (e.g. from GraphAligner)
+    // or a FASTQ file of reads, which are then threaded through the graph. Only paths with two or
+    // more unitigs are kept, since a read on a single unitig says nothing about junctions.
+    let paths = if is_gaf_file(filename) { load_gaf_paths(filename) }
+                                    else { thread_reads(graph, filename) };
+    paths.into_iter().filter(|p| p.len() > 1).collect()
+}
+
+
+fn load_gaf_paths(filename: &Path) -> Vec<Vec<i32>> {
+    load_file_lines(filename).iter().filter(|line| !line.is_empty()).map(|line| {
+        let path = line.split('\t').nth(5).unwrap_or_else(|| {
+            quit_with_error(&format!("GAF line has too few columns: {}", line));
+        });
+        parse_gaf_path(path).unwrap_or_else(|| {
+            quit_with_error(&format!("could not parse GAF path: {}", path));
+        })
+    }).collect()
+}
+
+
+fn parse_gaf_path(path: &str) -> Option<Vec<i32>> {
+    // GAF paths are oriented segment names, e.g. ">12<5>7". A path without orientation characters
+    // is a single segment on the forward strand. Segment names must be unitig numbers.
+    if !path.starts_with(['>', '<']) {
+        return path.parse::<i32>().ok().filter(|&n| n > 0).map(|n| vec![n]);
+    }
+    let mut unitigs = Vec::new();
+    let mut rest = path;
+    while !rest.is_empty() {
+        let sign = if rest.starts_with('>') { 1 } else { -1 };
+        rest = &rest[1..];
+        let end = rest.find(['>', '<']).unwrap_or(rest.len());
+        let num = rest[..end].parse::<i32>().ok().filter(|&n| n > 0)?;
+        unitigs.push(sign * num);
+        rest = &rest[end..];
+    }
+    Some(unitigs)
+}
+
+
+fn thread_reads(graph: &UnitigGraph, filename: &Path) -> Vec<Vec<i32>> {
+    let index = KmerIndex::new(graph, READ_KMER_SIZE);
+    let mut paths = Vec::new();
+    let mut reader = fastq_reader(filename);
+    while let Some(record) = reader.next() {
+        let record = record.unwrap_or_else(|e| {
+            quit_with_error(&format!("unable to load {}\n{}", filename.display(), e));
+        });
+        paths.push(thread_read(&record.seq().to_ascii_uppercase(), &index));
+    }
+    paths
+}
+
+
+pub struct KmerIndex<'a> {
+    // Maps k-mers to the one place in the graph they occur: a signed unitig and a position on
+    // that strand. K-mers which occur in more than one place (including twice in one unitig or on
+    // both strands) are left out. Only hashes are stored, so lookups check the k-mer's bases
+    // against the unitig, and a hash collision can't give a false hit.
+    graph: &'a UnitigGraph,
+    k_size: usize,
+    kmers: HashMap<u64, (i32, usize)>,
+}
+
+impl<'a> KmerIndex<'a> {
+    pub fn new(graph: &'a UnitigGraph, k_size: usize) -> Self {
+        let mut kmers: HashMap<u64, Option<(i32, usize)>> = HashMap::new();
+        for unitig in &graph.unitigs {
+            let num = unitig.number as i32;
+            for (seq, signed_num) in [(&unitig.forward_seq, num), (&unitig.reverse_seq, -num)] {
+                for (pos, kmer) in seq.windows(k_size).enumerate() {
+                    kmers.entry(xxh3_64(kmer)).and_modify(|h| *h = None)
+                         .or_insert(Some((signed_num, pos)));
+                }
+            }
+        }
+        let kmers = kmers.into_iter().filter_map(|(hash, h)| h.map(|h| (hash, h))).collect();
+        KmerIndex { graph, k_size, kmers }
+    }
+
+    pub fn get(&self, kmer: &[u8]) -> Option<(i32, usize)> {
+        let &(num, pos) = self.kmers.get(&xxh3_64(kmer))?;
+        let unitig = self.graph.unitig(num.unsigned_abs());
+        let seq = if num > 0 { &unitig.forward_seq } else { &unitig.reverse_seq };
+        (seq[pos..pos + self.k_size] == *kmer).then_some((num, pos))
+    }
+}
+
+
+fn thread_read(seq: &[u8], index: &KmerIndex) -> Vec<i32> {
+    // Turns a read into a path of the unitigs its k-mers land on, in read order.
+    let mut runs: Vec<(i32, usize)> = Vec::new();
+    for kmer in seq.windows(READ_KMER_SIZE) {
+        if let Some((n, _)) = index.get(kmer) {
+            match runs.last_mut() {
+                Some((last, count)) if *last == n => *count += 1,
+                _ => runs.push((n, 1)),
+            }
+        }
+    }
+    let mut path: Vec<i32> = Vec::new();
+    for (n, count) in runs {
+        if count >= READ_MIN_HITS && path.last() != Some(&n) {
+            path.push(n);
+        }
+    }
+    path
+}
+
+
+pub fn spanning_read_count(read_paths: &[Vec<i32>], start: i32, end: i32) -> usize {
+    // Counts the reads which go from the start unitig to the end unitig (on either strand).
+    read_paths.iter().filter(|p| read_path_between(p, start, end).is_some()).count()
+}
+
+
+pub fn path_support(read_paths: &[Vec<i32>], start: i32, end: i32, candidates: &[Vec<i32>])
+        -> Vec<usize> {
+    // Counts the reads which support each candidate path from start to end (candidates don't
+    // include the start and end unitigs). A read supports a candidate if the unitigs it has
+    // between start and end all occur, in order, in the candidate. Reads can miss short unitigs,
+    // so a read can fit more than one candidate, in which case it isn't counted.
+    let mut counts = vec![0; candidates.len()];
+    for read_path in read_paths {
+        let Some(between) = read_path_between(read_path, start, end) else { continue; };
+        let fits: Vec<usize> = candidates.iter().enumerate()
+            .filter(|(_, c)| is_subsequence(&between, c)).map(|(i, _)| i).collect();
+        if fits.len() == 1 {
+            counts[fits[0]] += 1;
+        }
+    }
+    counts
+}
+
+
+fn read_path_between(read_path: &[i32], start: i32, end: i32) -> Option<Vec<i32>> {
+    // Returns the part of the read path between start and end (exclusive), trying both strands.
+    for path in [read_path.to_vec(), reverse_path(read_path)] {
+        if let Some(i) = path.iter().position(|&n| n == start) {
+            if let Some(j) = path[i + 1..].iter().position(|&n| n == end) {
+                return Some(path[i + 1..i + 1 + j].to_vec());
+            }
+        }
+    }
+    None
+}
+
+
+fn is_subsequence(sub: &[i32], full: &[i32]) -> bool {
+    let mut full_iter = full.iter();
+    sub.iter().all(|n| full_iter.any(|m| m == n))
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_gaf_path() {
+        assert_eq!(parse_gaf_path(">12<5>7"), Some(vec![12, -5, 7]));
+        assert_eq!(parse_gaf_path("<3"), Some(vec![-3]));
+        assert_eq!(parse_gaf_path("8"), Some(vec![8]));
+        assert_eq!(parse_gaf_path(">a<5"), None);
+        assert_eq!(parse_gaf_path(">0"), None);
+    }
+
+    #[test]
+    fn test_kmer_index() {
+        let gfa: Vec<String> = vec!["H\tVN:Z:1.0\tKM:i:3",
+                                    "S\t1\tGCTAAAGACAATTACATAACA\tDP:f:1.00",
+                                    "S\t2\tACGTTAACGT\tDP:f:1.00"]
+            .into_iter().map(String::from).collect();
+        let (graph, _) = UnitigGraph::from_gfa_lines(&gfa);
+        let mut index = KmerIndex::new(&graph, 5);
+        assert_eq!(index.get(b"GCTAA"), Some((1, 0)));
+        assert_eq!(index.get(b"TAACA"), Some((1, 16)));
+        assert_eq!(index.get(b"TGTTA"), Some((-1, 0)));
+        assert_eq!(index.get(b"CCCCC"), None);
+        assert_eq!(index.get(b"ACGTT"), None);  // unitig 2 is its own reverse complement
+
+        // A k-mer whose hash matches but whose bases don't isn't a hit.
+        index.kmers.insert(xxh3_64(b"CCCCC"), (1, 0));
+        assert_eq!(index.get(b"CCCCC"), None);
+    }
+
+    #[test]
+    fn test_thread_read() {
+        let gfa: Vec<String> = vec!["H\tVN:Z:1.0\tKM:i:3",
+                                    "S\t1\tGCTAAAGACAATTACATAACATACACGTCA\tDP:f:1.00",
+                                    "S\t2\tGCACGAAACTTGTTGGCCCAGTGTGAATCG\tDP:f:1.00",
+                                    "S\t3\tCTTAAGGGTTAAGTAAGTGTGATGCATACG\tDP:f:1.00"]
+            .into_iter().map(String::from).collect();
+        let (graph, _) = UnitigGraph::from_gfa_lines(&gfa);
+        let index = KmerIndex::new(&graph, READ_KMER_SIZE);
+        let read = b"GCTAAAGACAATTACATAACATACACGTCACTTAAGGGTTAAGTAAGTGTGATGCATACG";
+        assert_eq!(thread_read(read, &index), vec![1, 3]);
+        assert_eq!(thread_read(&crate::misc::reverse_complement(read), &index), vec![-3, -1]);
+        assert_eq!(thread_read(b"GCACGAAACTTGTTGGCCCAGTG", &index), vec![2]);
+        assert!(thread_read(b"ACGT", &index).is_empty());
+    }
+
+    #[test]
+    fn test_path_support() {
+        let read_paths = vec![vec![1, 4, 5, 2], vec![1, 6, 2], vec![-2, -6, -1], vec![1, 2],
+                              vec![3, 1, 4], vec![-2, -5, -4, -1, 7]];
+        let candidates = vec![vec![4, 5], vec![6]];
+        assert_eq!(spanning_read_count(&read_paths, 1, 2), 5);
+        assert_eq!(path_support(&read_paths, 1, 2, &candidates), vec![2, 2]);
+        assert_eq!(path_support(&read_paths, 1, 2, &[vec![4, 5], vec![7]]), vec![2, 0]);
+        assert_eq!(spanning_read_count(&read_paths, 2, 1), 0);
+    }
+
+    #[test]
+    fn test_is_subsequence() {
+        assert!(is_subsequence(&[], &[1, 2]));
+        assert!(is_subsequence(&[1, 3], &[1, 2, 3]));
+        assert!(!is_subsequence(&[3, 1], &[1, 2, 3]));
+        assert!(!is_subsequence(&[4], &[1, 2, 3]));
+    }
+}
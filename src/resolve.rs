@@ -12,64 +12,204 @@
 // License along with Autocycler. If not, see <http://www.gnu.org/licenses/>.
 
 use colored::Colorize;
-use std::cell::RefCell;
 use std::cmp::Ordering;
 use std::collections::{HashMap, HashSet};
 use std::fmt;
+use std::fs::File;
+use std::io::Write;
 use std::path::{Path, PathBuf};
-use std::rc::Rc;
 
 use crate::graph_simplification::merge_linear_paths;
 use crate::log::{section_header, explanation};
-use crate::misc::{check_if_dir_exists, check_if_file_exists, reverse_path, load_file_lines,
-                  sign_at_end, sign_at_end_vec};
+use crate::metrics::{AlternativeDetails, AmbiguityDetails, AmbiguityReport,
+                     InvertedRepeatDetails};
+use crate::misc::{check_gfa_version, check_if_dir_exists, check_if_file_exists, reverse_path,
+                  load_file_lines, quit_with_error, sign_at_end, sign_at_end_vec, format_float,
+                  write_fasta_seq};
+use crate::read_threading::{load_read_paths, path_support, spanning_read_count};
 use crate::sequence::Sequence;
-use crate::unitig::Unitig;
+use crate::unitig::{Confidence, InvertedRepeat, Unitig};
 use crate::unitig_graph::UnitigGraph;
 
 
-pub fn resolve(cluster_dir: PathBuf, verbose: bool) {
+// When long reads are given, they only override the input assemblies if the read-supported choice
+// has at least this many reads.
+const MIN_READ_SUPPORT: usize = 3;
+
+// Alternative sequences in the ambiguity FASTA include this much of each anchor, to give context.
+const ALT_FLANK_SIZE: usize = 100;
+
+// The ambiguity report also lists up to this many paths per bridge which no assembly took.
+const MAX_UNTAKEN_PATHS: usize = 10;
+
+// Inverted repeats within a unitig are only flagged if at least this long.
+const INVERTED_REPEAT_MIN_LENGTH: usize = 1000;
+
+// In iterative mode, conflicting bridges are culled in rounds. A bridge is only culled in a round
+// if a bridge it conflicts with has at least this many times its depth. Rounds go from strict to
+// relaxed, finishing with the --min_ratio round.
+const ITERATIVE_RATIOS: [f64; 3] = [4.0, 2.0, 1.5];
+
+
+pub struct ResolveSettings {
+    // The settings for autocycler resolve, named as their options.
+    pub cluster_dir: PathBuf,
+    pub reads: Option<PathBuf>,
+    pub tie_break: String,
+    pub trusted: Option<String>,
+    pub weighting: String,
+    pub bubble_support: Option<f64>,
+    pub linear: bool,
+    pub iterative: bool,
+    pub min_ratio: Option<f64>,
+    pub dry_run: bool,
+    pub verbose: bool,
+    pub gfa_version: u32,
+}
+
+impl ResolveSettings {
+    pub fn new(cluster_dir: PathBuf, gfa_version: u32) -> Self {
+        // Makes settings with the option defaults.
+        ResolveSettings {
+            cluster_dir, reads: None, tie_break: "depth".to_string(), trusted: None,
+            weighting: "assembly".to_string(), bubble_support: None, linear: false,
+            iterative: false, min_ratio: None, dry_run: false, verbose: false, gfa_version,
+        }
+    }
+}
+
+
+pub fn resolve(settings: ResolveSettings) {
+    let cluster_dir = &settings.cluster_dir;
     let trimmed_gfa = cluster_dir.join("2_trimmed.gfa");
     let bridged_gfa = cluster_dir.join("3_bridged.gfa");
     let merged_gfa = cluster_dir.join("4_merged.gfa");
     let final_gfa = cluster_dir.join("5_final.gfa");
+    let ties_tsv = cluster_dir.join("tie_breaks.tsv");
+    let ambiguity_yaml = cluster_dir.join("ambiguities.yaml");
+    let ambiguity_fasta = cluster_dir.join("ambiguities.fasta");
+    let confidence_bed = cluster_dir.join("confidence.bed");
+    let rounds_tsv = cluster_dir.join("resolve_rounds.tsv");
 
-    check_settings(&cluster_dir, &trimmed_gfa);
+    check_settings(&settings, &trimmed_gfa);
     starting_message();
-    print_settings(&cluster_dir, verbose);
+    print_settings(&settings);
+    let ResolveSettings { ref reads, ref tie_break, ref trusted, ref weighting, bubble_support,
+                          linear, iterative, min_ratio, dry_run, verbose, gfa_version,
+                          .. } = settings;
 
     let gfa_lines = load_file_lines(&trimmed_gfa);
     let (mut unitig_graph, sequences) = load_graph(&gfa_lines, true, None);
 
     let anchors = find_anchor_unitigs(&mut unitig_graph, &sequences);
+    let linear_ends = choose_linear_ends(&unitig_graph, &sequences, &anchors, linear);
+    let tie_breaker = TieBreaker::new(tie_break, trusted, &unitig_graph, &sequences, &anchors);
     let mut bridges = create_bridges(&unitig_graph, &sequences, &anchors);
+    if weighting == "depth" {
+        if depth_weights_set(&sequences) {
+            weight_bridge_paths(&mut bridges, verbose);
+        } else {
+            eprintln!("Warning: --weighting depth has no effect because the graph's sequences \
+                       have no depth weights (see autocycler compress --weights)");
+            eprintln!();
+        }
+    }
+    if let Some(reads) = reads {
+        let read_paths = load_reads(&unitig_graph, reads);
+        add_read_support(&mut bridges, &read_paths, verbose);
+    }
+    let mut ties = break_path_ties(&mut bridges, &tie_breaker);
+    if let Some(bubble_support) = bubble_support {
+        mark_bubble_bridges(&mut bridges, bubble_support, verbose);
+    }
     let bridge_depth = sequences.len() as f64;
     determine_ambiguity(&mut bridges);
     print_bridges(&bridges, verbose);
+    let (mut report, alt_seqs) = ambiguity_report(&unitig_graph, &bridges);
 
     apply_unique_message();
-    apply_bridges(&mut unitig_graph, &bridges, bridge_depth);
-    unitig_graph.save_gfa(&bridged_gfa, &vec![]).unwrap();
+    apply_bridges(&mut unitig_graph, &bridges, &linear_ends, bridge_depth);
+    if !dry_run {
+        unitig_graph.save_gfa_with_version(&bridged_gfa, &[], gfa_version).unwrap();
+    }
     merge_after_bridging(&mut unitig_graph, bridge_depth);
-    unitig_graph.save_gfa(&merged_gfa, &vec![]).unwrap();
+    if !dry_run {
+        unitig_graph.save_gfa_with_version(&merged_gfa, &[], gfa_version).unwrap();
+    }
 
-    let cull_count = cull_ambiguity(&mut bridges, verbose);
+    let conflicting: Vec<_> = bridges.iter().filter(|b| b.conflicting)
+                                     .map(|b| path_string(b.start, &b.best_path, b.end)).collect();
+    let cull_count = if iterative {
+        let min_ratio = min_ratio.unwrap_or(1.0);
+        let rounds = cull_ambiguity_iteratively(&mut bridges, &tie_breaker, &mut ties, min_ratio,
+                                                verbose);
+        if !dry_run {
+            save_rounds(&rounds, &rounds_tsv);
+        }
+        rounds.len()
+    } else {
+        cull_ambiguity(&mut bridges, &tie_breaker, &mut ties, verbose)
+    };
     if cull_count > 0 {
         (unitig_graph, _) = load_graph(&gfa_lines, false, Some(&anchors));
         apply_final_message();
-        apply_bridges(&mut unitig_graph, &bridges, bridge_depth);
+        apply_bridges(&mut unitig_graph, &bridges, &linear_ends, bridge_depth);
         merge_after_bridging(&mut unitig_graph, bridge_depth);
+    } else if bridges.iter().any(|b| b.conflicting) {
+        eprintln!("No conflicting bridges were culled, so they are left unresolved.\n");
     } else {
         eprintln!("All bridges were unique, no culling necessary.\n");
     }
-    unitig_graph.save_gfa(&final_gfa, &vec![]).unwrap();
-    finished_message(&final_gfa);
+    flag_inverted_repeats(&mut unitig_graph, &mut report);
+    if dry_run {
+        let remaining: HashSet<_> = bridges.iter()
+            .map(|b| path_string(b.start, &b.best_path, b.end)).collect();
+        let culled: Vec<_> = conflicting.into_iter().filter(|b| !remaining.contains(b)).collect();
+        let (original_graph, _) = load_graph(&gfa_lines, false, None);
+        print_dry_run_plan(&dry_run_plan(&original_graph, &bridges, &culled,
+                                         unitig_graph.unitigs.len()));
+        return;
+    }
+    unitig_graph.save_gfa_with_version(&final_gfa, &[], gfa_version).unwrap();
+    save_ties(&ties, &ties_tsv);
+    mark_culled_bridges(&mut report, &bridges);
+    report.save_to_yaml(&ambiguity_yaml);
+    save_alternative_seqs(&report, &alt_seqs, &ambiguity_fasta);
+    save_confidence_bed(&unitig_graph, &confidence_bed);
+    finished_message(&final_gfa, &ties_tsv, &ambiguity_yaml, &ambiguity_fasta, &confidence_bed);
 }
 
 
-fn check_settings(cluster_dir: &Path, trimmed_gfa: &Path) {
+fn check_settings(settings: &ResolveSettings, trimmed_gfa: &Path) {
+    let ResolveSettings { ref cluster_dir, ref reads, ref tie_break, ref trusted, ref weighting,
+                          bubble_support, iterative, min_ratio, gfa_version, .. } = *settings;
     check_if_dir_exists(cluster_dir);
     check_if_file_exists(trimmed_gfa);
+    if let Some(reads) = reads {
+        check_if_file_exists(reads);
+    }
+    if !["depth", "length", "trusted"].contains(&tie_break.as_str()) {
+        quit_with_error("--tie_break must be depth, length or trusted");
+    }
+    if tie_break == "trusted" && trusted.is_none() {
+        quit_with_error("--tie_break trusted requires --trusted");
+    }
+    if tie_break != "trusted" && trusted.is_some() {
+        quit_with_error("--trusted can only be used with --tie_break trusted");
+    }
+    if !["assembly", "depth"].contains(&weighting.as_str()) {
+        quit_with_error("--weighting must be assembly or depth");
+    }
+    if bubble_support.is_some_and(|s| !(0.0..=1.0).contains(&s)) {
+        quit_with_error("--bubble_support must be between 0.0 and 1 (inclusive)");
+    }
+    if min_ratio.is_some() && !iterative {
+        quit_with_error("--min_ratio can only be used with --iterative");
+    }
+    if min_ratio.is_some_and(|r| r < 1.0) {
+        quit_with_error("--min_ratio cannot be less than 1");
+    }
+    check_gfa_version(gfa_version);
 }
 
 
@@ -92,19 +232,49 @@ fn apply_final_message() {
 }
 
 
-fn finished_message(final_gfa: &Path) {
+fn finished_message(final_gfa: &Path, ties_tsv: &Path, ambiguity_yaml: &Path,
+                    ambiguity_fasta: &Path, confidence_bed: &Path) {
     section_header("Finished!");
-    eprintln!("Final consensus graph: {}", final_gfa.display());
+    eprintln!("Final consensus graph:  {}", final_gfa.display());
+    eprintln!("Tie-break decisions:    {}", ties_tsv.display());
+    eprintln!("Ambiguity report:       {}", ambiguity_yaml.display());
+    eprintln!("Alternative sequences:  {}", ambiguity_fasta.display());
+    eprintln!("Confidence track:       {}", confidence_bed.display());
     eprintln!();
 }
 
 
-fn print_settings(cluster_dir: &Path, verbose: bool) {
+fn print_settings(settings: &ResolveSettings) {
+    let ResolveSettings { ref cluster_dir, ref reads, ref tie_break, ref trusted, ref weighting,
+                          bubble_support, linear, iterative, min_ratio, dry_run, verbose,
+                          gfa_version } = *settings;
     eprintln!("Settings:");
     eprintln!("  --cluster_dir {}", cluster_dir.display());
+    if let Some(reads) = reads {
+        eprintln!("  --reads {}", reads.display());
+    }
+    eprintln!("  --tie_break {}", tie_break);
+    if let Some(trusted) = trusted {
+        eprintln!("  --trusted {}", trusted);
+    }
+    eprintln!("  --weighting {}", weighting);
+    if let Some(bubble_support) = bubble_support {
+        eprintln!("  --bubble_support {}", format_float(bubble_support));
+    }
+    if linear {
+        eprintln!("  --linear");
+    }
+    if iterative {
+        eprintln!("  --iterative");
+        eprintln!("  --min_ratio {}", format_float(min_ratio.unwrap_or(1.0)));
+    }
+    if dry_run {
+        eprintln!("  --dry_run");
+    }
     if verbose {
         eprintln!("  --verbose");
     }
+    eprintln!("  --gfa_version {}", gfa_version);
     eprintln!();
 }
 
@@ -115,10 +285,14 @@ fn load_graph(gfa_lines: &Vec<String>, print_info: bool,
         section_header("Loading graph");
         explanation("The unitig graph is now loaded into memory.");
     }
-    let (unitig_graph, sequences) = UnitigGraph::from_gfa_lines(gfa_lines);
+    let (mut unitig_graph, sequences) = UnitigGraph::from_gfa_lines(gfa_lines);
+    if !sequences.is_empty() {
+        let assemblies: HashSet<&str> = sequences.iter().flat_map(|s| s.filenames()).collect();
+        unitig_graph.assembly_count = Some(assemblies.len() as u32);
+    }
     if let Some(anchors) = anchors {
-        for num in anchors {
-            unitig_graph.unitig_index.get(num).unwrap().borrow_mut().anchor = true;
+        for &num in anchors {
+            unitig_graph.unitig_mut(num).anchor = true;
         }
     }
     if print_info {
@@ -136,8 +310,7 @@ fn find_anchor_unitigs(graph: &mut UnitigGraph, sequences: &[Sequence]) -> Vec<u
     let mut all_seq_ids: Vec<_> = sequences.iter().map(|s| s.id).collect();
     all_seq_ids.sort();
     let mut anchor_ids = Vec::new();
-    for unitig_rc in &graph.unitigs {
-        let mut unitig = unitig_rc.borrow_mut();
+    for unitig in &mut graph.unitigs {
         let mut forward_seq_ids: Vec<_> = unitig.forward_positions.iter().map(|p| p.seq_id()).collect();
         forward_seq_ids.sort();
         if forward_seq_ids == all_seq_ids {
@@ -160,22 +333,471 @@ fn find_anchor_unitigs(graph: &mut UnitigGraph, sequences: &[Sequence]) -> Vec<u
 }
 
 
+fn choose_linear_ends(graph: &UnitigGraph, sequences: &[Sequence], anchors: &[u32],
+                      linear: bool) -> Vec<(Vec<i32>, f64)> {
+    // For a linear replicon, the parts of each sequence beyond its outermost anchors are real
+    // ends, not loose tips. Each end is given as a path from the dead end to its anchor, and for
+    // each anchor the most common end (ties going to the lower path) is chosen, along with the
+    // fraction of sequences which had it.
+    section_header("Checking for linear replicon");
+    explanation("Clusters where every sequence starts and ends at a dead end in the graph are \
+                 treated as linear replicons, and their ends are preserved.");
+    if linear {
+        eprintln!("Cluster marked as linear by --linear");
+    } else if graph.has_consistent_dead_ends(sequences) {
+        eprintln!("All sequences have consistent dead ends: cluster treated as linear");
+    } else {
+        eprintln!("Cluster not treated as linear\n");
+        return Vec::new();
+    }
+    let anchor_set: HashSet<u32> = anchors.iter().cloned().collect();
+    let mut end_counts: HashMap<i32, HashMap<Vec<i32>, usize>> = HashMap::new();
+    for path in graph.get_unitig_paths_for_sequences_i32(sequences) {
+        let anchor_indices: Vec<usize> = path.iter().enumerate()
+            .filter(|(_, u)| anchor_set.contains(&u.unsigned_abs())).map(|(i, _)| i).collect();
+        let (Some(&first), Some(&last)) = (anchor_indices.first(), anchor_indices.last()) else {
+            continue;
+        };
+        for end_path in [path[..=first].to_vec(), reverse_path(&path[last..])] {
+            *end_counts.entry(*end_path.last().unwrap()).or_default().entry(end_path)
+                       .or_insert(0) += 1;
+        }
+    }
+    let mut ends: Vec<(Vec<i32>, f64)> = end_counts.into_values().map(|counts| {
+        let total: usize = counts.values().sum();
+        let (path, count) = counts.into_iter().max_by(|(a, a_count), (b, b_count)| {
+            a_count.cmp(b_count).then(b.cmp(a))
+        }).unwrap();
+        (path, count as f64 / total as f64)
+    }).collect();
+    ends.sort_by(|a, b| a.0.cmp(&b.0));
+    for (end, support) in &ends {
+        eprintln!("linear end: {} ({:.0}% of sequences)", sign_at_end_vec(end), support * 100.0);
+    }
+    eprintln!();
+    ends
+}
+
+
 fn create_bridges(graph: &UnitigGraph, sequences: &[Sequence], anchors: &[u32]) -> Vec<Bridge> {
     section_header("Building bridges");
     explanation("Bridges connect one anchor unitig to the next.");
     let anchor_set: HashSet<u32> = anchors.iter().cloned().collect();
-    let sequence_paths: Vec<_> = sequences.iter().map(|s| graph.get_unitig_path_for_sequence_i32(s)).collect();
-    let anchor_to_anchor_paths = get_anchor_to_anchor_paths(&sequence_paths, &anchor_set);
-    let grouped_paths = group_paths_by_start_end(anchor_to_anchor_paths);
+    let sequence_paths: Vec<_> = graph.get_unitig_paths_for_sequences_i32(sequences);
+
+    // Paths are grouped one sequence at a time, so each path's assembly and weight are known.
+    let mut grouped_paths: HashMap<(i32, i32), Vec<Vec<i32>>> = HashMap::new();
+    let mut grouped_assemblies: HashMap<(i32, i32), Vec<String>> = HashMap::new();
+    let mut grouped_weights: HashMap<(i32, i32), Vec<f64>> = HashMap::new();
+    for (seq, path) in sequences.iter().zip(sequence_paths) {
+        let anchor_to_anchor_paths = get_anchor_to_anchor_paths(&[path], &anchor_set);
+        for (start_end, paths) in group_paths_by_start_end(anchor_to_anchor_paths) {
+            grouped_assemblies.entry(start_end).or_default()
+                              .extend(vec![seq.filename.clone(); paths.len()]);
+            grouped_weights.entry(start_end).or_default().extend(vec![seq.weight; paths.len()]);
+            grouped_paths.entry(start_end).or_default().extend(paths);
+        }
+    }
     let mut bridges = Vec::new();
     for ((start, end), paths) in grouped_paths {
-        bridges.push(Bridge::new(start, end, paths));
+        let mut bridge = Bridge::new(start, end, paths);
+        bridge.assemblies = grouped_assemblies.remove(&(start, end)).unwrap();
+        bridge.weights = grouped_weights.remove(&(start, end)).unwrap();
+        bridges.push(bridge);
     }
     bridges.sort();
     bridges
 }
 
 
+fn depth_weights_set(sequences: &[Sequence]) -> bool {
+    // Sequences only have depth weights if compress was given --weights or collapsed duplicate
+    // contigs. Otherwise every weight is 1.0, and weighting by depth is the same as by assembly.
+    sequences.iter().any(|s| s.weight != 1.0)
+}
+
+
+fn weight_bridge_paths(bridges: &mut [Bridge], verbose: bool) {
+    // With --weighting depth, each bridge's paths are counted using their sequences' depth
+    // weights instead of once per assembly, so down-weighted assemblies have less say.
+    section_header("Weighting bridge paths");
+    explanation("Bridge paths are now chosen by summing the depth weights of the sequences which \
+                 took them, instead of counting each input assembly once.");
+    let mut changed_count = 0;
+    for bridge in bridges.iter_mut() {
+        let old_path = bridge.best_path.clone();
+        bridge.choose_best_path();
+        if bridge.best_path != old_path {
+            changed_count += 1;
+            if verbose {
+                if changed_count == 1 { eprintln!("Bridge paths changed by weighting:"); }
+                eprintln!("  {}", bridge);
+            }
+        }
+    }
+    if verbose && changed_count > 0 { eprintln!(); }
+    eprintln!("{} bridge path{} changed by weighting", changed_count,
+              match changed_count { 1 => "", _ => "s" });
+    eprintln!();
+}
+
+
+fn mark_bubble_bridges(bridges: &mut [Bridge], min_support: f64, verbose: bool) {
+    // Bridges whose best path is taken by less than min_support of the assemblies (and which
+    // weren't decided by reads) are kept as bubbles: all of their paths go into the final graph,
+    // instead of forcing an arbitrary choice.
+    section_header("Keeping alternative paths");
+    explanation("Bridges without a confidently best path are kept as bubbles in the final graph, \
+                 so each alternative sequence is retained.");
+    let mut bubble_count = 0;
+    for bridge in bridges.iter_mut() {
+        let mut paths = bridge.all_paths.clone();
+        paths.sort();
+        paths.dedup();
+        if paths.len() < 2 || bridge.chosen_by_reads ||
+           bridge.path_support(&bridge.best_path) >= min_support {
+            continue;
+        }
+        bridge.keep_as_bubble = true;
+        bubble_count += 1;
+        if verbose {
+            if bubble_count == 1 { eprintln!("Bridges kept as bubbles:"); }
+            eprintln!("  {}", bridge);
+        }
+    }
+    if verbose && bubble_count > 0 { eprintln!(); }
+    eprintln!("{} bridge{} kept as bubbles", bubble_count, match bubble_count { 1 => "", _ => "s" });
+    eprintln!();
+}
+
+
+fn load_reads(graph: &UnitigGraph, reads: &Path) -> Vec<Vec<i32>> {
+    section_header("Threading reads");
+    explanation("Long reads are now placed onto the graph as unitig paths, so they can be used as \
+                 extra evidence where the input assemblies disagree.");
+    let read_paths = load_read_paths(graph, reads);
+    eprintln!("{} read{} span multiple unitigs", read_paths.len(),
+              match read_paths.len() { 1 => "", _ => "s" });
+    eprintln!();
+    read_paths
+}
+
+
+fn add_read_support(bridges: &mut [Bridge], read_paths: &[Vec<i32>], verbose: bool) {
+    // Counts the reads spanning each bridge, which is used when culling conflicting bridges. For
+    // bridges where the input assemblies took different paths, the path best supported by reads
+    // replaces the assembly-based choice.
+    let mut changed_count = 0;
+    for bridge in bridges.iter_mut() {
+        bridge.read_support = spanning_read_count(read_paths, bridge.start, bridge.end);
+        let mut candidates = bridge.all_paths.clone();
+        candidates.sort();
+        candidates.dedup();
+        if candidates.len() < 2 {
+            continue;
+        }
+        let counts = path_support(read_paths, bridge.start, bridge.end, &candidates);
+        let max_count = *counts.iter().max().unwrap();
+        if max_count < MIN_READ_SUPPORT || counts.iter().filter(|&&c| c == max_count).count() > 1 {
+            continue;
+        }
+        let read_path = &candidates[counts.iter().position(|&c| c == max_count).unwrap()];
+        bridge.chosen_by_reads = true;
+        if *read_path != bridge.best_path {
+            bridge.best_path = read_path.clone();
+            changed_count += 1;
+            if verbose {
+                if changed_count == 1 { eprintln!("Bridge paths changed by read support:"); }
+                eprintln!("  {} (chosen by {} reads)", bridge, max_count);
+            }
+        }
+    }
+    if verbose && changed_count > 0 { eprintln!(); }
+    eprintln!("{} bridge path{} changed by read support", changed_count,
+              match changed_count { 1 => "", _ => "s" });
+    eprintln!();
+}
+
+
+fn break_path_ties(bridges: &mut [Bridge], tie_breaker: &TieBreaker) -> Vec<TieRecord> {
+    // Bridges whose most common paths were equally common (e.g. a 50/50 split between the input
+    // assemblies) have their path chosen by the tie-breaking policy. Bridges where long reads made
+    // the choice are left alone.
+    let mut ties = Vec::new();
+    for bridge in bridges.iter_mut().filter(|b| b.tied_paths.len() > 1 && !b.chosen_by_reads) {
+        let scores: Vec<f64> = bridge.tied_paths.iter()
+            .map(|p| tie_breaker.score(bridge.start, bridge.end, p)).collect();
+        let max_score = scores.iter().cloned().fold(f64::NEG_INFINITY, f64::max);
+        let best: Vec<&Vec<i32>> = bridge.tied_paths.iter().zip(&scores)
+            .filter(|(_, &s)| s == max_score).map(|(p, _)| p).collect();
+        let policy = if best.len() == 1 { tie_breaker.policy.clone() }
+                                   else { "lexicographic".to_string() };
+        bridge.best_path = best.into_iter().min().unwrap().clone();
+        bridge.tie_policy = Some(policy.clone());
+        let rejected = bridge.tied_paths.iter().filter(|p| **p != bridge.best_path)
+            .map(|p| path_string(bridge.start, p, bridge.end)).collect::<Vec<_>>().join(";");
+        ties.push(TieRecord { kind: "bridge_path",
+                              chosen: path_string(bridge.start, &bridge.best_path, bridge.end),
+                              rejected, policy });
+    }
+    ties
+}
+
+
+fn path_string(start: i32, path: &[i32], end: i32) -> String {
+    let full: Vec<i32> = std::iter::once(start).chain(path.iter().cloned())
+                                               .chain(std::iter::once(end)).collect();
+    sign_at_end_vec(&full)
+}
+
+
+fn ambiguity_report(graph: &UnitigGraph, bridges: &[Bridge])
+        -> (AmbiguityReport, HashMap<String, Vec<u8>>) {
+    // Describes every place where the input assemblies disagreed, along with the alternative
+    // sequences (keyed by name). This must be run before bridges are applied, since that changes
+    // the graph. Which conflicting bridges were chosen is only known after culling, so that is
+    // filled in later by mark_culled_bridges.
+    let mut ambiguities = Vec::new();
+    let mut alt_seqs = HashMap::new();
+    for bridge in bridges {
+        let mut paths: Vec<&Vec<i32>> = bridge.all_paths.iter().collect();
+        paths.sort_by_key(|&p| (std::cmp::Reverse(bridge.path_count(p)), p));
+        paths.dedup();
+        if paths.len() < 2 {
+            continue;
+        }
+        let id = ambiguities.len() as u32 + 1;
+        let decided_by = bridge.decided_by();
+        let mut alternatives: Vec<AlternativeDetails> = paths.iter().enumerate().map(|(i, path)| {
+            let name = format!("ambiguity_{}_alt_{}", id, i + 1);
+            let chosen = bridge.keep_as_bubble || **path == bridge.best_path;
+            let alt = alternative(graph, &name, bridge.start, path, bridge.end,
+                                  bridge.path_assemblies(path), chosen);
+            alt_seqs.insert(name, alternative_seq(graph, bridge.start, path, bridge.end));
+            alt
+        }).collect();
+        for path in untaken_paths(graph, bridge, &paths) {
+            let name = format!("ambiguity_{}_alt_{}", id, alternatives.len() + 1);
+            alternatives.push(alternative(graph, &name, bridge.start, &path, bridge.end,
+                                          Vec::new(), false));
+            alt_seqs.insert(name, alternative_seq(graph, bridge.start, &path, bridge.end));
+        }
+        ambiguities.push(AmbiguityDetails { id, kind: "bridge_paths".to_string(), decided_by,
+                                            alternatives });
+    }
+    for group in group_conflicting_bridges(bridges) {
+        let id = ambiguities.len() as u32 + 1;
+        let alternatives = group.iter().enumerate().map(|(i, bridge)| {
+            let name = format!("ambiguity_{}_alt_{}", id, i + 1);
+            let mut assemblies = bridge.assemblies.clone();
+            assemblies.sort();
+            assemblies.dedup();
+            let alt = alternative(graph, &name, bridge.start, &bridge.best_path, bridge.end,
+                                  assemblies, false);
+            alt_seqs.insert(name, alternative_seq(graph, bridge.start, &bridge.best_path,
+                                                  bridge.end));
+            alt
+        }).collect();
+        ambiguities.push(AmbiguityDetails { id, kind: "conflicting_bridges".to_string(),
+                                            decided_by: "culling".to_string(), alternatives });
+    }
+    let report = AmbiguityReport { ambiguity_count: ambiguities.len() as u32, ambiguities,
+                                   ..Default::default() };
+    (report, alt_seqs)
+}
+
+
+fn untaken_paths(graph: &UnitigGraph, bridge: &Bridge, taken: &[&Vec<i32>]) -> Vec<Vec<i32>> {
+    // Returns other ways through the tangle between the bridge's anchors, i.e. paths in the graph
+    // which no assembly took. These are only found up to twice the length of the longest taken
+    // path, and paths through another anchor are excluded since they would skip sequence.
+    let length = |path: &[i32]| graph.get_sequence_from_path_signed(path).len() as u32;
+    let max_len = length(&[bridge.start]) + length(&[bridge.end]) +
+                  2 * taken.iter().map(|p| length(p)).max().unwrap_or(0);
+    graph.simple_paths(bridge.start, bridge.end, max_len, taken.len() + MAX_UNTAKEN_PATHS)
+        .into_iter().filter(|(path, _)| path.len() > 1)
+        .map(|(path, _)| path[1..path.len() - 1].to_vec())
+        .filter(|path| !taken.contains(&path) &&
+                       path.iter().all(|u| !graph.unitig(u.unsigned_abs()).anchor))
+        .take(MAX_UNTAKEN_PATHS).collect()
+}
+
+
+fn flag_inverted_repeats(graph: &mut UnitigGraph, report: &mut AmbiguityReport) {
+    // Flags inverted repeats in the final graph (as IR tags on its unitigs) and adds them to the
+    // report. These structures commonly cause false circularisation, so they are worth a look.
+    section_header("Checking for inverted repeats");
+    explanation("Long inverted repeats and hairpin links can make a sequence falsely appear \
+                 circular, so they are flagged in the final graph and the ambiguity report.");
+    graph.flag_inverted_repeats(INVERTED_REPEAT_MIN_LENGTH);
+    for u in &graph.unitigs {
+        for r in &u.inverted_repeats {
+            let kind = match r {
+                InvertedRepeat::Internal { .. } => "internal",
+                InvertedRepeat::HairpinStart    => "hairpin_start",
+                InvertedRepeat::HairpinEnd      => "hairpin_end",
+            };
+            eprintln!("unitig {}: {} {}", u.number, kind, r.tag_value());
+            report.inverted_repeats.push(InvertedRepeatDetails {
+                unitig: u.number, kind: kind.to_string(), position: r.tag_value(),
+                length: r.length() as u64 });
+        }
+    }
+    report.inverted_repeat_count = report.inverted_repeats.len() as u32;
+    if report.inverted_repeat_count == 0 {
+        eprintln!("No inverted repeats found.");
+    }
+    eprintln!();
+}
+
+
+fn alternative(graph: &UnitigGraph, name: &str, start: i32, path: &[i32], end: i32,
+               assemblies: Vec<String>, chosen: bool) -> AlternativeDetails {
+    AlternativeDetails { name: name.to_string(), path: path_string(start, path, end), assemblies,
+                         length: graph.get_sequence_from_path_signed(path).len() as u64, chosen }
+}
+
+
+fn alternative_seq(graph: &UnitigGraph, start: i32, path: &[i32], end: i32) -> Vec<u8> {
+    // The sequence between the anchors, with some flanking anchor sequence on each side.
+    let start_seq = graph.get_sequence_from_path_signed(&[start]);
+    let end_seq = graph.get_sequence_from_path_signed(&[end]);
+    let mut seq = start_seq[start_seq.len().saturating_sub(ALT_FLANK_SIZE)..].to_vec();
+    seq.extend(graph.get_sequence_from_path_signed(path));
+    seq.extend(&end_seq[..end_seq.len().min(ALT_FLANK_SIZE)]);
+    seq
+}
+
+
+fn group_conflicting_bridges(bridges: &[Bridge]) -> Vec<Vec<&Bridge>> {
+    // Groups conflicting bridges which compete (directly or indirectly) for the same anchors.
+    let mut groups: Vec<Vec<&Bridge>> = Vec::new();
+    for bridge in bridges.iter().filter(|b| b.conflicting) {
+        let (joined, mut separate): (Vec<_>, Vec<_>) = groups.into_iter()
+            .partition(|g| g.iter().any(|b| b.conflicts_with(bridge)));
+        let mut group: Vec<&Bridge> = joined.into_iter().flatten().collect();
+        group.push(bridge);
+        separate.push(group);
+        groups = separate;
+    }
+    for group in &mut groups {
+        group.sort();
+    }
+    groups.sort_by(|a, b| a[0].cmp(b[0]));
+    groups
+}
+
+
+fn mark_culled_bridges(report: &mut AmbiguityReport, bridges: &[Bridge]) {
+    // Marks the conflicting bridges which survived culling (and no longer conflict) as chosen.
+    let kept: HashSet<String> = bridges.iter().filter(|b| !b.conflicting)
+        .map(|b| path_string(b.start, &b.best_path, b.end)).collect();
+    for ambiguity in report.ambiguities.iter_mut().filter(|a| a.kind == "conflicting_bridges") {
+        for alt in &mut ambiguity.alternatives {
+            alt.chosen = kept.contains(&alt.path);
+        }
+    }
+}
+
+
+fn dry_run_plan(graph: &UnitigGraph, bridges: &[Bridge], culled: &[String],
+                 final_unitig_count: usize) -> Vec<String> {
+    // Describes what resolve would do to the graph: the path chosen for each bridge (with its
+    // supporting evidence), the links which would be deleted, the bridges which would be culled
+    // and how many unitigs would remain after merging. The graph given must be the unresolved one.
+    let mut lines = vec!["Bridge path choices:".to_string()];
+    let mut deleted_links = Vec::new();
+    for bridge in bridges.iter().filter(|b| !b.conflicting) {
+        let applied_paths = bridge.applied_paths();
+        let mut distinct_paths = bridge.all_paths.clone();
+        distinct_paths.sort();
+        distinct_paths.dedup();
+        for path in &applied_paths {
+            let reads = if bridge.read_support > 0 { format!(", {} reads", bridge.read_support) }
+                        else { String::new() };
+            let decided_by = if distinct_paths.len() > 1 {
+                format!(", decided by {}", bridge.decided_by()) } else { String::new() };
+            lines.push(format!("  {}: {}/{} assemblies{}{}",
+                               path_string(bridge.start, path, bridge.end),
+                               bridge.path_count(path), bridge.depth(), reads, decided_by));
+        }
+        let firsts: Vec<i32> = applied_paths.iter().map(|p| *p.first().unwrap_or(&bridge.end))
+                                                   .collect();
+        let lasts: Vec<i32> = applied_paths.iter().map(|p| *p.last().unwrap_or(&bridge.start))
+                                                  .collect();
+        for next in graph.outgoing_strands(bridge.start) {
+            if !firsts.contains(&next) { deleted_links.push((bridge.start, next)); }
+        }
+        for prev in graph.incoming_strands(bridge.end) {
+            if !lasts.contains(&prev) { deleted_links.push((prev, bridge.end)); }
+        }
+    }
+    deleted_links.sort();
+    deleted_links.dedup();
+    lines.push("Link deletions:".to_string());
+    lines.extend(deleted_links.iter().map(|(a, b)| format!("  {} → {}", sign_at_end(*a),
+                                                                    sign_at_end(*b))));
+    lines.push("Culled bridges:".to_string());
+    lines.extend(culled.iter().map(|b| format!("  {}", b)));
+    lines.push("Unresolved conflicting bridges:".to_string());
+    lines.extend(bridges.iter().filter(|b| b.conflicting)
+                        .map(|b| format!("  {}", path_string(b.start, &b.best_path, b.end))));
+    lines.push(format!("Merges: {} unitigs would become {} unitigs", graph.unitigs.len(),
+                       final_unitig_count));
+    lines
+}
+
+
+fn print_dry_run_plan(plan: &[String]) {
+    section_header("Dry run");
+    explanation("No files were written. These are the changes resolve would make to the graph.");
+    for line in plan {
+        eprintln!("{}", line);
+    }
+    eprintln!();
+}
+
+
+fn save_alternative_seqs(report: &AmbiguityReport, alt_seqs: &HashMap<String, Vec<u8>>,
+                         file_path: &Path) {
+    let mut file = File::create(file_path).unwrap();
+    for alt in report.ambiguities.iter().flat_map(|a| &a.alternatives) {
+        let assemblies = if alt.assemblies.is_empty() { "none".to_string() }
+                                                  else { alt.assemblies.join(",") };
+        writeln!(file, ">{} path={} assemblies={} chosen={}", alt.name, alt.path, assemblies,
+                 if alt.chosen { "yes" } else { "no" }).unwrap();
+        write_fasta_seq(&mut file, &String::from_utf8_lossy(&alt_seqs[&alt.name]), 0).unwrap();
+    }
+}
+
+
+fn save_confidence_bed(graph: &UnitigGraph, file_path: &Path) {
+    let mut file = File::create(file_path).unwrap();
+    for line in graph.get_confidence_bed_lines(0) {
+        writeln!(file, "{}", line).unwrap();
+    }
+}
+
+
+fn save_rounds(rounds: &[RoundRecord], file_path: &Path) {
+    let mut file = File::create(file_path).unwrap();
+    writeln!(file, "round\tratio\tculled\tculled_depth\tkept\tkept_depth").unwrap();
+    for r in rounds {
+        writeln!(file, "{}\t{}\t{}\t{}\t{}\t{}", r.round, format_float(r.ratio), r.culled,
+                 r.culled_depth, r.kept, r.kept_depth).unwrap();
+    }
+}
+
+
+fn save_ties(ties: &[TieRecord], file_path: &Path) {
+    let mut file = File::create(file_path).unwrap();
+    writeln!(file, "type\tchosen\trejected\tpolicy").unwrap();
+    for tie in ties {
+        writeln!(file, "{}\t{}\t{}\t{}", tie.kind, tie.chosen, tie.rejected, tie.policy).unwrap();
+    }
+}
+
+
 fn determine_ambiguity(bridges: &mut [Bridge]) -> usize {
     // This function classifies each Bridge as conflicting or not. A Bridge is conflicting if it
     // shares its start or end unitig with another Bridge. The return value is the number of
@@ -206,30 +828,57 @@ fn determine_ambiguity(bridges: &mut [Bridge]) -> usize {
 }
 
 
-fn apply_bridges(graph: &mut UnitigGraph, bridges: &Vec<Bridge>, bridge_depth: f64) {
+fn apply_bridges(graph: &mut UnitigGraph, bridges: &[Bridge],
+                 linear_ends: &[(Vec<i32>, f64)], bridge_depth: f64) {
     // This function applies bridges to the graph. If keep_all_paths is true, then unitigs in all
     // bridge paths are kept. If keep_all_paths is false, then only the best path is kept.
     graph.clear_positions();
-    for bridge in bridges {
+    for u in &mut graph.unitigs {
+        if u.anchor {
+            u.confidence = vec![Confidence { start: 0, end: u.length(), support: 1.0,
+                                             consistency: 1.0 }];
+        }
+    }
+    // Bridge confidence uses depths from before any bridges are applied, since applying a bridge
+    // reduces the depths of its unitigs.
+    let confidence: Vec<Vec<Confidence>> = bridges.iter().map(|b| {
+        b.applied_paths().iter().map(|p| path_confidence(graph, p, b.path_support(p))).collect()
+    }).collect();
+    for (bridge, confidence) in bridges.iter().zip(confidence) {
         if bridge.conflicting {
             continue;
         }
         graph.delete_outgoing_links(bridge.start);
         graph.delete_incoming_links(bridge.end);
 
-        if bridge.best_path.is_empty() {
-            graph.create_link(bridge.start, bridge.end);
+        // Usually only the best path is applied, but bridges kept as bubbles get all of their
+        // paths, each with a share of the depth.
+        let applied_paths = bridge.applied_paths();
+        let depth_share = if applied_paths.len() > 1 {
+            applied_paths.iter().map(|p| bridge.path_count(p) as f64 / bridge.depth() as f64)
+                         .collect()
         } else {
-            let bridge_seq = graph.get_sequence_from_path_signed(&bridge.best_path);
+            vec![1.0]
+        };
+        for ((path, confidence), share) in applied_paths.iter().zip(confidence).zip(depth_share) {
+            if path.is_empty() {
+                graph.create_link(bridge.start, bridge.end);
+                continue;
+            }
+            let bridge_seq = graph.get_sequence_from_path_signed(path);
             let bridge_num = graph.max_unitig_number() + 1;
-            let bridge_unitig = Unitig::bridge(bridge_num, bridge_seq, bridge_depth);
-            let bridge_unitig_rc = Rc::new(RefCell::new(bridge_unitig));
-            graph.unitigs.push(bridge_unitig_rc.clone());
-            graph.unitig_index.insert(bridge_num, bridge_unitig_rc);
-            reduce_depths(graph, bridge);
+            let mut bridge_unitig = Unitig::bridge(bridge_num, bridge_seq, bridge_depth * share);
+            bridge_unitig.confidence = vec![confidence];
+            graph.add_unitig(bridge_unitig);
             graph.create_link(bridge.start, bridge_num as i32);
             graph.create_link(bridge_num as i32, bridge.end)
         }
+        if applied_paths.iter().any(|p| !p.is_empty()) {
+            reduce_depths(graph, bridge);
+        }
+    }
+    for (end_path, support) in linear_ends {
+        apply_linear_end(graph, end_path, *support, bridge_depth);
     }
     delete_unitigs_not_connected_to_anchor(graph);
 
@@ -237,8 +886,40 @@ fn apply_bridges(graph: &mut UnitigGraph, bridges: &Vec<Bridge>, bridge_depth: f
 }
 
 
+fn apply_linear_end(graph: &mut UnitigGraph, end_path: &[i32], support: f64,
+                    bridge_depth: f64) {
+    // Applies a linear replicon's chosen end: a path from a dead end to an anchor. Other ends
+    // which led to the anchor are disconnected (and later deleted), and the chosen end's sequence
+    // is added as a bridge-like unitig leading to the anchor.
+    let (&anchor, end_unitigs) = end_path.split_last().unwrap();
+    graph.delete_incoming_links(anchor);
+    if end_unitigs.is_empty() {
+        return;
+    }
+    let end_seq = graph.get_sequence_from_path_signed(end_unitigs);
+    let end_num = graph.max_unitig_number() + 1;
+    let mut end_unitig = Unitig::bridge(end_num, end_seq, bridge_depth);
+    end_unitig.confidence = vec![path_confidence(graph, end_unitigs, support)];
+    graph.add_unitig(end_unitig);
+    graph.create_link(end_num as i32, anchor);
+}
+
+
+fn path_confidence(graph: &UnitigGraph, path: &[i32], support: f64) -> Confidence {
+    // Confidence for a bridge-like unitig built from the given path. Its depth consistency is the
+    // ratio of the lowest to highest depth of the path's unitigs.
+    let depths: Vec<f64> = path.iter()
+        .map(|n| graph.unitig(n.unsigned_abs()).depth).collect();
+    let max_depth = depths.iter().cloned().fold(0.0, f64::max);
+    let min_depth = depths.iter().cloned().fold(f64::INFINITY, f64::min);
+    let consistency = if max_depth > 0.0 { min_depth / max_depth } else { 0.0 };
+    let length = graph.get_sequence_from_path_signed(path).len() as u32;
+    Confidence { start: 0, end: length, support, consistency }
+}
+
+
 fn merge_after_bridging(graph: &mut UnitigGraph, bridge_depth: f64) {
-    merge_linear_paths(graph, &vec![], Some(bridge_depth));
+    merge_linear_paths(graph, &[], Some(bridge_depth));
     graph.print_basic_graph_info();
     graph.renumber_unitigs();
 }
@@ -249,25 +930,25 @@ fn reduce_depths(graph: &mut UnitigGraph, bridge: &Bridge) {
     // bridge.
     for path in &bridge.all_paths {
         for signed_num in path {
-            let mut unitig = graph.unitig_index.get(&signed_num.unsigned_abs()).unwrap().borrow_mut();
-            unitig.reduce_depth_by_one();
+            graph.unitig_mut(signed_num.unsigned_abs()).reduce_depth_by_one();
         }
     }
 }
 
 
 fn delete_unitigs_not_connected_to_anchor(graph: &mut UnitigGraph) {
-    let to_delete: HashSet<u32> = graph.connected_components().into_iter()
-        .filter_map(|component| {
-            if component.iter().all(|&num| !graph.unitig_index.get(&num).unwrap().borrow().anchor) { Some(component) }
-            else { None } })
-        .flat_map(|component| component.into_iter())
-        .collect();
+    graph.assign_component_ids();
+    let anchored_components: HashSet<u32> = graph.unitigs.iter()
+        .filter(|u| u.anchor).map(|u| u.component).collect();
+    let to_delete: HashSet<u32> = graph.unitigs.iter()
+        .filter(|u| !anchored_components.contains(&u.component))
+        .map(|u| u.number).collect();
     graph.remove_unitigs_by_number(to_delete);
 }
 
 
-fn cull_ambiguity(bridges: &mut Vec<Bridge>, verbose: bool) -> usize {
+fn cull_ambiguity(bridges: &mut Vec<Bridge>, tie_breaker: &TieBreaker, ties: &mut Vec<TieRecord>,
+                  verbose: bool) -> usize {
     let mut ambi_bridges: Vec<_> = bridges.iter().filter(|b| b.conflicting).collect();
     if ambi_bridges.is_empty() {
         return 0;
@@ -275,7 +956,8 @@ fn cull_ambiguity(bridges: &mut Vec<Bridge>, verbose: bool) -> usize {
     section_header("Culling conflicting bridges");
     explanation("The least-supported conflicting bridges are now culled until no bridges \
                  conflict.");
-    ambi_bridges.sort_by(|a, b| a.depth().cmp(&b.depth()).then(a.cmp(b)));
+    let cull_order = |a: &&Bridge, b: &&Bridge| cull_order(a, b, tie_breaker);
+    ambi_bridges.sort_by(cull_order);
     let mut cull_count = 0;
     if verbose {
         eprintln!("Culled bridges:");
@@ -285,11 +967,21 @@ fn cull_ambiguity(bridges: &mut Vec<Bridge>, verbose: bool) -> usize {
         if verbose {
             eprintln!("  {}", to_cull);
         }
+        if let Some(kept) = ambi_bridges[1..].iter()
+                .find(|b| b.support() == to_cull.support() && b.conflicts_with(to_cull)) {
+            let policy = if tie_breaker.bridge_score(kept) > tie_breaker.bridge_score(to_cull) {
+                tie_breaker.policy.clone() } else { "lexicographic".to_string() };
+            ties.push(TieRecord {
+                kind: "conflicting_bridge",
+                chosen: path_string(kept.start, &kept.best_path, kept.end),
+                rejected: path_string(to_cull.start, &to_cull.best_path, to_cull.end),
+                policy });
+        }
         bridges.remove(bridges.iter().position(|b| b.start == to_cull.start && b.end == to_cull.end).unwrap());
         cull_count += 1;
         determine_ambiguity(bridges);
         ambi_bridges = bridges.iter().filter(|b| b.conflicting).collect();
-        ambi_bridges.sort_by(|a, b| a.depth().cmp(&b.depth()).then(a.cmp(b)));
+        ambi_bridges.sort_by(cull_order);
     }
     if verbose { eprintln!(); }
     eprintln!("{} conflicting bridge{} culled", cull_count, match cull_count { 1 => "", _ => "s" });
@@ -298,6 +990,82 @@ fn cull_ambiguity(bridges: &mut Vec<Bridge>, verbose: bool) -> usize {
 }
 
 
+fn cull_order(a: &Bridge, b: &Bridge, tie_breaker: &TieBreaker) -> Ordering {
+    // Conflicting bridges are culled in this order: least supported first, then lowest scoring by
+    // the tie-breaking policy.
+    a.support().cmp(&b.support())
+        .then(tie_breaker.bridge_score(a).total_cmp(&tie_breaker.bridge_score(b)))
+        .then(a.cmp(b))
+}
+
+
+fn cull_ambiguity_iteratively(bridges: &mut Vec<Bridge>, tie_breaker: &TieBreaker,
+                              ties: &mut Vec<TieRecord>, min_ratio: f64, verbose: bool)
+        -> Vec<RoundRecord> {
+    // Culls conflicting bridges in rounds of decreasing strictness. In each round, a bridge is
+    // culled only if a bridge it conflicts with is ahead of it in the cull order and has at least
+    // the round's ratio times its depth. Bridges still conflicting after the last round are left
+    // unresolved. Returns a record of each culled bridge.
+    let mut records = Vec::new();
+    if bridges.iter().all(|b| !b.conflicting) {
+        return records;
+    }
+    section_header("Culling conflicting bridges iteratively");
+    explanation("Conflicting bridges are now culled in rounds, starting with those which are \
+                 clearly outnumbered and relaxing the threshold each round. Conflicts which \
+                 remain after the last round are left unresolved.");
+    let mut ratios: Vec<f64> = ITERATIVE_RATIOS.iter().cloned().filter(|&r| r > min_ratio)
+                                               .collect();
+    ratios.push(min_ratio);
+    for (i, &ratio) in ratios.iter().enumerate() {
+        let round = i + 1;
+        let mut round_count = 0;
+        loop {
+            let mut ambi_bridges: Vec<&Bridge> = bridges.iter().filter(|b| b.conflicting)
+                                                        .collect();
+            ambi_bridges.sort_by(|a, b| cull_order(a, b, tie_breaker));
+            let decision = ambi_bridges.iter().enumerate().find_map(|(j, &to_cull)| {
+                ambi_bridges[j+1..].iter().find(|b| b.conflicts_with(to_cull) &&
+                                                  b.depth() as f64 >= ratio * to_cull.depth() as f64)
+                                   .map(|&kept| (to_cull, kept))
+            });
+            let Some((to_cull, kept)) = decision else { break; };
+            if kept.support() == to_cull.support() {
+                let policy = if tie_breaker.bridge_score(kept) > tie_breaker.bridge_score(to_cull) {
+                    tie_breaker.policy.clone() } else { "lexicographic".to_string() };
+                ties.push(TieRecord {
+                    kind: "conflicting_bridge",
+                    chosen: path_string(kept.start, &kept.best_path, kept.end),
+                    rejected: path_string(to_cull.start, &to_cull.best_path, to_cull.end),
+                    policy });
+            }
+            let record = RoundRecord {
+                round, ratio,
+                culled: path_string(to_cull.start, &to_cull.best_path, to_cull.end),
+                culled_depth: to_cull.depth(),
+                kept: path_string(kept.start, &kept.best_path, kept.end),
+                kept_depth: kept.depth() };
+            if verbose {
+                eprintln!("  culled {} ({}×), kept {} ({}×)", record.culled, record.culled_depth,
+                          record.kept, record.kept_depth);
+            }
+            let (start, end) = (to_cull.start, to_cull.end);
+            bridges.remove(bridges.iter().position(|b| b.start == start && b.end == end).unwrap());
+            determine_ambiguity(bridges);
+            records.push(record);
+            round_count += 1;
+        }
+        let remaining = bridges.iter().filter(|b| b.conflicting).count();
+        eprintln!("Round {} (ratio {}): {} bridge{} culled, {} still conflicting", round,
+                  format_float(ratio), round_count, match round_count { 1 => "", _ => "s" },
+                  remaining);
+        if remaining == 0 { break; }
+    }
+    eprintln!();
+    records
+}
+
+
 fn print_bridges(bridges: &Vec<Bridge>, verbose: bool) {
     let unique_count = bridges.iter().filter(|b| !b.conflicting).count();
     let conflicting_count = bridges.iter().filter(|b| b.conflicting).count();
@@ -326,7 +1094,7 @@ fn print_bridges(bridges: &Vec<Bridge>, verbose: bool) {
 }
 
 
-fn get_anchor_to_anchor_paths(sequence_paths: &Vec<Vec<i32>>, anchor_set: &HashSet<u32>) -> Vec<Vec<i32>> {
+fn get_anchor_to_anchor_paths(sequence_paths: &[Vec<i32>], anchor_set: &HashSet<u32>) -> Vec<Vec<i32>> {
     let mut anchor_to_anchor_paths = Vec::new();
     for path in sequence_paths {
         let mut last_anchor_i: Option<usize> = None;
@@ -361,20 +1129,95 @@ fn group_paths_by_start_end(anchor_to_anchor_paths: Vec<Vec<i32>>) -> HashMap<(i
 
 
 fn compare_paths(path_a: &Vec<i32>, path_b: &Vec<i32>) -> bool {
-    // Returns true if path_a is 'better' than path_b. Used to break ties when two paths are equally
-    // common in a bridge.
-
-    // TODO: explore different tie-breaking strategies (just using lexographic order at the moment).
+    // Returns true if path_a is 'better' than path_b. Used to give an initial choice when two paths
+    // are equally common in a bridge, which break_path_ties may then change.
     path_a < path_b
 }
 
 
+struct TieRecord {
+    kind: &'static str,  // bridge_path or conflicting_bridge
+    chosen: String,
+    rejected: String,
+    policy: String,      // the tie-breaking policy, or lexicographic if that was also tied
+}
+
+
+struct RoundRecord {
+    round: usize,
+    ratio: f64,
+    culled: String,
+    culled_depth: usize,
+    kept: String,
+    kept_depth: usize,
+}
+
+
+struct TieBreaker {
+    // Scores bridge paths for the tie-breaking policy (higher is better). Unitig depths and
+    // lengths are stored up front, since the graph is modified when bridges are applied.
+    policy: String,
+    unitigs: HashMap<u32, (f64, u32)>,
+    trusted_paths: HashSet<(i32, i32, Vec<i32>)>,
+}
+
+impl TieBreaker {
+    fn new(policy: &str, trusted: &Option<String>, graph: &UnitigGraph, sequences: &[Sequence],
+           anchors: &[u32]) -> Self {
+        let unitigs = graph.unitigs.iter()
+            .map(|u| (u.number, (u.depth, u.length()))).collect();
+        let mut trusted_paths = HashSet::new();
+        if let Some(trusted) = trusted {
+            let trusted_seqs: Vec<Sequence> = sequences.iter()
+                .filter(|s| &s.filename == trusted).cloned().collect();
+            if trusted_seqs.is_empty() {
+                eprintln!("Trusted assembly {} has no contigs in this cluster\n", trusted);
+            }
+            let anchor_set: HashSet<u32> = anchors.iter().cloned().collect();
+            let paths = graph.get_unitig_paths_for_sequences_i32(&trusted_seqs);
+            for path in get_anchor_to_anchor_paths(&paths, &anchor_set) {
+                let (start, end) = (path[0], path[path.len() - 1]);
+                trusted_paths.insert((start, end, path[1..path.len() - 1].to_vec()));
+            }
+        }
+        TieBreaker { policy: policy.to_string(), unitigs, trusted_paths }
+    }
+
+    fn score(&self, start: i32, end: i32, path: &[i32]) -> f64 {
+        // depth: the length-weighted mean depth of the path's unitigs
+        // length: the path's sequence length
+        // trusted: 1 if the trusted assembly uses the path, otherwise 0
+        match self.policy.as_str() {
+            "depth" => {
+                let (weighted_depth, length) = path.iter()
+                    .map(|n| self.unitigs[&n.unsigned_abs()])
+                    .fold((0.0, 0), |(d, l), (depth, len)| (d + depth * len as f64, l + len));
+                if length == 0 { 0.0 } else { weighted_depth / length as f64 }
+            }
+            "length" => path.iter().map(|n| self.unitigs[&n.unsigned_abs()].1 as f64).sum(),
+            _ => if self.trusted_paths.contains(&(start, end, path.to_vec())) { 1.0 } else { 0.0 },
+        }
+    }
+
+    fn bridge_score(&self, bridge: &Bridge) -> f64 {
+        self.score(bridge.start, bridge.end, &bridge.best_path)
+    }
+}
+
+
 pub struct Bridge {
     start: i32,
     end: i32,
     all_paths: Vec<Vec<i32>>,
     best_path: Vec<i32>,
     conflicting: bool,
+    read_support: usize,  // number of long reads spanning the bridge (0 if no reads were given)
+    tied_paths: Vec<Vec<i32>>,  // the most common paths (more than one if they were tied)
+    chosen_by_reads: bool,
+    tie_policy: Option<String>,  // set if best_path was chosen by break_path_ties
+    assemblies: Vec<String>,     // the assembly each of all_paths came from
+    weights: Vec<f64>,           // the depth weight of each of all_paths' sequences
+    keep_as_bubble: bool,        // set if all paths are kept because none is confidently best
 }
 
 impl Bridge {
@@ -387,25 +1230,45 @@ impl Bridge {
             path.pop();
         }
 
-        // Set the best path to the most common path, using compare_paths to break ties.
-        let mut path_counts = HashMap::new();
-        for path in &trimmed_paths { *path_counts.entry(path).or_insert(0) += 1; }
-        let mut best_path = Vec::new();
-        let mut max_count = 0;
-        for (path, count) in path_counts {
-            if count > max_count || (count == max_count && compare_paths(path, &best_path)) {
-                best_path = path.clone();
-                max_count = count;
-            }
-        }
-
-        Bridge {
+        let path_count = trimmed_paths.len();
+        let mut bridge = Bridge {
             start,
             end,
             all_paths: trimmed_paths,
-            best_path,
+            best_path: Vec::new(),
             conflicting: false,
+            read_support: 0,
+            tied_paths: Vec::new(),
+            chosen_by_reads: false,
+            tie_policy: None,
+            assemblies: Vec::new(),
+            weights: vec![1.0; path_count],
+            keep_as_bubble: false,
+        };
+        bridge.choose_best_path();
+        bridge
+    }
+
+    fn choose_best_path(&mut self) {
+        // Sets the best path to the most common path (summing each path's weight), using
+        // compare_paths to break ties.
+        let mut path_counts: HashMap<&Vec<i32>, f64> = HashMap::new();
+        for (path, weight) in self.all_paths.iter().zip(&self.weights) {
+            *path_counts.entry(path).or_insert(0.0) += weight;
+        }
+        let mut best_path = Vec::new();
+        let mut max_count = 0.0;
+        for (path, &count) in &path_counts {
+            if count > max_count || (count == max_count && compare_paths(path, &best_path)) {
+                best_path = (*path).clone();
+                max_count = count;
+            }
         }
+        let mut tied_paths: Vec<Vec<i32>> = path_counts.into_iter()
+            .filter(|&(_, c)| c == max_count).map(|(p, _)| p.clone()).collect();
+        tied_paths.sort();
+        self.best_path = best_path;
+        self.tied_paths = tied_paths;
     }
 
     fn rev_start(&self) -> i32 {
@@ -419,6 +1282,61 @@ impl Bridge {
     fn depth(&self) -> usize {
         self.all_paths.len()
     }
+
+    fn path_count(&self, path: &[i32]) -> usize {
+        self.all_paths.iter().filter(|p| *p == path).count()
+    }
+
+    fn path_support(&self, path: &[i32]) -> f64 {
+        // The fraction of assemblies crossing this bridge which took the given path.
+        let mut all_assemblies = self.assemblies.clone();
+        all_assemblies.sort();
+        all_assemblies.dedup();
+        if all_assemblies.is_empty() { return 0.0; }
+        self.path_assemblies(path).len() as f64 / all_assemblies.len() as f64
+    }
+
+    fn applied_paths(&self) -> Vec<Vec<i32>> {
+        // The paths put into the graph for this bridge: just the best path, or if the bridge is
+        // kept as a bubble, all of its distinct paths (most common first).
+        if !self.keep_as_bubble {
+            return vec![self.best_path.clone()];
+        }
+        let mut paths = self.all_paths.clone();
+        paths.sort();
+        paths.dedup();
+        paths.sort_by_key(|p| std::cmp::Reverse(self.path_count(p)));
+        paths
+    }
+
+    fn path_assemblies(&self, path: &[i32]) -> Vec<String> {
+        let mut assemblies: Vec<String> = self.all_paths.iter().zip(&self.assemblies)
+            .filter(|(p, _)| *p == path).map(|(_, a)| a.clone()).collect();
+        assemblies.sort();
+        assemblies.dedup();
+        assemblies
+    }
+
+    fn conflicts_with(&self, other: &Bridge) -> bool {
+        let starts = [self.start, self.rev_start()];
+        let ends = [self.end, self.rev_end()];
+        starts.contains(&other.start) || starts.contains(&other.rev_start()) ||
+            ends.contains(&other.end) || ends.contains(&other.rev_end())
+    }
+
+    fn decided_by(&self) -> String {
+        // How the best path was chosen, for reporting.
+        if self.chosen_by_reads { "reads".to_string() }
+        else if self.keep_as_bubble { "bubble".to_string() }
+        else { self.tie_policy.clone().unwrap_or("majority".to_string()) }
+    }
+
+    fn support(&self) -> (usize, usize) {
+        // Used to decide which conflicting bridges are culled first. Read support only counts if
+        // there is enough of it, otherwise bridges are compared on depth (number of assemblies).
+        let reads = if self.read_support >= MIN_READ_SUPPORT { self.read_support } else { 0 };
+        (reads, self.depth())
+    }
 }
 
 impl fmt::Display for Bridge {
@@ -464,6 +1382,7 @@ impl Ord for Bridge {
 #[cfg(test)]
 mod tests {
     use maplit::hashmap;
+    use crate::test_gfa::*;
     use super::*;
 
     #[test]
@@ -493,6 +1412,48 @@ mod tests {
                             (8, 1) => vec![vec![8, -7, 1]]});
     }
 
+    #[test]
+    fn test_add_read_support() {
+        // The assemblies favour 3 over 4, but the reads favour 4. The second bridge's reads are
+        // too few to count.
+        let mut bridges = vec![Bridge::new(1, 2, vec![vec![1, 3, 2], vec![1, 3, 2], vec![1, 4, 2]]),
+                               Bridge::new(5, 6, vec![vec![5, 7, 6], vec![5, 7, 6], vec![5, 8, 6]])];
+        assert_eq!(bridges[0].best_path, vec![3]);
+        let read_paths = vec![vec![1, 4, 2], vec![-2, -4, -1], vec![1, 4, 2, 5], vec![1, 3, 2],
+                              vec![5, 8, 6], vec![5, 8, 6], vec![5, 6]];
+        add_read_support(&mut bridges, &read_paths, false);
+        assert_eq!(bridges[0].best_path, vec![4]);
+        assert_eq!(bridges[0].read_support, 4);
+        assert_eq!(bridges[0].support(), (4, 3));
+        assert_eq!(bridges[1].best_path, vec![7]);
+        assert_eq!(bridges[1].read_support, 3);
+        assert_eq!(bridges[1].support(), (3, 3));
+    }
+
+    #[test]
+    fn test_break_path_ties() {
+        // With only sequences a and b, both bridges are 50/50 splits: 1→2→4 vs 1→3→4 and 4→5→6 vs
+        // 4→6. Unitig 2 is given a higher depth than 3, but it is shorter.
+        let (mut graph, sequences) = UnitigGraph::from_gfa_lines(&get_test_gfa_15());
+        let sequences = sequences[..2].to_vec();
+        graph.remove_sequence_from_graph(3);
+        graph.unitig_mut(2).depth = 2.0;
+        graph.unitig_mut(3).depth = 1.0;
+        let anchors = find_anchor_unitigs(&mut graph, &sequences);
+        let best_paths = |policy: &str, trusted: Option<String>| {
+            let tie_breaker = TieBreaker::new(policy, &trusted, &graph, &sequences, &anchors);
+            let mut bridges = create_bridges(&graph, &sequences, &anchors);
+            let ties = break_path_ties(&mut bridges, &tie_breaker);
+            assert_eq!(ties.len(), 2);
+            assert!(ties.iter().all(|t| t.policy == policy));
+            bridges.iter().map(|b| b.best_path.clone()).collect::<Vec<_>>()
+        };
+        assert_eq!(best_paths("depth", None), vec![vec![2], vec![5]]);
+        assert_eq!(best_paths("length", None), vec![vec![3], vec![5]]);
+        assert_eq!(best_paths("trusted", Some("b.fasta".to_string())), vec![vec![3], vec![]]);
+        assert_eq!(best_paths("trusted", Some("a.fasta".to_string())), vec![vec![2], vec![5]]);
+    }
+
     #[test]
     fn test_bridge_unitig_nums() {
         let paths = vec![vec![1, 12, -23, -8, 41, 2],
@@ -542,5 +1503,210 @@ mod tests {
         assert!(bridges[5].conflicting);
         assert!(bridges[6].conflicting);
         assert!(bridges[7].conflicting);
+
+        let groups: Vec<Vec<(i32, i32)>> = group_conflicting_bridges(&bridges).iter()
+            .map(|g| g.iter().map(|b| (b.start, b.end)).collect()).collect();
+        assert_eq!(groups, vec![vec![(1, -2), (1, 8)], vec![(4, -5), (4, -8)],
+                                vec![(-4, 6), (-4, 7)]]);
+    }
+
+    #[test]
+    fn test_weight_bridge_paths() {
+        // Sequences a and c take unitig 2 (1→2→4) and sequence b takes unitig 3, but if b has more
+        // weight than a and c combined, its path is chosen.
+        let (mut graph, mut sequences) = UnitigGraph::from_gfa_lines(&get_test_gfa_15());
+        sequences[1].weight = 3.0;
+        let anchors = find_anchor_unitigs(&mut graph, &sequences);
+        let mut bridges = create_bridges(&graph, &sequences, &anchors);
+        assert_eq!(bridges[0].best_path, vec![2]);
+        weight_bridge_paths(&mut bridges, false);
+        assert_eq!(bridges[0].best_path, vec![3]);
+        assert!(bridges[1].best_path.is_empty());
+
+        sequences[1].weight = 2.0;
+        let mut bridges = create_bridges(&graph, &sequences, &anchors);
+        weight_bridge_paths(&mut bridges, false);
+        assert_eq!(bridges[0].tied_paths, vec![vec![2], vec![3]]);
+    }
+
+    #[test]
+    fn test_depth_weights_set() {
+        let (_, mut sequences) = UnitigGraph::from_gfa_lines(&get_test_gfa_15());
+        assert!(!depth_weights_set(&sequences));
+        sequences[1].weight = 0.5;
+        assert!(depth_weights_set(&sequences));
+    }
+
+    #[test]
+    fn test_cull_ambiguity_iteratively() {
+        // Three pairs of conflicting bridges, with depth ratios of 2.5, 1.5 and 1.
+        let make_bridges = || {
+            let mut bridges = vec![Bridge::new(1, -2, vec![vec![1, 12, 2]; 5]),
+                                   Bridge::new(1, 8, vec![vec![1, 14, 8]; 2]),
+                                   Bridge::new(-4, 6, vec![vec![-4, 12, 6]; 3]),
+                                   Bridge::new(-4, 7, vec![vec![-4, 13, 7]; 2]),
+                                   Bridge::new(4, -5, vec![vec![4, -5]]),
+                                   Bridge::new(4, -8, vec![vec![4, 9, -8]])];
+            determine_ambiguity(&mut bridges);
+            bridges
+        };
+        let (graph, sequences) = UnitigGraph::from_gfa_lines(&get_test_gfa_15());
+        let tie_breaker = TieBreaker::new("trusted", &Some("x.fasta".to_string()), &graph,
+                                          &sequences, &[]);
+
+        let mut bridges = make_bridges();
+        let mut ties = Vec::new();
+        let rounds = cull_ambiguity_iteratively(&mut bridges, &tie_breaker, &mut ties, 1.2, false);
+        assert_eq!(rounds.iter().map(|r| (r.round, r.culled.as_str())).collect::<Vec<_>>(),
+                   vec![(2, "1+,14+,8+"), (3, "4-,13+,7+")]);
+        assert_eq!(bridges.iter().filter(|b| b.conflicting).count(), 2);
+        assert!(ties.is_empty());
+
+        let mut bridges = make_bridges();
+        let rounds = cull_ambiguity_iteratively(&mut bridges, &tie_breaker, &mut ties, 1.0, false);
+        assert_eq!(rounds.len(), 3);
+        assert_eq!((rounds[2].round, rounds[2].ratio), (4, 1.0));
+        assert!(bridges.iter().all(|b| !b.conflicting));
+        assert_eq!(ties.len(), 1);
+    }
+
+    #[test]
+    fn test_linear_ends() {
+        // Sequence a has an extra unitig (7) before its start, so the ends are not consistent
+        // dead ends. As a linear cluster, the more common end (starting at 1) is chosen.
+        let mut gfa = get_test_gfa_15();
+        gfa.insert(7, "S\t7\tAAA\tDP:f:1.00".to_string());
+        gfa.insert(8, "L\t7\t+\t1\t+\t0M".to_string());
+        gfa.insert(9, "L\t1\t-\t7\t-\t0M".to_string());
+        gfa = gfa.into_iter().map(|l| l.replace("\t1+,2+,4+,5+,6+\t*\tLN:i:36",
+                                                "\t7+,1+,2+,4+,5+,6+\t*\tLN:i:39")).collect();
+        let (mut graph, sequences) = UnitigGraph::from_gfa_lines(&gfa);
+        let anchors = find_anchor_unitigs(&mut graph, &sequences);
+        assert!(choose_linear_ends(&graph, &sequences, &anchors, false).is_empty());
+        let ends = choose_linear_ends(&graph, &sequences, &anchors, true);
+        assert_eq!(ends, vec![(vec![-6], 1.0), (vec![1], 2.0 / 3.0)]);
+        assert_eq!(choose_linear_ends(&graph, &sequences[..1], &anchors, true),
+                   vec![(vec![-6], 1.0), (vec![7, 1], 1.0)]);
+
+        let bridges = create_bridges(&graph, &sequences, &anchors);
+        apply_bridges(&mut graph, &bridges, &ends, sequences.len() as f64);
+        assert!(!graph.unitig_index.contains_key(&7));
+        assert!(graph.incoming_strands(1).is_empty());
+        assert!(graph.outgoing_strands(6).is_empty());
+    }
+
+    #[test]
+    fn test_mark_bubble_bridges() {
+        // Both bridges have their best path in only two of three assemblies, so with a 0.8
+        // threshold they are kept as bubbles: 2/3 between 1 and 4, and 5/nothing between 4 and 6.
+        let (mut graph, sequences) = UnitigGraph::from_gfa_lines(&get_test_gfa_15());
+        let anchors = find_anchor_unitigs(&mut graph, &sequences);
+        let mut bridges = create_bridges(&graph, &sequences, &anchors);
+        mark_bubble_bridges(&mut bridges, 0.5, false);
+        assert!(bridges.iter().all(|b| !b.keep_as_bubble));
+        mark_bubble_bridges(&mut bridges, 0.8, false);
+        assert!(bridges.iter().all(|b| b.keep_as_bubble));
+        assert_eq!(bridges[0].applied_paths(), vec![vec![2], vec![3]]);
+        assert_eq!(bridges[1].applied_paths(), vec![vec![], vec![5]]);
+
+        apply_bridges(&mut graph, &bridges, &[], 3.0);
+        merge_after_bridging(&mut graph, 3.0);
+        assert_eq!(graph.unitigs.len(), 6);
+        assert_eq!(graph.unitigs.iter().map(|u| u.length()).sum::<u32>(), 39);
+        assert_eq!(graph.unitigs.iter()
+                        .filter(|u| u.forward_next.len() == 2 || u.reverse_next.len() == 2)
+                        .count(), 3);
+    }
+
+    #[test]
+    fn test_dry_run_plan() {
+        // Bridge 1→4 takes unitig 2, so the 1→3 and 3→4 links go, and bridge 4→6 is a direct
+        // link, so the 4→5 and 5→6 links go. The graph merges down to a single unitig.
+        let (mut graph, sequences) = UnitigGraph::from_gfa_lines(&get_test_gfa_15());
+        let anchors = find_anchor_unitigs(&mut graph, &sequences);
+        let bridges = create_bridges(&graph, &sequences, &anchors);
+        let plan = dry_run_plan(&graph, &bridges, &[], 1);
+        assert_eq!(plan, vec!["Bridge path choices:",
+                              "  1+,2+,4+: 2/3 assemblies, decided by majority",
+                              "  4+,6+: 2/3 assemblies, decided by majority",
+                              "Link deletions:",
+                              "  1+ → 3+",
+                              "  3+ → 4+",
+                              "  4+ → 5+",
+                              "  5+ → 6+",
+                              "Culled bridges:",
+                              "Unresolved conflicting bridges:",
+                              "Merges: 6 unitigs would become 1 unitigs"]);
+    }
+
+    #[test]
+    fn test_confidence() {
+        // Bridge 1→4 takes unitig 2 (from two of three assemblies), and bridge 4→6 is a direct
+        // link, so the merged consensus has a lower-confidence region where unitig 2 was.
+        let (mut graph, sequences) = UnitigGraph::from_gfa_lines(&get_test_gfa_15());
+        let anchors = find_anchor_unitigs(&mut graph, &sequences);
+        let bridges = create_bridges(&graph, &sequences, &anchors);
+        apply_bridges(&mut graph, &bridges, &[], 3.0);
+        merge_after_bridging(&mut graph, 3.0);
+        assert_eq!(graph.get_confidence_bed_lines(0),
+                   vec!["1\t0\t10\tsupport=1.00;consistency=1.00\t1000",
+                        "1\t10\t12\tsupport=0.67;consistency=1.00\t667",
+                        "1\t12\t22\tsupport=1.00;consistency=1.00\t1000",
+                        "1\t22\t32\tsupport=1.00;consistency=1.00\t1000"]);
+    }
+
+    #[test]
+    fn test_ambiguity_report() {
+        // Bridge 1→4 has 2 (sequences a and c) or 3 (sequence b), and bridge 4→6 is direct
+        // (sequences b and c) or has 5 (sequence a).
+        let (mut graph, sequences) = UnitigGraph::from_gfa_lines(&get_test_gfa_15());
+        let anchors = find_anchor_unitigs(&mut graph, &sequences);
+        let mut bridges = create_bridges(&graph, &sequences, &anchors);
+        determine_ambiguity(&mut bridges);
+        let (mut report, alt_seqs) = ambiguity_report(&graph, &bridges);
+        mark_culled_bridges(&mut report, &bridges);
+        assert_eq!(report.ambiguity_count, 2);
+        let alts = &report.ambiguities[0].alternatives;
+        assert_eq!(report.ambiguities[0].decided_by, "majority");
+        assert_eq!(alts[0].path, "1+,2+,4+");
+        assert_eq!(alts[0].assemblies, vec!["a.fasta", "c.fasta"]);
+        assert!(alts[0].chosen);
+        assert_eq!(alts[1].path, "1+,3+,4+");
+        assert_eq!(alts[1].assemblies, vec!["b.fasta"]);
+        assert!(!alts[1].chosen);
+        assert_eq!(alt_seqs[&alts[1].name], b"ACGACTACGACCCGATCGATCGA".to_vec());
+        let alts = &report.ambiguities[1].alternatives;
+        assert_eq!((alts[0].path.as_str(), alts[0].length), ("4+,6+", 0));
+        assert_eq!((alts[1].path.as_str(), alts[1].length), ("4+,5+,6+", 4));
+
+        let dir = tempfile::tempdir().unwrap();
+        let fasta = dir.path().join("ambiguities.fasta");
+        save_alternative_seqs(&report, &alt_seqs, &fasta);
+        let lines = crate::misc::load_file_lines(&fasta);
+        assert_eq!(lines.len(), 8);
+        assert_eq!(lines[0], ">ambiguity_1_alt_1 path=1+,2+,4+ assemblies=a.fasta,c.fasta \
+                              chosen=yes");
+    }
+
+    #[test]
+    fn test_ambiguity_report_untaken_paths() {
+        // With a link from 3 to 2, the tangle between anchors 1 and 4 can also be resolved as
+        // 1,3,2,4, which no sequence takes. The bridge from 4 to 6 is unaffected.
+        let mut gfa_lines = get_test_gfa_15();
+        gfa_lines.push("L\t3\t+\t2\t+\t0M".to_string());
+        gfa_lines.push("L\t2\t-\t3\t-\t0M".to_string());
+        let (mut graph, sequences) = UnitigGraph::from_gfa_lines(&gfa_lines);
+        let anchors = find_anchor_unitigs(&mut graph, &sequences);
+        let mut bridges = create_bridges(&graph, &sequences, &anchors);
+        determine_ambiguity(&mut bridges);
+        let (report, alt_seqs) = ambiguity_report(&graph, &bridges);
+        let alts = &report.ambiguities[0].alternatives;
+        assert_eq!(alts.iter().map(|a| a.path.as_str()).collect::<Vec<_>>(),
+                   vec!["1+,2+,4+", "1+,3+,4+", "1+,3+,2+,4+"]);
+        assert!(alts[2].assemblies.is_empty());
+        assert!(!alts[2].chosen);
+        assert_eq!(alts[2].length, 5);
+        assert_eq!(alt_seqs[&alts[2].name], b"ACGACTACGACCCTTGATCGATCGA".to_vec());
+        assert_eq!(report.ambiguities[1].alternatives.len(), 2);
     }
 }
@@ -0,0 +1,283 @@
+// This file contains the code for the autocycler rotate subcommand.
+
+// Copyright 2024 Ryan Wick (rrwick@gmail.com)
+// https://github.com/rrwick/Autocycler
+
+// This file is part of Autocycler. Autocycler is free software: you can redistribute it and/or
+// modify it under the terms of the GNU General Public License as published by the Free Software
+// Foundation, either version 3 of the License, or (at your option) any later version. Autocycler
+// is distributed in the hope that it will be useful, but WITHOUT ANY WARRANTY; without even the
+// implied warranty of MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the GNU General
+// Public License for more details. You should have received a copy of the GNU General Public
+// License along with Autocycler. If not, see <http://www.gnu.org/licenses/>.
+
+use std::collections::HashMap;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+
+use crate::log::{section_header, explanation};
+use crate::misc::{check_if_file_exists, load_fasta, output_writer, quit_with_error,
+                  reverse_complement, write_fasta_seq};
+
+
+// Start genes are found with k-mers of this size. Hits are grouped by diagonal (in bands of
+// ROTATE_BAND_SIZE to allow for indels), and a gene is only considered found if at least
+// ROTATE_MIN_KMER_FRACTION of its k-mers hit the same band.
+const ROTATE_KMER_SIZE: usize = 11;
+const ROTATE_BAND_SIZE: usize = 32;
+const ROTATE_MIN_KMER_FRACTION: f64 = 0.2;
+
+
+pub fn rotate(in_fasta: PathBuf, out_fasta: PathBuf, start_genes: PathBuf) {
+    check_settings(&in_fasta, &start_genes);
+    starting_message();
+    print_settings(&in_fasta, &out_fasta, &start_genes);
+    let sequences = load_fasta(&in_fasta);
+    let genes = load_start_genes(&start_genes);
+    let rotated = rotate_sequences(&sequences, &genes);
+    save_sequences(&rotated, &out_fasta);
+    finished_message(&out_fasta);
+}
+
+
+fn check_settings(in_fasta: &Path, start_genes: &Path) {
+    check_if_file_exists(in_fasta);
+    check_if_file_exists(start_genes);
+}
+
+
+fn starting_message() {
+    section_header("Starting autocycler rotate");
+    explanation("This command rotates circular sequences (those with circular=true in their \
+                 header, as made by autocycler combine) so they begin at a start gene (e.g. dnaA \
+                 or repA) on the forward strand.");
+}
+
+
+fn print_settings(in_fasta: &Path, out_fasta: &Path, start_genes: &Path) {
+    eprintln!("Settings:");
+    eprintln!("  --in_fasta {}", in_fasta.display());
+    eprintln!("  --out_fasta {}", out_fasta.display());
+    eprintln!("  --start_genes {}", start_genes.display());
+    eprintln!();
+}
+
+
+fn finished_message(out_fasta: &Path) {
+    section_header("Finished!");
+    eprintln!("Rotated sequences: {}", out_fasta.display());
+    eprintln!();
+}
+
+
+fn load_start_genes(start_genes: &Path) -> Vec<(String, String, String)> {
+    let genes = load_fasta(start_genes);
+    if let Some((name, _, _)) = genes.iter().find(|(_, _, seq)| seq.len() < ROTATE_KMER_SIZE) {
+        quit_with_error(&format!("start gene {} is too short", name));
+    }
+    genes
+}
+
+
+fn rotate_sequences(sequences: &[(String, String, String)], genes: &[(String, String, String)])
+        -> Vec<(String, String)> {
+    // Returns the output header and sequence for each input sequence.
+    section_header("Rotating sequences");
+    explanation("Each circular sequence is searched for the start genes, and if one is found, the \
+                 sequence is rotated (and reverse complemented if needed) to begin with it.");
+    let index = StartGeneIndex::new(genes);
+    sequences.iter().map(|(name, header, seq)| {
+        let length = seq.len();
+        if !header.split_whitespace().any(|t| t == "circular=true") {
+            eprintln!("{} ({} bp): not circular, left unchanged", name, length);
+            return (header.clone(), seq.clone());
+        }
+        match index.find(seq.as_bytes()) {
+            Some(hit) => {
+                eprintln!("{} ({} bp): starts with {} ({} strand)", name, length,
+                          genes[hit.gene].0, if hit.forward { "forward" } else { "reverse" });
+                let rotated = rotate_to_hit(seq.as_bytes(), &hit);
+                (format!("{} start_gene={}", header, genes[hit.gene].0),
+                 String::from_utf8(rotated).unwrap())
+            }
+            None => {
+                eprintln!("{} ({} bp): no start gene found, left unchanged", name, length);
+                (header.clone(), seq.clone())
+            }
+        }
+    }).collect()
+}
+
+
+fn rotate_to_hit(seq: &[u8], hit: &StartGeneHit) -> Vec<u8> {
+    let seq = if hit.forward { seq.to_vec() } else { reverse_complement(seq) };
+    let mut rotated = seq[hit.start..].to_vec();
+    rotated.extend_from_slice(&seq[..hit.start]);
+    rotated
+}
+
+
+fn save_sequences(sequences: &[(String, String)], out_fasta: &Path) {
+    let mut writer = output_writer(out_fasta).unwrap_or_else(|e| {
+        quit_with_error(&format!("unable to create {}\n{}", out_fasta.display(), e));
+    });
+    let result = sequences.iter().try_for_each(|(header, seq)| {
+        writeln!(writer, ">{}", header)?;
+        write_fasta_seq(&mut writer, seq, 0)
+    }).and_then(|_| writer.finish());
+    if let Err(e) = result {
+        quit_with_error(&format!("unable to write {}\n{}", out_fasta.display(), e));
+    }
+}
+
+
+struct StartGeneHit {
+    gene: usize,
+    forward: bool,
+    start: usize,  // gene start on the given strand of the sequence
+}
+
+
+struct StartGeneIndex {
+    // Maps each k-mer in the start genes (both strands) to its gene, strand and position. For the
+    // reverse strand, positions are in the gene's reverse complement.
+    kmers: HashMap<Vec<u8>, Vec<(usize, bool, usize)>>,
+    gene_lengths: Vec<usize>,
+}
+
+impl StartGeneIndex {
+    fn new(genes: &[(String, String, String)]) -> Self {
+        let mut kmers: HashMap<Vec<u8>, Vec<(usize, bool, usize)>> = HashMap::new();
+        for (i, (_, _, seq)) in genes.iter().enumerate() {
+            let forward = seq.as_bytes().to_vec();
+            for (strand_seq, strand) in [(reverse_complement(&forward), false), (forward, true)] {
+                for (pos, kmer) in strand_seq.windows(ROTATE_KMER_SIZE).enumerate() {
+                    kmers.entry(kmer.to_vec()).or_default().push((i, strand, pos));
+                }
+            }
+        }
+        let gene_lengths = genes.iter().map(|(_, _, seq)| seq.len()).collect();
+        StartGeneIndex { kmers, gene_lengths }
+    }
+
+    fn find(&self, seq: &[u8]) -> Option<StartGeneHit> {
+        // Finds the best-matching start gene in a circular sequence. Each k-mer hit gives a
+        // diagonal: where the gene (or its reverse complement) would begin in the sequence.
+        let length = seq.len();
+        if length < ROTATE_KMER_SIZE {
+            return None;
+        }
+        let mut circular_seq = seq.to_vec();
+        circular_seq.extend_from_slice(&seq[..ROTATE_KMER_SIZE - 1]);
+        let mut diagonals: HashMap<(usize, bool), Vec<usize>> = HashMap::new();
+        for (seq_pos, kmer) in circular_seq.windows(ROTATE_KMER_SIZE).enumerate() {
+            for &(gene, strand, gene_pos) in self.kmers.get(kmer).into_iter().flatten() {
+                let diagonal = (seq_pos + length - gene_pos % length) % length;
+                diagonals.entry((gene, strand)).or_default().push(diagonal);
+            }
+        }
+        let mut best: Option<(f64, StartGeneHit)> = None;
+        for ((gene, strand), diags) in diagonals {
+            let Some((count, diagonal)) = best_band(&diags, length) else { continue; };
+            let kmer_count = self.gene_lengths[gene] - ROTATE_KMER_SIZE + 1;
+            let fraction = count as f64 / kmer_count as f64;
+            if fraction < ROTATE_MIN_KMER_FRACTION ||
+                    best.as_ref().is_some_and(|(f, _)| fraction <= *f) {
+                continue;
+            }
+            // On the reverse strand, the diagonal is where the gene's reverse complement begins,
+            // so the gene begins at the mirrored position in the reverse-complemented sequence.
+            // The gene can be longer than the sequence, so this is done with signed arithmetic.
+            let start = if strand { diagonal } else {
+                (-(diagonal as isize) - self.gene_lengths[gene] as isize)
+                    .rem_euclid(length as isize) as usize
+            };
+            best = Some((fraction, StartGeneHit { gene, forward: strand, start }));
+        }
+        best.map(|(_, hit)| hit)
+    }
+}
+
+
+fn best_band(diagonals: &[usize], length: usize) -> Option<(usize, usize)> {
+    // Groups diagonals into bands and returns the hit count and median diagonal of the best band
+    // (counting hits in neighbouring bands too, so a gene straddling two bands isn't split).
+    let band_count = length.div_ceil(ROTATE_BAND_SIZE);
+    let mut bands: HashMap<usize, Vec<usize>> = HashMap::new();
+    for &d in diagonals {
+        bands.entry(d / ROTATE_BAND_SIZE).or_default().push(d);
+    }
+    let mut best: Option<(usize, usize)> = None;
+    for &band in bands.keys() {
+        let mut neighbours = vec![(band + band_count - 1) % band_count, band,
+                                  (band + 1) % band_count];
+        neighbours.sort_unstable();
+        neighbours.dedup();
+        let mut hits: Vec<usize> = neighbours.iter()
+            .filter_map(|b| bands.get(b)).flatten().cloned().collect();
+        if best.is_some_and(|(count, _)| hits.len() <= count) {
+            continue;
+        }
+        hits.sort_unstable();
+        best = Some((hits.len(), hits[hits.len() / 2]));
+    }
+    best
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::tests::random_seq;
+
+    #[test]
+    fn test_rotate_forward() {
+        let gene = random_seq(300, 1);
+        let (before, after) = (random_seq(2000, 2), random_seq(1000, 3));
+        let seq = format!("{}{}{}", before, gene, after);
+        let genes = vec![("dnaA".to_string(), "dnaA".to_string(), gene.clone())];
+        let sequences = vec![("1".to_string(), "1 length=3300 circular=true".to_string(), seq),
+                             ("2".to_string(), "2 length=3300".to_string(), random_seq(3300, 4))];
+        let rotated = rotate_sequences(&sequences, &genes);
+        assert_eq!(rotated[0].0, "1 length=3300 circular=true start_gene=dnaA");
+        assert_eq!(rotated[0].1, format!("{}{}{}", gene, after, before));
+        assert_eq!(rotated[1].0, "2 length=3300");
+    }
+
+    #[test]
+    fn test_rotate_reverse() {
+        // The gene is on the reverse strand and spans the sequence's start-end junction.
+        let gene = random_seq(300, 5);
+        let gene_rc = String::from_utf8(reverse_complement(gene.as_bytes())).unwrap();
+        let middle = random_seq(3000, 6);
+        let seq = format!("{}{}{}", &gene_rc[100..], middle, &gene_rc[..100]);
+        let index = StartGeneIndex::new(&[("repA".to_string(), "repA".to_string(), gene.clone())]);
+        let hit = index.find(seq.as_bytes()).unwrap();
+        assert!(!hit.forward);
+        let rotated = String::from_utf8(rotate_to_hit(seq.as_bytes(), &hit)).unwrap();
+        assert!(rotated.starts_with(&gene));
+        assert_eq!(rotated.len(), seq.len());
+    }
+
+    #[test]
+    fn test_rotate_reverse_long_gene() {
+        // A reverse-strand gene more than twice the length of the sequence (e.g. a plasmid
+        // fragment) still gives a start position within the sequence.
+        let gene = random_seq(1000, 9);
+        let gene_rc = String::from_utf8(reverse_complement(gene.as_bytes())).unwrap();
+        let seq = &gene_rc[200..650];
+        let index = StartGeneIndex::new(&[("repA".to_string(), "repA".to_string(), gene)]);
+        let hit = index.find(seq.as_bytes()).unwrap();
+        assert!(!hit.forward);
+        assert!(hit.start < seq.len());
+        assert_eq!(rotate_to_hit(seq.as_bytes(), &hit).len(), seq.len());
+    }
+
+    #[test]
+    fn test_no_start_gene() {
+        let index = StartGeneIndex::new(&[("dnaA".to_string(), "dnaA".to_string(),
+                                           random_seq(300, 7))]);
+        assert!(index.find(random_seq(5000, 8).as_bytes()).is_none());
+        assert!(index.find(b"ACGT").is_none());
+    }
+}
@@ -12,6 +12,7 @@
 // License along with Autocycler. If not, see <http://www.gnu.org/licenses/>.
 
 use std::fmt;
+use xxhash_rust::xxh3::xxh3_64;
 
 use crate::misc::{quit_with_error, reverse_complement, up_to_first_space, after_first_space};
 
@@ -25,6 +26,42 @@ pub struct Sequence {
     pub contig_header: String,
     pub length: usize,
     pub cluster: u16,
+
+    // How much this sequence contributes to the depth of the unitigs it passes through. This is
+    // the summed weight of its assembly and its duplicates' assemblies, each of which is 1.0
+    // unless a weight was given for that assembly.
+    pub weight: f64,
+
+    // Checksum of the original contig sequence, so reconstructions can be verified. This is only
+    // set for sequences which still trace their input contig exactly.
+    pub checksum: Option<u64>,
+
+    // Bases per line in the input FASTA, so decompress can preserve the original wrapping. This
+    // is 0 if the contig was on one line (or its wrapping is unknown).
+    pub line_width: usize,
+
+    // The contig's name in its input assembly, if compress renamed it to make names unique.
+    pub original_name: Option<String>,
+
+    // Identical contigs in other assemblies which --dedup_contigs collapsed into this one.
+    pub duplicates: Vec<Duplicate>,
+}
+
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct Duplicate {
+    pub filename: String,
+    pub contig_header: String,
+    pub reverse: bool,  // whether the duplicate is the reverse complement of the sequence
+}
+
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct Repair {
+    // A stretch of a sequence made of k-mers which --min_kmer_count removed from the graph, so
+    // its path skips these bases. pos is where they go in the sequence (forward strand).
+    pub pos: u32,
+    pub seq: Vec<u8>,
 }
 
 impl Sequence {
@@ -40,6 +77,7 @@ impl Sequence {
         if !forward_seq.iter().all(|&c| matches!(c, b'A' | b'C' | b'G' | b'T')) {
             quit_with_error(&format!("{} contains non-ACGT characters", filename));
         }
+        let checksum = Some(sequence_checksum(&forward_seq));
 
         let padding = vec![b'.'; half_k as usize];
         forward_seq.splice(0..0, padding.iter().cloned());
@@ -55,6 +93,11 @@ impl Sequence {
             contig_header,
             length,
             cluster: 0,
+            weight: 1.0,
+            checksum,
+            line_width: 0,
+            original_name: None,
+            duplicates: Vec::new(),
         }
     }
 
@@ -71,6 +114,11 @@ impl Sequence {
             contig_header,
             length,
             cluster,
+            weight: 1.0,
+            checksum: None,
+            line_width: 0,
+            original_name: None,
+            duplicates: Vec::new(),
         }
     }
 
@@ -82,6 +130,25 @@ impl Sequence {
         after_first_space(&self.contig_header)
     }
 
+    pub fn multiplicity(&self) -> usize {
+        1 + self.duplicates.len()
+    }
+
+    pub fn filenames(&self) -> impl Iterator<Item = &str> {
+        // The assemblies this sequence came from, including those of its duplicates.
+        std::iter::once(self.filename.as_str())
+            .chain(self.duplicates.iter().map(|d| d.filename.as_str()))
+    }
+
+    pub fn original_header(&self) -> String {
+        // The contig's header as it was in its input assembly, i.e. with any renaming undone.
+        match &self.original_name {
+            Some(name) if self.contig_description().is_empty() => name.clone(),
+            Some(name) => format!("{} {}", name, self.contig_description()),
+            None => self.contig_header.clone(),
+        }
+    }
+
     pub fn string_for_newick(&self) -> String {
         format!("{}__{}__{}__{}_bp", self.id, self.filename, self.contig_name(), self.length)
     }
@@ -96,3 +163,9 @@ impl fmt::Display for Sequence {
 impl fmt::Debug for Sequence {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result { fmt::Display::fmt(self, f) }
 }
+
+
+pub fn sequence_checksum(seq: &[u8]) -> u64 {
+    // A fast non-cryptographic hash, used to check that a contig was reconstructed exactly.
+    xxh3_64(seq)
+}
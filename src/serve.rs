@@ -0,0 +1,496 @@
+// This file contains the code for the autocycler serve subcommand.
+
+// Copyright 2024 Ryan Wick (rrwick@gmail.com)
+// https://github.com/rrwick/Autocycler
+
+// This file is part of Autocycler. Autocycler is free software: you can redistribute it and/or
+// modify it under the terms of the GNU General Public License as published by the Free Software
+// Foundation, either version 3 of the License, or (at your option) any later version. Autocycler
+// is distributed in the hope that it will be useful, but WITHOUT ANY WARRANTY; without even the
+// implied warranty of MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the GNU General
+// Public License for more details. You should have received a copy of the GNU General Public
+// License along with Autocycler. If not, see <http://www.gnu.org/licenses/>.
+
+use serde::Serialize;
+use std::collections::{BTreeMap, BTreeSet, HashMap};
+use std::fs;
+use std::io::{self, BufRead, BufReader, Write};
+use std::net::{TcpListener, TcpStream};
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::thread;
+use std::time::Duration;
+
+use crate::log::{section_header, explanation};
+use crate::misc::{check_if_dir_exists, check_if_file_exists, quit_with_error};
+use crate::table::find_all_yaml_files;
+use crate::unitig_graph::UnitigGraph;
+
+
+pub fn serve(autocycler_dir: PathBuf, gfa: String, port: u16) {
+    let gfa = autocycler_dir.join(gfa);
+    check_settings(&autocycler_dir, &gfa);
+    starting_message();
+    print_settings(&autocycler_dir, &gfa, port);
+    let data = load_data(&autocycler_dir, &gfa);
+
+    let listener = match TcpListener::bind(("127.0.0.1", port)) {
+        Ok(listener) => listener,
+        Err(e) => quit_with_error(&format!("unable to listen on port {}\n{}", port, e)),
+    };
+    section_header("Serving");
+    eprintln!("Open http://127.0.0.1:{}/ in a web browser. Press Ctrl-C to stop.", port);
+    eprintln!();
+    serve_connections(listener, Arc::new(data));
+}
+
+
+// Browsers often open connections before they have a request to send (and may never send one),
+// so each connection gets its own thread and gives up if no request arrives in time.
+const READ_TIMEOUT: Duration = Duration::from_secs(10);
+
+
+fn serve_connections(listener: TcpListener, data: Arc<ServeData>) {
+    for stream in listener.incoming() {
+        let mut stream = match stream {
+            Ok(stream) => stream,
+            Err(e) => { eprintln!("Warning: connection failed: {}", e); continue; }
+        };
+        let data = Arc::clone(&data);
+        thread::spawn(move || {
+            if let Err(e) = handle_connection(&mut stream, &data) {
+                eprintln!("Warning: connection failed: {}", e);
+            }
+        });
+    }
+}
+
+
+fn check_settings(autocycler_dir: &Path, gfa: &Path) {
+    check_if_dir_exists(autocycler_dir);
+    check_if_file_exists(gfa);
+}
+
+
+fn starting_message() {
+    section_header("Starting autocycler serve");
+    explanation("This command starts a local web server for viewing an Autocycler directory in a \
+                 browser: the unitig graph (coloured by cluster), the clusters and the metrics \
+                 from each step.");
+}
+
+
+fn print_settings(autocycler_dir: &Path, gfa: &Path, port: u16) {
+    eprintln!("Settings:");
+    eprintln!("  --autocycler_dir {}", autocycler_dir.display());
+    eprintln!("  --gfa {}", gfa.file_name().unwrap_or_default().to_string_lossy());
+    eprintln!("  --port {}", port);
+    eprintln!();
+}
+
+
+struct ServeData {
+    // Everything the page needs is made once at startup and served as JSON.
+    graph_json: String,
+    clusters_json: String,
+    metrics_json: String,
+}
+
+
+#[derive(Serialize)]
+struct GraphJson {
+    k_size: u32,
+    unitigs: Vec<UnitigJson>,
+    links: Vec<(u32, u32)>,  // unitig numbers, ignoring strands
+}
+
+
+#[derive(Serialize)]
+struct UnitigJson {
+    number: u32,
+    length: u32,
+    depth: f64,
+    clusters: Vec<u16>,
+}
+
+
+fn load_data(autocycler_dir: &Path, gfa: &Path) -> ServeData {
+    section_header("Loading data");
+    explanation("The unitig graph, clustering results and metrics are now loaded.");
+    let (graph, _) = UnitigGraph::from_gfa_file(gfa);
+    graph.print_basic_graph_info();
+    let seq_clusters = load_sequence_clusters(&autocycler_dir.join("clustering")
+                                                            .join("clustering.tsv"));
+    let metrics = load_metrics(autocycler_dir);
+    let clusters = metrics.get("clustering/clustering.yaml").and_then(|m| m.get("clusters"))
+                          .cloned().unwrap_or(serde_json::Value::Array(Vec::new()));
+    eprintln!("{} metrics file{} found", metrics.len(), match metrics.len() { 1 => "", _ => "s" });
+    eprintln!();
+    ServeData { graph_json: serde_json::to_string(&graph_json(&graph, &seq_clusters)).unwrap(),
+                clusters_json: serde_json::to_string(&clusters).unwrap(),
+                metrics_json: serde_json::to_string(&metrics).unwrap() }
+}
+
+
+fn graph_json(graph: &UnitigGraph, seq_clusters: &HashMap<u16, u16>) -> GraphJson {
+    // Each unitig is one node, and its clusters are those of the sequences passing through it.
+    let unitigs = graph.unitigs.iter().map(|u| {
+        let clusters: BTreeSet<u16> = u.forward_positions.iter()
+            .filter_map(|p| seq_clusters.get(&p.seq_id()).copied()).collect();
+        UnitigJson { number: u.number, length: u.length(), depth: u.depth,
+                     clusters: clusters.into_iter().collect() }
+    }).collect();
+    let mut links = BTreeSet::new();
+    for u in &graph.unitigs {
+        let a = u.number as i32;
+        for b in graph.outgoing_strands(a).into_iter().chain(graph.outgoing_strands(-a)) {
+            let b = b.unsigned_abs();
+            links.insert((a.unsigned_abs().min(b), a.unsigned_abs().max(b)));
+        }
+    }
+    GraphJson { k_size: graph.k_size, unitigs, links: links.into_iter().collect() }
+}
+
+
+fn load_sequence_clusters(clustering_tsv: &Path) -> HashMap<u16, u16> {
+    // Returns each sequence's cluster, from the all_clusters and sequence_id columns. Before
+    // autocycler cluster is run, there are no clusters.
+    let Ok(content) = fs::read_to_string(clustering_tsv) else { return HashMap::new(); };
+    content.lines().skip(1).filter_map(|line| {
+        let parts: Vec<&str> = line.split('\t').collect();
+        Some((parts.get(3)?.parse().ok()?, parts.get(2)?.parse().ok()?))
+    }).collect()
+}
+
+
+fn load_metrics(autocycler_dir: &Path) -> BTreeMap<String, serde_json::Value> {
+    // Every YAML file in the directory, keyed by its path relative to the directory. Unreadable
+    // files are skipped.
+    find_all_yaml_files(autocycler_dir).into_iter().filter_map(|path| {
+        let content = fs::read_to_string(&path).ok()?;
+        let value: serde_json::Value = serde_yaml::from_str(&content).ok()?;
+        let name = path.strip_prefix(autocycler_dir).unwrap_or(&path).to_string_lossy()
+                       .replace('\\', "/");
+        Some((name, value))
+    }).collect()
+}
+
+
+fn handle_connection(stream: &mut TcpStream, data: &ServeData) -> io::Result<()> {
+    stream.set_read_timeout(Some(READ_TIMEOUT))?;
+    let mut reader = BufReader::new(stream.try_clone()?);
+    let mut request_line = String::new();
+    reader.read_line(&mut request_line)?;
+    loop {  // the headers aren't needed, but are read so the browser isn't cut off
+        let mut header = String::new();
+        if reader.read_line(&mut header)? == 0 || header.trim().is_empty() { break; }
+    }
+    let (status, content_type, body) = route(&request_line, data);
+    write!(stream, "HTTP/1.1 {}\r\nContent-Type: {}\r\nContent-Length: {}\r\n\
+                    Connection: close\r\n\r\n", status, content_type, body.len())?;
+    stream.write_all(body.as_bytes())?;
+    stream.flush()
+}
+
+
+fn route<'a>(request_line: &str, data: &'a ServeData) -> (&'static str, &'static str, &'a str) {
+    // Returns the status, content type and body for a request line, e.g. "GET / HTTP/1.1".
+    let mut parts = request_line.split_whitespace();
+    let (method, target) = (parts.next().unwrap_or(""), parts.next().unwrap_or(""));
+    if method != "GET" {
+        return ("405 Method Not Allowed", "text/plain", "method not allowed");
+    }
+    let path = target.split('?').next().unwrap_or("");
+    match path {
+        "/" | "/index.html" => ("200 OK", "text/html; charset=utf-8", INDEX_HTML),
+        "/graph.json"       => ("200 OK", "application/json", &data.graph_json),
+        "/clusters.json"    => ("200 OK", "application/json", &data.clusters_json),
+        "/metrics.json"     => ("200 OK", "application/json", &data.metrics_json),
+        _                   => ("404 Not Found", "text/plain", "not found"),
+    }
+}
+
+
+// A self-contained page (no external scripts or styles). The graph is drawn on a canvas with a
+// simple force-directed layout: nodes repel their neighbours (found with a grid) and links act as
+// springs. Dragging pans, the mouse wheel zooms and clicking a unitig shows its details.
+const INDEX_HTML: &str = r##"<!DOCTYPE html>
+<html>
+<head>
+<meta charset="utf-8">
+<title>Autocycler</title>
+<style>
+body { font-family: sans-serif; margin: 0; display: flex; height: 100vh; }
+#graph { flex: 1; position: relative; background: #fafafa; }
+canvas { width: 100%; height: 100%; display: block; cursor: grab; }
+#side { width: 380px; overflow-y: auto; padding: 1em; border-left: 1px solid #ccc; }
+#info { min-height: 3em; }
+table { border-collapse: collapse; font-size: 90%; }
+th, td { border: 1px solid #ccc; padding: 2px 6px; text-align: left; }
+.pass { color: #1a7f37; } .fail { color: #cf222e; }
+pre { font-size: 80%; background: #f4f4f4; padding: 0.5em; overflow-x: auto; }
+</style>
+</head>
+<body>
+<div id="graph"><canvas id="canvas"></canvas></div>
+<div id="side">
+<h2>Autocycler</h2>
+<form id="search"><input id="query" placeholder="Unitig number" size="12">
+<button type="submit">Find</button></form>
+<p id="info">Click a unitig or search for one.</p>
+<h3>Clusters</h3><div id="clusters"></div>
+<h3>Metrics</h3><div id="metrics"></div>
+</div>
+<script>
+const PALETTE = ["#1f77b4", "#ff7f0e", "#2ca02c", "#d62728", "#9467bd", "#8c564b", "#e377c2",
+                 "#17becf"];
+const canvas = document.getElementById("canvas");
+const ctx = canvas.getContext("2d");
+let nodes = [], edges = [], byNumber = new Map(), passing = new Set();
+let view = {x: 0, y: 0, scale: 1}, selected = null, alpha = 1;
+
+function colour(n) {
+  const c = n.clusters.find(c => passing.has(c));
+  return c === undefined ? "#999999" : PALETTE[(c - 1) % PALETTE.length];
+}
+
+function resize() {
+  canvas.width = canvas.clientWidth * devicePixelRatio;
+  canvas.height = canvas.clientHeight * devicePixelRatio;
+  draw();
+}
+
+function step() {
+  const cell = 150, grid = new Map();
+  for (const n of nodes) {
+    const key = Math.floor(n.x / cell) + "," + Math.floor(n.y / cell);
+    if (!grid.has(key)) grid.set(key, []);
+    grid.get(key).push(n);
+  }
+  for (const a of nodes) {
+    const gx = Math.floor(a.x / cell), gy = Math.floor(a.y / cell);
+    for (let i = -1; i <= 1; i++) for (let j = -1; j <= 1; j++) {
+      for (const b of grid.get((gx + i) + "," + (gy + j)) || []) {
+        if (a === b) continue;
+        const dx = a.x - b.x, dy = a.y - b.y, d2 = Math.max(dx * dx + dy * dy, 1);
+        const f = 400 * alpha / d2;
+        a.vx += dx * f; a.vy += dy * f;
+      }
+    }
+    a.vx -= a.x * 0.002 * alpha; a.vy -= a.y * 0.002 * alpha;
+  }
+  for (const [a, b] of edges) {
+    const dx = b.x - a.x, dy = b.y - a.y, d = Math.max(Math.hypot(dx, dy), 1);
+    const f = (d - (a.r + b.r + 20)) * 0.05 * alpha / d;
+    a.vx += dx * f; a.vy += dy * f; b.vx -= dx * f; b.vy -= dy * f;
+  }
+  for (const n of nodes) {
+    n.x += n.vx; n.y += n.vy; n.vx *= 0.6; n.vy *= 0.6;
+  }
+  alpha *= 0.99;
+}
+
+function draw() {
+  const s = view.scale * devicePixelRatio;
+  ctx.setTransform(1, 0, 0, 1, 0, 0);
+  ctx.clearRect(0, 0, canvas.width, canvas.height);
+  ctx.setTransform(s, 0, 0, s, view.x * devicePixelRatio, view.y * devicePixelRatio);
+  ctx.strokeStyle = "#bbbbbb"; ctx.lineWidth = 1 / view.scale;
+  ctx.beginPath();
+  for (const [a, b] of edges) { ctx.moveTo(a.x, a.y); ctx.lineTo(b.x, b.y); }
+  ctx.stroke();
+  for (const n of nodes) {
+    ctx.beginPath(); ctx.arc(n.x, n.y, n.r, 0, 2 * Math.PI);
+    ctx.fillStyle = colour(n); ctx.fill();
+    if (n === selected) { ctx.strokeStyle = "#000000"; ctx.lineWidth = 3 / view.scale; ctx.stroke(); }
+  }
+  ctx.fillStyle = "#000000"; ctx.font = (12 / view.scale) + "px sans-serif";
+  for (const n of nodes) {
+    if (n.r * view.scale > 8 || n === selected) ctx.fillText(n.number, n.x + n.r, n.y - n.r);
+  }
+}
+
+function animate() {
+  if (alpha > 0.005) { step(); draw(); requestAnimationFrame(animate); }
+}
+
+function toGraph(event) {
+  const rect = canvas.getBoundingClientRect();
+  return [(event.clientX - rect.left - view.x) / view.scale,
+          (event.clientY - rect.top - view.y) / view.scale];
+}
+
+function select(n) {
+  selected = n;
+  document.getElementById("info").textContent = n === null ? "No unitig selected." :
+    "Unitig " + n.number + ": " + n.length + " bp, depth " + n.depth.toFixed(2) + "x, cluster" +
+    (n.clusters.length === 1 ? " " : "s ") + (n.clusters.join(", ") || "none");
+  draw();
+}
+
+let drag = null;
+canvas.addEventListener("mousedown", e => { drag = {x: e.clientX, y: e.clientY, moved: false}; });
+window.addEventListener("mouseup", e => {
+  if (drag && !drag.moved) {
+    const [x, y] = toGraph(e);
+    select(nodes.find(n => Math.hypot(n.x - x, n.y - y) <= Math.max(n.r, 4 / view.scale)) || null);
+  }
+  drag = null;
+});
+window.addEventListener("mousemove", e => {
+  if (!drag) return;
+  view.x += e.clientX - drag.x; view.y += e.clientY - drag.y;
+  if (Math.abs(e.clientX - drag.x) + Math.abs(e.clientY - drag.y) > 0) drag.moved = true;
+  drag.x = e.clientX; drag.y = e.clientY;
+  draw();
+});
+canvas.addEventListener("wheel", e => {
+  e.preventDefault();
+  const rect = canvas.getBoundingClientRect();
+  const mx = e.clientX - rect.left, my = e.clientY - rect.top;
+  const factor = Math.exp(-e.deltaY * 0.001);
+  view.x = mx - (mx - view.x) * factor; view.y = my - (my - view.y) * factor;
+  view.scale *= factor;
+  draw();
+}, {passive: false});
+document.getElementById("search").addEventListener("submit", e => {
+  e.preventDefault();
+  const n = byNumber.get(parseInt(document.getElementById("query").value));
+  if (n === undefined) { document.getElementById("info").textContent = "Unitig not found."; return; }
+  view.x = canvas.clientWidth / 2 - n.x * view.scale;
+  view.y = canvas.clientHeight / 2 - n.y * view.scale;
+  select(n);
+});
+
+function showClusters(clusters) {
+  const div = document.getElementById("clusters");
+  if (clusters.length === 0) { div.textContent = "Not clustered yet."; return; }
+  const table = document.createElement("table");
+  table.insertRow().innerHTML = "<th>Cluster</th><th>QC</th><th>Contigs</th><th>Median length</th>";
+  for (const c of clusters) {
+    const row = table.insertRow();
+    row.insertCell().textContent = c.cluster;
+    const qc = row.insertCell();
+    qc.textContent = c.pass ? "pass" : "fail"; qc.className = c.pass ? "pass" : "fail";
+    row.insertCell().textContent = c.contig_count;
+    row.insertCell().textContent = c.median_length;
+    if (c.pass) row.cells[0].style.color = PALETTE[(c.cluster - 1) % PALETTE.length];
+  }
+  div.appendChild(table);
+}
+
+function showMetrics(metrics) {
+  const div = document.getElementById("metrics");
+  for (const [name, value] of Object.entries(metrics)) {
+    const details = document.createElement("details");
+    const summary = document.createElement("summary");
+    const pre = document.createElement("pre");
+    summary.textContent = name; pre.textContent = JSON.stringify(value, null, 2);
+    details.appendChild(summary); details.appendChild(pre); div.appendChild(details);
+  }
+}
+
+Promise.all(["graph.json", "clusters.json", "metrics.json"].map(u => fetch(u).then(r => r.json())))
+  .then(([graph, clusters, metrics]) => {
+    passing = new Set(clusters.filter(c => c.pass).map(c => c.cluster));
+    nodes = graph.unitigs.map((u, i) => Object.assign({}, u, {
+      x: 10 * Math.sqrt(i) * Math.cos(i * 2.4), y: 10 * Math.sqrt(i) * Math.sin(i * 2.4),
+      vx: 0, vy: 0, r: Math.min(Math.max(Math.sqrt(u.length) / 4, 3), 40)}));
+    for (const n of nodes) byNumber.set(n.number, n);
+    edges = graph.links.filter(([a, b]) => a !== b).map(([a, b]) => [byNumber.get(a), byNumber.get(b)]);
+    view.x = canvas.clientWidth / 2; view.y = canvas.clientHeight / 2;
+    showClusters(clusters); showMetrics(metrics);
+    resize(); animate();
+  });
+window.addEventListener("resize", resize);
+</script>
+</body>
+</html>
+"##;
+
+
+#[cfg(test)]
+mod tests {
+    use std::io::Read;
+
+    use super::*;
+    use crate::test_gfa::*;
+    use crate::tests::make_test_file;
+
+    fn test_data() -> ServeData {
+        ServeData { graph_json: "{\"k_size\":1}".to_string(), clusters_json: "[]".to_string(),
+                    metrics_json: "{}".to_string() }
+    }
+
+    #[test]
+    fn test_route() {
+        let data = test_data();
+        assert_eq!(route("GET / HTTP/1.1", &data).0, "200 OK");
+        assert_eq!(route("GET /graph.json?x=1 HTTP/1.1", &data),
+                   ("200 OK", "application/json", "{\"k_size\":1}"));
+        assert_eq!(route("GET /clusters.json HTTP/1.1", &data).2, "[]");
+        assert_eq!(route("GET /other HTTP/1.1", &data).0, "404 Not Found");
+        assert_eq!(route("POST / HTTP/1.1", &data).0, "405 Method Not Allowed");
+        assert_eq!(route("", &data).0, "405 Method Not Allowed");
+    }
+
+    #[test]
+    fn test_graph_json() {
+        // Unitig 3 is only in b.fasta, which is in cluster 2.
+        let (graph, _) = UnitigGraph::from_gfa_lines(&get_test_gfa_15());
+        let seq_clusters = HashMap::from([(1, 1), (2, 2), (3, 1)]);
+        let json = graph_json(&graph, &seq_clusters);
+        assert_eq!(json.unitigs.len(), graph.unitigs.len());
+        let unitig_3 = json.unitigs.iter().find(|u| u.number == 3).unwrap();
+        assert_eq!(unitig_3.clusters, vec![2]);
+        assert!(json.links.iter().all(|(a, b)| a <= b));
+        assert!(!json.links.is_empty());
+    }
+
+    #[test]
+    fn test_load_sequence_clusters() {
+        let dir = tempfile::tempdir().unwrap();
+        let tsv = dir.path().join("clustering.tsv");
+        make_test_file(&tsv, "node_name\tpassing_clusters\tall_clusters\tsequence_id\t\
+                              file_name\tcontig_name\tlength\n\
+                              a\tnone\t1\t1\ta.fasta\tx\t100\n\
+                              b\t2\t2\t2\tb.fasta\ty\t100\n");
+        assert_eq!(load_sequence_clusters(&tsv), HashMap::from([(1, 1), (2, 2)]));
+        assert!(load_sequence_clusters(&dir.path().join("missing.tsv")).is_empty());
+    }
+
+    #[test]
+    fn test_handle_connection() {
+        let listener = TcpListener::bind(("127.0.0.1", 0)).unwrap();
+        let port = listener.local_addr().unwrap().port();
+        let client = std::thread::spawn(move || {
+            let mut stream = TcpStream::connect(("127.0.0.1", port)).unwrap();
+            stream.write_all(b"GET /metrics.json HTTP/1.1\r\nHost: localhost\r\n\r\n").unwrap();
+            let mut response = String::new();
+            stream.read_to_string(&mut response).unwrap();
+            response
+        });
+        let (mut stream, _) = listener.accept().unwrap();
+        handle_connection(&mut stream, &test_data()).unwrap();
+        drop(stream);
+        let response = client.join().unwrap();
+        assert!(response.starts_with("HTTP/1.1 200 OK\r\n"));
+        assert!(response.contains("Content-Length: 2\r\n"));
+        assert!(response.ends_with("\r\n\r\n{}"));
+    }
+
+    #[test]
+    fn test_serve_connections() {
+        // A connection which never sends a request mustn't stop others from being served.
+        let listener = TcpListener::bind(("127.0.0.1", 0)).unwrap();
+        let port = listener.local_addr().unwrap().port();
+        std::thread::spawn(move || serve_connections(listener, Arc::new(test_data())));
+        let _idle = TcpStream::connect(("127.0.0.1", port)).unwrap();
+        let mut stream = TcpStream::connect(("127.0.0.1", port)).unwrap();
+        stream.write_all(b"GET /clusters.json HTTP/1.1\r\n\r\n").unwrap();
+        let mut response = String::new();
+        stream.read_to_string(&mut response).unwrap();
+        assert!(response.starts_with("HTTP/1.1 200 OK\r\n"));
+        assert!(response.ends_with("\r\n\r\n[]"));
+    }
+}
@@ -0,0 +1,379 @@
+// This file contains the code for the autocycler simulate subcommand.
+
+// Copyright 2024 Ryan Wick (rrwick@gmail.com)
+// https://github.com/rrwick/Autocycler
+
+// This file is part of Autocycler. Autocycler is free software: you can redistribute it and/or
+// modify it under the terms of the GNU General Public License as published by the Free Software
+// Foundation, either version 3 of the License, or (at your option) any later version. Autocycler
+// is distributed in the hope that it will be useful, but WITHOUT ANY WARRANTY; without even the
+// implied warranty of MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the GNU General
+// Public License for more details. You should have received a copy of the GNU General Public
+// License along with Autocycler. If not, see <http://www.gnu.org/licenses/>.
+
+use rand::{rngs::StdRng, Rng, SeedableRng};
+use rand::seq::{IteratorRandom, SliceRandom};
+use std::fs::File;
+use std::io::{BufWriter, Write};
+use std::path::{Path, PathBuf};
+
+use crate::log::{section_header, explanation};
+use crate::misc::{check_if_dir_is_not_dir, create_dir, quit_with_error, reverse_complement,
+                  write_fasta_seq};
+use crate::subsample::parse_genome_size;
+
+
+const BASES: [u8; 4] = [b'A', b'C', b'G', b'T'];
+const MAX_INDEL_LEN: usize = 10;
+
+
+#[derive(Clone, Debug, PartialEq)]
+struct Replicon {
+    name: String,
+    seq: Vec<u8>,
+    circular: bool,
+}
+
+
+pub struct SimulateSettings {
+    // The settings for autocycler simulate, named as their options.
+    pub out_dir: PathBuf,
+    pub genome_size: String,
+    pub plasmids: String,
+    pub repeats: usize,
+    pub repeat_length: usize,
+    pub count: usize,
+    pub snps: usize,
+    pub indels: usize,
+    pub misjoins: usize,
+    pub seed: u64,
+}
+
+
+pub fn simulate(settings: SimulateSettings) {
+    let genome_size = parse_genome_size(&settings.genome_size) as usize;
+    let plasmids = parse_plasmid_sizes(&settings.plasmids);
+    check_settings(&settings, genome_size, &plasmids);
+    starting_message();
+    print_settings(&settings, genome_size, &plasmids);
+    let SimulateSettings { out_dir, repeats, repeat_length, count, snps, indels, misjoins, seed,
+                           .. } = settings;
+    let mut rng = StdRng::seed_from_u64(seed);
+
+    section_header("Making genome");
+    explanation("A random reference genome is now made, with copies of a random repeat sequence \
+                 inserted at random positions and orientations.");
+    let genome = make_genome(genome_size, &plasmids, repeats, repeat_length, &mut rng);
+    create_dir(&out_dir);
+    let reference = out_dir.join("reference.fasta");
+    save_replicons(&reference, &genome);
+    for replicon in &genome {
+        eprintln!("{}: {} bp", replicon.name, replicon.seq.len());
+    }
+    eprintln!();
+
+    section_header("Making assemblies");
+    explanation("Each simulated assembly is a copy of the genome with random SNPs and indels. \
+                 Every replicon gets a random starting position and strand, and assemblies with \
+                 a mis-join have two replicons wrongly joined into one linear contig (or a \
+                 segment inverted, if there is only one replicon).");
+    let assemblies_dir = out_dir.join("assemblies");
+    create_dir(&assemblies_dir);
+    let misjoined: Vec<usize> = (0..count).choose_multiple(&mut rng, misjoins);
+    for i in 0..count {
+        let misjoin = misjoined.contains(&i);
+        let assembly = perturb_genome(&genome, snps, indels, misjoin, &mut rng);
+        let filename = assemblies_dir.join(format!("assembly_{:02}.fasta", i + 1));
+        save_replicons(&filename, &assembly);
+        eprintln!("{}: {} contig{}, {} bp{}", filename.display(), assembly.len(),
+                  match assembly.len() { 1 => "", _ => "s" },
+                  assembly.iter().map(|r| r.seq.len()).sum::<usize>(),
+                  if misjoin { ", mis-join" } else { "" });
+    }
+    eprintln!();
+    finished_message(&reference, &assemblies_dir);
+}
+
+
+fn check_settings(settings: &SimulateSettings, genome_size: usize, plasmids: &[usize]) {
+    let SimulateSettings { ref out_dir, repeats, repeat_length, count, misjoins, .. } = *settings;
+    check_if_dir_is_not_dir(out_dir);
+    if genome_size < 1000 { quit_with_error("--genome_size must be at least 1000"); }
+    if plasmids.iter().any(|&p| p < 100) { quit_with_error("plasmids must be at least 100 bp"); }
+    if count < 1 { quit_with_error("--count must be at least 1"); }
+    if misjoins > count { quit_with_error("--misjoins cannot be greater than --count"); }
+    if repeats > 0 && repeat_length < 1 {
+        quit_with_error("--repeat_length must be at least 1");
+    }
+}
+
+
+fn starting_message() {
+    section_header("Starting autocycler simulate");
+    explanation("This command makes a synthetic genome and a set of imperfect assemblies of it, \
+                 which can be used as realistic test inputs for the Autocycler pipeline.");
+}
+
+
+fn print_settings(settings: &SimulateSettings, genome_size: usize, plasmids: &[usize]) {
+    let SimulateSettings { ref out_dir, repeats, repeat_length, count, snps, indels, misjoins,
+                           seed, .. } = *settings;
+    eprintln!("Settings:");
+    eprintln!("  --out_dir {}", out_dir.display());
+    eprintln!("  --genome_size {}", genome_size);
+    if !plasmids.is_empty() {
+        eprintln!("  --plasmids {}", plasmids.iter().map(|p| p.to_string())
+                                             .collect::<Vec<_>>().join(","));
+    }
+    eprintln!("  --repeats {}", repeats);
+    eprintln!("  --repeat_length {}", repeat_length);
+    eprintln!("  --count {}", count);
+    eprintln!("  --snps {}", snps);
+    eprintln!("  --indels {}", indels);
+    eprintln!("  --misjoins {}", misjoins);
+    eprintln!("  --seed {}", seed);
+    eprintln!();
+}
+
+
+fn finished_message(reference: &Path, assemblies_dir: &Path) {
+    section_header("Finished!");
+    explanation("You can now run autocycler compress on the simulated assemblies, and then use \
+                 autocycler benchmark to compare the final consensus to the reference.");
+    eprintln!("Reference genome:     {}", reference.display());
+    eprintln!("Simulated assemblies: {}", assemblies_dir.display());
+    eprintln!();
+}
+
+
+fn parse_plasmid_sizes(plasmids: &str) -> Vec<usize> {
+    // Plasmid sizes are comma-delimited and can use the same suffixes as genome sizes, e.g. 50k.
+    plasmids.split(',').map(|p| p.trim()).filter(|p| !p.is_empty())
+            .map(|p| parse_genome_size(p) as usize).collect()
+}
+
+
+fn make_genome(genome_size: usize, plasmids: &[usize], repeats: usize, repeat_length: usize,
+               rng: &mut StdRng) -> Vec<Replicon> {
+    // The chromosome is genome_size long and the plasmids follow it. Repeat copies are extra
+    // sequence, inserted into the replicons in proportion to their length.
+    let mut genome = vec![Replicon { name: "chromosome".to_string(),
+                                     seq: random_bases(genome_size, rng), circular: true }];
+    for (i, &size) in plasmids.iter().enumerate() {
+        genome.push(Replicon { name: format!("plasmid_{}", i + 1),
+                               seq: random_bases(size, rng), circular: true });
+    }
+    let repeat = random_bases(repeat_length, rng);
+    for _ in 0..repeats {
+        let copy = if rng.gen_bool(0.5) { repeat.clone() } else { reverse_complement(&repeat) };
+        let replicon = random_replicon(&mut genome, rng);
+        let pos = rng.gen_range(0..=replicon.seq.len());
+        replicon.seq.splice(pos..pos, copy);
+    }
+    genome
+}
+
+
+fn perturb_genome(genome: &[Replicon], snps: usize, indels: usize, misjoin: bool,
+                  rng: &mut StdRng) -> Vec<Replicon> {
+    let mut assembly = genome.to_vec();
+    for _ in 0..snps {
+        add_snp(random_replicon(&mut assembly, rng), rng);
+    }
+    for _ in 0..indels {
+        add_indel(random_replicon(&mut assembly, rng), rng);
+    }
+    for replicon in &mut assembly {
+        relocate_start(replicon, rng);
+    }
+    if misjoin {
+        add_misjoin(&mut assembly, rng);
+    }
+    for (i, replicon) in assembly.iter_mut().enumerate() {
+        replicon.name = (i + 1).to_string();
+    }
+    assembly
+}
+
+
+fn random_bases(length: usize, rng: &mut StdRng) -> Vec<u8> {
+    (0..length).map(|_| *BASES.choose(rng).unwrap()).collect()
+}
+
+
+fn random_replicon<'a>(replicons: &'a mut [Replicon], rng: &mut StdRng) -> &'a mut Replicon {
+    // Chooses a replicon with probability proportional to its length.
+    let total: usize = replicons.iter().map(|r| r.seq.len()).sum();
+    let mut pos = rng.gen_range(0..total.max(1));
+    let last = replicons.len() - 1;
+    for (i, replicon) in replicons.iter_mut().enumerate() {
+        if pos < replicon.seq.len() || i == last { return replicon; }
+        pos -= replicon.seq.len();
+    }
+    unreachable!()
+}
+
+
+fn add_snp(replicon: &mut Replicon, rng: &mut StdRng) {
+    if replicon.seq.is_empty() { return; }
+    let pos = rng.gen_range(0..replicon.seq.len());
+    let old_base = replicon.seq[pos];
+    replicon.seq[pos] = *BASES.iter().filter(|&&b| b != old_base).choose(rng).unwrap();
+}
+
+
+fn add_indel(replicon: &mut Replicon, rng: &mut StdRng) {
+    let length = rng.gen_range(1..=MAX_INDEL_LEN);
+    if rng.gen_bool(0.5) || replicon.seq.len() <= length {
+        let pos = rng.gen_range(0..=replicon.seq.len());
+        replicon.seq.splice(pos..pos, random_bases(length, rng));
+    } else {
+        let pos = rng.gen_range(0..=replicon.seq.len() - length);
+        replicon.seq.drain(pos..pos + length);
+    }
+}
+
+
+fn relocate_start(replicon: &mut Replicon, rng: &mut StdRng) {
+    // Circular replicons get a random starting position, and all replicons a random strand.
+    if replicon.circular && !replicon.seq.is_empty() {
+        let start = rng.gen_range(0..replicon.seq.len());
+        replicon.seq.rotate_left(start);
+    }
+    if rng.gen_bool(0.5) {
+        replicon.seq = reverse_complement(&replicon.seq);
+    }
+}
+
+
+fn add_misjoin(assembly: &mut Vec<Replicon>, rng: &mut StdRng) {
+    // With multiple replicons, two are joined into one linear contig. With only one, a random
+    // segment (up to half its length) is inverted in place.
+    if assembly.len() > 1 {
+        let i = rng.gen_range(0..assembly.len());
+        let mut j = rng.gen_range(0..assembly.len() - 1);
+        if j >= i { j += 1; }
+        let second = assembly[j].seq.clone();
+        assembly[i].seq.extend(second);
+        assembly[i].circular = false;
+        assembly.remove(j);
+    } else if let Some(replicon) = assembly.first_mut() {
+        let length = rng.gen_range(1..=(replicon.seq.len() / 2).max(1));
+        let start = rng.gen_range(0..=replicon.seq.len() - length);
+        let inverted = reverse_complement(&replicon.seq[start..start + length]);
+        replicon.seq.splice(start..start + length, inverted);
+        replicon.circular = false;
+    }
+}
+
+
+fn save_replicons(filename: &Path, replicons: &[Replicon]) {
+    let result: std::io::Result<()> = (|| {
+        let mut writer = BufWriter::new(File::create(filename)?);
+        for r in replicons {
+            writeln!(writer, ">{} length={}{}", r.name, r.seq.len(),
+                     if r.circular { " circular=true" } else { "" })?;
+            write_fasta_seq(&mut writer, std::str::from_utf8(&r.seq).unwrap(), 0)?;
+        }
+        writer.flush()
+    })();
+    if let Err(e) = result {
+        quit_with_error(&format!("unable to write {}\n{}", filename.display(), e));
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::misc::load_fasta;
+
+    fn replicon(seq: &str, circular: bool) -> Replicon {
+        Replicon { name: "r".to_string(), seq: seq.as_bytes().to_vec(), circular }
+    }
+
+    #[test]
+    fn test_parse_plasmid_sizes() {
+        assert_eq!(parse_plasmid_sizes(""), Vec::<usize>::new());
+        assert_eq!(parse_plasmid_sizes("50k, 2000,"), vec![50000, 2000]);
+    }
+
+    #[test]
+    fn test_make_genome() {
+        let mut rng = StdRng::seed_from_u64(0);
+        let genome = make_genome(10000, &[2000, 500], 3, 100, &mut rng);
+        assert_eq!(genome.iter().map(|r| r.name.as_str()).collect::<Vec<_>>(),
+                   vec!["chromosome", "plasmid_1", "plasmid_2"]);
+        assert_eq!(genome.iter().map(|r| r.seq.len()).sum::<usize>(), 12800);
+        assert!(genome.iter().all(|r| r.circular));
+
+        // The same seed gives the same genome.
+        let mut rng = StdRng::seed_from_u64(0);
+        assert_eq!(make_genome(10000, &[2000, 500], 3, 100, &mut rng), genome);
+    }
+
+    #[test]
+    fn test_add_snp_and_indel() {
+        let mut rng = StdRng::seed_from_u64(1);
+        let original = replicon(&"ACGT".repeat(25), true);
+        let mut r = original.clone();
+        add_snp(&mut r, &mut rng);
+        assert_eq!(r.seq.len(), 100);
+        assert_eq!(r.seq.iter().zip(&original.seq).filter(|(a, b)| a != b).count(), 1);
+        for _ in 0..20 {
+            let mut r = original.clone();
+            add_indel(&mut r, &mut rng);
+            let diff = r.seq.len().abs_diff(100);
+            assert!((1..=MAX_INDEL_LEN).contains(&diff));
+        }
+    }
+
+    #[test]
+    fn test_relocate_start() {
+        // A relocated circular replicon is a rotation of the original on one of the strands.
+        let mut rng = StdRng::seed_from_u64(2);
+        let original = "AACCCGTGTTAGCATGCAGT";
+        let doubled = original.repeat(2);
+        let doubled_rc = String::from_utf8(reverse_complement(doubled.as_bytes())).unwrap();
+        for _ in 0..10 {
+            let mut r = replicon(original, true);
+            relocate_start(&mut r, &mut rng);
+            let seq = String::from_utf8(r.seq).unwrap();
+            assert!(doubled.contains(&seq) || doubled_rc.contains(&seq));
+        }
+    }
+
+    #[test]
+    fn test_add_misjoin() {
+        let mut rng = StdRng::seed_from_u64(3);
+        let mut assembly = vec![replicon("AAAA", true), replicon("CCC", true)];
+        add_misjoin(&mut assembly, &mut rng);
+        assert_eq!(assembly.len(), 1);
+        assert_eq!(assembly[0].seq.len(), 7);
+        assert!(!assembly[0].circular);
+
+        let mut assembly = vec![replicon("AAAAAAAAAA", true)];
+        add_misjoin(&mut assembly, &mut rng);
+        assert_eq!(assembly.len(), 1);
+        assert!(assembly[0].seq.contains(&b'T'));
+        assert!(!assembly[0].circular);
+    }
+
+    #[test]
+    fn test_simulate() {
+        let dir = tempfile::tempdir().unwrap();
+        let out_dir = dir.path().join("sim");
+        simulate(SimulateSettings { out_dir: out_dir.clone(), genome_size: "5k".to_string(),
+                                    plasmids: "1k".to_string(), repeats: 2, repeat_length: 200,
+                                    count: 3, snps: 2, indels: 2, misjoins: 1, seed: 0 });
+        let reference = load_fasta(&out_dir.join("reference.fasta"));
+        assert_eq!(reference.len(), 2);
+        assert!(reference[0].1.contains("circular=true"));
+        let assemblies_dir = out_dir.join("assemblies");
+        let contig_counts: Vec<usize> = (1..=3).map(|i| {
+            load_fasta(&assemblies_dir.join(format!("assembly_{:02}.fasta", i))).len()
+        }).collect();
+        assert_eq!(contig_counts.iter().filter(|&&c| c == 1).count(), 1);
+        assert_eq!(contig_counts.iter().filter(|&&c| c == 2).count(), 2);
+    }
+}
@@ -0,0 +1,133 @@
+// This file defines a k-mer sketch for estimating sequence similarity without a unitig graph.
+
+// Copyright 2024 Ryan Wick (rrwick@gmail.com)
+// https://github.com/rrwick/Autocycler
+
+// This file is part of Autocycler. Autocycler is free software: you can redistribute it and/or
+// modify it under the terms of the GNU General Public License as published by the Free Software
+// Foundation, either version 3 of the License, or (at your option) any later version. Autocycler
+// is distributed in the hope that it will be useful, but WITHOUT ANY WARRANTY; without even the
+// implied warranty of MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the GNU General
+// Public License for more details. You should have received a copy of the GNU General Public
+// License along with Autocycler. If not, see <http://www.gnu.org/licenses/>.
+
+use xxhash_rust::xxh3::xxh3_64;
+
+use crate::misc::reverse_complement;
+
+
+pub struct Sketch {
+    // This is a FracMinHash sketch: the hashes of all canonical k-mers which fall below a
+    // threshold set by the scaling factor, so roughly one in every `scaled` k-mers is kept. Unlike
+    // a fixed-size MinHash sketch, this supports containment as well as Jaccard estimates, which
+    // matters when comparing a small contig against a much larger one.
+    pub k_size: usize,
+    hashes: Vec<u64>,  // sorted and deduplicated
+}
+
+impl Sketch {
+    pub fn new(seq: &[u8], k_size: usize, scaled: u64) -> Sketch {
+        let max_hash = u64::MAX / scaled;
+        let mut hashes = Vec::new();
+        if seq.len() >= k_size {
+            let rev_seq = reverse_complement(seq);
+            let n = seq.len();
+            for i in 0..=(n - k_size) {
+                let forward = &seq[i..i + k_size];
+                let reverse = &rev_seq[n - i - k_size..n - i];
+                let hash = xxh3_64(forward.min(reverse));
+                if hash <= max_hash {
+                    hashes.push(hash);
+                }
+            }
+        }
+        hashes.sort_unstable();
+        hashes.dedup();
+        Sketch { k_size, hashes }
+    }
+
+    fn len(&self) -> usize {
+        self.hashes.len()
+    }
+
+    fn intersection_size(&self, other: &Sketch) -> usize {
+        let (mut i, mut j, mut count) = (0, 0, 0);
+        while i < self.hashes.len() && j < other.hashes.len() {
+            match self.hashes[i].cmp(&other.hashes[j]) {
+                std::cmp::Ordering::Less => i += 1,
+                std::cmp::Ordering::Greater => j += 1,
+                std::cmp::Ordering::Equal => { count += 1; i += 1; j += 1; }
+            }
+        }
+        count
+    }
+
+    pub fn jaccard(&self, other: &Sketch) -> f64 {
+        let intersection = self.intersection_size(other);
+        let union = self.len() + other.len() - intersection;
+        if union == 0 { 0.0 } else { intersection as f64 / union as f64 }
+    }
+
+    pub fn containment(&self, other: &Sketch) -> f64 {
+        // The fraction of this sketch's k-mers which are also in the other sketch.
+        if self.hashes.is_empty() { 0.0 }
+        else { self.intersection_size(other) as f64 / self.len() as f64 }
+    }
+
+    pub fn mash_distance(&self, other: &Sketch) -> f64 {
+        // Symmetrical Mash distance (Ondov et al. 2016), which estimates the per-base divergence
+        // from the Jaccard index.
+        let jaccard = self.jaccard(other);
+        if jaccard == 0.0 {
+            return 1.0;
+        }
+        if jaccard == 1.0 {
+            return 0.0;  // avoids a negative zero from the log
+        }
+        let distance = -(2.0 * jaccard / (1.0 + jaccard)).ln() / self.k_size as f64;
+        distance.clamp(0.0, 1.0)
+    }
+
+    pub fn ani_distance(&self, other: &Sketch) -> f64 {
+        // One minus the containment ANI (average nucleotide identity) of this sequence in the
+        // other. Like the graph-based distance, this is asymmetrical: a small sequence contained
+        // in a larger one has a small distance, but not vice versa.
+        let containment = self.containment(other);
+        if containment == 0.0 {
+            return 1.0;
+        }
+        1.0 - containment.powf(1.0 / self.k_size as f64)
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_sketch() {
+        let seq = b"ACGACTACGACTACGACTGACTACGACTATCGACTACGAC";
+        let sketch = Sketch::new(seq, 5, 1);
+        assert_eq!(sketch.len(), Sketch::new(&reverse_complement(seq), 5, 1).len());
+        assert!(Sketch::new(seq, 5, 4).len() < sketch.len());
+        assert_eq!(Sketch::new(b"ACGT", 5, 1).len(), 0);
+    }
+
+    #[test]
+    fn test_distances() {
+        let a = Sketch::new(b"ACGACTACGACTAGCATCAGCATCAGCTACGACGTACGACT", 5, 1);
+        let b = Sketch::new(b"CATCAGCATCAGCTACGACG", 5, 1);
+        let c = Sketch::new(b"TTTTTTTTTTTTTTT", 5, 1);
+        assert_eq!(a.jaccard(&a), 1.0);
+        assert_eq!(a.mash_distance(&a), 0.0);
+        assert_eq!(a.ani_distance(&a), 0.0);
+        assert_eq!(b.containment(&a), 1.0);
+        assert_eq!(b.ani_distance(&a), 0.0);
+        assert!(a.ani_distance(&b) > 0.0);
+        assert_eq!(a.mash_distance(&b), b.mash_distance(&a));
+        assert!(a.mash_distance(&b) > 0.0);
+        assert_eq!(a.mash_distance(&c), 1.0);
+        assert_eq!(a.ani_distance(&c), 1.0);
+    }
+}
@@ -0,0 +1,251 @@
+// This file contains the code for the autocycler stats subcommand.
+
+// Copyright 2024 Ryan Wick (rrwick@gmail.com)
+// https://github.com/rrwick/Autocycler
+
+// This file is part of Autocycler. Autocycler is free software: you can redistribute it and/or
+// modify it under the terms of the GNU General Public License as published by the Free Software
+// Foundation, either version 3 of the License, or (at your option) any later version. Autocycler
+// is distributed in the hope that it will be useful, but WITHOUT ANY WARRANTY; without even the
+// implied warranty of MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the GNU General
+// Public License for more details. You should have received a copy of the GNU General Public
+// License along with Autocycler. If not, see <http://www.gnu.org/licenses/>.
+
+use serde::Serialize;
+use std::path::{Path, PathBuf};
+
+use crate::misc::{check_if_file_exists, first_char_in_file, format_float, load_fasta,
+                  load_file_lines, quit_with_error};
+use crate::unitig_graph::UnitigGraph;
+
+
+pub fn stats(input: PathBuf, format: String) {
+    check_settings(&input, &format);
+    let stats = match first_char_in_file(&input) {
+        Ok('>') => fasta_stats(&load_fasta(&input)),
+        Ok('H') | Ok('S') => {
+            let gfa_lines = load_file_lines(&input);
+            if has_k_size(&gfa_lines) { graph_stats(&UnitigGraph::from_gfa_file(&input).0) }
+                                 else { segment_stats(&gfa_lines) }
+        }
+        _ => quit_with_error(&format!("{} is neither GFA nor FASTA", input.display())),
+    };
+    print!("{}", format_stats(&stats, &format));
+}
+
+
+fn check_settings(input: &Path, format: &str) {
+    check_if_file_exists(input);
+    if !["text", "tsv", "json"].contains(&format) {
+        quit_with_error("--format must be text, tsv or json");
+    }
+}
+
+
+#[derive(Serialize, Debug, Default, PartialEq)]
+struct AssemblyStats {
+    sequence_count: usize,
+    total_length: u64,
+    n50: u64,
+    longest: u64,
+    gc: f64,
+    circular_count: usize,
+
+    // Only set for graphs: single-direction link count and number of unitig ends without a link.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    link_count: Option<usize>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    dead_end_count: Option<usize>,
+}
+
+
+fn fasta_stats(seqs: &[(String, String, String)]) -> AssemblyStats {
+    // Sequences are counted as circular if their header says so, as in Autocycler's consensus
+    // FASTA (circular=true) and some assemblers' output (circular=yes).
+    let circular_count = seqs.iter().filter(|(_, header, _)| {
+        let header = header.to_lowercase();
+        header.contains("circular=true") || header.contains("circular=yes")
+    }).count();
+    let seqs: Vec<&[u8]> = seqs.iter().map(|(_, _, seq)| seq.as_bytes()).collect();
+    AssemblyStats { circular_count, ..sequence_stats(&seqs) }
+}
+
+
+fn graph_stats(graph: &UnitigGraph) -> AssemblyStats {
+    // Each unitig is counted as a sequence, and it is circular if it has only a circularising link.
+    let seqs: Vec<Vec<u8>> = graph.unitigs.iter().map(|u| u.forward_seq.clone())
+                                          .collect();
+    let seqs: Vec<&[u8]> = seqs.iter().map(|s| s.as_slice()).collect();
+    let circular_count = graph.unitigs.iter().enumerate()
+        .filter(|(h, u)| u.is_isolated_and_circular(*h)).count();
+    let dead_end_count = graph.unitigs.iter()
+        .map(|u| u.blunt_start() as usize + u.blunt_end() as usize).sum();
+    AssemblyStats { circular_count, link_count: Some(graph.link_count().1),
+                    dead_end_count: Some(dead_end_count), ..sequence_stats(&seqs) }
+}
+
+
+fn has_k_size(gfa_lines: &[String]) -> bool {
+    // Autocycler's unitig graphs have a k-mer size in their header, but some of its GFAs (e.g.
+    // combine's consensus_assembly.gfa) don't and can't be loaded as a UnitigGraph.
+    gfa_lines.iter().filter(|l| l.starts_with("H\t"))
+             .any(|l| l.split('\t').any(|p| p.starts_with("KM:i:")))
+}
+
+
+fn segment_stats(gfa_lines: &[String]) -> AssemblyStats {
+    // Stats for a GFA which isn't a unitig graph, taken from its segments. A segment is circular
+    // if it has a circularising link and no other links. Graph-level stats are left unset.
+    let gfa2 = gfa_lines.iter().any(|l| l.starts_with("H\t") && l.contains("\tVN:Z:2.0"));
+    let mut segments = Vec::new();
+    let mut links = Vec::new();
+    for line in gfa_lines {
+        let parts: Vec<&str> = line.split('\t').collect();
+        match parts[0] {
+            "S" => {
+                let seq = parts.get(if gfa2 { 3 } else { 2 }).unwrap_or_else(|| {
+                    quit_with_error(&format!("S line has too few fields: {}", line));
+                });
+                segments.push((parts[1], seq.as_bytes()));
+            }
+            "L" if parts.len() >= 5 => links.push((format!("{}{}", parts[1], parts[2]),
+                                                    format!("{}{}", parts[3], parts[4]))),
+            "E" if parts.len() >= 4 => links.push((parts[2].to_string(), parts[3].to_string())),
+            _ => {}
+        }
+    }
+    let circular_count = segments.iter().filter(|(name, _)| {
+        let (pos, neg) = (format!("{}+", name), format!("{}-", name));
+        let touching: Vec<_> = links.iter().filter(|(a, b)| [a, b].iter()
+            .any(|s| **s == pos || **s == neg)).collect();
+        !touching.is_empty() && touching.iter().all(|(a, b)| a == b)
+    }).count();
+    let seqs: Vec<&[u8]> = segments.iter().map(|(_, seq)| *seq).collect();
+    AssemblyStats { circular_count, ..sequence_stats(&seqs) }
+}
+
+
+fn sequence_stats(seqs: &[&[u8]]) -> AssemblyStats {
+    let lengths: Vec<u64> = seqs.iter().map(|s| s.len() as u64).collect();
+    let total_length: u64 = lengths.iter().sum();
+    let gc_count = seqs.iter().flat_map(|s| s.iter())
+                       .filter(|&&b| matches!(b, b'G' | b'C' | b'g' | b'c')).count();
+    let gc = if total_length > 0 { gc_count as f64 / total_length as f64 } else { 0.0 };
+    AssemblyStats { sequence_count: seqs.len(), total_length, n50: n50(&lengths),
+                    longest: lengths.iter().copied().max().unwrap_or(0), gc,
+                    ..Default::default() }
+}
+
+
+fn n50(lengths: &[u64]) -> u64 {
+    // The length of the sequence which takes the cumulative length (from longest to shortest) to
+    // half the total or more.
+    let mut lengths = lengths.to_vec();
+    lengths.sort_unstable_by(|a, b| b.cmp(a));
+    let total: u64 = lengths.iter().sum();
+    let mut cumulative = 0;
+    for length in lengths {
+        cumulative += length;
+        if cumulative * 2 >= total { return length; }
+    }
+    0
+}
+
+
+fn format_stats(stats: &AssemblyStats, format: &str) -> String {
+    let mut fields = vec![("sequence_count", stats.sequence_count.to_string()),
+                          ("total_length", stats.total_length.to_string()),
+                          ("n50", stats.n50.to_string()),
+                          ("longest", stats.longest.to_string()),
+                          ("gc", format!("{:.4}", stats.gc)),
+                          ("circular_count", stats.circular_count.to_string())];
+    if let Some(link_count) = stats.link_count {
+        fields.push(("link_count", link_count.to_string()));
+    }
+    if let Some(dead_end_count) = stats.dead_end_count {
+        fields.push(("dead_end_count", dead_end_count.to_string()));
+    }
+    match format {
+        "json" => format!("{}\n", serde_json::to_string_pretty(stats).unwrap()),
+        "tsv" => {
+            let names: Vec<&str> = fields.iter().map(|(n, _)| *n).collect();
+            let values: Vec<&str> = fields.iter().map(|(_, v)| v.as_str()).collect();
+            format!("{}\n{}\n", names.join("\t"), values.join("\t"))
+        }
+        _ => {
+            let mut text = String::new();
+            for (name, value) in &fields {
+                let value = if *name == "gc" { format!("{}%", format_float(stats.gc * 100.0)) }
+                            else { value.clone() };
+                text.push_str(&format!("{:<15} {}\n", format!("{}:", name), value));
+            }
+            text
+        }
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_gfa::*;
+
+    #[test]
+    fn test_n50() {
+        assert_eq!(n50(&[]), 0);
+        assert_eq!(n50(&[10]), 10);
+        assert_eq!(n50(&[2, 3, 4, 1]), 3);
+        assert_eq!(n50(&[8, 1, 1]), 8);
+    }
+
+    #[test]
+    fn test_fasta_stats() {
+        let seqs = vec![("a".to_string(), "a length=8 circular=true".to_string(),
+                         "ACGTACGT".to_string()),
+                        ("b".to_string(), "b".to_string(), "GG".to_string())];
+        let stats = fasta_stats(&seqs);
+        assert_eq!(stats, AssemblyStats { sequence_count: 2, total_length: 10, n50: 8, longest: 8,
+                                          gc: 0.6, circular_count: 1, link_count: None,
+                                          dead_end_count: None });
+        assert_eq!(format_stats(&stats, "tsv"),
+                   "sequence_count\ttotal_length\tn50\tlongest\tgc\tcircular_count\n\
+                    2\t10\t8\t8\t0.6000\t1\n");
+        assert!(format_stats(&stats, "text").contains("gc:             60%\n"));
+        assert!(!format_stats(&stats, "json").contains("link_count"));
+    }
+
+    #[test]
+    fn test_graph_stats() {
+        let (graph, _) = UnitigGraph::from_gfa_lines(&get_test_gfa_15());
+        let stats = graph_stats(&graph);
+        assert_eq!(stats.sequence_count, 6);
+        assert_eq!(stats.total_length, 39);
+        assert_eq!(stats.longest, 10);
+        assert_eq!(stats.circular_count, 0);
+        assert_eq!(stats.link_count, Some(7));
+        assert_eq!(stats.dead_end_count, Some(2));
+        assert!(format_stats(&stats, "json").contains("\"dead_end_count\": 2"));
+    }
+
+    #[test]
+    fn test_segment_stats() {
+        // Like combine's consensus_assembly.gfa, with no k-mer size or depths.
+        let gfa: Vec<String> = ["H\tVN:Z:1.0",
+                                "S\t1\tACGTACGTAC\tCL:z:orangered\tRT:Z:consensus",
+                                "L\t1\t+\t1\t+\t0M", "L\t1\t-\t1\t-\t0M",
+                                "S\t2\tGGGG\tRT:Z:consensus", "S\t3\tACGT\tRT:Z:consensus",
+                                "L\t2\t+\t3\t-\t0M", "L\t3\t+\t2\t-\t0M"]
+            .iter().map(|s| s.to_string()).collect();
+        assert!(!has_k_size(&gfa));
+        assert_eq!(segment_stats(&gfa),
+                   AssemblyStats { sequence_count: 3, total_length: 18, n50: 10, longest: 10,
+                                   gc: 11.0 / 18.0, circular_count: 1, link_count: None,
+                                   dead_end_count: None });
+        let gfa2: Vec<String> = ["H\tVN:Z:2.0", "S\t1\t8\tACGTACGT\tRT:Z:consensus",
+                                 "E\t*\t1+\t1+\t8$\t8$\t0\t0\t0M", "S\t2\t2\tGG"]
+            .iter().map(|s| s.to_string()).collect();
+        let stats = segment_stats(&gfa2);
+        assert_eq!((stats.sequence_count, stats.total_length, stats.circular_count), (2, 10, 1));
+        assert!(has_k_size(&get_test_gfa_15()));
+    }
+}
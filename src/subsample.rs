@@ -13,47 +13,133 @@
 
 #![allow(clippy::needless_range_loop)]
 
+use fxhash::FxHashSet;
 use rand::{rngs::StdRng, SeedableRng};
+use rand::Rng;
 use rand::seq::SliceRandom;
-use seq_io::fastq::Record;
-use std::collections::HashSet;
-use std::fs::File;
+use seq_io::fastq::{Record, RefRecord};
+use std::collections::{BTreeMap, HashSet};
 use std::path::{Path, PathBuf};
 
 use crate::log::{section_header, explanation};
 use crate::metrics::{ReadSetDetails, SubsampleMetrics};
 use crate::misc::{check_if_dir_is_not_dir, check_if_file_exists, create_dir, fastq_reader,
-                  format_float, quit_with_error, spinner};
+                  format_float, is_stdin, load_file_lines, output_writer_with_level,
+                  quit_with_error, spinner, OutputWriter};
 
 
-pub fn subsample(fastq_file: PathBuf, out_dir: PathBuf, genome_size_str: String,
-                 subset_count: usize, min_read_depth: f64, seed: u64) {
+pub struct SubsampleSettings {
+    // The settings for autocycler subsample, named as their options.
+    pub reads: Vec<PathBuf>,
+    pub out_dir: PathBuf,
+    pub genome_size: String,
+    pub count: usize,
+    pub min_read_depth: f64,
+    pub min_length: usize,
+    pub min_mean_q: f64,
+    pub dedup_reads: bool,
+    pub holdout: f64,
+    pub compress: String,
+    pub compress_level: u32,
+    pub stream: bool,
+    pub input_bases: Option<String>,
+    pub length_weighted: bool,
+    pub stratified: bool,
+    pub seed: u64,
+}
+
+impl SubsampleSettings {
+    pub fn new(reads: Vec<PathBuf>, out_dir: PathBuf, genome_size: String, count: usize) -> Self {
+        // Makes settings with the option defaults.
+        SubsampleSettings {
+            reads, out_dir, genome_size, count, min_read_depth: 25.0, min_length: 0,
+            min_mean_q: 0.0, dedup_reads: false, holdout: 0.0, compress: "none".to_string(),
+            compress_level: 6, stream: false, input_bases: None, length_weighted: false,
+            stratified: false, seed: 0,
+        }
+    }
+}
+
+
+pub fn subsample(settings: SubsampleSettings) {
+    let SubsampleSettings { ref reads, ref out_dir, count: subset_count, min_read_depth,
+                            min_length, min_mean_q, dedup_reads, holdout: holdout_fraction,
+                            length_weighted, stratified, seed, .. } = settings;
     let subsample_yaml = out_dir.join("subsample.yaml");
-    let genome_size = parse_genome_size(&genome_size_str);
-    check_settings(&fastq_file, &out_dir, genome_size, subset_count, min_read_depth);
-    create_dir(&out_dir);
+    let genome_size = parse_genome_size(&settings.genome_size);
+    let fastq_files = expand_read_files(reads);
+    let input_bases = settings.input_bases.as_ref().map(|b| parse_genome_size(b));
+    let gzip_level = check_settings(&settings, &fastq_files, genome_size, input_bases);
+    create_dir(out_dir);
     starting_message();
-    print_settings(&fastq_file, &out_dir, genome_size, subset_count, min_read_depth, seed);
+    print_settings(&settings, genome_size, input_bases, gzip_level);
+    let read_filter = ReadFilter::new(min_length, min_mean_q, dedup_reads);
+    let holdout = Holdout::new(holdout_fraction, seed);
 
     // TODO: add automatic genome size estimation
 
+    let run = SubsetRun { fastq_files: &fastq_files, read_filter: &read_filter, holdout: &holdout,
+                          subset_count, out_dir, gzip_level, seed };
     let mut metrics = SubsampleMetrics::default();
-    let (input_count, input_bases) = input_fastq_stats(&fastq_file, &mut metrics);
-    let reads_per_subset = calculate_subsets(input_count, input_bases, genome_size, min_read_depth);
-    save_subsets(&fastq_file, subset_count, input_count, reads_per_subset, &out_dir, seed,
-                 &mut metrics);
+    if let Some(input_bases) = input_bases {
+        let subset_ratio = calculate_stream_subsets(input_bases, genome_size, min_read_depth,
+                                                    holdout_fraction);
+        stream_subsets(&run, subset_ratio, &mut metrics);
+    } else {
+        let read_lengths = input_fastq_stats(&fastq_files, &read_filter, &holdout, &mut metrics);
+        let (reads_per_subset, bases_per_subset) = calculate_subsets(&read_lengths, genome_size,
+                                                                     min_read_depth,
+                                                                     length_weighted);
+        let subset_size = if length_weighted { SubsetSize::Bases(bases_per_subset) }
+                                        else { SubsetSize::Reads(reads_per_subset) };
+        save_subsets(&run, &read_lengths, subset_size, stratified, &mut metrics);
+    }
     metrics.save_to_yaml(&subsample_yaml);
     finished_message();
 }
 
 
-fn check_settings(fastq_file: &Path, out_dir: &Path, genome_size: u64, subset_count: usize,
-                  min_read_depth: f64) {
-    check_if_file_exists(fastq_file);
+fn check_settings(settings: &SubsampleSettings, fastq_files: &[PathBuf], genome_size: u64,
+                  input_bases: Option<u64>) -> Option<u32> {
+    // Returns the gzip compression level for subset files (or None for uncompressed files).
+    let SubsampleSettings { ref out_dir, count: subset_count, min_read_depth, min_mean_q,
+                            holdout: holdout_fraction, ref compress, compress_level, stream,
+                            length_weighted, stratified, .. } = *settings;
+    for fastq_file in fastq_files {
+        if is_stdin(fastq_file) {
+            if !stream { quit_with_error("reading reads from stdin (-) requires --stream"); }
+            continue;
+        }
+        check_if_file_exists(fastq_file);
+    }
+    if stream && input_bases.is_none() { quit_with_error("--stream requires --input_bases"); }
+    if !stream && input_bases.is_some() { quit_with_error("--input_bases requires --stream"); }
+    if input_bases == Some(0) { quit_with_error("--input_bases must be at least 1"); }
+    if stream && length_weighted {
+        quit_with_error("--length_weighted cannot be used with --stream");
+    }
+    if stratified && stream {
+        quit_with_error("--stratified cannot be used with --stream");
+    }
+    if stratified && length_weighted {
+        quit_with_error("--stratified cannot be used with --length_weighted");
+    }
     check_if_dir_is_not_dir(out_dir);
     if genome_size < 1 {       quit_with_error("--genome_size must be at least 1"); }
     if subset_count < 1 {      quit_with_error("--count must be at least 2"); }
     if min_read_depth <= 0.0 { quit_with_error("--min_read_depth must be greater than 0"); }
+    if min_mean_q < 0.0 {      quit_with_error("--min_mean_q cannot be negative"); }
+    if !(0.0..1.0).contains(&holdout_fraction) {
+        quit_with_error("--holdout must be at least 0 and less than 1");
+    }
+    if !(1..=9).contains(&compress_level) {
+        quit_with_error("--compress_level must be between 1 and 9");
+    }
+    match compress.as_str() {
+        "none" => None,
+        "gz" => Some(compress_level),
+        _ => { quit_with_error("--compress must be none or gz"); }
+    }
 }
 
 
@@ -64,20 +150,92 @@ fn starting_message() {
 }
 
 
-fn print_settings(fastq_file: &Path, out_dir: &Path, genome_size: u64, subset_count: usize,
-                  min_read_depth: f64, seed: u64) {
+fn print_settings(settings: &SubsampleSettings, genome_size: u64, input_bases: Option<u64>,
+                  gzip_level: Option<u32>) {
+    let SubsampleSettings { ref reads, ref out_dir, count: subset_count, min_read_depth,
+                            min_length, min_mean_q, dedup_reads, holdout: holdout_fraction,
+                            length_weighted, stratified, seed, .. } = *settings;
     eprintln!("Settings:");
-    eprintln!("  --reads {}", fastq_file.display());
+    let reads: Vec<String> = reads.iter().map(|r| r.display().to_string()).collect();
+    eprintln!("  --reads {}", reads.join(" "));
     eprintln!("  --out_dir {}", out_dir.display());
     eprintln!("  --genome_size {}", genome_size);
     eprintln!("  --count {}", subset_count);
     eprintln!("  --min_read_depth {}", format_float(min_read_depth));
+    if min_length > 0 {
+        eprintln!("  --min_length {}", min_length);
+    }
+    if min_mean_q > 0.0 {
+        eprintln!("  --min_mean_q {}", format_float(min_mean_q));
+    }
+    if dedup_reads {
+        eprintln!("  --dedup_reads");
+    }
+    if holdout_fraction > 0.0 {
+        eprintln!("  --holdout {}", format_float(holdout_fraction));
+    }
+    if let Some(input_bases) = input_bases {
+        eprintln!("  --stream");
+        eprintln!("  --input_bases {}", input_bases);
+    }
+    if length_weighted {
+        eprintln!("  --length_weighted");
+    }
+    if stratified {
+        eprintln!("  --stratified");
+    }
+    if let Some(level) = gzip_level {
+        eprintln!("  --compress gz");
+        eprintln!("  --compress_level {}", level);
+    }
     eprintln!("  --seed {}", seed);
     eprintln!();
 }
 
 
-fn parse_genome_size(genome_size_str: &str) -> u64 {
+fn expand_read_files(reads: &[PathBuf]) -> Vec<PathBuf> {
+    // Returns the FASTQ files to pool together as the input read set. Each read file is used as
+    // is, except for manifests (.txt or .fofn files) which list read files one per line. Relative
+    // paths in a manifest are relative to the manifest's directory.
+    let mut fastq_files = Vec::new();
+    for read_file in reads {
+        if !is_manifest(read_file) {
+            fastq_files.push(read_file.clone());
+            continue;
+        }
+        check_if_file_exists(read_file);
+        let manifest_dir = read_file.parent().unwrap_or(Path::new(""));
+        for line in load_file_lines(read_file) {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') { continue; }
+            fastq_files.push(manifest_dir.join(line));
+        }
+    }
+    if fastq_files.is_empty() {
+        quit_with_error("no read files given");
+    }
+    fastq_files
+}
+
+
+fn is_manifest(path: &Path) -> bool {
+    matches!(path.extension().and_then(|e| e.to_str()), Some("txt") | Some("fofn"))
+}
+
+
+fn for_each_read<F: FnMut(&RefRecord)>(fastq_files: &[PathBuf], mut f: F) {
+    // Calls the given function on each read of each FASTQ file, in order, so multiple files can be
+    // treated as one read set.
+    for fastq_file in fastq_files {
+        let mut reader = fastq_reader(fastq_file);
+        while let Some(record) = reader.next() {
+            f(&record.expect("Error reading FASTQ file"));
+        }
+    }
+}
+
+
+pub fn parse_genome_size(genome_size_str: &str) -> u64 {
     let genome_size_str = genome_size_str.trim().to_lowercase();
     if let Ok(size) = genome_size_str.parse::<f64>() {
         return size.round() as u64;
@@ -96,25 +254,213 @@ fn parse_genome_size(genome_size_str: &str) -> u64 {
 }
 
 
-fn input_fastq_stats(fastq_file: &Path, metrics: &mut SubsampleMetrics) -> (usize, u64) {
-    let mut read_lengths: Vec<u64> = fastq_reader(fastq_file).records()
-        .map(|record| record.expect("Error reading FASTQ file").seq().len() as u64).collect();
-    read_lengths.sort_unstable();
-    let details = ReadSetDetails::new(&read_lengths);
-    metrics.input_read_count = details.count;
-    metrics.input_read_bases = details.bases;
-    metrics.input_read_n50 = details.n50;
-    eprintln!("Input FASTQ:");
+#[derive(Clone)]
+struct ReadFilter {
+    min_length: usize,
+    min_mean_q: f64,
+
+    // For deduplication, hashes of the IDs and sequences of reads which have passed the filter are
+    // kept. Each pass through the reads should start with a fresh copy of the filter.
+    dedup_reads: bool,
+    seen_ids: FxHashSet<u64>,
+    seen_seqs: FxHashSet<u64>,
+}
+
+impl ReadFilter {
+    fn new(min_length: usize, min_mean_q: f64, dedup_reads: bool) -> Self {
+        ReadFilter { min_length, min_mean_q, dedup_reads,
+                     seen_ids: FxHashSet::default(), seen_seqs: FxHashSet::default() }
+    }
+
+    fn is_active(&self) -> bool {
+        self.min_length > 0 || self.min_mean_q > 0.0 || self.dedup_reads
+    }
+
+    fn check(&mut self, id: &[u8], seq: &[u8], qual: &[u8]) -> Result<(), FilterReason> {
+        // Length is checked first, so reads which fail both filters count as short. Only reads
+        // which pass the other filters are checked for duplication, so the first passing copy of
+        // a read is kept.
+        if seq.len() < self.min_length {
+            return Err(FilterReason::Short);
+        }
+        if self.min_mean_q > 0.0 && mean_qual(qual) < self.min_mean_q {
+            return Err(FilterReason::LowQual);
+        }
+        if self.dedup_reads {
+            let (id_hash, seq_hash) = (read_hash(id, 0), read_hash(seq, 0));
+            if self.seen_ids.contains(&id_hash) || self.seen_seqs.contains(&seq_hash) {
+                return Err(FilterReason::Duplicate);
+            }
+            self.seen_ids.insert(id_hash);
+            self.seen_seqs.insert(seq_hash);
+        }
+        Ok(())
+    }
+}
+
+enum FilterReason {
+    Short,
+    LowQual,
+    Duplicate,
+}
+
+
+fn count_filtered_read(result: Result<(), FilterReason>, metrics: &mut SubsampleMetrics) -> bool {
+    // Adds a filtered read to the metrics and returns whether the read passed.
+    match result {
+        Ok(()) => return true,
+        Err(FilterReason::Short) => metrics.filtered_short_read_count += 1,
+        Err(FilterReason::LowQual) => metrics.filtered_low_qual_read_count += 1,
+        Err(FilterReason::Duplicate) => metrics.filtered_duplicate_read_count += 1,
+    }
+    false
+}
+
+
+struct Holdout {
+    fraction: f64,
+    seed: u64,
+}
+
+// Mixed into the seed for holdout positions, so they are independent of the positions used to
+// choose subsets in --stream mode.
+const HOLDOUT_SEED: u64 = 0x9e3779b97f4a7c15;
+
+impl Holdout {
+    fn new(fraction: f64, seed: u64) -> Self {
+        Holdout { fraction, seed: seed ^ HOLDOUT_SEED }
+    }
+
+    fn is_active(&self) -> bool {
+        self.fraction > 0.0
+    }
+
+    fn contains(&self, read_id: &[u8]) -> bool {
+        // Reads are held out based on a hash of their ID, so the same reads are held out in every
+        // pass and in --stream mode, and about the given fraction of reads is held out.
+        self.is_active() && read_position(read_id, self.seed) < self.fraction
+    }
+
+    fn writer(&self, out_dir: &Path, gzip_level: Option<u32>) -> Option<OutputWriter> {
+        if !self.is_active() {
+            return None;
+        }
+        let extension = if gzip_level.is_some() { "fastq.gz" } else { "fastq" };
+        let holdout_filename = out_dir.join(format!("qc_reads.{}", extension));
+        eprintln!("held-out QC reads:");
+        eprintln!("  {}", holdout_filename.display());
+        eprintln!();
+        Some(subset_writer(&holdout_filename, gzip_level))
+    }
+}
+
+
+fn report_holdout_stats(holdout: &Holdout, details: ReadSetDetails,
+                        metrics: &mut SubsampleMetrics) {
+    if !holdout.is_active() {
+        return;
+    }
+    eprintln!("Held-out QC reads:");
     eprintln!("  Read count: {}", details.count);
     eprintln!("  Read bases: {}", details.bases);
     eprintln!("  Read N50 length: {} bp", details.n50);
     eprintln!();
-    (details.count, details.bases)
+    metrics.holdout_read_count = details.count;
+    metrics.holdout_read_bases = details.bases;
+    metrics.holdout_read_n50 = details.n50;
+}
+
+
+fn report_read_stats(header: &str, details: ReadSetDetails, pass_details: ReadSetDetails,
+                     read_filter: &ReadFilter, metrics: &mut SubsampleMetrics) {
+    // Prints stats on the input reads and the reads which passed filtering, and saves them to the
+    // metrics.
+    eprintln!("{}:", header);
+    eprintln!("  Read count: {}", details.count);
+    eprintln!("  Read bases: {}", details.bases);
+    eprintln!("  Read N50 length: {} bp", details.n50);
+    eprintln!();
+    if read_filter.is_active() {
+        eprintln!("Filtered reads:");
+        eprintln!("  Too short: {}", metrics.filtered_short_read_count);
+        eprintln!("  Low quality: {}", metrics.filtered_low_qual_read_count);
+        if read_filter.dedup_reads {
+            eprintln!("  Duplicates: {}", metrics.filtered_duplicate_read_count);
+        }
+        eprintln!("  Read count: {}", pass_details.count);
+        eprintln!("  Read bases: {}", pass_details.bases);
+        eprintln!("  Read N50 length: {} bp", pass_details.n50);
+        eprintln!();
+    }
+    if pass_details.count == 0 {
+        quit_with_error("no reads remain after filtering");
+    }
+    metrics.input_read_count = details.count;
+    metrics.input_read_bases = details.bases;
+    metrics.input_read_n50 = details.n50;
+    metrics.pass_read_count = pass_details.count;
+    metrics.pass_read_bases = pass_details.bases;
+    metrics.pass_read_n50 = pass_details.n50;
+}
+
+
+fn mean_qual(qual: &[u8]) -> f64 {
+    // Returns a read's mean quality, based on the mean of its per-base error probabilities (not
+    // the mean of its Phred scores, which would overstate the quality of reads with bad regions).
+    if qual.is_empty() {
+        return 0.0;
+    }
+    let total_error: f64 = qual.iter()
+        .map(|&q| 10.0_f64.powf(-(q.saturating_sub(33) as f64) / 10.0)).sum();
+    -10.0 * (total_error / qual.len() as f64).log10()
+}
+
+
+fn input_fastq_stats(fastq_files: &[PathBuf], read_filter: &ReadFilter, holdout: &Holdout,
+                     metrics: &mut SubsampleMetrics) -> Vec<u64> {
+    // Gathers stats on the input reads and on the reads which pass filtering (only the passing
+    // reads are used for subsetting). Returns the lengths of the passing reads in file order,
+    // excluding any held-out reads.
+    let mut read_lengths = Vec::new();
+    let mut pass_read_lengths = Vec::new();
+    let mut holdout_read_lengths = Vec::new();
+    let mut subset_read_lengths = Vec::new();
+    let mut filter = read_filter.clone();
+    for_each_read(fastq_files, |record| {
+        let read_length = record.seq().len() as u64;
+        read_lengths.push(read_length);
+        let result = filter.check(record.id_bytes(), record.seq(), record.qual());
+        if !count_filtered_read(result, metrics) {
+            return;
+        }
+        pass_read_lengths.push(read_length);
+        if holdout.contains(record.id_bytes()) {
+            holdout_read_lengths.push(read_length);
+        } else {
+            subset_read_lengths.push(read_length);
+        }
+    });
+    read_lengths.sort_unstable();
+    pass_read_lengths.sort_unstable();
+    holdout_read_lengths.sort_unstable();
+    let header = if fastq_files.len() == 1 { "Input FASTQ".to_string() }
+                                       else { format!("Input FASTQs ({} files)", fastq_files.len()) };
+    report_read_stats(&header, ReadSetDetails::new(&read_lengths),
+                      ReadSetDetails::new(&pass_read_lengths), read_filter, metrics);
+    report_holdout_stats(holdout, ReadSetDetails::new(&holdout_read_lengths), metrics);
+    if subset_read_lengths.is_empty() {
+        quit_with_error("no reads remain after holding out QC reads");
+    }
+    subset_read_lengths
 }
 
 
-fn calculate_subsets(read_count: usize, read_bases: u64, genome_size: u64, min_depth: f64)
-        -> usize {
+fn calculate_subsets(read_lengths: &[u64], genome_size: u64, min_depth: f64, length_weighted: bool)
+        -> (usize, u64) {
+    // Returns the number of reads and number of bases per subset. Subsets are normally sized by
+    // read count, but length-weighted subsets are sized by bases.
+    let read_count = read_lengths.len();
+    let read_bases: u64 = read_lengths.iter().sum();
     section_header("Calculating subset size");
     explanation("Autocycler will now calculate the number of reads to put in each subset.");
     let total_depth = read_bases as f64 / genome_size as f64;
@@ -122,6 +468,41 @@ fn calculate_subsets(read_count: usize, read_bases: u64, genome_size: u64, min_d
     eprintln!("Total read depth: {:.1}×", total_depth);
     eprintln!("Mean read length: {} bp", mean_read_length);
     eprintln!();
+    let subset_ratio = calculate_subset_ratio(total_depth, min_depth);
+    let reads_per_subset = (subset_ratio * read_count as f64).round() as usize;
+    let bases_per_subset = (subset_ratio * read_bases as f64).round() as u64;
+    if length_weighted {
+        eprintln!("  bases per subset: {}", bases_per_subset);
+    } else {
+        eprintln!("  reads per subset: {}", reads_per_subset);
+    }
+    eprintln!();
+    (reads_per_subset, bases_per_subset)
+}
+
+
+fn calculate_stream_subsets(input_bases: u64, genome_size: u64, min_depth: f64,
+                            holdout_fraction: f64) -> f64 {
+    // In streaming mode, the reads can't be counted before subsetting, so the subset size is
+    // calculated from the estimated input size and given as a fraction of the reads.
+    section_header("Calculating subset size");
+    explanation("Autocycler will now calculate the fraction of reads to put in each subset, \
+                 using the estimated size of the input reads.");
+    let mut total_depth = input_bases as f64 / genome_size as f64;
+    eprintln!("Estimated total read depth: {:.1}×", total_depth);
+    if holdout_fraction > 0.0 {
+        total_depth *= 1.0 - holdout_fraction;
+        eprintln!("Estimated depth excluding held-out reads: {:.1}×", total_depth);
+    }
+    eprintln!();
+    let subset_ratio = calculate_subset_ratio(total_depth, min_depth);
+    eprintln!("  fraction of reads per subset: {:.3}", subset_ratio);
+    eprintln!();
+    subset_ratio
+}
+
+
+fn calculate_subset_ratio(total_depth: f64, min_depth: f64) -> f64 {
     if total_depth < min_depth {
         quit_with_error("input reads are too shallow to subset");
     }
@@ -130,41 +511,231 @@ fn calculate_subsets(read_count: usize, read_bases: u64, genome_size: u64, min_d
               format_float(min_depth), format_float(min_depth));
     let subset_depth = min_depth * (4.0 * total_depth / min_depth).log2() / 2.0;
     eprintln!("               = {:.1}x", subset_depth);
-    let subset_ratio = subset_depth / total_depth;
-    let reads_per_subset = (subset_ratio * read_count as f64).round() as usize;
-    eprintln!("  reads per subset: {}", reads_per_subset);
-    eprintln!();
-    reads_per_subset
+    subset_depth / total_depth
+}
+
+
+enum SubsetSize {
+    Reads(usize),
+    Bases(u64),
 }
 
 
-fn save_subsets(input_fastq: &Path, subset_count: usize, input_count: usize,
-                reads_per_subset: usize, out_dir: &Path, seed: u64,
+struct SubsetRun<'a> {
+    fastq_files: &'a [PathBuf],
+    read_filter: &'a ReadFilter,
+    holdout: &'a Holdout,
+    subset_count: usize,
+    out_dir: &'a Path,
+    gzip_level: Option<u32>,
+    seed: u64,
+}
+
+
+fn save_subsets(run: &SubsetRun, read_lengths: &[u64], subset_size: SubsetSize, stratified: bool,
                 metrics: &mut SubsampleMetrics) {
+    let SubsetRun { fastq_files, read_filter, holdout, subset_count, out_dir, gzip_level,
+                    seed } = *run;
     section_header("Subsetting reads");
-    explanation("The reads are now shuffled and grouped into subset files.");
+    if let SubsetSize::Bases(_) = subset_size {
+        explanation("Reads are now sampled for each subset, with longer reads more likely to be \
+                     chosen, and grouped into subset files.");
+    } else if stratified {
+        explanation("The reads are now shuffled within read-length bins, interleaved so that each \
+                     subset draws evenly from every bin, and grouped into subset files.");
+    } else {
+        explanation("The reads are now shuffled and grouped into subset files.");
+    }
     let mut rng = StdRng::seed_from_u64(seed);
-    let mut read_order: Vec<usize> = (0..input_count).collect();
-    read_order.shuffle(&mut rng);
+    let mut read_order: Vec<usize> = (0..read_lengths.len()).collect();
+    if let SubsetSize::Reads(_) = subset_size {
+        if stratified {
+            read_order = stratified_read_order(read_lengths, &mut rng);
+        } else {
+            read_order.shuffle(&mut rng);
+        }
+    }
     let mut subset_indices = Vec::new();
     let mut subset_files = Vec::new();
     for i in 0..subset_count {
         eprintln!("subset {}:", i+1);
-        subset_indices.push(subsample_indices(subset_count, reads_per_subset, &read_order, i));
-        let subset_filename = out_dir.join(format!("sample_{:02}.fastq", i + 1));
+        subset_indices.push(match subset_size {
+            SubsetSize::Reads(reads_per_subset) =>
+                subsample_indices(subset_count, reads_per_subset, &read_order, i),
+            SubsetSize::Bases(bases_per_subset) =>
+                length_weighted_indices(read_lengths, bases_per_subset, &mut rng),
+        });
+        let extension = if gzip_level.is_some() { "fastq.gz" } else { "fastq" };
+        let subset_filename = out_dir.join(format!("sample_{:02}.{}", i + 1, extension));
         eprintln!("  {}", subset_filename.display());
-        let subset_file = File::create(subset_filename).expect("Failed to create subset file");
-        subset_files.push(subset_file);
+        subset_files.push(subset_writer(&subset_filename, gzip_level));
         eprintln!();
     }
-    let sample_read_lengths = write_subsampled_reads(input_fastq, subset_count, &subset_indices,
-                                                     &mut subset_files);
+    let mut holdout_file = holdout.writer(out_dir, gzip_level);
+    let sample_read_lengths = write_subsampled_reads(fastq_files, read_filter, holdout,
+                                                     subset_count, &subset_indices,
+                                                     &mut subset_files, &mut holdout_file);
+    for subset_file in subset_files.into_iter().chain(holdout_file) {
+        if let Err(e) = subset_file.finish() {
+            quit_with_error(&format!("failed to write subset file\n{}", e));
+        }
+    }
     for i in 0..subset_count {
         metrics.output_reads.push(ReadSetDetails::new(&sample_read_lengths[i]));
     }
 }
 
 
+fn stream_subsets(run: &SubsetRun, subset_ratio: f64, metrics: &mut SubsampleMetrics) {
+    // Subsets reads in a single pass, without storing or shuffling read indices. Each read is
+    // given a pseudo-random position from a hash of its ID and the seed, and each subset takes the
+    // reads in a window of positions (like a window of the shuffled reads in save_subsets). Read
+    // lengths are stored as counts, so memory use doesn't grow with the number of reads.
+    let SubsetRun { fastq_files, read_filter, holdout, subset_count, out_dir, gzip_level,
+                    seed } = *run;
+    section_header("Subsetting reads");
+    explanation("Reads are now streamed into subset files based on a hash of their names.");
+    let mut subset_files = Vec::new();
+    for i in 0..subset_count {
+        eprintln!("subset {}:", i+1);
+        let (start, end) = subset_window(subset_count, subset_ratio, i);
+        eprintln!("  read positions {:.3}-{:.3}", start, end);
+        let extension = if gzip_level.is_some() { "fastq.gz" } else { "fastq" };
+        let subset_filename = out_dir.join(format!("sample_{:02}.{}", i + 1, extension));
+        eprintln!("  {}", subset_filename.display());
+        subset_files.push(subset_writer(&subset_filename, gzip_level));
+        eprintln!();
+    }
+    let mut holdout_file = holdout.writer(out_dir, gzip_level);
+    let mut input_lengths = BTreeMap::new();
+    let mut pass_lengths = BTreeMap::new();
+    let mut holdout_lengths = BTreeMap::new();
+    let mut subset_lengths = vec![BTreeMap::new(); subset_count];
+    let pb = spinner("writing subsampled reads to files...");
+    let mut filter = read_filter.clone();
+    for_each_read(fastq_files, |record| {
+        let read_length = record.seq().len() as u64;
+        *input_lengths.entry(read_length).or_insert(0) += 1;
+        let result = filter.check(record.id_bytes(), record.seq(), record.qual());
+        if !count_filtered_read(result, metrics) {
+            return;
+        }
+        *pass_lengths.entry(read_length).or_insert(0) += 1;
+        if holdout.contains(record.id_bytes()) {
+            record.write(holdout_file.as_mut().unwrap()).unwrap();
+            *holdout_lengths.entry(read_length).or_insert(0) += 1;
+            return;
+        }
+        let position = read_position(record.id_bytes(), seed);
+        for subset_i in 0..subset_count {
+            if in_subset_window(position, subset_count, subset_ratio, subset_i) {
+                record.write(&mut subset_files[subset_i]).unwrap();
+                *subset_lengths[subset_i].entry(read_length).or_insert(0) += 1;
+            }
+        }
+    });
+    for subset_file in subset_files.into_iter().chain(holdout_file) {
+        if let Err(e) = subset_file.finish() {
+            quit_with_error(&format!("failed to write subset file\n{}", e));
+        }
+    }
+    pb.finish_and_clear();
+
+    report_read_stats("Input reads", ReadSetDetails::from_length_counts(&input_lengths),
+                      ReadSetDetails::from_length_counts(&pass_lengths), read_filter, metrics);
+    report_holdout_stats(holdout, ReadSetDetails::from_length_counts(&holdout_lengths), metrics);
+    for lengths in &subset_lengths {
+        metrics.output_reads.push(ReadSetDetails::from_length_counts(lengths));
+    }
+}
+
+
+fn read_position(read_id: &[u8], seed: u64) -> f64 {
+    // Returns a pseudo-random position in [0, 1) for a read, from a hash of its ID and the seed.
+    (read_hash(read_id, seed) >> 11) as f64 / (1u64 << 53) as f64
+}
+
+
+fn read_hash(bytes: &[u8], seed: u64) -> u64 {
+    // The fxhash is passed through a SplitMix64 finaliser to spread its bits evenly.
+    let mut h = fxhash::hash64(&(seed, bytes));
+    h = (h ^ (h >> 30)).wrapping_mul(0xbf58476d1ce4e5b9);
+    h = (h ^ (h >> 27)).wrapping_mul(0x94d049bb133111eb);
+    h ^ (h >> 31)
+}
+
+
+fn subset_window(subset_count: usize, subset_ratio: f64, i: usize) -> (f64, f64) {
+    // Returns the window of read positions for a subset. Windows start evenly spaced and can wrap
+    // around past 1.
+    let start = i as f64 / subset_count as f64;
+    (start, start + subset_ratio)
+}
+
+
+fn in_subset_window(position: f64, subset_count: usize, subset_ratio: f64, i: usize) -> bool {
+    let (start, _) = subset_window(subset_count, subset_ratio, i);
+    (position - start).rem_euclid(1.0) < subset_ratio
+}
+
+
+fn subset_writer(filename: &Path, gzip_level: Option<u32>) -> OutputWriter {
+    match output_writer_with_level(filename, gzip_level) {
+        Ok(writer) => writer,
+        Err(e) => quit_with_error(&format!("unable to create {}\n{}", filename.display(), e)),
+    }
+}
+
+
+fn length_weighted_indices(read_lengths: &[u64], bases_per_subset: u64, rng: &mut StdRng)
+        -> HashSet<usize> {
+    // Samples reads (without replacement) with probability proportional to their length until the
+    // subset has the target number of bases. This uses Efraimidis-Spirakis weighted sampling: each
+    // read gets a key of u^(1/length) for a random u, and the reads with the highest keys are
+    // taken. Keys are compared as ln(u)/length, which has the same order.
+    let mut keys: Vec<(f64, usize)> = read_lengths.iter().enumerate()
+        .map(|(i, &length)| (rng.gen::<f64>().ln() / length as f64, i)).collect();
+    keys.sort_unstable_by(|a, b| b.0.total_cmp(&a.0).then(a.1.cmp(&b.1)));
+    let mut subsample_indices = HashSet::new();
+    let mut subset_bases = 0;
+    for (_, i) in keys {
+        if subset_bases >= bases_per_subset { break; }
+        subsample_indices.insert(i);
+        subset_bases += read_lengths[i];
+    }
+    eprintln!("  {} reads, {} bases", subsample_indices.len(), subset_bases);
+    subsample_indices
+}
+
+
+const STRATIFIED_BIN_COUNT: usize = 10;
+
+
+fn stratified_read_order(read_lengths: &[u64], rng: &mut StdRng) -> Vec<usize> {
+    // Returns a read order where any contiguous (or wrapped-around) window has about the same
+    // read-length distribution as the whole read set. Reads are sorted by length and split into
+    // equal-count bins, then shuffled within each bin. Each read is given a position spread evenly
+    // across [0, 1) by its rank in its bin (with some jitter), and the reads are ordered by
+    // position, so every window takes a proportional share of each bin.
+    let read_count = read_lengths.len();
+    let mut by_length: Vec<usize> = (0..read_count).collect();
+    by_length.sort_by_key(|&i| (read_lengths[i], i));
+    let mut positions = Vec::with_capacity(read_count);
+    for b in 0..STRATIFIED_BIN_COUNT {
+        let start = b * read_count / STRATIFIED_BIN_COUNT;
+        let end = (b + 1) * read_count / STRATIFIED_BIN_COUNT;
+        let bin = &mut by_length[start..end];
+        bin.shuffle(rng);
+        let bin_size = bin.len() as f64;
+        for (rank, &i) in bin.iter().enumerate() {
+            positions.push(((rank as f64 + rng.gen::<f64>()) / bin_size, i));
+        }
+    }
+    positions.sort_unstable_by(|a, b| a.0.total_cmp(&b.0).then(a.1.cmp(&b.1)));
+    positions.into_iter().map(|(_, i)| i).collect()
+}
+
+
 fn subsample_indices(subset_count: usize, reads_per_subset: usize, read_order: &[usize], i: usize)
         -> HashSet<usize> {
     // For a given subsample (index i), this function returns a HashSet of the read indices which
@@ -192,25 +763,34 @@ fn subsample_indices(subset_count: usize, reads_per_subset: usize, read_order: &
 }
 
 
-fn write_subsampled_reads(input_fastq: &Path, subset_count: usize,
-                          subset_indices: &[HashSet<usize>], subset_files: &mut [File])
-        -> Vec<Vec<u64>> {
+fn write_subsampled_reads(input_fastqs: &[PathBuf], read_filter: &ReadFilter, holdout: &Holdout,
+                          subset_count: usize, subset_indices: &[HashSet<usize>],
+                          subset_files: &mut [OutputWriter],
+                          holdout_file: &mut Option<OutputWriter>) -> Vec<Vec<u64>> {
     // This function loops through the input reads, and saves each read to the appropriate output
     // file. It also gathers up and returns the sorted read lengths for each subsampled read set.
+    // Read indices only count reads which pass filtering and aren't held out. Held-out reads are
+    // saved to their own file.
     let mut sample_read_lengths: Vec<Vec<u64>> = vec![Vec::new(); subset_count];
     let pb = spinner("writing subsampled reads to files...");
     let mut read_i = 0;
-    let mut reader = fastq_reader(input_fastq);
-    while let Some(record) = reader.next() {
-        let record = record.expect("Error reading FASTQ file");
+    let mut filter = read_filter.clone();
+    for_each_read(input_fastqs, |record| {
+        if filter.check(record.id_bytes(), record.seq(), record.qual()).is_err() {
+            return;
+        }
+        if holdout.contains(record.id_bytes()) {
+            record.write(holdout_file.as_mut().unwrap()).unwrap();
+            return;
+        }
         for subset_i in 0..subset_count {
             if subset_indices[subset_i].contains(&read_i) {
-                record.write(&subset_files[subset_i]).unwrap();
+                record.write(&mut subset_files[subset_i]).unwrap();
                 sample_read_lengths[subset_i].push(record.seq().len() as u64);
             }
         }
         read_i += 1;
-    }
+    });
     for i in 0..subset_count {
         sample_read_lengths[i].sort_unstable();
     }
@@ -229,8 +809,13 @@ fn finished_message() {
 #[cfg(test)]
 mod tests {
     use super::*;
+    use std::io::Write;
     use std::panic;
 
+    use tempfile::tempdir;
+
+    use crate::tests::{assert_almost_eq, make_test_file};
+
     #[test]
     fn test_parse_genome_size() {
         assert_eq!(parse_genome_size("100"), 100);
@@ -259,6 +844,205 @@ mod tests {
         }).is_err());
     }
 
+    #[test]
+    fn test_mean_qual() {
+        assert_almost_eq(mean_qual(b"+++++"), 10.0, 1e-8);
+        assert_almost_eq(mean_qual(b"55555"), 20.0, 1e-8);
+        assert_almost_eq(mean_qual(b"!"), 0.0, 1e-8);
+        assert_eq!(mean_qual(b""), 0.0);
+
+        // One bad base pulls the mean quality down much more than averaging Phred scores would.
+        assert_almost_eq(mean_qual(b"?+"), -10.0 * ((0.001 + 0.1) / 2.0_f64).log10(), 1e-8);
+        assert!(mean_qual(b"?+") < 20.0);
+    }
+
+    #[test]
+    fn test_read_filter() {
+        let mut read_filter = ReadFilter::new(3, 15.0, false);
+        assert!(read_filter.is_active());
+        assert!(read_filter.check(b"r1", b"ACGT", b"5555").is_ok());
+        assert!(read_filter.check(b"r1", b"ACGT", b"5555").is_ok());
+        assert!(matches!(read_filter.check(b"r2", b"AC", b"55"), Err(FilterReason::Short)));
+        assert!(matches!(read_filter.check(b"r3", b"AC", b"++"), Err(FilterReason::Short)));
+        assert!(matches!(read_filter.check(b"r4", b"ACGT", b"++++"), Err(FilterReason::LowQual)));
+        let mut read_filter = ReadFilter::new(0, 0.0, false);
+        assert!(!read_filter.is_active());
+        assert!(read_filter.check(b"r1", b"", b"").is_ok());
+    }
+
+    #[test]
+    fn test_read_filter_dedup() {
+        let mut read_filter = ReadFilter::new(0, 15.0, true);
+        assert!(read_filter.is_active());
+        let mut fresh_filter = read_filter.clone();
+        assert!(read_filter.check(b"r1", b"ACGT", b"5555").is_ok());
+        assert!(matches!(read_filter.check(b"r1", b"ACGA", b"5555"), Err(FilterReason::Duplicate)));
+        assert!(matches!(read_filter.check(b"r2", b"ACGT", b"5555"), Err(FilterReason::Duplicate)));
+        assert!(read_filter.check(b"r3", b"ACGA", b"5555").is_ok());
+
+        // A read which fails another filter isn't counted, so a later copy can still pass.
+        assert!(matches!(read_filter.check(b"r4", b"TTTT", b"++++"), Err(FilterReason::LowQual)));
+        assert!(read_filter.check(b"r4", b"TTTT", b"5555").is_ok());
+
+        // A copy of the filter made before any reads are seen starts fresh.
+        assert!(fresh_filter.check(b"r1", b"ACGT", b"5555").is_ok());
+    }
+
+    #[test]
+    fn test_holdout() {
+        let ids: Vec<String> = (0..10000).map(|i| format!("read_{}", i)).collect();
+        let holdout = Holdout::new(0.1, 0);
+        assert!(holdout.is_active());
+        let held_out: Vec<&String> = ids.iter()
+            .filter(|id| holdout.contains(id.as_bytes())).collect();
+        assert!((900..1100).contains(&held_out.len()));
+
+        // The same reads are held out every time, and a different seed holds out different reads.
+        assert!(held_out.iter().all(|id| holdout.contains(id.as_bytes())));
+        let other_holdout = Holdout::new(0.1, 1);
+        assert!(!held_out.iter().all(|id| other_holdout.contains(id.as_bytes())));
+
+        // Held-out positions are independent of the positions used for --stream subsets.
+        let early_stream_count = held_out.iter()
+            .filter(|id| read_position(id.as_bytes(), 0) < 0.5).count();
+        assert!((400..600).contains(&early_stream_count));
+
+        let holdout = Holdout::new(0.0, 0);
+        assert!(!holdout.is_active());
+        assert!(!ids.iter().any(|id| holdout.contains(id.as_bytes())));
+    }
+
+    #[test]
+    fn test_expand_read_files() {
+        let temp_dir = tempdir().unwrap();
+        let dir = temp_dir.path();
+        make_test_file(&dir.join("a.fastq"), "@a1\nACGT\n+\n++++\n@a2\nACG\n+\n+++\n");
+        make_test_file(&dir.join("b.fastq"), "@b1\nACGTA\n+\n+++++\n");
+        make_test_file(&dir.join("reads.txt"), "a.fastq\n\n# comment\nb.fastq\n");
+        let reads = vec![dir.join("reads.txt"), dir.join("a.fastq")];
+        let fastq_files = expand_read_files(&reads);
+        assert_eq!(fastq_files, vec![dir.join("a.fastq"), dir.join("b.fastq"), dir.join("a.fastq")]);
+
+        // Reads from all files are pooled in order.
+        let mut names = Vec::new();
+        for_each_read(&fastq_files, |record| names.push(record.id().unwrap().to_string()));
+        assert_eq!(names, vec!["a1", "a2", "b1", "a1", "a2"]);
+
+        make_test_file(&dir.join("empty.fofn"), "\n");
+        assert!(panic::catch_unwind(|| expand_read_files(&[dir.join("empty.fofn")])).is_err());
+    }
+
+    #[test]
+    fn test_subset_writer() {
+        let temp_dir = tempdir().unwrap();
+        for (filename, gzip_level) in [("a.fastq", None), ("b.fastq.gz", Some(1)),
+                                       ("c.fastq.gz", Some(9))] {
+            let path = temp_dir.path().join(filename);
+            let mut writer = subset_writer(&path, gzip_level);
+            writer.write_all(b"@r1\nACGT\n+\n++++\n").unwrap();
+            writer.finish().unwrap();
+            let is_gzipped = std::fs::read(&path).unwrap().starts_with(&[0x1f, 0x8b]);
+            assert_eq!(is_gzipped, gzip_level.is_some());
+            let mut names = Vec::new();
+            for_each_read(&[path], |record| names.push(record.id().unwrap().to_string()));
+            assert_eq!(names, vec!["r1"]);
+        }
+    }
+
+    #[test]
+    fn test_read_position() {
+        assert_eq!(read_position(b"read_1", 0), read_position(b"read_1", 0));
+        assert_ne!(read_position(b"read_1", 0), read_position(b"read_1", 1));
+        assert_ne!(read_position(b"read_1", 0), read_position(b"read_2", 0));
+
+        // Positions should be roughly uniform.
+        let mut bins = [0; 10];
+        for i in 0..10000 {
+            let p = read_position(format!("read_{}", i).as_bytes(), 0);
+            assert!((0.0..1.0).contains(&p));
+            bins[(p * 10.0) as usize] += 1;
+        }
+        assert!(bins.iter().all(|&b| (900..1100).contains(&b)));
+    }
+
+    #[test]
+    fn test_in_subset_window() {
+        assert_eq!(subset_window(4, 0.5, 3), (0.75, 1.25));
+        assert!(in_subset_window(0.1, 4, 0.5, 0));
+        assert!(!in_subset_window(0.6, 4, 0.5, 0));
+        assert!(in_subset_window(0.8, 4, 0.5, 3));
+        assert!(in_subset_window(0.2, 4, 0.5, 3));
+        assert!(!in_subset_window(0.3, 4, 0.5, 3));
+
+        // Each position is in the same number of subsets as the shuffled-index method would give.
+        for p in [0.0, 0.1, 0.33, 0.5, 0.99] {
+            let count = (0..4).filter(|&i| in_subset_window(p, 4, 0.5, i)).count();
+            assert_eq!(count, 2);
+        }
+    }
+
+    #[test]
+    fn test_length_weighted_indices() {
+        // Subsets reach the target bases, and long reads are chosen more often than short ones.
+        let read_lengths: Vec<u64> = (0..1000).map(|i| if i % 2 == 0 { 100 } else { 1000 }).collect();
+        let mut rng = StdRng::seed_from_u64(0);
+        let mut long_count = 0;
+        let mut short_count = 0;
+        for _ in 0..10 {
+            let indices = length_weighted_indices(&read_lengths, 50000, &mut rng);
+            let bases: u64 = indices.iter().map(|&i| read_lengths[i]).sum();
+            assert!((50000..51000).contains(&bases));
+            long_count += indices.iter().filter(|&&i| read_lengths[i] == 1000).count();
+            short_count += indices.iter().filter(|&&i| read_lengths[i] == 100).count();
+        }
+        assert!(long_count > short_count * 5);
+
+        // The same seed gives the same subsets.
+        let a = length_weighted_indices(&read_lengths, 50000, &mut StdRng::seed_from_u64(1));
+        let b = length_weighted_indices(&read_lengths, 50000, &mut StdRng::seed_from_u64(1));
+        assert_eq!(a, b);
+
+        // Asking for more bases than there are gives all reads.
+        let all = length_weighted_indices(&[10, 20, 0], 1000, &mut rng);
+        assert_eq!(all, HashSet::from([0, 1, 2]));
+    }
+
+    #[test]
+    fn test_stratified_read_order() {
+        let mut rng = StdRng::seed_from_u64(0);
+        let read_lengths: Vec<u64> = (0..1000).map(|_| rng.gen_range(100..50000)).collect();
+        let read_order = stratified_read_order(&read_lengths, &mut rng);
+        let mut sorted_order = read_order.clone();
+        sorted_order.sort_unstable();
+        assert_eq!(sorted_order, (0..1000).collect::<Vec<_>>());
+
+        // Every subset should take the same number of reads (give or take one) from each bin.
+        let mut sorted_lengths = read_lengths.clone();
+        sorted_lengths.sort_unstable();
+        let bin_of = |length: u64| sorted_lengths.partition_point(|&l| l < length) / 100;
+        for i in 0..4 {
+            let indices = subsample_indices(4, 400, &read_order, i);
+            let mut bin_counts = [0; STRATIFIED_BIN_COUNT];
+            for &j in &indices {
+                bin_counts[bin_of(read_lengths[j])] += 1;
+            }
+            for count in bin_counts {
+                assert!((39..=41).contains(&count), "{:?}", bin_counts);
+            }
+        }
+
+        // The order depends on the seed.
+        let a = stratified_read_order(&read_lengths, &mut StdRng::seed_from_u64(1));
+        let b = stratified_read_order(&read_lengths, &mut StdRng::seed_from_u64(1));
+        let c = stratified_read_order(&read_lengths, &mut StdRng::seed_from_u64(2));
+        assert_eq!(a, b);
+        assert_ne!(a, c);
+
+        // Fewer reads than bins still works.
+        assert_eq!(stratified_read_order(&[5, 3], &mut rng).len(), 2);
+        assert!(stratified_read_order(&[], &mut rng).is_empty());
+    }
+
     #[test]
     fn test_subsample_indices() {
         let read_order = vec![4, 2, 3, 1, 0, 5];
@@ -16,33 +16,61 @@ use std::collections::{HashMap, HashSet};
 use std::fs;
 use std::path::{Path, PathBuf};
 
-use crate::metrics::{ClusteringMetrics, CombineMetrics, InputAssemblyMetrics, SubsampleMetrics,
-                     TrimmedClusterMetrics, UntrimmedClusterMetrics};
+use crate::metrics::{BenchmarkMetrics, ClusteringMetrics, CombineMetrics, InputAssemblyMetrics,
+                     SubsampleMetrics, TrimmedClusterMetrics, UntrimmedClusterMetrics};
 use crate::misc::{check_if_dir_exists, quit_with_error, format_float_sigfigs};
 
 
-pub fn table(autocycler_dir: Option<PathBuf>, name: String, fields: String, sigfigs: usize) {
-    check_settings(&autocycler_dir, sigfigs);
+pub const DEFAULT_FIELDS: &str = "input_read_count, input_read_bases, input_read_n50, \
+                                  pass_cluster_count, fail_cluster_count, \
+                                  overall_clustering_score, untrimmed_cluster_size, \
+                                  untrimmed_cluster_distance, trimmed_cluster_size, \
+                                  trimmed_cluster_median, trimmed_cluster_mad, \
+                                  consensus_assembly_bases, consensus_assembly_unitigs, \
+                                  consensus_assembly_fully_resolved";
+
+
+pub fn table(autocycler_dirs: Vec<PathBuf>, name: String, fields: String, sigfigs: usize,
+             header: bool) {
+    // With no directory, only the header line is printed. With one directory, only its values are
+    // printed (unless a header is requested), so a loop can build up a table. With multiple
+    // directories, the header is printed followed by one line per directory, named by directory.
+    check_settings(&autocycler_dirs, &name, sigfigs);
     let fields = parse_fields(fields);
-    if let Some(autocycler_dir) = autocycler_dir {
-        print_values(&autocycler_dir, name, fields, sigfigs);
+    if autocycler_dirs.is_empty() || header || autocycler_dirs.len() > 1 {
+        print_header(&fields);
+    }
+    if autocycler_dirs.len() == 1 {
+        print_values(&autocycler_dirs[0], &name, &fields, sigfigs);
     } else {
-        print_header(fields);
+        for dir in &autocycler_dirs {
+            print_values(dir, &dir_name(dir), &fields, sigfigs);
+        }
     }
 }
 
 
-fn check_settings(autocycler_dir: &Option<PathBuf>, sigfigs: usize) {
-    if let Some(dir) = autocycler_dir.as_ref() {
+fn check_settings(autocycler_dirs: &[PathBuf], name: &str, sigfigs: usize) {
+    for dir in autocycler_dirs {
         check_if_dir_exists(dir);
     }
+    if autocycler_dirs.len() > 1 && !name.is_empty() {
+        quit_with_error("--name can only be used with a single --autocycler_dir");
+    }
     if sigfigs == 0 {
         quit_with_error("--sigfigs must be 1 or greater");
     }
 }
 
 
-fn parse_fields(comma_delimited_fields: String) -> Vec<String> {
+fn dir_name(dir: &Path) -> String {
+    // Samples in a multi-directory table are named using their directory's name.
+    let dir = dir.canonicalize().unwrap_or(dir.to_path_buf());
+    dir.file_name().map(|n| n.to_string_lossy().to_string()).unwrap_or_default()
+}
+
+
+pub fn parse_fields(comma_delimited_fields: String) -> Vec<String> {
     let fields = comma_delimited_fields.replace(" ", "").split(',')
                                        .map(|s| s.to_string()).collect();
     let mut valid_fields = HashSet::new();
@@ -52,6 +80,7 @@ fn parse_fields(comma_delimited_fields: String) -> Vec<String> {
     valid_fields.extend(CombineMetrics::get_field_names());
     valid_fields.extend(UntrimmedClusterMetrics::get_field_names());
     valid_fields.extend(TrimmedClusterMetrics::get_field_names());
+    valid_fields.extend(BenchmarkMetrics::get_field_names());
     for field in &fields {
         if !valid_fields.contains(field) {
             quit_with_error(&format!("{} is not a valid field name", field));
@@ -61,16 +90,27 @@ fn parse_fields(comma_delimited_fields: String) -> Vec<String> {
 }
 
 
-fn print_header(fields: Vec<String>) {
-    println!("name\t{}", fields.join("\t"));
+fn print_header(fields: &[String]) {
+    println!("{}", get_header_line(fields));
 }
 
 
-fn print_values(autocycler_dir: &Path, name: String, fields: Vec<String>, sigfigs: usize) {
+pub fn get_header_line(fields: &[String]) -> String {
+    format!("name\t{}", fields.join("\t"))
+}
+
+
+fn print_values(autocycler_dir: &Path, name: &str, fields: &[String], sigfigs: usize) {
+    println!("{}", get_values_line(autocycler_dir, name, fields, sigfigs));
+}
+
+
+pub fn get_values_line(autocycler_dir: &Path, name: &str, fields: &[String], sigfigs: usize)
+        -> String {
     if name.contains('\t') {
         quit_with_error("--name cannot contain tab characters")
     }
-    print!("{}", name);
+    let mut line = name.to_string();
 
     let yaml_files = find_all_yaml_files(autocycler_dir);
     let subsample_yaml = get_one_copy_yaml(&yaml_files, "subsample.yaml");
@@ -79,6 +119,7 @@ fn print_values(autocycler_dir: &Path, name: String, fields: Vec<String>, sigfig
     let consensus_assembly_yaml = get_one_copy_yaml(&yaml_files, "consensus_assembly.yaml");
     let untrimmed_yamls = get_multi_copy_yaml(&yaml_files, "1_untrimmed.yaml");
     let trimmed_yamls = get_multi_copy_yaml(&yaml_files, "2_trimmed.yaml");
+    let benchmark_yaml = get_one_copy_yaml(&yaml_files, "benchmark.yaml");
 
     let mut map: HashMap<String, Value> = HashMap::new();
     if let Some(path) = subsample_yaml          { map.extend(load_single_yaml_to_map(&path)); }
@@ -87,14 +128,15 @@ fn print_values(autocycler_dir: &Path, name: String, fields: Vec<String>, sigfig
     if let Some(path) = consensus_assembly_yaml { map.extend(load_single_yaml_to_map(&path)); }
     if !untrimmed_yamls.is_empty() { map.extend(load_multi_yaml_to_map(&untrimmed_yamls)); }
     if !trimmed_yamls.is_empty()   { map.extend(load_multi_yaml_to_map(&trimmed_yamls)); }
+    if let Some(path) = benchmark_yaml          { map.extend(load_single_yaml_to_map(&path)); }
 
     for field in fields {
-        print!("\t");
-        if let Some(value) = map.get(&field) {
-            print!("{}", format_value(value, sigfigs));
+        line.push('\t');
+        if let Some(value) = map.get(field) {
+            line.push_str(&format_value(value, sigfigs));
         }
     }
-    println!();
+    line
 }
 
 
@@ -118,7 +160,7 @@ fn load_multi_yaml_to_map(yaml_paths: &Vec<PathBuf>) -> HashMap<String, Value> {
 }
 
 
-fn find_all_yaml_files(autocycler_dir: &Path) -> Vec<PathBuf> {
+pub fn find_all_yaml_files(autocycler_dir: &Path) -> Vec<PathBuf> {
     let mut yaml_files = Vec::new();
     visit_dirs_for_yaml_files(autocycler_dir, &mut yaml_files);
     yaml_files.sort();
@@ -238,6 +280,21 @@ mod tests {
         }).is_err());
     }
 
+    #[test]
+    fn test_get_values_line() {
+        let dir = tempfile::tempdir().unwrap();
+        let sample = dir.path().join("sample_1");
+        fs::create_dir_all(sample.join("clustering/qc_pass/cluster_001")).unwrap();
+        fs::write(sample.join("clustering/clustering.yaml"),
+                  "pass_cluster_count: 1\nfail_cluster_count: 2\n").unwrap();
+        fs::write(sample.join("clustering/qc_pass/cluster_001/2_trimmed.yaml"),
+                  "trimmed_cluster_median: 1234.5\n").unwrap();
+        let fields = parse_fields("pass_cluster_count,trimmed_cluster_median,\
+                                   consensus_assembly_bases".to_string());
+        assert_eq!(get_values_line(&sample, &dir_name(&sample), &fields, 3),
+                   "sample_1\t1\t[1230]\t");
+    }
+
     #[test]
     fn test_format_value_simple() {
         assert_eq!(format_value(&Value::Number(serde_yaml::Number::from(12)), 2), "12");
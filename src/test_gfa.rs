@@ -285,3 +285,60 @@ pub fn get_test_gfa_14() -> Vec<String> {
          "P\t4\t5+,38-,18+,36-,17+,22-,8-,19-,37-,21+,12-,5+,34-,18+,36-,17+,22-,8-,19-\t*\tLN:i:178\tFN:Z:b.fasta\tHD:Z:b_2\tCL:i:2",
          "P\t7\t17-,36+,18-,34+,5-,12+,21-,37+,19+,8+\t*\tLN:i:95\tFN:Z:d.fasta\tHD:Z:d_2\tCL:i:2"].into_iter().map(String::from).collect()
 }
+
+
+#[cfg(test)]
+pub fn get_test_gfa_15() -> Vec<String> {
+    // One connected component
+    // Linear structure with two bubbles: 2/3 between 1 and 4, and 5/nothing between 4 and 6
+    vec!["H\tVN:Z:1.0\tKM:i:3",
+         "S\t1\tACGACTACGA\tDP:f:3.00",
+         "S\t2\tTT\tDP:f:2.00",
+         "S\t3\tCCC\tDP:f:1.00",
+         "S\t4\tGATCGATCGA\tDP:f:3.00",
+         "S\t5\tGGGG\tDP:f:1.00",
+         "S\t6\tTACGTACGTA\tDP:f:3.00",
+         "L\t1\t+\t2\t+\t0M",
+         "L\t2\t-\t1\t-\t0M",
+         "L\t1\t+\t3\t+\t0M",
+         "L\t3\t-\t1\t-\t0M",
+         "L\t2\t+\t4\t+\t0M",
+         "L\t4\t-\t2\t-\t0M",
+         "L\t3\t+\t4\t+\t0M",
+         "L\t4\t-\t3\t-\t0M",
+         "L\t4\t+\t5\t+\t0M",
+         "L\t5\t-\t4\t-\t0M",
+         "L\t4\t+\t6\t+\t0M",
+         "L\t6\t-\t4\t-\t0M",
+         "L\t5\t+\t6\t+\t0M",
+         "L\t6\t-\t5\t-\t0M",
+         "P\t1\t1+,2+,4+,5+,6+\t*\tLN:i:36\tFN:Z:a.fasta\tHD:Z:a",
+         "P\t2\t1+,3+,4+,6+\t*\tLN:i:33\tFN:Z:b.fasta\tHD:Z:b",
+         "P\t3\t6-,4-,2-,1-\t*\tLN:i:32\tFN:Z:c.fasta\tHD:Z:c"].into_iter().map(String::from).collect()
+}
+
+
+#[cfg(test)]
+pub fn get_test_gfa_16() -> Vec<String> {
+    // One connected component
+    // Tandem repeat loop: unitig 2 is a junction between 1 and 4, with unitig 3 looping from its
+    // end back to its start. Sequences go around the loop once (a and c), twice (b) or not at all
+    // (d).
+    vec!["H\tVN:Z:1.0\tKM:i:3",
+         "S\t1\tACTACTCAACT\tDP:f:4.00",
+         "S\t2\tTCA\tDP:f:8.00",
+         "S\t3\tGAT\tDP:f:4.00",
+         "S\t4\tGCTACGACTAC\tDP:f:4.00",
+         "L\t1\t+\t2\t+\t0M",
+         "L\t2\t-\t1\t-\t0M",
+         "L\t2\t+\t3\t+\t0M",
+         "L\t3\t-\t2\t-\t0M",
+         "L\t3\t+\t2\t+\t0M",
+         "L\t2\t-\t3\t-\t0M",
+         "L\t2\t+\t4\t+\t0M",
+         "L\t4\t-\t2\t-\t0M",
+         "P\t1\t1+,2+,3+,2+,4+\t*\tLN:i:31\tFN:Z:a.fasta\tHD:Z:a",
+         "P\t2\t1+,2+,3+,2+,3+,2+,4+\t*\tLN:i:37\tFN:Z:b.fasta\tHD:Z:b",
+         "P\t3\t4-,2-,3-,2-,1-\t*\tLN:i:31\tFN:Z:c.fasta\tHD:Z:c",
+         "P\t4\t1+,2+,4+\t*\tLN:i:25\tFN:Z:d.fasta\tHD:Z:d"].into_iter().map(String::from).collect()
+}
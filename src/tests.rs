@@ -19,13 +19,17 @@ use rand::seq::SliceRandom;
 use std::fs::{File, read_to_string};
 use std::io::{Read, Write};
 use std::path::Path;
+use std::time::UNIX_EPOCH;
 use tempfile::tempdir;
 
-use crate::compress::load_sequences;
-use crate::decompress::save_original_seqs_to_dir;
+use crate::cluster::{cluster, ClusterSettings};
+use crate::compress::{compress, load_sequences, CompressSettings, LoadSettings};
+use crate::decompress::{save_original_seqs_to_dir, Wrap};
 use crate::graph_simplification::simplify_structure;
 use crate::kmer_graph::KmerGraph;
-use crate::metrics::InputAssemblyMetrics;
+use crate::misc::load_file_lines;
+use crate::metrics::{InputAssemblyMetrics, SimplificationMetrics};
+use crate::minimizer_graph::MinimizerGraph;
 use crate::unitig_graph::UnitigGraph;
 
 
@@ -49,7 +53,7 @@ pub fn make_gzipped_test_file(file_path: &Path, contents: &str) {
 }
 
 
-fn random_seq(length: usize, seed: u64) -> String {
+pub fn random_seq(length: usize, seed: u64) -> String {
     let bases = ['A', 'C', 'G', 'T'];
     let mut rng = StdRng::seed_from_u64(seed);
     (0..length).map(|_| *bases.choose(&mut rng).unwrap()).collect()
@@ -94,26 +98,94 @@ fn test_high_level(seq_a: &str, seq_b: &str, seq_c: &str, seq_d: &str, seq_e: &s
 
     // Build a k-mer graph from the sequences.
     let mut metrics = InputAssemblyMetrics::default();
-    let (sequences, assembly_count) = load_sequences(&assembly_dir.path().to_path_buf(),
-                                                        k_size, &mut metrics);
+    let load_settings = LoadSettings { k_size, ..Default::default() };
+    let (mut sequences, assembly_count) = load_sequences(&assembly_dir.path().to_path_buf(),
+                                                         &load_settings, &mut metrics);
     assert_eq!(assembly_count, 5);
-    let mut kmer_graph = KmerGraph::new(k_size);
+    let mut kmer_graph = KmerGraph::new(k_size, 4);
     kmer_graph.add_sequences(&sequences, assembly_count);
 
     // Build a unitig graph and save it to file.
     let mut unitig_graph = UnitigGraph::from_kmer_graph(&kmer_graph);
-    simplify_structure(&mut unitig_graph, &sequences);
+    simplify_structure(&mut unitig_graph, &mut sequences, 0, 0.0,
+                       &mut SimplificationMetrics::default());
     let gfa_1 = graph_dir.path().join("graph_1.gfa");
     unitig_graph.save_gfa(&gfa_1, &sequences).unwrap();
 
+    // A canonical k-mer graph should give exactly the same unitig graph.
+    let mut canonical_kmer_graph = KmerGraph::new_canonical(k_size, 4);
+    canonical_kmer_graph.add_sequences(&sequences, assembly_count);
+    assert_eq!(canonical_kmer_graph.kmer_count(), kmer_graph.kmer_count());
+    let mut canonical_unitig_graph = UnitigGraph::from_kmer_graph(&canonical_kmer_graph);
+    simplify_structure(&mut canonical_unitig_graph, &mut sequences, 0, 0.0,
+                       &mut SimplificationMetrics::default());
+    let gfa_canonical = graph_dir.path().join("graph_canonical.gfa");
+    canonical_unitig_graph.save_gfa(&gfa_canonical, &sequences).unwrap();
+    assert_same_content(&gfa_1, &gfa_canonical);
+
+    // Removing rare k-mers gives a different graph, but repairs keep it lossless.
+    for min_kmer_count in [2, 3] {
+        let mut filtered_kmer_graph = KmerGraph::new(k_size, 4);
+        filtered_kmer_graph.add_sequences(&sequences, assembly_count);
+        filtered_kmer_graph.remove_rare_kmers(min_kmer_count);
+        let mut filtered_unitig_graph = UnitigGraph::from_filtered_kmer_graph(&filtered_kmer_graph, &sequences);
+        simplify_structure(&mut filtered_unitig_graph, &mut sequences, 0, 0.0,
+                           &mut SimplificationMetrics::default());
+        let gfa_filtered = graph_dir.path().join("graph_filtered.gfa");
+        filtered_unitig_graph.save_gfa(&gfa_filtered, &sequences).unwrap();
+        let filtered_dir = tempdir().unwrap();
+        let (filtered_unitig_graph, filtered_sequences) = UnitigGraph::from_gfa_file(&gfa_filtered);
+        save_original_seqs_to_dir(filtered_dir.path(), &filtered_unitig_graph, &filtered_sequences, &Wrap::Fixed(0), Some(6));
+        assert_same_content(&original_a, &filtered_dir.path().join("a.fasta"));
+        assert_same_content(&original_b, &filtered_dir.path().join("b.fna"));
+        assert_same_content(&original_c, &filtered_dir.path().join("c.fa"));
+        assert_same_content_gzipped(&original_d, &filtered_dir.path().join("d.fasta.gz"));
+        assert_same_content_gzipped(&original_e, &filtered_dir.path().join("e.fna.gz"));
+    }
+
+    // A minimizer graph gives a less compact unitig graph, but it must still be lossless.
+    if k_size > 1 {
+        for window in [1, 4, 20] {
+            let mut minimizer_graph = MinimizerGraph::new(k_size, window);
+            minimizer_graph.add_sequences(&sequences);
+            let mut minimizer_unitig_graph = UnitigGraph::from_minimizer_graph(&minimizer_graph);
+            simplify_structure(&mut minimizer_unitig_graph, &mut sequences, 0, 0.0,
+                               &mut SimplificationMetrics::default());
+            let gfa_minimizer = graph_dir.path().join("graph_minimizer.gfa");
+            minimizer_unitig_graph.save_gfa(&gfa_minimizer, &sequences).unwrap();
+            if window == 1 {
+                assert_same_content(&gfa_1, &gfa_minimizer);
+            }
+            let minimizer_dir = tempdir().unwrap();
+            let (minimizer_unitig_graph, minimizer_sequences) = UnitigGraph::from_gfa_file(&gfa_minimizer);
+            save_original_seqs_to_dir(minimizer_dir.path(), &minimizer_unitig_graph, &minimizer_sequences, &Wrap::Fixed(0), Some(6));
+            assert_same_content(&original_a, &minimizer_dir.path().join("a.fasta"));
+            assert_same_content(&original_b, &minimizer_dir.path().join("b.fna"));
+            assert_same_content(&original_c, &minimizer_dir.path().join("c.fa"));
+            assert_same_content_gzipped(&original_d, &minimizer_dir.path().join("d.fasta.gz"));
+            assert_same_content_gzipped(&original_e, &minimizer_dir.path().join("e.fna.gz"));
+        }
+    }
+
     // Load the unitig graph from file, save it back to file and ensure the files are the same.
     let gfa_2 = graph_dir.path().join("graph_2.gfa");
     let (unitig_graph, sequences) = UnitigGraph::from_gfa_file(&gfa_1);
     unitig_graph.save_gfa(&gfa_2, &sequences).unwrap();
     assert_same_content(&gfa_1, &gfa_2);
 
+    // The same should be true after a round trip through GFA2, except for the order of links,
+    // since GFA2 has one edge line for both directions of a link.
+    let gfa_3 = graph_dir.path().join("graph_3.gfa");
+    let gfa_4 = graph_dir.path().join("graph_4.gfa");
+    unitig_graph.save_gfa2(&gfa_3, &sequences).unwrap();
+    let (unitig_graph, sequences) = UnitigGraph::from_gfa_file(&gfa_3);
+    unitig_graph.save_gfa(&gfa_4, &sequences).unwrap();
+    let sorted_lines = |gfa: &Path| { let mut lines = load_file_lines(gfa); lines.sort(); lines };
+    assert_eq!(sorted_lines(&gfa_1), sorted_lines(&gfa_4));
+
     // Reconstruct the sequences from the unitig graph.
-    save_original_seqs_to_dir(reconstructed_dir.path(), &unitig_graph, &sequences);
+    save_original_seqs_to_dir(reconstructed_dir.path(), &unitig_graph, &sequences,
+                              &Wrap::new("original", &sequences), Some(6));
     let reconstructed_a = reconstructed_dir.path().join("a.fasta");
     let reconstructed_b = reconstructed_dir.path().join("b.fna");
     let reconstructed_c = reconstructed_dir.path().join("c.fa");
@@ -163,6 +235,220 @@ fn test_random_seqs() {
 }
 
 
+#[test]
+fn test_threads_give_same_output() {
+    // Compress should make exactly the same graph regardless of how many threads it uses.
+    let assembly_dir = tempdir().unwrap();
+    let base = random_seq(3000, 0);
+    let seqs = [base.clone(),
+                format!("{}{}", &base[1000..], &base[..1000]),
+                format!("{}{}{}", &base[..1500], random_seq(50, 1), &base[1500..]),
+                format!("{}{}", random_seq(500, 2), &base[..2000])];
+    for (i, seq) in seqs.iter().enumerate() {
+        make_test_file(&assembly_dir.path().join(format!("{}.fasta", i)),
+                       &format!(">{}\n{}\n", i, seq));
+    }
+    let out_dir = tempdir().unwrap();
+    for canonical_kmers in [false, true] {
+        let mut gfas = Vec::new();
+        for threads in [1, 8] {
+            let autocycler_dir = out_dir.path().join(format!("{}_{}", canonical_kmers, threads));
+            let settings = CompressSettings::new(assembly_dir.path().to_path_buf(),
+                                                 autocycler_dir.clone(), 51, threads, 1);
+            compress(CompressSettings { canonical_kmers, ..settings });
+            gfas.push(read_to_string(autocycler_dir.join("input_assemblies.gfa")).unwrap());
+        }
+        assert_eq!(gfas[0], gfas[1]);
+    }
+}
+
+
+#[test]
+fn test_min_kmer_count() {
+    // An insertion in only one assembly is left out of the graph by --min_kmer_count 2 (so there
+    // are fewer unitigs), but decompress still gives back every contig.
+    let assembly_dir = tempdir().unwrap();
+    let base = random_seq(2000, 0);
+    let inserted = format!("{}{}{}", &base[..1000], random_seq(100, 1), &base[1000..]);
+    for (i, seq) in [&base, &base, &inserted].iter().enumerate() {
+        make_test_file(&assembly_dir.path().join(format!("{}.fasta", i)),
+                       &format!(">{}\n{}\n", i, seq));
+    }
+    let out_dir = tempdir().unwrap();
+    let mut unitig_counts = Vec::new();
+    for min_kmer_count in [1, 2] {
+        let autocycler_dir = out_dir.path().join(min_kmer_count.to_string());
+        let settings = CompressSettings::new(assembly_dir.path().to_path_buf(),
+                                             autocycler_dir.clone(), 51, 1, 1);
+        compress(CompressSettings { min_kmer_count, ..settings });
+        let gfa = autocycler_dir.join("input_assemblies.gfa");
+        let (unitig_graph, sequences) = UnitigGraph::from_gfa_file(&gfa);
+        unitig_counts.push(unitig_graph.unitigs.len());
+        let decompressed_dir = autocycler_dir.join("decompressed");
+        std::fs::create_dir(&decompressed_dir).unwrap();
+        save_original_seqs_to_dir(&decompressed_dir, &unitig_graph, &sequences, &Wrap::Fixed(0),
+                                  None);
+        for i in 0..3 {
+            let filename = format!("{}.fasta", i);
+            assert_same_content(&assembly_dir.path().join(&filename),
+                                &decompressed_dir.join(&filename));
+        }
+    }
+    assert!(unitig_counts[1] < unitig_counts[0], "{:?}", unitig_counts);
+}
+
+
+#[test]
+fn test_fastq_round_trip() {
+    // A FASTQ assembly is decompressed into a FASTQ file (with placeholder qualities), not a
+    // FASTA file with a FASTQ name.
+    let assembly_dir = tempdir().unwrap();
+    let (seq_1, seq_2) = (random_seq(300, 0), random_seq(200, 1));
+    make_test_file(&assembly_dir.path().join("a.fasta"), &format!(">a\n{}\n", seq_1));
+    make_test_file(&assembly_dir.path().join("b.fastq"),
+                   &format!("@b1 circular=true\n{}\n+\n{}\n@b2\n{}\n+\n{}\n",
+                            seq_1, "I".repeat(300), seq_2, "I".repeat(200)));
+    let autocycler_dir = tempdir().unwrap();
+    compress(CompressSettings::new(assembly_dir.path().to_path_buf(),
+                                   autocycler_dir.path().to_path_buf(), 51, 1, 1));
+    let gfa = autocycler_dir.path().join("input_assemblies.gfa");
+    let (unitig_graph, sequences) = UnitigGraph::from_gfa_file(&gfa);
+    let decompressed_dir = tempdir().unwrap();
+    save_original_seqs_to_dir(decompressed_dir.path(), &unitig_graph, &sequences,
+                              &Wrap::Fixed(0), None);
+    assert_eq!(read_to_string(decompressed_dir.path().join("b.fastq")).unwrap(),
+               format!("@b1 circular=true\n{}\n+\n{}\n@b2\n{}\n+\n{}\n",
+                       seq_1, "!".repeat(300), seq_2, "!".repeat(200)));
+    assert_same_content(&assembly_dir.path().join("a.fasta"),
+                        &decompressed_dir.path().join("a.fasta"));
+}
+
+
+#[test]
+fn test_gfa_round_trip() {
+    // A GFA assembly is decompressed into a FASTA file of its segments, named with a .fasta
+    // extension in place of .gfa.
+    let assembly_dir = tempdir().unwrap();
+    let (seq_1, seq_2) = (random_seq(300, 0), random_seq(200, 1));
+    make_test_file(&assembly_dir.path().join("a.fasta"), &format!(">a\n{}\n", seq_1));
+    make_test_file(&assembly_dir.path().join("flye.gfa"),
+                   &format!("H\tVN:Z:1.0\nS\t1\t{}\nS\t2\t{}\nL\t1\t+\t1\t+\t0M\n",
+                            seq_1, seq_2));
+    let autocycler_dir = tempdir().unwrap();
+    compress(CompressSettings::new(assembly_dir.path().to_path_buf(),
+                                   autocycler_dir.path().to_path_buf(), 51, 1, 1));
+    let gfa = autocycler_dir.path().join("input_assemblies.gfa");
+    let (unitig_graph, sequences) = UnitigGraph::from_gfa_file(&gfa);
+    let decompressed_dir = tempdir().unwrap();
+    save_original_seqs_to_dir(decompressed_dir.path(), &unitig_graph, &sequences,
+                              &Wrap::Fixed(0), None);
+    assert!(!decompressed_dir.path().join("flye.gfa").exists());
+    let fasta = read_to_string(decompressed_dir.path().join("flye.fasta")).unwrap();
+    let lines: Vec<&str> = fasta.lines().collect();
+    assert_eq!(lines.len(), 4);
+    assert!(lines[0].starts_with(">1"));
+    assert_eq!(lines[1], seq_1);
+    assert!(lines[2].starts_with(">2"));
+    assert_eq!(lines[3], seq_2);
+}
+
+
+#[test]
+fn test_rename_with_simplification() {
+    // A renamed duplicate contig keeps its original name and line width when simplification
+    // collapses a unitig on its path, so decompress gives back the original headers and wrapping.
+    let assembly_dir = tempdir().unwrap();
+    let (seq_1, seq_2) = (random_seq(1000, 0), random_seq(1000, 1));
+    let snp = format!("{}{}{}", &seq_2[..500], if &seq_2[500..501] == "A" { "C" } else { "A" },
+                      &seq_2[501..]);
+    let wrap = |seq: &str| seq.as_bytes().chunks(60).map(|c| std::str::from_utf8(c).unwrap())
+                              .collect::<Vec<_>>().join("\n");
+    for i in 0..4 {
+        let seq_2 = if i == 3 { &snp } else { &seq_2 };
+        make_test_file(&assembly_dir.path().join(format!("a{}.fasta", i)),
+                       &format!(">c\n{}\n>c\n{}\n", wrap(&seq_1), wrap(seq_2)));
+    }
+    let autocycler_dir = tempdir().unwrap();
+    let settings = CompressSettings::new(assembly_dir.path().to_path_buf(),
+                                         autocycler_dir.path().to_path_buf(), 51, 1, 1);
+    compress(CompressSettings { rename_duplicates: true, max_unitig_diff: 5, ..settings });
+    let gfa = autocycler_dir.path().join("input_assemblies.gfa");
+    let (unitig_graph, sequences) = UnitigGraph::from_gfa_file(&gfa);
+    let decompressed_dir = tempdir().unwrap();
+    save_original_seqs_to_dir(decompressed_dir.path(), &unitig_graph, &sequences,
+                              &Wrap::new("original", &sequences), None);
+    for i in 0..4 {
+        let fasta = read_to_string(decompressed_dir.path().join(format!("a{}.fasta", i))).unwrap();
+        let headers: Vec<&str> = fasta.lines().filter(|l| l.starts_with('>')).collect();
+        assert_eq!(headers, vec![">c", ">c"], "a{}.fasta", i);
+        assert!(fasta.lines().all(|l| l.len() <= 60), "a{}.fasta", i);
+    }
+}
+
+
+#[test]
+fn test_distance_cache_with_chimeras() {
+    // With --split_chimeras, clustering runs on the original graph and then the split graph. Each
+    // has its own distance cache, so a second run loads both instead of recalculating.
+    let assembly_dir = tempdir().unwrap();
+    let (chromosome, plasmid) = (random_seq(3000, 0), random_seq(1000, 1));
+    for i in 0..4 {
+        make_test_file(&assembly_dir.path().join(format!("{}.fasta", i)),
+                       &format!(">c\n{}\n>p\n{}\n", chromosome, plasmid));
+    }
+    make_test_file(&assembly_dir.path().join("4.fasta"),
+                   &format!(">chimera\n{}{}\n", chromosome, plasmid));
+    let autocycler_dir = tempdir().unwrap();
+    compress(CompressSettings::new(assembly_dir.path().to_path_buf(),
+                                   autocycler_dir.path().to_path_buf(), 51, 1, 1));
+    let settings = || ClusterSettings { split_chimeras: true,
+                                        ..ClusterSettings::new(autocycler_dir.path().to_path_buf(),
+                                                               1, 1) };
+    cluster(settings());
+    let chimeras = read_to_string(autocycler_dir.path().join("clustering/chimeras.tsv")).unwrap();
+    assert_eq!(chimeras.lines().count(), 2, "{}", chimeras);
+    let caches = [autocycler_dir.path().join("distance_cache.tsv"),
+                  autocycler_dir.path().join("distance_cache_split.tsv")];
+    for cache in &caches {
+        File::options().write(true).open(cache).unwrap().set_modified(UNIX_EPOCH).unwrap();
+    }
+    cluster(settings());
+    for cache in &caches {
+        assert_eq!(cache.metadata().unwrap().modified().unwrap(), UNIX_EPOCH,
+                   "{} was recalculated", cache.display());
+    }
+}
+
+
+#[test]
+fn test_preserve_original_wrap() {
+    // With --wrap original, decompress gives back byte-identical files for wrapped input.
+    let assembly_dir = tempdir().unwrap();
+    let seq = random_seq(250, 0);
+    let wrapped: Vec<&str> = (0..250).step_by(60).map(|i| &seq[i..(i + 60).min(250)]).collect();
+    let original = assembly_dir.path().join("a.fasta");
+    make_test_file(&original, &format!(">a\n{}\n>b\n{}\n", wrapped.join("\n"),
+                                       random_seq(40, 1)));
+    let mut metrics = InputAssemblyMetrics::default();
+    let load_settings = LoadSettings { k_size: 11, ..Default::default() };
+    let (mut sequences, assembly_count) = load_sequences(assembly_dir.path(), &load_settings,
+                                                         &mut metrics);
+    assert_eq!(sequences.iter().map(|s| s.line_width).collect::<Vec<_>>(), vec![60, 0]);
+    let mut kmer_graph = KmerGraph::new(11, 1);
+    kmer_graph.add_sequences(&sequences, assembly_count);
+    let mut unitig_graph = UnitigGraph::from_kmer_graph(&kmer_graph);
+    simplify_structure(&mut unitig_graph, &mut sequences, 0, 0.0,
+                       &mut SimplificationMetrics::default());
+    let graph_dir = tempdir().unwrap();
+    let gfa = graph_dir.path().join("graph.gfa");
+    unitig_graph.save_gfa(&gfa, &sequences).unwrap();
+    let (unitig_graph, sequences) = UnitigGraph::from_gfa_file(&gfa);
+    save_original_seqs_to_dir(graph_dir.path(), &unitig_graph, &sequences,
+                              &Wrap::new("original", &sequences), None);
+    assert_same_content(&original, &graph_dir.path().join("a.fasta"));
+}
+
+
 #[test]
 fn test_whitespace() {
     // This test checks that each instance of whitespace in contig headers is turned into a
@@ -173,8 +459,9 @@ fn test_whitespace() {
     make_test_file(&temp_file, &fasta);
     let k_size = 11;
     let mut metrics = InputAssemblyMetrics::default();
-    let (sequences, assembly_count) = load_sequences(&temp_dir.path().to_path_buf(), k_size,
-                                                        &mut metrics);
+    let load_settings = LoadSettings { k_size, ..Default::default() };
+    let (sequences, assembly_count) = load_sequences(&temp_dir.path().to_path_buf(),
+                                                     &load_settings, &mut metrics);
     assert_eq!(assembly_count, 1);
     let sequence = sequences.first().unwrap();
     assert_eq!(sequence.filename, "assembly.fasta");
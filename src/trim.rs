@@ -20,11 +20,10 @@ use std::collections::{HashMap, VecDeque};
 use std::fmt;
 use std::path::{Path, PathBuf};
 
-use crate::graph_simplification::merge_linear_paths;
 use crate::log::{section_header, explanation};
 use crate::metrics::TrimmedClusterMetrics;
-use crate::misc::{check_if_dir_exists, check_if_file_exists, format_float, quit_with_error,
-                  median_isize, mad_isize, reverse_path};
+use crate::misc::{check_gfa_version, check_if_dir_exists, check_if_file_exists, format_float,
+                  quit_with_error, median_isize, mad_isize, reverse_path};
 use crate::sequence::Sequence;
 use crate::unitig_graph::UnitigGraph;
 
@@ -33,37 +32,70 @@ const GAP: i32 = 0;
 const NONE: usize = usize::MAX;
 
 
-pub fn trim(cluster_dir: PathBuf, min_identity: f64, max_unitigs: usize, mad: f64, threads: usize) {
-    let untrimmed_gfa = cluster_dir.join("1_untrimmed.gfa");
-    let trimmed_gfa = cluster_dir.join("2_trimmed.gfa");
-    let trimmed_yaml = cluster_dir.join("2_trimmed.yaml");
-    check_settings(&cluster_dir, &untrimmed_gfa, min_identity, mad, threads);
+pub struct TrimSettings {
+    // The settings for autocycler trim, named as their options.
+    pub cluster_dir: PathBuf,
+    pub min_identity: f64,
+    pub max_unitigs: usize,
+    pub mad: f64,
+    pub linear: bool,
+    pub threads: usize,
+    pub gfa_version: u32,
+}
+
+impl TrimSettings {
+    pub fn new(cluster_dir: PathBuf, threads: usize, gfa_version: u32) -> Self {
+        // Makes settings with the option defaults.
+        TrimSettings {
+            cluster_dir, min_identity: 0.75, max_unitigs: 5000, mad: 5.0, linear: false, threads,
+            gfa_version,
+        }
+    }
+}
+
+
+pub fn trim(settings: TrimSettings) {
+    let untrimmed_gfa = settings.cluster_dir.join("1_untrimmed.gfa");
+    let trimmed_gfa = settings.cluster_dir.join("2_trimmed.gfa");
+    let trimmed_yaml = settings.cluster_dir.join("2_trimmed.yaml");
+    check_settings(&settings, &untrimmed_gfa);
     starting_message();
-    print_settings(&cluster_dir, min_identity, max_unitigs, mad, threads);
-    let (mut graph, sequences) = load_graph(&untrimmed_gfa);
-    let unitig_lengths: HashMap<_, _> = graph.unitigs.iter().map(|rc| {let u = rc.borrow(); (u.number as i32, u.length())}).collect();
-    let start_end_results = trim_start_end_overlap(&graph, &sequences, &unitig_lengths, min_identity, max_unitigs);
-    let hairpin_results = trim_harpin_overlap(&graph, &sequences, &unitig_lengths, min_identity, max_unitigs);
-    let sequences = choose_trim_type(start_end_results, hairpin_results, &mut graph, &sequences);
-    let sequences = exclude_outliers_in_length(&mut graph, &sequences, mad);
-    clean_up_graph(&mut graph, &sequences);
-    graph.save_gfa(&trimmed_gfa, &sequences).unwrap();
-    save_metrics(&trimmed_yaml, &sequences);
+    print_settings(&settings);
+    let TrimSettings { min_identity, max_unitigs, mad, linear, threads, gfa_version,
+                       .. } = settings;
+    // Parallel steps use a local thread pool, so --threads is respected even when other steps
+    // have already run in this process (e.g. autocycler pipeline).
+    let pool = ThreadPoolBuilder::new().num_threads(threads).build().unwrap();
+    pool.install(|| {
+        let (mut graph, sequences) = load_graph(&untrimmed_gfa);
+        let linear = check_linear(&graph, &sequences, linear);
+        let max_unitigs = if linear { 0 } else { max_unitigs };
+        let unitig_lengths: HashMap<_, _> = graph.unitigs.iter().map(|u| (u.number as i32, u.length())).collect();
+        let start_end_results = trim_start_end_overlap(&graph, &sequences, &unitig_lengths, min_identity, max_unitigs);
+        let hairpin_results = trim_harpin_overlap(&graph, &sequences, &unitig_lengths, min_identity, max_unitigs);
+        let sequences = choose_trim_type(start_end_results, hairpin_results, &mut graph, &sequences);
+        let sequences = exclude_outliers_in_length(&mut graph, &sequences, mad);
+        clean_up_graph(&mut graph, &sequences);
+        graph.estimate_multiplicities(&sequences);
+        graph.save_gfa_with_version(&trimmed_gfa, &sequences, gfa_version).unwrap();
+        save_metrics(&trimmed_yaml, &sequences);
+    });
     finished_message(&trimmed_gfa);
 }
 
 
-fn check_settings(cluster_dir: &Path, untrimmed_gfa: &Path, min_identity: f64, mad: f64,
-                  threads: usize) {
+fn check_settings(settings: &TrimSettings, untrimmed_gfa: &Path) {
+    let TrimSettings { ref cluster_dir, min_identity, mad, threads, gfa_version,
+                       .. } = *settings;
     check_if_dir_exists(cluster_dir);
     check_if_file_exists(untrimmed_gfa);
+    check_gfa_version(gfa_version);
     if !(0.0..=1.0).contains(&min_identity) {
         quit_with_error("--min_identity must be between 0.0 and 1 (inclusive)");
     }
     if threads < 1   { quit_with_error("--threads cannot be less than 1"); }
     if threads > 100 { quit_with_error("--threads cannot be greater than 100"); }
     if mad < 0.0     { quit_with_error("--mad cannot be less than 0"); }
-    ThreadPoolBuilder::new().num_threads(threads).build_global().unwrap();
 }
 
 
@@ -85,14 +117,19 @@ fn finished_message(trimmed_gfa: &Path) {
 }
 
 
-fn print_settings(cluster_dir: &Path, min_identity: f64, max_unitigs: usize, mad: f64,
-                  threads: usize) {
+fn print_settings(settings: &TrimSettings) {
+    let TrimSettings { ref cluster_dir, min_identity, max_unitigs, mad, linear, threads,
+                       gfa_version } = *settings;
     eprintln!("Settings:");
     eprintln!("  --cluster_dir {}", cluster_dir.display());
     eprintln!("  --min_identity {}", format_float(min_identity));
     eprintln!("  --max_unitigs {}", max_unitigs);
     eprintln!("  --mad {}", format_float(mad));
+    if linear {
+        eprintln!("  --linear");
+    }
     eprintln!("  --threads {}", threads);
+    eprintln!("  --gfa_version {}", gfa_version);
     eprintln!();
     if max_unitigs == 0 {
         eprintln!("Since --max_unitigs was set to 0, trimming is disabled.");
@@ -110,6 +147,31 @@ fn load_graph(gfa: &Path) -> (UnitigGraph, Vec<Sequence>) {
 }
 
 
+fn check_linear(graph: &UnitigGraph, sequences: &[Sequence], linear: bool) -> bool {
+    // Linear replicons (e.g. linear plasmids and phages) are not trimmed, since their ends are
+    // real and any overlap there (e.g. terminal inverted repeats) should be kept. A cluster is
+    // treated as linear if --linear was used or if all of its sequences share consistent dead ends.
+    section_header("Checking for linear replicon");
+    explanation("Clusters where every sequence starts and ends at a dead end in the graph are \
+                 treated as linear replicons, and their ends are preserved instead of trimmed.");
+    let linear = if linear {
+        eprintln!("Cluster marked as linear by --linear");
+        true
+    } else if graph.has_consistent_dead_ends(sequences) {
+        eprintln!("All sequences have consistent dead ends: cluster treated as linear");
+        true
+    } else {
+        eprintln!("Cluster not treated as linear");
+        false
+    };
+    if linear {
+        eprintln!("Start-end and hairpin trimming will be skipped.");
+    }
+    eprintln!();
+    linear
+}
+
+
 fn trim_start_end_overlap(graph: &UnitigGraph, sequences: &Vec<Sequence>, weights: &HashMap<i32, u32>,
                           min_identity: f64, max_unitigs: usize) -> Vec<Option<(Vec<i32>, u32)>> {
     if max_unitigs == 0 {
@@ -118,7 +180,7 @@ fn trim_start_end_overlap(graph: &UnitigGraph, sequences: &Vec<Sequence>, weight
     section_header("Trim start-end overlaps");
     explanation("Paths for circular replicons may contain start-end overlaps. These overlaps \
                  are searched for and trimmed if found.");
-    let paths: Vec<_> = sequences.iter().map(|seq| graph.get_unitig_path_for_sequence_i32(seq)).collect();
+    let paths: Vec<_> = graph.get_unitig_paths_for_sequences_i32(sequences);
     let results: Vec<_> = sequences.par_iter().zip(paths.par_iter()).map(|(seq, path)| {  // parallel for loop with rayon
         let trimmed_path = trim_path_start_end(path, weights, min_identity, max_unitigs);
         if let Some(trimmed_path) = trimmed_path {
@@ -144,7 +206,7 @@ fn trim_harpin_overlap(graph: &UnitigGraph, sequences: &Vec<Sequence>, weights:
     section_header("Trim hairpin overlaps");
     explanation("Paths for linear replicons may contain hairpin overlaps at the start and/or end \
                  of the contig. These overlaps are searched for and trimmed if found.");
-    let paths: Vec<_> = sequences.iter().map(|seq| graph.get_unitig_path_for_sequence_i32(seq)).collect();
+    let paths: Vec<_> = graph.get_unitig_paths_for_sequences_i32(sequences);
     let results: Vec<_> = sequences.par_iter().zip(paths.par_iter()).map(|(seq, path)| {  // parallel for loop with rayon
         let mut trimmed_start = false;
         let mut trimmed_end = false;
@@ -215,14 +277,24 @@ fn choose_trim_type(start_end_results: Vec<Option<(Vec<i32>, u32)>>, hairpin_res
     }
     assert!(sequences.len() == results.len());
 
-    for (seq, result) in sequences.iter().zip(results.iter()) {
+    let paths = graph.get_unitig_paths_for_sequences(sequences);
+    for ((seq, result), full_path) in sequences.iter().zip(results.iter()).zip(paths) {
         if result.is_none() {
             trimmed_sequences.push(seq.clone());
         } else {
+            // The trimmed path is a stretch of the full path, and the trimmed sequence keeps any
+            // repairs within that stretch (which also count towards its length).
+            let path = path_to_tuples(&result.as_ref().unwrap().0);
+            let start = full_path.windows(path.len()).position(|w| w == path).unwrap();
+            let (repairs, trimmed_length) =
+                graph.get_piece_repairs(seq, &full_path, start..start + path.len());
             graph.remove_sequence_from_graph(seq.id);
-            let (path, trimmed_length) = result.as_ref().unwrap();
-            let trimmed_sequence = graph.create_sequence_and_positions(seq.id, *trimmed_length, seq.filename.clone(),
-                                                                        seq.contig_header.clone(), seq.cluster, path_to_tuples(path));
+            graph.seq_repairs.remove(&seq.id);
+            if !repairs.is_empty() {
+                graph.seq_repairs.insert(seq.id, repairs);
+            }
+            let trimmed_sequence = graph.create_sequence_and_positions(seq.id, trimmed_length, seq.filename.clone(),
+                                                                        seq.contig_header.clone(), seq.cluster, path);
             trimmed_sequences.push(trimmed_sequence);
         }
     }
@@ -261,15 +333,14 @@ fn exclude_outliers_in_length(graph: &mut UnitigGraph, sequences: &Vec<Sequence>
 }
 
 
-fn clean_up_graph(graph: &mut UnitigGraph, sequences: &Vec<Sequence>) {
+fn clean_up_graph(graph: &mut UnitigGraph, sequences: &[Sequence]) {
     section_header("Clean graph");
     explanation("The unitig graph is now cleaned up based on any trimming and/or exclusion that \
                  has occurred above.");
     graph.recalculate_depths();
     graph.remove_zero_depth_unitigs();
-    merge_linear_paths(graph, sequences, None);
+    graph.merge_linear_paths(sequences);
     graph.print_basic_graph_info();
-    graph.renumber_unitigs();
 }
 
 
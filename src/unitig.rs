@@ -11,13 +11,12 @@
 // Public License for more details. You should have received a copy of the GNU General Public
 // License along with Autocycler. If not, see <http://www.gnu.org/licenses/>.
 
-use std::cell::RefCell;
-use std::collections::VecDeque;
+use fxhash::FxHashMap;
+use std::collections::{HashMap, VecDeque};
 use std::fmt;
-use std::rc::Rc;
 
-use crate::kmer_graph::Kmer;
-use crate::misc::{quit_with_error, reverse_complement, strand};
+use crate::kmer_graph::KmerStrand;
+use crate::misc::{reverse_complement, strand};
 use crate::position::Position;
 
 
@@ -29,8 +28,8 @@ static CONSENTIG_COLOUR: &str = "steelblue";
 #[derive(Clone, Default)]
 pub struct Unitig {
     pub number: u32,
-    pub forward_kmers: VecDeque<*const Kmer>,
-    pub reverse_kmers: VecDeque<*const Kmer>,
+    pub kmer_seq: VecDeque<u8>,
+    pub kmer_depths: Vec<usize>,
     pub forward_seq: Vec<u8>,
     pub reverse_seq: Vec<u8>,
     pub depth: f64,
@@ -40,6 +39,26 @@ pub struct Unitig {
     pub anchor: bool,
     pub bridge: bool,
 
+    // Connected component (1-based), set by UnitigGraph::assign_component_ids. This is not kept
+    // up to date when the graph changes, so it is only valid after that method has been called.
+    pub component: u32,
+
+    // Alternative versions of parts of this unitig's sequence, left behind when near-identical
+    // unitigs were collapsed into it.
+    pub ambiguities: Vec<Ambiguity>,
+
+    // Estimated number of times this unitig occurs per input genome, set by
+    // UnitigGraph::estimate_multiplicities (None if not estimated).
+    pub multiplicity: Option<u32>,
+
+    // Inverted repeats found in this unitig (within its sequence or at a hairpin link on one of
+    // its ends), set by UnitigGraph::flag_inverted_repeats.
+    pub inverted_repeats: Vec<InvertedRepeat>,
+
+    // Confidence in each region of this unitig's sequence, set by autocycler resolve for the
+    // anchors and bridges which make up the consensus.
+    pub confidence: Vec<Confidence>,
+
     pub forward_positions: Vec<Position>,
     pub reverse_positions: Vec<Position>,
     pub forward_next: Vec<UnitigStrand>,
@@ -49,44 +68,78 @@ pub struct Unitig {
 }
 
 impl Unitig {
-    pub fn from_kmers(number: u32, forward_kmer: &Kmer, reverse_kmer: &Kmer) -> Self {
+    pub fn from_kmers(number: u32, forward_kmer: &KmerStrand) -> Self {
         // This constructor is for Unitig objects built from k-mers. Happens in multiple stages:
-        // 1. Initialised with a starting k-mer (forward and reverse).
+        // 1. Initialised with a starting k-mer.
         // 2. K-mers are then added with add_kmer_to_end and add_kmer_to_start methods.
-        // 3. The simplify_seqs method combines the k-mers into forward and reverse sequences.
-        // 4. The trim_overlaps method removes overlapping sequences from both ends.
+        // 3. Positions are taken from the first k-mer on each strand with set_kmer_positions.
+        // 4. The simplify_seqs method turns the k-mers into forward and reverse sequences.
+        // 5. The trim_overlaps method removes overlapping sequences from both ends.
         Unitig {
             number,
-            forward_kmers: VecDeque::from(vec![forward_kmer as *const Kmer]),
-            reverse_kmers: VecDeque::from(vec![reverse_kmer as *const Kmer]),
+            kmer_seq: forward_kmer.seq().iter().copied().collect(),
+            kmer_depths: vec![forward_kmer.depth()],
             ..Default::default()
         }
     }
 
-    pub fn from_segment_line(segment_line: &str) -> Self {
+    pub fn from_segment_line(segment_line: &str) -> Result<Self, String> {
         let parts: Vec<&str> = segment_line.split('\t').collect();
         if parts.len() < 3 {
-            quit_with_error("Segment line does not have enough parts.");
+            return Err("Segment line does not have enough parts.".to_string());
         }
-        let number = parts[1].parse::<u32>().unwrap_or_else(|_| {
-            quit_with_error("Unable to parse unitig number.");
-        });
+        let number = parts[1].parse::<u32>()
+            .map_err(|_| "Unable to parse unitig number.".to_string())?;
         let forward_seq = parts[2].as_bytes().to_owned();
         let reverse_seq = reverse_complement(&forward_seq);
         let depth = parts.iter()
             .find(|&p| p.starts_with("DP:f:")).and_then(|p| p[5..].parse::<f64>().ok())
-            .unwrap_or_else(|| {
-                quit_with_error("Could not find a depth tag (e.g. DP:f:10.00) in the GFA segment \
-                                 line.\nAre you sure this is an Autocycler-generated GFA file?");
-            });
+            .ok_or_else(|| "Could not find a depth tag (e.g. DP:f:10.00) in the GFA segment \
+                            line.\nAre you sure this is an Autocycler-generated GFA file?"
+                            .to_string())?;
         let anchor = parts.iter().any(|p| *p == format!("CL:z:{}", ANCHOR_COLOUR)) ||
                      parts.iter().any(|p| *p == format!("CL:z:{}", CONSENTIG_COLOUR));
         let bridge = parts.iter().any(|p| *p == format!("CL:z:{}", BRIDGE_COLOUR)) ||
                      parts.iter().any(|p| *p == format!("CL:z:{}", CONSENTIG_COLOUR));
-        Unitig {
-            number, forward_seq, reverse_seq, depth, anchor, bridge,
-            ..Default::default()
+        let ambiguities = parts.iter().find(|&p| p.starts_with("AM:Z:"))
+            .map(|p| p[5..].split(',').map(|a| {
+                Ambiguity::from_tag_value(a)
+                    .ok_or_else(|| format!("Unable to parse ambiguity: {}", a))
+            }).collect::<Result<_, _>>()).transpose()?.unwrap_or_default();
+        let multiplicity = parts.iter().find(|&p| p.starts_with("CN:i:")).map(|p| {
+            p[5..].parse::<u32>().map_err(|_| format!("Unable to parse multiplicity tag: {}", p))
+        }).transpose()?;
+        let inverted_repeats = parts.iter().find(|&p| p.starts_with("IR:Z:"))
+            .map(|p| p[5..].split(',').map(|r| {
+                InvertedRepeat::from_tag_value(r)
+                    .ok_or_else(|| format!("Unable to parse inverted repeat: {}", r))
+            }).collect::<Result<_, _>>()).transpose()?.unwrap_or_default();
+        let confidence = parts.iter().find(|&p| p.starts_with("CF:Z:"))
+            .map(|p| p[5..].split(',').map(|c| {
+                Confidence::from_tag_value(c)
+                    .ok_or_else(|| format!("Unable to parse confidence: {}", c))
+            }).collect::<Result<_, _>>()).transpose()?.unwrap_or_default();
+        Ok(Unitig {
+            number, forward_seq, reverse_seq, depth, anchor, bridge, ambiguities, multiplicity,
+            inverted_repeats, confidence, ..Default::default()
+        })
+    }
+
+    pub fn from_gfa2_segment_line(segment_line: &str) -> Result<Self, String> {
+        // GFA2 segment lines are the same as GFA1 segment lines except for an extra length field
+        // before the sequence. This checks that length and then removes it, so the rest of the
+        // line can be parsed like a GFA1 segment line.
+        let mut parts: Vec<&str> = segment_line.split('\t').collect();
+        if parts.len() < 4 {
+            return Err("Segment line does not have enough parts.".to_string());
+        }
+        let length = parts[2].parse::<usize>()
+            .map_err(|_| "Unable to parse segment length.".to_string())?;
+        if length != parts[3].len() {
+            return Err("Segment length does not match sequence length.".to_string());
         }
+        parts.remove(2);
+        Self::from_segment_line(&parts.join("\t"))
     }
 
     pub fn bridge(number: u32, forward_seq: Vec<u8>, depth: f64) -> Self {
@@ -98,59 +151,29 @@ impl Unitig {
         }
     }
 
-    pub fn add_kmer_to_end(&mut self, forward_kmer: &Kmer, reverse_kmer: &Kmer) {
-        self.forward_kmers.push_back(forward_kmer);
-        self.reverse_kmers.push_front(reverse_kmer);
+    pub fn add_kmer_to_end(&mut self, forward_kmer: &KmerStrand) {
+        self.kmer_seq.push_back(*forward_kmer.seq().last().unwrap());
+        self.kmer_depths.push(forward_kmer.depth());
     }
 
-    pub fn add_kmer_to_start(&mut self, forward_kmer: &Kmer, reverse_kmer: &Kmer) {
-        self.forward_kmers.push_front(forward_kmer);
-        self.reverse_kmers.push_back(reverse_kmer);
+    pub fn add_kmer_to_start(&mut self, forward_kmer: &KmerStrand) {
+        self.kmer_seq.push_front(*forward_kmer.seq().first().unwrap());
+        self.kmer_depths.push(forward_kmer.depth());
     }
 
-    pub fn simplify_seqs(&mut self) {
-        self.combine_kmers_into_sequences();
-        self.set_positions();
-        self.set_average_depth();
-        self.forward_kmers.clear();
-        self.reverse_kmers.clear();
-    }
-
-    fn combine_kmers_into_sequences(&mut self) {
-        if let Some(first_kmer) = self.forward_kmers.front() {
-            self.forward_seq = unsafe{&**first_kmer}.seq().to_vec();
-            self.forward_kmers.iter().skip(1).for_each(|kmer| {
-                self.forward_seq.push(*unsafe{&**kmer}.seq().last().unwrap());
-            });
-        }
-        if let Some(first_kmer) = self.reverse_kmers.front() {
-            self.reverse_seq = unsafe{&**first_kmer}.seq().to_vec();
-            self.reverse_kmers.iter().skip(1).for_each(|kmer| {
-                self.reverse_seq.push(*unsafe{&**kmer}.seq().last().unwrap());
-            });
-        }
-    }
-
-    fn set_positions(&mut self) {
+    pub fn set_kmer_positions(&mut self, first_forward_kmer: &KmerStrand,
+                              first_reverse_kmer: &KmerStrand) {
         // Sets the Unitig's positions on each strand to be the same as the positions of the first
-        // Kmer on that strand.
-        if let Some(&kmer_ptr) = self.forward_kmers.front() {
-            let kmer = unsafe { &*kmer_ptr };
-            self.forward_positions.extend_from_slice(&kmer.positions);
-        }
-        if let Some(&kmer_ptr) = self.reverse_kmers.front() {
-            let kmer = unsafe { &*kmer_ptr };
-            self.reverse_positions.extend_from_slice(&kmer.positions);
-        }
+        // k-mer on that strand.
+        self.forward_positions = first_forward_kmer.positions();
+        self.reverse_positions = first_reverse_kmer.positions();
     }
 
-    fn set_average_depth(&mut self) {
-        let for_depths: Vec<f64> = self.forward_kmers.iter().map(|k| unsafe{&**k}.depth() as f64).collect();
-        let rev_depths: Vec<f64> = self.reverse_kmers.iter().map(|k| unsafe{&**k}.depth() as f64).collect();
-        let forward_avg = for_depths.iter().sum::<f64>() / for_depths.len() as f64;
-        let reverse_avg = rev_depths.iter().sum::<f64>() / rev_depths.len() as f64;
-        assert_eq!(forward_avg, reverse_avg);
-        self.depth = forward_avg;
+    pub fn simplify_seqs(&mut self) {
+        self.forward_seq = self.kmer_seq.drain(..).collect();
+        self.reverse_seq = reverse_complement(&self.forward_seq);
+        self.depth = self.kmer_depths.iter().sum::<usize>() as f64 / self.kmer_depths.len() as f64;
+        self.kmer_depths.clear();
     }
 
     pub fn trim_overlaps(&mut self, k_size: usize) {
@@ -165,7 +188,41 @@ impl Unitig {
 
     pub fn gfa_segment_line(&self) -> String {
         let seq_str = String::from_utf8_lossy(&self.forward_seq);
-        format!("S\t{}\t{}\tDP:f:{:.2}{}", self.number, seq_str, self.depth, self.colour_tag())
+        format!("S\t{}\t{}\tDP:f:{:.2}{}{}{}{}{}", self.number, seq_str, self.depth,
+                self.multiplicity_tag(), self.ambiguity_tag(), self.inverted_repeat_tag(),
+                self.confidence_tag(), self.colour_tag())
+    }
+
+    pub fn gfa2_segment_line(&self) -> String {
+        let seq_str = String::from_utf8_lossy(&self.forward_seq);
+        format!("S\t{}\t{}\t{}\tDP:f:{:.2}{}{}{}{}{}", self.number, self.length(), seq_str,
+                self.depth, self.multiplicity_tag(), self.ambiguity_tag(),
+                self.inverted_repeat_tag(), self.confidence_tag(), self.colour_tag())
+    }
+
+    pub fn multiplicity_tag(&self) -> String {
+        match self.multiplicity {
+            Some(m) => format!("\tCN:i:{}", m),
+            None    => String::new(),
+        }
+    }
+
+    pub fn ambiguity_tag(&self) -> String {
+        if self.ambiguities.is_empty() { return String::new(); }
+        let values: Vec<String> = self.ambiguities.iter().map(|a| a.tag_value()).collect();
+        format!("\tAM:Z:{}", values.join(","))
+    }
+
+    pub fn inverted_repeat_tag(&self) -> String {
+        if self.inverted_repeats.is_empty() { return String::new(); }
+        let values: Vec<String> = self.inverted_repeats.iter().map(|r| r.tag_value()).collect();
+        format!("\tIR:Z:{}", values.join(","))
+    }
+
+    pub fn confidence_tag(&self) -> String {
+        if self.confidence.is_empty() { return String::new(); }
+        let values: Vec<String> = self.confidence.iter().map(|c| c.tag_value()).collect();
+        format!("\tCF:Z:{}", values.join(","))
     }
 
     pub fn colour_tag(&self) -> String {
@@ -196,22 +253,28 @@ impl Unitig {
         self.forward_next.is_empty()
     }
 
-    pub fn hairpin_start(&self) -> bool {
+    pub fn hairpin_start(&self, handle: usize) -> bool {
+        // The handle is this unitig's own index in UnitigGraph::unitigs, which links refer to it
+        // by (likewise for the other self-link checks below).
         self.reverse_next.len() == 1 &&
-        self.reverse_next[0].strand == strand::FORWARD &&
-        self.reverse_next[0].unitig.borrow().number == self.number
+        self.reverse_next[0] == UnitigStrand::new(handle, strand::FORWARD)
     }
 
-    pub fn hairpin_end(&self) -> bool {
+    pub fn hairpin_end(&self, handle: usize) -> bool {
         self.forward_next.len() == 1 &&
-        self.forward_next[0].strand == strand::REVERSE &&
-        self.forward_next[0].unitig.borrow().number == self.number
+        self.forward_next[0] == UnitigStrand::new(handle, strand::REVERSE)
     }
 
     pub fn remove_seq_from_start(&mut self, amount: usize) {
         for p in &mut self.forward_positions {
             p.pos += amount as u32
         }
+        let amount_u32 = amount as u32;
+        self.ambiguities.retain(|a| a.start >= amount_u32);
+        for a in &mut self.ambiguities {
+            a.start -= amount_u32;
+            a.end -= amount_u32;
+        }
         assert!(amount <= self.forward_seq.len());
         self.forward_seq.drain(0..amount);
         self.reverse_seq.truncate(self.reverse_seq.len() - amount);
@@ -221,6 +284,8 @@ impl Unitig {
         for p in &mut self.reverse_positions {
             p.pos += amount as u32
         }
+        let new_length = self.length().saturating_sub(amount as u32);
+        self.ambiguities.retain(|a| a.end <= new_length);
         assert!(amount <= self.forward_seq.len());
         self.forward_seq.truncate(self.reverse_seq.len() - amount);
         self.reverse_seq.drain(0..amount);
@@ -230,6 +295,7 @@ impl Unitig {
         for p in &mut self.forward_positions {
             p.pos -= seq.len() as u32;
         }
+        self.ambiguities = self.ambiguities.iter().map(|a| a.shifted(seq.len() as u32)).collect();
         self.forward_seq.splice(0..0, seq.iter().cloned());
         self.reverse_seq = reverse_complement(&self.forward_seq);
     }
@@ -242,17 +308,20 @@ impl Unitig {
         self.reverse_seq = reverse_complement(&self.forward_seq);
     }
 
-    pub fn remove_sequence(&mut self, id: u16) {
+    pub fn remove_sequence(&mut self, id: u16, seq_weights: &HashMap<u16, f64>) {
         // Removes all Positions from the Unitig which have the given sequence ID. This can reduce
         // the Unitig's depth.
         self.forward_positions.retain(|p| p.seq_id() != id);
         self.reverse_positions.retain(|p| p.seq_id() != id);
         assert_eq!(self.forward_positions.len(), self.reverse_positions.len());
-        self.recalculate_depth();
+        self.recalculate_depth(seq_weights);
     }
 
-    pub fn recalculate_depth(&mut self) {
-        self.depth = self.forward_positions.len() as f64;
+    pub fn recalculate_depth(&mut self, seq_weights: &HashMap<u16, f64>) {
+        // Sets the depth to the summed weight of the Unitig's sequences. Sequences without a
+        // weight count as 1, so with no weights this is the number of Positions.
+        self.depth = self.forward_positions.iter()
+            .fold(0.0, |d, p| d + seq_weights.get(&p.seq_id()).copied().unwrap_or(1.0));
     }
 
     pub fn clear_positions(&mut self) {
@@ -267,14 +336,58 @@ impl Unitig {
         }
     }
 
-    pub fn is_isolated_and_circular(&self) -> bool {
+    pub fn is_isolated_and_circular(&self, handle: usize) -> bool {
         // Returns whether or not this unitig has a circularising link and no other links.
-        if self.forward_next.len() != 1 || self.forward_prev.len() != 1 {
-            return false;
+        let self_link = UnitigStrand::new(handle, strand::FORWARD);
+        self.forward_next == [self_link] && self.forward_prev == [self_link]
+    }
+
+    pub fn has_clean_circular_join(&self, handle: usize) -> bool {
+        // Verifies a circularising link more strictly than is_isolated_and_circular: the reverse
+        // strand must carry the mirror-image self-link, so the join has no dead end on either
+        // strand. Unitig links have no overlap, so this is all that's needed for the sequence's
+        // end to join cleanly back onto its start.
+        let self_link = UnitigStrand::new(handle, strand::REVERSE);
+        self.is_isolated_and_circular(handle) && !self.forward_seq.is_empty() &&
+        self.reverse_next == [self_link] && self.reverse_prev == [self_link]
+    }
+
+    pub fn find_inverted_repeats(&self, k_size: usize, min_length: usize) -> Vec<InvertedRepeat> {
+        // Finds pairs of regions in this unitig's sequence which are reverse complements of each
+        // other, at least min_length bases long. Matching k-mers (only those unique in the
+        // forward sequence) are chained along diagonals to give each repeat's extent.
+        let n = self.forward_seq.len();
+        if k_size == 0 || n < k_size || min_length == 0 { return Vec::new(); }
+        let mut index: FxHashMap<&[u8], Option<usize>> = FxHashMap::default();
+        for (i, kmer) in self.forward_seq.windows(k_size).enumerate() {
+            index.entry(kmer).and_modify(|e| *e = None).or_insert(Some(i));
+        }
+
+        // Each run is (first forward pos, first reverse pos, last reverse pos), keyed by diagonal.
+        let mut runs: HashMap<isize, (usize, usize, usize)> = HashMap::new();
+        let mut finished = Vec::new();
+        for (j, kmer) in self.reverse_seq.windows(k_size).enumerate() {
+            let Some(&Some(i)) = index.get(kmer) else { continue; };
+            let diagonal = i as isize - j as isize;
+            match runs.get_mut(&diagonal) {
+                Some(run) if run.2 + 1 == j => { run.2 = j; }
+                _ => {
+                    if let Some(run) = runs.insert(diagonal, (i, j, j)) { finished.push(run); }
+                }
+            }
         }
-        let next = &self.forward_next[0];
-        let prev = &self.forward_prev[0];
-        next.number() == self.number && next.strand && prev.number() == self.number && prev.strand
+        finished.extend(runs.into_values());
+
+        let mut repeats: Vec<InvertedRepeat> = finished.into_iter().filter_map(|(i, j0, j1)| {
+            let length = j1 - j0 + k_size;
+            let partner_start = n - j1 - k_size;
+            if length < min_length || partner_start < i { return None; }
+            Some(InvertedRepeat::Internal { start: i as u32, end: (i + length) as u32,
+                                            partner_start: partner_start as u32,
+                                            partner_end: (partner_start + length) as u32 })
+        }).collect();
+        repeats.sort();
+        repeats
     }
 
     fn is_consentig(&self) -> bool {
@@ -288,116 +401,291 @@ impl Unitig {
     }
 }
 
-impl fmt::Display for Unitig {
-    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
-        let display_seq = if self.forward_seq.len() < 15 {
-            String::from_utf8_lossy(&self.forward_seq).to_string()
-        } else {
-            format!("{}...{}",
-                    String::from_utf8_lossy(&self.forward_seq[..6]),
-                    String::from_utf8_lossy(&self.forward_seq[self.forward_seq.len() - 6..]))
-        };
-        write!(f, "unitig {}: {}, {} bp, {:.2}x", self.number, display_seq, self.forward_seq.len(), self.depth)
+#[derive(Clone, Debug, PartialEq)]
+pub struct Ambiguity {
+    // Bases start..end of the unitig's forward sequence were alt in some of the input sequences,
+    // which had the given total depth. The range can be empty (an insertion) as can alt (a
+    // deletion).
+    pub start: u32,
+    pub end: u32,
+    pub alt: Vec<u8>,
+    pub depth: f64,
+}
+
+impl Ambiguity {
+    pub fn tag_value(&self) -> String {
+        format!("{}-{}:{}:{:.2}", self.start, self.end, String::from_utf8_lossy(&self.alt),
+                self.depth)
+    }
+
+    pub fn from_tag_value(value: &str) -> Option<Self> {
+        let parts: Vec<&str> = value.split(':').collect();
+        if parts.len() != 3 { return None; }
+        let (start, end) = parts[0].split_once('-')?;
+        let (start, end) = (start.parse::<u32>().ok()?, end.parse::<u32>().ok()?);
+        if end < start { return None; }
+        let alt = parts[1].as_bytes().to_owned();
+        let depth = parts[2].parse::<f64>().ok()?;
+        Some(Ambiguity { start, end, alt, depth })
+    }
+
+    pub fn shifted(&self, offset: u32) -> Self {
+        Ambiguity { start: self.start + offset, end: self.end + offset, ..self.clone() }
+    }
+
+    pub fn flipped(&self, unitig_length: u32) -> Self {
+        // Returns this ambiguity as it would be on the unitig's reverse strand.
+        Ambiguity {
+            start: unitig_length - self.end,
+            end: unitig_length - self.start,
+            alt: reverse_complement(&self.alt),
+            depth: self.depth,
+        }
     }
 }
 
-impl fmt::Debug for Unitig {
-    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result { fmt::Display::fmt(self, f) }
+#[derive(Clone, Debug, PartialEq)]
+pub struct Confidence {
+    // Bases start..end of the unitig's forward sequence came from one anchor or bridge. Support
+    // is the fraction of input assemblies which agreed on it, and consistency is the ratio of the
+    // lowest to highest depth of the unitigs it was built from.
+    pub start: u32,
+    pub end: u32,
+    pub support: f64,
+    pub consistency: f64,
 }
 
+impl Confidence {
+    pub fn score(&self) -> f64 {
+        self.support * self.consistency
+    }
 
-// Since Unitigs are often dealt with in a strand-specific manner, this struct bundles up a Unitig
-// (via a reference-counted reference) along with its strand.
-#[derive(Clone)]
-pub struct UnitigStrand {
-    pub unitig: Rc<RefCell<Unitig>>,
-    pub strand: bool,
-}
+    pub fn tag_value(&self) -> String {
+        format!("{}-{}:{:.2}:{:.2}", self.start, self.end, self.support, self.consistency)
+    }
 
-impl UnitigStrand {
-    pub fn new(unitig: &Rc<RefCell<Unitig>>, strand: bool) -> Self {
-        UnitigStrand {
-            unitig: Rc::clone(unitig),
-            strand,
-        }
+    pub fn from_tag_value(value: &str) -> Option<Self> {
+        let parts: Vec<&str> = value.split(':').collect();
+        if parts.len() != 3 { return None; }
+        let (start, end) = parts[0].split_once('-')?;
+        let (start, end) = (start.parse::<u32>().ok()?, end.parse::<u32>().ok()?);
+        if end < start { return None; }
+        let support = parts[1].parse::<f64>().ok()?;
+        let consistency = parts[2].parse::<f64>().ok()?;
+        Some(Confidence { start, end, support, consistency })
     }
 
-    pub fn number(&self) -> u32 {
-        self.unitig.borrow().number
+    pub fn shifted(&self, offset: u32) -> Self {
+        Confidence { start: self.start + offset, end: self.end + offset, ..self.clone() }
     }
 
-    pub fn signed_number(&self) -> i32 {
-        if self.strand {
-            self.unitig.borrow().number as i32
-        } else {
-            -(self.unitig.borrow().number as i32)
-        }
+    pub fn flipped(&self, unitig_length: u32) -> Self {
+        // Returns this region as it would be on the unitig's reverse strand.
+        Confidence { start: unitig_length - self.end, end: unitig_length - self.start,
+                     ..self.clone() }
     }
+}
 
-    pub fn length(&self) -> u32 {
-        self.unitig.borrow().length()
+#[derive(Clone, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub enum InvertedRepeat {
+    // Bases start..end of the unitig's forward sequence are the reverse complement of bases
+    // partner_start..partner_end (the two ranges are the same for a palindrome).
+    Internal { start: u32, end: u32, partner_start: u32, partner_end: u32 },
+
+    // The unitig's start or end links to its own other strand, folding the sequence back on
+    // itself.
+    HairpinStart,
+    HairpinEnd,
+}
+
+impl InvertedRepeat {
+    pub fn tag_value(&self) -> String {
+        match self {
+            InvertedRepeat::Internal { start, end, partner_start, partner_end } =>
+                format!("{}-{}:{}-{}", start, end, partner_start, partner_end),
+            InvertedRepeat::HairpinStart => "start".to_string(),
+            InvertedRepeat::HairpinEnd => "end".to_string(),
+        }
     }
 
-    pub fn get_seq(&self) -> Vec<u8> {
-        self.unitig.borrow().get_seq(self.strand)
+    pub fn from_tag_value(value: &str) -> Option<Self> {
+        match value {
+            "start" => return Some(InvertedRepeat::HairpinStart),
+            "end" => return Some(InvertedRepeat::HairpinEnd),
+            _ => {}
+        }
+        let (a, b) = value.split_once(':')?;
+        let (start, end) = a.split_once('-')?;
+        let (partner_start, partner_end) = b.split_once('-')?;
+        let (start, end) = (start.parse::<u32>().ok()?, end.parse::<u32>().ok()?);
+        let (partner_start, partner_end) = (partner_start.parse::<u32>().ok()?,
+                                            partner_end.parse::<u32>().ok()?);
+        if end < start || partner_end < partner_start { return None; }
+        Some(InvertedRepeat::Internal { start, end, partner_start, partner_end })
     }
 
-    pub fn anchor(&self) -> bool {
-        self.unitig.borrow().anchor
+    pub fn length(&self) -> u32 {
+        match self {
+            InvertedRepeat::Internal { start, end, .. } => end - start,
+            _ => 0,
+        }
     }
 }
 
-impl fmt::Display for UnitigStrand {
+impl fmt::Display for Unitig {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
-        write!(f, "{}{}", self.unitig.borrow().number, if self.strand { "+" } else { "-" })
+        let display_seq = if self.forward_seq.len() < 15 {
+            String::from_utf8_lossy(&self.forward_seq).to_string()
+        } else {
+            format!("{}...{}",
+                    String::from_utf8_lossy(&self.forward_seq[..6]),
+                    String::from_utf8_lossy(&self.forward_seq[self.forward_seq.len() - 6..]))
+        };
+        write!(f, "unitig {}: {}, {} bp, {:.2}x", self.number, display_seq, self.forward_seq.len(), self.depth)
     }
 }
 
-impl fmt::Debug for UnitigStrand {
+impl fmt::Debug for Unitig {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result { fmt::Display::fmt(self, f) }
 }
 
 
+// Since Unitigs are often dealt with in a strand-specific manner, this struct bundles up a
+// Unitig's handle (its index in UnitigGraph::unitigs) along with its strand. Links between unitigs
+// are stored this way, so they must be remapped whenever unitigs are removed from the graph (see
+// UnitigGraph::retain_unitigs).
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub struct UnitigStrand {
+    pub unitig: usize,
+    pub strand: bool,
+}
+
+impl UnitigStrand {
+    pub fn new(unitig: usize, strand: bool) -> Self {
+        UnitigStrand { unitig, strand }
+    }
+}
+
+
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::kmer_graph::KmerGraph;
     use crate::sequence::Sequence;
     use crate::misc::strand;
 
     #[test]
     fn test_from_segment_line() {
-        let u1 = Unitig::from_segment_line("S\t123\tACGATCGACTACGT\tDP:f:4.56");
+        let u1 = Unitig::from_segment_line("S\t123\tACGATCGACTACGT\tDP:f:4.56").unwrap();
         assert_eq!(format!("{}", u1), "unitig 123: ACGATCGACTACGT, 14 bp, 4.56x");
 
-        let u1 = Unitig::from_segment_line("S\t321\tATCGACTACGACTACGACATCG\tDP:f:6.54");
+        let u1 = Unitig::from_segment_line("S\t321\tATCGACTACGACTACGACATCG\tDP:f:6.54").unwrap();
         assert_eq!(format!("{}", u1), "unitig 321: ATCGAC...ACATCG, 22 bp, 6.54x");
     }
 
     #[test]
-    fn test_from_kmers() {
-        let k_size = 5; let half_k = k_size / 2;
-        let seq = Sequence::new_with_seq(1, "ACGCATAGCACTAGCTACGA".to_string(),
-                                         "assembly.fasta".to_string(), "contig_1".to_string(), 20, half_k);
-        let forward_raw = seq.forward_seq.as_ptr();
-        let reverse_raw = seq.reverse_seq.as_ptr();
+    fn test_from_gfa2_segment_line() {
+        let u1 = Unitig::from_gfa2_segment_line("S\t123\t14\tACGATCGACTACGT\tDP:f:4.56").unwrap();
+        assert_eq!(format!("{}", u1), "unitig 123: ACGATCGACTACGT, 14 bp, 4.56x");
+        assert_eq!(u1.gfa2_segment_line(), "S\t123\t14\tACGATCGACTACGT\tDP:f:4.56");
+        assert!(Unitig::from_gfa2_segment_line("S\t123\t15\tACGATCGACTACGT\tDP:f:4.56").is_err());
+    }
+
+    #[test]
+    fn test_multiplicity_tag() {
+        let u = Unitig::from_segment_line("S\t1\tACGT\tDP:f:4.00").unwrap();
+        assert_eq!(u.multiplicity, None);
+        let line = "S\t1\tACGT\tDP:f:4.00\tCN:i:2\tCL:z:forestgreen";
+        let u = Unitig::from_segment_line(line).unwrap();
+        assert_eq!(u.multiplicity, Some(2));
+        assert_eq!(u.gfa_segment_line(), line);
+        assert_eq!(u.gfa2_segment_line(), "S\t1\t4\tACGT\tDP:f:4.00\tCN:i:2\tCL:z:forestgreen");
+        assert!(Unitig::from_segment_line("S\t1\tACGT\tDP:f:4.00\tCN:i:x").is_err());
+    }
 
-        let forward_k1 = Kmer::new(unsafe{forward_raw.add(4)}, 5, 1);
-        let reverse_k1 = Kmer::new(unsafe{reverse_raw.add(15)}, 5, 1);
+    #[test]
+    fn test_ambiguities() {
+        let line = "S\t1\tACGATCGACTACGT\tDP:f:4.56\tAM:Z:3-4:TT:1.00,8-8:G:2.00";
+        let mut u = Unitig::from_segment_line(line).unwrap();
+        assert_eq!(u.ambiguities,
+                   vec![Ambiguity { start: 3, end: 4, alt: b"TT".to_vec(), depth: 1.0 },
+                        Ambiguity { start: 8, end: 8, alt: b"G".to_vec(), depth: 2.0 }]);
+        assert_eq!(u.gfa_segment_line(), line);
+        assert_eq!(u.ambiguities[0].flipped(14),
+                   Ambiguity { start: 10, end: 11, alt: b"AA".to_vec(), depth: 1.0 });
+
+        // Ambiguities move with the sequence, and are dropped if their bases are removed.
+        u.add_seq_to_start(b"AA".to_vec());
+        assert_eq!(u.ambiguity_tag(), "\tAM:Z:5-6:TT:1.00,10-10:G:2.00");
+        u.remove_seq_from_start(4);
+        assert_eq!(u.ambiguity_tag(), "\tAM:Z:1-2:TT:1.00,6-6:G:2.00");
+        u.remove_seq_from_end(6);
+        assert_eq!(u.ambiguity_tag(), "\tAM:Z:1-2:TT:1.00,6-6:G:2.00");
+        u.remove_seq_from_end(1);
+        assert_eq!(u.ambiguity_tag(), "\tAM:Z:1-2:TT:1.00");
+        u.remove_seq_from_start(2);
+        assert_eq!(u.ambiguity_tag(), "");
 
-        let forward_k2 = Kmer::new(unsafe{forward_raw.add(5)}, 5, 1);
-        let reverse_k2 = Kmer::new(unsafe{reverse_raw.add(14)}, 5, 1);
+        assert!(Unitig::from_segment_line("S\t1\tACGT\tDP:f:4.56\tAM:Z:3-1:TT:1.00").is_err());
+    }
+
+    #[test]
+    fn test_confidence() {
+        let line = "S\t1\tACGATCGACTACGT\tDP:f:4.56\tCF:Z:0-10:1.00:1.00,10-14:0.50:0.80";
+        let u = Unitig::from_segment_line(line).unwrap();
+        assert_eq!(u.confidence,
+                   vec![Confidence { start: 0, end: 10, support: 1.0, consistency: 1.0 },
+                        Confidence { start: 10, end: 14, support: 0.5, consistency: 0.8 }]);
+        assert_eq!(u.gfa_segment_line(), line);
+        assert_eq!(u.confidence[1].score(), 0.4);
+        assert_eq!(u.confidence[1].flipped(14),
+                   Confidence { start: 0, end: 4, support: 0.5, consistency: 0.8 });
+        assert!(Unitig::from_segment_line("S\t1\tACGT\tDP:f:4.56\tCF:Z:3-1:1.00:1.00").is_err());
+    }
 
-        let forward_k3 = Kmer::new(unsafe{forward_raw.add(6)}, 5, 1);
-        let reverse_k3 = Kmer::new(unsafe{reverse_raw.add(13)}, 5, 1);
+    #[test]
+    fn test_inverted_repeats() {
+        let seq = "GGATCACAGTCTACACTGCTCACTCCAACCCCGGCCCCTGAGTCCGAGGAGAGGGTGCTT\
+                   CAGGGGCCGGGGTTGGAGTGAGCAGTGTAGACTGTGATCC";
+        let u = Unitig::from_segment_line(&format!("S\t1\t{}\tDP:f:1", seq)).unwrap();
+        let repeat = InvertedRepeat::Internal { start: 0, end: 41, partner_start: 59,
+                                                partner_end: 100 };
+        assert_eq!(u.find_inverted_repeats(11, 30), vec![repeat.clone()]);
+        assert_eq!(u.find_inverted_repeats(11, 50), vec![]);
+        assert_eq!(u.find_inverted_repeats(0, 30), vec![]);
+        assert_eq!(repeat.length(), 41);
+
+        let line = "S\t1\tACGT\tDP:f:1.00\tIR:Z:0-41:59-100,end";
+        let u = Unitig::from_segment_line(line).unwrap();
+        assert_eq!(u.inverted_repeats, vec![repeat, InvertedRepeat::HairpinEnd]);
+        assert_eq!(u.gfa_segment_line(), line);
+        assert!(Unitig::from_segment_line("S\t1\tACGT\tDP:f:1.00\tIR:Z:5-1:6-10").is_err());
+    }
 
-        let mut u = Unitig::from_kmers(123, &forward_k2, &reverse_k2);
-        u.add_kmer_to_start(&forward_k1, &reverse_k1);
-        u.add_kmer_to_end(&forward_k3, &reverse_k3);
+    #[test]
+    fn test_from_kmers() {
+        let k_size = 5; let half_k = k_size / 2;
+        let seq = Sequence::new_with_seq(1, "ACGCATAGCACTAGCTACGA".to_string(),
+                                         "assembly.fasta".to_string(), "contig_1".to_string(), 20, half_k);
+        let mut kmer_graph = KmerGraph::new(k_size, 1);
+        kmer_graph.add_sequence(&seq, 1);
+        let forward_k1 = kmer_graph.get(b"GCATA").unwrap();
+        let forward_k2 = kmer_graph.get(b"CATAG").unwrap();
+        let forward_k3 = kmer_graph.get(b"ATAGC").unwrap();
+        let reverse_k3 = kmer_graph.reverse(&forward_k3);
+
+        let mut u = Unitig::from_kmers(123, &forward_k2);
+        u.add_kmer_to_start(&forward_k1);
+        u.add_kmer_to_end(&forward_k3);
+        u.set_kmer_positions(&forward_k1, &reverse_k3);
         u.simplify_seqs();
 
         assert_eq!(u.length(), 7_u32);
         assert_eq!(std::str::from_utf8(&u.forward_seq).unwrap(), "GCATAGC");
         assert_eq!(std::str::from_utf8(&u.reverse_seq).unwrap(), "GCTATGC");
+        assert_eq!(u.depth, 1.0);
+        assert_eq!(u.forward_positions, vec![Position::new(1, strand::FORWARD, 4)]);
+        assert_eq!(u.reverse_positions, vec![Position::new(1, strand::REVERSE, 13)]);
 
         u.trim_overlaps(k_size as usize);
         assert_eq!(u.length(), 3_u32);
@@ -407,22 +695,22 @@ mod tests {
 
     #[test]
     fn test_get_seq() {
-        let unitig_a = Rc::new(RefCell::new(Unitig::from_segment_line("S\t1\tGCTGAAGGGC\tDP:f:1")));
-        let unitig_b = Rc::new(RefCell::new(Unitig::from_segment_line("S\t2\tCGCGTTCGAC\tDP:f:1")));
-        unitig_a.borrow_mut().forward_next.push(UnitigStrand::new(&unitig_b, strand::FORWARD));
-        unitig_b.borrow_mut().forward_prev.push(UnitigStrand::new(&unitig_a, strand::FORWARD));
-        unitig_b.borrow_mut().reverse_next.push(UnitigStrand::new(&unitig_a, strand::REVERSE));
-        unitig_a.borrow_mut().reverse_prev.push(UnitigStrand::new(&unitig_b, strand::REVERSE));
+        let mut unitig_a = Unitig::from_segment_line("S\t1\tGCTGAAGGGC\tDP:f:1").unwrap();
+        let mut unitig_b = Unitig::from_segment_line("S\t2\tCGCGTTCGAC\tDP:f:1").unwrap();
+        unitig_a.forward_next.push(UnitigStrand::new(1, strand::FORWARD));
+        unitig_b.forward_prev.push(UnitigStrand::new(0, strand::FORWARD));
+        unitig_b.reverse_next.push(UnitigStrand::new(0, strand::REVERSE));
+        unitig_a.reverse_prev.push(UnitigStrand::new(1, strand::REVERSE));
 
-        assert_eq!(std::str::from_utf8(&unitig_a.borrow().get_seq(strand::FORWARD)).unwrap(), "GCTGAAGGGC");
-        assert_eq!(std::str::from_utf8(&unitig_a.borrow().get_seq(strand::REVERSE)).unwrap(), "GCCCTTCAGC");
-        assert_eq!(std::str::from_utf8(&unitig_b.borrow().get_seq(strand::FORWARD)).unwrap(), "CGCGTTCGAC");
-        assert_eq!(std::str::from_utf8(&unitig_b.borrow().get_seq(strand::REVERSE)).unwrap(), "GTCGAACGCG");
+        assert_eq!(std::str::from_utf8(&unitig_a.get_seq(strand::FORWARD)).unwrap(), "GCTGAAGGGC");
+        assert_eq!(std::str::from_utf8(&unitig_a.get_seq(strand::REVERSE)).unwrap(), "GCCCTTCAGC");
+        assert_eq!(std::str::from_utf8(&unitig_b.get_seq(strand::FORWARD)).unwrap(), "CGCGTTCGAC");
+        assert_eq!(std::str::from_utf8(&unitig_b.get_seq(strand::REVERSE)).unwrap(), "GTCGAACGCG");
     }
 
     #[test]
     fn test_remove_seq_from_start() {
-        let mut u = Unitig::from_segment_line("S\t1\tGCTGAAGGGC\tDP:f:1");
+        let mut u = Unitig::from_segment_line("S\t1\tGCTGAAGGGC\tDP:f:1").unwrap();
         u.forward_positions.push(Position::new(1, strand::FORWARD, 100));
         u.reverse_positions.push(Position::new(2, strand::REVERSE, 890));
         u.forward_positions.push(Position::new(2, strand::REVERSE, 200));
@@ -447,7 +735,7 @@ mod tests {
 
     #[test]
     fn test_remove_seq_from_end() {
-        let mut u = Unitig::from_segment_line("S\t1\tGCTGAAGGGC\tDP:f:1");
+        let mut u = Unitig::from_segment_line("S\t1\tGCTGAAGGGC\tDP:f:1").unwrap();
         u.forward_positions.push(Position::new(1, strand::FORWARD, 100));
         u.reverse_positions.push(Position::new(2, strand::REVERSE, 890));
         u.forward_positions.push(Position::new(2, strand::REVERSE, 200));
@@ -472,7 +760,7 @@ mod tests {
 
     #[test]
     fn test_add_seq_to_start() {
-        let mut u = Unitig::from_segment_line("S\t1\tGCTGAAGGGC\tDP:f:1");
+        let mut u = Unitig::from_segment_line("S\t1\tGCTGAAGGGC\tDP:f:1").unwrap();
         u.forward_positions.push(Position::new(1, strand::FORWARD, 100));
         u.reverse_positions.push(Position::new(2, strand::REVERSE, 890));
         u.forward_positions.push(Position::new(2, strand::REVERSE, 200));
@@ -497,7 +785,7 @@ mod tests {
 
     #[test]
     fn test_add_seq_to_end() {
-        let mut u = Unitig::from_segment_line("S\t1\tGCTGAAGGGC\tDP:f:1");
+        let mut u = Unitig::from_segment_line("S\t1\tGCTGAAGGGC\tDP:f:1").unwrap();
         u.forward_positions.push(Position::new(1, strand::FORWARD, 100));
         u.reverse_positions.push(Position::new(2, strand::REVERSE, 890));
         u.forward_positions.push(Position::new(2, strand::REVERSE, 200));
@@ -11,25 +11,56 @@
 // Public License for more details. You should have received a copy of the GNU General Public
 // License along with Autocycler. If not, see <http://www.gnu.org/licenses/>.
 
-use std::cell::RefCell;
-use std::collections::{HashMap, HashSet};
-use std::fs::File;
+use fxhash::{FxHashMap, FxHashSet};
+use std::cmp::Reverse;
+use std::collections::{BinaryHeap, HashMap, HashSet, VecDeque};
+use std::fmt;
 use std::io::{self, Write};
+use std::ops::Range;
 use std::path::Path;
-use std::rc::Rc;
 
+use crate::graph_simplification;
 use crate::kmer_graph::KmerGraph;
+use crate::minimizer_graph::MinimizerGraph;
 use crate::position::Position;
-use crate::sequence::Sequence;
-use crate::unitig::{Unitig, UnitigStrand};
-use crate::misc::{quit_with_error, strand, load_file_lines};
+use crate::sequence::{Duplicate, Repair, Sequence};
+use crate::unitig::{InvertedRepeat, Unitig, UnitigStrand};
+use crate::misc::{quit_with_error, reverse_complement, sign_at_end, strand, load_file_lines,
+                  up_to_first_space, after_first_space, median_usize, output_writer,
+                  write_fasta_seq};
+
+
+// Maps (sequence ID, position) to (unitig number, unitig strand) for forward-strand sequence
+// positions, for fast sequence path lookup.
+type PositionIndex = HashMap<(u16, u32), (u32, bool)>;
 
 
 #[derive(Default)]
 pub struct UnitigGraph {
-    pub unitigs: Vec<Rc<RefCell<Unitig>>>,
+    // The graph owns its unitigs, and each unitig's index in this Vec is its handle: links (see
+    // UnitigStrand) refer to unitigs by handle, and unitig_index maps unitig numbers to handles.
+    // Handles only change when unitigs are removed or reordered, which goes through
+    // rearrange_unitigs so the links stay valid.
+    pub unitigs: Vec<Unitig>,
     pub k_size: u32,
-    pub unitig_index: HashMap<u32, Rc<RefCell<Unitig>>>,
+    pub unitig_index: HashMap<u32, usize>,
+
+    // Depth weights for sequences whose weight isn't 1 (from per-assembly weights or collapsed
+    // duplicates). These are saved with the sequences' paths, so they still apply when the graph
+    // is reloaded from GFA.
+    pub seq_weights: HashMap<u16, f64>,
+
+    // Collapsed duplicates for sequences which have any, kept like the weights so sequences
+    // rebuilt from new paths (e.g. when trimming) don't lose them.
+    pub seq_duplicates: HashMap<u16, Vec<Duplicate>>,
+
+    // Stretches of sequences which aren't in the graph (see from_filtered_kmer_graph), sorted by
+    // position. A sequence's path skips over its repairs, so they are needed to reconstruct it.
+    pub seq_repairs: HashMap<u16, Vec<Repair>>,
+
+    // The number of input assemblies in the graph's cluster, set by resolve (which saves its
+    // graphs without sequence paths) so later steps can turn support fractions into counts.
+    pub assembly_count: Option<u32>,
 }
 
 impl UnitigGraph {
@@ -43,111 +74,439 @@ impl UnitigGraph {
         u_graph.create_links();
         u_graph.trim_overlaps();
         u_graph.renumber_unitigs();
-        u_graph.check_links();
+        u_graph.check_links().unwrap();
+        u_graph
+    }
+
+    pub fn from_filtered_kmer_graph(k_graph: &KmerGraph, sequences: &[Sequence]) -> Self {
+        // Builds a unitig graph from a k-mer graph which had rare k-mers removed, so some input
+        // sequences can no longer be threaded through its unitigs. Positions are therefore added
+        // by tiling each sequence with unitigs, and any stretch of removed k-mers is kept out of
+        // the graph as one of the sequence's repairs (see seq_repairs).
+        let mut u_graph = UnitigGraph {
+            k_size: k_graph.k_size,
+            ..Default::default()
+        };
+        u_graph.build_unitigs_from_kmer_graph(k_graph);
+        u_graph.simplify_seqs();
+        let paths = u_graph.tile_sequences(sequences);
+        u_graph.create_links();
+        u_graph.trim_overlaps();
+        u_graph.build_unitig_index();
+        for (seq, forward_path) in sequences.iter().zip(paths) {
+            let reverse_path = reverse_path(&forward_path);
+            let length = seq.length as u32;
+            u_graph.add_positions_from_path(&forward_path, strand::FORWARD, seq.id, length);
+            u_graph.add_positions_from_path(&reverse_path, strand::REVERSE, seq.id, length);
+        }
+        u_graph.recalculate_depths();
+        u_graph.renumber_unitigs();
+        u_graph.check_links().unwrap();
+        u_graph
+    }
+
+    fn tile_sequences(&mut self, sequences: &[Sequence]) -> Vec<Vec<(u32, bool)>> {
+        // Finds a path for each sequence through the (untrimmed) unitigs, which overlap by k-1
+        // bases. Each of the sequence's k-mers is looked up in the unitigs, and stretches of
+        // removed k-mers are skipped by the path and saved as the sequence's repairs. The exception
+        // is a sequence with none of its k-mers left, whose removed k-mers become new tiles. Tile
+        // k-mers are looked up like any other, so a removed k-mer shared with a later sequence is
+        // only added once. Unitigs (including tiles) are then split wherever a sequence enters or
+        // leaves partway through, so each sequence is a path of whole unitigs and no k-mer is in
+        // more than one unitig.
+        let k_size = self.k_size as usize;
+        let unitig_seqs: Vec<Vec<u8>> = self.unitigs.drain(..).map(|u| u.forward_seq).collect();
+        let unitig_count = unitig_seqs.len();
+        let reverse_seqs: Vec<Vec<u8>> = unitig_seqs.iter()
+            .map(|s| reverse_complement(s)).collect();
+        let mut index: FxHashMap<&[u8], TileKmer> = FxHashMap::default();
+        for (u, (forward, reverse)) in unitig_seqs.iter().zip(&reverse_seqs).enumerate() {
+            let kmer_count = forward.len() - k_size + 1;
+            for i in 0..kmer_count {
+                let kmer = TileKmer { tile: u, index: i, reverse: false };
+                index.insert(&forward[i..i + k_size], kmer);
+                let j = kmer_count - 1 - i;
+                index.entry(&reverse[j..j + k_size]).or_insert(TileKmer { reverse: true, ..kmer });
+            }
+        }
+
+        // Each extra tile is a stretch of one sequence: (sequence index, first k-mer, k-mer count).
+        let mut tiles: Vec<(usize, usize, usize)> = Vec::new();
+        let mut cuts = Vec::new();
+        for (s, seq) in sequences.iter().enumerate() {
+            let seq_len = seq.forward_seq.len();
+            let add_tiles = (0..seq.length)
+                .all(|p| !index.contains_key(&seq.forward_seq[p..p + k_size]));
+            let mut prev: Option<TileKmer> = None;
+            let mut open_tile: Option<usize> = None;
+            let mut repairs: Vec<Repair> = Vec::new();
+            for p in 0..seq.length {
+                let forward = &seq.forward_seq[p..p + k_size];
+                let kmer = if let Some(&kmer) = index.get(forward) {
+                    open_tile = None;
+                    kmer
+                } else if add_tiles {
+                    let t = match open_tile {
+                        Some(t) => { tiles[t].2 += 1; t }
+                        None => { tiles.push((s, p, 1)); tiles.len() - 1 }
+                    };
+                    open_tile = Some(t);
+                    let kmer = TileKmer { tile: unitig_count + t, index: tiles[t].2 - 1,
+                                          reverse: false };
+                    index.insert(forward, kmer);
+                    let reverse = &seq.reverse_seq[seq_len - p - k_size..seq_len - p];
+                    index.entry(reverse).or_insert(TileKmer { reverse: true, ..kmer });
+                    kmer
+                } else {
+                    // A repair gets the middle base of each removed k-mer, i.e. the base which
+                    // the k-mer would have given the sequence's path after overlap trimming.
+                    if let Some(prev) = prev.take() { cuts.push(prev.exit_cut()); }
+                    let base = seq.forward_seq[p + k_size / 2];
+                    match repairs.last_mut() {
+                        Some(r) if r.pos as usize + r.seq.len() == p => r.seq.push(base),
+                        _ => repairs.push(Repair { pos: p as u32, seq: vec![base] }),
+                    }
+                    continue;
+                };
+                match prev {
+                    Some(prev) if prev.followed_by(&kmer) => {}
+                    Some(prev) => { cuts.push(prev.exit_cut()); cuts.push(kmer.entry_cut()); }
+                    None => { cuts.push(kmer.entry_cut()); }
+                }
+                prev = Some(kmer);
+            }
+            if let Some(prev) = prev {
+                cuts.push(prev.exit_cut());
+            }
+            if !repairs.is_empty() {
+                self.seq_repairs.insert(seq.id, repairs);
+            }
+        }
+
+        // Split each unitig into pieces at its cuts (given as the k-mer index where each piece
+        // starts), and number the pieces in order.
+        let tile_seq = |t: usize| -> &[u8] {
+            if t < unitig_count { return &unitig_seqs[t]; }
+            let (s, start, kmer_count) = tiles[t - unitig_count];
+            &sequences[s].forward_seq[start..start + kmer_count + k_size - 1]
+        };
+        let tile_count = unitig_count + tiles.len();
+        let mut piece_starts: Vec<Vec<usize>> = vec![vec![0]; tile_count];
+        for (t, i) in cuts {
+            if i > 0 && i < tile_seq(t).len() - k_size + 1 {
+                piece_starts[t].push(i);
+            }
+        }
+        let mut first_piece = Vec::with_capacity(tile_count);
+        for (t, starts) in piece_starts.iter_mut().enumerate() {
+            starts.sort_unstable();
+            starts.dedup();
+            first_piece.push(self.unitigs.len());
+            let seq = tile_seq(t);
+            let ends = starts.iter().skip(1).copied().chain([seq.len() - k_size + 1]);
+            for (&start, end) in starts.iter().zip(ends) {
+                let forward_seq = seq[start..end + k_size - 1].to_vec();
+                let reverse_seq = reverse_complement(&forward_seq);
+                self.unitigs.push(Unitig {
+                    number: (self.unitigs.len() + 1) as u32,
+                    forward_seq,
+                    reverse_seq,
+                    ..Default::default()
+                });
+            }
+        }
+
+        // Each sequence's path gets a piece whenever the sequence reaches the k-mer it enters that
+        // piece on, which is the piece's first k-mer on the strand being followed. A repair's
+        // k-mers are skipped, as a later sequence may have added them in a tile.
+        sequences.iter().map(|seq| {
+            let mut repaired = vec![false; seq.length];
+            for r in self.seq_repairs.get(&seq.id).into_iter().flatten() {
+                repaired[r.pos as usize..r.pos as usize + r.seq.len()].fill(true);
+            }
+            (0..seq.length).filter(|&p| !repaired[p]).filter_map(|p| {
+                let kmer = *index.get(&seq.forward_seq[p..p + k_size])?;
+                let starts = &piece_starts[kmer.tile];
+                let piece = starts.partition_point(|&start| start <= kmer.index) - 1;
+                let entry = if kmer.reverse {
+                    starts.get(piece + 1).map_or(tile_seq(kmer.tile).len() - k_size, |&e| e - 1)
+                } else {
+                    starts[piece]
+                };
+                let strand = if kmer.reverse { strand::REVERSE } else { strand::FORWARD };
+                (kmer.index == entry)
+                    .then_some(((first_piece[kmer.tile] + piece + 1) as u32, strand))
+            }).collect()
+        }).collect()
+    }
+
+    pub fn from_minimizer_graph(m_graph: &MinimizerGraph) -> Self {
+        // Minimizer graph segments overlap by k-1 bases, so once they are compacted into unitigs,
+        // links and overlap trimming work the same as for a k-mer graph. Sequence positions are
+        // then added by threading each sequence's segment path through the unitigs.
+        let mut u_graph = UnitigGraph {
+            k_size: m_graph.k_size,
+            ..Default::default()
+        };
+        let segment_unitigs = u_graph.build_unitigs_from_minimizer_graph(m_graph);
+        u_graph.create_links();
+        u_graph.trim_overlaps();
+        u_graph.build_unitig_index();
+        for (seq_id, length, segment_path) in &m_graph.paths {
+            let forward_path: Vec<(u32, bool)> = segment_path.iter()
+                .filter_map(|&s| segment_unitigs[s]).collect();
+            let reverse_path = reverse_path(&forward_path);
+            u_graph.add_positions_from_path(&forward_path, strand::FORWARD, *seq_id, *length);
+            u_graph.add_positions_from_path(&reverse_path, strand::REVERSE, *seq_id, *length);
+        }
+        u_graph.recalculate_depths();
+        u_graph.renumber_unitigs();
+        u_graph.check_links().unwrap();
         u_graph
     }
 
     pub fn from_gfa_file(gfa_filename: &Path) -> (Self, Vec<Sequence>) {
         let gfa_lines = load_file_lines(gfa_filename);
-        Self::from_gfa_lines(&gfa_lines)
+        let (u_graph, sequences, _) = Self::load_gfa_lines(&gfa_lines, false)
+            .unwrap_or_else(|e| {
+                quit_with_error(&format!("malformed GFA file {}\n{}", gfa_filename.display(), e))
+            });
+        u_graph.check_loaded_links();
+        (u_graph, sequences)
     }
 
     pub fn from_gfa_lines(gfa_lines: &Vec<String>) -> (Self, Vec<Sequence>) {
+        let (u_graph, sequences, _) = Self::load_gfa_lines(gfa_lines, false)
+            .unwrap_or_else(|e| quit_with_error(&format!("malformed GFA\n{}", e)));
+        u_graph.check_loaded_links();
+        (u_graph, sequences)
+    }
+
+    pub fn from_gfa_file_skipping_bad_paths(gfa_filename: &Path)
+            -> (Self, Vec<Sequence>, Vec<String>) {
+        // Same as from_gfa_file, but paths which can't be followed through the graph (e.g. after
+        // the graph was manually edited) are skipped instead of causing an error. Each skipped
+        // path is described in the returned problems.
+        let gfa_lines = load_file_lines(gfa_filename);
+        let (u_graph, sequences, problems) = Self::load_gfa_lines(&gfa_lines, true)
+            .unwrap_or_else(|e| {
+                quit_with_error(&format!("malformed GFA file {}\n{}", gfa_filename.display(), e))
+            });
+        u_graph.check_loaded_links();
+        (u_graph, sequences, problems)
+    }
+
+    pub fn load_gfa_lines(gfa_lines: &Vec<String>, skip_bad_paths: bool)
+            -> Result<(Self, Vec<Sequence>, Vec<String>), String> {
+        // Builds a graph from GFA lines, returning an error (with its line number) for anything
+        // which prevents that. The links aren't checked for consistency here, so callers should
+        // use check_links on the loaded graph.
         let mut u_graph = UnitigGraph::default();
-        let mut link_lines: Vec<&str> = Vec::new();
-        let mut path_lines: Vec<&str> = Vec::new();
-        for line in gfa_lines {
+        let mut link_lines: Vec<(usize, &str)> = Vec::new();
+        let mut path_lines: Vec<(usize, &str)> = Vec::new();
+        let mut gfa_version = 1;
+        for (line_num, line) in (1..).zip(gfa_lines) {
             let parts: Vec<&str> = line.trim_end_matches('\n').split('\t').collect();
+            let line_error = |e: String| format!("line {}: {}", line_num, e);
             match parts.first() {
-                Some(&"H") => u_graph.read_gfa_header_line(&parts),
-                Some(&"S") => u_graph.unitigs.push(Rc::new(RefCell::new(Unitig::from_segment_line(line)))),
-                Some(&"L") => link_lines.push(line),
-                Some(&"P") => path_lines.push(line),
+                Some(&"H") => gfa_version = u_graph.read_gfa_header_line(&parts)
+                                                   .map_err(line_error)?,
+                Some(&"S") => {
+                    let unitig = if gfa_version == 2 { Unitig::from_gfa2_segment_line(line) }
+                                                else { Unitig::from_segment_line(line) }
+                        .map_err(line_error)?;
+                    if u_graph.unitig_index.insert(unitig.number, u_graph.unitigs.len()).is_some() {
+                        return Err(line_error(format!("segment {} is defined more than once",
+                                                      unitig.number)));
+                    }
+                    u_graph.unitigs.push(unitig);
+                }
+                Some(&"L") | Some(&"E") => link_lines.push((line_num, line)),
+                Some(&"P") | Some(&"O") => path_lines.push((line_num, line)),
                 _ => {}
             }
         }
-        u_graph.build_unitig_index();
-        u_graph.build_links_from_gfa(&link_lines);
-        let sequences = u_graph.build_paths_from_gfa(&path_lines);
-        u_graph.check_links();
-        (u_graph, sequences)
+        u_graph.build_links_from_gfa(&link_lines)?;
+        let (sequences, problems) = u_graph.build_paths_from_gfa(&path_lines, skip_bad_paths)?;
+        Ok((u_graph, sequences, problems))
+    }
+
+    fn check_loaded_links(&self) {
+        if let Err(e) = self.check_links() {
+            quit_with_error(&format!("inconsistent links in GFA: {}", e));
+        }
     }
 
     pub fn build_unitig_index(&mut self) {
-        self.unitig_index = self.unitigs.iter().map(|u| {(u.borrow().number, Rc::clone(u))}).collect();
+        self.unitig_index = self.unitigs.iter().enumerate().map(|(h, u)| (u.number, h)).collect();
+    }
+
+    pub fn unitig(&self, number: u32) -> &Unitig {
+        // Looks up a unitig by its number, which must be in the graph.
+        &self.unitigs[self.unitig_index[&number]]
+    }
+
+    pub fn unitig_mut(&mut self, number: u32) -> &mut Unitig {
+        &mut self.unitigs[self.unitig_index[&number]]
+    }
+
+    pub fn number(&self, unitig_strand: &UnitigStrand) -> u32 {
+        self.unitigs[unitig_strand.unitig].number
+    }
+
+    pub fn signed_number(&self, unitig_strand: &UnitigStrand) -> i32 {
+        let number = self.number(unitig_strand) as i32;
+        if unitig_strand.strand { number } else { -number }
+    }
+
+    pub fn add_unitig(&mut self, unitig: Unitig) {
+        // Adds the unitig (without links) to the end of the graph, so existing handles are not
+        // affected.
+        self.unitig_index.insert(unitig.number, self.unitigs.len());
+        self.unitigs.push(unitig);
+    }
+
+    fn rearrange_unitigs(&mut self, order: &[usize]) {
+        // Rebuilds the unitig Vec from the unitigs at the given handles (in the given order), so
+        // any unitigs not in the order are removed. Links are remapped to the new handles, and
+        // links to removed unitigs are deleted.
+        let mut new_handles = vec![None; self.unitigs.len()];
+        for (new_handle, &old_handle) in order.iter().enumerate() {
+            new_handles[old_handle] = Some(new_handle);
+        }
+        let mut old_unitigs: Vec<Option<Unitig>> = self.unitigs.drain(..).map(Some).collect();
+        self.unitigs = order.iter().map(|&h| old_unitigs[h].take().unwrap()).collect();
+        for unitig in &mut self.unitigs {
+            for links in [&mut unitig.forward_next, &mut unitig.forward_prev,
+                          &mut unitig.reverse_next, &mut unitig.reverse_prev] {
+                links.retain_mut(|link| match new_handles[link.unitig] {
+                    Some(new_handle) => { link.unitig = new_handle; true }
+                    None => false,
+                });
+            }
+        }
+        self.build_unitig_index();
+    }
+
+    pub fn retain_unitigs<F: FnMut(&Unitig) -> bool>(&mut self, mut keep: F) {
+        // Removes the unitigs for which keep returns false, along with any links to them. This
+        // should be used instead of modifying the unitig Vec directly, as it keeps links valid.
+        let order: Vec<usize> = (0..self.unitigs.len()).filter(|&h| keep(&self.unitigs[h]))
+                                                       .collect();
+        self.rearrange_unitigs(&order);
     }
 
-    fn read_gfa_header_line(&mut self, parts: &Vec<&str>) {
+    fn read_gfa_header_line(&mut self, parts: &Vec<&str>) -> Result<u32, String> {
+        // This method reads the k-mer size (and assembly count, if any) from the header line and
+        // returns the GFA version (GFA1 unless the header says otherwise).
+        let gfa_version = if parts.contains(&"VN:Z:2.0") { 2 } else { 1 };
+        self.assembly_count = parts.iter().find_map(|p| p.strip_prefix("AC:i:"))
+            .and_then(|v| v.parse::<u32>().ok());
         for &p in parts {
             if let Some(tag_val) = p.strip_prefix("KM:i:") {
                 if let Ok(k) = tag_val.parse::<u32>() {
                     self.k_size = k;
-                    return;
+                    return Ok(gfa_version);
                 }
             }
         }
-        quit_with_error("could not find a valid k-mer tag (e.g. KM:i:51) in the GFA header line.\n\
-                         Are you sure this is an Autocycler-generated GFA file?");
-    }
-
-    fn build_links_from_gfa(&mut self, link_lines: &[&str]) {
-        for line in link_lines {
-            let parts: Vec<&str> = line.split('\t').collect();
-            if parts.len() < 6 || parts[5] != "0M" {
-                quit_with_error("non-zero overlap found on the GFA link line.\n\
-                                 Are you sure this is an Autocycler-generated GFA file?");
-            }
-            let seg_1: u32 = parts[1].parse().expect("Error parsing segment 1 as integer");
-            let seg_2: u32 = parts[3].parse().expect("Error parsing segment 2 as integer");
-            let strand_1 = parts[2] == "+";
-            let strand_2 = parts[4] == "+";
-            if let Some(unitig_1) = self.unitig_index.get(&seg_1) {
-                if let Some(unitig_2) = self.unitig_index.get(&seg_2) {
-                    if strand_1 {unitig_1.borrow_mut().forward_next.push(UnitigStrand::new(unitig_2, strand_2));
-                         } else {unitig_1.borrow_mut().reverse_next.push(UnitigStrand::new(unitig_2, strand_2));}
-                    if strand_2 {unitig_2.borrow_mut().forward_prev.push(UnitigStrand::new(unitig_1, strand_1));
-                         } else {unitig_2.borrow_mut().reverse_prev.push(UnitigStrand::new(unitig_1, strand_1));}
-                } else {
-                    quit_with_error(&format!("link refers to nonexistent unitig: {}", seg_2));
+        Err("could not find a valid k-mer tag (e.g. KM:i:51) in the GFA header line.\n\
+             Are you sure this is an Autocycler-generated GFA file?".to_string())
+    }
+
+    fn build_links_from_gfa(&mut self, link_lines: &[(usize, &str)]) -> Result<(), String> {
+        // GFA1 has a link line for each direction of a link, but GFA2 has one edge line per link,
+        // so each edge also adds its reverse-complement link (unless the edge is its own reverse
+        // complement).
+        for &(line_num, line) in link_lines {
+            let line_error = |e: String| format!("line {}: {}", line_num, e);
+            let mut links = Vec::new();
+            if line.starts_with('E') {
+                let (seg_1, strand_1, seg_2, strand_2) = parse_gfa2_edge_line(line)
+                    .map_err(line_error)?;
+                links.push((seg_1, strand_1, seg_2, strand_2));
+                if (seg_1, strand_1) != (seg_2, !strand_2) {
+                    links.push((seg_2, !strand_2, seg_1, !strand_1));
                 }
             } else {
-                quit_with_error(&format!("link refers to nonexistent unitig: {}", seg_1));
+                links.push(parse_gfa1_link_line(line).map_err(line_error)?);
+            }
+            for (seg_1, strand_1, seg_2, strand_2) in links {
+                let unitig = |seg: u32| self.unitig_index.get(&seg).copied().ok_or_else(|| {
+                    line_error(format!("link refers to nonexistent unitig: {}", seg))
+                });
+                let (unitig_1, unitig_2) = (unitig(seg_1)?, unitig(seg_2)?);
+                self.add_link(UnitigStrand::new(unitig_1, strand_1),
+                              UnitigStrand::new(unitig_2, strand_2));
             }
         }
+        Ok(())
     }
 
-    fn build_paths_from_gfa(&mut self, path_lines: &[&str]) -> Vec<Sequence> {
+    fn build_paths_from_gfa(&mut self, path_lines: &[(usize, &str)], skip_bad_paths: bool)
+            -> Result<(Vec<Sequence>, Vec<String>), String> {
+        // Builds sequences from the GFA's path lines (given with their line numbers). A path
+        // which can't be parsed or followed through the graph is an error, unless skip_bad_paths
+        // is set, in which case it is described in the returned problems instead.
         let mut sequences = Vec::new();
-        for line in path_lines {
-            let parts: Vec<&str> = line.split('\t').collect();
-            let seq_id: u16 = parts[1].parse().expect("Error parsing sequence ID as integer");
-            let mut length = None;
-            let mut filename = None;
-            let mut header = None;
-            let mut cluster = 0;
-            for p in &parts[2..] {
-                if let Some(tag_val) = p.strip_prefix("LN:i:") {
-                    length = Some(tag_val.parse::<u32>().expect("Error parsing length"));
-                } else if let Some(tag_val) = p.strip_prefix("FN:Z:") {
-                    filename = Some(tag_val.to_string());
-                } else if let Some(tag_val) = p.strip_prefix("HD:Z:") {
-                    header = Some(tag_val.to_string());
-                } else if let Some(tag_val) = p.strip_prefix("CL:i:") {
-                    cluster = tag_val.parse::<u16>().expect("Error parsing cluster");
+        let mut problems = Vec::new();
+        let mut seen_ids = HashSet::new();
+        for &(line_num, line) in path_lines {
+            let p = parse_path_line(line).and_then(|p| {
+                let check = if seen_ids.insert(p.seq_id) {
+                    self.check_path(&p.path, &p.repairs, p.length)
+                }
+                    else { Err(format!("sequence ID {} is used by more than one path", p.seq_id)) };
+                check.map_err(|e| format!("{} {} (sequence {}): {}", p.filename,
+                                          up_to_first_space(&p.header), p.seq_id, e))?;
+                Ok(p)
+            });
+            let p = match p {
+                Ok(p) => p,
+                Err(e) if skip_bad_paths => {
+                    problems.push(format!("line {}: {}", line_num, e));
+                    continue;
                 }
+                Err(e) => return Err(format!("line {}: {}", line_num, e)),
+            };
+            if p.weight != 1.0 {
+                self.seq_weights.insert(p.seq_id, p.weight);
             }
-            if length.is_none() || filename.is_none() || header.is_none() {
-                quit_with_error("missing required tag in GFA path line.");
+            if !p.duplicates.is_empty() {
+                self.seq_duplicates.insert(p.seq_id, p.duplicates);
             }
-            let length = length.unwrap();
-            let filename = filename.unwrap();
-            let header = header.unwrap();
-            let path = parse_unitig_path(parts[2]);
-            let sequence = self.create_sequence_and_positions(seq_id, length, filename, header,
-                                                              cluster, path);
+            if !p.repairs.is_empty() {
+                self.seq_repairs.insert(p.seq_id, p.repairs);
+            }
+            let mut sequence = self.create_sequence_and_positions(p.seq_id, p.length, p.filename,
+                                                                  p.header, p.cluster, p.path);
+            sequence.checksum = p.checksum;
+            sequence.line_width = p.line_width;
+            sequence.original_name = p.original_name;
             sequences.push(sequence);
         }
-        sequences
+        Ok((sequences, problems))
+    }
+
+    fn check_path(&self, path: &[(u32, bool)], repairs: &[Repair], length: u32)
+            -> Result<(), String> {
+        // Checks that a path (with its repairs) can be followed through the graph to give a
+        // sequence of the expected length.
+        if path.is_empty() {
+            return Err("path is empty".to_string());
+        }
+        let mut path_length: u32 = repairs.iter().map(|r| r.seq.len() as u32).sum();
+        for (unitig_num, _) in path {
+            match self.unitig_index.get(unitig_num) {
+                Some(&unitig) => path_length += self.unitigs[unitig].length(),
+                None => return Err(format!("unitig {} is not in the graph", unitig_num)),
+            }
+        }
+        if path_length != length {
+            return Err(format!("path length ({} bp) does not match sequence length ({} bp)",
+                               path_length, length));
+        }
+        Ok(())
     }
 
     pub fn create_sequence_and_positions(&mut self, seq_id: u16, length: u32,
@@ -156,14 +515,28 @@ impl UnitigGraph {
         let reverse_path = reverse_path(&forward_path);
         self.add_positions_from_path(&forward_path, strand::FORWARD, seq_id, length);
         self.add_positions_from_path(&reverse_path, strand::REVERSE, seq_id, length);
-        Sequence::new_without_seq(seq_id, filename, header, length as usize, cluster)
+        let mut sequence = Sequence::new_without_seq(seq_id, filename, header, length as usize,
+                                                     cluster);
+        if let Some(&weight) = self.seq_weights.get(&seq_id) {
+            sequence.weight = weight;
+        }
+        if let Some(duplicates) = self.seq_duplicates.get(&seq_id) {
+            sequence.duplicates = duplicates.clone();
+        }
+        sequence
     }
 
-    fn add_positions_from_path(&mut self, path: &[(u32, bool)], path_strand: bool, seq_id: u16, length: u32) {
+    fn add_positions_from_path(&mut self, path: &[(u32, bool)], path_strand: bool, seq_id: u16,
+                               length: u32) {
+        // Positions skip over the sequence's repairs, which aren't in the path.
+        let mut gaps = self.repair_gaps(seq_id, length, path_strand).into_iter().peekable();
         let mut pos = 0;
         for (unitig_num, unitig_strand) in path {
-            if let Some(unitig) = self.unitig_index.get(unitig_num) {
-                let mut u = unitig.borrow_mut();
+            while let Some((_, gap_len)) = gaps.next_if(|&(gap_pos, _)| gap_pos == pos) {
+                pos += gap_len;
+            }
+            if let Some(&unitig) = self.unitig_index.get(unitig_num) {
+                let u = &mut self.unitigs[unitig];
                 let positions = if *unitig_strand {&mut u.forward_positions} 
                                              else {&mut u.reverse_positions};
                 positions.push(Position::new(seq_id, path_strand, pos as usize));
@@ -172,38 +545,108 @@ impl UnitigGraph {
                 quit_with_error(&format!("unitig {} not found in unitig index", unitig_num));
             }
         }
+        while let Some((_, gap_len)) = gaps.next_if(|&(gap_pos, _)| gap_pos == pos) {
+            pos += gap_len;
+        }
         assert!(pos == length, "Position calculation mismatch");
     }
 
+    fn repair_gaps(&self, seq_id: u16, length: u32, path_strand: bool) -> Vec<(u32, u32)> {
+        // Returns the sequence's repairs as (position, length) on the given strand, in order.
+        let Some(repairs) = self.seq_repairs.get(&seq_id) else { return Vec::new(); };
+        let gaps = repairs.iter().map(|r| (r.pos, r.seq.len() as u32));
+        if path_strand { gaps.collect() }
+                  else { gaps.rev().map(|(pos, len)| (length - pos - len, len)).collect() }
+    }
+
+    pub fn set_repair_positions(&mut self, seq_id: u16, gapped_path: &[i32])
+            -> (Vec<(u32, bool)>, u32) {
+        // Takes a sequence's new path with a 0 for each of its repairs (as given by
+        // get_gapped_unitig_paths_i32) and moves the repairs to where the 0s now are. Returns the
+        // path without the 0s and the sequence's new length.
+        let mut repairs = self.seq_repairs.remove(&seq_id).unwrap_or_default().into_iter();
+        let (mut moved, mut path, mut pos) = (Vec::new(), Vec::new(), 0);
+        for &n in gapped_path {
+            if n == 0 {
+                let mut repair = repairs.next().unwrap();
+                repair.pos = pos;
+                pos += repair.seq.len() as u32;
+                moved.push(repair);
+            } else {
+                path.push((n.unsigned_abs(), n > 0));
+                pos += self.unitig(n.unsigned_abs()).length();
+            }
+        }
+        if !moved.is_empty() {
+            self.seq_repairs.insert(seq_id, moved);
+        }
+        (path, pos)
+    }
+
+    pub fn get_path_positions(&self, seq: &Sequence, path: &[(u32, bool)]) -> Vec<u32> {
+        // Returns where each unitig in the sequence's path starts in the sequence, which allows for
+        // any repairs before it.
+        let mut gaps = self.repair_gaps(seq.id, seq.length as u32, strand::FORWARD)
+            .into_iter().peekable();
+        let mut pos = 0;
+        path.iter().map(|&(unitig_num, _)| {
+            while let Some((_, gap_len)) = gaps.next_if(|&(gap_pos, _)| gap_pos == pos) {
+                pos += gap_len;
+            }
+            let start = pos;
+            pos += self.unitig(unitig_num).length();
+            start
+        }).collect()
+    }
+
+    pub fn get_piece_repairs(&self, seq: &Sequence, path: &[(u32, bool)], piece: Range<usize>)
+            -> (Vec<Repair>, u32) {
+        // For a piece of a sequence's path (path[piece]), this returns the repairs between the
+        // piece's unitigs (shifted to its coordinates) and the piece's length. Repairs at either
+        // end of the piece are dropped along with the rest of the sequence.
+        let positions = self.get_path_positions(seq, path);
+        let start = positions[piece.start];
+        let end = positions[piece.end - 1] + self.unitig(path[piece.end - 1].0).length();
+        let repairs = self.seq_repairs.get(&seq.id).map(|r| r.as_slice()).unwrap_or_default();
+        let piece_repairs = repairs.iter().filter(|r| r.pos > start && r.pos < end)
+            .map(|r| Repair { pos: r.pos - start, seq: r.seq.clone() }).collect();
+        (piece_repairs, end - start)
+    }
+
     fn build_unitigs_from_kmer_graph(&mut self, k_graph: &KmerGraph) {
-        let mut seen: HashSet<&[u8]> = HashSet::new();
+        let mut seen: FxHashSet<usize> = FxHashSet::default();
         let mut unitig_number = 0;
+        let mut next_kmers = Vec::with_capacity(4);
+        let mut prev_kmers = Vec::with_capacity(4);
         for forward_kmer in k_graph.iterate_kmers() {
-            if seen.contains(forward_kmer.seq()) {
+            if seen.contains(&forward_kmer.id()) {
                 continue;
             }
-            let reverse_kmer = k_graph.reverse(forward_kmer);
+            let reverse_kmer = k_graph.reverse(&forward_kmer);
             unitig_number += 1;
-            let mut unitig = Unitig::from_kmers(unitig_number, forward_kmer, reverse_kmer);
-            seen.insert(forward_kmer.seq());
-            seen.insert(reverse_kmer.seq());
+            let mut unitig = Unitig::from_kmers(unitig_number, &forward_kmer);
+            seen.insert(forward_kmer.id());
+            seen.insert(reverse_kmer.id());
+            let mut first_forward_kmer = forward_kmer;
+            let mut first_reverse_kmer = reverse_kmer;
 
             // Extend unitig forward
             let mut for_k = forward_kmer;
             let mut rev_k = reverse_kmer;
             loop {
                 if rev_k.first_position() { break; }
-                let next_kmers = k_graph.next_kmers(for_k.seq());
+                k_graph.next_kmers(&for_k, &mut next_kmers);
                 if next_kmers.len() != 1 { break; }
                 for_k = next_kmers[0];
-                if seen.contains(for_k.seq()) { break; }
-                let prev_kmers = k_graph.prev_kmers(for_k.seq());
+                if seen.contains(&for_k.id()) { break; }
+                k_graph.prev_kmers(&for_k, &mut prev_kmers);
                 if prev_kmers.len() != 1 { break; }
-                rev_k = k_graph.reverse(for_k);
+                rev_k = k_graph.reverse(&for_k);
                 if for_k.first_position() { break; }
-                unitig.add_kmer_to_end(for_k, rev_k);
-                seen.insert(for_k.seq());
-                seen.insert(rev_k.seq());
+                unitig.add_kmer_to_end(&for_k);
+                first_reverse_kmer = rev_k;
+                seen.insert(for_k.id());
+                seen.insert(rev_k.id());
             }
 
             // Extend unitig backward
@@ -211,25 +654,99 @@ impl UnitigGraph {
             let mut rev_k;
             loop {
                 if for_k.first_position() { break; }
-                let prev_kmers = k_graph.prev_kmers(for_k.seq());
+                k_graph.prev_kmers(&for_k, &mut prev_kmers);
                 if prev_kmers.len() != 1 { break; }
                 for_k = prev_kmers[0];
-                if seen.contains(for_k.seq()) { break; }
-                let next_kmers = k_graph.next_kmers(for_k.seq());
+                if seen.contains(&for_k.id()) { break; }
+                k_graph.next_kmers(&for_k, &mut next_kmers);
                 if next_kmers.len() != 1 { break; }
-                rev_k = k_graph.reverse(for_k);
+                rev_k = k_graph.reverse(&for_k);
                 if rev_k.first_position() { break; }
-                unitig.add_kmer_to_start(for_k, rev_k);
-                seen.insert(for_k.seq());
-                seen.insert(rev_k.seq());
+                unitig.add_kmer_to_start(&for_k);
+                first_forward_kmer = for_k;
+                seen.insert(for_k.id());
+                seen.insert(rev_k.id());
+            }
+            unitig.set_kmer_positions(&first_forward_kmer, &first_reverse_kmer);
+            self.unitigs.push(unitig);
+        }
+    }
+
+    fn build_unitigs_from_minimizer_graph(&mut self, m_graph: &MinimizerGraph)
+            -> Vec<Option<(u32, bool)>> {
+        // Compacts non-branching chains of segments into unitigs. Like k-mer unitigs, chains stop
+        // at sequence starts/ends so every sequence path begins and ends on unitig boundaries.
+        // Palindromic segments are never merged with others, as they can be traversed in either
+        // direction. Returns the unitig strand which each segment begins (if any).
+        let m = m_graph.k_size as usize - 1;
+        let segment_count = m_graph.segment_count();
+        let mut order: Vec<usize> = (0..segment_count).collect();
+        order.sort_by_key(|&i| m_graph.segments[i]);
+        let mut seen = vec![false; segment_count];
+        let mut segment_unitigs = vec![None; segment_count];
+        let is_palindrome = |i: usize| m_graph.reverse[i] == i;
+        let mut unitig_number = 0;
+        for first in order {
+            if seen[first] {
+                continue;
+            }
+            seen[first] = true;
+            seen[m_graph.reverse[first]] = true;
+            let mut chain = VecDeque::from([first]);
+
+            // Extend chain forward
+            let mut cur = first;
+            while !is_palindrome(first) {
+                if m_graph.starts_sequence[m_graph.reverse[cur]] { break; }
+                let next = m_graph.next_segments(cur);
+                if next.len() != 1 { break; }
+                let seg = next[0];
+                if seen[seg] || is_palindrome(seg) { break; }
+                if m_graph.prev_segments(seg).len() != 1 { break; }
+                if m_graph.starts_sequence[seg] { break; }
+                chain.push_back(seg);
+                seen[seg] = true;
+                seen[m_graph.reverse[seg]] = true;
+                cur = seg;
+            }
+
+            // Extend chain backward
+            let mut cur = first;
+            while !is_palindrome(first) {
+                if m_graph.starts_sequence[cur] { break; }
+                let prev = m_graph.prev_segments(cur);
+                if prev.len() != 1 { break; }
+                let seg = prev[0];
+                if seen[seg] || is_palindrome(seg) { break; }
+                if m_graph.next_segments(seg).len() != 1 { break; }
+                if m_graph.starts_sequence[m_graph.reverse[seg]] { break; }
+                chain.push_front(seg);
+                seen[seg] = true;
+                seen[m_graph.reverse[seg]] = true;
+                cur = seg;
             }
-            self.unitigs.push(Rc::new(RefCell::new(unitig)));
+
+            unitig_number += 1;
+            segment_unitigs[chain[0]] = Some((unitig_number, strand::FORWARD));
+            segment_unitigs[m_graph.reverse[*chain.back().unwrap()]] = Some((unitig_number, strand::REVERSE));
+            let mut forward_seq = m_graph.segments[chain[0]].to_vec();
+            for &seg in chain.iter().skip(1) {
+                forward_seq.extend_from_slice(&m_graph.segments[seg][m..]);
+            }
+            let reverse_seq = reverse_complement(&forward_seq);
+            self.unitigs.push(Unitig {
+                number: unitig_number,
+                forward_seq,
+                reverse_seq,
+                ..Default::default()
+            });
         }
+        segment_unitigs
     }
 
     fn simplify_seqs(&mut self) {
-        for unitig in &self.unitigs {
-            unitig.borrow_mut().simplify_seqs();
+        for unitig in &mut self.unitigs {
+            unitig.simplify_seqs();
         }
     }
 
@@ -240,66 +757,63 @@ impl UnitigGraph {
         let mut forward_starts = HashMap::new();
         let mut reverse_starts = HashMap::new();
         for (i, unitig) in self.unitigs.iter().enumerate() {
-            let forward_key = unitig.borrow().forward_seq[..piece_len].to_vec();
-            let reverse_key = unitig.borrow().reverse_seq[..piece_len].to_vec();
+            let forward_key = unitig.forward_seq[..piece_len].to_vec();
+            let reverse_key = unitig.reverse_seq[..piece_len].to_vec();
             forward_starts.entry(forward_key).or_insert_with(Vec::new).push(i);
             reverse_starts.entry(reverse_key).or_insert_with(Vec::new).push(i);
         }
 
         // Use the indices to find connections between unitigs.
-        for i in 0..self.unitigs.len() {
-            let unitig_a = Rc::clone(&self.unitigs[i]);
-            let ending_forward_seq = unitig_a.borrow().forward_seq[unitig_a.borrow().forward_seq.len() - piece_len..].to_vec();
-            let ending_reverse_seq = unitig_a.borrow().reverse_seq[unitig_a.borrow().reverse_seq.len() - piece_len..].to_vec();
+        for a in 0..self.unitigs.len() {
+            let unitig_a = &self.unitigs[a];
+            let end = unitig_a.length() as usize - piece_len;
+            let ending_forward_seq = unitig_a.forward_seq[end..].to_vec();
+            let ending_reverse_seq = unitig_a.reverse_seq[end..].to_vec();
 
             if let Some(next_idxs) = forward_starts.get(&ending_forward_seq) {
-                for &j in next_idxs {
-                    let unitig_b = Rc::clone(&self.unitigs[j]);
-
+                for &b in next_idxs {
                     // unitig_a+ -> unitig_b+
-                    unitig_a.borrow_mut().forward_next.push(UnitigStrand::new(&unitig_b, strand::FORWARD));
-                    unitig_b.borrow_mut().forward_prev.push(UnitigStrand::new(&unitig_a, strand::FORWARD));
+                    self.add_link(UnitigStrand::new(a, strand::FORWARD),
+                                  UnitigStrand::new(b, strand::FORWARD));
 
                     // unitig_b- -> unitig_a-
-                    unitig_b.borrow_mut().reverse_next.push(UnitigStrand::new(&unitig_a, strand::REVERSE));
-                    unitig_a.borrow_mut().reverse_prev.push(UnitigStrand::new(&unitig_b, strand::REVERSE));
+                    self.add_link(UnitigStrand::new(b, strand::REVERSE),
+                                  UnitigStrand::new(a, strand::REVERSE));
                 }
             }
 
             if let Some(next_idxs) = reverse_starts.get(&ending_forward_seq) {
-                for &j in next_idxs {
-                    let unitig_b = Rc::clone(&self.unitigs[j]);
-
+                for &b in next_idxs {
                     // unitig_a+ -> unitig_b-
-                    unitig_a.borrow_mut().forward_next.push(UnitigStrand::new(&unitig_b, strand::REVERSE));
-                    unitig_b.borrow_mut().reverse_prev.push(UnitigStrand::new(&unitig_a, strand::FORWARD));
+                    self.add_link(UnitigStrand::new(a, strand::FORWARD),
+                                  UnitigStrand::new(b, strand::REVERSE));
                 }
             }
 
             if let Some(next_idxs) = forward_starts.get(&ending_reverse_seq) {
-                for &j in next_idxs {
-                    let unitig_b = Rc::clone(&self.unitigs[j]);
-
+                for &b in next_idxs {
                     // unitig_a- -> unitig_b+
-                    unitig_a.borrow_mut().reverse_next.push(UnitigStrand::new(&unitig_b, strand::FORWARD));
-                    unitig_b.borrow_mut().forward_prev.push(UnitigStrand::new(&unitig_a, strand::REVERSE));
+                    self.add_link(UnitigStrand::new(a, strand::REVERSE),
+                                  UnitigStrand::new(b, strand::FORWARD));
                 }
             }
         }
     }
 
     pub fn trim_overlaps(&mut self) {
-        for unitig in &self.unitigs {
-            unitig.borrow_mut().trim_overlaps(self.k_size as usize);
+        let k_size = self.k_size as usize;
+        for unitig in &mut self.unitigs {
+            unitig.trim_overlaps(k_size);
         }
     }
 
     pub fn renumber_unitigs(&mut self) {
         // This method sorts and renumbers Unitigs by: length (decreasing), sequence (lexicographic)
         // and depth (decreasing).
-        self.unitigs.sort_by(|a_rc, b_rc| {
-            let a = a_rc.borrow();
-            let b = b_rc.borrow();
+        let mut order: Vec<usize> = (0..self.unitigs.len()).collect();
+        order.sort_by(|&a, &b| {
+            let a = &self.unitigs[a];
+            let b = &self.unitigs[b];
             let length_cmp = a.length().cmp(&b.length()).reverse();
             if length_cmp != std::cmp::Ordering::Equal {
                 return length_cmp;
@@ -310,39 +824,160 @@ impl UnitigGraph {
             }
             a.depth.partial_cmp(&b.depth).unwrap_or(std::cmp::Ordering::Equal).reverse()
         });
-        for (new_number, unitig) in self.unitigs.iter().enumerate() {
-            unitig.borrow_mut().number = (new_number + 1) as u32;
+        self.rearrange_unitigs(&order);
+        for (new_number, unitig) in self.unitigs.iter_mut().enumerate() {
+            unitig.number = (new_number + 1) as u32;
         }
         self.build_unitig_index();
     }
 
-    pub fn save_gfa(&self, gfa_filename: &Path, sequences: &Vec<Sequence>) -> io::Result<()> {
-        let mut file = File::create(gfa_filename)?;
-        writeln!(file, "H\tVN:Z:1.0\tKM:i:{}", self.k_size)?;
+    fn gfa_header_line(&self, gfa_version: u32) -> String {
+        let assembly_count = self.assembly_count.map(|c| format!("\tAC:i:{}", c))
+                                                .unwrap_or_default();
+        format!("H\tVN:Z:{}.0\tKM:i:{}{}", gfa_version, self.k_size, assembly_count)
+    }
+
+        pub fn save_gfa(&self, gfa_filename: &Path, sequences: &[Sequence]) -> io::Result<()> {
+        let mut file = output_writer(gfa_filename)?;
+        for line in self.gfa_lines(sequences) {
+            writeln!(file, "{}", line)?;
+        }
+        file.finish()
+    }
+
+    pub fn save_gfa2(&self, gfa_filename: &Path, sequences: &[Sequence]) -> io::Result<()> {
+        // This method saves the graph in GFA2 format. Links become edge lines with explicit (and
+        // zero-length) overlap coordinates, and sequence paths become ordered group lines.
+        let mut file = output_writer(gfa_filename)?;
+        writeln!(file, "{}", self.gfa_header_line(2))?;
         for unitig in &self.unitigs {
-            writeln!(file, "{}", unitig.borrow().gfa_segment_line())?;
+            writeln!(file, "{}", unitig.gfa2_segment_line())?;
         }
-        for (a, a_strand, b, b_strand) in self.get_links_for_gfa(0) {
-            writeln!(file, "L\t{}\t{}\t{}\t{}\t0M", a, a_strand, b, b_strand)?;
+        for edge_line in self.get_gfa2_edge_lines(0) {
+            writeln!(file, "{}", edge_line)?;
         }
-        for s in sequences {
-            writeln!(file, "{}", self.get_gfa_path_line(s))?;
+        for (s, path) in sequences.iter().zip(self.get_unitig_paths_for_sequences(sequences)) {
+            writeln!(file, "{}", get_gfa2_path_line(s, &path, self.seq_repairs.get(&s.id)))?;
         }
-        Ok(())
+        file.finish()
+    }
+
+    pub fn gfa_lines(&self, sequences: &[Sequence]) -> Vec<String> {
+        // Returns the graph as GFA1 lines, for use without a file (e.g. re-clustering in memory).
+        let mut lines = vec![self.gfa_header_line(1)];
+        lines.extend(self.unitigs.iter().map(|u| u.gfa_segment_line()));
+        lines.extend(self.get_links_for_gfa(0).into_iter().map(|(a, a_strand, b, b_strand)|
+            format!("L\t{}\t{}\t{}\t{}\t0M", a, a_strand, b, b_strand)));
+        lines.extend(sequences.iter().zip(self.get_unitig_paths_for_sequences(sequences))
+            .map(|(s, path)| get_gfa_path_line(s, &path, self.seq_repairs.get(&s.id))));
+        lines
+    }
+
+    pub fn save_gfa_with_version(&self, gfa_filename: &Path, sequences: &[Sequence],
+                                 gfa_version: u32) -> io::Result<()> {
+        if gfa_version == 2 {
+            self.save_gfa2(gfa_filename, sequences)
+        } else {
+            self.save_gfa(gfa_filename, sequences)
+        }
+    }
+
+    pub fn save_dot(&self, dot_filename: &Path) -> io::Result<()> {
+        // This method saves the graph in Graphviz DOT format. Each unitig strand is its own node,
+        // so the output contains both strands of the graph (like Bandage's double mode).
+        let mut file = output_writer(dot_filename)?;
+        writeln!(file, "digraph autocycler {{")?;
+        for (name, number, strand, length, depth) in self.get_strand_nodes() {
+            writeln!(file, "    \"{}\" [unitig={}, strand=\"{}\", length={}, depth={:.2}];",
+                     name, number, strand, length, depth)?;
+        }
+        for (a, b) in self.get_strand_edges() {
+            writeln!(file, "    \"{}\" -> \"{}\";", a, b)?;
+        }
+        writeln!(file, "}}")?;
+        file.finish()
+    }
+
+    pub fn save_graphml(&self, graphml_filename: &Path) -> io::Result<()> {
+        // This method saves the graph in GraphML format. Like the DOT output, each unitig strand
+        // is its own node.
+        let mut file = output_writer(graphml_filename)?;
+        writeln!(file, "<?xml version=\"1.0\" encoding=\"UTF-8\"?>")?;
+        writeln!(file, "<graphml xmlns=\"http://graphml.graphdrawing.org/xmlns\">")?;
+        writeln!(file, "  <key id=\"unitig\" for=\"node\" attr.name=\"unitig\" attr.type=\"int\"/>")?;
+        writeln!(file, "  <key id=\"strand\" for=\"node\" attr.name=\"strand\" attr.type=\"string\"/>")?;
+        writeln!(file, "  <key id=\"length\" for=\"node\" attr.name=\"length\" attr.type=\"int\"/>")?;
+        writeln!(file, "  <key id=\"depth\" for=\"node\" attr.name=\"depth\" attr.type=\"double\"/>")?;
+        writeln!(file, "  <graph id=\"autocycler\" edgedefault=\"directed\">")?;
+        for (name, number, strand, length, depth) in self.get_strand_nodes() {
+            writeln!(file, "    <node id=\"{}\">", name)?;
+            writeln!(file, "      <data key=\"unitig\">{}</data>", number)?;
+            writeln!(file, "      <data key=\"strand\">{}</data>", strand)?;
+            writeln!(file, "      <data key=\"length\">{}</data>", length)?;
+            writeln!(file, "      <data key=\"depth\">{:.2}</data>", depth)?;
+            writeln!(file, "    </node>")?;
+        }
+        for (a, b) in self.get_strand_edges() {
+            writeln!(file, "    <edge source=\"{}\" target=\"{}\"/>", a, b)?;
+        }
+        writeln!(file, "  </graph>")?;
+        writeln!(file, "</graphml>")?;
+        file.finish()
+    }
+
+    pub fn save_unitig_fasta(&self, fasta_filename: &Path, sequences: &[Sequence])
+            -> io::Result<()> {
+        // This method saves each unitig's forward sequence to a FASTA file. Headers include the
+        // unitig's number, length and depth, plus the clusters of any sequences which pass
+        // through the unitig.
+        let seq_clusters: HashMap<u16, u16> = sequences.iter().map(|s| (s.id, s.cluster)).collect();
+        let mut file = output_writer(fasta_filename)?;
+        for u in &self.unitigs {
+            let mut clusters: Vec<u16> = u.forward_positions.iter()
+                .filter_map(|p| seq_clusters.get(&p.seq_id()).copied())
+                .filter(|&c| c > 0).collect();
+            clusters.sort();
+            clusters.dedup();
+            let cluster_str = if clusters.is_empty() { String::new() } else {
+                format!(" cluster={}", clusters.iter().map(|c| c.to_string())
+                                               .collect::<Vec<_>>().join(","))
+            };
+            writeln!(file, ">{} length={} depth={:.2}{}", u.number, u.length(), u.depth,
+                     cluster_str)?;
+            write_fasta_seq(&mut file, &String::from_utf8_lossy(&u.forward_seq), 0)?;
+        }
+        file.finish()
+    }
+
+    fn get_strand_nodes(&self) -> Vec<(String, u32, &str, u32, f64)> {
+        // Returns a node for each strand of each unitig: (name, number, strand, length, depth).
+        let mut nodes = Vec::new();
+        for u in &self.unitigs {
+            for strand in ["+", "-"] {
+                nodes.push((format!("{}{}", u.number, strand), u.number, strand, u.length(), u.depth));
+            }
+        }
+        nodes
+    }
+
+    fn get_strand_edges(&self) -> Vec<(String, String)> {
+        // Returns an edge for each link between unitig strands, using the same names as the nodes
+        // from get_strand_nodes.
+        self.get_links_for_gfa(0).into_iter()
+            .map(|(a, a_strand, b, b_strand)| (a + &a_strand, b + &b_strand)).collect()
     }
 
     pub fn get_links_for_gfa(&self, offset: u32) -> Vec<(String, String, String, String)> {
         let mut links = Vec::new();
-        for a_rc in &self.unitigs {
-            let a = a_rc.borrow();
+        for a in &self.unitigs {
             let a_num = a.number + offset;
             for b in &a.forward_next {
-                let b_num = b.number() + offset;
+                let b_num = self.number(b) + offset;
                 links.push((a_num.to_string(), "+".to_string(), b_num.to_string(),
                             (if b.strand {"+"} else {"-"}).to_string()));
             }
             for b in &a.reverse_next {
-                let b_num = b.number() + offset;
+                let b_num = self.number(b) + offset;
                 links.push((a_num.to_string(), "-".to_string(), b_num.to_string(),
                             (if b.strand {"+"} else {"-"}).to_string()));
             }
@@ -350,116 +985,193 @@ impl UnitigGraph {
         links
     }
 
-    fn get_gfa_path_line(&self, seq: &Sequence) -> String {
-        let unitig_path = self.get_unitig_path_for_sequence(seq);
-        let path_str: Vec<String> = unitig_path.iter()
-            .map(|(num, strand)| format!("{}{}", num, if *strand { "+" } else { "-" })).collect();
-        let path_str = path_str.join(",");
-        let cluster_tag = if seq.cluster > 0 {format!("\tCL:i:{}", seq.cluster)} else {"".to_string()};
-        format!("P\t{}\t{}\t*\tLN:i:{}\tFN:Z:{}\tHD:Z:{}{}",
-                seq.id, path_str, seq.length, seq.filename, seq.contig_header, cluster_tag)
+    pub fn get_confidence_bed_lines(&self, offset: u32) -> Vec<String> {
+        // Returns one BED line per confidence region, named by unitig number (plus the offset) as
+        // in the consensus FASTA. The BED score is the confidence score scaled to 0-1000.
+        let mut lines = Vec::new();
+        for u in &self.unitigs {
+            for c in &u.confidence {
+                lines.push(format!("{}\t{}\t{}\tsupport={:.2};consistency={:.2}\t{}",
+                                   u.number + offset, c.start, c.end, c.support, c.consistency,
+                                   (c.score() * 1000.0).round() as u32));
+            }
+        }
+        lines
+    }
+
+    pub fn get_support_bedgraph_lines(&self, offset: u32) -> Vec<String> {
+        // Returns bedGraph lines giving the number of input assemblies which agree with each base,
+        // from the confidence regions' support fractions and the assembly count saved by resolve.
+        // Graphs without an assembly count give no lines. Adjacent regions with the same count
+        // are joined.
+        let Some(assembly_count) = self.assembly_count else { return Vec::new(); };
+        let assembly_count = assembly_count as f64;
+        let mut lines = Vec::new();
+        for u in &self.unitigs {
+            let mut regions: Vec<(u32, u32, u32)> = Vec::new();
+            for c in &u.confidence {
+                let count = (c.support * assembly_count).round() as u32;
+                match regions.last_mut() {
+                    Some(last) if last.1 == c.start && last.2 == count => last.1 = c.end,
+                    _ => regions.push((c.start, c.end, count)),
+                }
+            }
+            for (start, end, count) in regions {
+                lines.push(format!("{}\t{}\t{}\t{}", u.number + offset, start, end, count));
+            }
+        }
+        lines
+    }
+
+    pub fn get_gfa2_edge_lines(&self, offset: u32) -> Vec<String> {
+        // Autocycler links have no overlap, so each GFA2 edge is a zero-length alignment at the
+        // appropriate end of each segment: the end of a forward-strand source (or the start of a
+        // reverse-strand source) and the start of a forward-strand destination (or the end of a
+        // reverse-strand destination). A GFA2 edge covers both directions of a link, so only one
+        // of each link and its reverse complement is written.
+        let mut edges = Vec::new();
+        for a in &self.unitigs {
+            let a_num = a.number + offset;
+            let a_end = format!("{}$", a.length());
+            for (a_strand, next) in [("+", &a.forward_next), ("-", &a.reverse_next)] {
+                let a_pos = if a_strand == "+" { a_end.as_str() } else { "0" };
+                for b in next {
+                    let b_unitig = &self.unitigs[b.unitig];
+                    if (a.number, a_strand == "+") > (b_unitig.number, !b.strand) { continue; }
+                    let b_num = b_unitig.number + offset;
+                    let b_strand = if b.strand { "+" } else { "-" };
+                    let b_pos = if b.strand { "0".to_string() }
+                                       else { format!("{}$", b_unitig.length()) };
+                    edges.push(format!("E\t*\t{}{}\t{}{}\t{}\t{}\t{}\t{}\t0M", a_num, a_strand,
+                                       b_num, b_strand, a_pos, a_pos, b_pos, b_pos));
+                }
+            }
+        }
+        edges
     }
 
-    pub fn reconstruct_original_sequences(&self, seqs: &Vec<Sequence>)
+    pub fn reconstruct_original_sequences(&self, seqs: &[Sequence])
             -> HashMap<String, Vec<(String, String)>> {
         let mut original_seqs = HashMap::new();
-        for seq in seqs {
-            let (filename, header, sequence) = self.reconstruct_original_sequence(seq);
+        for (seq, path) in seqs.iter().zip(self.get_unitig_paths_for_sequences(seqs)) {
+            let (filename, header, sequence) = self.reconstruct_original_sequence(seq, &path);
+            for d in &seq.duplicates {
+                let sequence = if d.reverse {
+                    String::from_utf8(reverse_complement(sequence.as_bytes())).unwrap()
+                } else {
+                    sequence.clone()
+                };
+                original_seqs.entry(d.filename.clone()).or_insert_with(Vec::new)
+                    .push((d.contig_header.clone(), sequence));
+            }
             original_seqs.entry(filename).or_insert_with(Vec::new).push((header, sequence));
         }
         original_seqs
     }
 
-    pub fn reconstruct_original_sequences_u8(&self, seqs: &Vec<Sequence>)
+    pub fn reconstruct_original_sequences_u8(&self, seqs: &[Sequence])
             -> Vec<((String, String), Vec<u8>)> {
         let mut original_seqs = Vec::new();
-        for seq in seqs {
-            let (filename, _header, sequence) = self.reconstruct_original_sequence(seq);
+        for (seq, path) in seqs.iter().zip(self.get_unitig_paths_for_sequences(seqs)) {
+            let (filename, _header, sequence) = self.reconstruct_original_sequence(seq, &path);
             original_seqs.push(((filename, seq.contig_name()), sequence.as_bytes().to_owned()));
         }
         original_seqs.sort();
         original_seqs
     }
 
-    fn reconstruct_original_sequence(&self, seq: &Sequence) -> (String, String, String) {
-        let path = self.get_unitig_path_for_sequence(seq);
-        let sequence = self.get_sequence_from_path(&path);
+    fn reconstruct_original_sequence(&self, seq: &Sequence, path: &[(u32, bool)])
+            -> (String, String, String) {
+        let sequence = self.get_repaired_sequence_from_path(seq, path);
         assert_eq!(sequence.len(), seq.length, "reconstructed sequence does not have expected length");
-        (seq.filename.clone(), seq.contig_header.clone(), sequence)
+        (seq.filename.clone(), seq.original_header(), sequence)
     }
 
-    fn get_sequence_from_path(&self, path: &[(u32, bool)]) -> String {
+    pub fn get_sequence_from_path(&self, path: &[(u32, bool)]) -> String {
         // Given a path (vector of unitig IDs and strands), this function returns the sequence
         // traced by that path. It also requires a unitig index so it can quickly look up unitigs
         // by their number.
         let mut sequence = Vec::new();
         for (unitig_num, strand) in path.iter() {
-            let unitig = self.unitig_index.get(unitig_num).unwrap();
-            sequence.push(String::from_utf8(unitig.borrow().get_seq(*strand)).unwrap());
+            let unitig = self.unitig(*unitig_num);
+            sequence.push(String::from_utf8(unitig.get_seq(*strand)).unwrap());
         }
         sequence.into_iter().collect()
     }
 
+    pub fn get_repaired_sequence_from_path(&self, seq: &Sequence, path: &[(u32, bool)]) -> String {
+        // Same as get_sequence_from_path, but with the sequence's repairs put back in, so the
+        // result is the sequence itself.
+        let mut repairs = self.seq_repairs.get(&seq.id).into_iter().flatten().peekable();
+        let mut sequence = Vec::with_capacity(seq.length);
+        for &(unitig_num, strand) in path {
+            while let Some(r) = repairs.next_if(|r| r.pos as usize == sequence.len()) {
+                sequence.extend_from_slice(&r.seq);
+            }
+            sequence.extend(self.unitig(unitig_num).get_seq(strand));
+        }
+        while let Some(r) = repairs.next_if(|r| r.pos as usize == sequence.len()) {
+            sequence.extend_from_slice(&r.seq);
+        }
+        String::from_utf8(sequence).unwrap()
+    }
+
     pub fn get_sequence_from_path_signed(&self, path: &[i32]) -> Vec<u8> {
         let path: Vec<_> = path.iter().map(|&x| (x.unsigned_abs(), x >= 0)).collect();
         self.get_sequence_from_path(&path).as_bytes().to_owned()
     }
 
-    fn find_starting_unitig(&self, seq_id: u16) -> UnitigStrand {
-        // For a given sequence ID, this function returns the Unitig and strand where that sequence
-        // begins.
-        let mut starting_unitigs = Vec::new();
-        for unitig in &self.unitigs {
-            for p in &unitig.borrow().forward_positions {
-                if p.seq_id() == seq_id && p.strand() && p.pos == 0 {
-                    starting_unitigs.push(UnitigStrand::new(unitig, strand::FORWARD));
-                }
-            }
-            for p in &unitig.borrow().reverse_positions {
-                if p.seq_id() == seq_id && p.strand() && p.pos == 0 {
-                    starting_unitigs.push(UnitigStrand::new(unitig, strand::REVERSE));
-                }
-            }
-        }
-        assert_eq!(starting_unitigs.len(), 1);
-        starting_unitigs[0].clone()
-    }
-
-    pub fn get_next_unitig(&self, seq_id: u16, seq_strand: bool, unitig_rc: &Rc<RefCell<Unitig>>,
-                           strand: bool, pos: u32) -> Option<(UnitigStrand, u32)> {
-        // For a given unitig that's part of a sequence's path, this function will return the next
-        // unitig in that sequence's path.
-        let unitig = unitig_rc.borrow();
-        let next_pos = pos + unitig.length();
-        let next_unitigs = if strand { &unitig.forward_next } else { &unitig.reverse_next };
-        for next in next_unitigs {
-            let u = next.unitig.borrow();
-            let positions = if next.strand { &u.forward_positions } else { &u.reverse_positions};
-            for p in positions {
-                if p.seq_id() == seq_id && p.strand() == seq_strand && p.pos == next_pos {
-                    return Some((UnitigStrand::new(&next.unitig, next.strand), next_pos));
+    fn build_position_index(&self, seq_ids: Option<&HashSet<u16>>) -> PositionIndex {
+        // Builds a lookup from (sequence ID, position) to the unitig strand which the sequence's
+        // forward strand occupies at that position. If seq_ids is given, only those sequences are
+        // included.
+        let mut index = HashMap::new();
+        for u in &self.unitigs {
+            for (positions, unitig_strand) in [(&u.forward_positions, strand::FORWARD),
+                                               (&u.reverse_positions, strand::REVERSE)] {
+                for p in positions {
+                    if !p.strand() { continue; }
+                    if let Some(ids) = seq_ids {
+                        if !ids.contains(&p.seq_id()) { continue; }
+                    }
+                    index.insert((p.seq_id(), p.pos), (u.number, unitig_strand));
                 }
             }
         }
-        None
+        index
     }
 
-    pub fn get_unitig_path_for_sequence(&self, seq: &Sequence) -> Vec<(u32, bool)> {
+    fn get_gapped_path_from_index(&self, seq: &Sequence, index: &PositionIndex)
+            -> Vec<Option<(u32, bool)>> {
+        // Follows a sequence's path through the graph by looking up each successive position. Each
+        // of the sequence's repairs is skipped over and given as None.
+        let gaps: HashMap<u32, u32> = self.repair_gaps(seq.id, seq.length as u32, strand::FORWARD)
+            .into_iter().collect();
         let mut unitig_path = Vec::new();
-        let mut u = self.find_starting_unitig(seq.id);
         let mut pos = 0;
         loop {
-            unitig_path.push((u.number(), u.strand));
-            match self.get_next_unitig(seq.id, strand::FORWARD, &u.unitig, u.strand, pos) {
-                None => break,
-                Some((next, next_pos)) => {
-                    (u, pos) = (next, next_pos);
-                }
+            if let Some(&(number, unitig_strand)) = index.get(&(seq.id, pos)) {
+                unitig_path.push(Some((number, unitig_strand)));
+                pos += self.unitig(number).length();
+            } else if let Some(&gap_len) = gaps.get(&pos) {
+                unitig_path.push(None);
+                pos += gap_len;
+            } else {
+                break;
             }
         }
+        assert!(unitig_path.iter().any(|u| u.is_some()),
+                "no starting unitig for sequence {}", seq.id);
         unitig_path
     }
 
+    #[cfg(test)]
+    pub fn get_unitig_path_for_sequence(&self, seq: &Sequence) -> Vec<(u32, bool)> {
+        let index = self.build_position_index(Some(&HashSet::from([seq.id])));
+        self.get_gapped_path_from_index(seq, &index).into_iter().flatten().collect()
+    }
+
+    #[cfg(test)]
     pub fn get_unitig_path_for_sequence_i32(&self, seq: &Sequence) -> Vec<i32> {
         // Same as the above function, but instead of giving unitig IDs and strands as a (u32, bool)
         // tuple, it gives them as i32 (negative numbers for reverse strand).
@@ -467,8 +1179,46 @@ impl UnitigGraph {
         unitig_path.iter().map(|(u, s)| if *s { *u as i32 } else { -(*u as i32)}).collect()
     }
 
+    pub fn get_unitig_paths_for_sequences(&self, seqs: &[Sequence]) -> Vec<Vec<(u32, bool)>> {
+        // Same as get_unitig_path_for_sequence but for many sequences at once, which is much faster than
+        // calling it repeatedly because the position index is only built once.
+        let index = self.build_position_index(None);
+        seqs.iter().map(|s| self.get_gapped_path_from_index(s, &index).into_iter().flatten()
+                                .collect()).collect()
+    }
+
+    pub fn get_gapped_unitig_paths_i32(&self, seqs: &[Sequence]) -> Vec<Vec<i32>> {
+        // Same as get_unitig_paths_for_sequences_i32, but with a 0 wherever a sequence's path skips
+        // one of its repairs. This is for code which rebuilds sequences on rewritten paths (see
+        // set_repair_positions).
+        let index = self.build_position_index(None);
+        seqs.iter().map(|s| self.get_gapped_path_from_index(s, &index).into_iter().map(|u| {
+            match u { Some((n, s)) => if s { n as i32 } else { -(n as i32) }, None => 0 }
+        }).collect()).collect()
+    }
+
+    pub fn get_unitig_paths_for_sequences_i32(&self, seqs: &[Sequence]) -> Vec<Vec<i32>> {
+        self.get_unitig_paths_for_sequences(seqs).into_iter()
+            .map(|path| path.iter().map(|(u, s)| if *s { *u as i32 } else { -(*u as i32) }).collect())
+            .collect()
+    }
+
+    pub fn has_consistent_dead_ends(&self, sequences: &[Sequence]) -> bool {
+        // Returns whether every sequence's path starts and ends at a dead end, as expected for a
+        // linear replicon whose ends were assembled consistently. Circular sequences instead start
+        // at different places, so their path ends have links onward.
+        if sequences.is_empty() { return false; }
+        self.get_unitig_paths_for_sequences_i32(sequences).iter().all(|path| {
+            match (path.first(), path.last()) {
+                (Some(&first), Some(&last)) => self.incoming_strands(first).is_empty() &&
+                                               self.outgoing_strands(last).is_empty(),
+                _ => false,
+            }
+        })
+    }
+
     pub fn total_length(&self) -> u64 {
-        self.unitigs.iter().map(|u| u.borrow().length() as u64).sum()
+        self.unitigs.iter().map(|u| u.length() as u64).sum()
     }
 
     pub fn link_count(&self) -> (usize, usize) {
@@ -479,11 +1229,10 @@ impl UnitigGraph {
         // have a reverse).
         let mut all_links = HashSet::new();
         let mut one_way_links = HashSet::new();
-        for a_rc in &self.unitigs {
-            let a = a_rc.borrow();
-            let a_num = a_rc.borrow().number as i32;
+        for a in &self.unitigs {
+            let a_num = a.number as i32;
             for b in &a.forward_next {
-                let b_num = b.signed_number();
+                let b_num = self.signed_number(b);
                 let link = (a_num, b_num);
                 let rev_link = (-b_num, -a_num);
                 all_links.insert(link);
@@ -491,7 +1240,7 @@ impl UnitigGraph {
                 one_way_links.insert(if link > rev_link { link } else { rev_link });
             }
             for b in &a.reverse_next {
-                let b_num = b.signed_number();
+                let b_num = self.signed_number(b);
                 let link = (-a_num, b_num);
                 let rev_link = (-b_num, a_num);
                 all_links.insert(link);
@@ -522,72 +1271,160 @@ impl UnitigGraph {
         // * other: none of the above (e.g. a circularising link and a hairpin link, should be rare)
         if self.unitigs.is_empty() { return "empty".to_string(); }
         if self.unitigs.len() > 1 { return "fragmented".to_string(); }
-        let u = self.unitigs[0].borrow();  // the only unitig in the graph
+        let u = &self.unitigs[0];  // the only unitig in the graph
         if self.link_count().0 == 0 { return "linear_blunt_blunt".to_string(); }
-        if u.is_isolated_and_circular() { return "circular".to_string(); }
-        if u.hairpin_start() && u.hairpin_end() { return "linear_hairpin_hairpin".to_string(); }
-        if u.hairpin_start() && u.blunt_end() { return "linear_blunt_hairpin".to_string(); }
-        if u.blunt_start() && u.hairpin_end() { return "linear_blunt_hairpin".to_string(); }
+        if u.is_isolated_and_circular(0) { return "circular".to_string(); }
+        if u.hairpin_start(0) && u.hairpin_end(0) { return "linear_hairpin_hairpin".to_string(); }
+        if u.hairpin_start(0) && u.blunt_end() { return "linear_blunt_hairpin".to_string(); }
+        if u.blunt_start() && u.hairpin_end(0) { return "linear_blunt_hairpin".to_string(); }
         "other".to_string()
     }
 
-    pub fn delete_dangling_links(&mut self) {
-        // This method deletes any links to no-longer-existing unitigs. It should be run after any
-        // code which deletes Unitigs from the graph.
-        let unitig_numbers: HashSet<u32> = self.unitigs.iter().map(|u| u.borrow().number).collect();
-        for unitig_rc in &self.unitigs {
-            let unitig = unitig_rc.borrow();
-            let forward_next_to_remove = unitig.forward_next.iter().enumerate().filter_map(|(index, u)| {if !unitig_numbers.contains(&u.number()) {Some(index)} else {None}}).collect::<Vec<_>>();
-            let forward_prev_to_remove = unitig.forward_prev.iter().enumerate().filter_map(|(index, u)| {if !unitig_numbers.contains(&u.number()) {Some(index)} else {None}}).collect::<Vec<_>>();
-            let reverse_next_to_remove = unitig.reverse_next.iter().enumerate().filter_map(|(index, u)| {if !unitig_numbers.contains(&u.number()) {Some(index)} else {None}}).collect::<Vec<_>>();
-            let reverse_prev_to_remove = unitig.reverse_prev.iter().enumerate().filter_map(|(index, u)| {if !unitig_numbers.contains(&u.number()) {Some(index)} else {None}}).collect::<Vec<_>>();
-            drop(unitig);
-            let mut unitig = unitig_rc.borrow_mut();
-            for index in forward_next_to_remove.into_iter().rev() { unitig.forward_next.remove(index); }
-            for index in forward_prev_to_remove.into_iter().rev() { unitig.forward_prev.remove(index); }
-            for index in reverse_next_to_remove.into_iter().rev() { unitig.reverse_next.remove(index); }
-            for index in reverse_prev_to_remove.into_iter().rev() { unitig.reverse_prev.remove(index); }
-        }
-    }
-
     pub fn remove_sequence_from_graph(&mut self, seq_id: u16) {
         // Removes all Positions from the Unitigs which have the given sequence ID. This reduces
         // depths of affected Unitigs, and can result in zero-depth unitigs, so it may be necessary
         // to run remove_zero_depth_unitigs after this.
-        for u in &self.unitigs {
-            u.borrow_mut().remove_sequence(seq_id);
+        for u in &mut self.unitigs {
+            u.remove_sequence(seq_id, &self.seq_weights);
         }
     }
 
     pub fn recalculate_depths(&mut self) {
         // Sets each unitig's depth based on its Positions. Useful after adding/removing paths.
-        for u in &self.unitigs {
-            u.borrow_mut().recalculate_depth();
+        for u in &mut self.unitigs {
+            u.recalculate_depth(&self.seq_weights);
+        }
+    }
+
+    pub fn estimate_multiplicities(&mut self, sequences: &[Sequence]) {
+        // Sets each unitig's multiplicity to the median number of times it is traversed by each
+        // input assembly's sequences. The median is over all assemblies, so a unitig missing from
+        // most assemblies gets 0. Does nothing if there are no sequences.
+        if sequences.is_empty() { return; }
+        let mut assemblies: Vec<&str> = sequences.iter().map(|s| s.filename.as_str()).collect();
+        assemblies.sort();
+        assemblies.dedup();
+        let seq_assemblies: HashMap<u16, usize> = sequences.iter()
+            .map(|s| (s.id, assemblies.binary_search(&s.filename.as_str()).unwrap())).collect();
+        for u in &mut self.unitigs {
+            let mut counts = vec![0; assemblies.len()];
+            for p in &u.forward_positions {
+                if let Some(&i) = seq_assemblies.get(&p.seq_id()) { counts[i] += 1; }
+            }
+            u.multiplicity = Some(median_usize(&counts) as u32);
+        }
+    }
+
+    pub fn flag_inverted_repeats(&mut self, min_length: usize) {
+        // Sets each unitig's inverted repeats: long ones within its sequence, plus any hairpin
+        // links where one of its ends leads to its own other strand. Both can make a sequence
+        // falsely look circular.
+        let k_size = self.k_size as usize;
+        for (h, u) in self.unitigs.iter_mut().enumerate() {
+            let mut repeats = u.find_inverted_repeats(k_size, min_length);
+            if u.reverse_next.contains(&UnitigStrand::new(h, strand::FORWARD)) {
+                repeats.push(InvertedRepeat::HairpinStart);
+            }
+            if u.forward_next.contains(&UnitigStrand::new(h, strand::REVERSE)) {
+                repeats.push(InvertedRepeat::HairpinEnd);
+            }
+            u.inverted_repeats = repeats;
         }
     }
 
+    pub fn set_sequence_weights(&mut self, sequences: &[Sequence]) {
+        // Stores the sequences' depth weights (and duplicates) and recalculates unitig depths to
+        // use them.
+        self.seq_weights = sequences.iter().filter(|s| s.weight != 1.0)
+                                    .map(|s| (s.id, s.weight)).collect();
+        self.seq_duplicates = sequences.iter().filter(|s| !s.duplicates.is_empty())
+                                       .map(|s| (s.id, s.duplicates.clone())).collect();
+        self.recalculate_depths();
+    }
+
     pub fn remove_zero_depth_unitigs(&mut self) {
-        self.unitigs.retain(|u| u.borrow().depth > 0.0);
-        self.delete_dangling_links();
-        self.build_unitig_index();
+        self.retain_unitigs(|u| u.depth > 0.0);
+    }
+
+    pub fn merge_linear_paths(&mut self, sequences: &[Sequence]) {
+        // Re-compacts the graph after unitigs or links have been removed: chains of unitigs with
+        // single exclusive links are merged (without merging across sequence path starts/ends, so
+        // paths stay valid), and then the unitigs are renumbered.
+        graph_simplification::merge_linear_paths(self, sequences, None);
+        self.renumber_unitigs();
     }
 
     pub fn remove_unitigs_by_number(&mut self, to_remove: HashSet<u32>) {
-        self.unitigs.retain(|u| !to_remove.contains(&u.borrow().number));
-        self.delete_dangling_links();
-        self.build_unitig_index();
+        self.retain_unitigs(|u| !to_remove.contains(&u.number));
+    }
+
+    pub fn extract_subgraph(&self, unitig_numbers: &[u32], sequences: &[Sequence])
+            -> (UnitigGraph, Vec<Sequence>) {
+        // Returns a new graph containing copies of the given unitigs and the links between them,
+        // along with the sequences whose paths are entirely within those unitigs. Positions for
+        // other sequences are dropped and depths are recalculated. The original graph is not
+        // modified.
+        let selected: HashSet<u32> = unitig_numbers.iter().copied()
+            .filter(|n| self.unitig_index.contains_key(n)).collect();
+        let sub_sequences: Vec<Sequence> = sequences.iter()
+            .zip(self.get_unitig_paths_for_sequences(sequences))
+            .filter(|(_, path)| path.iter().all(|(n, _)| selected.contains(n)))
+            .map(|(s, _)| s.clone()).collect();
+        let seq_ids: HashSet<u16> = sub_sequences.iter().map(|s| s.id).collect();
+
+        // Only the links which are internal to the selection are copied over, using the copies'
+        // handles in the subgraph.
+        let mut new_handles = vec![None; self.unitigs.len()];
+        let mut count = 0;
+        for (h, unitig) in self.unitigs.iter().enumerate() {
+            if selected.contains(&unitig.number) { new_handles[h] = Some(count); count += 1; }
+        }
+        let copy_links = |links: &Vec<UnitigStrand>| -> Vec<UnitigStrand> {
+            links.iter().filter_map(|l| new_handles[l.unitig].map(|h| UnitigStrand::new(h, l.strand)))
+                .collect()
+        };
+
+        let mut subgraph = UnitigGraph { k_size: self.k_size,
+                                         seq_weights: self.seq_weights.clone(),
+                                         seq_duplicates: self.seq_duplicates.clone(),
+                                         seq_repairs: self.seq_repairs.clone(),
+                                         ..Default::default() };
+        for unitig in &self.unitigs {
+            if !selected.contains(&unitig.number) { continue; }
+            let mut copy = Unitig {
+                number: unitig.number,
+                forward_seq: unitig.forward_seq.clone(),
+                reverse_seq: unitig.reverse_seq.clone(),
+                anchor: unitig.anchor,
+                bridge: unitig.bridge,
+                ambiguities: unitig.ambiguities.clone(),
+                multiplicity: unitig.multiplicity,
+                inverted_repeats: unitig.inverted_repeats.clone(),
+                confidence: unitig.confidence.clone(),
+                forward_positions: unitig.forward_positions.iter()
+                    .filter(|p| seq_ids.contains(&p.seq_id())).cloned().collect(),
+                reverse_positions: unitig.reverse_positions.iter()
+                    .filter(|p| seq_ids.contains(&p.seq_id())).cloned().collect(),
+                forward_next: copy_links(&unitig.forward_next),
+                forward_prev: copy_links(&unitig.forward_prev),
+                reverse_next: copy_links(&unitig.reverse_next),
+                reverse_prev: copy_links(&unitig.reverse_prev),
+                ..Default::default()
+            };
+            copy.recalculate_depth(&subgraph.seq_weights);
+            subgraph.unitigs.push(copy);
+        }
+        subgraph.build_unitig_index();
+        subgraph.check_links().unwrap();
+        (subgraph, sub_sequences)
     }
 
     pub fn link_exists(&self, a_num: u32, a_strand: bool, b_num: u32, b_strand: bool) -> bool {
         // Checks if the given link exists (looks for it in forward_next/reverse_next).
-        if let Some(unitig_a) = self.unitig_index.get(&a_num) {
-            let unitig_a = unitig_a.borrow();
+        if let (Some(&a), Some(&b)) = (self.unitig_index.get(&a_num), self.unitig_index.get(&b_num)) {
+            let unitig_a = &self.unitigs[a];
             let next_links = if a_strand {&unitig_a.forward_next} else {&unitig_a.reverse_next};
-            for next in next_links {
-                if next.number() == b_num && next.strand == b_strand {
-                    return true;
-                }
-            }
+            return next_links.contains(&UnitigStrand::new(b, b_strand));
         }
         false
     }
@@ -595,83 +1432,64 @@ impl UnitigGraph {
     pub fn link_exists_prev(&self, a_num: u32, a_strand: bool, b_num: u32, b_strand: bool) -> bool {
         // This is like the link_exists method, but it checks in the opposite direction (looks for
         // it in forward_prev/reverse_prev).
-        if let Some(unitig_b) = self.unitig_index.get(&b_num) {
-            let unitig_b = unitig_b.borrow();
+        if let (Some(&a), Some(&b)) = (self.unitig_index.get(&a_num), self.unitig_index.get(&b_num)) {
+            let unitig_b = &self.unitigs[b];
             let prev_links = if b_strand {&unitig_b.forward_prev} else {&unitig_b.reverse_prev};
-            for prev in prev_links {
-                if prev.number() == a_num && prev.strand == a_strand {
-                    return true;
-                }
-            }
+            return prev_links.contains(&UnitigStrand::new(a, a_strand));
         }
         false
     }
 
-    pub fn check_links(&self) {
+    pub fn check_links(&self) -> Result<(), GraphError> {
         // Makes sure that all of the graph's links are valid:
         // * Each link should have a corresponding link on the opposite strand.
         // * Each next link should be matched with a prev link.
         // * All linked Unitigs should be in the unitig_index.
-        // If any of the above aren't true, this method will panic.
-        for a_rc in &self.unitigs {
-            let a = a_rc.borrow();
-            for b in &a.forward_next {
-                let a_strand = strand::FORWARD;
-                if !self.link_exists(a.number, a_strand, b.number(), b.strand) {panic!("missing next link");}
-                if !self.link_exists_prev(a.number, a_strand, b.number(), b.strand) {panic!("missing prev link");}
-                if !self.link_exists(b.number(), !b.strand, a.number, !a_strand) {panic!("missing next link");}
-                if !self.link_exists_prev(b.number(), !b.strand, a.number, !a_strand) {panic!("missing prev link");}
-                if !self.unitig_index.contains_key(&b.number()) {panic!("unitig missing from index");}
-            }
-            for b in &a.reverse_next {
-                let a_strand = strand::REVERSE;
-                if !self.link_exists(a.number, a_strand, b.number(), b.strand) {panic!("missing next link");}
-                if !self.link_exists_prev(a.number, a_strand, b.number(), b.strand) {panic!("missing prev link");}
-                if !self.link_exists(b.number(), !b.strand, a.number, !a_strand) {panic!("missing next link");}
-                if !self.link_exists_prev(b.number(), !b.strand, a.number, !a_strand) {panic!("missing prev link");}
-                if !self.unitig_index.contains_key(&b.number()) {panic!("unitig missing from index");}
+        // The first problem found is returned as an error.
+        for a in &self.unitigs {
+            let a_num = a.number as i32;
+            for b in &a.forward_next { self.check_link(a_num, self.signed_number(b))?; }
+            for b in &a.reverse_next { self.check_link(-a_num, self.signed_number(b))?; }
+            for b in &a.forward_prev { self.check_link(self.signed_number(b), a_num)?; }
+            for b in &a.reverse_prev { self.check_link(self.signed_number(b), -a_num)?; }
+        }
+        Ok(())
+    }
+
+    fn check_link(&self, from: i32, to: i32) -> Result<(), GraphError> {
+        // Checks that a link (given as signed unitig numbers) and its reverse-complement link are
+        // both present as next and prev links.
+        for unitig in [from.unsigned_abs(), to.unsigned_abs()] {
+            if !self.unitig_index.contains_key(&unitig) {
+                return Err(GraphError::UnitigNotInIndex { unitig });
             }
-            for b in &a.forward_prev {
-                let a_strand = strand::FORWARD;
-                if !self.link_exists(b.number(), b.strand, a.number, a_strand) {panic!("missing next link");}
-                if !self.link_exists_prev(b.number(), b.strand, a.number, a_strand) {panic!("missing prev link");}
-                if !self.link_exists(a.number, !a_strand, b.number(), !b.strand) {panic!("missing next link");}
-                if !self.link_exists_prev(a.number, !a_strand, b.number(), !b.strand) {panic!("missing prev link");}
-                if !self.unitig_index.contains_key(&b.number()) {panic!("unitig missing from index");}
+        }
+        for (a, b) in [(from, to), (-to, -from)] {
+            let (a_num, a_strand, b_num, b_strand) = (a.unsigned_abs(), a > 0, b.unsigned_abs(), b > 0);
+            if !self.link_exists(a_num, a_strand, b_num, b_strand) {
+                return Err(GraphError::MissingNextLink { from: a, to: b });
             }
-            for b in &a.reverse_prev {
-                let a_strand = strand::REVERSE;
-                if !self.link_exists(b.number(), b.strand, a.number, a_strand) {panic!("missing next link");}
-                if !self.link_exists_prev(b.number(), b.strand, a.number, a_strand) {panic!("missing prev link");}
-                if !self.link_exists(a.number, !a_strand, b.number(), !b.strand) {panic!("missing next link");}
-                if !self.link_exists_prev(a.number, !a_strand, b.number(), !b.strand) {panic!("missing prev link");}
-                if !self.unitig_index.contains_key(&b.number()) {panic!("unitig missing from index");}
+            if !self.link_exists_prev(a_num, a_strand, b_num, b_strand) {
+                return Err(GraphError::MissingPrevLink { from: a, to: b });
             }
         }
+        Ok(())
+    }
+
+    fn unitig_strand(&self, signed_num: i32) -> UnitigStrand {
+        // Converts a signed unitig number (negative for the reverse strand) to a handle and
+        // strand. The unitig must be in the graph.
+        UnitigStrand::new(self.unitig_index[&signed_num.unsigned_abs()], signed_num > 0)
     }
 
     pub fn delete_outgoing_links(&mut self, signed_num: i32) {
-        let strand = if signed_num > 0 { strand::FORWARD } else { strand::REVERSE };
-        let unitig_num = signed_num.unsigned_abs();
-        let next_numbers: Vec<i32> = {
-            let unitig = self.unitig_index.get(&unitig_num).unwrap().borrow();
-            let next_unitigs = if strand { &unitig.forward_next } else { &unitig.reverse_next }; 
-            next_unitigs.iter().map(|u| u.signed_number()).collect()
-        };
-        for next_num in next_numbers {
+        for next_num in self.outgoing_strands(signed_num) {
             self.delete_link(signed_num, next_num);
         }
     }
 
     pub fn delete_incoming_links(&mut self, signed_num: i32) {
-        let strand = if signed_num > 0 { strand::FORWARD } else { strand::REVERSE };
-        let unitig_num = signed_num.unsigned_abs();
-        let prev_numbers: Vec<i32> = {
-            let unitig = self.unitig_index.get(&unitig_num).unwrap().borrow();
-            let prev_unitigs = if strand { &unitig.forward_prev } else { &unitig.reverse_prev }; 
-            prev_unitigs.iter().map(|u| u.signed_number()).collect()
-        };
-        for prev_num in prev_numbers {
+        for prev_num in self.incoming_strands(signed_num) {
             self.delete_link(prev_num, signed_num);
         }
     }
@@ -682,44 +1500,15 @@ impl UnitigGraph {
     }
 
     fn delete_link_one_way(&mut self, start_num: i32, end_num: i32) {
-        let start_strand = if start_num > 0 { strand::FORWARD } else { strand::REVERSE };
-        let end_strand = if end_num > 0 { strand::FORWARD } else { strand::REVERSE };
-        let start_num = start_num.unsigned_abs();
-        let end_num = end_num.unsigned_abs();
-        let start_rc = self.unitig_index.get(&start_num).unwrap();
-        let end_rc = self.unitig_index.get(&end_num).unwrap();
-
-        // Collect the indices to remove for start unitig
-        let start_indices: Vec<usize> = {
-            let start = start_rc.borrow();
-            let next_unitigs = if start_strand { &start.forward_next } else { &start.reverse_next };
-            next_unitigs.iter().enumerate().filter_map(|(i, connection)| { if connection.unitig.borrow().number == end_num && connection.strand == end_strand { Some(i) } else { None } }).collect()
-        };
-
-        // Remove the elements from start unitig
-        {
-            let mut start = start_rc.borrow_mut();
-            let next_unitigs = if start_strand { &mut start.forward_next } else { &mut start.reverse_next };
-            for &i in start_indices.iter().rev() {
-                next_unitigs.remove(i);
-            }
-        }
-
-        // Collect the indices to remove for end unitig
-        let end_indices: Vec<usize> = {
-            let end = end_rc.borrow();
-            let prev_unitigs = if end_strand { &end.forward_prev } else { &end.reverse_prev };
-            prev_unitigs.iter().enumerate().filter_map(|(i, connection)| { if connection.unitig.borrow().number == start_num && connection.strand == start_strand { Some(i) } else { None } }).collect()
-        };
-
-        // Remove the elements from end unitig
-        {
-            let mut end = end_rc.borrow_mut();
-            let prev_unitigs = if end_strand { &mut end.forward_prev } else { &mut end.reverse_prev };
-            for &i in end_indices.iter().rev() {
-                prev_unitigs.remove(i);
-            }
-        }
+        let start = self.unitig_strand(start_num);
+        let end = self.unitig_strand(end_num);
+        let start_unitig = &mut self.unitigs[start.unitig];
+        let next = if start.strand { &mut start_unitig.forward_next }
+                              else { &mut start_unitig.reverse_next };
+        next.retain(|&n| n != end);
+        let end_unitig = &mut self.unitigs[end.unitig];
+        let prev = if end.strand { &mut end_unitig.forward_prev } else { &mut end_unitig.reverse_prev };
+        prev.retain(|&p| p != start);
     }
 
     pub fn create_link(&mut self, start_num: i32, end_num: i32) {
@@ -730,80 +1519,60 @@ impl UnitigGraph {
     }
 
     fn create_link_one_way(&mut self, start_num: i32, end_num: i32) {
-        let start_strand = if start_num > 0 { strand::FORWARD } else { strand::REVERSE };
-        let end_strand = if end_num > 0 { strand::FORWARD } else { strand::REVERSE };
-        let start_num = start_num.unsigned_abs();
-        let end_num = end_num.unsigned_abs();
-        let start_rc = self.unitig_index.get(&start_num).unwrap();
-        let end_rc = self.unitig_index.get(&end_num).unwrap();
-        {
-            let mut start = start_rc.borrow_mut();
-            let connection = UnitigStrand { unitig: Rc::clone(end_rc), strand: end_strand };
-            let next_unitigs = if start_strand { &mut start.forward_next } else { &mut start.reverse_next };
-            next_unitigs.push(connection);
-        }
-        {
-            let mut end = end_rc.borrow_mut();
-            let reverse_connection = UnitigStrand { unitig: Rc::clone(start_rc), strand: start_strand };
-            let prev_unitigs = if end_strand { &mut end.forward_prev } else { &mut end.reverse_prev };
-            prev_unitigs.push(reverse_connection);
-        }
+        self.add_link(self.unitig_strand(start_num), self.unitig_strand(end_num));
+    }
+
+    pub fn add_link(&mut self, start: UnitigStrand, end: UnitigStrand) {
+        // Adds a link in one direction only (as a next link on the start and a prev link on the
+        // end), given by handles. Callers are responsible for adding the reverse-complement link.
+        let start_unitig = &mut self.unitigs[start.unitig];
+        if start.strand { start_unitig.forward_next.push(end); }
+                   else { start_unitig.reverse_next.push(end); }
+        let end_unitig = &mut self.unitigs[end.unitig];
+        if end.strand { end_unitig.forward_prev.push(start); }
+                 else { end_unitig.reverse_prev.push(start); }
     }
 
     pub fn clear_positions(&mut self) {
-        for u in &self.unitigs {
-            u.borrow_mut().clear_positions();
+        for u in &mut self.unitigs {
+            u.clear_positions();
         }
     }
 
     pub fn max_unitig_number(&self) -> u32 {
-        self.unitigs.iter().map(|u| u.borrow().number).max().unwrap_or(0)
+        self.unitigs.iter().map(|u| u.number).max().unwrap_or(0)
     }
 
     pub fn connected_components(&self) -> Vec<Vec<u32>> {
-        let mut visited = HashSet::new();
-        let mut components = Vec::new();
-        for unitig in &self.unitigs {
-            let unitig_num = unitig.borrow().number;
-            if !visited.contains(&unitig_num) {
-                let mut component = Vec::new();
-                self.dfs(unitig_num, &mut visited, &mut component);
-                component.sort();
-                components.push(component);
+        // Finds the graph's connected components using union-find, returning them as sorted lists
+        // of unitig numbers.
+        let mut parents: Vec<usize> = (0..self.unitigs.len()).collect();
+        for (i, u) in self.unitigs.iter().enumerate() {
+            for link in u.forward_next.iter().chain(u.reverse_next.iter()) {
+                union(&mut parents, i, link.unitig);
             }
         }
-        components.sort();
+        let mut components: HashMap<usize, Vec<u32>> = HashMap::new();
+        for (i, unitig) in self.unitigs.iter().enumerate() {
+            let root = find(&mut parents, i);
+            components.entry(root).or_default().push(unitig.number);
+        }
+        let mut components: Vec<Vec<u32>> = components.into_values()
+            .map(|mut c| { c.sort(); c }).collect();
+        components.sort();
         components
     }
 
-    fn dfs(&self, unitig_num: u32, visited: &mut HashSet<u32>, component: &mut Vec<u32>) {
-        let mut stack = vec![unitig_num];
-        while let Some(current) = stack.pop() {
-            if visited.insert(current) {
-                component.push(current);
-                for neighbor in self.connected_unitigs(current) {
-                    if !visited.contains(&neighbor) {
-                        stack.push(neighbor);
-                    }
-                }
+    pub fn assign_component_ids(&mut self) {
+        // Sets each unitig's component field (1-based, in the order given by
+        // connected_components) so later code can look up a unitig's component directly.
+        for (i, component) in self.connected_components().iter().enumerate() {
+            for num in component {
+                self.unitig_mut(*num).component = i as u32 + 1;
             }
         }
     }
 
-    fn connected_unitigs(&self, unitig_num: u32) -> HashSet<u32> {
-        // Given a unitig (by number), this function returns the unitigs (by number) it is directly
-        // connected to.
-        let mut connections = HashSet::new();
-        if let Some(unitig_rc) = self.unitig_index.get(&unitig_num) {
-            let unitig = unitig_rc.borrow();
-            for c in &unitig.forward_next { connections.insert(c.number()); }
-            for c in &unitig.forward_prev { connections.insert(c.number()); }
-            for c in &unitig.reverse_next { connections.insert(c.number()); }
-            for c in &unitig.reverse_prev { connections.insert(c.number()); }
-        }
-        connections
-    }
-
     pub fn component_is_circular_loop(&self, component: &[u32]) -> bool {
         // Given a connected component of the graph, this function returns whether or not it forms
         // a simple circular loop.
@@ -814,26 +1583,647 @@ impl UnitigGraph {
         let mut visited = HashSet::new();
         while num != first || visited.is_empty() {
             if !visited.insert(num) { return false; }
-            let unitig = self.unitig_index.get(&num).unwrap().borrow();
+            let unitig = self.unitig(num);
             if unitig.forward_next.len() != 1 || unitig.forward_prev.len() != 1 ||
                unitig.reverse_next.len() != 1 || unitig.reverse_prev.len() != 1 { return false; }
             let next = if strand { &unitig.forward_next[0] } else { &unitig.reverse_next[0] };
-            num = next.number();
+            num = self.number(next);
             strand = next.strand;
         }
         visited.len() == component.len()
     }
-}
 
+    pub fn outgoing_strands(&self, signed_num: i32) -> Vec<i32> {
+        // Returns the signed numbers of the unitig strands which the given unitig strand leads to.
+        let unitig = self.unitig(signed_num.unsigned_abs());
+        let next = if signed_num > 0 { &unitig.forward_next } else { &unitig.reverse_next };
+        next.iter().map(|u| self.signed_number(u)).collect()
+    }
+
+    pub fn incoming_strands(&self, signed_num: i32) -> Vec<i32> {
+        // Returns the signed numbers of the unitig strands which lead to the given unitig strand.
+        let unitig = self.unitig(signed_num.unsigned_abs());
+        let prev = if signed_num > 0 { &unitig.forward_prev } else { &unitig.reverse_prev };
+        prev.iter().map(|u| self.signed_number(u)).collect()
+    }
+
+    pub fn shortest_path(&self, start: i32, end: i32, max_len: Option<u32>) -> Option<Vec<i32>> {
+        // Finds the shortest path (in bp, including the start and end unitigs) from one unitig
+        // strand to another using Dijkstra's algorithm. Returns None if there is no such path or
+        // if all such paths are longer than max_len.
+        let unitig_length = |n: i32| self.unitig(n.unsigned_abs()).length();
+        if !self.unitig_index.contains_key(&start.unsigned_abs()) ||
+           !self.unitig_index.contains_key(&end.unsigned_abs()) {
+            return None;
+        }
+        let max_len = max_len.unwrap_or(u32::MAX);
+        let start_len = unitig_length(start);
+        if start_len > max_len { return None; }
+        if start == end { return Some(vec![start]); }
+
+        let mut distances: HashMap<i32, u32> = HashMap::from([(start, start_len)]);
+        let mut previous: HashMap<i32, i32> = HashMap::new();
+        let mut queue = BinaryHeap::from([Reverse((start_len, start))]);
+        while let Some(Reverse((dist, current))) = queue.pop() {
+            if current == end {
+                let mut path = vec![end];
+                while let Some(&prev) = previous.get(path.last().unwrap()) {
+                    path.push(prev);
+                }
+                path.reverse();
+                return Some(path);
+            }
+            if dist > distances[&current] { continue; }
+            for next in self.outgoing_strands(current) {
+                let next_dist = dist.saturating_add(unitig_length(next));
+                if next_dist > max_len || next == start { continue; }
+                if distances.get(&next).map_or(true, |&d| next_dist < d) {
+                    distances.insert(next, next_dist);
+                    previous.insert(next, current);
+                    queue.push(Reverse((next_dist, next)));
+                }
+            }
+        }
+        None
+    }
+
+    pub fn simple_paths(&self, start: i32, end: i32, max_len: u32, max_paths: usize)
+            -> Vec<(Vec<i32>, Vec<u8>)> {
+        // Enumerates simple paths (no unitig strand used more than once) from one unitig strand to
+        // another, returning each path along with its sequence. Paths longer than max_len (in bp,
+        // including the start and end unitigs) are not followed, and the search stops once
+        // max_paths paths have been found. Paths are returned shortest first.
+        if !self.unitig_index.contains_key(&start.unsigned_abs()) ||
+           !self.unitig_index.contains_key(&end.unsigned_abs()) {
+            return Vec::new();
+        }
+        let mut paths = Vec::new();
+        let mut current_path = vec![start];
+        let start_len = self.unitig(start.unsigned_abs()).length();
+        if start_len <= max_len {
+            self.simple_paths_dfs(end, start_len, max_len, max_paths, &mut current_path,
+                                  &mut paths);
+        }
+        let mut paths: Vec<_> = paths.into_iter()
+            .map(|p| { let seq = self.get_sequence_from_path_signed(&p); (p, seq) }).collect();
+        paths.sort_by(|a, b| a.1.len().cmp(&b.1.len()).then_with(|| a.0.cmp(&b.0)));
+        paths
+    }
+
+    fn simple_paths_dfs(&self, end: i32, length: u32, max_len: u32, max_paths: usize,
+                        current_path: &mut Vec<i32>, paths: &mut Vec<Vec<i32>>) {
+        let current = *current_path.last().unwrap();
+        if current == end {
+            paths.push(current_path.clone());
+            return;
+        }
+        for next in self.outgoing_strands(current) {
+            if paths.len() >= max_paths { return; }
+            if current_path.contains(&next) { continue; }
+            let next_len = length.saturating_add(self.unitig(next.unsigned_abs()).length());
+            if next_len > max_len { continue; }
+            current_path.push(next);
+            self.simple_paths_dfs(end, next_len, max_len, max_paths, current_path, paths);
+            current_path.pop();
+        }
+    }
 
-fn parse_unitig_path(path_str: &str) -> Vec<(u32, bool)> {
-    path_str.split(',')
-        .map(|u| {
-            let strand = if u.ends_with('+') { strand::FORWARD } else if u.ends_with('-') { strand::REVERSE }
-                         else { panic!("Invalid path strand") };
-            let num = u[..u.len() - 1].parse::<u32>().expect("Error parsing unitig number");
-            (num, strand)
+    pub fn find_tips(&self, max_len: u32) -> Vec<u32> {
+        // This method returns the numbers of tip unitigs: unitigs no longer than max_len which
+        // are connected to other unitigs on one side but are a dead end on the other side.
+        self.unitigs.iter().enumerate().filter_map(|(h, u)| {
+            if u.length() > max_len { return None; }
+            let start_links: Vec<usize> = u.forward_prev.iter().map(|p| p.unitig).collect();
+            let end_links: Vec<usize> = u.forward_next.iter().map(|n| n.unitig).collect();
+            if start_links.contains(&h) || end_links.contains(&h) { return None; }
+            if start_links.is_empty() != end_links.is_empty() { Some(u.number) } else { None }
         }).collect()
+    }
+
+    pub fn trim_tips(&mut self, sequences: &[Sequence], max_len: u32, min_depth: f64)
+            -> Vec<Sequence> {
+        // This method removes tips (see find_tips) with a depth below min_depth. Since tips are
+        // dead ends, any sequence path using one must start or end there, so those sequences are
+        // truncated (or dropped entirely if their whole path is in tips). The returned sequences
+        // reflect those changes.
+        let tips: HashSet<u32> = self.find_tips(max_len).into_iter()
+            .filter(|&n| self.unitig(n).depth < min_depth).collect();
+        self.remove_unitigs_and_split_paths(sequences, tips)
+    }
+
+    pub fn remove_low_depth_unitigs(&mut self, sequences: &[Sequence], min_depth: f64)
+            -> Vec<Sequence> {
+        // This method removes all unitigs with a depth below min_depth, splitting the sequence
+        // paths which used them (see remove_unitigs_and_split_paths).
+        let to_remove: HashSet<u32> = self.unitigs.iter()
+            .filter(|u| u.depth < min_depth).map(|u| u.number).collect();
+        self.remove_unitigs_and_split_paths(sequences, to_remove)
+    }
+
+    pub fn remove_unitigs_and_split_paths(&mut self, sequences: &[Sequence],
+                                          to_remove: HashSet<u32>) -> Vec<Sequence> {
+        // This method removes the given unitigs and repairs the sequence paths which used them by
+        // splitting them at the removed unitigs (see split_sequence_paths).
+        if to_remove.is_empty() {
+            return sequences.to_vec();
+        }
+        let new_sequences = self.split_sequence_paths(sequences, |path| {
+            path.split(|(n, _)| to_remove.contains(n)).filter(|p| !p.is_empty())
+                .map(|p| p.to_vec()).collect()
+        });
+        self.remove_unitigs_by_number(to_remove);
+        new_sequences
+    }
+
+    pub fn split_sequence_paths<F>(&mut self, sequences: &[Sequence], split: F) -> Vec<Sequence>
+            where F: Fn(&[(u32, bool)]) -> Vec<Vec<(u32, bool)>> {
+        // This method gives each sequence path to the split function, which returns the pieces
+        // that should replace it. Each piece becomes its own truncated sequence (so a sequence
+        // with no pieces is dropped). The first piece keeps the original sequence ID, and any
+        // others get new IDs and a numbered suffix on their contig name (and on their original
+        // name, if renamed). All pieces keep the sequence's line width. The returned sequences
+        // reflect those changes.
+        let mut next_id = sequences.iter().map(|s| s.id).max().unwrap_or(0) as usize + 1;
+        let mut new_sequences = Vec::new();
+        let mut any_changed = false;
+        for (seq, path) in sequences.iter().zip(self.get_unitig_paths_for_sequences(sequences)) {
+            let pieces = split(&path);
+            if pieces.len() == 1 && pieces[0] == path {
+                new_sequences.push(seq.clone());
+                continue;
+            }
+            any_changed = true;
+            let mut start = 0;
+            let mut piece_repairs = Vec::new();
+            for piece in &pieces {
+                // Pieces are consecutive stretches of the path, so each is found after the last.
+                start += path[start..].windows(piece.len()).position(|w| w == piece).unwrap();
+                piece_repairs.push(self.get_piece_repairs(seq, &path, start..start + piece.len()));
+                start += piece.len();
+            }
+            self.remove_sequence_from_graph(seq.id);
+            self.seq_repairs.remove(&seq.id);
+            for (i, (piece, (repairs, length))) in pieces.into_iter().zip(piece_repairs)
+                                                         .enumerate() {
+                let (id, header, original_name) = if i == 0 {
+                    (seq.id, seq.contig_header.clone(), seq.original_name.clone())
+                } else {
+                    if next_id > u16::MAX as usize {
+                        quit_with_error("too many sequences after splitting paths");
+                    }
+                    let id = next_id as u16;
+                    next_id += 1;
+                    if seq.weight != 1.0 {
+                        self.seq_weights.insert(id, seq.weight);
+                    }
+                    if !seq.duplicates.is_empty() {
+                        let duplicates = seq.duplicates.iter().map(|d| Duplicate {
+                            contig_header: split_piece_header(&d.contig_header, i + 1),
+                            ..d.clone() }).collect();
+                        self.seq_duplicates.insert(id, duplicates);
+                    }
+                    (id, split_piece_header(&seq.contig_header, i + 1),
+                     seq.original_name.as_ref().map(|n| split_piece_header(n, i + 1)))
+                };
+                if !repairs.is_empty() {
+                    self.seq_repairs.insert(id, repairs);
+                }
+                let mut new_seq = self.create_sequence_and_positions(
+                    id, length, seq.filename.clone(), header, seq.cluster, piece);
+                new_seq.original_name = original_name;
+                new_seq.line_width = seq.line_width;
+                new_sequences.push(new_seq);
+            }
+        }
+        if any_changed {
+            self.recalculate_depths();
+        }
+        new_sequences
+    }
+
+    pub fn find_bubbles(&self, sequences: &[Sequence]) -> Vec<Bubble> {
+        // This method finds simple bubbles: places where a unitig strand branches into two or more
+        // arms that all rejoin at the same unitig strand. Each arm is a chain of unitigs with one
+        // input and one output (or empty, if the start links directly to the end). Each bubble
+        // can be found from both strands, so only the orientation with the lower-numbered start
+        // is kept.
+        let seq_filenames: HashMap<u16, &str> = sequences.iter()
+            .map(|s| (s.id, s.filename.as_str())).collect();
+        let mut bubbles = Vec::new();
+        for unitig in &self.unitigs {
+            let num = unitig.number as i32;
+            for start in [num, -num] {
+                let mut arms_by_end: HashMap<i32, Vec<Vec<i32>>> = HashMap::new();
+                for next in self.outgoing_strands(start) {
+                    if let Some((arm, end)) = self.follow_bubble_arm(start, next) {
+                        arms_by_end.entry(end).or_default().push(arm);
+                    }
+                }
+                let mut ends: Vec<_> = arms_by_end.keys().copied().collect();
+                ends.sort_by_key(|e| (e.abs(), *e < 0));
+                for end in ends {
+                    let mut arms = arms_by_end.remove(&end).unwrap();
+                    if arms.len() < 2 || !bubble_orientation_is_canonical(start, end) { continue; }
+                    arms.sort();
+                    let arms = arms.into_iter().map(|path| {
+                        let seq = self.get_sequence_from_path_signed(&path);
+                        let assemblies = self.arm_assemblies(start, end, &path, &seq_filenames);
+                        BubbleArm { path, seq, assemblies }
+                    }).collect();
+                    bubbles.push(Bubble { start, end, arms });
+                }
+            }
+        }
+        bubbles
+    }
+
+    fn follow_bubble_arm(&self, start: i32, first: i32) -> Option<(Vec<i32>, i32)> {
+        // Starting at the first unitig strand after a bubble's start, this method follows a chain
+        // of single-input/single-output unitigs until it reaches a unitig strand with multiple
+        // inputs (the potential end of the bubble). Returns None if the chain dead-ends, branches
+        // or loops before that happens.
+        let mut arm = Vec::new();
+        let mut current = first;
+        loop {
+            if current.abs() == start.abs() || arm.iter().any(|&u: &i32| u.abs() == current.abs()) {
+                return None;
+            }
+            if self.incoming_strands(current).len() > 1 {
+                return Some((arm, current));
+            }
+            let outgoing = self.outgoing_strands(current);
+            if outgoing.len() != 1 { return None; }
+            arm.push(current);
+            current = outgoing[0];
+        }
+    }
+
+    fn arm_assemblies(&self, start: i32, end: i32, arm: &[i32],
+                      seq_filenames: &HashMap<u16, &str>) -> Vec<String> {
+        // Returns the input assemblies (filenames) with a sequence that passes through the given
+        // bubble arm. For an empty arm, the sequence must pass directly from start to end.
+        let positions_on = |signed_num: i32| -> Vec<Position> {
+            let u = self.unitig(signed_num.unsigned_abs());
+            if signed_num > 0 { u.forward_positions.clone() } else { u.reverse_positions.clone() }
+        };
+        let seq_ids: Vec<u16> = if let Some(&first) = arm.first() {
+            positions_on(first).iter().map(|p| p.seq_id()).collect()
+        } else {
+            let start_length = self.unitig(start.unsigned_abs()).length();
+            let end_positions: HashSet<Position> = positions_on(end).into_iter().collect();
+            positions_on(start).iter()
+                .filter(|p| end_positions.contains(&Position::new(p.seq_id(), p.strand(),
+                                                                  (p.pos + start_length) as usize)))
+                .map(|p| p.seq_id()).collect()
+        };
+        let mut assemblies: Vec<String> = seq_ids.iter()
+            .filter_map(|id| seq_filenames.get(id).map(|f| f.to_string())).collect();
+        assemblies.sort();
+        assemblies.dedup();
+        assemblies
+    }
+}
+
+
+fn split_piece_header(header: &str, piece_num: usize) -> String {
+    // Adds a piece number to the name part of a contig header, e.g. "contig_1 circular=true"
+    // becomes "contig_1_2 circular=true" for the second piece.
+    let description = after_first_space(header);
+    let name = format!("{}_{}", up_to_first_space(header), piece_num);
+    if description.is_empty() { name } else { format!("{} {}", name, description) }
+}
+
+
+#[derive(Clone, Copy)]
+struct TileKmer {
+    // Where a k-mer is found when tiling sequences: a unitig (or extra tile), the index of the
+    // k-mer in its forward sequence, and whether it's the reverse complement of that k-mer.
+    tile: usize,
+    index: usize,
+    reverse: bool,
+}
+
+impl TileKmer {
+    fn followed_by(&self, next: &TileKmer) -> bool {
+        // Returns true if the next k-mer of a sequence continues along the same unitig strand.
+        self.tile == next.tile && self.reverse == next.reverse
+            && if self.reverse { next.index + 1 == self.index }
+                              else { next.index == self.index + 1 }
+    }
+
+    fn entry_cut(&self) -> (usize, usize) {
+        // A sequence which starts or arrives at this k-mer needs a piece to start here.
+        (self.tile, if self.reverse { self.index + 1 } else { self.index })
+    }
+
+    fn exit_cut(&self) -> (usize, usize) {
+        // A sequence which ends or leaves after this k-mer needs a piece to end here.
+        (self.tile, if self.reverse { self.index } else { self.index + 1 })
+    }
+}
+
+
+#[derive(Debug, PartialEq)]
+pub struct Bubble {
+    pub start: i32,
+    pub end: i32,
+    pub arms: Vec<BubbleArm>,
+}
+
+
+#[derive(Debug, PartialEq)]
+pub struct BubbleArm {
+    pub path: Vec<i32>,
+    pub seq: Vec<u8>,
+    pub assemblies: Vec<String>,
+}
+
+
+#[derive(Debug, PartialEq)]
+pub enum GraphError {
+    MissingNextLink { from: i32, to: i32 },
+    MissingPrevLink { from: i32, to: i32 },
+    UnitigNotInIndex { unitig: u32 },
+}
+
+impl fmt::Display for GraphError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            GraphError::MissingNextLink { from, to } =>
+                write!(f, "link {} -> {} is missing from {}'s next links",
+                       sign_at_end(*from), sign_at_end(*to), sign_at_end(*from)),
+            GraphError::MissingPrevLink { from, to } =>
+                write!(f, "link {} -> {} is missing from {}'s prev links",
+                       sign_at_end(*from), sign_at_end(*to), sign_at_end(*to)),
+            GraphError::UnitigNotInIndex { unitig } =>
+                write!(f, "linked unitig {} is missing from the unitig index", unitig),
+        }
+    }
+}
+
+
+fn bubble_orientation_is_canonical(start: i32, end: i32) -> bool {
+    // A bubble from start to end is the same as a bubble from -end to -start. This function
+    // returns true for whichever of these begins with the lower unitig number (preferring the
+    // forward strand in a tie).
+    (start.abs(), start < 0) <= ((-end).abs(), -end < 0)
+}
+
+
+fn find(parents: &mut [usize], mut i: usize) -> usize {
+    // Union-find root lookup with path halving.
+    while parents[i] != i {
+        parents[i] = parents[parents[i]];
+        i = parents[i];
+    }
+    i
+}
+
+
+fn union(parents: &mut [usize], a: usize, b: usize) {
+    let a_root = find(parents, a);
+    let b_root = find(parents, b);
+    if a_root != b_root {
+        parents[a_root.max(b_root)] = a_root.min(b_root);
+    }
+}
+
+
+fn weight_tag(seq: &Sequence) -> String {
+    if seq.weight != 1.0 { format!("\tWT:f:{}", seq.weight) } else { "".to_string() }
+}
+
+
+fn checksum_tag(seq: &Sequence) -> String {
+    if let Some(checksum) = seq.checksum { format!("\tXH:Z:{:016x}", checksum) }
+                                    else { "".to_string() }
+}
+
+
+fn line_width_tag(seq: &Sequence) -> String {
+    if seq.line_width > 0 { format!("\tLW:i:{}", seq.line_width) } else { "".to_string() }
+}
+
+
+fn original_name_tag(seq: &Sequence) -> String {
+    match &seq.original_name { Some(name) => format!("\tON:Z:{}", name), None => "".to_string() }
+}
+
+
+fn duplicates_tags(seq: &Sequence) -> String {
+    // A sequence with collapsed duplicates gets its multiplicity (MU) and the duplicates (DU),
+    // each as a strand, filename and header. Commas, colons and percent signs in the filenames
+    // and headers are percent-encoded.
+    if seq.duplicates.is_empty() { return "".to_string(); }
+    let duplicates: Vec<String> = seq.duplicates.iter().map(|d| {
+        format!("{}{}:{}", if d.reverse { "-" } else { "+" }, encode_tag_field(&d.filename),
+                encode_tag_field(&d.contig_header))
+    }).collect();
+    format!("\tMU:i:{}\tDU:Z:{}", seq.multiplicity(), duplicates.join(","))
+}
+
+
+fn repairs_tag(repairs: Option<&Vec<Repair>>) -> String {
+    // A sequence's repairs (RP) are each given as a position and bases, e.g. 120:ACGT.
+    let Some(repairs) = repairs else { return "".to_string(); };
+    let repairs: Vec<String> = repairs.iter()
+        .map(|r| format!("{}:{}", r.pos, String::from_utf8_lossy(&r.seq))).collect();
+    format!("\tRP:Z:{}", repairs.join(","))
+}
+
+
+fn parse_repairs(tag_val: &str) -> Option<Vec<Repair>> {
+    tag_val.split(',').map(|r| {
+        let (pos, seq) = r.split_once(':')?;
+        if seq.is_empty() { return None; }
+        Some(Repair { pos: pos.parse().ok()?, seq: seq.as_bytes().to_vec() })
+    }).collect()
+}
+
+
+fn parse_duplicates(tag_val: &str) -> Option<Vec<Duplicate>> {
+    tag_val.split(',').map(|d| {
+        let reverse = match d.chars().next()? { '+' => false, '-' => true, _ => return None };
+        let (filename, header) = d[1..].split_once(':')?;
+        Some(Duplicate { filename: decode_tag_field(filename)?,
+                         contig_header: decode_tag_field(header)?, reverse })
+    }).collect()
+}
+
+
+fn encode_tag_field(s: &str) -> String {
+    s.replace('%', "%25").replace(',', "%2C").replace(':', "%3A")
+}
+
+
+fn decode_tag_field(s: &str) -> Option<String> {
+    let mut decoded = String::new();
+    let mut parts = s.split('%');
+    decoded.push_str(parts.next()?);
+    for part in parts {
+        let code = part.get(..2)?;
+        decoded.push(u8::from_str_radix(code, 16).ok()? as char);
+        decoded.push_str(&part[2..]);
+    }
+    Some(decoded)
+}
+
+
+fn get_gfa_path_line(seq: &Sequence, unitig_path: &[(u32, bool)],
+                     repairs: Option<&Vec<Repair>>) -> String {
+    let path_str: Vec<String> = unitig_path.iter()
+        .map(|(num, strand)| format!("{}{}", num, if *strand { "+" } else { "-" })).collect();
+    let path_str = path_str.join(",");
+    let cluster_tag = if seq.cluster > 0 {format!("\tCL:i:{}", seq.cluster)} else {"".to_string()};
+    format!("P\t{}\t{}\t*\tLN:i:{}\tFN:Z:{}\tHD:Z:{}{}{}{}{}{}{}{}",
+            seq.id, path_str, seq.length, seq.filename, seq.contig_header, cluster_tag,
+            weight_tag(seq), checksum_tag(seq), line_width_tag(seq), original_name_tag(seq),
+            duplicates_tags(seq), repairs_tag(repairs))
+}
+
+
+fn get_gfa2_path_line(seq: &Sequence, unitig_path: &[(u32, bool)],
+                      repairs: Option<&Vec<Repair>>) -> String {
+    let path_str: Vec<String> = unitig_path.iter()
+        .map(|(num, strand)| format!("{}{}", num, if *strand { "+" } else { "-" })).collect();
+    let path_str = path_str.join(" ");
+    let cluster_tag = if seq.cluster > 0 {format!("\tCL:i:{}", seq.cluster)} else {"".to_string()};
+    format!("O\tP{}\t{}\tLN:i:{}\tFN:Z:{}\tHD:Z:{}{}{}{}{}{}{}{}",
+            seq.id, path_str, seq.length, seq.filename, seq.contig_header, cluster_tag,
+            weight_tag(seq), checksum_tag(seq), line_width_tag(seq), original_name_tag(seq),
+            duplicates_tags(seq), repairs_tag(repairs))
+}
+
+
+struct PathLine {
+    seq_id: u16,
+    path: Vec<(u32, bool)>,
+    length: u32,
+    filename: String,
+    header: String,
+    cluster: u16,
+    weight: f64,
+    checksum: Option<u64>,
+    line_width: usize,
+    original_name: Option<String>,
+    duplicates: Vec<Duplicate>,
+    repairs: Vec<Repair>,
+}
+
+
+fn parse_path_line(line: &str) -> Result<PathLine, String> {
+    // Parses a GFA1 path line (P) or GFA2 group line (O), with errors naming the offending field.
+    let parts: Vec<&str> = line.split('\t').collect();
+    if parts.len() < 3 {
+        return Err("path line has too few fields".to_string());
+    }
+    // GFA2 group IDs share a namespace with segment IDs, so they have a P prefix.
+    let gfa2 = line.starts_with('O');
+    let seq_id = if gfa2 { parts[1].strip_prefix('P') } else { Some(parts[1]) }
+        .and_then(|id| id.parse::<u16>().ok())
+        .ok_or_else(|| format!("invalid sequence ID: {}", parts[1]))?;
+    let path = if gfa2 { parse_gfa2_unitig_path(parts[2])? }
+                  else { parse_unitig_path(parts[2])? };
+    let (mut length, mut filename, mut header) = (None, None, None);
+    let (mut cluster, mut weight, mut checksum, mut line_width) = (0, 1.0, None, 0);
+    let (mut original_name, mut duplicates, mut repairs) = (None, Vec::new(), Vec::new());
+    for p in &parts[2..] {
+        let bad_tag = || format!("invalid tag: {}", p);
+        if let Some(tag_val) = p.strip_prefix("LN:i:") {
+            length = Some(tag_val.parse::<u32>().map_err(|_| bad_tag())?);
+        } else if let Some(tag_val) = p.strip_prefix("FN:Z:") {
+            filename = Some(tag_val.to_string());
+        } else if let Some(tag_val) = p.strip_prefix("HD:Z:") {
+            header = Some(tag_val.to_string());
+        } else if let Some(tag_val) = p.strip_prefix("CL:i:") {
+            cluster = tag_val.parse::<u16>().map_err(|_| bad_tag())?;
+        } else if let Some(tag_val) = p.strip_prefix("WT:f:") {
+            weight = tag_val.parse::<f64>().map_err(|_| bad_tag())?;
+        } else if let Some(tag_val) = p.strip_prefix("XH:Z:") {
+            checksum = Some(u64::from_str_radix(tag_val, 16).map_err(|_| bad_tag())?);
+        } else if let Some(tag_val) = p.strip_prefix("LW:i:") {
+            line_width = tag_val.parse::<usize>().map_err(|_| bad_tag())?;
+        } else if let Some(tag_val) = p.strip_prefix("ON:Z:") {
+            original_name = Some(tag_val.to_string());
+        } else if let Some(tag_val) = p.strip_prefix("DU:Z:") {
+            duplicates = parse_duplicates(tag_val).ok_or_else(bad_tag)?;
+        } else if let Some(tag_val) = p.strip_prefix("RP:Z:") {
+            repairs = parse_repairs(tag_val).ok_or_else(bad_tag)?;
+        }
+    }
+    let missing: Vec<&str> = [("LN:i", length.is_none()), ("FN:Z", filename.is_none()),
+                              ("HD:Z", header.is_none())]
+        .iter().filter(|(_, m)| *m).map(|(t, _)| *t).collect();
+    if !missing.is_empty() {
+        return Err(format!("path for sequence {} is missing required tag: {}", seq_id,
+                           missing.join(", ")));
+    }
+    Ok(PathLine { seq_id, path, length: length.unwrap(), filename: filename.unwrap(),
+                  header: header.unwrap(), cluster, weight, checksum, line_width,
+                  original_name, duplicates, repairs })
+}
+
+
+fn parse_unitig_path(path_str: &str) -> Result<Vec<(u32, bool)>, String> {
+    path_str.split(',').map(|u| {
+        let strand = if u.ends_with('+') { strand::FORWARD }
+                else if u.ends_with('-') { strand::REVERSE }
+                else { return Err(format!("invalid unitig in path: {}", u)) };
+        let num = u[..u.len() - 1].parse::<u32>()
+            .map_err(|_| format!("invalid unitig in path: {}", u))?;
+        Ok((num, strand))
+    }).collect()
+}
+
+
+fn parse_gfa2_unitig_path(path_str: &str) -> Result<Vec<(u32, bool)>, String> {
+    // GFA2 group lines separate references with spaces instead of commas.
+    path_str.split(' ').map(parse_gfa2_reference).collect()
+}
+
+
+fn parse_gfa1_link_line(line: &str) -> Result<(u32, bool, u32, bool), String> {
+    let parts: Vec<&str> = line.split('\t').collect();
+    if parts.len() < 6 || parts[5] != "0M" {
+        return Err("non-zero overlap found on the GFA link line.\n\
+                    Are you sure this is an Autocycler-generated GFA file?".to_string());
+    }
+    let bad_link = || format!("could not parse link line: {}", line);
+    let parse_strand = |s: &str| match s {
+        "+" => Ok(strand::FORWARD),
+        "-" => Ok(strand::REVERSE),
+        _ => Err(bad_link()),
+    };
+    let seg_1 = parts[1].parse::<u32>().map_err(|_| bad_link())?;
+    let seg_2 = parts[3].parse::<u32>().map_err(|_| bad_link())?;
+    Ok((seg_1, parse_strand(parts[2])?, seg_2, parse_strand(parts[4])?))
+}
+
+
+fn parse_gfa2_edge_line(line: &str) -> Result<(u32, bool, u32, bool), String> {
+    // GFA2 edge lines give segment references with a trailing strand (e.g. 12+) and then the
+    // begin/end coordinates of the alignment on each segment. Autocycler only uses zero-length
+    // overlaps, so each begin must equal its end.
+    let parts: Vec<&str> = line.split('\t').collect();
+    if parts.len() < 8 || parts[4] != parts[5] || parts[6] != parts[7] {
+        return Err("non-zero overlap found on the GFA edge line.\n\
+                    Are you sure this is an Autocycler-generated GFA file?".to_string());
+    }
+    let (seg_1, strand_1) = parse_gfa2_reference(parts[2])?;
+    let (seg_2, strand_2) = parse_gfa2_reference(parts[3])?;
+    Ok((seg_1, strand_1, seg_2, strand_2))
+}
+
+
+fn parse_gfa2_reference(reference: &str) -> Result<(u32, bool), String> {
+    let bad_reference = || format!("could not parse segment reference: {}", reference);
+    let strand = match reference.chars().last() {
+        Some('+') => strand::FORWARD,
+        Some('-') => strand::REVERSE,
+        _ => return Err(bad_reference()),
+    };
+    let num = reference[..reference.len()-1].parse::<u32>().map_err(|_| bad_reference())?;
+    Ok((num, strand))
 }
 
 
@@ -850,59 +2240,612 @@ mod tests {
     #[test]
     fn test_graph_stats() {
         let (graph, _) = UnitigGraph::from_gfa_lines(&get_test_gfa_1());
-        graph.check_links();
+        graph.check_links().unwrap();
         assert_eq!(graph.k_size, 9);
         assert_eq!(graph.unitigs.len(), 10);
         assert_eq!(graph.total_length(), 92);
         assert_eq!(graph.link_count(), (21, 11));
 
         let (graph, _) = UnitigGraph::from_gfa_lines(&get_test_gfa_2());
-        graph.check_links();
+        graph.check_links().unwrap();
         assert_eq!(graph.k_size, 9);
         assert_eq!(graph.unitigs.len(), 3);
         assert_eq!(graph.total_length(), 31);
         assert_eq!(graph.link_count(), (8, 4));
 
         let (graph, _) = UnitigGraph::from_gfa_lines(&get_test_gfa_3());
-        graph.check_links();
+        graph.check_links().unwrap();
         assert_eq!(graph.k_size, 9);
         assert_eq!(graph.unitigs.len(), 7);
         assert_eq!(graph.total_length(), 85);
         assert_eq!(graph.link_count(), (15, 8));
 
         let (graph, _) = UnitigGraph::from_gfa_lines(&get_test_gfa_4());
-        graph.check_links();
+        graph.check_links().unwrap();
         assert_eq!(graph.k_size, 3);
         assert_eq!(graph.unitigs.len(), 5);
         assert_eq!(graph.total_length(), 43);
         assert_eq!(graph.link_count(), (10, 5));
 
         let (graph, _) = UnitigGraph::from_gfa_lines(&get_test_gfa_5());
-        graph.check_links();
+        graph.check_links().unwrap();
         assert_eq!(graph.k_size, 3);
         assert_eq!(graph.unitigs.len(), 6);
         assert_eq!(graph.total_length(), 60);
         assert_eq!(graph.link_count(), (8, 4));
 
         let (graph, _) = UnitigGraph::from_gfa_lines(&get_test_gfa_6());
-        graph.check_links();
+        graph.check_links().unwrap();
         assert_eq!(graph.k_size, 3);
         assert_eq!(graph.unitigs.len(), 2);
         assert_eq!(graph.total_length(), 34);
         assert_eq!(graph.link_count(), (2, 1));
 
         let (graph, _) = UnitigGraph::from_gfa_lines(&get_test_gfa_7());
-        graph.check_links();
+        graph.check_links().unwrap();
         assert_eq!(graph.k_size, 3);
         assert_eq!(graph.unitigs.len(), 2);
         assert_eq!(graph.total_length(), 34);
         assert_eq!(graph.link_count(), (2, 1));
     }
 
+    #[test]
+    fn test_gfa2_edge_lines() {
+        let (graph, _) = UnitigGraph::from_gfa_lines(&get_test_gfa_2());
+        let edges = graph.get_gfa2_edge_lines(0);
+        assert_eq!(edges.len(), 4);
+        assert!(edges.contains(&"E\t*\t1+\t2+\t22$\t22$\t0\t0\t0M".to_string()));
+        assert!(edges.contains(&"E\t*\t1+\t2-\t22$\t22$\t5$\t5$\t0M".to_string()));
+        assert!(edges.contains(&"E\t*\t1-\t3+\t0\t0\t0\t0\t0M".to_string()));
+        assert!(!edges.contains(&"E\t*\t3-\t1+\t0\t0\t0\t0\t0M".to_string()));
+        let edges = graph.get_gfa2_edge_lines(10);
+        assert!(edges.contains(&"E\t*\t11+\t12+\t22$\t22$\t0\t0\t0M".to_string()));
+    }
+
+    #[test]
+    fn test_gfa2_round_trip() {
+        let (graph, sequences) = UnitigGraph::from_gfa_lines(&get_test_gfa_14());
+        let temp_dir = tempfile::tempdir().unwrap();
+        let gfa1_a = temp_dir.path().join("a.gfa");
+        let gfa1_b = temp_dir.path().join("b.gfa");
+        let gfa2 = temp_dir.path().join("c.gfa");
+        graph.save_gfa(&gfa1_a, &sequences).unwrap();
+        graph.save_gfa_with_version(&gfa2, &sequences, 2).unwrap();
+        let gfa2_lines = load_file_lines(&gfa2);
+        assert_eq!(gfa2_lines[0], "H\tVN:Z:2.0\tKM:i:13");
+        assert!(gfa2_lines.iter().any(|l| l.starts_with("O\tP2\t8+ 22+ 17- 27+ ")));
+        let (graph, sequences) = UnitigGraph::from_gfa_file(&gfa2);
+        assert_eq!(sequences.len(), 3);
+        graph.save_gfa(&gfa1_b, &sequences).unwrap();
+        assert_eq!(load_file_lines(&gfa1_a), load_file_lines(&gfa1_b));
+    }
+
+    #[test]
+    fn test_assembly_count_round_trip() {
+        let (mut graph, sequences) = UnitigGraph::from_gfa_lines(&get_test_gfa_14());
+        assert_eq!(graph.assembly_count, None);
+        graph.assembly_count = Some(5);
+        let lines = graph.gfa_lines(&sequences);
+        assert_eq!(lines[0], "H\tVN:Z:1.0\tKM:i:13\tAC:i:5");
+        assert_eq!(UnitigGraph::from_gfa_lines(&lines).0.assembly_count, Some(5));
+        let temp_dir = tempfile::tempdir().unwrap();
+        let gfa2 = temp_dir.path().join("a.gfa");
+        graph.save_gfa2(&gfa2, &sequences).unwrap();
+        assert_eq!(UnitigGraph::from_gfa_file(&gfa2).0.assembly_count, Some(5));
+    }
+
+    #[test]
+    fn test_original_name_round_trip() {
+        // A renamed contig's original name is stored on its path line, so reconstruction gives
+        // the original header.
+        let (graph, mut sequences) = UnitigGraph::from_gfa_lines(&get_test_gfa_14());
+        let name = sequences[1].contig_name();
+        sequences[1].contig_header = format!("{}_2", name);
+        sequences[1].original_name = Some(name.clone());
+        let temp_dir = tempfile::tempdir().unwrap();
+        for gfa_version in [1, 2] {
+            let gfa = temp_dir.path().join("graph.gfa");
+            graph.save_gfa_with_version(&gfa, &sequences, gfa_version).unwrap();
+            assert!(load_file_lines(&gfa).iter().any(|l| l.ends_with(&format!("\tON:Z:{}", name))));
+            let (graph, sequences) = UnitigGraph::from_gfa_file(&gfa);
+            assert_eq!(sequences[1].original_name, Some(name.clone()));
+            let reconstructed = graph.reconstruct_original_sequences(&sequences);
+            assert!(reconstructed[&sequences[1].filename].iter().any(|(h, _)| *h == name));
+        }
+    }
+
+    #[test]
+    fn test_duplicates_round_trip() {
+        // Collapsed duplicates are stored on their sequence's path line, so they still count
+        // towards depth and are reconstructed (on the right strand) from the saved graph, even
+        // for an assembly which only had duplicates.
+        let (mut graph, mut sequences) = UnitigGraph::from_gfa_lines(&get_test_gfa_14());
+        let depths: Vec<f64> = graph.unitigs.iter().map(|u| u.depth).collect();
+        sequences[0].duplicates = vec![Duplicate { filename: "x,y.fasta".to_string(),
+                                                   contig_header: "c:1 a%b".to_string(),
+                                                   reverse: true }];
+        sequences[0].weight = 2.0;
+        graph.set_sequence_weights(&sequences);
+        let temp_dir = tempfile::tempdir().unwrap();
+        let gfa = temp_dir.path().join("graph.gfa");
+        graph.save_gfa(&gfa, &sequences).unwrap();
+        assert!(load_file_lines(&gfa).iter()
+            .any(|l| l.ends_with("\tMU:i:2\tDU:Z:-x%2Cy.fasta:c%3A1 a%25b")));
+
+        let (graph, sequences) = UnitigGraph::from_gfa_file(&gfa);
+        assert_eq!(sequences[0].multiplicity(), 2);
+        assert_eq!(sequences[0].duplicates[0].filename, "x,y.fasta");
+        let new_depths: Vec<f64> = graph.unitigs.iter().map(|u| u.depth).collect();
+        assert!(new_depths.iter().zip(&depths).all(|(new, old)| new >= old));
+        assert!(new_depths.iter().sum::<f64>() > depths.iter().sum::<f64>());
+        let reconstructed = graph.reconstruct_original_sequences(&sequences);
+        let (header, seq) = &reconstructed["x,y.fasta"][0];
+        let (_, original) = reconstructed[&sequences[0].filename].iter()
+            .find(|(h, _)| *h == sequences[0].contig_header).unwrap();
+        assert_eq!(header, "c:1 a%b");
+        assert_eq!(seq.as_bytes(), reverse_complement(original.as_bytes()));
+    }
+
+    #[test]
+    fn test_gzipped_gfa_round_trip() {
+        let (graph, sequences) = UnitigGraph::from_gfa_lines(&get_test_gfa_14());
+        let temp_dir = tempfile::tempdir().unwrap();
+        let gfa = temp_dir.path().join("graph.gfa");
+        let gfa_gz = temp_dir.path().join("graph.gfa.gz");
+        graph.save_gfa(&gfa, &sequences).unwrap();
+        graph.save_gfa(&gfa_gz, &sequences).unwrap();
+        let mut magic = [0u8; 2];
+        std::io::Read::read_exact(&mut std::fs::File::open(&gfa_gz).unwrap(), &mut magic).unwrap();
+        assert_eq!(magic, [31, 139]);
+        assert_eq!(load_file_lines(&gfa), load_file_lines(&gfa_gz));
+        let (graph, sequences) = UnitigGraph::from_gfa_file(&gfa_gz);
+        assert_eq!(graph.unitigs.len(), 13);
+        assert_eq!(sequences.len(), 3);
+    }
+
+    #[test]
+    fn test_save_dot_and_graphml() {
+        let (graph, _) = UnitigGraph::from_gfa_lines(&get_test_gfa_2());
+        let temp_dir = tempfile::tempdir().unwrap();
+        let dot = temp_dir.path().join("graph.dot");
+        let graphml = temp_dir.path().join("graph.graphml");
+        graph.save_dot(&dot).unwrap();
+        graph.save_graphml(&graphml).unwrap();
+
+        let dot_lines = load_file_lines(&dot);
+        assert_eq!(dot_lines.len(), 16);
+        assert_eq!(dot_lines[0], "digraph autocycler {");
+        assert!(dot_lines.contains(&"    \"1+\" [unitig=1, strand=\"+\", length=22, depth=1.00];".to_string()));
+        assert!(dot_lines.contains(&"    \"2-\" [unitig=2, strand=\"-\", length=5, depth=1.00];".to_string()));
+        assert!(dot_lines.contains(&"    \"1+\" -> \"2-\";".to_string()));
+        assert!(dot_lines.contains(&"    \"3+\" -> \"1+\";".to_string()));
+        assert_eq!(dot_lines[15], "}");
+
+        let graphml_lines = load_file_lines(&graphml);
+        assert_eq!(graphml_lines.iter().filter(|l| l.contains("<node ")).count(), 6);
+        assert_eq!(graphml_lines.iter().filter(|l| l.contains("<edge ")).count(), 8);
+        assert!(graphml_lines.contains(&"    <edge source=\"1-\" target=\"3+\"/>".to_string()));
+        assert!(graphml_lines.contains(&"      <data key=\"length\">4</data>".to_string()));
+        assert_eq!(graphml_lines.last().unwrap(), "</graphml>");
+    }
+
+    #[test]
+    fn test_save_unitig_fasta() {
+        let (graph, sequences) = UnitigGraph::from_gfa_lines(&get_test_gfa_14());
+        let temp_dir = tempfile::tempdir().unwrap();
+        let fasta = temp_dir.path().join("unitigs.fasta");
+        graph.save_unitig_fasta(&fasta, &sequences).unwrap();
+        let lines = load_file_lines(&fasta);
+        assert_eq!(lines.len(), 26);
+        assert_eq!(lines[0], ">5 length=21 depth=4.00 cluster=2");
+        assert_eq!(lines[1], "TGCTCAAAGCCTCGTATTGAG");
+
+        // Without sequences, there is no cluster information.
+        graph.save_unitig_fasta(&fasta, &[]).unwrap();
+        let lines = load_file_lines(&fasta);
+        assert_eq!(lines[0], ">5 length=21 depth=4.00");
+    }
+
+    #[test]
+    fn test_gfa_lines() {
+        let (graph, sequences) = UnitigGraph::from_gfa_lines(&get_test_gfa_15());
+        let lines = graph.gfa_lines(&sequences);
+        assert_eq!(lines[0], "H\tVN:Z:1.0\tKM:i:3");
+        assert_eq!(lines.iter().filter(|l| l.starts_with('P')).count(), 3);
+        let (graph_2, sequences_2) = UnitigGraph::from_gfa_lines(&lines);
+        assert_eq!(graph_2.gfa_lines(&sequences_2), lines);
+    }
+
+    #[test]
+    fn test_find_bubbles() {
+        let (graph, sequences) = UnitigGraph::from_gfa_lines(&get_test_gfa_15());
+        let bubbles = graph.find_bubbles(&sequences);
+        assert_eq!(bubbles.len(), 2);
+
+        assert_eq!(bubbles[0].start, 1);
+        assert_eq!(bubbles[0].end, 4);
+        assert_eq!(bubbles[0].arms.len(), 2);
+        assert_eq!(bubbles[0].arms[0].path, vec![2]);
+        assert_eq!(bubbles[0].arms[0].seq, b"TT".to_vec());
+        assert_eq!(bubbles[0].arms[0].assemblies, vec!["a.fasta", "c.fasta"]);
+        assert_eq!(bubbles[0].arms[1].path, vec![3]);
+        assert_eq!(bubbles[0].arms[1].seq, b"CCC".to_vec());
+        assert_eq!(bubbles[0].arms[1].assemblies, vec!["b.fasta"]);
+
+        assert_eq!(bubbles[1].start, 4);
+        assert_eq!(bubbles[1].end, 6);
+        assert_eq!(bubbles[1].arms[0].path, Vec::<i32>::new());
+        assert_eq!(bubbles[1].arms[0].seq, Vec::<u8>::new());
+        assert_eq!(bubbles[1].arms[0].assemblies, vec!["b.fasta", "c.fasta"]);
+        assert_eq!(bubbles[1].arms[1].path, vec![5]);
+        assert_eq!(bubbles[1].arms[1].assemblies, vec!["a.fasta"]);
+
+        // Graphs without bubbles.
+        let (graph, sequences) = UnitigGraph::from_gfa_lines(&get_test_gfa_9());
+        assert!(graph.find_bubbles(&sequences).is_empty());
+        let (graph, sequences) = UnitigGraph::from_gfa_lines(&get_test_gfa_6());
+        assert!(graph.find_bubbles(&sequences).is_empty());
+    }
+
+    #[test]
+    fn test_bubble_orientation_is_canonical() {
+        assert!(bubble_orientation_is_canonical(1, 4));
+        assert!(!bubble_orientation_is_canonical(-4, -1));
+        assert!(bubble_orientation_is_canonical(2, -2));
+        assert!(bubble_orientation_is_canonical(3, 3));
+        assert!(!bubble_orientation_is_canonical(-3, -3));
+    }
+
+    #[test]
+    fn test_find_tips() {
+        let (mut graph, _) = UnitigGraph::from_gfa_lines(&get_test_gfa_15());
+        assert_eq!(graph.find_tips(100), vec![1, 6]);
+        assert!(graph.find_tips(9).is_empty());
+        graph.delete_link(2, 4);
+        assert_eq!(graph.find_tips(100), vec![1, 2, 6]);
+        assert_eq!(graph.find_tips(9), vec![2]);
+        let (graph, _) = UnitigGraph::from_gfa_lines(&get_test_gfa_9());
+        assert!(graph.find_tips(100).is_empty());
+        let (graph, _) = UnitigGraph::from_gfa_lines(&get_test_gfa_11());
+        assert!(graph.find_tips(100).is_empty());
+    }
+
+    #[test]
+    fn test_merge_linear_paths_method() {
+        // Removing sequence a leaves unitig 5 with zero depth, after which unitigs 4 and 6 form a
+        // linear chain.
+        let (mut graph, mut sequences) = UnitigGraph::from_gfa_lines(&get_test_gfa_15());
+        let original = graph.reconstruct_original_sequences(&sequences);
+        graph.remove_sequence_from_graph(sequences[0].id);
+        sequences.remove(0);
+        graph.remove_zero_depth_unitigs();
+        assert_eq!(graph.unitigs.len(), 5);
+        graph.merge_linear_paths(&sequences);
+        assert_eq!(graph.unitigs.len(), 4);
+        assert_eq!(std::str::from_utf8(&graph.unitig(1).forward_seq).unwrap(),
+                   "GATCGATCGATACGTACGTA");
+        assert_eq!(graph.unitigs.iter().map(|u| u.number).collect::<Vec<_>>(),
+                   vec![1, 2, 3, 4]);
+        assert_eq!(graph.get_unitig_path_for_sequence_i32(&sequences[0]), vec![2, 3, 1]);
+        assert_eq!(graph.get_unitig_path_for_sequence_i32(&sequences[1]), vec![-1, -4, -2]);
+        let reconstructed = graph.reconstruct_original_sequences(&sequences);
+        assert_eq!(reconstructed["b.fasta"], original["b.fasta"]);
+        assert_eq!(reconstructed["c.fasta"], original["c.fasta"]);
+    }
+
+    #[test]
+    fn test_sequence_weights() {
+        let (mut graph, mut sequences) = UnitigGraph::from_gfa_lines(&get_test_gfa_15());
+        sequences[0].weight = 2.0;
+        sequences[2].weight = 0.5;
+        graph.set_sequence_weights(&sequences);
+        let depths = |g: &UnitigGraph| g.unitigs.iter().map(|u| u.depth).collect::<Vec<_>>();
+        assert_eq!(depths(&graph), vec![3.5, 2.5, 1.0, 3.5, 2.0, 3.5]);
+
+        // Weights are saved in path lines, so they still apply to a reloaded graph.
+        let temp_dir = tempfile::tempdir().unwrap();
+        let gfa = temp_dir.path().join("graph.gfa");
+        graph.save_gfa(&gfa, &sequences).unwrap();
+        let (mut graph, sequences) = UnitigGraph::from_gfa_file(&gfa);
+        assert_eq!(sequences.iter().map(|s| s.weight).collect::<Vec<_>>(), vec![2.0, 1.0, 0.5]);
+        assert_eq!(depths(&graph), vec![3.5, 2.5, 1.0, 3.5, 2.0, 3.5]);
+        graph.remove_sequence_from_graph(sequences[0].id);
+        assert_eq!(depths(&graph), vec![1.5, 0.5, 1.0, 1.5, 0.0, 1.5]);
+
+        // Without weights, depths are the number of sequences.
+        graph.set_sequence_weights(&[]);
+        assert_eq!(depths(&graph), vec![2.0, 1.0, 1.0, 2.0, 0.0, 2.0]);
+    }
+
+    #[test]
+    fn test_extract_subgraph() {
+        let (graph, sequences) = UnitigGraph::from_gfa_lines(&get_test_gfa_15());
+
+        // Only sequence b is entirely within unitigs 1, 3, 4 and 6.
+        let (subgraph, sub_seqs) = graph.extract_subgraph(&[1, 3, 4, 6], &sequences);
+        assert_eq!(subgraph.unitigs.len(), 4);
+        assert_eq!(sub_seqs.len(), 1);
+        assert_eq!(sub_seqs[0].filename, "b.fasta");
+        assert_eq!(subgraph.get_links_for_gfa(0).len(), 6);
+        assert_eq!(subgraph.get_unitig_path_for_sequence_i32(&sub_seqs[0]), vec![1, 3, 4, 6]);
+        for u in &subgraph.unitigs {
+            assert_eq!(u.depth, 1.0);
+        }
+        assert_eq!(graph.reconstruct_original_sequences(&sequences)["b.fasta"],
+                   subgraph.reconstruct_original_sequences(&sub_seqs)["b.fasta"]);
+
+        // The original graph is unchanged.
+        assert_eq!(graph.unitigs.len(), 6);
+        assert_eq!(graph.unitig(1).depth, 3.0);
+
+        // Unknown unitig numbers are ignored.
+        let (subgraph, sub_seqs) = graph.extract_subgraph(&[2, 100], &sequences);
+        assert_eq!(subgraph.unitigs.len(), 1);
+        assert!(sub_seqs.is_empty());
+        assert!(subgraph.get_links_for_gfa(0).is_empty());
+    }
+
+    #[test]
+    fn test_shortest_path() {
+        let (graph, _) = UnitigGraph::from_gfa_lines(&get_test_gfa_15());
+        assert_eq!(graph.shortest_path(1, 6, None), Some(vec![1, 2, 4, 6]));
+        assert_eq!(graph.shortest_path(-6, -1, None), Some(vec![-6, -4, -2, -1]));
+        assert_eq!(graph.shortest_path(3, 6, None), Some(vec![3, 4, 6]));
+        assert_eq!(graph.shortest_path(4, 4, None), Some(vec![4]));
+        assert_eq!(graph.shortest_path(1, 6, Some(32)), Some(vec![1, 2, 4, 6]));
+        assert_eq!(graph.shortest_path(1, 6, Some(31)), None);
+        assert_eq!(graph.shortest_path(6, 1, None), None);
+        assert_eq!(graph.shortest_path(1, -1, None), None);
+        assert_eq!(graph.shortest_path(1, 100, None), None);
+    }
+
+    #[test]
+    fn test_simple_paths() {
+        let (graph, _) = UnitigGraph::from_gfa_lines(&get_test_gfa_15());
+        let paths = graph.simple_paths(1, 6, 1000, 100);
+        assert_eq!(paths.iter().map(|p| p.0.clone()).collect::<Vec<_>>(),
+                   vec![vec![1, 2, 4, 6], vec![1, 3, 4, 6], vec![1, 2, 4, 5, 6], vec![1, 3, 4, 5, 6]]);
+        assert_eq!(paths.iter().map(|p| p.1.len()).collect::<Vec<_>>(), vec![32, 33, 36, 37]);
+        assert_eq!(std::str::from_utf8(&paths[0].1).unwrap(),
+                   "ACGACTACGATTGATCGATCGATACGTACGTA");
+        assert_eq!(graph.simple_paths(1, 6, 33, 100).len(), 2);
+        assert_eq!(graph.simple_paths(1, 6, 1000, 3).len(), 3);
+        assert_eq!(graph.simple_paths(-6, -1, 1000, 100).len(), 4);
+        assert!(graph.simple_paths(6, 1, 1000, 100).is_empty());
+        assert!(graph.simple_paths(1, 100, 1000, 100).is_empty());
+        assert_eq!(graph.simple_paths(4, 4, 1000, 100), vec![(vec![4], b"GATCGATCGA".to_vec())]);
+    }
+
+    #[test]
+    fn test_get_unitig_paths_for_sequences() {
+        let (graph, sequences) = UnitigGraph::from_gfa_lines(&get_test_gfa_14());
+        let paths = graph.get_unitig_paths_for_sequences(&sequences);
+        assert_eq!(paths.len(), sequences.len());
+        for (seq, path) in sequences.iter().zip(paths) {
+            assert_eq!(graph.get_unitig_path_for_sequence(seq), path);
+        }
+        let (graph, sequences) = UnitigGraph::from_gfa_lines(&get_test_gfa_15());
+        assert_eq!(graph.get_unitig_paths_for_sequences_i32(&sequences),
+                   vec![vec![1, 2, 4, 5, 6], vec![1, 3, 4, 6], vec![-6, -4, -2, -1]]);
+    }
+
+    #[test]
+    fn test_tile_sequences() {
+        // Two sequences share a variant at position 100, and one has another at position 103, so
+        // at a min count of 3 their removed k-mers are left out of the graph as repairs. Each k-mer
+        // must still end up in only one unitig, and each sequence's path (with its repairs) must
+        // spell out the sequence.
+        let k_size = 21;
+        let base = crate::tests::random_seq(300, 0).into_bytes();
+        let mutate = |seq: &mut Vec<u8>, i: usize| {
+            seq[i] = if seq[i] == b'A' { b'C' } else { b'A' };
+        };
+        let (mut variant_1, mut variant_2) = (base.clone(), base.clone());
+        mutate(&mut variant_1, 100);
+        mutate(&mut variant_2, 100);
+        mutate(&mut variant_2, 103);
+        let sequences: Vec<Sequence> = [&base, &base, &variant_1, &variant_2].iter().enumerate()
+            .map(|(i, seq)| Sequence::new_with_seq(i + 1, String::from_utf8(seq.to_vec()).unwrap(),
+                                                   "a.fasta".to_string(), format!("{}", i + 1),
+                                                   300, k_size / 2))
+            .collect();
+        let mut k_graph = KmerGraph::new(k_size, 1);
+        k_graph.add_sequences(&sequences, 4);
+        assert!(k_graph.remove_rare_kmers(3) > 0);
+        let mut u_graph = UnitigGraph { k_size, ..Default::default() };
+        u_graph.build_unitigs_from_kmer_graph(&k_graph);
+        u_graph.simplify_seqs();
+        let paths = u_graph.tile_sequences(&sequences);
+
+        let mut kmers = HashSet::new();
+        for unitig in &u_graph.unitigs {
+            let seq = &unitig.forward_seq;
+            for kmer in seq.windows(k_size as usize) {
+                let canonical = kmer.to_vec().min(reverse_complement(kmer));
+                assert!(kmers.insert(canonical));
+            }
+        }
+        assert_eq!(u_graph.seq_repairs.len(), 4);  // no k-mer at position 100 is in 3 sequences
+        let half_k = k_size as usize / 2;
+        for (seq, path) in sequences.iter().zip(&paths) {
+            // Each unitig gives its bases without the half-k overlaps at either end.
+            let mut repairs = u_graph.seq_repairs.get(&seq.id).into_iter().flatten().peekable();
+            let mut spelled = Vec::new();
+            for &(number, strand) in path {
+                while let Some(r) = repairs.next_if(|r| r.pos as usize == spelled.len()) {
+                    spelled.extend_from_slice(&r.seq);
+                }
+                let unitig = &u_graph.unitigs[number as usize - 1];
+                let unitig_seq = if strand { &unitig.forward_seq } else { &unitig.reverse_seq };
+                spelled.extend_from_slice(&unitig_seq[half_k..unitig_seq.len() - half_k]);
+            }
+            spelled.extend(repairs.flat_map(|r| r.seq.clone()));
+            assert_eq!(spelled, seq.forward_seq[half_k..half_k + seq.length]);
+        }
+    }
+
+    #[test]
+    fn test_check_links() {
+        let (mut graph, _) = UnitigGraph::from_gfa_lines(&get_test_gfa_15());
+        assert_eq!(graph.check_links(), Ok(()));
+        graph.delete_link_one_way(1, 2);
+        assert_eq!(graph.check_links(), Err(GraphError::MissingNextLink { from: 1, to: 2 }));
+        assert_eq!(graph.check_links().unwrap_err().to_string(),
+                   "link 1+ -> 2+ is missing from 1+'s next links");
+        graph.delete_link_one_way(-2, -1);
+        assert_eq!(graph.check_links(), Ok(()));
+
+        let (mut graph, _) = UnitigGraph::from_gfa_lines(&get_test_gfa_15());
+        graph.unitig_index.remove(&6);
+        assert_eq!(graph.check_links(), Err(GraphError::UnitigNotInIndex { unitig: 6 }));
+    }
+
+    #[test]
+    fn test_trim_tips() {
+        // Unitigs 1 and 6 are tips (at the ends of the linear structure).
+        let (mut graph, sequences) = UnitigGraph::from_gfa_lines(&get_test_gfa_15());
+        assert_eq!(graph.find_tips(100), vec![1, 6]);
+
+        // Nothing is trimmed if the depth threshold is too low.
+        let trimmed = graph.trim_tips(&sequences, 100, 3.0);
+        assert_eq!(graph.unitigs.len(), 6);
+        assert_eq!(trimmed.len(), 3);
+
+        // Trimming tips truncates the sequences which start/end in them.
+        let trimmed = graph.trim_tips(&sequences, 100, 4.0);
+        assert_eq!(graph.unitigs.len(), 4);
+        assert_eq!(graph.find_tips(100), vec![2, 3, 5]);
+        assert_eq!(trimmed.iter().map(|s| s.length).collect::<Vec<_>>(), vec![16, 13, 12]);
+        assert_eq!(graph.get_unitig_path_for_sequence_i32(&trimmed[0]), vec![2, 4, 5]);
+        assert_eq!(graph.get_unitig_path_for_sequence_i32(&trimmed[1]), vec![3, 4]);
+        assert_eq!(graph.get_unitig_path_for_sequence_i32(&trimmed[2]), vec![-4, -2]);
+        let reconstructed = graph.reconstruct_original_sequences(&trimmed);
+        assert_eq!(reconstructed["b.fasta"][0].1, "CCCGATCGATCGA");
+        for u in &graph.unitigs {
+            assert_eq!(u.depth, u.forward_positions.len() as f64);
+        }
+    }
+
+    #[test]
+    fn test_estimate_multiplicities() {
+        // Each sequence is from a different assembly, so every unitig used by two of the three
+        // gets a multiplicity of 1.
+        let (mut graph, sequences) = UnitigGraph::from_gfa_lines(&get_test_gfa_15());
+        graph.estimate_multiplicities(&sequences);
+        let multiplicities: Vec<_> = graph.unitigs.iter()
+            .map(|u| u.multiplicity.unwrap()).collect();
+        assert_eq!(multiplicities, vec![1, 1, 0, 1, 0, 1]);
+
+        // With all three sequences from one assembly, multiplicity is the traversal count.
+        let mut one_assembly = sequences.clone();
+        for s in &mut one_assembly { s.filename = "a.fasta".to_string(); }
+        graph.estimate_multiplicities(&one_assembly);
+        let multiplicities: Vec<_> = graph.unitigs.iter()
+            .map(|u| u.multiplicity.unwrap()).collect();
+        assert_eq!(multiplicities, vec![3, 2, 1, 3, 1, 3]);
+        assert_eq!(graph.unitigs[2].gfa_segment_line(), "S\t3\tCCC\tDP:f:1.00\tCN:i:1");
+
+        // A graph without sequences is left unchanged.
+        let (mut graph, _) = UnitigGraph::from_gfa_lines(&get_test_gfa_15());
+        graph.estimate_multiplicities(&[]);
+        assert!(graph.unitigs.iter().all(|u| u.multiplicity.is_none()));
+    }
+
+    #[test]
+    fn test_skipping_bad_paths() {
+        let mut gfa = get_test_gfa_15();
+        let n = gfa.len();
+        gfa[n - 3] = gfa[n - 3].replace("LN:i:36", "LN:i:35");
+        gfa[n - 2] = gfa[n - 2].replace("3+", "7+");
+        gfa.push("P\t3\t1+\t*\tLN:i:10\tFN:Z:d.fasta\tHD:Z:d".to_string());
+        let (_, sequences, problems) = UnitigGraph::load_gfa_lines(&gfa, true).unwrap();
+        assert_eq!(sequences.iter().map(|s| s.id).collect::<Vec<_>>(), vec![3]);
+        assert_eq!(problems,
+                   vec!["line 22: a.fasta a (sequence 1): path length (36 bp) does not match \
+                         sequence length (35 bp)".to_string(),
+                        "line 23: b.fasta b (sequence 2): unitig 7 is not in the graph"
+                            .to_string(),
+                        "line 25: d.fasta d (sequence 3): sequence ID 3 is used by more than \
+                         one path".to_string()]);
+    }
+
+    #[test]
+    fn test_path_line_errors() {
+        let error = |path_line: &str| {
+            let mut gfa = get_test_gfa_15();
+            gfa.push(path_line.to_string());
+            UnitigGraph::load_gfa_lines(&gfa, false).err().unwrap()
+        };
+        assert_eq!(error("P\tx\t1+\t*\tLN:i:10\tFN:Z:d.fasta\tHD:Z:d"),
+                   "line 25: invalid sequence ID: x");
+        assert_eq!(error("P\t4\t1+,2\t*\tLN:i:12\tFN:Z:d.fasta\tHD:Z:d"),
+                   "line 25: invalid unitig in path: 2");
+        assert_eq!(error("P\t4\t1+\t*\tLN:i:ten\tFN:Z:d.fasta\tHD:Z:d"),
+                   "line 25: invalid tag: LN:i:ten");
+        assert_eq!(error("P\t4\t1+\t*\tLN:i:10"),
+                   "line 25: path for sequence 4 is missing required tag: FN:Z, HD:Z");
+        assert_eq!(error("P\t4"), "line 25: path line has too few fields");
+        assert_eq!(error("P\t4\t1+\t*\tLN:i:11\tFN:Z:d.fasta\tHD:Z:d"),
+                   "line 25: d.fasta d (sequence 4): path length (10 bp) does not match \
+                    sequence length (11 bp)");
+        let mut gfa = get_test_gfa_15();
+        gfa.push("P\t4\t1+\t*\tLN:i:10\tFN:Z:d.fasta\tHD:Z:d".to_string());
+        assert!(UnitigGraph::load_gfa_lines(&gfa, false).is_ok());
+    }
+
+    #[test]
+    fn test_remove_low_depth_unitigs() {
+        // Unitigs 3 and 5 have a depth of 1, so sequence a (1,2,4,5,6) is split into two pieces,
+        // as is sequence b (1,3,4,6). Sequence a was renamed from x and wrapped at 60 bp, and its
+        // pieces keep both.
+        let (mut graph, mut sequences) = UnitigGraph::from_gfa_lines(&get_test_gfa_15());
+        sequences[0].original_name = Some("x".to_string());
+        sequences[0].line_width = 60;
+        let new_sequences = graph.remove_low_depth_unitigs(&sequences, 1.0);
+        assert_eq!(new_sequences.len(), 3);
+        let new_sequences = graph.remove_low_depth_unitigs(&sequences, 2.0);
+        assert_eq!(graph.unitigs.len(), 4);
+        assert!(graph.check_links().is_ok());
+        assert_eq!(new_sequences.iter().map(|s| s.id).collect::<Vec<_>>(), vec![1, 4, 2, 5, 3]);
+        assert_eq!(new_sequences.iter().map(|s| s.length).collect::<Vec<_>>(),
+                   vec![22, 10, 10, 20, 32]);
+        assert_eq!(graph.get_unitig_path_for_sequence_i32(&new_sequences[0]), vec![1, 2, 4]);
+        assert_eq!(graph.get_unitig_path_for_sequence_i32(&new_sequences[1]), vec![6]);
+        assert_eq!(graph.get_unitig_path_for_sequence_i32(&new_sequences[3]), vec![4, 6]);
+        assert_eq!(new_sequences[1].contig_header, "a_2");
+        assert_eq!(new_sequences[0].original_header(), "x");
+        assert_eq!(new_sequences[1].original_header(), "x_2");
+        assert_eq!(new_sequences[1].line_width, 60);
+        let reconstructed = graph.reconstruct_original_sequences(&new_sequences);
+        assert_eq!(reconstructed["b.fasta"][0], ("b".to_string(), "ACGACTACGA".to_string()));
+        assert_eq!(reconstructed["b.fasta"][1],
+                   ("b_2".to_string(), "GATCGATCGATACGTACGTA".to_string()));
+        for u in &graph.unitigs {
+            assert_eq!(u.depth, u.forward_positions.len() as f64);
+        }
+    }
+
+    #[test]
+    fn test_split_piece_header() {
+        assert_eq!(split_piece_header("contig_1", 2), "contig_1_2");
+        assert_eq!(split_piece_header("contig_1 circular=true", 3), "contig_1_3 circular=true");
+    }
+
+    #[test]
+    fn test_parse_gfa2_reference() {
+        assert_eq!(parse_gfa2_reference("12+"), Ok((12, strand::FORWARD)));
+        assert_eq!(parse_gfa2_reference("3-"), Ok((3, strand::REVERSE)));
+        assert_eq!(parse_gfa2_unitig_path("2+ 1-"), Ok(vec![(2, strand::FORWARD), (1, strand::REVERSE)]));
+        assert!(parse_gfa2_reference("12").is_err());
+        assert!(parse_gfa2_reference("x+").is_err());
+    }
+
     #[test]
     fn test_parse_unitig_path() {
-        assert_eq!(parse_unitig_path("2+,1-"), vec![(2, strand::FORWARD), (1, strand::REVERSE)]);
-        assert_eq!(parse_unitig_path("3+,8-,4-"), vec![(3, strand::FORWARD), (8, strand::REVERSE), (4, strand::REVERSE)]);
+        assert_eq!(parse_unitig_path("2+,1-"), Ok(vec![(2, strand::FORWARD), (1, strand::REVERSE)]));
+        assert_eq!(parse_unitig_path("3+,8-,4-"), Ok(vec![(3, strand::FORWARD), (8, strand::REVERSE), (4, strand::REVERSE)]));
+        assert_eq!(parse_unitig_path("3+,8,4-"), Err("invalid unitig in path: 8".to_string()));
+        assert_eq!(parse_unitig_path("3+,x-"), Err("invalid unitig in path: x-".to_string()));
     }
 
     #[test]
@@ -1137,8 +3080,12 @@ mod tests {
         let (graph, _) = UnitigGraph::from_gfa_lines(&get_test_gfa_3());
         assert_eq!(graph.connected_components(), vec![vec![1, 2, 3, 4, 5, 6, 7]]);
 
-        let (graph, _) = UnitigGraph::from_gfa_lines(&get_test_gfa_4());
+        let (mut graph, _) = UnitigGraph::from_gfa_lines(&get_test_gfa_4());
         assert_eq!(graph.connected_components(), vec![vec![1, 2, 3], vec![4, 5]]);
+        assert_eq!(graph.unitig(2).component, 0);
+        graph.assign_component_ids();
+        assert_eq!(graph.unitig(2).component, 1);
+        assert_eq!(graph.unitig(5).component, 2);
 
         let (graph, _) = UnitigGraph::from_gfa_lines(&get_test_gfa_5());
         assert_eq!(graph.connected_components(), vec![vec![1, 5], vec![2], vec![3, 6], vec![4]]);
@@ -1170,6 +3117,29 @@ mod tests {
         assert!(!graph.component_is_circular_loop(&[]));
     }
 
+    #[test]
+    fn test_has_consistent_dead_ends() {
+        let (mut graph, sequences) = UnitigGraph::from_gfa_lines(&get_test_gfa_15());
+        assert!(graph.has_consistent_dead_ends(&sequences));
+        assert!(!graph.has_consistent_dead_ends(&[]));
+        graph.create_link(6, 1);
+        assert!(!graph.has_consistent_dead_ends(&sequences));
+    }
+
+    #[test]
+    fn test_flag_inverted_repeats() {
+        let flags = |gfa: Vec<String>| {
+            let (mut graph, _) = UnitigGraph::from_gfa_lines(&gfa);
+            graph.flag_inverted_repeats(100);
+            graph.unitigs.iter().map(|u| u.inverted_repeat_tag()).collect::<Vec<_>>()
+        };
+        assert_eq!(flags(get_test_gfa_10()), vec!["\tIR:Z:start,end"]);
+        assert_eq!(flags(get_test_gfa_11()), vec!["\tIR:Z:end"]);
+        assert_eq!(flags(get_test_gfa_12()), vec!["\tIR:Z:start"]);
+        assert_eq!(flags(get_test_gfa_13()), vec!["\tIR:Z:start"]);
+        assert!(flags(get_test_gfa_4()).iter().all(|t| t.is_empty()));
+    }
+
     #[test]
     fn test_delete_link_break_into_components() {
         let (mut graph, _) = UnitigGraph::from_gfa_lines(&get_test_gfa_6());
@@ -1203,41 +3173,41 @@ mod tests {
     #[test]
     fn test_is_isolated_and_circular() {
         let (graph, _) = UnitigGraph::from_gfa_lines(&get_test_gfa_1());
-        for unitig in &graph.unitigs {
-            assert!(!unitig.borrow().is_isolated_and_circular());
+        for (handle, unitig) in graph.unitigs.iter().enumerate() {
+            assert!(!unitig.is_isolated_and_circular(handle));
         }
 
         let (graph, _) = UnitigGraph::from_gfa_lines(&get_test_gfa_2());
-        for unitig in &graph.unitigs {
-            assert!(!unitig.borrow().is_isolated_and_circular());
+        for (handle, unitig) in graph.unitigs.iter().enumerate() {
+            assert!(!unitig.is_isolated_and_circular(handle));
         }
 
         let (graph, _) = UnitigGraph::from_gfa_lines(&get_test_gfa_3());
-        for unitig in &graph.unitigs {
-            assert!(!unitig.borrow().is_isolated_and_circular());
+        for (handle, unitig) in graph.unitigs.iter().enumerate() {
+            assert!(!unitig.is_isolated_and_circular(handle));
         }
 
         let (graph, _) = UnitigGraph::from_gfa_lines(&get_test_gfa_4());
-        for unitig in &graph.unitigs {
-            assert!(!unitig.borrow().is_isolated_and_circular());
+        for (handle, unitig) in graph.unitigs.iter().enumerate() {
+            assert!(!unitig.is_isolated_and_circular(handle));
         }
 
         let (graph, _) = UnitigGraph::from_gfa_lines(&get_test_gfa_5());
-        assert!(!graph.unitig_index.get(&1).unwrap().borrow().is_isolated_and_circular());
-        assert!(!graph.unitig_index.get(&2).unwrap().borrow().is_isolated_and_circular());
-        assert!(!graph.unitig_index.get(&3).unwrap().borrow().is_isolated_and_circular());
-        assert!(graph.unitig_index.get(&4).unwrap().borrow().is_isolated_and_circular());
-        assert!(!graph.unitig_index.get(&5).unwrap().borrow().is_isolated_and_circular());
-        assert!(!graph.unitig_index.get(&6).unwrap().borrow().is_isolated_and_circular());
+        assert!(!graph.unitig(1).is_isolated_and_circular(0));
+        assert!(!graph.unitig(2).is_isolated_and_circular(1));
+        assert!(!graph.unitig(3).is_isolated_and_circular(2));
+        assert!(graph.unitig(4).is_isolated_and_circular(3));
+        assert!(!graph.unitig(5).is_isolated_and_circular(4));
+        assert!(!graph.unitig(6).is_isolated_and_circular(5));
 
         let (graph, _) = UnitigGraph::from_gfa_lines(&get_test_gfa_6());
-        for unitig in &graph.unitigs {
-            assert!(!unitig.borrow().is_isolated_and_circular());
+        for (handle, unitig) in graph.unitigs.iter().enumerate() {
+            assert!(!unitig.is_isolated_and_circular(handle));
         }
 
         let (graph, _) = UnitigGraph::from_gfa_lines(&get_test_gfa_7());
-        for unitig in &graph.unitigs {
-            assert!(!unitig.borrow().is_isolated_and_circular());
+        for (handle, unitig) in graph.unitigs.iter().enumerate() {
+            assert!(!unitig.is_isolated_and_circular(handle));
         }
     }
 
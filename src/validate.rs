@@ -0,0 +1,209 @@
+// This file contains the code for the autocycler validate subcommand.
+
+// Copyright 2024 Ryan Wick (rrwick@gmail.com)
+// https://github.com/rrwick/Autocycler
+
+// This file is part of Autocycler. Autocycler is free software: you can redistribute it and/or
+// modify it under the terms of the GNU General Public License as published by the Free Software
+// Foundation, either version 3 of the License, or (at your option) any later version. Autocycler
+// is distributed in the hope that it will be useful, but WITHOUT ANY WARRANTY; without even the
+// implied warranty of MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the GNU General
+// Public License for more details. You should have received a copy of the GNU General Public
+// License along with Autocycler. If not, see <http://www.gnu.org/licenses/>.
+
+use colored::Colorize;
+use std::fmt;
+use std::path::{Path, PathBuf};
+
+use crate::log::{section_header, explanation};
+use crate::misc::{check_if_file_exists, load_file_lines, quit_with_error, sign_at_end};
+use crate::unitig_graph::{GraphError, UnitigGraph};
+
+
+pub fn validate(in_gfa: PathBuf) {
+    check_if_file_exists(&in_gfa);
+    starting_message();
+    print_settings(&in_gfa);
+    let gfa_lines = load_file_lines(&in_gfa);
+    let problems = validate_gfa_lines(&gfa_lines);
+    print_problems(&problems);
+    if !problems.is_empty() {
+        quit_with_error(&format!("{} problem{} found in {}", problems.len(),
+                                 match problems.len() { 1 => "", _ => "s" }, in_gfa.display()));
+    }
+}
+
+
+fn starting_message() {
+    section_header("Starting autocycler validate");
+    explanation("This command checks a GFA file to make sure it can be used as an Autocycler unitig \
+                 graph: segment numbers are unique, links refer to existing segments and have \
+                 their reverse-complement counterparts, and paths follow links and match their \
+                 length tags.");
+}
+
+
+fn print_settings(in_gfa: &Path) {
+    eprintln!("Settings:");
+    eprintln!("  --in_gfa {}", in_gfa.display());
+    eprintln!();
+}
+
+
+fn print_problems(problems: &[ValidationError]) {
+    section_header("Results");
+    if problems.is_empty() {
+        eprintln!("{}", "No problems found".green());
+    }
+    for p in problems {
+        eprintln!("{}", p.to_string().red());
+    }
+    eprintln!();
+}
+
+
+#[derive(Debug, PartialEq)]
+pub enum ValidationError {
+    Unloadable(String),
+    InconsistentLinks(GraphError),
+    BadPath(String),
+    PathMissingLink { seq_id: u16, from: i32, to: i32 },
+}
+
+impl fmt::Display for ValidationError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ValidationError::Unloadable(e) =>
+                write!(f, "unable to load graph: {}", e),
+            ValidationError::InconsistentLinks(e) =>
+                write!(f, "inconsistent links: {}", e),
+            ValidationError::BadPath(e) =>
+                write!(f, "{}", e),
+            ValidationError::PathMissingLink { seq_id, from, to } =>
+                write!(f, "path {}: no link from {} to {}", seq_id, sign_at_end(*from),
+                       sign_at_end(*to)),
+        }
+    }
+}
+
+
+pub fn validate_gfa_lines(gfa_lines: &Vec<String>) -> Vec<ValidationError> {
+    // This function loads GFA lines as an Autocycler unitig graph and checks it. A problem which
+    // prevents the graph from loading is the only one reported, but otherwise all of the graph's
+    // problems are collected instead of quitting at the first one.
+    let (graph, sequences, path_problems) = match UnitigGraph::load_gfa_lines(gfa_lines, true) {
+        Ok(loaded) => loaded,
+        Err(e) => return vec![ValidationError::Unloadable(e)],
+    };
+    let mut problems = Vec::new();
+    if let Err(e) = graph.check_links() {
+        problems.push(ValidationError::InconsistentLinks(e));
+    }
+    problems.extend(path_problems.into_iter().map(ValidationError::BadPath));
+
+    // Loading only checks that each path's unitigs are in the graph, so this also checks that
+    // paths follow links. A 0 in a gapped path is one of the sequence's repairs, which isn't in
+    // the graph, so there is no link across it.
+    for (seq, path) in sequences.iter().zip(graph.get_gapped_unitig_paths_i32(&sequences)) {
+        for w in path.windows(2) {
+            let (from, to) = (w[0], w[1]);
+            if from == 0 || to == 0 {
+                continue;
+            }
+            if !graph.link_exists(from.unsigned_abs(), from > 0, to.unsigned_abs(), to > 0) {
+                problems.push(ValidationError::PathMissingLink { seq_id: seq.id, from, to });
+            }
+        }
+    }
+    problems
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_gfa::*;
+
+    fn to_lines(lines: &[&str]) -> Vec<String> {
+        lines.iter().map(|s| s.to_string()).collect()
+    }
+
+    #[test]
+    fn test_valid_gfas() {
+        assert!(validate_gfa_lines(&get_test_gfa_1()).is_empty());
+        assert!(validate_gfa_lines(&get_test_gfa_2()).is_empty());
+        assert!(validate_gfa_lines(&get_test_gfa_14()).is_empty());
+        let gfa = to_lines(&["H\tVN:Z:2.0\tKM:i:9",
+                             "S\t1\t4\tACGT\tDP:f:1.00",
+                             "S\t2\t3\tACG\tDP:f:1.00",
+                             "E\t*\t1+\t2+\t4$\t4$\t0\t0\t0M",
+                             "O\tP1\t2- 1-\tLN:i:7\tFN:Z:a.fasta\tHD:Z:a"]);
+        assert!(validate_gfa_lines(&gfa).is_empty());
+    }
+
+    #[test]
+    fn test_unloadable_gfas() {
+        let problem = |lines: &[&str]| {
+            let problems = validate_gfa_lines(&to_lines(lines));
+            assert_eq!(problems.len(), 1);
+            match &problems[0] {
+                ValidationError::Unloadable(e) => e.clone(),
+                p => panic!("unexpected problem: {}", p),
+            }
+        };
+        assert!(problem(&["H\tVN:Z:1.0", "S\t1\tACGT\tDP:f:1.00"])
+                    .starts_with("line 1: could not find a valid k-mer tag"));
+        assert_eq!(problem(&["H\tVN:Z:1.0\tKM:i:9", "S\t1\tACGT\tDP:f:1.00",
+                             "S\t1\tACGT\tDP:f:1.00"]),
+                   "line 3: segment 1 is defined more than once");
+        assert_eq!(problem(&["H\tVN:Z:1.0\tKM:i:9", "S\tx\tACGT\tDP:f:1.00"]),
+                   "line 2: Unable to parse unitig number.");
+        assert!(problem(&["H\tVN:Z:1.0\tKM:i:9", "S\t2\tACGT"])
+                    .starts_with("line 2: Could not find a depth tag"));
+        let links = |link: &str| problem(&["H\tVN:Z:1.0\tKM:i:9", "S\t1\tACGT\tDP:f:1.00",
+                                           "S\t2\tACGT\tDP:f:1.00", link]);
+        assert_eq!(links("L\t1\t+\t3\t+\t0M"), "line 4: link refers to nonexistent unitig: 3");
+        assert!(links("L\t2\t+\t1\t+\t5M").starts_with("line 4: non-zero overlap found"));
+        assert_eq!(links("L\t2\t?\t1\t+\t0M"),
+                   "line 4: could not parse link line: L\t2\t?\t1\t+\t0M");
+    }
+
+    #[test]
+    fn test_link_problems() {
+        let gfa = to_lines(&["H\tVN:Z:1.0\tKM:i:9",
+                             "S\t1\tACGT\tDP:f:1.00",
+                             "S\t2\tACGT\tDP:f:1.00",
+                             "L\t1\t+\t2\t+\t0M"]);
+        let problems = validate_gfa_lines(&gfa);
+        assert_eq!(problems,
+                   vec![ValidationError::InconsistentLinks(
+                            GraphError::MissingNextLink { from: -2, to: -1 })]);
+        assert_eq!(problems[0].to_string(),
+                   "inconsistent links: link 2- -> 1- is missing from 2-'s next links");
+    }
+
+    #[test]
+    fn test_path_problems() {
+        let gfa = to_lines(&["H\tVN:Z:1.0\tKM:i:9",
+                             "S\t1\tACGT\tDP:f:1.00",
+                             "S\t2\tACG\tDP:f:1.00",
+                             "L\t1\t+\t2\t+\t0M",
+                             "L\t2\t-\t1\t-\t0M",
+                             "P\t1\t1+,2+\t*\tLN:i:8\tFN:Z:a.fasta\tHD:Z:a",
+                             "P\t2\t2+,1+\t*\tLN:i:7\tFN:Z:a.fasta\tHD:Z:b",
+                             "P\t3\t1+,3+\t*\tLN:i:7\tFN:Z:a.fasta\tHD:Z:c",
+                             "P\t4\t1+,2+\t*\tFN:Z:a.fasta\tHD:Z:d",
+                             "P\t5\t1+,2*\t*\tLN:i:7\tFN:Z:a.fasta\tHD:Z:e"]);
+        let problems = validate_gfa_lines(&gfa);
+        assert_eq!(problems.len(), 5);
+        assert_eq!(problems[0].to_string(),
+                   "line 6: a.fasta a (sequence 1): path length (7 bp) does not match sequence \
+                    length (8 bp)");
+        assert_eq!(problems[1].to_string(),
+                   "line 8: a.fasta c (sequence 3): unitig 3 is not in the graph");
+        assert!(matches!(&problems[2], ValidationError::BadPath(e) if e.starts_with("line 9: ")));
+        assert!(matches!(&problems[3], ValidationError::BadPath(e) if e.starts_with("line 10: ")));
+        assert_eq!(problems[4], ValidationError::PathMissingLink { seq_id: 2, from: 2, to: 1 });
+        assert_eq!(problems[4].to_string(), "path 2: no link from 2+ to 1+");
+    }
+}
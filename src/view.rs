@@ -0,0 +1,301 @@
+// This file contains the code for the autocycler view subcommand.
+
+// Copyright 2024 Ryan Wick (rrwick@gmail.com)
+// https://github.com/rrwick/Autocycler
+
+// This file is part of Autocycler. Autocycler is free software: you can redistribute it and/or
+// modify it under the terms of the GNU General Public License as published by the Free Software
+// Foundation, either version 3 of the License, or (at your option) any later version. Autocycler
+// is distributed in the hope that it will be useful, but WITHOUT ANY WARRANTY; without even the
+// implied warranty of MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the GNU General
+// Public License for more details. You should have received a copy of the GNU General Public
+// License along with Autocycler. If not, see <http://www.gnu.org/licenses/>.
+
+use console::{Key, Term};
+use std::path::{Path, PathBuf};
+
+use crate::misc::{check_if_file_exists, format_float, quit_with_error, sign_at_end};
+use crate::sequence::Sequence;
+use crate::unitig_graph::UnitigGraph;
+
+
+// How many bases of a unitig's sequence are shown in the detail view.
+const SEQ_PREVIEW_LEN: usize = 60;
+
+
+pub fn view(in_gfa: PathBuf) {
+    check_settings(&in_gfa);
+    let term = Term::stdout();
+    if !term.is_term() {
+        quit_with_error("autocycler view must be run in an interactive terminal");
+    }
+    let (graph, sequences) = UnitigGraph::from_gfa_file(&in_gfa);
+    if graph.unitigs.is_empty() {
+        quit_with_error(&format!("{} contains no unitigs", in_gfa.display()));
+    }
+    let mut viewer = Viewer::new(&graph, &sequences, &in_gfa);
+    term.hide_cursor().ok();
+    loop {
+        let (rows, cols) = term.size();
+        term.clear_screen().ok();
+        for line in viewer.render(rows as usize, cols as usize) {
+            term.write_line(&line).ok();
+        }
+        let key = match term.read_key() {
+            Ok(key) => key,
+            Err(_) => break,
+        };
+        if !viewer.handle_key(key, rows as usize) {
+            break;
+        }
+    }
+    term.clear_screen().ok();
+    term.show_cursor().ok();
+}
+
+
+fn check_settings(in_gfa: &Path) {
+    check_if_file_exists(in_gfa);
+}
+
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum SortOrder {
+    Number,
+    Length,
+    Depth,
+}
+
+impl SortOrder {
+    fn next(self) -> Self {
+        match self {
+            SortOrder::Number => SortOrder::Length,
+            SortOrder::Length => SortOrder::Depth,
+            SortOrder::Depth  => SortOrder::Number,
+        }
+    }
+
+    fn name(self) -> &'static str {
+        match self {
+            SortOrder::Number => "number",
+            SortOrder::Length => "length",
+            SortOrder::Depth  => "depth",
+        }
+    }
+}
+
+
+struct Viewer<'a> {
+    graph: &'a UnitigGraph,
+    sequences: &'a [Sequence],
+    filename: String,
+    order: Vec<u32>,          // unitig numbers in the list, in the current sort order
+    sort: SortOrder,
+    cursor: usize,            // index into order
+    selected: Option<i32>,    // signed unitig number shown in the detail view
+    history: Vec<i32>,        // previously viewed unitig strands, for going back
+}
+
+impl<'a> Viewer<'a> {
+    fn new(graph: &'a UnitigGraph, sequences: &'a [Sequence], in_gfa: &Path) -> Self {
+        let mut viewer = Viewer { graph, sequences, filename: in_gfa.display().to_string(),
+                                  order: Vec::new(), sort: SortOrder::Number, cursor: 0,
+                                  selected: None, history: Vec::new() };
+        viewer.sort_unitigs();
+        viewer
+    }
+
+    fn sort_unitigs(&mut self) {
+        // Length and depth are sorted largest first, with unitig number breaking ties.
+        let mut unitigs: Vec<(u32, u32, f64)> = self.graph.unitigs.iter()
+            .map(|u| (u.number, u.length(), u.depth)).collect();
+        match self.sort {
+            SortOrder::Number => unitigs.sort_by_key(|u| u.0),
+            SortOrder::Length => unitigs.sort_by(|a, b| b.1.cmp(&a.1).then(a.0.cmp(&b.0))),
+            SortOrder::Depth  => unitigs.sort_by(|a, b| b.2.total_cmp(&a.2).then(a.0.cmp(&b.0))),
+        }
+        self.order = unitigs.into_iter().map(|u| u.0).collect();
+    }
+
+    fn handle_key(&mut self, key: Key, rows: usize) -> bool {
+        // Updates the view for a key press, returning false if the viewer should quit.
+        let page = rows.saturating_sub(4).max(1);
+        match (self.selected, key) {
+            (_, Key::Char('q')) => return false,
+            (None, Key::ArrowDown) | (None, Key::Char('j')) => self.move_cursor(1),
+            (None, Key::ArrowUp) | (None, Key::Char('k')) => self.move_cursor(-1),
+            (None, Key::PageDown) => self.move_cursor(page as isize),
+            (None, Key::PageUp) => self.move_cursor(-(page as isize)),
+            (None, Key::Char('s')) => {
+                let current = self.order[self.cursor];
+                self.sort = self.sort.next();
+                self.sort_unitigs();
+                self.cursor = self.order.iter().position(|&n| n == current).unwrap();
+            }
+            (None, Key::Enter) => self.selected = Some(self.order[self.cursor] as i32),
+            (Some(current), Key::Char(c)) if ('1'..='9').contains(&c) ||
+                                             ('a'..='i').contains(&c) => {
+                // Digits follow outgoing links and letters follow incoming links.
+                let (links, i) = if c.is_ascii_digit() {
+                    (self.graph.outgoing_strands(current), c as usize - '1' as usize)
+                } else {
+                    (self.graph.incoming_strands(current), c as usize - 'a' as usize)
+                };
+                if let Some(&next) = links.get(i) {
+                    self.history.push(current);
+                    self.selected = Some(next);
+                }
+            }
+            (Some(current), Key::Char('r')) => self.selected = Some(-current),
+            (Some(_), Key::Backspace) => {
+                self.selected = self.history.pop().or(self.selected);
+            }
+            (Some(current), Key::Escape) => {
+                self.cursor = self.order.iter().position(|&n| n == current.unsigned_abs()).unwrap();
+                self.selected = None;
+                self.history.clear();
+            }
+            _ => {}
+        }
+        true
+    }
+
+    fn move_cursor(&mut self, amount: isize) {
+        let max = self.order.len() as isize - 1;
+        self.cursor = (self.cursor as isize + amount).clamp(0, max) as usize;
+    }
+
+    fn render(&self, rows: usize, cols: usize) -> Vec<String> {
+        let lines = match self.selected {
+            None => self.render_list(rows),
+            Some(num) => self.render_detail(num),
+        };
+        lines.into_iter().take(rows.max(1)).map(|l| truncate(&l, cols)).collect()
+    }
+
+    fn render_list(&self, rows: usize) -> Vec<String> {
+        let mut lines = vec![format!("{}: {} unitigs, sorted by {}", self.filename,
+                                     self.order.len(), self.sort.name()),
+                             format!("  {:>8}  {:>10}  {:>8}", "unitig", "length", "depth")];
+        let visible = rows.saturating_sub(4).max(1);
+        let first = self.cursor.saturating_sub(visible - 1);
+        for (i, &num) in self.order.iter().enumerate().skip(first).take(visible) {
+            let u = self.graph.unitig(num);
+            let marker = if i == self.cursor { ">" } else { " " };
+            lines.push(format!("{} {:>8}  {:>10}  {:>8}", marker, num, u.length(),
+                               format_float(u.depth)));
+        }
+        lines.push(String::new());
+        lines.push("↑/↓ move, PgUp/PgDn page, s sort, Enter view unitig, q quit".to_string());
+        lines
+    }
+
+    fn render_detail(&self, num: i32) -> Vec<String> {
+        let u = self.graph.unitig(num.unsigned_abs());
+        let seq = u.get_seq(num > 0);
+        let preview: String = String::from_utf8_lossy(&seq[..seq.len().min(SEQ_PREVIEW_LEN)])
+            .to_string();
+        let ellipsis = if seq.len() > SEQ_PREVIEW_LEN { "..." } else { "" };
+        let mut lines = vec![format!("Unitig {}", sign_at_end(num)),
+                             format!("  length: {} bp", u.length()),
+                             format!("  depth: {}", format_float(u.depth)),
+                             format!("  sequence: {}{}", preview, ellipsis),
+                             String::new(), "Incoming links:".to_string()];
+        for (i, prev) in self.graph.incoming_strands(num).iter().enumerate() {
+            let key = if i < 9 { format!("[{}]", (b'a' + i as u8) as char) }
+                      else { "   ".to_string() };
+            lines.push(format!("  {} {}", key, sign_at_end(*prev)));
+        }
+        lines.push("Outgoing links:".to_string());
+        for (i, next) in self.graph.outgoing_strands(num).iter().enumerate() {
+            let key = if i < 9 { format!("[{}]", i + 1) } else { "   ".to_string() };
+            lines.push(format!("  {} {}", key, sign_at_end(*next)));
+        }
+        lines.push(String::new());
+        lines.push("Input sequences:".to_string());
+        for (seq, strand, pos) in self.sequences_through(num) {
+            lines.push(format!("  {} {} ({}), position {}", seq.filename, seq.contig_header,
+                               if strand { "+" } else { "-" }, pos));
+        }
+        lines.push(String::new());
+        lines.push("1-9 follow outgoing, a-i follow incoming, r flip strand, Backspace back, \
+                    Esc list, q quit".to_string());
+        lines
+    }
+
+    fn sequences_through(&self, num: i32) -> Vec<(&Sequence, bool, u32)> {
+        // Returns the input sequences which pass through the given unitig strand, with the strand
+        // of the sequence and its position.
+        let u = self.graph.unitig(num.unsigned_abs());
+        let positions = if num > 0 { &u.forward_positions } else { &u.reverse_positions };
+        let mut found: Vec<_> = positions.iter().filter_map(|p| {
+            self.sequences.iter().find(|s| s.id == p.seq_id()).map(|s| (s, p.strand(), p.pos))
+        }).collect();
+        found.sort_by_key(|(s, strand, pos)| (s.id, !*strand, *pos));
+        found
+    }
+}
+
+
+fn truncate(line: &str, cols: usize) -> String {
+    if cols == 0 || line.chars().count() <= cols { return line.to_string(); }
+    line.chars().take(cols).collect()
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_gfa::*;
+
+    #[test]
+    fn test_viewer_navigation() {
+        let (graph, sequences) = UnitigGraph::from_gfa_lines(&get_test_gfa_15());
+        let mut viewer = Viewer::new(&graph, &sequences, Path::new("test.gfa"));
+        assert_eq!(viewer.order, vec![1, 2, 3, 4, 5, 6]);
+        viewer.handle_key(Key::Char('s'), 20);
+        assert_eq!(viewer.sort, SortOrder::Length);
+        assert_eq!(viewer.order, vec![1, 4, 6, 5, 3, 2]);
+        assert_eq!(viewer.cursor, 0);
+        viewer.handle_key(Key::Char('s'), 20);
+        assert_eq!(viewer.order, vec![1, 4, 6, 2, 3, 5]);
+
+        // Open unitig 4, follow its second outgoing link (6+) and then go back.
+        viewer.handle_key(Key::ArrowDown, 20);
+        viewer.handle_key(Key::Enter, 20);
+        assert_eq!(viewer.selected, Some(4));
+        viewer.handle_key(Key::Char('2'), 20);
+        assert_eq!(viewer.selected, Some(6));
+        viewer.handle_key(Key::Char('9'), 20);
+        assert_eq!(viewer.selected, Some(6));
+        viewer.handle_key(Key::Char('r'), 20);
+        assert_eq!(viewer.selected, Some(-6));
+        viewer.handle_key(Key::Backspace, 20);
+        assert_eq!(viewer.selected, Some(4));
+        viewer.handle_key(Key::Escape, 20);
+        assert_eq!(viewer.selected, None);
+        assert_eq!(viewer.cursor, 1);
+        assert!(!viewer.handle_key(Key::Char('q'), 20));
+    }
+
+    #[test]
+    fn test_viewer_render() {
+        let (graph, sequences) = UnitigGraph::from_gfa_lines(&get_test_gfa_15());
+        let mut viewer = Viewer::new(&graph, &sequences, Path::new("test.gfa"));
+        let lines = viewer.render(6, 80);
+        assert_eq!(lines.len(), 6);
+        assert_eq!(lines[0], "test.gfa: 6 unitigs, sorted by number");
+        assert_eq!(lines[2], ">        1          10         3");
+        assert_eq!(lines[3], "         2           2         2");
+
+        viewer.handle_key(Key::ArrowDown, 6);
+        viewer.handle_key(Key::Enter, 6);
+        let lines = viewer.render(100, 80);
+        assert_eq!(lines[0], "Unitig 2+");
+        assert_eq!(lines[3], "  sequence: TT");
+        assert!(lines.contains(&"  [a] 1+".to_string()));
+        assert!(lines.contains(&"  [1] 4+".to_string()));
+        assert_eq!(lines.iter().filter(|l| l.contains(".fasta")).count(), 2);
+        assert_eq!(viewer.render(100, 6)[0], "Unitig");
+    }
+}